@@ -1,5 +1,9 @@
 use anyhow::Result;
+use qb_core::{Arithmetic, Dialect, NumberFormat};
+use qb_hal::{DisplayOptions, TerminalGraphics};
 use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use std::path::PathBuf;
 
 
 /// Configuration for QB-COM
@@ -17,6 +21,12 @@ pub struct CompilerConfig {
     pub target: String,
     pub emit_llvm_ir: bool,
     pub emit_bytecode: bool,
+    /// "qb45" or "qb64", the language programs are read as
+    #[serde(default)]
+    pub dialect: Dialect,
+    /// Read \xNN in string literals as the character with code NN
+    #[serde(default)]
+    pub string_escapes: bool,
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
@@ -26,6 +36,18 @@ pub struct RuntimeConfig {
     pub enable_graphics: bool,
     pub enable_sound: bool,
     pub strict_mode: bool,
+    /// Host directories for the drive letters in DOS paths, as C = "game".
+    /// Relative directories are taken from the program's directory.
+    #[serde(default)]
+    pub drives: BTreeMap<String, PathBuf>,
+    /// Decimal separator of printed and VAL numbers: "classic", a period
+    /// as in QBasic, or "locale", the host's
+    #[serde(default)]
+    pub number_format: NumberFormat,
+    /// "classic", INTEGER and LONG overflow being an error as in QBasic,
+    /// or "modern", an overflowing result given as a LONG or DOUBLE
+    #[serde(default)]
+    pub arithmetic: Arithmetic,
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
@@ -35,6 +57,14 @@ pub struct DisplayConfig {
     pub height: u32,
     pub scale: f32,
     pub vsync: bool,
+    /// Scaling, aspect correction, scanlines and DAC accuracy of graphics
+    /// screens, which _FULLSCREEN can also change
+    #[serde(default, flatten)]
+    pub output: DisplayOptions,
+    /// How graphics are shown in the terminal: "blocks", "blocks256",
+    /// "sixel" or "off". Without it the terminal's color support decides.
+    #[serde(default)]
+    pub terminal_graphics: Option<TerminalGraphics>,
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
@@ -52,6 +82,8 @@ impl Default for Config {
                 target: "native".to_string(),
                 emit_llvm_ir: false,
                 emit_bytecode: false,
+                dialect: Dialect::Qb45,
+                string_escapes: false,
             },
             runtime: RuntimeConfig {
                 memory_limit_mb: 16, // 16MB like old DOS
@@ -59,6 +91,9 @@ impl Default for Config {
                 enable_graphics: true,
                 enable_sound: true,
                 strict_mode: false,
+                drives: BTreeMap::new(),
+                number_format: NumberFormat::default(),
+                arithmetic: Arithmetic::default(),
             },
             display: DisplayConfig {
                 screen_mode: 0,
@@ -66,6 +101,8 @@ impl Default for Config {
                 height: 480,
                 scale: 2.0,
                 vsync: true,
+                output: DisplayOptions::default(),
+                terminal_graphics: None,
             },
             sound: SoundConfig {
                 enabled: true,
@@ -101,3 +138,22 @@ impl Config {
         Ok(())
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_load_baseline_config() {
+        // A config.toml saved before the dialect and other options existed
+        let content = "[compiler]\noptimization_level = 2\ntarget = \"native\"\nemit_llvm_ir = false\n\
+                       emit_bytecode = false\n\n[runtime]\nmemory_limit_mb = 16\nstack_limit = 1024\n\
+                       enable_graphics = true\nenable_sound = true\nstrict_mode = false\n\n[display]\n\
+                       screen_mode = 0\nwidth = 640\nheight = 480\nscale = 2.0\nvsync = true\n\n[sound]\n\
+                       enabled = true\nsample_rate = 44100\nbuffer_size = 512\n";
+        let config: Config = toml::from_str(content).unwrap();
+        assert_eq!(config.compiler.dialect, Dialect::Qb45);
+        assert!(!config.compiler.string_escapes);
+        assert!(config.runtime.drives.is_empty());
+    }
+}
@@ -0,0 +1,65 @@
+//! `qb run --coverage` and `qb cov`: collect line coverage across runs and
+//! report it per file, optionally with the source annotated by hit counts
+
+use anyhow::{Context, Result};
+use std::fs;
+use std::path::Path;
+
+use qb_vm::{ByteCode, CoverageData, VirtualMachine};
+
+/// Default coverage data file, in the current directory
+pub const DEFAULT_DATA: &str = "qb.cov";
+
+fn load(path: &Path) -> Result<CoverageData> {
+    let text = fs::read_to_string(path)
+        .with_context(|| format!("Failed to read coverage data: {}", path.display()))?;
+    serde_json::from_str(&text).with_context(|| format!("Invalid coverage data: {}", path.display()))
+}
+
+/// Add the lines `vm` ran of `file` to the data file, creating it if needed
+pub fn save_run(data: &Path, file: &Path, bytecode: &ByteCode, vm: &VirtualMachine) -> Result<()> {
+    let mut coverage = if data.exists() { load(data)? } else { CoverageData::default() };
+    coverage.record(&file.display().to_string(), bytecode, vm.instruction_counts());
+    fs::write(data, serde_json::to_string_pretty(&coverage)?)
+        .with_context(|| format!("Failed to write coverage data: {}", data.display()))
+}
+
+/// Merge the data files and print line coverage per file
+pub fn report(data: &[impl AsRef<Path>], annotate: bool) -> Result<()> {
+    let mut coverage = CoverageData::default();
+    for path in data {
+        coverage.merge(load(path.as_ref())?);
+    }
+
+    let mut total_lines = 0;
+    let mut total_covered = 0;
+    println!("{:<40} {:>7} {:>8} {:>8}", "FILE", "LINES", "COVERED", "PERCENT");
+    for (file, lines) in &coverage.files {
+        println!("{:<40} {:>7} {:>8} {:>7.1}%", file, lines.lines.len(), lines.covered(), lines.percent());
+        total_lines += lines.lines.len();
+        total_covered += lines.covered();
+    }
+    if coverage.files.len() > 1 {
+        let percent = if total_lines == 0 { 100.0 } else { total_covered as f64 * 100.0 / total_lines as f64 };
+        println!("{:<40} {:>7} {:>8} {:>7.1}%", "TOTAL", total_lines, total_covered, percent);
+    }
+
+    if annotate {
+        for (file, lines) in &coverage.files {
+            println!();
+            println!("{}:", file);
+            let source = fs::read_to_string(file)
+                .with_context(|| format!("Failed to read file: {}", file))?;
+            // Hit count per executable line; ##### marks lines that never ran
+            for (index, text) in source.lines().enumerate() {
+                let count = match lines.lines.get(&(index + 1)) {
+                    Some(0) => "#####".to_string(),
+                    Some(hits) => hits.to_string(),
+                    None => "-".to_string(),
+                };
+                println!("{:>8} | {:4} | {}", count, index + 1, text.trim_end_matches('\r'));
+            }
+        }
+    }
+    Ok(())
+}
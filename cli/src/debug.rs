@@ -0,0 +1,92 @@
+//! `qb debug`: run a program under a small command-line debugger that can
+//! watch variables and inspect them while the program is paused
+
+use anyhow::Result;
+use rustyline::error::ReadlineError;
+use rustyline::DefaultEditor;
+use std::path::Path;
+
+use crate::build_options;
+use crate::config::CompilerConfig;
+use qb_core::errors::QError;
+use qb_driver::Artifacts;
+use qb_vm::{evaluate_expression, parse_watch, Pause, Sandbox, VirtualMachine};
+
+fn print_help() {
+    println!("Commands:");
+    println!("  watch NAME    - Pause whenever a variable, array or element such as a(3) is written");
+    println!("  print EXPR    - Show the value of an expression (also ? EXPR)");
+    println!("  continue      - Start or resume the program (also run); Ctrl+C pauses it");
+    println!("  quit          - Leave the debugger");
+}
+
+pub fn debug_file(file: &Path, sandbox: Sandbox, compiler: &CompilerConfig) -> Result<()> {
+    let Artifacts { names, bytecode, .. } = qb_driver::build_file(file, &build_options(file, compiler))?;
+
+    // Ctrl+C pauses the program instead of ending the debugger
+    qb_hal::break_key::install();
+    let mut vm = VirtualMachine::new();
+    vm.set_dialect(compiler.dialect);
+    vm.set_sandbox(sandbox);
+    let mut started = false;
+    let mut finished = false;
+
+    println!("Debugging {}; type 'help' for commands", file.display());
+    let mut editor = DefaultEditor::new()?;
+    loop {
+        let input = match editor.readline("(qb) ") {
+            Ok(line) => line,
+            Err(ReadlineError::Interrupted) | Err(ReadlineError::Eof) => break,
+            Err(e) => return Err(e.into()),
+        };
+        let line = input.trim();
+        if !line.is_empty() {
+            let _ = editor.add_history_entry(line);
+        }
+        let (command, rest) = match line.strip_prefix('?') {
+            Some(expr) => ("print", expr),
+            None => line.split_once(char::is_whitespace).unwrap_or((line, "")),
+        };
+
+        match command.to_lowercase().as_str() {
+            "" => {}
+            "help" | "h" => print_help(),
+            "quit" | "q" | "exit" => break,
+            "watch" | "w" => match parse_watch(&mut vm, &names, rest) {
+                Ok(watch) => {
+                    println!("Watching {}", watch);
+                    vm.add_watch(watch);
+                }
+                Err(e) => eprintln!("Error: {}", e),
+            },
+            "print" | "p" => match evaluate_expression(&mut vm, &names, rest) {
+                Ok(value) => println!("{}", value),
+                Err(e) => eprintln!("Error: {}", e),
+            },
+            "continue" | "c" | "run" | "r" => {
+                if finished {
+                    println!("The program has finished.");
+                    continue;
+                }
+                if !started {
+                    vm.start();
+                    started = true;
+                }
+                match vm.resume(&bytecode) {
+                    Ok(Some(Pause::Watch(hit))) => println!("Watch: {}", hit),
+                    Ok(Some(Pause::Break { line })) => println!("{}", QError::Break { line }),
+                    Ok(None) => {
+                        println!("Program finished.");
+                        finished = true;
+                    }
+                    Err(e) => {
+                        eprintln!("{}", e);
+                        finished = true;
+                    }
+                }
+            }
+            other => println!("Unknown command '{}'; type 'help' for commands", other),
+        }
+    }
+    Ok(())
+}
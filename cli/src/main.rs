@@ -1,17 +1,29 @@
 mod config;
+mod coverage;
+mod debug;
+mod repl;
+mod tokenize;
 
 use anyhow::{Context, Result};
-use clap::{Parser, Subcommand};
+use clap::{Parser, Subcommand, ValueEnum};
 use std::fs;
-use std::path::PathBuf;
+use std::io::{self, Write};
+use std::path::{Path, PathBuf};
 use std::process;
 
-use config::Config;
+use config::{CompilerConfig, Config};
+use debug::debug_file;
+use repl::run_repl;
+use tokenize::tokenize_file;
 // use qb_core::errors::QError;
-use qb_lexer::tokenize;
-use qb_parser::parse;
-use qb_semantic::analyze;
-use qb_vm::{compile, run};
+use qb_core::{Arithmetic, Dialect, NumberFormat};
+use qb_core::errors::QError;
+use qb_hal::audio_file::WavWriter;
+use qb_hal::{Clock, TerminalGraphics};
+use qb_driver::{Diagnostics, Options};
+use qb_parser::Program;
+use qb_semantic::{analyze, analyze_usage, build_index, Names, Symbol, UsageReport};
+use qb_vm::{ByteCode, ConsoleInput, Sandbox, Session, Vfs, VirtualMachine};
 
 /// QB-COM: QBasic Compiler and Interpreter
 #[derive(Parser)]
@@ -29,6 +41,18 @@ struct Cli {
     /// Configuration file path
     #[arg(short, long, global = true)]
     config: Option<PathBuf>,
+
+    /// The BASIC the program is written for: qb45 (the default) or qb64,
+    /// which also reserves QB64's keywords (also compiler.dialect in the config)
+    #[arg(long, value_name = "DIALECT", global = true)]
+    dialect: Option<Dialect>,
+}
+
+/// Output format for analysis reports
+#[derive(Clone, Copy, ValueEnum)]
+enum ReportFormat {
+    Text,
+    Json,
 }
 
 #[derive(Subcommand)]
@@ -38,8 +62,83 @@ enum Commands {
         /// Path to the QBasic source file
         file: PathBuf,
         
-        /// Command line arguments to pass to the program
+        /// Arguments passed to the program, read with COMMAND$ (put them
+        /// after -- if they start with a dash)
         args: Vec<String>,
+
+        /// Let the program open network connections
+        #[arg(long)]
+        allow_net: bool,
+
+        /// Record the source lines this run executes, adding them to a
+        /// coverage data file (--coverage=DATA; qb.cov by default)
+        #[arg(
+            long,
+            value_name = "DATA",
+            num_args = 0..=1,
+            require_equals = true,
+            default_missing_value = coverage::DEFAULT_DATA
+        )]
+        coverage: Option<PathBuf>,
+
+        /// Save every line typed at INPUT and key read by INKEY$, with its
+        /// timing, to a session file
+        #[arg(long, value_name = "SESSION", conflicts_with = "replay")]
+        record: Option<PathBuf>,
+
+        /// Feed the input saved by --record back to the program. Time runs
+        /// on a virtual clock from midnight, so the run repeats exactly
+        #[arg(long, value_name = "SESSION")]
+        replay: Option<PathBuf>,
+
+        /// With --replay, wait as long between lines and keys as the user
+        /// did, by the real clock
+        #[arg(long, requires = "replay")]
+        realtime: bool,
+
+        /// Stop with "Variable not defined" when the program reads a
+        /// variable it never assigned (also runtime.strict_mode in the config)
+        #[arg(long)]
+        strict: bool,
+
+        /// Send LPRINT output to this file; without it LPRINT fails with
+        /// "Device unavailable"
+        #[arg(long, value_name = "FILE")]
+        printer: Option<PathBuf>,
+
+        /// Show graphics screens in the terminal: blocks, blocks256 (for
+        /// terminals without 24-bit color), sixel, or off (also
+        /// display.terminal_graphics in the config). By default, blocks in
+        /// as many colors as the terminal shows
+        #[arg(long, value_name = "MODE")]
+        terminal_graphics: Option<TerminalGraphics>,
+
+        /// Decimal separator of numbers that PRINT and STR$ write and VAL
+        /// reads: classic, the period QBasic always used, or locale, the
+        /// host's (also runtime.number_format in the config)
+        #[arg(long, value_name = "FORMAT")]
+        number_format: Option<NumberFormat>,
+
+        /// What INTEGER and LONG arithmetic does with a result too large:
+        /// classic, an Overflow error as in QBasic, or modern, the result
+        /// as a LONG or DOUBLE (also runtime.arithmetic in the config)
+        #[arg(long, value_name = "MODE")]
+        arithmetic: Option<Arithmetic>,
+
+        /// Record the screen as an animated PNG, for documentation and bug
+        /// reports
+        #[arg(long, value_name = "FILE")]
+        record_screen: Option<PathBuf>,
+
+        /// Write the program's sound, all voices and sounds mixed, to a WAV
+        /// file
+        #[arg(long, value_name = "FILE")]
+        sound_out: Option<PathBuf>,
+
+        /// Map a drive letter of the program's DOS paths onto a host
+        /// directory, as --drive C=game (also runtime.drives in the config)
+        #[arg(long, value_name = "LETTER=DIR", value_parser = parse_drive)]
+        drive: Vec<(char, PathBuf)>,
     },
     
     /// Compile a QBasic program to bytecode
@@ -78,18 +177,66 @@ enum Commands {
     Tokenize {
         /// Path to the QBasic source file
         file: PathBuf,
+
+        /// Show each source line with its tokens underlined
+        #[arg(long)]
+        detailed: bool,
+
+        /// Colorize tokens by kind (implies --detailed)
+        #[arg(long)]
+        color: bool,
     },
     
     /// Parse a QBasic program and print AST
     Parse {
         /// Path to the QBasic source file
         file: PathBuf,
+
+        /// Print the program back as QBasic source instead of the AST
+        #[arg(long)]
+        emit_source: bool,
     },
     
     /// Check a QBasic program for errors without running
     Check {
         /// Path to the QBasic source file
         file: PathBuf,
+
+        /// Print a variable usage report (type, declaration, reads/writes, SHARED)
+        #[arg(long)]
+        report: bool,
+
+        /// Output format for the report
+        #[arg(long, value_enum, default_value = "text")]
+        format: ReportFormat,
+
+        /// Warn about = and <> tests on SINGLE or DOUBLE values, which
+        /// QBasic compares exactly
+        #[arg(long)]
+        float_equality: bool,
+
+        /// Correct mechanical mistakes (ENDIF, a NEXT without its variable,
+        /// an unclosed string, an unmarked banner) and save the file
+        #[arg(long)]
+        fix: bool,
+
+        /// Print the corrections --fix would make as a diff, leaving the
+        /// file as it is
+        #[arg(long)]
+        diff: bool,
+    },
+    
+    /// Show the definition and all references of a symbol
+    Xref {
+        /// Label, SUB/FUNCTION or variable name
+        name: String,
+
+        /// Path to the QBasic source file
+        file: PathBuf,
+
+        /// Output format
+        #[arg(long, value_enum, default_value = "text")]
+        format: ReportFormat,
     },
     
     /// Initialize a new QBasic project
@@ -111,13 +258,34 @@ enum Commands {
     
     /// Run REPL (Interactive mode)
     Repl,
+
+    /// Report line coverage merged from runs made with `qb run --coverage`
+    Cov {
+        /// Coverage data files to merge
+        #[arg(default_value = coverage::DEFAULT_DATA)]
+        data: Vec<PathBuf>,
+
+        /// Show each file's source with the times every line ran
+        #[arg(long)]
+        annotate: bool,
+    },
+
+    /// Run a program under the debugger, with watchpoints and expression printing
+    Debug {
+        /// Path to the QBasic source file
+        file: PathBuf,
+
+        /// Let the program open network connections
+        #[arg(long)]
+        allow_net: bool,
+    },
 }
 
 fn main() {
     let cli = Cli::parse();
     
     // Load configuration
-    let config = if let Some(config_path) = cli.config {
+    let mut config = if let Some(config_path) = cli.config {
         match fs::read_to_string(&config_path) {
             Ok(content) => {
                 match toml::from_str(&content) {
@@ -137,6 +305,10 @@ fn main() {
         Config::load().unwrap_or_default()
     };
     
+    if let Some(dialect) = cli.dialect {
+        config.compiler.dialect = dialect;
+    }
+
     if let Err(e) = run_command(cli.command, config, cli.verbose) {
         eprintln!("Error: {}", e);
         process::exit(1);
@@ -145,8 +317,45 @@ fn main() {
 
 fn run_command(command: Commands, config: Config, verbose: bool) -> Result<()> {
     match command {
-        Commands::Run { file, args: _ } => {
-            run_file(&file, config, verbose)
+        Commands::Run {
+            file,
+            args,
+            allow_net,
+            coverage,
+            record,
+            replay,
+            realtime,
+            strict,
+            printer,
+            terminal_graphics,
+            number_format,
+            arithmetic,
+            record_screen,
+            sound_out,
+            drive,
+        } => {
+            let strict = strict || config.runtime.strict_mode;
+            let dialect = config.compiler.dialect;
+            let options = RunOptions {
+                args,
+                sandbox: Sandbox { network: allow_net },
+                coverage,
+                record,
+                replay,
+                realtime,
+                strict,
+                dialect,
+                printer,
+                terminal_graphics: terminal_graphics
+                    .or(config.display.terminal_graphics)
+                    .unwrap_or_else(TerminalGraphics::detect),
+                number_format: number_format.unwrap_or(config.runtime.number_format),
+                arithmetic: arithmetic.unwrap_or(config.runtime.arithmetic),
+                record_screen,
+                sound_out,
+                drives: drive,
+            };
+            run_file(&file, config, verbose, options)
         }
         Commands::Build { file, output, llvm, bytecode } => {
             build_file(&file, output, config, verbose, llvm, bytecode)
@@ -154,14 +363,18 @@ fn run_command(command: Commands, config: Config, verbose: bool) -> Result<()> {
         Commands::Compile { file, output, optimize } => {
             compile_native(&file, output, optimize, config, verbose)
         }
-        Commands::Tokenize { file } => {
-            tokenize_file(&file)
+        Commands::Tokenize { file, detailed, color } => {
+            tokenize_file(&file, detailed, color)
         }
-        Commands::Parse { file } => {
-            parse_file(&file)
+        Commands::Parse { file, emit_source } => {
+            parse_file(&file, emit_source, &config.compiler)
         }
-        Commands::Check { file } => {
-            check_file(&file)
+        Commands::Check { file, report, format, float_equality, fix, diff } => {
+            let fixes = CheckFixes { write: fix, diff };
+            check_file(&file, report, format, float_equality, fixes, &config.compiler)
+        }
+        Commands::Xref { name, file, format } => {
+            xref_symbol(&name, &file, format, &config.compiler)
         }
         Commands::Init { name, path } => {
             init_project(&name, path)
@@ -176,77 +389,221 @@ fn run_command(command: Commands, config: Config, verbose: bool) -> Result<()> {
         Commands::Repl => {
             run_repl()
         }
+        Commands::Cov { data, annotate } => {
+            coverage::report(&data, annotate)
+        }
+        Commands::Debug { file, allow_net } => {
+            debug_file(&file, Sandbox { network: allow_net }, &config.compiler)
+        }
     }
 }
 
-fn run_file(file: &PathBuf, _config: Config, verbose: bool) -> Result<()> {
-    let source = fs::read_to_string(file)
-        .with_context(|| format!("Failed to read file: {}", file.display()))?;
-    
-    if verbose {
-        eprintln!("Tokenizing...");
+/// How `qb run` runs the program, beyond the program itself
+struct RunOptions {
+    args: Vec<String>,
+    sandbox: Sandbox,
+    coverage: Option<PathBuf>,
+    record: Option<PathBuf>,
+    replay: Option<PathBuf>,
+    realtime: bool,
+    strict: bool,
+    dialect: Dialect,
+    printer: Option<PathBuf>,
+    terminal_graphics: TerminalGraphics,
+    number_format: NumberFormat,
+    arithmetic: Arithmetic,
+    record_screen: Option<PathBuf>,
+    sound_out: Option<PathBuf>,
+    /// Drive letters from the command line, which override the config's
+    drives: Vec<(char, PathBuf)>,
+}
+
+/// A --drive argument: a drive letter, =, and a host directory
+fn parse_drive(arg: &str) -> Result<(char, PathBuf), String> {
+    let mut chars = arg.chars();
+    match (chars.next(), chars.next()) {
+        (Some(letter), Some('=')) if letter.is_ascii_alphabetic() => Ok((letter, PathBuf::from(&arg[2..]))),
+        _ => Err("expected a drive letter and a directory, as C=game".to_string()),
     }
-    let tokens = tokenize(&source)?;
-    
-    if verbose {
-        eprintln!("Parsing...");
+}
+
+/// The VFS for running `file`: the config's drives, relative to the
+/// program's directory, then those given on the command line
+fn drive_map(file: &Path, config: &Config, drives: &[(char, PathBuf)]) -> Result<Vfs> {
+    let base = file.parent().unwrap_or(Path::new(""));
+    let mut vfs = Vfs::new();
+    for (letter, dir) in &config.runtime.drives {
+        let mut chars = letter.chars();
+        let letter = match (chars.next(), chars.next()) {
+            (Some(letter), None) => letter,
+            _ => ' ',
+        };
+        if !vfs.map_drive(letter, base.join(dir)) {
+            anyhow::bail!("Invalid drive letter in runtime.drives: {}", letter);
+        }
     }
-    let ast = parse(tokens)?;
-    
-    if verbose {
-        eprintln!("Analyzing...");
+    for (letter, dir) in drives {
+        vfs.map_drive(*letter, dir.clone());
     }
-    analyze(&ast)?;
-    
+    Ok(vfs)
+}
+
+/// The driver options for the program in `file`, as the compiler settings
+/// say
+pub(crate) fn build_options(file: &Path, compiler: &CompilerConfig) -> Options {
+    let options = Options {
+        dialect: compiler.dialect,
+        string_escapes: compiler.string_escapes,
+        include_dirs: Vec::new(),
+        optimize: compiler.optimization_level > 0,
+    };
+    options.for_file(file)
+}
+
+/// Read a source file and parse it, with its includes
+fn parse_program(file: &Path, compiler: &CompilerConfig) -> Result<Program> {
+    let source = fs::read_to_string(file)
+        .with_context(|| format!("Failed to read file: {}", file.display()))?;
+    Ok(qb_driver::parse_source(&source, &build_options(file, compiler))?.0)
+}
+
+/// Tokenize, parse, analyze and compile a source file to bytecode
+fn compile_file(file: &Path, config: &Config, verbose: bool) -> Result<ByteCode> {
     if verbose {
         eprintln!("Compiling to bytecode...");
     }
-    let bytecode = compile(&ast)?;
+    Ok(qb_driver::build_file(file, &build_options(file, &config.compiler))?.bytecode)
+}
+
+/// The bytecode of a .qbc file, or of a source file compiled
+fn load_program(file: &Path, config: &Config, verbose: bool) -> Result<ByteCode> {
+    if file.extension().is_some_and(|ext| ext.eq_ignore_ascii_case("qbc")) {
+        let bytes = fs::read(file)
+            .with_context(|| format!("Failed to read file: {}", file.display()))?;
+        Ok(ByteCode::from_qbc(&bytes)?)
+    } else {
+        compile_file(file, config, verbose)
+    }
+}
+
+fn run_file(file: &Path, config: Config, verbose: bool, options: RunOptions) -> Result<()> {
+    let mut bytecode = load_program(file, &config, verbose)?;
     
     if verbose {
         eprintln!("Running...");
     }
-    run(&bytecode)?;
+    let mut vm = VirtualMachine::new();
+    vm.set_sandbox(options.sandbox);
+    vm.set_vfs(drive_map(file, &config, &options.drives)?);
+    vm.set_strict(options.strict);
+    vm.set_dialect(options.dialect);
+    vm.set_number_format(options.number_format);
+    vm.set_arithmetic(options.arithmetic);
+    vm.set_terminal_graphics(options.terminal_graphics);
+    vm.set_display_options(config.display.output);
+    if config.runtime.enable_graphics {
+        vm.enable_window(config.display.scale);
+    }
+    if let Some(path) = &options.printer {
+        let printer = fs::File::create(path)
+            .with_context(|| format!("Failed to create printer file: {}", path.display()))?;
+        vm.set_printer(Box::new(printer));
+    }
+    vm.set_command_line(std::iter::once(file.display().to_string()).chain(options.args.iter().cloned()).collect());
+    if options.coverage.is_some() {
+        vm.enable_coverage();
+    }
+    if options.record_screen.is_some() {
+        vm.record_screen();
+    }
+    if let Some(path) = &options.replay {
+        let text = fs::read_to_string(path)
+            .with_context(|| format!("Failed to read session: {}", path.display()))?;
+        let session: Session = serde_json::from_str(&text)
+            .with_context(|| format!("Invalid session file: {}", path.display()))?;
+        vm.set_console_input(ConsoleInput::replay(session, options.realtime));
+        if !options.realtime {
+            vm.set_clock(Clock::fixed(0.0));
+        }
+    } else if options.record.is_some() {
+        vm.set_console_input(ConsoleInput::record());
+    }
+    // After the clock is set, which the sound is timed by
+    if let Some(path) = &options.sound_out {
+        let out = fs::File::create(path)
+            .with_context(|| format!("Failed to create sound file: {}", path.display()))?;
+        let writer = WavWriter::new(io::BufWriter::new(out))
+            .with_context(|| format!("Failed to write sound file: {}", path.display()))?;
+        vm.set_sound_output(Box::new(writer));
+    }
+    // Ctrl+C stops the program at the next statement instead of killing it
+    qb_hal::break_key::install();
+    let mut result = vm.execute(&bytecode);
+    // A CHAIN statement goes on to the next program in the same VM
+    let mut file = file.to_path_buf();
+    while result.is_ok() {
+        let Some(next) = vm.take_chain() else { break };
+        if let Some(data) = &options.coverage {
+            coverage::save_run(data, &file, &bytecode, &vm)?;
+            vm.enable_coverage();
+        }
+        bytecode = load_program(&next, &config, verbose)?;
+        vm.set_command_line(std::iter::once(next.display().to_string()).chain(options.args.iter().cloned()).collect());
+        file = next;
+        result = vm.execute(&bytecode);
+    }
+    if result.is_ok() {
+        vm.hold_window();
+    }
+
+    // A run that fails still shows how far it got
+    if let Some(data) = &options.coverage {
+        coverage::save_run(data, &file, &bytecode, &vm)?;
+    }
+    if let (Some(path), Some(session)) = (&options.record, vm.recorded_session()) {
+        fs::write(path, serde_json::to_string_pretty(session)?)
+            .with_context(|| format!("Failed to write session: {}", path.display()))?;
+    }
+    if let Some(path) = &options.sound_out {
+        vm.finish_sound()
+            .with_context(|| format!("Failed to write sound file: {}", path.display()))?;
+    }
+    if let (Some(path), Some(recording)) = (&options.record_screen, vm.screen_recording()) {
+        let out = fs::File::create(path)
+            .with_context(|| format!("Failed to create screen recording: {}", path.display()))?;
+        recording
+            .write_apng(io::BufWriter::new(out))
+            .with_context(|| format!("Failed to write screen recording: {}", path.display()))?;
+    }
+    if let Err(e @ QError::Break { .. }) = result {
+        io::stdout().flush()?;
+        eprintln!("{}", e);
+        process::exit(130);
+    }
+    result?;
+
+    // END n and SYSTEM n become the exit status, for use in scripts
+    if vm.exit_code() != 0 {
+        io::stdout().flush()?;
+        process::exit(vm.exit_code());
+    }
     
     Ok(())
 }
 
 fn build_file(
-    file: &PathBuf, 
+    file: &Path, 
     output: Option<PathBuf>, 
-    _config: Config, 
+    config: Config, 
     verbose: bool,
     _llvm: bool,
     _bytecode: bool
 ) -> Result<()> {
-    let source = fs::read_to_string(file)
-        .with_context(|| format!("Failed to read file: {}", file.display()))?;
-    
-    if verbose {
-        eprintln!("Tokenizing...");
-    }
-    let tokens = tokenize(&source)?;
-    
-    if verbose {
-        eprintln!("Parsing...");
-    }
-    let ast = parse(tokens)?;
-    
-    if verbose {
-        eprintln!("Analyzing...");
-    }
-    analyze(&ast)?;
-    
-    if verbose {
-        eprintln!("Compiling to bytecode...");
-    }
-    let bytecode = compile(&ast)?;
+    let bytecode = compile_file(file, &config, verbose)?;
     
     let output_path = output.unwrap_or_else(|| file.with_extension("qbc"));
     
-    // Serialize bytecode
-    let serialized = bincode::serialize(&bytecode)?;
-    fs::write(&output_path, serialized)?;
+    fs::write(&output_path, bytecode.to_qbc()?)?;
     
     println!("Built: {}", output_path.display());
     
@@ -254,29 +611,16 @@ fn build_file(
 }
 
 fn compile_native(
-    file: &PathBuf,
+    file: &Path,
     output: Option<PathBuf>,
     optimize: u8,
-    _config: Config,
+    config: Config,
     verbose: bool,
 ) -> Result<()> {
-    let source = fs::read_to_string(file)
-        .with_context(|| format!("Failed to read file: {}", file.display()))?;
-    
-    if verbose {
-        eprintln!("Tokenizing...");
-    }
-    let tokens = tokenize(&source)?;
-    
-    if verbose {
-        eprintln!("Parsing...");
-    }
-    let ast = parse(tokens)?;
-    
     if verbose {
         eprintln!("Analyzing...");
     }
-    analyze(&ast)?;
+    let ast = qb_driver::build_file(file, &build_options(file, &config.compiler))?.program;
     
     let output_path = output.unwrap_or_else(|| {
         if cfg!(windows) {
@@ -298,49 +642,174 @@ fn compile_native(
     Ok(())
 }
 
-fn tokenize_file(file: &PathBuf) -> Result<()> {
-    let source = fs::read_to_string(file)
-        .with_context(|| format!("Failed to read file: {}", file.display()))?;
+fn parse_file(file: &Path, emit_source: bool, compiler: &CompilerConfig) -> Result<()> {
+    let ast = parse_program(file, compiler)?;
     
-    let tokens = tokenize(&source)?;
-    
-    for (i, token_info) in tokens.iter().enumerate() {
-        println!("{:4}: {:?} (line {}, col {})", 
-            i, 
-            token_info.token, 
-            token_info.line, 
-            token_info.column
-        );
+    if emit_source {
+        print!("{}", qb_parser::to_source(&ast));
+    } else {
+        println!("{:#?}", ast);
     }
     
     Ok(())
 }
 
-fn parse_file(file: &PathBuf) -> Result<()> {
-    let source = fs::read_to_string(file)
+fn check_file(
+    file: &PathBuf,
+    report: bool,
+    format: ReportFormat,
+    float_equality: bool,
+    fixes: CheckFixes,
+    compiler: &CompilerConfig,
+) -> Result<()> {
+    let mut source = fs::read_to_string(file)
         .with_context(|| format!("Failed to read file: {}", file.display()))?;
+
+    if fixes.write || fixes.diff {
+        let (fixed, made) = qb_parser::fix::fix_source(&source);
+        if fixes.diff {
+            print_fix_diff(file, &made);
+        } else {
+            for fix in &made {
+                println!("{}:{}: fixed: {}", file.display(), fix.line, fix.message);
+            }
+            if !made.is_empty() {
+                fs::write(file, &fixed)
+                    .with_context(|| format!("Failed to write file: {}", file.display()))?;
+            }
+        }
+        // Check what the program would be once fixed
+        source = fixed;
+    }
+
+    let (mut ast, sources) = qb_driver::parse_source(&source, &build_options(file, compiler))?;
+
+    if report {
+        // X and X! are one variable once their names are spelled out
+        Names::new().canonicalize(&mut ast);
+        let usage = analyze_usage(&ast);
+        match format {
+            ReportFormat::Text => print_usage_report(&usage),
+            ReportFormat::Json => println!("{}", serde_json::to_string_pretty(&usage)?),
+        }
+        return Ok(());
+    }
+
+    analyze(&mut ast).map_err(|error| Diagnostics::located(error, &sources))?;
+
+    if float_equality {
+        for lint in qb_semantic::float_equality(&ast) {
+            println!("{}:{}: warning: {}", file.display(), lint.line, lint.message);
+        }
+    }
     
-    let tokens = tokenize(&source)?;
-    let ast = parse(tokens)?;
-    
-    println!("{:#?}", ast);
+    println!("✓ No errors found!");
     
     Ok(())
 }
 
-fn check_file(file: &PathBuf) -> Result<()> {
-    let source = fs::read_to_string(file)
-        .with_context(|| format!("Failed to read file: {}", file.display()))?;
-    
-    let tokens = tokenize(&source)?;
-    let ast = parse(tokens)?;
-    analyze(&ast)?;
-    
-    println!("✓ No errors found!");
-    
+/// What `check` does with the corrections it can make
+#[derive(Clone, Copy)]
+struct CheckFixes {
+    /// Save them to the file
+    write: bool,
+    /// Print them as a diff
+    diff: bool,
+}
+
+/// A unified diff of fixed lines, without context, as `patch` takes it
+fn print_fix_diff(file: &Path, fixes: &[qb_parser::fix::Fix]) {
+    if fixes.is_empty() {
+        return;
+    }
+    println!("--- {}", file.display());
+    println!("+++ {}", file.display());
+    for fix in fixes {
+        println!("@@ -{} +{} @@ {}", fix.line, fix.line, fix.message);
+        println!("-{}", fix.before);
+        println!("+{}", fix.after);
+    }
+}
+
+fn xref_symbol(name: &str, file: &Path, format: ReportFormat, compiler: &CompilerConfig) -> Result<()> {
+    let ast = parse_program(file, compiler)?;
+    let index = build_index(&ast);
+    let symbols = index.lookup(name);
+
+    match format {
+        ReportFormat::Json => println!("{}", serde_json::to_string_pretty(&symbols)?),
+        ReportFormat::Text => {
+            if symbols.is_empty() {
+                anyhow::bail!("Symbol not found: {}", name);
+            }
+            for symbol in symbols {
+                print_symbol(symbol, file);
+            }
+        }
+    }
+
     Ok(())
 }
 
+fn print_symbol(symbol: &Symbol, file: &Path) {
+    let mut header = format!(
+        "{} ({}, {})",
+        symbol.name,
+        symbol.kind.as_str(),
+        symbol.scope.as_deref().unwrap_or("module")
+    );
+    if let Some(type_name) = &symbol.type_name {
+        header.push_str(&format!(" AS {}", type_name));
+    }
+    println!("{}", header);
+    match symbol.definition {
+        Some(span) => println!("  {}:{}: definition", file.display(), span.line),
+        None => println!("  (no explicit definition)"),
+    }
+    for reference in &symbol.references {
+        println!("  {}:{}: {}", file.display(), reference.span.line, reference.access.as_str());
+    }
+}
+
+fn print_usage_report(report: &UsageReport) {
+    println!(
+        "{:<20} {:<12} {:<16} {:<16} {:>6} {:>6}  FLAGS",
+        "VARIABLE", "SCOPE", "TYPE", "DECLARED", "READS", "WRITES"
+    );
+    for var in &report.variables {
+        let mut flags = Vec::new();
+        if var.shared {
+            flags.push("SHARED");
+        }
+        if var.is_array {
+            flags.push("ARRAY");
+        }
+        if var.reads == 0 && var.writes > 0 {
+            flags.push("UNREAD");
+        }
+        if var.writes == 0 && var.reads > 0 {
+            flags.push("UNASSIGNED");
+        }
+        println!(
+            "{:<20} {:<12} {:<16} {:<16} {:>6} {:>6}  {}",
+            var.name,
+            var.scope.as_deref().unwrap_or("(module)"),
+            var.inferred_type,
+            format!("{} line {}", var.declaration.as_str(), var.line),
+            var.reads,
+            var.writes,
+            flags.join(" ")
+        );
+    }
+    println!();
+    println!(
+        "{} variable(s), {} never read, {} never assigned",
+        report.variables.len(),
+        report.unread().count(),
+        report.unassigned().count()
+    );
+}
+
 fn init_project(name: &str, path: Option<PathBuf>) -> Result<()> {
     let project_dir = path.unwrap_or_else(|| PathBuf::from(name));
     
@@ -403,105 +872,3 @@ fn update_config(_settings: Vec<String>) -> Result<()> {
     Ok(())
 }
 
-fn run_repl() -> Result<()> {
-    use std::io::{self, BufRead, Write};
-    
-    println!("QB-COM Interactive Shell (REPL)");
-    println!("Type 'exit' or 'quit' to exit, 'help' for commands");
-    println!();
-    
-    let stdin = io::stdin();
-    let mut line_num = 10;
-    let mut program_lines: Vec<String> = Vec::new();
-    
-    print!("{} ", line_num);
-    io::stdout().flush()?;
-    
-    for line in stdin.lock().lines() {
-        let input = line?;
-        
-        if input.trim().eq_ignore_ascii_case("exit") || 
-           input.trim().eq_ignore_ascii_case("quit") {
-            break;
-        }
-        
-        if input.trim().eq_ignore_ascii_case("help") {
-            println!("Commands:");
-            println!("  run    - Run the current program");
-            println!("  clear  - Clear the current program");
-            println!("  list   - List the current program");
-            println!("  exit   - Exit the REPL");
-            println!();
-            print!("{} ", line_num);
-            io::stdout().flush()?;
-            continue;
-        }
-        
-        if input.trim().eq_ignore_ascii_case("clear") {
-            program_lines.clear();
-            line_num = 10;
-            println!("Program cleared.");
-            print!("{} ", line_num);
-            io::stdout().flush()?;
-            continue;
-        }
-        
-        if input.trim().eq_ignore_ascii_case("list") {
-            if program_lines.is_empty() {
-                println!("No program loaded.");
-            } else {
-                for (i, line) in program_lines.iter().enumerate() {
-                    println!("{} {}", (i + 1) * 10, line);
-                }
-            }
-            print!("{} ", line_num);
-            io::stdout().flush()?;
-            continue;
-        }
-        
-        if input.trim().eq_ignore_ascii_case("run") {
-            if program_lines.is_empty() {
-                println!("No program to run.");
-            } else {
-                let source = program_lines.join("\n");
-                match tokenize(&source) {
-                    Ok(tokens) => {
-                        match parse(tokens) {
-                            Ok(ast) => {
-                                match analyze(&ast) {
-                                    Ok(_) => {
-                                        match compile(&ast) {
-                                            Ok(bytecode) => {
-                                                if let Err(e) = run(&bytecode) {
-                                                    eprintln!("Runtime error: {:?}", e);
-                                                }
-                                            }
-                                            Err(e) => eprintln!("Compile error: {:?}", e),
-                                        }
-                                    }
-                                    Err(e) => eprintln!("Analysis error: {:?}", e),
-                                }
-                            }
-                            Err(e) => eprintln!("Parse error: {:?}", e),
-                        }
-                    }
-                    Err(e) => eprintln!("Tokenize error: {:?}", e),
-                }
-            }
-            print!("{} ", line_num);
-            io::stdout().flush()?;
-            continue;
-        }
-        
-        if !input.trim().is_empty() {
-            program_lines.push(input);
-            line_num += 10;
-        }
-        
-        print!("{} ", line_num);
-        io::stdout().flush()?;
-    }
-    
-    println!("\nGoodbye!");
-    Ok(())
-}
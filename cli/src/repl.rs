@@ -0,0 +1,270 @@
+//! Interactive shell (REPL)
+
+use anyhow::Result;
+use rustyline::completion::{Completer, Pair};
+use rustyline::error::ReadlineError;
+use rustyline::highlight::Highlighter;
+use rustyline::hint::Hinter;
+use rustyline::history::DefaultHistory;
+use rustyline::validate::Validator;
+use rustyline::{Context, Editor, Helper};
+use std::collections::BTreeSet;
+
+use qb_core::data_types::{QType, VariableId};
+use qb_core::errors::QError;
+use qb_driver::{build, Artifacts, Options};
+use qb_lexer::{tokenize, KEYWORDS};
+use qb_parser::parse;
+use qb_semantic::{build_index, Names};
+use qb_vm::{evaluate_expression, VirtualMachine};
+
+const COMMANDS: &[&str] = &["RUN", "LIST", "CLEAR", "VARS", "DUMP", "HELP", "EXIT", "QUIT"];
+
+/// TAB completion for keywords, built-ins, REPL commands and known variables
+struct ReplHelper {
+    variables: BTreeSet<String>,
+}
+
+impl ReplHelper {
+    fn candidates(&self, prefix: &str) -> Vec<String> {
+        let prefix = prefix.to_uppercase();
+        let mut found: BTreeSet<String> = BTreeSet::new();
+        let keywords = KEYWORDS.iter().map(|(name, _)| *name);
+        for name in COMMANDS.iter().copied().chain(keywords) {
+            if name.starts_with(&prefix) {
+                found.insert(name.to_string());
+            }
+        }
+        for name in &self.variables {
+            if name.starts_with(&prefix) {
+                found.insert(name.clone());
+            }
+        }
+        found.into_iter().collect()
+    }
+}
+
+impl Completer for ReplHelper {
+    type Candidate = Pair;
+
+    fn complete(&self, line: &str, pos: usize, _ctx: &Context<'_>) -> rustyline::Result<(usize, Vec<Pair>)> {
+        let start = line[..pos]
+            .rfind(|c: char| !(c.is_ascii_alphanumeric() || "_$%&!#.".contains(c)))
+            .map(|i| i + 1)
+            .unwrap_or(0);
+        let word = &line[start..pos];
+        if word.is_empty() {
+            return Ok((pos, Vec::new()));
+        }
+        let pairs = self
+            .candidates(word)
+            .into_iter()
+            .map(|name| Pair { display: name.clone(), replacement: name })
+            .collect();
+        Ok((start, pairs))
+    }
+}
+
+impl Hinter for ReplHelper {
+    type Hint = String;
+}
+
+impl Highlighter for ReplHelper {}
+
+impl Validator for ReplHelper {}
+
+impl Helper for ReplHelper {}
+
+pub fn run_repl() -> Result<()> {
+    // Ctrl+C stops a running program and returns to the prompt
+    qb_hal::break_key::install();
+    println!("QB-COM Interactive Shell (REPL)");
+    println!("Type 'exit' or 'quit' to exit, 'help' for commands");
+    println!();
+
+    let mut editor: Editor<ReplHelper, DefaultHistory> = Editor::new()?;
+    editor.set_helper(Some(ReplHelper { variables: BTreeSet::new() }));
+
+    let mut line_num = 10;
+    let mut program_lines: Vec<String> = Vec::new();
+    let mut last_vm: Option<VirtualMachine> = None;
+    let mut last_names = Names::new();
+
+    loop {
+        let input = match editor.readline(&format!("{} ", line_num)) {
+            Ok(line) => line,
+            Err(ReadlineError::Interrupted) | Err(ReadlineError::Eof) => break,
+            Err(e) => return Err(e.into()),
+        };
+        let trimmed = input.trim();
+        if !trimmed.is_empty() {
+            let _ = editor.add_history_entry(trimmed);
+        }
+        // Immediate mode: ? EXPR evaluates against the last run's variables
+        if let Some(expr) = trimmed.strip_prefix('?') {
+            let vm = last_vm.get_or_insert_with(VirtualMachine::new);
+            match evaluate_expression(vm, &last_names, expr) {
+                Ok(value) => println!("{}", format_value(&value)),
+                Err(e) => eprintln!("Error: {}", e),
+            }
+            continue;
+        }
+
+        let mut words = trimmed.split_whitespace();
+        let command = words.next().unwrap_or("").to_uppercase();
+
+        match command.as_str() {
+            "EXIT" | "QUIT" if words.clone().next().is_none() => break,
+            "HELP" => {
+                println!("Commands:");
+                println!("  run        - Run the current program");
+                println!("  clear      - Clear the current program");
+                println!("  list       - List the current program");
+                println!("  vars       - List variables from the last run");
+                println!("  dump NAME  - Show the contents of an array from the last run");
+                println!("  ? EXPR     - Evaluate an expression against the last run");
+                println!("  exit       - Exit the REPL");
+                println!("Press TAB to complete keywords, built-ins and variable names.");
+                println!();
+            }
+            "CLEAR" => {
+                program_lines.clear();
+                line_num = 10;
+                last_vm = None;
+                last_names = Names::new();
+                if let Some(helper) = editor.helper_mut() {
+                    helper.variables.clear();
+                }
+                println!("Program cleared.");
+            }
+            "LIST" => {
+                if program_lines.is_empty() {
+                    println!("No program loaded.");
+                } else {
+                    for (i, line) in program_lines.iter().enumerate() {
+                        println!("{} {}", (i + 1) * 10, line);
+                    }
+                }
+            }
+            "RUN" => {
+                if program_lines.is_empty() {
+                    println!("No program to run.");
+                } else if let Some((vm, names)) = run_program(&program_lines.join("\n")) {
+                    if let Some(helper) = editor.helper_mut() {
+                        helper.variables.extend(vm.variables().into_iter().map(|(n, _)| n.to_string()));
+                        helper.variables.extend(vm.array_names().into_iter().map(String::from));
+                    }
+                    last_vm = Some(vm);
+                    last_names = names;
+                }
+            }
+            "VARS" => match &last_vm {
+                Some(vm) => print_vars(vm),
+                None => println!("No variables yet; use 'run' first."),
+            },
+            "DUMP" => match (&last_vm, words.next()) {
+                (Some(vm), Some(name)) => dump_array(vm, &last_names, name),
+                (None, _) => println!("No arrays yet; use 'run' first."),
+                (_, None) => println!("Usage: dump NAME"),
+            },
+            _ => {
+                if !trimmed.is_empty() {
+                    program_lines.push(input);
+                    line_num += 10;
+                    if let Some(helper) = editor.helper_mut() {
+                        collect_program_names(&program_lines, &mut helper.variables);
+                    }
+                }
+            }
+        }
+    }
+
+    println!("\nGoodbye!");
+    Ok(())
+}
+
+/// Compile and run the program, returning the VM and the program's names so
+/// its state can be inspected
+fn run_program(source: &str) -> Option<(VirtualMachine, Names)> {
+    let Artifacts { names, bytecode, .. } = build(source, &Options::default()).map_err(|e| eprintln!("{}", e)).ok()?;
+    let mut vm = VirtualMachine::new();
+    match vm.execute(&bytecode) {
+        Err(e @ QError::Break { .. }) => println!("{}", e),
+        Err(e) => eprintln!("{}", e),
+        Ok(()) => {}
+    }
+    Some((vm, names))
+}
+
+/// Add variable names declared or used by the program typed so far.
+/// Incomplete programs (e.g. an open FOR block) simply contribute nothing.
+fn collect_program_names(lines: &[String], names: &mut BTreeSet<String>) {
+    let Ok(tokens) = tokenize(&lines.join("\n")) else { return };
+    let Ok(ast) = parse(tokens) else { return };
+    for symbol in build_index(&ast).symbols {
+        if symbol.kind.is_variable() {
+            names.insert(symbol.name);
+        }
+    }
+}
+
+fn print_vars(vm: &VirtualMachine) {
+    let vars = vm.variables();
+    let arrays = vm.array_names();
+    if vars.is_empty() && arrays.is_empty() {
+        println!("No variables.");
+        return;
+    }
+    println!("{:<20} {:<12} VALUE", "NAME", "TYPE");
+    for (name, value) in vars {
+        println!("{:<20} {:<12} {}", name, value.type_name(), format_value(value));
+    }
+    for (name, view) in vm.arrays() {
+        println!("{:<20} {:<12} {}", format!("{}()", name), view.element_type(), format_bounds(view.bounds));
+    }
+}
+
+fn dump_array(vm: &VirtualMachine, names: &Names, name: &str) {
+    let name = VariableId::new(name.trim_end_matches("()").to_uppercase(), None);
+    let name = names.resolve(&name).full_name();
+    let Some(view) = vm.array(&name) else {
+        println!("No array named {}", name);
+        return;
+    };
+    println!("{}{}", name, format_bounds(view.bounds));
+    for (offset, value) in view.elements.iter().enumerate() {
+        let subscripts: Vec<String> = view.subscripts(offset).iter().map(|i| i.to_string()).collect();
+        println!("  {}({}) = {}", name, subscripts.join(", "), format_value(value));
+    }
+}
+
+fn format_bounds(shape: &[(i32, i32)]) -> String {
+    let dims: Vec<String> = shape.iter().map(|(lo, hi)| format!("{} TO {}", lo, hi)).collect();
+    format!("({})", dims.join(", "))
+}
+
+fn format_value(value: &QType) -> String {
+    match value {
+        QType::String(s) | QType::FixedString(_, s) => format!("\"{}\"", s),
+        other => other.to_string(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_completion_candidates() {
+        let mut variables = BTreeSet::new();
+        collect_program_names(&["total = 1".into(), "FOR i = 1 TO 3".into()], &mut variables);
+        // An open FOR block does not parse, so only complete lines count
+        assert!(variables.is_empty());
+        collect_program_names(&["total = 1".into(), "DIM names$(3)".into()], &mut variables);
+        let helper = ReplHelper { variables };
+        assert_eq!(helper.candidates("tot"), vec!["TOTAL"]);
+        assert_eq!(helper.candidates("names"), vec!["NAMES$"]);
+        assert!(helper.candidates("du").contains(&"DUMP".to_string()));
+        assert!(helper.candidates("PRI").contains(&"PRINT".to_string()));
+    }
+}
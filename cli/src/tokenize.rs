@@ -0,0 +1,113 @@
+//! `qb tokenize`: list the tokens of a program, optionally lined up under
+//! the source text and colorized
+
+use anyhow::{bail, Context, Result};
+use std::fs;
+use std::path::Path;
+
+use qb_core::errors::QError;
+use qb_lexer::{tokenize_recovering, Token, TokenInfo};
+
+const RESET: &str = "\x1b[0m";
+const RED: &str = "\x1b[31m";
+
+/// ANSI color for a token's category: keywords, literals, operators and
+/// punctuation; names are left plain
+fn token_color(token: &Token) -> &'static str {
+    match token {
+        Token::String(_) => "\x1b[32m",
+        Token::Integer(_) | Token::Long(_) | Token::Single(_) | Token::Double(_) | Token::LineNumber(_) => {
+            "\x1b[36m"
+        }
+        Token::Identifier(_) | Token::Label(_) => "",
+        Token::Rem => "\x1b[90m",
+        Token::LParen | Token::RParen | Token::LBracket | Token::RBracket | Token::Comma
+        | Token::Semicolon | Token::Colon | Token::Hash | Token::Period => "\x1b[33m",
+        t if t.is_binary_op() || t.is_type_suffix() => "\x1b[33m",
+        _ => "\x1b[1;34m",
+    }
+}
+
+/// Message, line and column of a lexical error
+fn error_position(error: &QError) -> (String, usize, usize) {
+    match error {
+        QError::Compile { message, line, column } => (message.clone(), *line, *column),
+        other => (other.to_string(), 0, 0),
+    }
+}
+
+pub fn tokenize_file(file: &Path, detailed: bool, color: bool) -> Result<()> {
+    let source = fs::read_to_string(file)
+        .with_context(|| format!("Failed to read file: {}", file.display()))?;
+
+    let (tokens, errors) = tokenize_recovering(&source);
+
+    if detailed || color {
+        print_detailed(&source, &tokens, &errors, color);
+    } else {
+        for (i, token_info) in tokens.iter().enumerate() {
+            println!("{:4}: {:?} (line {}, col {})",
+                i,
+                token_info.token,
+                token_info.line,
+                token_info.column
+            );
+        }
+        for error in &errors {
+            let (message, line, column) = error_position(error);
+            eprintln!("error: {} (line {}, col {})", message, line, column);
+        }
+    }
+
+    if !errors.is_empty() {
+        bail!("{} lexical error(s) in {}", errors.len(), file.display());
+    }
+    Ok(())
+}
+
+/// Each source line followed by its tokens, each underlined at its span
+fn print_detailed(source: &str, tokens: &[TokenInfo], errors: &[QError], color: bool) {
+    let errors: Vec<(String, usize, usize)> = errors.iter().map(error_position).collect();
+    let paint = |text: &str, code: &str| {
+        if color && !code.is_empty() {
+            format!("{}{}{}", code, text, RESET)
+        } else {
+            text.to_string()
+        }
+    };
+
+    for (index, text) in source.lines().enumerate() {
+        let line = index + 1;
+        // Columns count characters, so show a tab as a single space
+        let chars: Vec<char> = text.trim_end_matches('\r').chars().map(|c| if c == '\t' { ' ' } else { c }).collect();
+        let on_line: Vec<&TokenInfo> = tokens
+            .iter()
+            .filter(|t| t.line == line && !matches!(t.token, Token::NewLine | Token::EOF))
+            .collect();
+
+        // The source line, colored by the token covering each character
+        let mut shown = String::new();
+        let mut column = 1;
+        for t in &on_line {
+            if t.column < column || t.column > chars.len() {
+                continue;
+            }
+            shown.extend(&chars[column - 1..t.column - 1]);
+            let end = (t.column - 1 + t.length).min(chars.len());
+            let span: String = chars[t.column - 1..end].iter().collect();
+            shown.push_str(&paint(&span, token_color(&t.token)));
+            column = end + 1;
+        }
+        shown.extend(chars.iter().skip(column - 1));
+        println!("{:5} | {}", line, shown);
+
+        for t in &on_line {
+            let marker = "^".repeat(t.length.max(1));
+            println!("      | {}{} {:?}", " ".repeat(t.column - 1), paint(&marker, token_color(&t.token)), t.token);
+        }
+        for (message, _, column) in errors.iter().filter(|(_, l, _)| *l == line) {
+            let marker = paint("^", RED);
+            println!("      | {}{} {}", " ".repeat(column.saturating_sub(1)), marker, paint(&format!("error: {}", message), RED));
+        }
+    }
+}
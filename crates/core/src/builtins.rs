@@ -0,0 +1,203 @@
+//! The builtin functions. Each is listed once with the arguments it takes
+//! and the type it returns; the lexer's keywords, the parser, the type
+//! checker and the bytecode compiler all look names up here.
+
+use crate::data_types::QType;
+
+/// Type of a builtin's result
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Returns {
+    Integer,
+    Long,
+    Single,
+    Double,
+    String,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Builtin {
+    pub name: &'static str,
+    /// Fewest arguments it takes
+    pub min_args: usize,
+    /// Most arguments it takes
+    pub max_args: usize,
+    pub returns: Returns,
+}
+
+impl Builtin {
+    /// Whether a call may pass `count` arguments
+    pub fn accepts(&self, count: usize) -> bool {
+        (self.min_args..=self.max_args).contains(&count)
+    }
+
+    /// Zero value of the result type
+    pub fn return_type(&self) -> QType {
+        match self.returns {
+            Returns::Integer => QType::Integer(0),
+            Returns::Long => QType::Long(0),
+            Returns::Single => QType::Single(0.0),
+            Returns::Double => QType::Double(0.0),
+            Returns::String => QType::String(String::new()),
+        }
+    }
+}
+
+const fn builtin(name: &'static str, min_args: usize, max_args: usize, returns: Returns) -> Builtin {
+    Builtin { name, min_args, max_args, returns }
+}
+
+pub const BUILTINS: &[Builtin] = &[
+    // Math
+    builtin("ABS", 1, 1, Returns::Single),
+    builtin("ATN", 1, 1, Returns::Single),
+    builtin("COS", 1, 1, Returns::Single),
+    builtin("EXP", 1, 1, Returns::Single),
+    builtin("FIX", 1, 1, Returns::Single),
+    builtin("INT", 1, 1, Returns::Single),
+    builtin("LOG", 1, 1, Returns::Single),
+    builtin("RND", 0, 1, Returns::Single),
+    builtin("SGN", 1, 1, Returns::Single),
+    builtin("SIN", 1, 1, Returns::Single),
+    builtin("SQR", 1, 1, Returns::Single),
+    builtin("TAN", 1, 1, Returns::Single),
+    // Strings
+    builtin("ASC", 1, 2, Returns::Integer),
+    builtin("CHR$", 1, 1, Returns::String),
+    builtin("INSTR", 2, 3, Returns::Integer),
+    builtin("_INSTRREV", 2, 3, Returns::Long),
+    builtin("LCASE$", 1, 1, Returns::String),
+    builtin("LEFT$", 2, 2, Returns::String),
+    builtin("LEN", 1, 1, Returns::Integer),
+    builtin("LTRIM$", 1, 1, Returns::String),
+    builtin("MID$", 2, 3, Returns::String),
+    builtin("RIGHT$", 2, 2, Returns::String),
+    builtin("RTRIM$", 1, 1, Returns::String),
+    builtin("SPACE$", 1, 1, Returns::String),
+    builtin("STR$", 1, 1, Returns::String),
+    builtin("STRING$", 2, 2, Returns::String),
+    builtin("TRIM$", 1, 1, Returns::String),
+    builtin("UCASE$", 1, 1, Returns::String),
+    builtin("VAL", 1, 1, Returns::Double),
+    // Conversion
+    builtin("CDBL", 1, 1, Returns::Double),
+    builtin("CINT", 1, 1, Returns::Integer),
+    builtin("CLNG", 1, 1, Returns::Long),
+    builtin("CSNG", 1, 1, Returns::Single),
+    builtin("CSTR", 1, 1, Returns::String),
+    // Numbers packed in strings, as random-access records hold them
+    builtin("CVD", 1, 1, Returns::Double),
+    builtin("CVI", 1, 1, Returns::Integer),
+    builtin("CVL", 1, 1, Returns::Long),
+    builtin("CVS", 1, 1, Returns::Single),
+    builtin("MKD$", 1, 1, Returns::String),
+    builtin("MKI$", 1, 1, Returns::String),
+    builtin("MKL$", 1, 1, Returns::String),
+    builtin("MKS$", 1, 1, Returns::String),
+    // Time and keyboard
+    builtin("DATE$", 0, 0, Returns::String),
+    builtin("INKEY$", 0, 0, Returns::String),
+    builtin("TIME$", 0, 0, Returns::String),
+    builtin("TIMER", 0, 1, Returns::Single),
+    // Memory
+    builtin("FRE", 1, 1, Returns::Long),
+    builtin("PEEK", 1, 1, Returns::Integer),
+    builtin("VARPTR$", 1, 1, Returns::String),
+    // Arrays
+    builtin("LBOUND", 1, 2, Returns::Integer),
+    builtin("UBOUND", 1, 2, Returns::Integer),
+    // Error trapping
+    builtin("ERL", 0, 0, Returns::Long),
+    builtin("ERR", 0, 0, Returns::Integer),
+    // Files and the command line
+    builtin("COMMAND$", 0, 1, Returns::String),
+    builtin("EOF", 1, 1, Returns::Integer),
+    builtin("FREEFILE", 0, 0, Returns::Integer),
+    builtin("LOC", 1, 1, Returns::Long),
+    builtin("LOF", 1, 1, Returns::Long),
+    // ISAM, which is not there to call
+    builtin("BOF", 1, 1, Returns::Integer),
+    builtin("GETINDEX$", 1, 1, Returns::String),
+    builtin("TEXTCOMP", 2, 2, Returns::Integer),
+    // Sound and joystick
+    builtin("PLAY", 1, 1, Returns::Integer),
+    builtin("STICK", 1, 1, Returns::Integer),
+    builtin("STRIG", 1, 1, Returns::Integer),
+    // QB64 input devices
+    builtin("_AXIS", 1, 1, Returns::Single),
+    builtin("_BUTTON", 1, 1, Returns::Integer),
+    builtin("_DEVICE$", 1, 1, Returns::String),
+    builtin("_DEVICEINPUT", 0, 1, Returns::Integer),
+    builtin("_DEVICES", 0, 0, Returns::Integer),
+    builtin("_LASTAXIS", 1, 1, Returns::Integer),
+    builtin("_LASTBUTTON", 1, 1, Returns::Integer),
+    // QB64 sound
+    builtin("_SNDOPEN", 1, 2, Returns::Long),
+    builtin("_SNDPLAYING", 1, 1, Returns::Integer),
+    builtin("_SNDRATE", 0, 0, Returns::Long),
+    builtin("_SNDRAWLEN", 0, 0, Returns::Double),
+    // QB64 window and console
+    builtin("_CONSOLE", 0, 0, Returns::Long),
+    builtin("_RESIZE", 0, 0, Returns::Integer),
+    builtin("_FULLSCREEN", 0, 0, Returns::Integer),
+    builtin("_RESIZEHEIGHT", 0, 0, Returns::Long),
+    builtin("_RESIZEWIDTH", 0, 0, Returns::Long),
+    // QB64 images
+    builtin("_COPYIMAGE", 0, 1, Returns::Long),
+    builtin("_DEST", 0, 0, Returns::Long),
+    builtin("_HEIGHT", 0, 1, Returns::Long),
+    builtin("_LOADIMAGE", 1, 2, Returns::Long),
+    builtin("_NEWIMAGE", 2, 3, Returns::Long),
+    builtin("_PRINTWIDTH", 1, 2, Returns::Long),
+    builtin("_SOURCE", 0, 0, Returns::Long),
+    builtin("_WIDTH", 0, 1, Returns::Long),
+    // QB64 colors
+    builtin("_ALPHA", 1, 2, Returns::Long),
+    builtin("_ALPHA32", 1, 1, Returns::Long),
+    builtin("_BLUE", 1, 2, Returns::Long),
+    builtin("_BLUE32", 1, 1, Returns::Long),
+    builtin("_GREEN", 1, 2, Returns::Long),
+    builtin("_GREEN32", 1, 1, Returns::Long),
+    builtin("_RED", 1, 2, Returns::Long),
+    builtin("_RED32", 1, 1, Returns::Long),
+    builtin("_RGB", 3, 4, Returns::Long),
+    builtin("_RGB32", 1, 4, Returns::Long),
+    builtin("_RGBA", 4, 5, Returns::Long),
+    builtin("_RGBA32", 4, 4, Returns::Long),
+    // QB64 memory blocks
+    builtin("_MEMIMAGE", 0, 1, Returns::Long),
+    builtin("_MEMNEW", 1, 1, Returns::Long),
+    // QB64 networking
+    builtin("_CONNECTED", 1, 1, Returns::Long),
+    builtin("_CONNECTIONADDRESS$", 1, 1, Returns::String),
+    builtin("_OPENCLIENT", 1, 1, Returns::Long),
+    builtin("_OPENCONNECTION", 1, 1, Returns::Long),
+    builtin("_OPENHOST", 1, 1, Returns::Long),
+    // Reflection, for debugging
+    builtin("_CALLDEPTH", 0, 0, Returns::Long),
+    builtin("_PROGRAMNAME$", 0, 0, Returns::String),
+    builtin("_SOURCELINE", 0, 0, Returns::Long),
+    builtin("_SOURCELINE$", 0, 0, Returns::String),
+    // QB64 time
+    builtin("_TIMER64", 0, 0, Returns::Double),
+];
+
+/// The builtin called `name`, in any case
+pub fn lookup(name: &str) -> Option<&'static Builtin> {
+    BUILTINS.iter().find(|b| b.name.eq_ignore_ascii_case(name))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_lookup() {
+        assert_eq!(lookup("instr").map(|b| (b.min_args, b.max_args)), Some((2, 3)));
+        assert_eq!(lookup("STRING$").map(|b| b.returns), Some(Returns::String));
+        assert!(lookup("RND").is_some_and(|b| b.accepts(0) && b.accepts(1) && !b.accepts(2)));
+        assert!(lookup("NOSUCH").is_none());
+        for (i, b) in BUILTINS.iter().enumerate() {
+            assert!(BUILTINS[i + 1..].iter().all(|other| other.name != b.name), "{} listed twice", b.name);
+        }
+    }
+}
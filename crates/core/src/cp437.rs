@@ -0,0 +1,50 @@
+//! Code page 437, the character set of the IBM PC. Strings hold Unicode
+//! characters; under QB 4.5 CHR$, ASC and string comparison go through
+//! this table so character codes and sort order match DOS.
+
+/// Characters for codes 128-255. Codes below 128 are ASCII.
+const UPPER: [char; 128] = [
+    'Ç', 'ü', 'é', 'â', 'ä', 'à', 'å', 'ç', 'ê', 'ë', 'è', 'ï', 'î', 'ì', 'Ä', 'Å',
+    'É', 'æ', 'Æ', 'ô', 'ö', 'ò', 'û', 'ù', 'ÿ', 'Ö', 'Ü', '¢', '£', '¥', '₧', 'ƒ',
+    'á', 'í', 'ó', 'ú', 'ñ', 'Ñ', 'ª', 'º', '¿', '⌐', '¬', '½', '¼', '¡', '«', '»',
+    '░', '▒', '▓', '│', '┤', '╡', '╢', '╖', '╕', '╣', '║', '╗', '╝', '╜', '╛', '┐',
+    '└', '┴', '┬', '├', '─', '┼', '╞', '╟', '╚', '╔', '╩', '╦', '╠', '═', '╬', '╧',
+    '╨', '╤', '╥', '╙', '╘', '╒', '╓', '╫', '╪', '┘', '┌', '█', '▄', '▌', '▐', '▀',
+    'α', 'ß', 'Γ', 'π', 'Σ', 'σ', 'µ', 'τ', 'Φ', 'Θ', 'Ω', 'δ', '∞', 'φ', 'ε', '∩',
+    '≡', '±', '≥', '≤', '⌠', '⌡', '÷', '≈', '°', '∙', '·', '√', 'ⁿ', '²', '■', '\u{A0}',
+];
+
+/// The character for a code
+pub fn to_char(code: u8) -> char {
+    if code < 0x80 { char::from(code) } else { UPPER[usize::from(code - 0x80)] }
+}
+
+/// The code of a character, if code page 437 has it
+pub fn from_char(c: char) -> Option<u8> {
+    if c.is_ascii() {
+        return Some(c as u8);
+    }
+    UPPER.iter().position(|&u| u == c).map(|i| 0x80 + i as u8)
+}
+
+/// Sort key of a character: its code, with characters outside the code
+/// page after all of them
+pub fn collation_key(c: char) -> u32 {
+    from_char(c).map_or(0x100 + u32::from(c), u32::from)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_round_trip() {
+        for code in 0..=255u8 {
+            assert_eq!(from_char(to_char(code)), Some(code));
+        }
+        assert_eq!(to_char(201), '╔');
+        assert_eq!(from_char('é'), Some(130));
+        assert_eq!(from_char('€'), None);
+        assert!(collation_key('€') > collation_key('\u{A0}'));
+    }
+}
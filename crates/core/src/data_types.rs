@@ -1,4 +1,5 @@
 use std::fmt;
+use crate::dialect::Dialect;
 use crate::errors::{QError, QErrorCode, QResult};
 
 /// QBasic type suffixes
@@ -40,6 +41,7 @@ impl TypeSuffix {
         }
     }
     
+    #[allow(clippy::should_implement_trait)]
     pub fn from_str(s: &str) -> Option<Self> {
         match s {
             "%" => Some(TypeSuffix::Integer),
@@ -52,6 +54,26 @@ impl TypeSuffix {
             _ => None,
         }
     }
+
+    /// Suffix a variable name ends in, if any
+    pub fn of_name(name: &str) -> Option<Self> {
+        ["&&", "##", "%", "&", "!", "#", "$"]
+            .iter()
+            .find(|s| name.ends_with(*s))
+            .and_then(|s| Self::from_str(s))
+    }
+
+    /// Initial value of a variable with this suffix
+    pub fn default_value(&self) -> QType {
+        match self {
+            TypeSuffix::Integer => QType::Integer(0),
+            TypeSuffix::Long => QType::Long(0),
+            TypeSuffix::Single => QType::Single(0.0),
+            TypeSuffix::Double | TypeSuffix::Float => QType::Double(0.0),
+            TypeSuffix::String => QType::String(String::new()),
+            TypeSuffix::Integer64 => QType::Integer64(0),
+        }
+    }
 }
 
 /// QBasic data types
@@ -155,25 +177,30 @@ impl QType {
         matches!(self, QType::String(_) | QType::FixedString(_, _))
     }
 
-    /// Convert to integer
+    /// Convert to integer, rounding halves to even as CINT does
     pub fn to_integer(&self) -> QResult<i16> {
         match self {
             QType::Integer(v) => Ok(*v),
-            QType::Long(v) => Ok(*v as i16),
-            QType::Single(v) => Ok(*v as i16),
-            QType::Double(v) => Ok(*v as i16),
-            _ => Err(QError::runtime(QErrorCode::TypeMismatch, 0, 0)),
+            _ => Ok(Self::rounded(self.to_double()?, i16::MIN.into(), i16::MAX.into())? as i16),
         }
     }
 
-    /// Convert to long
+    /// Convert to long, rounding halves to even as CLNG does
     pub fn to_long(&self) -> QResult<i32> {
         match self {
             QType::Integer(v) => Ok(*v as i32),
             QType::Long(v) => Ok(*v),
-            QType::Single(v) => Ok(*v as i32),
-            QType::Double(v) => Ok(*v as i32),
-            _ => Err(QError::runtime(QErrorCode::TypeMismatch, 0, 0)),
+            _ => Ok(Self::rounded(self.to_double()?, i32::MIN.into(), i32::MAX.into())? as i32),
+        }
+    }
+
+    /// `value` rounded half to even; Overflow unless it lies in min..=max
+    fn rounded(value: f64, min: f64, max: f64) -> QResult<f64> {
+        let value = value.round_ties_even();
+        if (min..=max).contains(&value) {
+            Ok(value)
+        } else {
+            Err(QError::runtime(QErrorCode::Overflow, 0, 0))
         }
     }
 
@@ -183,6 +210,9 @@ impl QType {
             QType::Integer(v) => Ok(*v as f32),
             QType::Long(v) => Ok(*v as f32),
             QType::Single(v) => Ok(*v),
+            QType::Double(v) if v.is_finite() && v.abs() > f32::MAX as f64 => {
+                Err(QError::runtime(QErrorCode::Overflow, 0, 0))
+            }
             QType::Double(v) => Ok(*v as f32),
             _ => Err(QError::runtime(QErrorCode::TypeMismatch, 0, 0)),
         }
@@ -195,6 +225,10 @@ impl QType {
             QType::Long(v) => Ok(*v as f64),
             QType::Single(v) => Ok(*v as f64),
             QType::Double(v) => Ok(*v),
+            QType::Integer64(v) => Ok(*v as f64),
+            QType::UnsignedInteger(v) => Ok(*v as f64),
+            QType::UnsignedLong(v) => Ok(*v as f64),
+            QType::UnsignedInteger64(v) => Ok(*v as f64),
             _ => Err(QError::runtime(QErrorCode::TypeMismatch, 0, 0)),
         }
     }
@@ -206,17 +240,134 @@ impl QType {
             QType::FixedString(_, s) => Ok(s.clone()),
             QType::Integer(v) => Ok(v.to_string()),
             QType::Long(v) => Ok(v.to_string()),
-            QType::Single(v) => Ok(v.to_string()),
-            QType::Double(v) => Ok(v.to_string()),
+            QType::Single(_) | QType::Double(_) => Ok(self.to_string()),
             _ => Err(QError::runtime(QErrorCode::TypeMismatch, 0, 0)),
         }
     }
 
+    /// Convert to the type of `template`, as when assigning to a typed variable
+    pub fn convert_to(&self, template: &QType) -> QResult<QType> {
+        // Negative values wrap around into the unsigned types
+        let unsigned = |v: f64| if v < 0.0 { v as i64 as u64 } else { v as u64 };
+        Ok(match template {
+            QType::Integer(_) => QType::Integer(self.to_integer()?),
+            QType::Long(_) => QType::Long(self.to_long()?),
+            QType::Single(_) => QType::Single(self.to_single()?),
+            QType::Double(_) => QType::Double(self.to_double()?),
+            QType::Integer64(_) => match self {
+                QType::Integer64(_) => self.clone(),
+                _ => QType::Integer64(Self::rounded(self.to_double()?, i64::MIN as f64, i64::MAX as f64)? as i64),
+            },
+            QType::UnsignedInteger(_) => QType::UnsignedInteger(unsigned(self.to_double()?) as u16),
+            QType::UnsignedLong(_) => QType::UnsignedLong(unsigned(self.to_double()?) as u32),
+            QType::UnsignedInteger64(_) => QType::UnsignedInteger64(unsigned(self.to_double()?)),
+            QType::String(_) => QType::String(self.to_qstring()?),
+            QType::FixedString(len, _) => {
+                let mut s: String = self.to_qstring()?.chars().take(*len).collect();
+                let pad = len - s.chars().count();
+                s.extend(std::iter::repeat_n(' ', pad));
+                QType::FixedString(*len, s)
+            }
+            _ => return Err(QError::runtime(QErrorCode::TypeMismatch, 0, 0)),
+        })
+    }
+
+    /// Memory image of the value: little-endian numbers, one byte per
+    /// string character in the character set of `dialect`, '?' for one
+    /// it lacks
+    pub fn to_bytes(&self, dialect: Dialect) -> Vec<u8> {
+        match self {
+            QType::Integer(v) => v.to_le_bytes().to_vec(),
+            QType::Long(v) => v.to_le_bytes().to_vec(),
+            QType::Single(v) => v.to_le_bytes().to_vec(),
+            QType::Double(v) => v.to_le_bytes().to_vec(),
+            QType::Integer64(v) => v.to_le_bytes().to_vec(),
+            QType::UnsignedInteger(v) => v.to_le_bytes().to_vec(),
+            QType::UnsignedLong(v) => v.to_le_bytes().to_vec(),
+            QType::UnsignedInteger64(v) => v.to_le_bytes().to_vec(),
+            QType::String(s) | QType::FixedString(_, s) => {
+                s.chars().map(|c| dialect.char_byte(c).unwrap_or(b'?')).collect()
+            }
+            QType::UserDefined(bytes) => bytes.clone(),
+            QType::Empty | QType::Null => Vec::new(),
+        }
+    }
+
+    /// Read a value of the same type as `self` back from its memory image;
+    /// `bytes` must be as long as the value (a plain STRING takes all of it)
+    pub fn from_bytes(&self, bytes: &[u8], dialect: Dialect) -> QType {
+        fn array<const N: usize>(bytes: &[u8]) -> [u8; N] {
+            let mut out = [0; N];
+            out.copy_from_slice(&bytes[..N]);
+            out
+        }
+        let text = || bytes.iter().map(|&b| dialect.byte_char(b)).collect::<String>();
+        match self {
+            QType::Integer(_) => QType::Integer(i16::from_le_bytes(array(bytes))),
+            QType::Long(_) => QType::Long(i32::from_le_bytes(array(bytes))),
+            QType::Single(_) => QType::Single(f32::from_le_bytes(array(bytes))),
+            QType::Double(_) => QType::Double(f64::from_le_bytes(array(bytes))),
+            QType::Integer64(_) => QType::Integer64(i64::from_le_bytes(array(bytes))),
+            QType::UnsignedInteger(_) => QType::UnsignedInteger(u16::from_le_bytes(array(bytes))),
+            QType::UnsignedLong(_) => QType::UnsignedLong(u32::from_le_bytes(array(bytes))),
+            QType::UnsignedInteger64(_) => QType::UnsignedInteger64(u64::from_le_bytes(array(bytes))),
+            QType::String(_) => QType::String(text()),
+            QType::FixedString(len, _) => QType::FixedString(*len, text()),
+            QType::UserDefined(_) => QType::UserDefined(bytes.to_vec()),
+            QType::Empty => QType::Empty,
+            QType::Null => QType::Null,
+        }
+    }
+
+    /// MKI$, MKL$, MKS$ and MKD$: the value converted to the type of
+    /// `template`, its memory image a string of one character per byte as
+    /// CHR$ of `dialect` gives them
+    pub fn to_binary_string(&self, template: &QType, dialect: Dialect) -> QResult<String> {
+        let bytes = self.convert_to(template)?.to_bytes(dialect);
+        Ok(bytes.into_iter().map(|b| dialect.byte_char(b)).collect())
+    }
+
+    /// CVI, CVL, CVS and CVD: the value of the type of `template` whose
+    /// memory image starts `text`, each character the byte ASC of
+    /// `dialect` gives for it
+    pub fn from_binary_string(text: &str, template: &QType, dialect: Dialect) -> QResult<QType> {
+        let illegal = || QError::runtime(QErrorCode::IllegalFunctionCall, 0, 0);
+        let bytes = text
+            .chars()
+            .take(template.size())
+            .map(|c| dialect.char_byte(c).ok_or_else(illegal))
+            .collect::<QResult<Vec<u8>>>()?;
+        if bytes.len() < template.size() {
+            return Err(illegal());
+        }
+        Ok(template.from_bytes(&bytes, dialect))
+    }
+
+    /// The result of INTEGER or LONG arithmetic, `long` saying which, worked
+    /// out wider. One that does not fit is an Overflow, or under modern
+    /// arithmetic a value of the next type that holds it.
+    fn integer_result(value: i64, long: bool, arithmetic: Arithmetic) -> QResult<QType> {
+        let modern = arithmetic == Arithmetic::Modern;
+        if let (false, Ok(v)) = (long, i16::try_from(value)) {
+            return Ok(QType::Integer(v));
+        }
+        match i32::try_from(value) {
+            Ok(v) if long || modern => Ok(QType::Long(v)),
+            _ if modern => Ok(QType::Double(value as f64)),
+            _ => Err(QError::runtime(QErrorCode::Overflow, 0, 0)),
+        }
+    }
+
     /// Negate the value
     pub fn negate(&self) -> QResult<QType> {
+        self.negate_in(Arithmetic::default())
+    }
+
+    /// Negate the value, overflowing as `arithmetic` does
+    pub fn negate_in(&self, arithmetic: Arithmetic) -> QResult<QType> {
         match self {
-            QType::Integer(v) => Ok(QType::Integer(-v)),
-            QType::Long(v) => Ok(QType::Long(-v)),
+            QType::Integer(v) => Self::integer_result(-i64::from(*v), false, arithmetic),
+            QType::Long(v) => Self::integer_result(-i64::from(*v), true, arithmetic),
             QType::Single(v) => Ok(QType::Single(-v)),
             QType::Double(v) => Ok(QType::Double(-v)),
             _ => Err(QError::runtime(QErrorCode::TypeMismatch, 0, 0)),
@@ -225,6 +376,11 @@ impl QType {
 
     /// Add two values
     pub fn add(&self, other: &QType) -> QResult<QType> {
+        self.add_in(other, Arithmetic::default())
+    }
+
+    /// Add two values, overflowing as `arithmetic` does
+    pub fn add_in(&self, other: &QType, arithmetic: Arithmetic) -> QResult<QType> {
         match (self, other) {
             // String concatenation
             (QType::String(a), QType::String(b)) => Ok(QType::String(format!("{}{}", a, b))),
@@ -232,105 +388,151 @@ impl QType {
             (a, QType::String(b)) => Ok(QType::String(format!("{}{}", a.to_qstring()?, b))),
             
             // Numeric addition with promotion
-            (QType::Double(a), b) => Ok(QType::Double(a + b.to_double()?)),
-            (a, QType::Double(b)) => Ok(QType::Double(a.to_double()? + b)),
-            (QType::Single(a), b) => Ok(QType::Single(a + b.to_single()?)),
-            (a, QType::Single(b)) => Ok(QType::Single(a.to_single()? + b)),
-            (QType::Long(a), b) => Ok(QType::Long(a + b.to_long()?)),
-            (a, QType::Long(b)) => Ok(QType::Long(a.to_long()? + b)),
-            (QType::Integer(a), QType::Integer(b)) => {
-                let result = a.wrapping_add(*b);
-                Ok(QType::Integer(result))
-            }
+            (QType::Double(a), b) => Self::double_result(a + b.to_double()?),
+            (a, QType::Double(b)) => Self::double_result(a.to_double()? + b),
+            (QType::Single(a), b) => Self::single_result(a + b.to_single()?),
+            (a, QType::Single(b)) => Self::single_result(a.to_single()? + b),
+            (QType::Long(a), b) => Self::integer_result(i64::from(*a) + i64::from(b.to_long()?), true, arithmetic),
+            (a, QType::Long(b)) => Self::integer_result(i64::from(a.to_long()?) + i64::from(*b), true, arithmetic),
+            (QType::Integer(a), QType::Integer(b)) => Self::integer_result(i64::from(*a) + i64::from(*b), false, arithmetic),
             _ => Err(QError::runtime(QErrorCode::TypeMismatch, 0, 0)),
         }
     }
 
     /// Subtract two values
     pub fn subtract(&self, other: &QType) -> QResult<QType> {
+        self.subtract_in(other, Arithmetic::default())
+    }
+
+    /// Subtract two values, overflowing as `arithmetic` does
+    pub fn subtract_in(&self, other: &QType, arithmetic: Arithmetic) -> QResult<QType> {
         match (self, other) {
-            (QType::Double(a), b) => Ok(QType::Double(a - b.to_double()?)),
-            (a, QType::Double(b)) => Ok(QType::Double(a.to_double()? - b)),
-            (QType::Single(a), b) => Ok(QType::Single(a - b.to_single()?)),
-            (a, QType::Single(b)) => Ok(QType::Single(a.to_single()? - b)),
-            (QType::Long(a), b) => Ok(QType::Long(a - b.to_long()?)),
-            (a, QType::Long(b)) => Ok(QType::Long(a.to_long()? - b)),
-            (QType::Integer(a), QType::Integer(b)) => {
-                let result = a.wrapping_sub(*b);
-                Ok(QType::Integer(result))
-            }
+            (QType::Double(a), b) => Self::double_result(a - b.to_double()?),
+            (a, QType::Double(b)) => Self::double_result(a.to_double()? - b),
+            (QType::Single(a), b) => Self::single_result(a - b.to_single()?),
+            (a, QType::Single(b)) => Self::single_result(a.to_single()? - b),
+            (QType::Long(a), b) => Self::integer_result(i64::from(*a) - i64::from(b.to_long()?), true, arithmetic),
+            (a, QType::Long(b)) => Self::integer_result(i64::from(a.to_long()?) - i64::from(*b), true, arithmetic),
+            (QType::Integer(a), QType::Integer(b)) => Self::integer_result(i64::from(*a) - i64::from(*b), false, arithmetic),
             _ => Err(QError::runtime(QErrorCode::TypeMismatch, 0, 0)),
         }
     }
 
     /// Multiply two values
     pub fn multiply(&self, other: &QType) -> QResult<QType> {
+        self.multiply_in(other, Arithmetic::default())
+    }
+
+    /// Multiply two values, overflowing as `arithmetic` does
+    pub fn multiply_in(&self, other: &QType, arithmetic: Arithmetic) -> QResult<QType> {
         match (self, other) {
-            (QType::Double(a), b) => Ok(QType::Double(a * b.to_double()?)),
-            (a, QType::Double(b)) => Ok(QType::Double(a.to_double()? * b)),
-            (QType::Single(a), b) => Ok(QType::Single(a * b.to_single()?)),
-            (a, QType::Single(b)) => Ok(QType::Single(a.to_single()? * b)),
-            (QType::Long(a), b) => Ok(QType::Long(a * b.to_long()?)),
-            (a, QType::Long(b)) => Ok(QType::Long(a.to_long()? * b)),
-            (QType::Integer(a), QType::Integer(b)) => {
-                let result = a.wrapping_mul(*b);
-                Ok(QType::Integer(result))
-            }
+            (QType::Double(a), b) => Self::double_result(a * b.to_double()?),
+            (a, QType::Double(b)) => Self::double_result(a.to_double()? * b),
+            (QType::Single(a), b) => Self::single_result(a * b.to_single()?),
+            (a, QType::Single(b)) => Self::single_result(a.to_single()? * b),
+            (QType::Long(a), b) => Self::integer_result(i64::from(*a) * i64::from(b.to_long()?), true, arithmetic),
+            (a, QType::Long(b)) => Self::integer_result(i64::from(a.to_long()?) * i64::from(*b), true, arithmetic),
+            (QType::Integer(a), QType::Integer(b)) => Self::integer_result(i64::from(*a) * i64::from(*b), false, arithmetic),
             _ => Err(QError::runtime(QErrorCode::TypeMismatch, 0, 0)),
         }
     }
 
+    /// A floating-point result: DOUBLE when either operand is DOUBLE,
+    /// SINGLE otherwise, as QBasic types / and ^ and functions such as SQR
+    fn float_result(&self, other: &QType, value: f64) -> QResult<QType> {
+        if matches!(self, QType::Double(_)) || matches!(other, QType::Double(_)) {
+            Self::double_result(value)
+        } else {
+            Self::single_result(value as f32)
+        }
+    }
+
+    /// A SINGLE result; one too large for SINGLE has become infinite and
+    /// is an Overflow
+    fn single_result(value: f32) -> QResult<QType> {
+        match value {
+            v if v.is_finite() => Ok(QType::Single(v)),
+            v if v.is_nan() => Err(QError::runtime(QErrorCode::IllegalFunctionCall, 0, 0)),
+            _ => Err(QError::runtime(QErrorCode::Overflow, 0, 0)),
+        }
+    }
+
+    /// A DOUBLE result, checked as `single_result` checks a SINGLE
+    fn double_result(value: f64) -> QResult<QType> {
+        match value {
+            v if v.is_finite() => Ok(QType::Double(v)),
+            v if v.is_nan() => Err(QError::runtime(QErrorCode::IllegalFunctionCall, 0, 0)),
+            _ => Err(QError::runtime(QErrorCode::Overflow, 0, 0)),
+        }
+    }
+
     /// Divide two values
     pub fn divide(&self, other: &QType) -> QResult<QType> {
         let divisor = other.to_double()?;
         if divisor == 0.0 {
             return Err(QError::runtime(QErrorCode::DivisionByZero, 0, 0));
         }
-        Ok(QType::Double(self.to_double()? / divisor))
+        self.float_result(other, self.to_double()? / divisor)
     }
 
-    /// Integer divide
+    /// Integer divide; both operands are rounded to integers first
     pub fn int_divide(&self, other: &QType) -> QResult<QType> {
+        self.int_divide_in(other, Arithmetic::default())
+    }
+
+    /// Integer divide, overflowing as `arithmetic` does
+    pub fn int_divide_in(&self, other: &QType, arithmetic: Arithmetic) -> QResult<QType> {
         let divisor = other.to_long()?;
         if divisor == 0 {
             return Err(QError::runtime(QErrorCode::DivisionByZero, 0, 0));
         }
-        Ok(QType::Long(self.to_long()? / divisor))
+        // Only -2147483648 \ -1 is out of range
+        Self::integer_result(i64::from(self.to_long()?) / i64::from(divisor), true, arithmetic)
     }
 
-    /// Modulo
+    /// Modulo; both operands are rounded to integers first
     pub fn modulo(&self, other: &QType) -> QResult<QType> {
         let divisor = other.to_long()?;
         if divisor == 0 {
             return Err(QError::runtime(QErrorCode::DivisionByZero, 0, 0));
         }
-        Ok(QType::Long(self.to_long()? % divisor))
+        // As i64, since -2147483648 MOD -1 overflows an i32
+        Ok(QType::Long((i64::from(self.to_long()?) % i64::from(divisor)) as i32))
     }
 
     /// Power
     pub fn power(&self, other: &QType) -> QResult<QType> {
         let base = self.to_double()?;
         let exp = other.to_double()?;
-        Ok(QType::Double(base.powf(exp)))
+        self.float_result(other, base.powf(exp))
     }
 
     /// Compare two values
     pub fn compare(&self, other: &QType, op: CompareOp) -> QResult<bool> {
+        self.compare_in(other, op, Dialect::default())
+    }
+
+    /// Compare two values, ordering strings as `dialect` does
+    pub fn compare_in(&self, other: &QType, op: CompareOp, dialect: Dialect) -> QResult<bool> {
         let result = match (self, other) {
-            (QType::String(a), QType::String(b)) => match op {
-                CompareOp::Eq => a == b,
-                CompareOp::Ne => a != b,
-                CompareOp::Lt => a < b,
-                CompareOp::Le => a <= b,
-                CompareOp::Gt => a > b,
-                CompareOp::Ge => a >= b,
+            (a, b) if a.is_string() && b.is_string() => {
+                let order = dialect.collate(&a.to_qstring()?, &b.to_qstring()?);
+                match op {
+                    CompareOp::Eq => order.is_eq(),
+                    CompareOp::Ne => order.is_ne(),
+                    CompareOp::Lt => order.is_lt(),
+                    CompareOp::Le => order.is_le(),
+                    CompareOp::Gt => order.is_gt(),
+                    CompareOp::Ge => order.is_ge(),
+                }
             }
+            // Exact, as in QBasic: a SINGLE holding 0.1 * 3 is not 0.3
             (a, b) if a.is_numeric() && b.is_numeric() => {
                 let a = a.to_double()?;
                 let b = b.to_double()?;
                 match op {
-                    CompareOp::Eq => (a - b).abs() < f64::EPSILON,
-                    CompareOp::Ne => (a - b).abs() >= f64::EPSILON,
+                    CompareOp::Eq => a == b,
+                    CompareOp::Ne => a != b,
                     CompareOp::Lt => a < b,
                     CompareOp::Le => a <= b,
                     CompareOp::Gt => a > b,
@@ -423,24 +625,131 @@ impl QType {
         if n < 0.0 {
             Err(QError::runtime(QErrorCode::IllegalFunctionCall, 0, 0))
         } else {
-            Ok(QType::Double(n.sqrt()))
+            self.float_result(self, n.sqrt())
         }
     }
 
-    pub fn math_sin(&self) -> QResult<QType> { Ok(QType::Double(self.to_double()?.sin())) }
-    pub fn math_cos(&self) -> QResult<QType> { Ok(QType::Double(self.to_double()?.cos())) }
-    pub fn math_tan(&self) -> QResult<QType> { Ok(QType::Double(self.to_double()?.tan())) }
-    pub fn math_atn(&self) -> QResult<QType> { Ok(QType::Double(self.to_double()?.atan())) }
-    pub fn math_exp(&self) -> QResult<QType> { Ok(QType::Double(self.to_double()?.exp())) }
+    pub fn math_sin(&self) -> QResult<QType> { self.float_result(self, self.to_double()?.sin()) }
+    pub fn math_cos(&self) -> QResult<QType> { self.float_result(self, self.to_double()?.cos()) }
+    pub fn math_tan(&self) -> QResult<QType> { self.float_result(self, self.to_double()?.tan()) }
+    pub fn math_atn(&self) -> QResult<QType> { self.float_result(self, self.to_double()?.atan()) }
+    pub fn math_exp(&self) -> QResult<QType> { self.float_result(self, self.to_double()?.exp()) }
     
     pub fn math_log(&self) -> QResult<QType> {
         let n = self.to_double()?;
         if n <= 0.0 {
             Err(QError::runtime(QErrorCode::IllegalFunctionCall, 0, 0))
         } else {
-            Ok(QType::Double(n.ln()))
+            self.float_result(self, n.ln())
         }
     }
+
+    /// STR$: the number as PRINT shows it, with a leading space standing in
+    /// for the sign of a positive value
+    pub fn str_value(&self) -> QResult<String> {
+        if !self.is_numeric() {
+            return Err(QError::runtime(QErrorCode::TypeMismatch, 0, 0));
+        }
+        let text = self.to_string();
+        Ok(if text.starts_with('-') { text } else { format!(" {}", text) })
+    }
+
+    /// VAL: the number at the start of `text`, or 0 if there is none.
+    /// Blanks, tabs and line feeds are ignored anywhere, reading stops at
+    /// the first character that can't continue the number, and &H, &O or a
+    /// bare & read hexadecimal and octal.
+    pub fn val(text: &str) -> QResult<QType> {
+        let text: String = text.chars().filter(|c| !matches!(c, ' ' | '\t' | '\n')).collect();
+        let overflow = || QError::runtime(QErrorCode::Overflow, 0, 0);
+
+        if let Some(rest) = text.strip_prefix('&') {
+            let (radix, digits) = match rest.chars().next().map(|c| c.to_ascii_uppercase()) {
+                Some('H') => (16, &rest[1..]),
+                Some('O') => (8, &rest[1..]),
+                _ => (8, rest),
+            };
+            let end = digits.find(|c: char| !c.is_digit(radix)).unwrap_or(digits.len());
+            let mut value: u32 = 0;
+            for c in digits[..end].chars() {
+                value = value
+                    .checked_mul(radix)
+                    .and_then(|v| v.checked_add(c.to_digit(radix).unwrap_or(0)))
+                    .ok_or_else(overflow)?;
+            }
+            // As with &H literals, 16-bit values are INTEGERs and wider ones
+            // LONGs, so the top bit makes either negative
+            let value = if value <= 0xFFFF { f64::from(value as u16 as i16) } else { f64::from(value as i32) };
+            return Ok(QType::Double(value));
+        }
+
+        let bytes = text.as_bytes();
+        let digits_from = |mut i: usize| {
+            while bytes.get(i).is_some_and(u8::is_ascii_digit) {
+                i += 1;
+            }
+            i
+        };
+        let mut end = digits_from(usize::from(matches!(bytes.first(), Some(b'+' | b'-'))));
+        if bytes.get(end) == Some(&b'.') {
+            end = digits_from(end + 1);
+        }
+        if matches!(bytes.get(end), Some(b'E' | b'e' | b'D' | b'd')) {
+            let sign = usize::from(matches!(bytes.get(end + 1), Some(b'+' | b'-')));
+            let exponent_end = digits_from(end + 1 + sign);
+            if exponent_end > end + 1 + sign {
+                end = exponent_end;
+            }
+        }
+        let value = text[..end].replace(['D', 'd'], "E").parse::<f64>().unwrap_or(0.0);
+        if value.is_infinite() {
+            return Err(overflow());
+        }
+        Ok(QType::Double(value))
+    }
+}
+
+/// Format a floating-point number as PRINT shows it: at most `digits`
+/// significant digits, no leading zero before the point, and scaled
+/// notation such as 1.5E+07 only when the plain form would need more digits
+fn format_float(value: f64, digits: usize, exponent_letter: char) -> String {
+    if value == 0.0 {
+        return "0".to_string();
+    }
+    if !value.is_finite() {
+        return value.to_string();
+    }
+    // Round to the significant digits, then split into digits and exponent
+    let scaled = format!("{:.*e}", digits - 1, value.abs());
+    let (mantissa, exponent) = scaled.split_once('e').unwrap_or((&scaled, "0"));
+    let exponent: i32 = exponent.parse().unwrap_or(0);
+    let significant = mantissa.replace('.', "");
+    let significant = significant.trim_end_matches('0');
+    let sign = if value < 0.0 { "-" } else { "" };
+
+    // Digits the plain form needs: the integer part, or the leading zeros
+    // after the point plus the significant digits
+    let plain_digits = if exponent >= 0 {
+        (exponent as usize + 1).max(significant.len())
+    } else {
+        exponent.unsigned_abs() as usize - 1 + significant.len()
+    };
+    if plain_digits <= digits {
+        let text = if exponent >= 0 {
+            let point = exponent as usize + 1;
+            if significant.len() <= point {
+                format!("{:0<width$}", significant, width = point)
+            } else {
+                format!("{}.{}", &significant[..point], &significant[point..])
+            }
+        } else {
+            format!(".{}{}", "0".repeat(exponent.unsigned_abs() as usize - 1), significant)
+        };
+        format!("{}{}", sign, text)
+    } else {
+        let (first, rest) = significant.split_at(1);
+        let point = if rest.is_empty() { "" } else { "." };
+        format!("{}{}{}{}{}{:+03}", sign, first, point, rest, exponent_letter, exponent)
+    }
 }
 
 impl fmt::Display for QType {
@@ -448,8 +757,8 @@ impl fmt::Display for QType {
         match self {
             QType::Integer(v) => write!(f, "{}", v),
             QType::Long(v) => write!(f, "{}", v),
-            QType::Single(v) => write!(f, "{}", v),
-            QType::Double(v) => write!(f, "{}", v),
+            QType::Single(v) => write!(f, "{}", format_float(f64::from(*v), 7, 'E')),
+            QType::Double(v) => write!(f, "{}", format_float(*v, 16, 'D')),
             // QB64 extended types
             QType::Integer64(v) => write!(f, "{}", v),
             QType::UnsignedInteger(v) => write!(f, "{}", v),
@@ -464,6 +773,40 @@ impl fmt::Display for QType {
     }
 }
 
+/// What INTEGER and LONG arithmetic does with a result too large for its
+/// type
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, serde::Serialize, serde::Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Arithmetic {
+    /// Overflow, as in QBasic
+    #[default]
+    Classic,
+    /// Give it in a wider type: an INTEGER result as a LONG, and a LONG
+    /// one as a DOUBLE
+    Modern,
+}
+
+impl std::str::FromStr for Arithmetic {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_lowercase().as_str() {
+            "classic" => Ok(Arithmetic::Classic),
+            "modern" => Ok(Arithmetic::Modern),
+            _ => Err(format!("unknown arithmetic '{}' (expected classic or modern)", s)),
+        }
+    }
+}
+
+impl fmt::Display for Arithmetic {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            Arithmetic::Classic => "classic",
+            Arithmetic::Modern => "modern",
+        })
+    }
+}
+
 #[derive(Debug, Clone, Copy, PartialEq, Eq)]
 pub enum CompareOp {
     Eq, // =
@@ -531,6 +874,7 @@ pub enum VariableRef {
 pub enum ParamType {
     ByVal(VariableId),  // Pass by value
     ByRef(VariableId),  // Pass by reference
+    Array(VariableId),  // A whole array, always by reference
 }
 
 /// User-defined type definition
@@ -565,6 +909,28 @@ mod tests {
         assert_eq!(neg.math_sgn().unwrap(), QType::Integer(-1));
     }
 
+    #[test]
+    fn test_integer_overflow() {
+        let overflows = |result: QResult<QType>| matches!(result, Err(QError::Runtime { code: QErrorCode::Overflow, .. }));
+        let (int_max, long_max) = (QType::Integer(i16::MAX), QType::Long(i32::MAX));
+        assert!(overflows(int_max.add(&QType::Integer(1))));
+        assert!(overflows(QType::Integer(i16::MIN).negate()));
+        assert!(overflows(long_max.multiply(&QType::Integer(2))));
+        assert!(overflows(QType::Long(i32::MIN).subtract(&QType::Long(1))));
+        assert!(overflows(QType::Long(i32::MIN).int_divide(&QType::Integer(-1))));
+        assert_eq!(QType::Long(i32::MIN).modulo(&QType::Integer(-1)).unwrap(), QType::Long(0));
+        assert_eq!(int_max.add(&QType::Integer(-1)).unwrap(), QType::Integer(32766));
+
+        let modern = Arithmetic::Modern;
+        assert_eq!(int_max.add_in(&QType::Integer(1), modern).unwrap(), QType::Long(32768));
+        assert_eq!(QType::Integer(i16::MIN).negate_in(modern).unwrap(), QType::Long(32768));
+        assert_eq!(long_max.add_in(&QType::Integer(1), modern).unwrap(), QType::Double(2_147_483_648.0));
+        assert_eq!(long_max.multiply_in(&long_max, modern).unwrap(), QType::Double(4_611_686_014_132_420_609.0));
+        assert_eq!(QType::Integer(2).multiply_in(&QType::Integer(3), modern).unwrap(), QType::Integer(6));
+        assert_eq!(QType::Long(i32::MIN).int_divide_in(&QType::Integer(-1), modern).unwrap(), QType::Double(2_147_483_648.0));
+
+    }
+
     #[test]
     fn test_math_sqr() {
         let val = QType::Double(16.0);
@@ -586,6 +952,130 @@ mod tests {
         assert_eq!(val2.math_fix().unwrap(), QType::Double(-2.0));
     }
 
+    #[test]
+    fn test_string_collation() {
+        let lt = |a: &str, b: &str, dialect| {
+            QType::String(a.into()).compare_in(&QType::String(b.into()), CompareOp::Lt, dialect).unwrap()
+        };
+        // CP437 puts accented letters (128-) before box drawing (179-) and
+        // Greek (224-); Unicode orders them the other way round
+        assert!(lt("é", "╔", Dialect::Qb45));
+        assert!(lt("╔", "α", Dialect::Qb45));
+        assert!(!lt("╔", "α", Dialect::Qb64));
+        assert!(lt("°", "é", Dialect::Qb64));
+        assert!(!lt("°", "é", Dialect::Qb45));
+        assert!(lt("Z", "a", Dialect::Qb45));
+
+        let fixed = QType::FixedString(3, "ab ".into());
+        assert!(fixed.compare(&QType::String("ab ".into()), CompareOp::Eq).unwrap());
+    }
+
+    #[test]
+    fn test_float_comparison_is_exact() {
+        let third = QType::Double(1.0).divide(&QType::Double(3.0)).unwrap();
+        let nearly = QType::Double(1.0 / 3.0 + 1e-16);
+        assert!(!third.compare(&nearly, CompareOp::Eq).unwrap());
+        assert!(third.compare(&nearly, CompareOp::Ne).unwrap());
+        // A SINGLE is widened, not rounded, when compared with a DOUBLE
+        assert!(QType::Single(0.5).compare(&QType::Double(0.5), CompareOp::Eq).unwrap());
+        assert!(!QType::Single(0.1).compare(&QType::Double(0.1), CompareOp::Eq).unwrap());
+    }
+
+    #[test]
+    fn test_str_and_val() {
+        assert_eq!(QType::Integer(42).str_value().unwrap(), " 42");
+        assert_eq!(QType::Integer(-42).str_value().unwrap(), "-42");
+        assert_eq!(QType::Single(0.5).str_value().unwrap(), " .5");
+        assert!(QType::String("1".into()).str_value().is_err());
+
+        let val = |s: &str| QType::val(s).unwrap().to_double().unwrap();
+        assert_eq!(val("  12abc"), 12.0);
+        assert_eq!(val("1 000 000"), 1_000_000.0);
+        assert_eq!(val("-3.5E2x"), -350.0);
+        assert_eq!(val("1.5D+3"), 1500.0);
+        assert_eq!(val("7e"), 7.0);
+        assert_eq!(val("1.2.3"), 1.2);
+        assert_eq!(val(".5"), 0.5);
+        assert_eq!(val("abc"), 0.0);
+        assert_eq!(val("-"), 0.0);
+        assert_eq!(val("&HFF"), 255.0);
+        assert_eq!(val("&HFFFF"), -1.0);
+        assert_eq!(val("&H10000"), 65536.0);
+        assert_eq!(val("&O17"), 15.0);
+        assert_eq!(val("&17"), 15.0);
+        assert_eq!(val("&H1G"), 1.0);
+        assert!(QType::val("&H123456789").is_err());
+        assert!(QType::val("1E999").is_err());
+    }
+
+    #[test]
+    fn test_rounding_is_half_even() {
+        assert_eq!(QType::Single(2.5).to_integer().unwrap(), 2);
+        assert_eq!(QType::Single(3.5).to_integer().unwrap(), 4);
+        assert_eq!(QType::Double(-2.5).to_long().unwrap(), -2);
+        assert_eq!(QType::Double(2.7).to_long().unwrap(), 3);
+
+        // 7.5 \ 2.5 is 8 \ 2, and 10.5 MOD 3.5 is 10 MOD 4
+        assert_eq!(QType::Double(7.5).int_divide(&QType::Double(2.5)).unwrap(), QType::Long(4));
+        assert_eq!(QType::Double(10.5).modulo(&QType::Double(3.5)).unwrap(), QType::Long(2));
+        // 0.4 rounds to a zero divisor
+        assert!(QType::Long(5).int_divide(&QType::Single(0.4)).is_err());
+    }
+
+    #[test]
+    fn test_conversions_overflow() {
+        let overflow = |result: QResult<i32>| {
+            matches!(result, Err(QError::Runtime { code: QErrorCode::Overflow, .. }))
+        };
+        assert_eq!(QType::Double(32767.4).to_integer().unwrap(), 32767);
+        assert!(overflow(QType::Double(32767.5).to_integer().map(i32::from)));
+        assert!(overflow(QType::Long(40000).to_integer().map(i32::from)));
+        assert!(overflow(QType::Single(-32768.6).to_integer().map(i32::from)));
+        assert_eq!(QType::Double(-2147483648.4).to_long().unwrap(), i32::MIN);
+        assert!(overflow(QType::Double(2147483647.5).to_long()));
+        assert!(overflow(QType::Double(f64::NAN).to_long()));
+        assert!(QType::Double(1e300).to_single().is_err());
+        assert!(QType::Double(70000.0).convert_to(&QType::Integer(0)).is_err());
+    }
+
+    #[test]
+    fn test_float_results_are_single_unless_double() {
+        let third = QType::Integer(1).divide(&QType::Integer(3)).unwrap();
+        assert!(matches!(third, QType::Single(_)));
+        assert_eq!(third.to_string(), ".3333333");
+        let third = QType::Integer(1).divide(&QType::Double(3.0)).unwrap();
+        assert_eq!(third.to_string(), ".3333333333333333");
+        assert!(matches!(QType::Integer(2).power(&QType::Integer(10)).unwrap(), QType::Single(v) if v == 1024.0));
+        assert!(matches!(QType::Long(2).math_sqr().unwrap(), QType::Single(_)));
+    }
+
+    #[test]
+    fn test_float_overflow() {
+        let overflow = |result: QResult<QType>| matches!(result, Err(QError::Runtime { code: QErrorCode::Overflow, .. }));
+        assert!(overflow(QType::Single(1e30).divide(&QType::Single(1e-10))));
+        assert!(overflow(QType::Single(1e38).multiply(&QType::Integer(10))));
+        assert!(overflow(QType::Single(3e38).add(&QType::Single(3e38))));
+        assert!(overflow(QType::Double(1e308).multiply(&QType::Double(10.0))));
+        assert!(overflow(QType::Integer(1000).math_exp()));
+        // DOUBLE holds what SINGLE cannot
+        assert_eq!(QType::Double(1e30).divide(&QType::Double(1e-10)).unwrap(), QType::Double(1e40));
+        assert!(matches!(
+            QType::Integer(-8).power(&QType::Single(0.5)),
+            Err(QError::Runtime { code: QErrorCode::IllegalFunctionCall, .. })
+        ));
+    }
+
+    #[test]
+    fn test_float_display() {
+        assert_eq!(QType::Single(2.5).to_string(), "2.5");
+        assert_eq!(QType::Single(-0.25).to_string(), "-.25");
+        assert_eq!(QType::Single(12345678.0).to_string(), "1.234568E+07");
+        assert_eq!(QType::Single(0.0000125).to_string(), ".0000125");
+        assert_eq!(QType::Single(0.00001234).to_string(), "1.234E-05");
+        assert_eq!(QType::Single(1000000.0).to_string(), "1000000");
+        assert_eq!(QType::Double(1e16).to_string(), "1D+16");
+    }
+
     #[test]
     fn test_math_log() {
         let e = QType::Double(std::f64::consts::E);
@@ -594,4 +1084,58 @@ mod tests {
         let zero = QType::Double(0.0);
         assert!(zero.math_log().is_err());
     }
+
+    #[test]
+    fn test_bytes_round_trip() {
+        let values = [
+            QType::Integer(-2),
+            QType::Long(0x12345678),
+            QType::Single(1.5),
+            QType::UnsignedLong(0xFFFF0000),
+            QType::FixedString(3, "AB\u{E9}".to_string()),
+        ];
+        for value in values {
+            let bytes = value.to_bytes(Dialect::Qb64);
+            assert_eq!(bytes.len(), value.size());
+            assert_eq!(value.from_bytes(&bytes, Dialect::Qb64), value);
+        }
+        assert_eq!(QType::Long(0x12345678).to_bytes(Dialect::Qb45), [0x78, 0x56, 0x34, 0x12]);
+        // Strings are stored in code page 437 under QB 4.5
+        let box_chars = QType::String("─█".to_string());
+        assert_eq!(box_chars.to_bytes(Dialect::Qb45), [196, 219]);
+        assert_eq!(box_chars.from_bytes(&[196, 219], Dialect::Qb45), box_chars);
+        assert_eq!(QType::String("\u{2603}".into()).to_bytes(Dialect::Qb45), b"?");
+    }
+
+    #[test]
+    fn test_binary_strings() {
+        let integer = QType::Integer(0);
+        let (qb45, qb64) = (Dialect::Qb45, Dialect::Qb64);
+        assert_eq!(QType::Single(258.4).to_binary_string(&integer, qb45).unwrap(), "\u{2}\u{1}");
+        assert_eq!(QType::from_binary_string("\u{2}\u{1}extra", &integer, qb45).unwrap(), QType::Integer(258));
+        // Built with CHR$: CHR$(130) + CHR$(0) is 130 in either dialect
+        for dialect in [qb45, qb64] {
+            let chr: String = [dialect.chr(130).unwrap(), '\0'].iter().collect();
+            assert_eq!(QType::from_binary_string(&chr, &integer, dialect).unwrap(), QType::Integer(130));
+            assert_eq!(QType::Integer(130).to_binary_string(&integer, dialect).unwrap(), chr);
+        }
+        assert_eq!(QType::from_binary_string("╚\u{0}", &integer, qb45).unwrap(), QType::Integer(200));
+        let double = QType::Double(0.0);
+        let packed = QType::Double(-1.25).to_binary_string(&double, qb45).unwrap();
+        assert_eq!(packed.chars().count(), 8);
+        assert_eq!(QType::from_binary_string(&packed, &double, qb45).unwrap(), QType::Double(-1.25));
+        assert!(QType::from_binary_string("abc", &QType::Long(0), qb45).is_err());
+        assert!(QType::from_binary_string("\u{2603}a", &integer, qb45).is_err());
+        assert!(QType::from_binary_string("╚\u{0}", &integer, qb64).is_err());
+        assert!(QType::Long(40000).to_binary_string(&integer, qb45).is_err());
+    }
+
+    #[test]
+    fn test_convert_to() {
+        let fixed = QType::FixedString(4, String::new());
+        assert_eq!(QType::String("AB".into()).convert_to(&fixed).unwrap(), QType::FixedString(4, "AB  ".into()));
+        assert_eq!(QType::Integer(7).convert_to(&QType::UnsignedLong(0)).unwrap(), QType::UnsignedLong(7));
+        assert_eq!(QType::Long(-1).convert_to(&QType::UnsignedLong(0)).unwrap(), QType::UnsignedLong(u32::MAX));
+        assert!(QType::String("7".into()).convert_to(&QType::Long(0)).is_err());
+    }
 }
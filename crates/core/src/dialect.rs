@@ -0,0 +1,92 @@
+//! Which BASIC a program is written for. QB 4.5 is the default; QB64
+//! widens some limits and turns on its extensions.
+
+use crate::cp437;
+use serde::{Deserialize, Serialize};
+use std::cmp::Ordering;
+use std::fmt;
+use std::str::FromStr;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Dialect {
+    /// QuickBASIC 4.5 and QBasic 1.1
+    #[default]
+    Qb45,
+    /// QB64, with character codes taken as Unicode
+    Qb64,
+}
+
+impl Dialect {
+    /// CHR$: code page 437 under QB 4.5, any Unicode scalar under QB64
+    pub fn chr(&self, code: i32) -> Option<char> {
+        match self {
+            Dialect::Qb45 => u8::try_from(code).ok().map(cp437::to_char),
+            Dialect::Qb64 => u32::try_from(code).ok().and_then(char::from_u32),
+        }
+    }
+
+    /// ASC of a character. Under QB 4.5 one missing from code page 437
+    /// gives its Unicode value.
+    pub fn asc(&self, c: char) -> u32 {
+        match self {
+            Dialect::Qb45 => cp437::from_char(c).map_or(u32::from(c), u32::from),
+            Dialect::Qb64 => u32::from(c),
+        }
+    }
+
+    /// The character a byte of a file, of memory or of an MKI$ string
+    /// stands for: the one CHR$ gives for its code
+    pub fn byte_char(&self, byte: u8) -> char {
+        match self {
+            Dialect::Qb45 => cp437::to_char(byte),
+            Dialect::Qb64 => char::from(byte),
+        }
+    }
+
+    /// The byte a character is stored as, the reverse of `byte_char`;
+    /// None for a character that no byte stands for
+    pub fn char_byte(&self, c: char) -> Option<u8> {
+        match self {
+            Dialect::Qb45 => cp437::from_char(c),
+            Dialect::Qb64 => u8::try_from(c).ok(),
+        }
+    }
+
+    /// Whether TIMER counts BIOS clock ticks, about 18.2 a second, as DOS
+    /// did. Old games pace themselves on those steps; QB64 reads the
+    /// system clock itself.
+    pub fn coarse_timer(&self) -> bool {
+        matches!(self, Dialect::Qb45)
+    }
+
+    /// Order of two strings for the relational operators: by code page
+    /// 437 code under QB 4.5, by Unicode value under QB64
+    pub fn collate(&self, a: &str, b: &str) -> Ordering {
+        match self {
+            Dialect::Qb45 => a.chars().map(cp437::collation_key).cmp(b.chars().map(cp437::collation_key)),
+            Dialect::Qb64 => a.cmp(b),
+        }
+    }
+}
+
+impl FromStr for Dialect {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_lowercase().as_str() {
+            "qb45" | "qbasic" => Ok(Dialect::Qb45),
+            "qb64" => Ok(Dialect::Qb64),
+            _ => Err(format!("unknown dialect '{}' (expected qb45 or qb64)", s)),
+        }
+    }
+}
+
+impl fmt::Display for Dialect {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            Dialect::Qb45 => "qb45",
+            Dialect::Qb64 => "qb64",
+        })
+    }
+}
@@ -16,6 +16,7 @@ pub enum QErrorCode {
     DiskNotReady = 71,
     RenameAcrossDisks = 74,
     PathFileAccessError = 75,
+    PathNotFound = 76,
 
     // Device errors (25-26, 68-72)
     DeviceFault = 25,
@@ -50,6 +51,7 @@ pub enum QErrorCode {
     FieldOverflow = 50,
     InternalError = 51,
     BadFileNumber = 52,
+    PermissionDenied = 70,
     UndefinedLineNumber = 90, // Different from LabelNotDefined
     BadRecordNumber = 63,
     Null = 94,
@@ -59,9 +61,35 @@ pub enum QErrorCode {
 
     // System errors (100+)
     FeatureNotYetImplemented = 100,
+    VariableNotDefined = 101, // Only raised in strict mode
     UnknownError = 255,
+
+    // QB64 errors (258+)
+    InvalidHandle = 258,
 }
 
+/// Every error code, for looking one up by number
+const ALL_CODES: [QErrorCode; 52] = [
+    QErrorCode::FileNotFound, QErrorCode::DeviceIOError, QErrorCode::FileAlreadyExists,
+    QErrorCode::BadFileName, QErrorCode::DiskFull, QErrorCode::InputPastEndOfFile,
+    QErrorCode::BadFileMode, QErrorCode::FileAlreadyOpen, QErrorCode::BadRecordLength,
+    QErrorCode::DiskNotReady, QErrorCode::RenameAcrossDisks, QErrorCode::PathFileAccessError,
+    QErrorCode::PathNotFound, QErrorCode::DeviceFault, QErrorCode::FatalError,
+    QErrorCode::DeviceUnavailable, QErrorCode::CommunicationBufferOverflow,
+    QErrorCode::DiskMediaError, QErrorCode::NextWithoutFor, QErrorCode::SyntaxError,
+    QErrorCode::ReturnWithoutGosub, QErrorCode::OutOfData, QErrorCode::IllegalFunctionCall,
+    QErrorCode::Overflow, QErrorCode::OutOfMemory, QErrorCode::LabelNotDefined,
+    QErrorCode::SubscriptOutOfRange, QErrorCode::DuplicateDefinition, QErrorCode::DivisionByZero,
+    QErrorCode::TypeMismatch, QErrorCode::OutOfStringSpace, QErrorCode::StringFormulaTooComplex,
+    QErrorCode::CannotContinue, QErrorCode::FunctionNotDefined, QErrorCode::NoResume,
+    QErrorCode::ResumeWithoutError, QErrorCode::UnprintableError, QErrorCode::MissingOperand,
+    QErrorCode::LineBufferOverflow, QErrorCode::AlreadyInContext, QErrorCode::FieldOverflow,
+    QErrorCode::InternalError, QErrorCode::BadFileNumber, QErrorCode::PermissionDenied,
+    QErrorCode::UndefinedLineNumber, QErrorCode::BadRecordNumber, QErrorCode::Null,
+    QErrorCode::AdvancedFeatureUnavailable, QErrorCode::FeatureNotYetImplemented,
+    QErrorCode::VariableNotDefined, QErrorCode::UnknownError, QErrorCode::InvalidHandle,
+];
+
 impl std::fmt::Display for QErrorCode {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
         write!(f, "{}", self.as_str())
@@ -92,14 +120,14 @@ impl QErrorCode {
             QErrorCode::UnprintableError => "Unprintable error",
             QErrorCode::MissingOperand => "Missing operand",
             QErrorCode::LineBufferOverflow => "Line buffer overflow",
-            QErrorCode::DeviceFault => "Device Fault",
+            QErrorCode::DeviceFault => "Device fault",
             QErrorCode::FatalError => "Fatal error",
             QErrorCode::AlreadyInContext => "WHILE without WEND",
             QErrorCode::FieldOverflow => "FIELD overflow",
             QErrorCode::InternalError => "Internal error",
             QErrorCode::BadFileNumber => "Bad file number",
             QErrorCode::FileNotFound => "File not found",
-            QErrorCode::DeviceUnavailable => "Device Unavailable",
+            QErrorCode::DeviceUnavailable => "Device unavailable",
             QErrorCode::CommunicationBufferOverflow => "Communication buffer overflow",
             QErrorCode::DeviceIOError => "Device I/O error",
             QErrorCode::FileAlreadyExists => "File already exists",
@@ -112,24 +140,35 @@ impl QErrorCode {
             QErrorCode::DiskMediaError => "Disk media error",
             QErrorCode::AdvancedFeatureUnavailable => "Advanced feature unavailable",
             QErrorCode::PathFileAccessError => "Path/File access error",
+            QErrorCode::PathNotFound => "Path not found",
+            QErrorCode::PermissionDenied => "Permission denied",
             QErrorCode::RenameAcrossDisks => "Rename across disks",
             QErrorCode::BadFileMode => "Bad file mode",
             QErrorCode::FileAlreadyOpen => "File already open",
             QErrorCode::UndefinedLineNumber => "Undefined line number",
             QErrorCode::Null => "Null",
             QErrorCode::FeatureNotYetImplemented => "Feature not yet implemented",
+            QErrorCode::VariableNotDefined => "Variable not defined",
             QErrorCode::UnknownError => "Unknown error",
+            QErrorCode::InvalidHandle => "Invalid handle",
         }
     }
 
     pub fn code(&self) -> i32 {
         *self as i32
     }
+
+    /// The error numbered `code`, if there is one
+    pub fn from_code(code: i32) -> Option<Self> {
+        ALL_CODES.iter().copied().find(|error| error.code() == code)
+    }
 }
 
+/// Errors read the way QuickBASIC reports them, such as "Subscript out of
+/// range in line 120"; the line is left out when it is not known
 #[derive(Error, Debug, Clone)]
 pub enum QError {
-    #[error("Error {code}: {message} at line {line}, column {column}")]
+    #[error("{message}{}", location(*.line, *.column))]
     Runtime {
         code: QErrorCode,
         message: String,
@@ -137,13 +176,17 @@ pub enum QError {
         column: usize,
     },
     
-    #[error("Compile Error: {message} at line {line}, column {column}")]
+    #[error("{message}{}", location(*.line, *.column))]
     Compile {
         message: String,
         line: usize,
         column: usize,
     },
     
+    /// Ctrl+Break stopped the program
+    #[error("Break{}", location(*.line, 0))]
+    Break { line: usize },
+
     #[error("IO Error: {0}")]
     Io(String),
     
@@ -151,6 +194,14 @@ pub enum QError {
     System(String),
 }
 
+fn location(line: usize, column: usize) -> String {
+    match (line, column) {
+        (0, _) => String::new(),
+        (line, 0) => format!(" in line {}", line),
+        (line, column) => format!(" in line {}, column {}", line, column),
+    }
+}
+
 impl From<std::io::Error> for QError {
     fn from(e: std::io::Error) -> Self {
         QError::Io(e.to_string())
@@ -167,6 +218,14 @@ impl QError {
         QError::Runtime { code, message: message.into(), line, column }
     }
 
+    /// Attribute a runtime error raised without position to `line`
+    pub fn with_line(self, line: usize) -> Self {
+        match self {
+            QError::Runtime { code, message, line: 0, column } => QError::Runtime { code, message, line, column },
+            other => other,
+        }
+    }
+
     pub fn compile(message: impl Into<String>, line: usize, column: usize) -> Self {
         QError::Compile { message: message.into(), line, column }
     }
@@ -181,3 +240,19 @@ impl QError {
 }
 
 pub type QResult<T> = Result<T, QError>;
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_classic_messages() {
+        let error = QError::runtime(QErrorCode::SubscriptOutOfRange, 0, 0);
+        assert_eq!(error.to_string(), "Subscript out of range");
+        assert_eq!(error.with_line(120).to_string(), "Subscript out of range in line 120");
+        assert_eq!(QError::compile("Expected expression", 2, 31).to_string(), "Expected expression in line 2, column 31");
+        assert_eq!(QError::Break { line: 7 }.to_string(), "Break in line 7");
+        assert_eq!(QErrorCode::from_code(9), Some(QErrorCode::SubscriptOutOfRange));
+        assert_eq!(QErrorCode::from_code(200), None);
+    }
+}
@@ -3,13 +3,19 @@
 //! This crate provides the fundamental data types, memory emulation,
 //! and error handling for the QBasic compiler.
 
+pub mod builtins;
+pub mod cp437;
 pub mod data_types;
+pub mod dialect;
 pub mod errors;
+pub mod locale;
 pub mod memory_map;
 
 // Re-export commonly used items
 pub use data_types::{
-    ArrayBounds, CompareOp, ParamType, QType, TypeSuffix, UserTypeDef, VariableId, VariableRef,
+    Arithmetic, ArrayBounds, CompareOp, ParamType, QType, TypeSuffix, UserTypeDef, VariableId, VariableRef,
 };
+pub use dialect::Dialect;
 pub use errors::{QError, QErrorCode, QResult};
+pub use locale::NumberFormat;
 pub use memory_map::{create_shared_memory, segments, DosMemory, SharedMemory};
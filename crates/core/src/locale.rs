@@ -0,0 +1,91 @@
+//! The decimal separator of numbers that PRINT and STR$ write and VAL
+//! reads. QBasic used a period whatever country DOS was set to, so that is
+//! the default; a program meant for people may ask for the host's instead.
+
+use serde::{Deserialize, Serialize};
+use std::fmt;
+use std::str::FromStr;
+
+/// Languages that write a decimal comma
+const COMMA_LANGUAGES: &[&str] = &[
+    "af", "az", "be", "bg", "bs", "ca", "cs", "da", "de", "el", "es", "et", "eu", "fi", "fo", "fr", "gl", "hr",
+    "hu", "hy", "id", "is", "it", "ka", "kk", "ky", "lt", "lv", "mk", "mn", "nb", "nl", "nn", "no", "pl", "pt",
+    "ro", "ru", "sk", "sl", "sq", "sr", "sv", "tr", "uk", "uz", "vi",
+];
+
+/// Countries where those languages write a period all the same
+const PERIOD_TERRITORIES: &[&str] = &["CH", "LI", "MX", "US", "GT", "HN", "NI", "PA", "PR", "SV", "DO"];
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum NumberFormat {
+    /// A period, as QBasic wrote numbers everywhere
+    #[default]
+    Classic,
+    /// The separator of the host's locale, as LC_ALL, LC_NUMERIC or LANG
+    /// name it
+    Locale,
+}
+
+impl NumberFormat {
+    /// The decimal separator to write and read numbers with
+    pub fn decimal_separator(&self) -> char {
+        match self {
+            NumberFormat::Classic => '.',
+            NumberFormat::Locale => ["LC_ALL", "LC_NUMERIC", "LANG"]
+                .iter()
+                .filter_map(|var| std::env::var(var).ok())
+                .find(|locale| !locale.is_empty())
+                .map_or('.', |locale| decimal_separator_of(&locale)),
+        }
+    }
+}
+
+/// The decimal separator of a POSIX locale such as `de_DE.UTF-8`
+pub fn decimal_separator_of(locale: &str) -> char {
+    let name = locale.split(['.', '@']).next().unwrap_or_default();
+    let (language, territory) = name.split_once('_').unwrap_or((name, ""));
+    if COMMA_LANGUAGES.contains(&language) && !PERIOD_TERRITORIES.contains(&territory) {
+        ','
+    } else {
+        '.'
+    }
+}
+
+impl FromStr for NumberFormat {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_lowercase().as_str() {
+            "classic" => Ok(NumberFormat::Classic),
+            "locale" => Ok(NumberFormat::Locale),
+            _ => Err(format!("unknown number format '{}' (expected classic or locale)", s)),
+        }
+    }
+}
+
+impl fmt::Display for NumberFormat {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            NumberFormat::Classic => "classic",
+            NumberFormat::Locale => "locale",
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_decimal_separator_of() {
+        assert_eq!(decimal_separator_of("de_DE.UTF-8"), ',');
+        assert_eq!(decimal_separator_of("fr_FR@euro"), ',');
+        assert_eq!(decimal_separator_of("pt"), ',');
+        assert_eq!(decimal_separator_of("de_CH.UTF-8"), '.');
+        assert_eq!(decimal_separator_of("es_MX"), '.');
+        assert_eq!(decimal_separator_of("en_US.UTF-8"), '.');
+        assert_eq!(decimal_separator_of("C"), '.');
+        assert_eq!(NumberFormat::Classic.decimal_separator(), '.');
+    }
+}
@@ -0,0 +1,116 @@
+//! $INCLUDE: the metacommand that reads another source file into the
+//! program where it stands
+//!
+//! It is written in a comment, as `'$INCLUDE: 'COMMON.BI'` or with REM.
+//! The included lines go in after the comment, which stays, and may include
+//! further files. A `SourceMap` remembers the file and line each line of the
+//! result came from, so errors can point at them.
+
+use qb_core::errors::{QError, QResult};
+use qb_lexer::decode;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+/// Files that may include one another before QuickBASIC gives up
+const MAX_DEPTH: usize = 16;
+
+/// Where each line of a program with its includes read in came from
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct SourceMap {
+    /// The included files, in the order they were read
+    files: Vec<PathBuf>,
+    /// For each line, from 1: the file, 0 for the program itself and n for
+    /// files[n - 1], and the line in it
+    lines: Vec<(usize, usize)>,
+}
+
+impl SourceMap {
+    /// The included file (None for the program itself) and line that line
+    /// `line` of the expanded program came from
+    pub fn locate(&self, line: usize) -> (Option<&Path>, usize) {
+        match line.checked_sub(1).and_then(|index| self.lines.get(index)) {
+            Some(&(0, original)) => (None, original),
+            Some(&(file, original)) => (Some(self.files[file - 1].as_path()), original),
+            None => (None, line),
+        }
+    }
+
+    /// The files read in, in order
+    pub fn included(&self) -> &[PathBuf] {
+        &self.files
+    }
+}
+
+/// `source` with the files it includes read in, looking for them in `dirs`
+/// in turn
+pub fn expand(source: &str, dirs: &[PathBuf]) -> QResult<(String, SourceMap)> {
+    let mut expanded = String::with_capacity(source.len());
+    let mut map = SourceMap::default();
+    expand_into(source, 0, dirs, &mut expanded, &mut map, 0)?;
+    Ok((expanded, map))
+}
+
+fn expand_into(
+    source: &str,
+    file: usize,
+    dirs: &[PathBuf],
+    expanded: &mut String,
+    map: &mut SourceMap,
+    depth: usize,
+) -> QResult<()> {
+    for (index, line) in source.lines().enumerate() {
+        expanded.push_str(line);
+        expanded.push('\n');
+        map.lines.push((file, index + 1));
+        let Some(name) = directive(line) else { continue };
+        let at = |message: String| match file {
+            0 => QError::compile(message, map.lines.len(), 0),
+            _ => QError::compile(format!("{}: {}", map.files[file - 1].display(), message), index + 1, 0),
+        };
+        if depth >= MAX_DEPTH {
+            return Err(at(format!("$INCLUDE nested more than {} deep", MAX_DEPTH)));
+        }
+        let path = dirs
+            .iter()
+            .map(|dir| dir.join(name))
+            .find(|path| path.is_file())
+            .ok_or_else(|| at(format!("File not found: {}", name)))?;
+        let bytes = fs::read(&path).map_err(|e| at(format!("{}: {}", name, e)))?;
+        map.files.push(path);
+        let included = map.files.len();
+        expand_into(&decode(&bytes), included, dirs, expanded, map, depth + 1)?;
+    }
+    Ok(())
+}
+
+/// The file an $INCLUDE comment names
+fn directive(line: &str) -> Option<&str> {
+    let text = line.trim_start();
+    let comment = match text.strip_prefix('\'') {
+        Some(rest) => rest,
+        None => {
+            let word = text.get(..3).filter(|word| word.eq_ignore_ascii_case("REM"))?;
+            text[word.len()..].strip_prefix([' ', '\t'])?
+        }
+    };
+    let comment = comment.trim_start();
+    let rest = comment.get(..8).filter(|word| word.eq_ignore_ascii_case("$INCLUDE"))?;
+    let rest = comment[rest.len()..].trim_start().strip_prefix(':')?.trim_start();
+    let name = rest.strip_prefix('\'')?;
+    Some(&name[..name.find('\'')?]).filter(|name| !name.trim().is_empty()).map(str::trim)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_directive() {
+        assert_eq!(directive("'$INCLUDE: 'common.bi'"), Some("common.bi"));
+        assert_eq!(directive("  REM $include:'QB.BI' trailing"), Some("QB.BI"));
+        assert_eq!(directive("' $INCLUDE : ' x.bi '"), Some("x.bi"));
+        assert_eq!(directive("PRINT \"'$INCLUDE: 'a.bi'\""), None);
+        assert_eq!(directive("REMARK $INCLUDE: 'a.bi'"), None);
+        assert_eq!(directive("'$INCLUDE: ''"), None);
+    }
+}
@@ -0,0 +1,190 @@
+//! QB-Driver: the compiler pipeline in one call
+//!
+//! Reads in $INCLUDE files, tokenizes for the chosen dialect, parses,
+//! analyzes, compiles to bytecode and optimizes it, so the CLI, the tests
+//! and programs embedding QB-COM all build a program the same way.
+
+pub mod include;
+pub mod optimize;
+
+use qb_core::errors::QError;
+use qb_core::Dialect;
+use qb_lexer::{decode, Scanner};
+use qb_parser::{parse, Program};
+use qb_semantic::{analyze, Names};
+use qb_vm::{compile, ByteCode};
+use std::fmt;
+use std::path::{Path, PathBuf};
+
+pub use include::SourceMap;
+
+/// How to build a program
+#[derive(Debug, Clone)]
+pub struct Options {
+    pub dialect: Dialect,
+    /// Read \xNN in string literals as the character with code NN
+    pub string_escapes: bool,
+    /// Directories searched for $INCLUDE files, in order
+    pub include_dirs: Vec<PathBuf>,
+    /// Run the bytecode passes of `optimize`
+    pub optimize: bool,
+}
+
+impl Default for Options {
+    fn default() -> Self {
+        Self { dialect: Dialect::Qb45, string_escapes: false, include_dirs: Vec::new(), optimize: true }
+    }
+}
+
+/// Everything a build makes
+pub struct Artifacts {
+    /// The analyzed program
+    pub program: Program,
+    /// The names the analysis settled on, which the debugger and REPL
+    /// look variables up by
+    pub names: Names,
+    pub bytecode: ByteCode,
+    /// Where the lines of the program came from
+    pub sources: SourceMap,
+}
+
+/// An error in a program, placed in the file it was found in
+#[derive(Debug, Clone)]
+pub struct Diagnostic {
+    /// The included file, or None for the program itself
+    pub file: Option<PathBuf>,
+    /// With its line that of the file
+    pub error: QError,
+}
+
+impl fmt::Display for Diagnostic {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match &self.file {
+            Some(file) => write!(f, "{}: {}", file.display(), self.error),
+            None => write!(f, "{}", self.error),
+        }
+    }
+}
+
+/// The errors that stopped a build
+#[derive(Debug, Clone)]
+pub struct Diagnostics(pub Vec<Diagnostic>);
+
+impl fmt::Display for Diagnostics {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        for (index, diagnostic) in self.0.iter().enumerate() {
+            if index > 0 {
+                writeln!(f)?;
+            }
+            write!(f, "{}", diagnostic)?;
+        }
+        Ok(())
+    }
+}
+
+impl std::error::Error for Diagnostics {}
+
+impl Diagnostics {
+    /// `error` in the expanded program, placed by `sources`
+    pub fn located(error: QError, sources: &SourceMap) -> Self {
+        let (file, error) = match error {
+            QError::Compile { message, line, column } => {
+                let (file, line) = sources.locate(line);
+                (file.map(Path::to_path_buf), QError::Compile { message, line, column })
+            }
+            QError::Runtime { code, message, line, column } => {
+                let (file, line) = sources.locate(line);
+                (file.map(Path::to_path_buf), QError::Runtime { code, message, line, column })
+            }
+            other => (None, other),
+        };
+        Diagnostics(vec![Diagnostic { file, error }])
+    }
+}
+
+impl From<QError> for Diagnostics {
+    fn from(error: QError) -> Self {
+        Diagnostics(vec![Diagnostic { file: None, error }])
+    }
+}
+
+/// Read in the includes of `source`, tokenize and parse it
+pub fn parse_source(source: &str, options: &Options) -> Result<(Program, SourceMap), Diagnostics> {
+    let (expanded, sources) = include::expand(source, &options.include_dirs)?;
+    let program = Scanner::for_dialect(&expanded, options.dialect)
+        .with_string_escapes(options.string_escapes)
+        .scan_tokens()
+        .and_then(parse)
+        .map_err(|error| Diagnostics::located(error, &sources))?;
+    Ok((program, sources))
+}
+
+/// Build `source` through to bytecode
+pub fn build(source: &str, options: &Options) -> Result<Artifacts, Diagnostics> {
+    let (mut program, sources) = parse_source(source, options)?;
+    let located = |error| Diagnostics::located(error, &sources);
+    let names = analyze(&mut program).map_err(located)?;
+    let mut bytecode = compile(&program).map_err(located)?;
+    if options.optimize {
+        optimize::optimize(&mut bytecode);
+    }
+    Ok(Artifacts { program, names, bytecode, sources })
+}
+
+/// Build the program in `file`, UTF-8 or code page 437, looking for its
+/// includes beside it first
+pub fn build_file(file: &Path, options: &Options) -> Result<Artifacts, Diagnostics> {
+    let bytes = std::fs::read(file)
+        .map_err(|e| QError::io(format!("Failed to read file: {}: {}", file.display(), e)))?;
+    build(&decode(&bytes), &options.for_file(file))
+}
+
+impl Options {
+    /// These options for the program in `file`, with its directory first
+    /// among those searched for includes
+    pub fn for_file(&self, file: &Path) -> Options {
+        let mut options = self.clone();
+        let dir = file.parent().map(Path::to_path_buf).unwrap_or_default();
+        options.include_dirs.insert(0, dir);
+        options
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use qb_core::QType;
+    use qb_vm::VirtualMachine;
+
+    #[test]
+    fn test_build_and_run() {
+        let artifacts = build("FOR i = 1 TO 3\n  IF i > 1 THEN x = x + i ELSE x = 10\nNEXT i\n", &Options::default()).unwrap();
+        let mut vm = VirtualMachine::new();
+        vm.execute(&artifacts.bytecode).unwrap();
+        assert!(vm.variables().contains(&("X!", &QType::Single(15.0))));
+    }
+
+    #[test]
+    fn test_includes_and_error_lines() {
+        let dir = std::env::temp_dir().join(format!("qb-driver-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        std::fs::write(dir.join("A.BI"), "CONST TOP = 3\n'$INCLUDE: 'B.BI'\n").unwrap();
+        std::fs::write(dir.join("B.BI"), "x = 1\n").unwrap();
+        let options = Options { include_dirs: vec![dir.clone()], ..Options::default() };
+
+        let artifacts = build("REM $INCLUDE: 'A.BI'\nPRINT TOP\n", &options).unwrap();
+        assert_eq!(artifacts.sources.included(), [dir.join("A.BI"), dir.join("B.BI")]);
+        assert_eq!(artifacts.sources.locate(4), (Some(dir.join("B.BI").as_path()), 1));
+        assert_eq!(artifacts.sources.locate(5), (None, 2));
+
+        // An error in an included file is reported at its own line
+        std::fs::write(dir.join("B.BI"), "x = 1\nx = (\n").unwrap();
+        let Err(error) = build("'$INCLUDE: 'A.BI'\n", &options) else { panic!("built") };
+        assert_eq!(error.0[0].file.as_deref(), Some(dir.join("B.BI").as_path()));
+        assert!(matches!(error.0[0].error, QError::Compile { line: 2, .. }));
+
+        let Err(missing) = build("'$INCLUDE: 'NONE.BI'\n", &options) else { panic!("built") };
+        assert!(missing.to_string().contains("File not found: NONE.BI"));
+        std::fs::remove_dir_all(dir).unwrap();
+    }
+}
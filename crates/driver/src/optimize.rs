@@ -0,0 +1,70 @@
+//! Passes over compiled bytecode that make it faster without changing what
+//! it does
+//!
+//! A pass may not move instructions, since their addresses are in the line
+//! table, the DATA labels and the event traps.
+
+use qb_vm::{ByteCode, OpCode};
+use std::collections::HashSet;
+
+/// Run every pass
+pub fn optimize(bytecode: &mut ByteCode) {
+    thread_jumps(bytecode);
+}
+
+/// Point jumps that land on another jump straight at where it goes, as the
+/// compiler leaves them at the end of nested IF and loop blocks. A jump
+/// that starts a source line, such as GOTO, is kept, so the line still
+/// counts as run for TRON and coverage.
+pub fn thread_jumps(bytecode: &mut ByteCode) {
+    let line_starts: HashSet<usize> = bytecode.lines.iter().map(|&(first, _)| first).collect();
+    let instructions = &bytecode.instructions;
+    let follow = |mut target: u32| {
+        // Bounded, as a jump may lead round in a circle
+        for _ in 0..instructions.len() {
+            match instructions.get(target as usize) {
+                Some(OpCode::Jump(next)) if !line_starts.contains(&(target as usize)) && *next != target => target = *next,
+                _ => break,
+            }
+        }
+        target
+    };
+    let threaded: Vec<Option<OpCode>> = instructions
+        .iter()
+        .map(|op| match op {
+            OpCode::Jump(target) => Some(OpCode::Jump(follow(*target))),
+            OpCode::JumpIfTrue(target) => Some(OpCode::JumpIfTrue(follow(*target))),
+            OpCode::JumpIfFalse(target) => Some(OpCode::JumpIfFalse(follow(*target))),
+            _ => None,
+        })
+        .collect();
+    for (op, new) in bytecode.instructions.iter_mut().zip(threaded) {
+        if let Some(new) = new {
+            *op = new;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_thread_jumps() {
+        let mut bytecode = ByteCode::new();
+        for op in [OpCode::JumpIfFalse(2), OpCode::Jump(3), OpCode::Jump(4), OpCode::Jump(5), OpCode::Jump(4), OpCode::Halt] {
+            bytecode.emit(op);
+        }
+        // Instruction 4 starts a line
+        bytecode.lines = vec![(0, 1), (4, 2)];
+        thread_jumps(&mut bytecode);
+        let targets: Vec<u32> = bytecode.instructions[..5]
+            .iter()
+            .map(|op| match op {
+                OpCode::Jump(target) | OpCode::JumpIfFalse(target) => *target,
+                _ => unreachable!(),
+            })
+            .collect();
+        assert_eq!(targets, vec![4, 5, 4, 5, 4]);
+    }
+}
@@ -0,0 +1,195 @@
+//! QB-Golden: the example corpus, run and checked against snapshots
+//!
+//! Each program `examples/corpus/NAME.bas` has beside it the snapshot
+//! `NAME.out` of what it prints, and may have `NAME.in`, the lines typed at
+//! its INPUT statements. A program runs in a VM with a virtual clock and
+//! no window, so it prints the same every time; a runtime error ends the
+//! output as the CLI would report it.
+//!
+//! When a change to QB-COM changes what a program prints on purpose, run
+//! the tests with `QB_UPDATE_SNAPSHOTS=1` to write the new snapshots, and
+//! check the difference before committing them. A new program gets its
+//! first snapshot the same way; until then its test fails.
+
+use qb_driver::{Diagnostics, Options};
+use qb_hal::Clock;
+use qb_vm::{ConsoleInput, RecordedInput, Session, VirtualMachine};
+use std::cell::RefCell;
+use std::fs;
+use std::io::{self, Write};
+use std::path::{Path, PathBuf};
+use std::rc::Rc;
+
+/// Environment variable that makes `check` write snapshots
+pub const UPDATE_VAR: &str = "QB_UPDATE_SNAPSHOTS";
+
+/// The directory of the corpus
+pub fn corpus_dir() -> PathBuf {
+    Path::new(env!("CARGO_MANIFEST_DIR")).join("../../examples/corpus")
+}
+
+/// The programs in `dir`, sorted by name
+pub fn programs(dir: &Path) -> io::Result<Vec<PathBuf>> {
+    let mut programs: Vec<PathBuf> = fs::read_dir(dir)?
+        .filter_map(|entry| Some(entry.ok()?.path()))
+        .filter(|path| path.extension().is_some_and(|ext| ext.eq_ignore_ascii_case("bas")))
+        .collect();
+    programs.sort();
+    Ok(programs)
+}
+
+/// The lines typed at the INPUT statements of the program in `file`
+fn input(file: &Path) -> Vec<String> {
+    match fs::read_to_string(file.with_extension("in")) {
+        Ok(text) => text.lines().map(String::from).collect(),
+        Err(_) => Vec::new(),
+    }
+}
+
+/// Console output shared between the VM and the caller
+#[derive(Clone, Default)]
+struct Capture(Rc<RefCell<Vec<u8>>>);
+
+impl Write for Capture {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.0.borrow_mut().extend_from_slice(buf);
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+/// What the program in `file` prints with `input` typed at the console.
+/// Only a program that does not build is an error.
+pub fn run_program(file: &Path, input: &[String]) -> Result<String, Diagnostics> {
+    let artifacts = qb_driver::build_file(file, &Options::default())?;
+    let capture = Capture::default();
+    let mut vm = VirtualMachine::new();
+    vm.set_console_output(Box::new(capture.clone()));
+    vm.set_clock(Clock::fixed(0.0));
+    let inputs = input.iter().map(|text| RecordedInput::Line { delay_ms: 0, text: text.clone() }).collect();
+    vm.set_console_input(ConsoleInput::replay(Session { inputs }, false));
+    vm.set_command_line(vec![file.display().to_string()]);
+    let result = vm.execute(&artifacts.bytecode);
+    let mut output = String::from_utf8_lossy(&capture.0.borrow()).into_owned();
+    if let Err(error) = result {
+        if !output.is_empty() && !output.ends_with('\n') {
+            output.push('\n');
+        }
+        output.push_str(&format!("Error: {}\n", error));
+    }
+    Ok(output)
+}
+
+/// How a program's output compared with its snapshot
+#[derive(Debug, Clone, PartialEq)]
+pub enum Outcome {
+    Matches,
+    /// There is no snapshot, and `update` is not set
+    Missing,
+    /// The snapshot was written, `update` being set
+    Written,
+    Differs { expected: String, actual: String },
+}
+
+/// Run the program in `file` and compare what it prints with its
+/// snapshot, writing the snapshot instead when `update`
+pub fn check(file: &Path, update: bool) -> Result<Outcome, String> {
+    let actual = run_program(file, &input(file)).map_err(|e| e.to_string())?;
+    let snapshot = file.with_extension("out");
+    match fs::read_to_string(&snapshot) {
+        // Git may check the snapshot out with CRLF line endings
+        Ok(expected) if expected.replace("\r\n", "\n") == actual => Ok(Outcome::Matches),
+        Ok(expected) if !update => Ok(Outcome::Differs { expected, actual }),
+        Err(_) if !update => Ok(Outcome::Missing),
+        _ => {
+            fs::write(&snapshot, &actual).map_err(|e| format!("{}: {}", snapshot.display(), e))?;
+            Ok(Outcome::Written)
+        }
+    }
+}
+
+/// The first line where two outputs differ, for a short report
+pub fn first_difference(expected: &str, actual: &str) -> String {
+    let (mut expected_lines, mut actual_lines) = (expected.lines(), actual.lines());
+    for number in 1.. {
+        match (expected_lines.next(), actual_lines.next()) {
+            (None, None) => break,
+            (want, got) if want != got => {
+                return format!("line {}: expected {:?}, got {:?}", number, want.unwrap_or(""), got.unwrap_or(""));
+            }
+            _ => {}
+        }
+    }
+    "line endings differ".to_string()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_corpus() {
+        let update = std::env::var_os(UPDATE_VAR).is_some();
+        let programs = programs(&corpus_dir()).unwrap();
+        assert!(!programs.is_empty());
+        let mut failures = Vec::new();
+        for program in &programs {
+            let name = program.file_name().unwrap().to_string_lossy();
+            match check(program, update) {
+                Ok(Outcome::Matches) => {}
+                Ok(Outcome::Missing) => failures.push(format!("{}: no snapshot", name)),
+                Ok(Outcome::Written) => eprintln!("{}: snapshot written", name),
+                Ok(Outcome::Differs { expected, actual }) => {
+                    failures.push(format!("{}: {}", name, first_difference(&expected, &actual)));
+                }
+                Err(error) => failures.push(format!("{}: {}", name, error)),
+            }
+        }
+        assert!(failures.is_empty(), "set {}=1 to accept new output\n{}", UPDATE_VAR, failures.join("\n"));
+    }
+
+    #[test]
+    fn test_emitted_source() {
+        // Printed back from its syntax tree, each program runs the same
+        let dir = std::env::temp_dir().join(format!("qb_emitted_{}", std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        let mut failures = Vec::new();
+        for program in programs(&corpus_dir()).unwrap() {
+            let name = program.file_name().unwrap().to_string_lossy().into_owned();
+            let source = fs::read_to_string(&program).unwrap();
+            let (ast, _) = qb_driver::parse_source(&source, &Options::default()).unwrap();
+            let emitted = dir.join(&name);
+            fs::write(&emitted, qb_parser::to_source(&ast)).unwrap();
+            let expected = fs::read_to_string(program.with_extension("out")).unwrap().replace("\r\n", "\n");
+            match run_program(&emitted, &input(&program)) {
+                Ok(actual) if actual == expected => {}
+                Ok(actual) => failures.push(format!("{}: {}", name, first_difference(&expected, &actual))),
+                Err(error) => failures.push(format!("{}: {}", name, error)),
+            }
+        }
+        fs::remove_dir_all(&dir).unwrap();
+        assert!(failures.is_empty(), "{}", failures.join("\n"));
+    }
+
+    #[test]
+    fn test_missing_snapshot() {
+        let dir = std::env::temp_dir().join(format!("qb_snapshot_{}", std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        let program = dir.join("new.bas");
+        fs::write(&program, "PRINT \"new\"\n").unwrap();
+        assert_eq!(check(&program, false), Ok(Outcome::Missing));
+        assert!(!program.with_extension("out").exists());
+        assert_eq!(check(&program, true), Ok(Outcome::Written));
+        assert_eq!(check(&program, false), Ok(Outcome::Matches));
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn test_first_difference() {
+        assert_eq!(first_difference("a\nb\n", "a\nc\n"), "line 2: expected \"b\", got \"c\"");
+        assert_eq!(first_difference("a\n", "a\nmore\n"), "line 2: expected \"\", got \"more\"");
+    }
+}
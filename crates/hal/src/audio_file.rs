@@ -0,0 +1,153 @@
+//! WAV files: loading them for _SNDOPEN, and writing the mixed sound of a
+//! run to one
+
+use crate::mixer::{AudioSink, SAMPLE_RATE};
+use qb_core::errors::{QError, QErrorCode, QResult};
+use std::io::{self, Seek, SeekFrom, Write};
+use std::path::Path;
+
+/// Load an 8, 16 or 32-bit PCM or 32-bit float WAV file as samples at
+/// `SAMPLE_RATE`, its channels mixed to one
+pub fn load_wav(path: &Path) -> QResult<Vec<f32>> {
+    let bytes = std::fs::read(path).map_err(|_| QError::runtime(QErrorCode::FileNotFound, 0, 0))?;
+    decode_wav(&bytes)
+}
+
+fn bad_sound() -> QError {
+    QError::runtime(QErrorCode::BadFileMode, 0, 0)
+}
+
+fn decode_wav(bytes: &[u8]) -> QResult<Vec<f32>> {
+    if bytes.get(0..4) != Some(b"RIFF") || bytes.get(8..12) != Some(b"WAVE") {
+        return Err(bad_sound());
+    }
+    let mut format = None;
+    let mut data = None;
+    let mut at = 12;
+    while let Some(header) = bytes.get(at..at + 8) {
+        let size = u32::from_le_bytes([header[4], header[5], header[6], header[7]]) as usize;
+        let body = bytes.get(at + 8..(at + 8 + size).min(bytes.len())).ok_or_else(bad_sound)?;
+        match &header[..4] {
+            b"fmt " => format = Some(body),
+            b"data" => data = Some(body),
+            _ => {}
+        }
+        // Chunks are padded to an even length
+        at += 8 + size + size % 2;
+    }
+    let (format, data) = format.zip(data).ok_or_else(bad_sound)?;
+    let field = |at: usize| format.get(at..at + 2).map(|b| u16::from_le_bytes([b[0], b[1]])).ok_or_else(bad_sound);
+    let (encoding, channels, bits) = (field(0)?, usize::from(field(2)?), field(14)?);
+    let rate = format.get(4..8).map(|b| u32::from_le_bytes([b[0], b[1], b[2], b[3]])).ok_or_else(bad_sound)?;
+    let width = usize::from(bits / 8);
+    if channels == 0 || rate == 0 || width == 0 {
+        return Err(bad_sound());
+    }
+    let sample = |b: &[u8]| -> QResult<f32> {
+        Ok(match (encoding, bits) {
+            (1, 8) => (f32::from(b[0]) - 128.0) / 128.0,
+            (1, 16) => f32::from(i16::from_le_bytes([b[0], b[1]])) / 32768.0,
+            (1, 32) => i32::from_le_bytes([b[0], b[1], b[2], b[3]]) as f32 / 2_147_483_648.0,
+            (3, 32) => f32::from_le_bytes([b[0], b[1], b[2], b[3]]),
+            _ => return Err(bad_sound()),
+        })
+    };
+    let frames = data
+        .chunks_exact(width * channels)
+        .map(|frame| {
+            let sum = frame.chunks_exact(width).map(sample).sum::<QResult<f32>>()?;
+            Ok(sum / channels as f32)
+        })
+        .collect::<QResult<Vec<f32>>>()?;
+    Ok(resample(&frames, rate))
+}
+
+/// Samples at `rate` converted to `SAMPLE_RATE` by linear interpolation
+fn resample(samples: &[f32], rate: u32) -> Vec<f32> {
+    if rate == SAMPLE_RATE || samples.is_empty() {
+        return samples.to_vec();
+    }
+    let step = f64::from(rate) / f64::from(SAMPLE_RATE);
+    let length = (samples.len() as f64 / step) as usize;
+    (0..length)
+        .map(|i| {
+            let position = i as f64 * step;
+            let first = position as usize;
+            let next = (first + 1).min(samples.len() - 1);
+            let t = (position - first as f64) as f32;
+            samples[first] * (1.0 - t) + samples[next] * t
+        })
+        .collect()
+}
+
+/// Writes the mixer's output as a 16-bit mono WAV file. The sizes in the
+/// header are filled in by `finish`.
+pub struct WavWriter<W: Write + Seek> {
+    out: W,
+    samples: u32,
+}
+
+impl<W: Write + Seek> WavWriter<W> {
+    pub fn new(mut out: W) -> io::Result<Self> {
+        out.write_all(b"RIFF\0\0\0\0WAVEfmt ")?;
+        out.write_all(&16u32.to_le_bytes())?;
+        out.write_all(&1u16.to_le_bytes())?; // PCM
+        out.write_all(&1u16.to_le_bytes())?; // mono
+        out.write_all(&SAMPLE_RATE.to_le_bytes())?;
+        out.write_all(&(SAMPLE_RATE * 2).to_le_bytes())?;
+        out.write_all(&2u16.to_le_bytes())?;
+        out.write_all(&16u16.to_le_bytes())?;
+        out.write_all(b"data\0\0\0\0")?;
+        Ok(Self { out, samples: 0 })
+    }
+}
+
+impl<W: Write + Seek> AudioSink for WavWriter<W> {
+    fn write(&mut self, samples: &[f32]) -> io::Result<()> {
+        let bytes: Vec<u8> = samples
+            .iter()
+            .flat_map(|s| ((s.clamp(-1.0, 1.0) * 32767.0).round() as i16).to_le_bytes())
+            .collect();
+        self.out.write_all(&bytes)?;
+        self.samples += samples.len() as u32;
+        Ok(())
+    }
+
+    fn finish(&mut self) -> io::Result<()> {
+        let data = self.samples * 2;
+        self.out.seek(SeekFrom::Start(4))?;
+        self.out.write_all(&(36 + data).to_le_bytes())?;
+        self.out.seek(SeekFrom::Start(40))?;
+        self.out.write_all(&data.to_le_bytes())?;
+        self.out.seek(SeekFrom::End(0))?;
+        self.out.flush()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::Cursor;
+
+    #[test]
+    fn test_write_and_read_back() {
+        let mut writer = WavWriter::new(Cursor::new(Vec::new())).unwrap();
+        writer.write(&[0.0, 0.5, -0.5, 2.0]).unwrap();
+        writer.finish().unwrap();
+        let bytes = writer.out.into_inner();
+        assert_eq!(bytes.len(), 44 + 8);
+        let samples = decode_wav(&bytes).unwrap();
+        assert_eq!(samples.len(), 4);
+        assert!((samples[1] - 0.5).abs() < 0.001);
+        // Clipped on the way out
+        assert!((samples[3] - 1.0).abs() < 0.001);
+    }
+
+    #[test]
+    fn test_resample() {
+        let half_rate = resample(&[0.0, 1.0], SAMPLE_RATE / 2);
+        assert_eq!(half_rate.len(), 4);
+        assert_eq!(half_rate[1], 0.5);
+        assert!(decode_wav(b"RIFF\0\0\0\0WAVE").is_err());
+    }
+}
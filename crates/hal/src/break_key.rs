@@ -0,0 +1,79 @@
+//! Ctrl+Break: once installed, Ctrl+C (SIGINT on Unix, the console control
+//! handler on Windows) no longer kills the process but sets a flag the VM
+//! checks between instructions
+
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Once;
+
+static PENDING: AtomicBool = AtomicBool::new(false);
+static INSTALL: Once = Once::new();
+
+/// Catch Ctrl+C from now on; later calls do nothing
+pub fn install() {
+    INSTALL.call_once(platform::install);
+}
+
+/// Whether Ctrl+C was pressed since the last call
+pub fn take() -> bool {
+    PENDING.load(Ordering::Relaxed) && PENDING.swap(false, Ordering::Relaxed)
+}
+
+/// Act as if Ctrl+C was pressed
+pub fn press() {
+    PENDING.store(true, Ordering::Relaxed);
+}
+
+#[cfg(unix)]
+mod platform {
+    extern "C" fn on_interrupt(_signal: libc::c_int) {
+        super::press();
+    }
+
+    pub fn install() {
+        let handler: extern "C" fn(libc::c_int) = on_interrupt;
+        // SAFETY: the handler only stores to an atomic, which is
+        // async-signal-safe
+        unsafe {
+            libc::signal(libc::SIGINT, handler as libc::sighandler_t);
+        }
+    }
+}
+
+#[cfg(windows)]
+mod platform {
+    use windows_sys::Win32::Foundation::BOOL;
+    use windows_sys::Win32::System::Console::{SetConsoleCtrlHandler, CTRL_BREAK_EVENT, CTRL_C_EVENT};
+
+    unsafe extern "system" fn on_control(event: u32) -> BOOL {
+        if event == CTRL_C_EVENT || event == CTRL_BREAK_EVENT {
+            super::press();
+            1
+        } else {
+            0
+        }
+    }
+
+    pub fn install() {
+        // SAFETY: registering a handler that only stores to an atomic
+        unsafe {
+            SetConsoleCtrlHandler(Some(on_control), 1);
+        }
+    }
+}
+
+#[cfg(not(any(unix, windows)))]
+mod platform {
+    pub fn install() {}
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_press_is_taken_once() {
+        press();
+        assert!(take());
+        assert!(!take());
+    }
+}
@@ -0,0 +1,175 @@
+//! The clock every timed feature reads: TIMER, the BIOS tick count, the
+//! SOUND and PLAY queue, _LIMIT and ON TIMER. It follows real time by
+//! default. A virtual clock starts at a fixed time of day and moves only
+//! when the program waits or runs, so timed programs run the same way
+//! every time and without the waiting.
+
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Arc;
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+
+const SECONDS_PER_DAY: f64 = 86_400.0;
+
+/// Days from 1 January 1970 to 1 January 1980, the date a DOS machine
+/// without a battery-backed clock started on
+const DOS_EPOCH_DAY: i64 = 3652;
+
+/// The BIOS tick: the 1.193182 MHz timer chip divided by 65536
+pub const TICKS_PER_SECOND: f64 = 1_193_182.0 / 65_536.0;
+
+#[derive(Debug)]
+enum Source {
+    Real(Instant),
+    /// Nanoseconds waited so far
+    Virtual(AtomicU64),
+}
+
+/// A handle on the clock; clones share it
+#[derive(Debug, Clone)]
+pub struct Clock {
+    /// Seconds since midnight when the clock started
+    start_of_day: f64,
+    /// Days since 1 January 1970 when the clock started
+    start_day: i64,
+    source: Arc<Source>,
+}
+
+impl Clock {
+    /// The system clock
+    pub fn real() -> Self {
+        let now = SystemTime::now().duration_since(UNIX_EPOCH).unwrap_or_default();
+        Self {
+            start_of_day: now.as_secs_f64() % SECONDS_PER_DAY,
+            start_day: (now.as_secs() / 86_400) as i64,
+            source: Arc::new(Source::Real(Instant::now())),
+        }
+    }
+
+    /// A virtual clock reading `seconds_since_midnight` on 1 January 1980
+    /// until something waits on it
+    pub fn fixed(seconds_since_midnight: f64) -> Self {
+        Self {
+            start_of_day: seconds_since_midnight.rem_euclid(SECONDS_PER_DAY),
+            start_day: DOS_EPOCH_DAY,
+            source: Arc::new(Source::Virtual(AtomicU64::new(0))),
+        }
+    }
+
+    pub fn is_virtual(&self) -> bool {
+        matches!(*self.source, Source::Virtual(_))
+    }
+
+    /// Time since the clock started
+    pub fn elapsed(&self) -> Duration {
+        match &*self.source {
+            Source::Real(start) => start.elapsed(),
+            Source::Virtual(nanos) => Duration::from_nanos(nanos.load(Ordering::Relaxed)),
+        }
+    }
+
+    /// TIMER: seconds since midnight, wrapping at midnight
+    pub fn seconds_since_midnight(&self) -> f64 {
+        (self.start_of_day + self.elapsed().as_secs_f64()) % SECONDS_PER_DAY
+    }
+
+    /// Year, month and day of the date, which moves on at midnight
+    pub fn date(&self) -> (i64, u32, u32) {
+        let days = self.start_day + ((self.start_of_day + self.elapsed().as_secs_f64()) / SECONDS_PER_DAY) as i64;
+        // Days to the civil date, counting in 400-year eras from 1 March 0
+        let days = days + 719_468;
+        let era = days.div_euclid(146_097);
+        let day_of_era = days.rem_euclid(146_097);
+        let year_of_era = (day_of_era - day_of_era / 1460 + day_of_era / 36_524 - day_of_era / 146_096) / 365;
+        let day_of_year = day_of_era - (365 * year_of_era + year_of_era / 4 - year_of_era / 100);
+        let month_from_march = (5 * day_of_year + 2) / 153;
+        let day = (day_of_year - (153 * month_from_march + 2) / 5 + 1) as u32;
+        let month = if month_from_march < 10 { month_from_march + 3 } else { month_from_march - 9 } as u32;
+        let year = year_of_era + era * 400 + i64::from(month <= 2);
+        (year, month, day)
+    }
+
+    /// Seconds since midnight in whole BIOS ticks, as DOS's TIMER saw them
+    pub fn bios_seconds(&self) -> f64 {
+        (self.seconds_since_midnight() * TICKS_PER_SECOND).floor() / TICKS_PER_SECOND
+    }
+
+    pub fn sleep(&self, length: Duration) {
+        match &*self.source {
+            Source::Real(_) => std::thread::sleep(length),
+            Source::Virtual(_) => self.step(length),
+        }
+    }
+
+    /// Move a virtual clock on by `length`, the time some work took; the
+    /// real clock moves by itself
+    pub fn step(&self, length: Duration) {
+        if let Source::Virtual(nanos) = &*self.source {
+            let length = u64::try_from(length.as_nanos()).unwrap_or(u64::MAX);
+            nanos.fetch_add(length, Ordering::Relaxed);
+        }
+    }
+
+    /// Wait until `elapsed` reaches `time`
+    pub fn sleep_until(&self, time: Duration) {
+        let now = self.elapsed();
+        if time > now {
+            self.sleep(time - now);
+        }
+    }
+}
+
+impl Default for Clock {
+    fn default() -> Self {
+        Self::real()
+    }
+}
+
+/// _LIMIT: holds a loop to a number of passes per second
+#[derive(Debug, Default)]
+pub struct Limiter {
+    next: Option<Duration>,
+}
+
+impl Limiter {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Wait out the rest of the current pass at `rate` passes per second.
+    /// A loop that falls behind starts counting again rather than racing
+    /// to catch up.
+    pub fn wait(&mut self, clock: &Clock, rate: f64) {
+        if rate <= 0.0 {
+            return;
+        }
+        let now = clock.elapsed();
+        let due = self.next.unwrap_or(now);
+        clock.sleep_until(due);
+        self.next = Some(due.max(now) + Duration::from_secs_f64(1.0 / rate));
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_virtual_clock() {
+        let clock = Clock::fixed(86_399.5);
+        let shared = clock.clone();
+        assert_eq!(clock.seconds_since_midnight(), 86_399.5);
+        shared.sleep(Duration::from_secs(1));
+        assert_eq!(clock.seconds_since_midnight(), 0.5);
+        // 9.1 ticks have passed since midnight
+        assert_eq!(clock.bios_seconds(), 9.0 / TICKS_PER_SECOND);
+        // Midnight passed into the second day of 1980
+        assert_eq!(clock.date(), (1980, 1, 2));
+
+        // 10 passes a second: the first runs at once, each later one waits
+        let mut limiter = Limiter::new();
+        for _ in 0..3 {
+            limiter.wait(&clock, 10.0);
+        }
+        assert_eq!(clock.elapsed(), Duration::from_millis(1200));
+    }
+}
@@ -0,0 +1,226 @@
+//! How a frame becomes the picture on the display: scaling to the space
+//! there is, 4:3 aspect correction of the old screen modes, a CRT's
+//! scanlines and the precision of the VGA DAC. Set from the configuration
+//! file and by _FULLSCREEN.
+
+use crate::graphics::Image;
+use serde::{Deserialize, Serialize};
+
+/// How the frame is sized to the display
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Scaling {
+    /// As large as fits, keeping the shape
+    #[default]
+    Fit,
+    /// The largest whole multiple of the frame that fits, so every pixel
+    /// is the same size (_FULLSCREEN _SQUAREPIXELS)
+    Integer,
+    /// Filling the display, whatever its shape (_FULLSCREEN _STRETCH)
+    Stretch,
+}
+
+/// How pixels are sampled when scaled
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Filter {
+    /// Averaged when shrunk and blended when grown (_SMOOTH)
+    #[default]
+    Smooth,
+    /// The nearest pixel, keeping edges sharp
+    Nearest,
+}
+
+/// _FULLSCREEN: whether the window fills the display, and how the frame is
+/// scaled to it there
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Serialize, Deserialize)]
+pub enum FullScreen {
+    /// In a window (_FULLSCREEN _OFF)
+    #[default]
+    Off,
+    Stretch,
+    SquarePixels,
+}
+
+impl FullScreen {
+    /// The _FULLSCREEN function's value: 0 in a window, 1 for _STRETCH and
+    /// 2 for _SQUAREPIXELS
+    pub fn code(self) -> i16 {
+        match self {
+            FullScreen::Off => 0,
+            FullScreen::Stretch => 1,
+            FullScreen::SquarePixels => 2,
+        }
+    }
+
+    pub fn scaling(self) -> Scaling {
+        match self {
+            FullScreen::Off => Scaling::Fit,
+            FullScreen::Stretch => Scaling::Stretch,
+            FullScreen::SquarePixels => Scaling::Integer,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Serialize, Deserialize)]
+#[serde(default)]
+pub struct DisplayOptions {
+    pub scaling: Scaling,
+    pub filter: Filter,
+    /// Show palette screen modes at 4:3, as a CRT did: SCREEN 13's 320x200
+    /// fills the shape of 320x240
+    pub aspect_correction: bool,
+    /// Darken the gaps between the frame's lines when each gets two or
+    /// more display lines
+    pub scanlines: bool,
+    /// Show palette colors at the 6 bits per channel a VGA DAC has,
+    /// instead of the full 8 bits _PALETTECOLOR can set
+    pub vga_dac: bool,
+}
+
+/// Share of the brightness a scanline gap keeps
+const SCANLINE_LEVEL: f64 = 0.6;
+
+impl DisplayOptions {
+    /// Size of the picture for `frame` on a display of `target` pixels
+    pub fn output_size(&self, frame: &Image, target: (u32, u32)) -> (u32, u32) {
+        let (width, height) = (frame.width as f64, frame.height as f64);
+        let shape_height = if self.aspect_correction && frame.bits <= 8 { width * 3.0 / 4.0 } else { height };
+        let (target_width, target_height) = (target.0 as f64, target.1 as f64);
+        let fit = (target_width / width).min(target_height / shape_height);
+        let (out_width, out_height) = match self.scaling {
+            Scaling::Stretch => (target_width, target_height),
+            Scaling::Integer if fit >= 1.0 => {
+                let factor = fit.floor();
+                (width * factor, (shape_height * factor).min(target_height))
+            }
+            Scaling::Fit | Scaling::Integer => (width * fit, shape_height * fit),
+        };
+        ((out_width as u32).max(1), (out_height as u32).max(1))
+    }
+
+    /// The picture for `frame` on a display of `target` pixels, as a 32-bit
+    /// image
+    pub fn render(&self, frame: &Image, target: (u32, u32)) -> Image {
+        let (out_width, out_height) = self.output_size(frame, target);
+        let columns = self.weights(frame.width, out_width);
+        let rows = self.weights(frame.height, out_height);
+        let gaps = self.scanlines && out_height >= frame.height * 2;
+
+        let mut picture = Image::new(out_width, out_height, 32);
+        for (y, row) in rows.iter().enumerate() {
+            for (x, column) in columns.iter().enumerate() {
+                let mut sum = [0.0; 3];
+                for &(sy, wy) in row {
+                    for &(sx, wx) in column {
+                        let value = frame.point(sx as i32, sy as i32).unwrap_or(0);
+                        let argb = self.color(frame, value);
+                        for (channel, shift) in sum.iter_mut().zip([16, 8, 0]) {
+                            *channel += f64::from((argb >> shift) as u8) * wx * wy;
+                        }
+                    }
+                }
+                if gaps && ((y as f64 + 0.5) * frame.height as f64 / out_height as f64).fract() >= 0.5 {
+                    sum = sum.map(|channel| channel * SCANLINE_LEVEL);
+                }
+                let [r, g, b] = sum.map(|channel| channel.round().clamp(0.0, 255.0) as u32);
+                picture.pset(x as i32, y as i32, 0xFF000000 | (r << 16) | (g << 8) | b);
+            }
+        }
+        picture
+    }
+
+    /// ARGB a pixel value is shown as
+    fn color(&self, frame: &Image, value: u32) -> u32 {
+        let argb = frame.to_argb(value);
+        if !self.vga_dac || frame.bits > 8 {
+            return argb;
+        }
+        let dac = |shift: u32| {
+            let level = ((argb >> shift) & 0xFF) >> 2;
+            ((level * 255 + 31) / 63) << shift
+        };
+        (argb & 0xFF000000) | dac(16) | dac(8) | dac(0)
+    }
+
+    /// For each of `out` display pixels along an axis, the frame pixels
+    /// along the same axis it is made of and their weights, adding to 1
+    fn weights(&self, size: u32, out: u32) -> Vec<Vec<(u32, f64)>> {
+        let ratio = size as f64 / out as f64;
+        (0..out)
+            .map(|o| {
+                let center = (o as f64 + 0.5) * ratio;
+                match self.filter {
+                    Filter::Nearest => vec![((center as u32).min(size - 1), 1.0)],
+                    // Growing: blend the two nearest pixels
+                    Filter::Smooth if ratio < 1.0 => {
+                        let position = (center - 0.5).clamp(0.0, (size - 1) as f64);
+                        let first = position.floor();
+                        let t = position - first;
+                        let next = (first as u32 + 1).min(size - 1);
+                        vec![(first as u32, 1.0 - t), (next, t)]
+                    }
+                    // Shrinking: average what the display pixel covers
+                    Filter::Smooth => {
+                        let (start, end) = (o as f64 * ratio, (o as f64 + 1.0) * ratio);
+                        (start.floor() as u32..(end.ceil() as u32).min(size))
+                            .map(|i| {
+                                let covered = (end.min(i as f64 + 1.0) - start.max(i as f64)).max(0.0);
+                                (i, covered / ratio)
+                            })
+                            .collect()
+                    }
+                }
+            })
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_output_size() {
+        let screen13 = Image::new(320, 200, 8);
+        let options = DisplayOptions::default();
+        assert_eq!(options.output_size(&screen13, (800, 600)), (800, 500));
+        let corrected = DisplayOptions { aspect_correction: true, ..options };
+        assert_eq!(corrected.output_size(&screen13, (800, 600)), (800, 600));
+        let integer = DisplayOptions { scaling: Scaling::Integer, ..options };
+        assert_eq!(integer.output_size(&screen13, (800, 600)), (640, 400));
+        assert_eq!(
+            DisplayOptions { aspect_correction: true, ..integer }.output_size(&screen13, (800, 600)),
+            (640, 480)
+        );
+        let stretch = DisplayOptions { scaling: Scaling::Stretch, ..options };
+        assert_eq!(stretch.output_size(&screen13, (800, 450)), (800, 450));
+        // 32-bit images have square pixels
+        assert_eq!(corrected.output_size(&Image::new(320, 200, 32), (800, 600)), (800, 500));
+    }
+
+    #[test]
+    fn test_render() {
+        let mut frame = Image::new(2, 1, 8);
+        frame.pset(0, 0, 15);
+        frame.palette[1] = 0xFF818181;
+        frame.pset(1, 0, 1);
+
+        let nearest = DisplayOptions { filter: Filter::Nearest, ..DisplayOptions::default() };
+        let picture = nearest.render(&frame, (4, 2));
+        assert_eq!((picture.width, picture.height), (4, 2));
+        assert_eq!(picture.point(1, 1), Some(0xFFFFFFFF));
+        assert_eq!(picture.point(2, 0), Some(0xFF818181));
+
+        // The DAC keeps 6 bits of 0x81, which it shows as 0x82
+        let dac = DisplayOptions { vga_dac: true, ..nearest };
+        assert_eq!(dac.render(&frame, (2, 1)).point(1, 0), Some(0xFF828282));
+        // Shrinking averages
+        assert_eq!(DisplayOptions::default().render(&frame, (1, 1)).point(0, 0), Some(0xFFC0C0C0));
+
+        let scanlines = DisplayOptions { scanlines: true, ..nearest };
+        let picture = scanlines.render(&frame, (4, 2));
+        assert_eq!(picture.point(0, 0), Some(0xFFFFFFFF));
+        assert_eq!(picture.point(0, 1), Some(0xFF999999));
+    }
+}
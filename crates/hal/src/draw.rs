@@ -0,0 +1,238 @@
+//! DRAW: the graphics macro language
+//!
+//! A DRAW string moves a pen from the last point referenced, drawing as it
+//! goes. U, D, L, R, E, F, G and H move by a number of steps in the eight
+//! directions, M moves to a point or by an offset, and the B and N prefixes
+//! make the next move blank or return the pen afterwards. A, TA, S and C
+//! set the rotation, scale and color for this and later DRAW strings.
+//!
+//! X substrings and =variable; arguments are expanded by the caller, which
+//! knows the program's variables, before the string gets here.
+
+use crate::graphics::{Graphics, LineShape};
+use qb_core::errors::{QError, QErrorCode, QResult};
+
+/// Pen settings that carry over from one DRAW string to the next
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Turtle {
+    /// Rotation in degrees counterclockwise, from A (in quarter turns) or TA
+    angle: f64,
+    /// S: steps are scale / 4 pixels long
+    scale: u32,
+    /// C: None draws in the foreground color
+    color: Option<u32>,
+}
+
+impl Turtle {
+    pub fn new() -> Self {
+        Self { angle: 0.0, scale: 4, color: None }
+    }
+
+    /// Run a DRAW string on the _DEST image
+    pub fn draw(&mut self, graphics: &mut Graphics, commands: &str) -> QResult<()> {
+        let mut reader = Reader { chars: commands.chars().collect(), pos: 0 };
+        let (mut blank, mut keep) = (false, false);
+        while let Some(command) = reader.next_command() {
+            let step = match command {
+                'B' => {
+                    blank = true;
+                    continue;
+                }
+                'N' => {
+                    keep = true;
+                    continue;
+                }
+                'U' => (0.0, -1.0),
+                'D' => (0.0, 1.0),
+                'L' => (-1.0, 0.0),
+                'R' => (1.0, 0.0),
+                'E' => (1.0, -1.0),
+                'F' => (1.0, 1.0),
+                'G' => (-1.0, 1.0),
+                'H' => (-1.0, -1.0),
+                'M' => {
+                    let relative = matches!(reader.peek(), Some('+' | '-'));
+                    let x = reader.number()?.ok_or_else(illegal_function_call)?;
+                    reader.expect(',')?;
+                    let y = reader.number()?.ok_or_else(illegal_function_call)?;
+                    let from = graphics.last_point()?;
+                    let to = if relative {
+                        let (dx, dy) = self.transform(x, y);
+                        (from.0 + dx, from.1 + dy)
+                    } else {
+                        (x.round() as i32, y.round() as i32)
+                    };
+                    self.stroke(graphics, from, to, blank, keep)?;
+                    (blank, keep) = (false, false);
+                    continue;
+                }
+                'A' => {
+                    let turns = reader.number()?.ok_or_else(illegal_function_call)?;
+                    if !(0.0..=3.0).contains(&turns) {
+                        return Err(illegal_function_call());
+                    }
+                    self.angle = turns.trunc() * 90.0;
+                    continue;
+                }
+                'T' => {
+                    reader.expect('A')?;
+                    let degrees = reader.number()?.ok_or_else(illegal_function_call)?;
+                    if !(-360.0..=360.0).contains(&degrees) {
+                        return Err(illegal_function_call());
+                    }
+                    self.angle = degrees;
+                    continue;
+                }
+                'S' => {
+                    let scale = reader.number()?.ok_or_else(illegal_function_call)?;
+                    if !(1.0..=255.0).contains(&scale) {
+                        return Err(illegal_function_call());
+                    }
+                    self.scale = scale as u32;
+                    continue;
+                }
+                'C' => {
+                    let color = reader.number()?.ok_or_else(illegal_function_call)?;
+                    if color < 0.0 {
+                        return Err(illegal_function_call());
+                    }
+                    self.color = Some(color as u32);
+                    continue;
+                }
+                _ => return Err(illegal_function_call()),
+            };
+            let length = reader.number()?.unwrap_or(1.0);
+            let from = graphics.last_point()?;
+            let (dx, dy) = self.transform(step.0 * length, step.1 * length);
+            self.stroke(graphics, from, (from.0 + dx, from.1 + dy), blank, keep)?;
+            (blank, keep) = (false, false);
+        }
+        Ok(())
+    }
+
+    /// A move in pen steps as a pixel offset, after scaling and rotation
+    fn transform(&self, x: f64, y: f64) -> (i32, i32) {
+        let scale = self.scale as f64 / 4.0;
+        let (sin, cos) = self.angle.to_radians().sin_cos();
+        // y grows downward, so a counterclockwise turn is this way round
+        let rx = x * cos + y * sin;
+        let ry = -x * sin + y * cos;
+        ((rx * scale).round() as i32, (ry * scale).round() as i32)
+    }
+
+    fn stroke(&self, graphics: &mut Graphics, from: (i32, i32), to: (i32, i32), blank: bool, keep: bool) -> QResult<()> {
+        if blank {
+            graphics.move_to(to)?;
+        } else {
+            graphics.line(from, to, self.color, LineShape::Line, 0xFFFF)?;
+        }
+        if keep {
+            graphics.move_to(from)?;
+        }
+        Ok(())
+    }
+}
+
+impl Default for Turtle {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+struct Reader {
+    chars: Vec<char>,
+    pos: usize,
+}
+
+impl Reader {
+    /// Skip blanks and semicolons, which only separate commands
+    fn skip_separators(&mut self) {
+        while matches!(self.chars.get(self.pos), Some(' ' | ';' | '\t')) {
+            self.pos += 1;
+        }
+    }
+
+    fn peek(&mut self) -> Option<char> {
+        self.skip_separators();
+        self.chars.get(self.pos).copied()
+    }
+
+    fn next_command(&mut self) -> Option<char> {
+        let command = self.peek()?.to_ascii_uppercase();
+        self.pos += 1;
+        Some(command)
+    }
+
+    fn expect(&mut self, wanted: char) -> QResult<()> {
+        match self.next_command() {
+            Some(c) if c == wanted => Ok(()),
+            _ => Err(illegal_function_call()),
+        }
+    }
+
+    /// An optionally signed decimal number, or None when there is none
+    fn number(&mut self) -> QResult<Option<f64>> {
+        self.skip_separators();
+        let start = self.pos;
+        if matches!(self.chars.get(self.pos), Some('+' | '-')) {
+            self.pos += 1;
+        }
+        while self.chars.get(self.pos).is_some_and(|c| c.is_ascii_digit() || *c == '.') {
+            self.pos += 1;
+        }
+        let text: String = self.chars[start..self.pos].iter().collect();
+        match text.as_str() {
+            "" => Ok(None),
+            _ => text.parse().map(Some).map_err(|_| illegal_function_call()),
+        }
+    }
+}
+
+fn illegal_function_call() -> QError {
+    QError::runtime(QErrorCode::IllegalFunctionCall, 0, 0)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::graphics::SCREEN_HANDLE;
+
+    fn drawn(commands: &str) -> Graphics {
+        let mut graphics = Graphics::new();
+        graphics.set_mode(13).unwrap();
+        graphics.move_to((10, 10)).unwrap();
+        Turtle::new().draw(&mut graphics, commands).unwrap();
+        graphics
+    }
+
+    #[test]
+    fn test_moves_and_prefixes() {
+        let mut graphics = drawn("C4 R5 D5; BL5 NE2 M+0,-10 M 30,30");
+        let screen = graphics.image(SCREEN_HANDLE).unwrap();
+        assert_eq!(screen.point(15, 10), Some(4));
+        assert_eq!(screen.point(15, 15), Some(4));
+        // The blank move left nothing behind
+        assert_eq!(screen.point(12, 15), Some(0));
+        assert_eq!(screen.point(12, 13), Some(4));
+        assert_eq!(screen.point(10, 6), Some(4));
+        assert_eq!(screen.point(30, 30), Some(4));
+        assert_eq!(graphics.last_point().unwrap(), (30, 30));
+    }
+
+    #[test]
+    fn test_rotation_and_scale() {
+        // A1 turns U into L; S8 doubles the steps
+        let mut graphics = drawn("A1 S8 C2 U3");
+        assert_eq!(graphics.last_point().unwrap(), (4, 10));
+        assert_eq!(graphics.image(SCREEN_HANDLE).unwrap().point(4, 10), Some(2));
+
+        let graphics = drawn("TA90 R4");
+        assert_eq!(graphics.last_point().unwrap(), (10, 6));
+
+        let mut graphics = Graphics::new();
+        graphics.set_mode(13).unwrap();
+        for bad in ["Q", "A4", "M5", "S0"] {
+            assert!(Turtle::new().draw(&mut graphics, bad).is_err(), "{}", bad);
+        }
+    }
+}
@@ -0,0 +1,58 @@
+//! Bundled 8-pixel-wide bitmap font for text drawn on images (_PRINTSTRING)
+//!
+//! Glyphs come from the public domain font8x8 set and are stretched to the
+//! character height of the screen mode: 8 rows in CGA and SCREEN 13 modes,
+//! 14 in EGA modes and 16 in VGA modes and 32-bit images.
+
+use font8x8::legacy::{BASIC_LEGACY, BLOCK_LEGACY, BOX_LEGACY, LATIN_LEGACY};
+
+/// Width of every character cell, in pixels
+pub const CHAR_WIDTH: u32 = 8;
+
+/// Character height of a SCREEN mode
+pub fn mode_char_height(mode: u8) -> u32 {
+    match mode {
+        9 | 10 => 14,
+        11 | 12 => 16,
+        _ => 8,
+    }
+}
+
+/// Rows of a character, top first; bit 0 of each row is the leftmost pixel
+pub fn glyph(ch: char) -> [u8; 8] {
+    let code = ch as usize;
+    match code {
+        0..=0x7F => BASIC_LEGACY[code],
+        0xA0..=0xFF => LATIN_LEGACY[code - 0xA0],
+        0x2500..=0x257F => BOX_LEGACY[code - 0x2500],
+        0x2580..=0x259F => BLOCK_LEGACY[code - 0x2580],
+        _ => BASIC_LEGACY[usize::from(b'?')],
+    }
+}
+
+/// Whether the pixel at (x, y) of a character cell `height` rows tall is set
+pub fn glyph_pixel(rows: &[u8; 8], x: u32, y: u32, height: u32) -> bool {
+    let row = rows[(y * 8 / height) as usize];
+    row & (1 << x) != 0
+}
+
+/// _PRINTWIDTH: width of a string in pixels
+pub fn text_width(text: &str) -> u32 {
+    text.chars().count() as u32 * CHAR_WIDTH
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_glyph_lookup() {
+        assert_eq!(glyph(' '), [0; 8]);
+        assert_eq!(glyph('\u{2588}'), [0xFF; 8]); // full block
+        assert_eq!(glyph('\u{4E00}'), glyph('?'));
+        // Doubled to 16 rows, each font row covers two pixel rows
+        let rows = glyph('A');
+        assert_eq!(glyph_pixel(&rows, 2, 2, 16), glyph_pixel(&rows, 2, 1, 8));
+        assert_eq!(text_width("Hello"), 40);
+    }
+}
@@ -0,0 +1,308 @@
+//! A window showing the screen image, in builds with the `window` feature.
+//! Where no window can be opened, as over SSH or in CI, or in builds
+//! without one, `FramebufferWindow::open` returns None and the terminal
+//! shows graphics instead.
+
+use crate::display::DisplayOptions;
+use crate::graphics::Image;
+use std::time::{Duration, Instant};
+
+/// Fewest seconds between two redraws of the window
+const FRAME_INTERVAL: Duration = Duration::from_millis(16);
+
+pub struct FramebufferWindow {
+    window: backend::Window,
+    title: String,
+    /// Size of the window when not full screen, to restore it to
+    windowed_size: (usize, usize),
+    /// Size of the frames shown, which full screen scales to fit the display
+    frame_size: (usize, usize),
+    full_screen: bool,
+    options: DisplayOptions,
+    /// The frame last drawn and the window size it was drawn for
+    shown: Option<(Image, (usize, usize))>,
+    shown_at: Option<Instant>,
+    /// The picture as sent to the window, centered on black
+    buffer: Vec<u32>,
+}
+
+impl FramebufferWindow {
+    /// Open a window showing `frame` at `scale` times its size, or filling
+    /// the display when `full_screen` is set
+    pub fn open(title: &str, frame: &Image, scale: f32, options: DisplayOptions, full_screen: bool) -> Option<Self> {
+        let scale = f64::from(scale.max(1.0));
+        let height = if options.aspect_correction && frame.bits <= 8 {
+            frame.width as f64 * 3.0 / 4.0
+        } else {
+            frame.height as f64
+        };
+        let windowed_size = ((frame.width as f64 * scale) as usize, (height * scale) as usize);
+        let frame_size = (frame.width as usize, frame.height as usize);
+        let window = match full_screen {
+            true => backend::open_full_screen(title, frame_size)?,
+            false => backend::open(title, windowed_size)?,
+        };
+        Some(Self {
+            window,
+            title: title.to_string(),
+            windowed_size,
+            frame_size,
+            full_screen,
+            options,
+            shown: None,
+            shown_at: None,
+            buffer: Vec::new(),
+        })
+    }
+
+    /// False once the user has closed the window
+    pub fn is_open(&self) -> bool {
+        self.window.is_open()
+    }
+
+    pub fn set_title(&mut self, title: &str) {
+        self.title = title.to_string();
+        self.window.set_title(title);
+    }
+
+    /// Switch between filling the display and a window of the size it had,
+    /// by opening the window again. Returns false, leaving the window as it
+    /// was, when the new one cannot be opened.
+    pub fn set_full_screen(&mut self, full_screen: bool) -> bool {
+        if full_screen == self.full_screen {
+            return true;
+        }
+        if !self.full_screen {
+            self.windowed_size = self.window.size();
+        }
+        let window = match full_screen {
+            true => backend::open_full_screen(&self.title, self.frame_size),
+            false => backend::open(&self.title, self.windowed_size),
+        };
+        let Some(window) = window else {
+            return false;
+        };
+        self.window = window;
+        self.full_screen = full_screen;
+        self.shown = None;
+        true
+    }
+
+    pub fn set_options(&mut self, options: DisplayOptions) {
+        self.options = options;
+        self.shown = None;
+    }
+
+    /// Draw `frame` if it or the window size changed, and handle the
+    /// window's events. Unless `now` is set, calls that come too soon after
+    /// the last redraw do nothing.
+    pub fn present(&mut self, frame: &Image, now: bool) {
+        if !now && self.shown_at.is_some_and(|at| at.elapsed() < FRAME_INTERVAL) {
+            return;
+        }
+        self.frame_size = (frame.width as usize, frame.height as usize);
+        let size = self.window.size();
+        if self.shown.as_ref().is_none_or(|(shown, shown_size)| shown != frame || *shown_size != size) {
+            self.buffer = letterbox(&self.options, frame, size);
+            self.shown = Some((frame.clone(), size));
+        }
+        self.window.show(&self.buffer, size);
+        self.shown_at = Some(Instant::now());
+    }
+
+    /// Keys typed into the window since the last call, as (ASCII, scan
+    /// code) pairs the way the BIOS buffers them
+    pub fn take_keys(&mut self) -> Vec<(u8, u8)> {
+        self.window.take_keys()
+    }
+}
+
+/// The picture for `frame` centered in a window of `size`, with black bars
+/// where it does not fill it
+fn letterbox(options: &DisplayOptions, frame: &Image, size: (usize, usize)) -> Vec<u32> {
+    let (width, height) = (size.0.max(1), size.1.max(1));
+    let picture = options.render(frame, (width as u32, height as u32));
+    let left = (width - picture.width as usize) / 2;
+    let top = (height - picture.height as usize) / 2;
+    let mut buffer = vec![0; width * height];
+    for y in 0..picture.height as usize {
+        for x in 0..picture.width as usize {
+            let argb = picture.point(x as i32, y as i32).unwrap_or(0);
+            buffer[(top + y) * width + left + x] = argb & 0xFFFFFF;
+        }
+    }
+    buffer
+}
+
+#[cfg(feature = "window")]
+mod backend {
+    use crate::keyboard::Keymap;
+    use minifb::{InputCallback, Key, KeyRepeat, Scale, WindowOptions};
+    use std::cell::RefCell;
+    use std::rc::Rc;
+
+    pub struct Window {
+        window: minifb::Window,
+        /// Characters typed, as the window reports them
+        typed: Rc<RefCell<Vec<u32>>>,
+    }
+
+    struct Typed(Rc<RefCell<Vec<u32>>>);
+
+    impl InputCallback for Typed {
+        fn add_char(&mut self, uni_char: u32) {
+            self.0.borrow_mut().push(uni_char);
+        }
+    }
+
+    pub fn open(title: &str, size: (usize, usize)) -> Option<Window> {
+        create(title, size, WindowOptions { resize: true, ..WindowOptions::default() })
+    }
+
+    /// minifb has no full screen mode. The nearest it comes is a window
+    /// without borders at the top left, scaled up as far as the display
+    /// allows.
+    pub fn open_full_screen(title: &str, frame_size: (usize, usize)) -> Option<Window> {
+        let options = WindowOptions {
+            borderless: true,
+            title: false,
+            topmost: true,
+            scale: Scale::FitScreen,
+            ..WindowOptions::default()
+        };
+        let mut window = create(title, frame_size, options)?;
+        window.window.set_position(0, 0);
+        Some(window)
+    }
+
+    fn create(title: &str, size: (usize, usize), options: WindowOptions) -> Option<Window> {
+        let mut window = minifb::Window::new(title, size.0, size.1, options).ok()?;
+        // The VM paces itself; the window should never hold it up
+        window.set_target_fps(0);
+        let typed = Rc::new(RefCell::new(Vec::new()));
+        window.set_input_callback(Box::new(Typed(Rc::clone(&typed))));
+        Some(Window { window, typed })
+    }
+
+    impl Window {
+        pub fn is_open(&self) -> bool {
+            self.window.is_open()
+        }
+
+        pub fn set_title(&mut self, title: &str) {
+            self.window.set_title(title);
+        }
+
+        pub fn size(&self) -> (usize, usize) {
+            self.window.get_size()
+        }
+
+        pub fn show(&mut self, buffer: &[u32], size: (usize, usize)) {
+            let _ = self.window.update_with_buffer(buffer, size.0, size.1);
+        }
+
+        pub fn take_keys(&mut self) -> Vec<(u8, u8)> {
+            // Keys without a character come from the key state; the rest
+            // from the characters typed
+            let mut keys: Vec<(u8, u8)> = self
+                .window
+                .get_keys_pressed(KeyRepeat::Yes)
+                .into_iter()
+                .filter_map(bios_key)
+                .collect();
+            for c in self.typed.borrow_mut().drain(..) {
+                if let Ok(ascii) = u8::try_from(c) {
+                    if ascii.is_ascii() && !ascii.is_ascii_control() {
+                        keys.push((ascii, Keymap::scan_code(ascii)));
+                    }
+                }
+            }
+            keys
+        }
+    }
+
+    /// The BIOS code of a key that types no printable character
+    fn bios_key(key: Key) -> Option<(u8, u8)> {
+        let extended = |scan: u8| Some((0, scan));
+        match key {
+            Key::Enter | Key::NumPadEnter => Some((13, 0x1C)),
+            Key::Escape => Some((27, 0x01)),
+            Key::Backspace => Some((8, 0x0E)),
+            Key::Tab => Some((9, 0x0F)),
+            Key::Up => extended(72),
+            Key::Down => extended(80),
+            Key::Left => extended(75),
+            Key::Right => extended(77),
+            Key::Home => extended(71),
+            Key::End => extended(79),
+            Key::PageUp => extended(73),
+            Key::PageDown => extended(81),
+            Key::Insert => extended(82),
+            Key::Delete => extended(83),
+            Key::F1 => extended(59),
+            Key::F2 => extended(60),
+            Key::F3 => extended(61),
+            Key::F4 => extended(62),
+            Key::F5 => extended(63),
+            Key::F6 => extended(64),
+            Key::F7 => extended(65),
+            Key::F8 => extended(66),
+            Key::F9 => extended(67),
+            Key::F10 => extended(68),
+            Key::F11 => extended(133),
+            Key::F12 => extended(134),
+            _ => None,
+        }
+    }
+}
+
+#[cfg(not(feature = "window"))]
+mod backend {
+    /// Never made: without a window backend `open` always fails
+    pub enum Window {}
+
+    pub fn open(_title: &str, _size: (usize, usize)) -> Option<Window> {
+        None
+    }
+
+    pub fn open_full_screen(_title: &str, _frame_size: (usize, usize)) -> Option<Window> {
+        None
+    }
+
+    impl Window {
+        pub fn is_open(&self) -> bool {
+            match *self {}
+        }
+
+        pub fn set_title(&mut self, _title: &str) {
+            match *self {}
+        }
+
+        pub fn size(&self) -> (usize, usize) {
+            match *self {}
+        }
+
+        pub fn show(&mut self, _buffer: &[u32], _size: (usize, usize)) {
+            match *self {}
+        }
+
+        pub fn take_keys(&mut self) -> Vec<(u8, u8)> {
+            match *self {}
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_letterbox() {
+        let mut frame = Image::new(2, 2, 4);
+        frame.fill_box((0, 0), (1, 1), 4);
+        let options = DisplayOptions { filter: crate::display::Filter::Nearest, ..DisplayOptions::default() };
+        // A square frame in a wide window gets bars left and right
+        let buffer = letterbox(&options, &frame, (4, 2));
+        assert_eq!(buffer, vec![0, 0xAA0000, 0xAA0000, 0, 0, 0xAA0000, 0xAA0000, 0]);
+    }
+}
@@ -0,0 +1,1168 @@
+//! Software framebuffer for SCREEN modes and QB64 image handles
+//!
+//! Every surface lives in a handle table: the screen of the current SCREEN
+//! mode and the images made by _NEWIMAGE, _LOADIMAGE and _COPYIMAGE. Any of
+//! those images can also become the screen, as in SCREEN _NEWIMAGE(640, 480, 32).
+//! Handle 0 always means the screen. Drawing goes to the _DEST image.
+//!
+//! Backends show `Graphics::frame`, which follows every change while auto
+//! display is on (the default) and only changes on _DISPLAY once a program
+//! has taken control of presentation.
+//!
+//! PSET, LINE and CIRCLE do not draw at once: they queue in a draw list for the
+//! _DEST image and are drawn together when anything next looks at or changes
+//! the images, at the latest when the frame is taken.
+
+use crate::font;
+use crate::image_file;
+use crate::palette::{default_palette, nearest, EGA_COLORS};
+use qb_core::errors::{QError, QErrorCode, QResult};
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::path::Path;
+
+/// Width, height and bits per pixel of a graphics SCREEN mode
+pub fn mode_geometry(mode: u8) -> Option<(u32, u32, u8)> {
+    match mode {
+        1 => Some((320, 200, 2)),
+        2 => Some((640, 200, 1)),
+        7 => Some((320, 200, 4)),
+        8 => Some((640, 200, 4)),
+        9 => Some((640, 350, 4)),
+        10 => Some((640, 350, 2)),
+        11 => Some((640, 480, 1)),
+        12 => Some((640, 480, 4)),
+        13 => Some((320, 200, 8)),
+        _ => None,
+    }
+}
+
+/// A pixel surface. Pixels hold palette indices, or ARGB for 32-bit images.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Image {
+    pub width: u32,
+    pub height: u32,
+    pub bits: u8,
+    /// ARGB of each palette index; empty for 32-bit images
+    pub palette: Vec<u32>,
+    /// COLOR foreground and background
+    pub fg: u32,
+    pub bg: u32,
+    /// Height of a text character in pixels
+    pub char_height: u32,
+    /// PRINT position as (column, row) character cells from the top left
+    pub cursor: (u32, u32),
+    /// Last point referenced, where STEP coordinates count from
+    pub last_point: (i32, i32),
+    pixels: Vec<u32>,
+}
+
+impl Image {
+    pub fn new(width: u32, height: u32, bits: u8) -> Self {
+        let (fg, bg) = match bits {
+            8 => (15, 0),
+            32 => (0xFFFFFFFF, 0xFF000000),
+            _ => ((1 << bits) - 1, 0),
+        };
+        Self {
+            width,
+            height,
+            bits,
+            palette: default_palette(bits),
+            fg,
+            bg,
+            char_height: 16,
+            cursor: (0, 0),
+            last_point: (width as i32 / 2, height as i32 / 2),
+            pixels: vec![0; (width as usize) * (height as usize)],
+        }
+    }
+
+    /// ARGB of a pixel value
+    pub fn to_argb(&self, value: u32) -> u32 {
+        if self.bits >= 32 {
+            value
+        } else {
+            self.palette.get(value as usize).copied().unwrap_or(0xFF000000)
+        }
+    }
+
+    /// Highest color value a pixel can hold
+    pub fn max_color(&self) -> u32 {
+        if self.bits >= 32 { u32::MAX } else { (1 << self.bits) - 1 }
+    }
+
+    fn index(&self, x: i32, y: i32) -> Option<usize> {
+        if x < 0 || y < 0 || x as u32 >= self.width || y as u32 >= self.height {
+            return None;
+        }
+        Some(y as usize * self.width as usize + x as usize)
+    }
+
+    /// Set a pixel; points outside the image are clipped
+    pub fn pset(&mut self, x: i32, y: i32, color: u32) {
+        let color = color & self.max_color();
+        if let Some(i) = self.index(x, y) {
+            self.pixels[i] = color;
+        }
+    }
+
+    /// Pixel value, or None outside the image
+    pub fn point(&self, x: i32, y: i32) -> Option<u32> {
+        self.index(x, y).map(|i| self.pixels[i])
+    }
+
+    /// Draw a line with both ends included. Each pixel in turn takes the
+    /// next bit of `style` from the top, and is only drawn where it is set.
+    pub fn line(&mut self, from: (i32, i32), to: (i32, i32), color: u32, style: u16) {
+        let (dx, dy) = ((to.0 - from.0).abs(), -(to.1 - from.1).abs());
+        let (sx, sy) = ((to.0 - from.0).signum(), (to.1 - from.1).signum());
+        let (mut x, mut y) = from;
+        let mut error = dx + dy;
+        let mut mask = style.rotate_right(1);
+        loop {
+            mask = mask.rotate_left(1);
+            if mask & 0x8000 != 0 {
+                self.pset(x, y, color);
+            }
+            if (x, y) == to {
+                break;
+            }
+            let twice = 2 * error;
+            if twice >= dy {
+                error += dy;
+                x += sx;
+            }
+            if twice <= dx {
+                error += dx;
+                y += sy;
+            }
+        }
+    }
+
+    /// Draw the ellipse with the given radii around a center. With an arc,
+    /// only the points between its start and end angle are drawn, going
+    /// counterclockwise from the right.
+    pub fn ellipse(&mut self, center: (i32, i32), radii: (i32, i32), color: u32, arc: Option<(f64, f64)>) {
+        let (rx, ry) = radii;
+        if rx == 0 || ry == 0 {
+            // Flattened all the way: a line, or the center alone
+            self.line((center.0 - rx, center.1 - ry), (center.0 + rx, center.1 + ry), color, 0xFFFF);
+            return;
+        }
+        // Half a pixel of slack at the ends, for angles such as 3.14159 / 2
+        let slack = 0.5 / rx.max(ry) as f64;
+        let plot = |image: &mut Image, dx: i32, dy: i32| {
+            let inside = arc.is_none_or(|(start, end)| {
+                let angle = ellipse_angle(dx, dy, radii);
+                let (after_start, before_end) = (angle >= start - slack, angle <= end + slack);
+                if start <= end { after_start && before_end } else { after_start || before_end }
+            });
+            if inside {
+                image.pset(center.0 + dx, center.1 + dy, color);
+            }
+        };
+        let four = |image: &mut Image, x: i32, y: i32| {
+            for (dx, dy) in [(x, y), (-x, y), (x, -y), (-x, -y)] {
+                plot(image, dx, dy);
+            }
+        };
+        // Midpoint ellipse algorithm, one quadrant mirrored four ways
+        let (a2, b2) = ((rx as f64).powi(2), (ry as f64).powi(2));
+        let (mut x, mut y) = (0, ry);
+        let mut d = b2 - a2 * ry as f64 + a2 / 4.0;
+        while b2 * x as f64 <= a2 * y as f64 {
+            four(self, x, y);
+            if d >= 0.0 {
+                y -= 1;
+                d -= 2.0 * a2 * y as f64;
+            }
+            x += 1;
+            d += b2 * (2.0 * x as f64 + 1.0);
+        }
+        let mut d = b2 * (x as f64 + 0.5).powi(2) + a2 * (y as f64 - 1.0).powi(2) - a2 * b2;
+        while y >= 0 {
+            four(self, x, y);
+            if d <= 0.0 {
+                x += 1;
+                d += 2.0 * b2 * x as f64;
+            }
+            y -= 1;
+            d += a2 * (1.0 - 2.0 * y as f64);
+        }
+    }
+
+    /// Fill the box between two opposite corners, clipped to the image
+    pub fn fill_box(&mut self, corner: (i32, i32), opposite: (i32, i32), color: u32) {
+        let color = color & self.max_color();
+        let left = corner.0.min(opposite.0).max(0);
+        let right = corner.0.max(opposite.0).min(self.width as i32 - 1);
+        let top = corner.1.min(opposite.1).max(0);
+        let bottom = corner.1.max(opposite.1).min(self.height as i32 - 1);
+        for y in top..=bottom {
+            let row = y as usize * self.width as usize;
+            for x in left..=right {
+                self.pixels[row + x as usize] = color;
+            }
+        }
+    }
+
+    pub fn clear(&mut self, color: u32) {
+        let color = color & self.max_color();
+        self.pixels.fill(color);
+    }
+
+    /// Draw text with its top-left corner at (x, y), in the foreground color
+    /// over the background color
+    pub fn print_string(&mut self, x: i32, y: i32, text: &str) {
+        let (fg, bg, height) = (self.fg, self.bg, self.char_height);
+        for (i, ch) in text.chars().enumerate() {
+            let rows = font::glyph(ch);
+            let left = x + (i as u32 * font::CHAR_WIDTH) as i32;
+            for row in 0..height {
+                for col in 0..font::CHAR_WIDTH {
+                    let set = font::glyph_pixel(&rows, col, row, height);
+                    self.pset(left + col as i32, y + row as i32, if set { fg } else { bg });
+                }
+            }
+        }
+    }
+
+    /// PRINT at the text cursor, wrapping at the right edge and scrolling
+    /// the image up a line after the bottom row
+    pub fn print_text(&mut self, text: &str) {
+        let columns = (self.width / font::CHAR_WIDTH).max(1);
+        for ch in text.chars() {
+            if ch == '\n' {
+                self.new_line();
+                continue;
+            }
+            if self.cursor.0 >= columns {
+                self.new_line();
+            }
+            let (col, row) = self.cursor;
+            let x = (col * font::CHAR_WIDTH) as i32;
+            let y = (row * self.char_height) as i32;
+            self.print_string(x, y, &ch.to_string());
+            self.cursor.0 += 1;
+        }
+    }
+
+    fn new_line(&mut self) {
+        let rows = (self.height / self.char_height).max(1);
+        self.cursor = (0, self.cursor.1 + 1);
+        if self.cursor.1 >= rows {
+            self.cursor.1 = rows - 1;
+            let shift = (self.char_height * self.width) as usize;
+            let visible = (rows * self.char_height * self.width) as usize;
+            self.pixels.copy_within(shift..visible, 0);
+            let bg = self.bg;
+            self.pixels[visible - shift..visible].fill(bg);
+        }
+    }
+
+    /// 8-bit copy of a 32-bit image, each color mapped to the nearest entry
+    /// of the VGA palette
+    pub fn to_indexed(&self) -> Image {
+        let mut indexed = Image::new(self.width, self.height, 8);
+        let mut matches = HashMap::new();
+        for (out, &color) in indexed.pixels.iter_mut().zip(&self.pixels) {
+            *out = *matches
+                .entry(self.to_argb(color))
+                .or_insert_with_key(|&argb| nearest(&indexed.palette, argb));
+        }
+        indexed
+    }
+
+    /// Row-major pixel data
+    pub fn pixels(&self) -> &[u32] {
+        &self.pixels
+    }
+
+    /// Bytes per pixel in memory (_MEMIMAGE): 4 for 32-bit images, stored
+    /// as B, G, R, A; one byte holding the color index otherwise
+    pub fn bytes_per_pixel(&self) -> usize {
+        if self.bits >= 32 { 4 } else { 1 }
+    }
+
+    /// Size of the pixel data in bytes
+    pub fn byte_len(&self) -> usize {
+        self.pixels.len() * self.bytes_per_pixel()
+    }
+
+    /// Copy pixel memory starting at byte `offset` into `buf`
+    pub fn read_bytes(&self, offset: usize, buf: &mut [u8]) {
+        let bpp = self.bytes_per_pixel();
+        for (i, out) in buf.iter_mut().enumerate() {
+            let at = offset + i;
+            *out = (self.pixels[at / bpp] >> (8 * (at % bpp))) as u8;
+        }
+    }
+
+    /// Overwrite pixel memory starting at byte `offset`
+    pub fn write_bytes(&mut self, offset: usize, data: &[u8]) {
+        let bpp = self.bytes_per_pixel();
+        let max = self.max_color();
+        for (i, &byte) in data.iter().enumerate() {
+            let at = offset + i;
+            let shift = 8 * (at % bpp);
+            let pixel = &mut self.pixels[at / bpp];
+            *pixel = ((*pixel & !(0xFF << shift)) | (u32::from(byte) << shift)) & max;
+        }
+    }
+}
+
+/// Where _PUTIMAGE draws, or which part of the source it copies
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Area {
+    /// The whole image
+    Whole,
+    /// Top-left corner; the size comes from the other image
+    At(i32, i32),
+    /// Two opposite corners; reversed corners flip the picture
+    Rect(i32, i32, i32, i32),
+}
+
+/// Handle of the screen in _DEST, _SOURCE and _PUTIMAGE
+pub const SCREEN_HANDLE: i32 = 0;
+
+/// Handle _CONSOLE returns under $CONSOLE; never an image handle
+pub const CONSOLE_HANDLE: i32 = 1;
+
+/// Columns and rows of the SCREEN 0 text screen
+pub const TEXT_SIZE: (u32, u32) = (80, 25);
+
+/// Largest number of queued primitives before they are drawn anyway
+const MAX_PENDING: usize = 1 << 16;
+
+/// Most pixels _NEWIMAGE gives an image, 16384 x 16384
+const MAX_IMAGE_PIXELS: usize = 1 << 28;
+
+/// A drawing statement queued for the _DEST image
+/// What LINE draws between its two points
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Serialize, Deserialize)]
+pub enum LineShape {
+    #[default]
+    Line,
+    /// The outline of the box with the points as opposite corners (B)
+    Box,
+    /// The filled box (BF)
+    FilledBox,
+}
+
+#[derive(Debug, Clone, Copy)]
+enum Primitive {
+    Pixel { x: i32, y: i32, color: u32 },
+    Line { from: (i32, i32), to: (i32, i32), color: u32, style: u16 },
+    Ellipse { center: (i32, i32), radii: (i32, i32), color: u32, arc: Option<(f64, f64)> },
+    FilledBox { corner: (i32, i32), opposite: (i32, i32), color: u32 },
+}
+
+/// Screen state: the image handle table, the screen and the _DEST/_SOURCE
+/// images
+pub struct Graphics {
+    images: HashMap<i32, Image>,
+    /// Handle of the screen image, None in text mode
+    screen: Option<i32>,
+    /// The screen was made by SCREEN n and goes away with the next SCREEN
+    screen_owned: bool,
+    dest: i32,
+    source: i32,
+    next_handle: i32,
+    auto_display: bool,
+    /// Screen changed since the frame was last taken in auto display mode
+    dirty: bool,
+    frame: Option<Image>,
+    /// Draw list for the _DEST image
+    pending: Vec<Primitive>,
+}
+
+impl Graphics {
+    pub fn new() -> Self {
+        Self {
+            images: HashMap::new(),
+            screen: None,
+            screen_owned: false,
+            dest: SCREEN_HANDLE,
+            source: SCREEN_HANDLE,
+            // -1 is the failure value of _LOADIMAGE
+            next_handle: -2,
+            auto_display: true,
+            dirty: false,
+            frame: None,
+            pending: Vec::new(),
+        }
+    }
+
+    /// SCREEN n: switch modes, clearing the screen and drawing to it again
+    pub fn set_mode(&mut self, mode: u8) -> QResult<()> {
+        let screen = match mode {
+            0 => None,
+            _ => {
+                let (width, height, bits) = mode_geometry(mode).ok_or_else(illegal_function_call)?;
+                let mut image = Image::new(width, height, bits);
+                image.char_height = font::mode_char_height(mode);
+                Some(image)
+            }
+        };
+        let handle = screen.map(|image| self.insert(image));
+        self.show(handle, true);
+        Ok(())
+    }
+
+    /// SCREEN handle: show an image as the screen. The image is used as it
+    /// is, without clearing it.
+    pub fn set_screen_image(&mut self, handle: i32) -> QResult<()> {
+        let key = self.resolve(handle)?;
+        if Some(key) != self.screen {
+            self.show(Some(key), false);
+        }
+        Ok(())
+    }
+
+    fn show(&mut self, screen: Option<i32>, owned: bool) {
+        self.flush();
+        if let Some(old) = self.screen.take() {
+            if self.screen_owned {
+                self.images.remove(&old);
+            }
+        }
+        self.screen = screen;
+        self.screen_owned = owned;
+        self.dest = SCREEN_HANDLE;
+        self.source = SCREEN_HANDLE;
+        self.mark_dirty();
+        if !self.auto_display {
+            self.frame = self.screen_image().cloned();
+        }
+    }
+
+    fn insert(&mut self, image: Image) -> i32 {
+        let handle = self.next_handle;
+        self.next_handle -= 1;
+        self.images.insert(handle, image);
+        handle
+    }
+
+    /// Table key of a handle, with 0 standing for the screen
+    fn resolve(&self, handle: i32) -> QResult<i32> {
+        let key = match handle {
+            SCREEN_HANDLE => self.screen.ok_or_else(illegal_function_call)?,
+            _ => handle,
+        };
+        if self.images.contains_key(&key) {
+            Ok(key)
+        } else {
+            Err(QError::runtime(QErrorCode::InvalidHandle, 0, 0))
+        }
+    }
+
+    fn screen_image(&self) -> Option<&Image> {
+        self.screen.and_then(|key| self.images.get(&key))
+    }
+
+    pub fn image(&mut self, handle: i32) -> QResult<&Image> {
+        self.flush();
+        self.lookup(handle)
+    }
+
+    /// An image as it stands without the draw list, for its settings
+    fn lookup(&self, handle: i32) -> QResult<&Image> {
+        let key = self.resolve(handle)?;
+        Ok(&self.images[&key])
+    }
+
+    pub fn image_mut(&mut self, handle: i32) -> QResult<&mut Image> {
+        self.flush();
+        let key = self.resolve(handle)?;
+        if Some(key) == self.screen {
+            self.mark_dirty();
+        }
+        Ok(self.images.get_mut(&key).expect("resolved handle"))
+    }
+
+    /// _NEWIMAGE: mode 32 gives a 32-bit image, 256 an 8-bit one, and a
+    /// SCREEN mode number an image with that mode's color depth
+    pub fn new_image(&mut self, width: i32, height: i32, mode: i32) -> QResult<i32> {
+        let bits = match mode {
+            32 => 32,
+            256 => 8,
+            1..=13 => {
+                mode_geometry(mode as u8).ok_or_else(illegal_function_call)?.2
+            }
+            _ => return Err(illegal_function_call()),
+        };
+        if width <= 0 || height <= 0 {
+            return Err(illegal_function_call());
+        }
+        (width as usize)
+            .checked_mul(height as usize)
+            .filter(|&pixels| pixels <= MAX_IMAGE_PIXELS)
+            .ok_or_else(|| QError::runtime(QErrorCode::OutOfMemory, 0, 0))?;
+        let mut image = Image::new(width as u32, height as u32, bits);
+        if mode <= 13 {
+            image.char_height = font::mode_char_height(mode as u8);
+        }
+        Ok(self.insert(image))
+    }
+
+    /// _LOADIMAGE: an image of a PNG or BMP file, or -1 if it could not be
+    /// loaded. Mode 32 keeps the file's colors; mode 256 maps them onto the
+    /// VGA palette.
+    pub fn load_image(&mut self, path: &Path, mode: i32) -> QResult<i32> {
+        if mode != 32 && mode != 256 {
+            return Err(illegal_function_call());
+        }
+        Ok(match image_file::load_image(path) {
+            Ok(image) if mode == 256 => self.insert(image.to_indexed()),
+            Ok(image) => self.insert(image),
+            Err(_) => -1,
+        })
+    }
+
+    /// _SAVEIMAGE: write an image to a PNG file
+    pub fn save_image(&mut self, handle: i32, path: &Path) -> QResult<()> {
+        image_file::save_png(self.image(handle)?, path)
+    }
+
+    /// _COPYIMAGE
+    pub fn copy_image(&mut self, handle: i32) -> QResult<i32> {
+        let image = self.image(handle)?.clone();
+        Ok(self.insert(image))
+    }
+
+    /// _FREEIMAGE; the screen cannot be freed while it is shown
+    pub fn free_image(&mut self, handle: i32) -> QResult<()> {
+        self.flush();
+        let key = self.resolve(handle)?;
+        if Some(key) == self.screen {
+            return Err(illegal_function_call());
+        }
+        self.images.remove(&key);
+        if self.dest == key {
+            self.dest = SCREEN_HANDLE;
+        }
+        if self.source == key {
+            self.source = SCREEN_HANDLE;
+        }
+        Ok(())
+    }
+
+    /// _DEST: image that drawing statements go to
+    pub fn set_dest(&mut self, handle: i32) -> QResult<()> {
+        self.flush();
+        self.dest = self.resolve(handle).map(|_| handle)?;
+        Ok(())
+    }
+
+    /// _WIDTH and _HEIGHT: an image's size in pixels, or in characters for
+    /// the text screen
+    pub fn size(&self, handle: i32) -> QResult<(u32, u32)> {
+        if handle == SCREEN_HANDLE && self.screen.is_none() {
+            return Ok(TEXT_SIZE);
+        }
+        let image = self.lookup(handle)?;
+        Ok((image.width, image.height))
+    }
+
+    /// _DEST function: the screen reports its own handle, as in QB64
+    pub fn dest(&self) -> i32 {
+        self.report(self.dest)
+    }
+
+    /// _SOURCE: image that POINT and _PUTIMAGE read by default
+    pub fn set_source(&mut self, handle: i32) -> QResult<()> {
+        self.source = self.resolve(handle).map(|_| handle)?;
+        Ok(())
+    }
+
+    pub fn source(&self) -> i32 {
+        self.report(self.source)
+    }
+
+    fn report(&self, handle: i32) -> i32 {
+        match handle {
+            SCREEN_HANDLE => self.screen.unwrap_or(SCREEN_HANDLE),
+            _ => handle,
+        }
+    }
+
+    /// SCREEN 0 is showing
+    pub fn text_mode(&self) -> bool {
+        self.screen.is_none()
+    }
+
+    /// PRINT goes to the text screen rather than onto the _DEST image
+    pub fn dest_is_text(&self) -> bool {
+        self.dest == SCREEN_HANDLE && self.screen.is_none()
+    }
+
+    /// PRINT onto the _DEST image
+    pub fn print_text(&mut self, text: &str) -> QResult<()> {
+        self.dest_mut()?.print_text(text);
+        Ok(())
+    }
+
+    /// Text cursor column of the _DEST image, for PRINT zones
+    pub fn text_column(&self) -> QResult<u32> {
+        Ok(self.lookup(self.dest)?.cursor.0)
+    }
+
+    /// The _DEST image; graphics statements are illegal in text mode
+    pub fn dest_mut(&mut self) -> QResult<&mut Image> {
+        self.image_mut(self.dest)
+    }
+
+    /// _RGB and _RGBA: the color value that shows an ARGB color on an image,
+    /// the nearest palette index on indexed images
+    pub fn match_color(&self, argb: u32, handle: i32) -> QResult<u32> {
+        let (bits, palette) = self.color_format(handle)?;
+        Ok(if bits >= 32 { argb } else { nearest(palette, argb) })
+    }
+
+    /// ARGB shown by a color value on an image, for _RED, _GREEN, _BLUE and
+    /// _ALPHA
+    pub fn color_argb(&self, color: u32, handle: i32) -> QResult<u32> {
+        let (bits, palette) = self.color_format(handle)?;
+        if bits >= 32 {
+            return Ok(color);
+        }
+        palette.get(color as usize).copied().ok_or_else(illegal_function_call)
+    }
+
+    /// Depth and palette of an image; the text screen has the 16 EGA colors
+    fn color_format(&self, handle: i32) -> QResult<(u8, &[u32])> {
+        if handle == SCREEN_HANDLE && self.screen.is_none() {
+            return Ok((4, &EGA_COLORS));
+        }
+        let image = self.lookup(handle)?;
+        Ok((image.bits, &image.palette))
+    }
+
+    /// Color used when a statement omits one
+    pub fn default_color(&self) -> u32 {
+        self.lookup(self.dest).map_or(7, |image| image.fg)
+    }
+
+    /// COLOR [foreground][, background] on the _DEST image. Text mode colors
+    /// are left to the terminal.
+    pub fn set_color(&mut self, fg: Option<u32>, bg: Option<u32>) -> QResult<()> {
+        let Ok(image) = self.dest_mut() else { return Ok(()) };
+        let max = image.max_color();
+        if fg.into_iter().chain(bg).any(|color| color > max) {
+            return Err(illegal_function_call());
+        }
+        image.fg = fg.unwrap_or(image.fg);
+        image.bg = bg.unwrap_or(image.bg);
+        Ok(())
+    }
+
+    /// _PRINTSTRING (x, y), text[, handle]
+    pub fn print_string(&mut self, x: i32, y: i32, text: &str, handle: i32) -> QResult<()> {
+        self.image_mut(handle)?.print_string(x, y, text);
+        Ok(())
+    }
+
+    /// _PRINTWIDTH(text[, handle])
+    pub fn print_width(&self, text: &str, handle: i32) -> QResult<u32> {
+        self.color_format(handle)?;
+        Ok(font::text_width(text))
+    }
+
+    /// PSET: queue a point on the _DEST image
+    pub fn pset(&mut self, x: i32, y: i32, color: Option<u32>) -> QResult<()> {
+        let color = color.unwrap_or_else(|| self.default_color());
+        self.queue(Primitive::Pixel { x, y, color }, (x, y))
+    }
+
+    /// LINE: queue a line or box on the _DEST image. A box outline is drawn
+    /// in the line style too; a filled box ignores it.
+    pub fn line(
+        &mut self,
+        from: (i32, i32),
+        to: (i32, i32),
+        color: Option<u32>,
+        shape: LineShape,
+        style: u16,
+    ) -> QResult<()> {
+        let color = color.unwrap_or_else(|| self.default_color());
+        match shape {
+            LineShape::Line => self.queue(Primitive::Line { from, to, color, style }, to),
+            LineShape::Box => {
+                let corners = [from, (to.0, from.1), to, (from.0, to.1)];
+                for (i, &corner) in corners.iter().enumerate() {
+                    let next = corners[(i + 1) % 4];
+                    self.queue(Primitive::Line { from: corner, to: next, color, style }, to)?;
+                }
+                Ok(())
+            }
+            LineShape::FilledBox => self.queue(Primitive::FilledBox { corner: from, opposite: to, color }, to),
+        }
+    }
+
+    /// CIRCLE: queue a circle, ellipse or arc on the _DEST image. The radius
+    /// is horizontal unless the aspect (y radius / x radius) is over 1; the
+    /// default aspect makes a round circle on a 4:3 display. A negative
+    /// start or end angle also draws a line from the center to that end.
+    pub fn circle(
+        &mut self,
+        center: (i32, i32),
+        radius: f32,
+        color: Option<u32>,
+        (start, end): (Option<f32>, Option<f32>),
+        aspect: Option<f32>,
+    ) -> QResult<()> {
+        let (width, height) = self.size(self.dest)?;
+        let aspect = aspect.map_or(4.0 / 3.0 * height as f64 / width as f64, f64::from);
+        let radius = radius as f64;
+        let (rx, ry) = if aspect > 1.0 { (radius / aspect, radius) } else { (radius, radius * aspect) };
+        let radii = (rx.round() as i32, ry.round() as i32);
+        let full = std::f64::consts::TAU;
+        let mut angles = [start.map_or(0.0, f64::from), end.map_or(full, f64::from)];
+        if angles.iter().any(|angle| angle.abs() > full + 1e-6) {
+            return Err(illegal_function_call());
+        }
+        let color = color.unwrap_or_else(|| self.default_color());
+        for angle in angles.iter_mut().filter(|angle| **angle < 0.0) {
+            *angle = -*angle;
+            let to = (
+                center.0 + (rx * angle.cos()).round() as i32,
+                center.1 - (ry * angle.sin()).round() as i32,
+            );
+            self.queue(Primitive::Line { from: center, to, color, style: 0xFFFF }, center)?;
+        }
+        let arc = (start.is_some() || end.is_some()).then_some((angles[0], angles[1]));
+        self.queue(Primitive::Ellipse { center, radii, color, arc }, center)
+    }
+
+    /// Last point referenced on the _DEST image, for STEP
+    pub fn last_point(&self) -> QResult<(i32, i32)> {
+        Ok(self.lookup(self.dest)?.last_point)
+    }
+
+    /// Make a point the last point referenced without drawing it
+    pub fn move_to(&mut self, point: (i32, i32)) -> QResult<()> {
+        let key = self.resolve(self.dest)?;
+        self.images.get_mut(&key).expect("resolved handle").last_point = point;
+        Ok(())
+    }
+
+    /// Add to the _DEST image's draw list; `last` becomes its last point
+    /// referenced
+    fn queue(&mut self, primitive: Primitive, last: (i32, i32)) -> QResult<()> {
+        if self.pending.len() >= MAX_PENDING {
+            self.flush();
+        }
+        let key = self.resolve(self.dest)?;
+        self.images.get_mut(&key).expect("resolved handle").last_point = last;
+        self.pending.push(primitive);
+        Ok(())
+    }
+
+    /// Draw the queued primitives onto the _DEST image. Whatever changes
+    /// the _DEST or the images flushes first, so it is still the image they
+    /// were queued for.
+    fn flush(&mut self) {
+        if self.pending.is_empty() {
+            return;
+        }
+        let key = self.resolve(self.dest).expect("_DEST checked when queued");
+        let image = self.images.get_mut(&key).expect("resolved handle");
+        for primitive in self.pending.drain(..) {
+            match primitive {
+                Primitive::Pixel { x, y, color } => image.pset(x, y, color),
+                Primitive::Line { from, to, color, style } => image.line(from, to, color, style),
+                Primitive::Ellipse { center, radii, color, arc } => image.ellipse(center, radii, color, arc),
+                Primitive::FilledBox { corner, opposite, color } => image.fill_box(corner, opposite, color),
+            }
+        }
+        if Some(key) == self.screen {
+            self.mark_dirty();
+        }
+    }
+
+    /// CLS clears the _DEST image
+    pub fn cls(&mut self) {
+        if let Ok(image) = self.dest_mut() {
+            image.clear(image.bg);
+            image.cursor = (0, 0);
+        }
+    }
+
+    /// _PUTIMAGE: copy part of one image onto another, scaling it to fit the
+    /// destination area. Indexed images exchange palette indices directly;
+    /// otherwise colors are converted through the palettes, and 32-bit
+    /// destinations blend by the source alpha.
+    pub fn put_image(&mut self, dest_area: Area, src: i32, dst: i32, src_area: Area) -> QResult<()> {
+        self.flush();
+        let src_key = self.resolve(src)?;
+        let dst_key = self.resolve(dst)?;
+        // Drawing an image onto itself reads from a snapshot
+        let source = if src_key == dst_key {
+            self.images[&src_key].clone()
+        } else {
+            self.images.remove(&src_key).expect("resolved handle")
+        };
+        let result = self
+            .image_mut(dst)
+            .map(|target| blit(&source, src_area, target, dest_area));
+        if src_key != dst_key {
+            self.images.insert(src_key, source);
+        }
+        result
+    }
+
+    /// _DISPLAY: show the screen as drawn so far and stop showing changes
+    /// until the next _DISPLAY
+    pub fn display(&mut self) {
+        self.flush();
+        self.auto_display = false;
+        self.frame = self.screen_image().cloned();
+        self.dirty = false;
+    }
+
+    /// _AUTODISPLAY: show every change as it is drawn
+    pub fn set_auto_display(&mut self) {
+        self.auto_display = true;
+        self.mark_dirty();
+    }
+
+    pub fn auto_display(&self) -> bool {
+        self.auto_display
+    }
+
+    /// The image a backend should show, or None in text mode
+    pub fn frame(&mut self) -> Option<&Image> {
+        self.flush();
+        if self.auto_display && self.dirty {
+            self.frame = self.screen_image().cloned();
+            self.dirty = false;
+        }
+        self.frame.as_ref()
+    }
+
+    fn mark_dirty(&mut self) {
+        self.dirty = true;
+    }
+}
+
+impl Default for Graphics {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// Angle of a point on an ellipse around the origin, in [0, 2 pi),
+/// counterclockwise from the right with y growing down
+fn ellipse_angle(dx: i32, dy: i32, (rx, ry): (i32, i32)) -> f64 {
+    let x = dx as f64 / rx.max(1) as f64;
+    let y = -dy as f64 / ry.max(1) as f64;
+    y.atan2(x).rem_euclid(std::f64::consts::TAU)
+}
+
+fn illegal_function_call() -> QError {
+    QError::runtime(QErrorCode::IllegalFunctionCall, 0, 0)
+}
+
+/// Corners of an area as (x1, y1, x2, y2), inclusive. `size` is the size an
+/// area given by its corner alone takes.
+fn corners(area: Area, image: &Image, size: (i32, i32)) -> (i32, i32, i32, i32) {
+    match area {
+        Area::Whole => (0, 0, image.width as i32 - 1, image.height as i32 - 1),
+        Area::At(x, y) => (x, y, x + size.0 - 1, y + size.1 - 1),
+        Area::Rect(x1, y1, x2, y2) => (x1, y1, x2, y2),
+    }
+}
+
+fn blit(source: &Image, src_area: Area, target: &mut Image, dest_area: Area) {
+    let (sx1, sy1, sx2, sy2) = match (src_area, dest_area) {
+        // A source corner alone copies as much as the destination area holds
+        (Area::At(_, _), Area::Rect(dx1, dy1, dx2, dy2)) => {
+            corners(src_area, source, ((dx2 - dx1).abs() + 1, (dy2 - dy1).abs() + 1))
+        }
+        (Area::At(x, y), _) => (x, y, source.width as i32 - 1, source.height as i32 - 1),
+        _ => corners(src_area, source, (0, 0)),
+    };
+    let src_size = ((sx2 - sx1).abs() + 1, (sy2 - sy1).abs() + 1);
+    let (dx1, dy1, dx2, dy2) = corners(dest_area, target, src_size);
+    let dst_size = ((dx2 - dx1).abs() + 1, (dy2 - dy1).abs() + 1);
+
+    for j in 0..dst_size.1 {
+        let sy = sy1 + (sy2 - sy1).signum() * (j as i64 * src_size.1 as i64 / dst_size.1 as i64) as i32;
+        let ty = dy1 + (dy2 - dy1).signum() * j;
+        for i in 0..dst_size.0 {
+            let sx = sx1 + (sx2 - sx1).signum() * (i as i64 * src_size.0 as i64 / dst_size.0 as i64) as i32;
+            let tx = dx1 + (dx2 - dx1).signum() * i;
+            let Some(value) = source.point(sx, sy) else { continue };
+            let Some(under) = target.point(tx, ty) else { continue };
+            let color = match (source.bits >= 32, target.bits >= 32) {
+                (false, false) => value,
+                (false, true) => source.to_argb(value),
+                (true, true) => blend(value, under),
+                (true, false) if value >> 24 == 0 => continue,
+                (true, false) => nearest(&target.palette, value),
+            };
+            target.pset(tx, ty, color);
+        }
+    }
+}
+
+/// Draw an ARGB color over another by its alpha
+fn blend(over: u32, under: u32) -> u32 {
+    let alpha = over >> 24;
+    match alpha {
+        0xFF => over,
+        0 => under,
+        _ => {
+            let mix = |shift: u32| {
+                let a = (over >> shift) & 0xFF;
+                let b = (under >> shift) & 0xFF;
+                ((a * alpha + b * (255 - alpha)) / 255) << shift
+            };
+            let out_alpha = alpha + (under >> 24) * (255 - alpha) / 255;
+            (out_alpha << 24) | mix(16) | mix(8) | mix(0)
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_pset_clips_and_masks() {
+        let mut image = Image::new(4, 4, 4);
+        image.pset(1, 2, 0x1F);
+        image.pset(-1, 9, 3);
+        assert_eq!(image.point(1, 2), Some(0xF));
+        assert_eq!(image.point(4, 0), None);
+    }
+
+    #[test]
+    fn test_line_styles_and_boxes() {
+        let mut image = Image::new(8, 4, 4);
+        image.line((0, 0), (7, 0), 1, 0xCCCC);
+        let row: Vec<u32> = (0..8).map(|x| image.point(x, 0).unwrap()).collect();
+        assert_eq!(row, [1, 1, 0, 0, 1, 1, 0, 0]);
+        // Steep lines and reversed ends
+        image.line((2, 3), (2, 1), 2, 0xFFFF);
+        assert_eq!((1..4).map(|y| image.point(2, y).unwrap()).collect::<Vec<_>>(), [2, 2, 2]);
+        image.fill_box((9, 3), (6, 2), 3);
+        assert_eq!(image.point(6, 2), Some(3));
+        assert_eq!(image.point(7, 3), Some(3));
+        assert_eq!(image.point(5, 3), Some(0));
+
+        let mut graphics = Graphics::new();
+        graphics.set_mode(13).unwrap();
+        assert_eq!(graphics.last_point().unwrap(), (160, 100));
+        graphics.line((1, 1), (4, 3), Some(5), LineShape::Box, 0xFFFF).unwrap();
+        assert_eq!(graphics.last_point().unwrap(), (4, 3));
+        let screen = graphics.image(SCREEN_HANDLE).unwrap();
+        assert_eq!(screen.point(4, 1), Some(5));
+        assert_eq!(screen.point(1, 3), Some(5));
+        assert_eq!(screen.point(2, 2), Some(0));
+    }
+
+    #[test]
+    fn test_ellipses_and_arcs() {
+        let mut image = Image::new(40, 40, 4);
+        image.ellipse((20, 20), (10, 5), 1, None);
+        for (x, y) in [(30, 20), (10, 20), (20, 15), (20, 25)] {
+            assert_eq!(image.point(x, y), Some(1), "({}, {})", x, y);
+        }
+        assert_eq!(image.point(20, 20), Some(0));
+
+        // The top left quarter only, counterclockwise from the top
+        let mut image = Image::new(40, 40, 4);
+        image.ellipse((20, 20), (8, 8), 2, Some((std::f64::consts::FRAC_PI_2, std::f64::consts::PI)));
+        assert_eq!(image.point(12, 20), Some(2));
+        assert_eq!(image.point(20, 12), Some(2));
+        assert_eq!(image.point(28, 20), Some(0));
+        assert_eq!(image.point(20, 28), Some(0));
+
+        let mut graphics = Graphics::new();
+        graphics.set_mode(13).unwrap();
+        assert!(graphics.circle((0, 0), 5.0, None, (Some(7.0), None), None).is_err());
+        // A negative start angle draws its radius too
+        graphics.circle((50, 50), 10.0, Some(3), (Some(-0.0001), Some(1.0)), Some(1.0)).unwrap();
+        assert_eq!(graphics.last_point().unwrap(), (50, 50));
+        let screen = graphics.image(SCREEN_HANDLE).unwrap();
+        assert_eq!(screen.point(55, 50), Some(3));
+        assert_eq!(screen.point(60, 50), Some(3));
+        assert_eq!(screen.point(40, 50), Some(0));
+    }
+
+    #[test]
+    fn test_display_buffers_frames() {
+        let mut graphics = Graphics::new();
+        graphics.set_mode(13).unwrap();
+        graphics.pset(0, 0, Some(4)).unwrap();
+        assert_eq!(graphics.frame().unwrap().point(0, 0), Some(4));
+
+        // After _DISPLAY, drawing is hidden until the next _DISPLAY
+        graphics.display();
+        graphics.pset(1, 0, Some(5)).unwrap();
+        assert_eq!(graphics.frame().unwrap().point(1, 0), Some(0));
+        graphics.display();
+        assert_eq!(graphics.frame().unwrap().point(1, 0), Some(5));
+
+        graphics.set_auto_display();
+        graphics.pset(2, 0, Some(6)).unwrap();
+        assert_eq!(graphics.frame().unwrap().point(2, 0), Some(6));
+    }
+
+    #[test]
+    fn test_draw_list_targets_its_dest() {
+        let mut graphics = Graphics::new();
+        graphics.set_mode(13).unwrap();
+        let image = graphics.new_image(4, 4, 32).unwrap();
+        graphics.pset(1, 1, Some(9)).unwrap();
+        graphics.set_dest(image).unwrap();
+        graphics.pset(1, 1, Some(0xFF00FF00)).unwrap();
+        assert_eq!(graphics.image(SCREEN_HANDLE).unwrap().point(1, 1), Some(9));
+        assert_eq!(graphics.image(image).unwrap().point(1, 1), Some(0xFF00FF00));
+
+        // Without a screen to draw on the PSET itself fails
+        graphics.free_image(image).unwrap();
+        graphics.set_mode(0).unwrap();
+        assert!(graphics.pset(0, 0, None).is_err());
+    }
+
+    #[test]
+    fn test_text_mode_rejects_graphics() {
+        let mut graphics = Graphics::new();
+        assert!(graphics.pset(0, 0, None).is_err());
+        assert!(graphics.set_mode(5).is_err());
+    }
+
+    #[test]
+    fn test_image_handles() {
+        let mut graphics = Graphics::new();
+        graphics.set_mode(13).unwrap();
+        let image = graphics.new_image(8, 4, 32).unwrap();
+        assert!(image < -1);
+
+        graphics.set_dest(image).unwrap();
+        graphics.pset(1, 1, Some(0xFF112233)).unwrap();
+        assert_eq!(graphics.dest(), image);
+        let copy = graphics.copy_image(image).unwrap();
+        assert_eq!(graphics.image(copy).unwrap().point(1, 1), Some(0xFF112233));
+
+        // Freeing the _DEST image sends drawing back to the screen
+        graphics.free_image(image).unwrap();
+        assert_eq!(graphics.dest(), graphics.source());
+        assert!(graphics.image(image).is_err());
+        assert!(graphics.free_image(SCREEN_HANDLE).is_err());
+        assert_eq!(graphics.load_image(Path::new("missing.png"), 32).unwrap(), -1);
+
+        // Too many pixels is Out of memory, not an allocation abort
+        let error = graphics.new_image(i32::MAX, i32::MAX, 32).unwrap_err();
+        assert!(matches!(error, QError::Runtime { code: QErrorCode::OutOfMemory, .. }));
+        assert!(graphics.new_image(1 << 15, 1 << 14, 32).is_err());
+    }
+
+    #[test]
+    fn test_image_size() {
+        let mut graphics = Graphics::new();
+        assert_eq!(graphics.size(SCREEN_HANDLE).unwrap(), TEXT_SIZE);
+        let image = graphics.new_image(8, 4, 32).unwrap();
+        assert_eq!(graphics.size(image).unwrap(), (8, 4));
+        graphics.set_mode(12).unwrap();
+        assert_eq!(graphics.size(SCREEN_HANDLE).unwrap(), (640, 480));
+        assert_eq!(graphics.size(graphics.dest()).unwrap(), (640, 480));
+        graphics.free_image(image).unwrap();
+        assert!(graphics.size(image).is_err());
+    }
+
+    #[test]
+    fn test_image_as_screen() {
+        let mut graphics = Graphics::new();
+        graphics.set_mode(13).unwrap();
+        let canvas = graphics.new_image(64, 32, 32).unwrap();
+        graphics.set_screen_image(canvas).unwrap();
+        graphics.pset(3, 4, None).unwrap();
+        assert_eq!(graphics.frame().unwrap().point(3, 4), Some(0xFFFFFFFF));
+        assert_eq!(graphics.match_color(0xFF00FF00, SCREEN_HANDLE).unwrap(), 0xFF00FF00);
+        assert!(graphics.free_image(canvas).is_err());
+
+        // Going back to a SCREEN mode keeps the image, which the program owns
+        graphics.set_mode(13).unwrap();
+        assert_eq!(graphics.match_color(0xFF00FF00, SCREEN_HANDLE).unwrap(), 48);
+        assert_eq!(graphics.color_argb(10, SCREEN_HANDLE).unwrap(), 0xFF55FF55);
+        graphics.free_image(canvas).unwrap();
+    }
+
+    #[test]
+    fn test_image_bytes() {
+        let mut image = Image::new(2, 1, 32);
+        image.pset(1, 0, 0xFF102030);
+        let mut buf = [0; 4];
+        image.read_bytes(4, &mut buf);
+        assert_eq!(buf, [0x30, 0x20, 0x10, 0xFF]);
+        image.write_bytes(6, &[0x40]);
+        assert_eq!(image.point(1, 0), Some(0xFF402030));
+
+        let mut indexed = Image::new(4, 1, 4);
+        assert_eq!(indexed.byte_len(), 4);
+        indexed.write_bytes(1, &[0xFE]);
+        assert_eq!(indexed.point(1, 0), Some(14));
+    }
+
+    #[test]
+    fn test_print_string() {
+        let mut graphics = Graphics::new();
+        graphics.set_mode(13).unwrap();
+        graphics.set_color(Some(4), Some(1)).unwrap();
+        graphics.print_string(10, 20, "\u{2588} ", SCREEN_HANDLE).unwrap();
+        let screen = graphics.image(SCREEN_HANDLE).unwrap();
+        assert_eq!(screen.point(10, 20), Some(4));
+        assert_eq!(screen.point(17, 27), Some(4));
+        assert_eq!(screen.point(18, 20), Some(1));
+        assert_eq!(screen.point(17, 28), Some(0)); // 8 rows in SCREEN 13
+        assert!(graphics.set_color(Some(256), None).is_err());
+
+        let canvas = graphics.new_image(32, 32, 32).unwrap();
+        graphics.print_string(0, 0, "\u{2588}", canvas).unwrap();
+        assert_eq!(graphics.image(canvas).unwrap().point(0, 15), Some(0xFFFFFFFF));
+        assert_eq!(graphics.print_width("abc", canvas).unwrap(), 24);
+    }
+
+    #[test]
+    fn test_print_text_wraps_and_scrolls() {
+        // Two columns, two rows
+        let mut image = Image::new(16, 16, 8);
+        image.char_height = 8;
+        image.print_text("\u{2588}\u{2588}\u{2588}");
+        assert_eq!(image.cursor, (1, 1));
+        assert_eq!(image.point(8, 0), Some(15));
+        image.print_text("\n\u{2588}");
+        assert_eq!(image.cursor, (1, 1));
+        assert_eq!(image.point(0, 0), Some(15)); // scrolled up from the second row
+        assert_eq!(image.point(8, 0), Some(0));
+        assert_eq!(image.point(0, 8), Some(15));
+        assert_eq!(image.point(8, 8), Some(0));
+    }
+
+    #[test]
+    fn test_put_image_scales_flips_and_converts() {
+        let mut graphics = Graphics::new();
+        graphics.set_mode(13).unwrap();
+        let sprite = graphics.new_image(2, 1, 32).unwrap();
+        graphics.image_mut(sprite).unwrap().pset(0, 0, 0xFFFF0000);
+        graphics.image_mut(sprite).unwrap().pset(1, 0, 0x00000000);
+
+        // Doubled in size and mirrored: the transparent pixel lands on the left
+        graphics.put_image(Area::Rect(13, 0, 10, 1), sprite, SCREEN_HANDLE, Area::Whole).unwrap();
+        let screen = graphics.image(SCREEN_HANDLE).unwrap();
+        assert_eq!(screen.point(10, 0), Some(0));
+        assert_eq!(screen.point(12, 1), Some(40)); // nearest VGA red
+        assert_eq!(screen.point(13, 0), Some(40));
+
+        // Unscaled copy of part of the screen into a 32-bit image
+        let target = graphics.new_image(4, 4, 32).unwrap();
+        graphics.put_image(Area::At(0, 0), SCREEN_HANDLE, target, Area::Rect(12, 0, 13, 1)).unwrap();
+        let target = graphics.image(target).unwrap();
+        assert_eq!(target.point(1, 1), Some(0xFFFF0000));
+        assert_eq!(target.point(2, 0), Some(0));
+    }
+}
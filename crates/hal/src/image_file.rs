@@ -0,0 +1,210 @@
+//! Loading PNG and BMP files into 32-bit images for _LOADIMAGE, and saving
+//! images as PNG for _SAVEIMAGE
+
+use crate::graphics::Image;
+use qb_core::errors::{QError, QErrorCode, QResult};
+use std::fs::File;
+use std::io::{BufReader, BufWriter};
+use std::path::Path;
+
+/// Load a PNG or BMP file as a 32-bit ARGB image
+pub fn load_image(path: &Path) -> QResult<Image> {
+    let bytes = std::fs::read(path).map_err(|_| QError::runtime(QErrorCode::FileNotFound, 0, 0))?;
+    if bytes.starts_with(b"BM") {
+        decode_bmp(&bytes)
+    } else if bytes.starts_with(b"\x89PNG") {
+        decode_png(path)
+    } else {
+        Err(bad_image())
+    }
+}
+
+fn bad_image() -> QError {
+    QError::runtime(QErrorCode::BadFileMode, 0, 0)
+}
+
+fn decode_png(path: &Path) -> QResult<Image> {
+    let file = File::open(path)?;
+    let mut decoder = png::Decoder::new(BufReader::new(file));
+    decoder.set_transformations(png::Transformations::EXPAND | png::Transformations::STRIP_16);
+    let mut reader = decoder.read_info().map_err(|_| bad_image())?;
+    let mut buffer = vec![0; reader.output_buffer_size()];
+    let info = reader.next_frame(&mut buffer).map_err(|_| bad_image())?;
+
+    let channels = info.color_type.samples();
+    let mut image = Image::new(info.width, info.height, 32);
+    for (i, px) in buffer[..info.buffer_size()].chunks_exact(channels).enumerate() {
+        let (r, g, b, a) = match px {
+            [v] => (*v, *v, *v, 255),
+            [v, a] => (*v, *v, *v, *a),
+            [r, g, b] => (*r, *g, *b, 255),
+            [r, g, b, a, ..] => (*r, *g, *b, *a),
+            [] => unreachable!(),
+        };
+        let x = (i as u32 % info.width) as i32;
+        let y = (i as u32 / info.width) as i32;
+        image.pset(x, y, argb(a, r, g, b));
+    }
+    Ok(image)
+}
+
+/// Save an image as a PNG file in its palette's colors
+pub fn save_png(image: &Image, path: &Path) -> QResult<()> {
+    let file = File::create(path).map_err(|_| QError::runtime(QErrorCode::PathNotFound, 0, 0))?;
+    let mut encoder = png::Encoder::new(BufWriter::new(file), image.width, image.height);
+    encoder.set_color(png::ColorType::Rgba);
+    encoder.set_depth(png::BitDepth::Eight);
+    let mut writer = encoder.write_header().map_err(|_| device_error())?;
+    writer.write_image_data(&rgba(image)).map_err(|_| device_error())?;
+    writer.finish().map_err(|_| device_error())
+}
+
+fn device_error() -> QError {
+    QError::runtime(QErrorCode::DeviceIOError, 0, 0)
+}
+
+/// The pixels of an image as RGBA bytes, row by row
+pub(crate) fn rgba(image: &Image) -> Vec<u8> {
+    let mut bytes = Vec::with_capacity((image.width * image.height * 4) as usize);
+    for y in 0..image.height as i32 {
+        for x in 0..image.width as i32 {
+            let argb = image.to_argb(image.point(x, y).unwrap_or(0));
+            bytes.extend([(argb >> 16) as u8, (argb >> 8) as u8, argb as u8, (argb >> 24) as u8]);
+        }
+    }
+    bytes
+}
+
+fn argb(a: u8, r: u8, g: u8, b: u8) -> u32 {
+    (u32::from(a) << 24) | (u32::from(r) << 16) | (u32::from(g) << 8) | u32::from(b)
+}
+
+fn read_u16(bytes: &[u8], at: usize) -> QResult<u16> {
+    bytes
+        .get(at..at + 2)
+        .map(|b| u16::from_le_bytes([b[0], b[1]]))
+        .ok_or_else(bad_image)
+}
+
+fn read_u32(bytes: &[u8], at: usize) -> QResult<u32> {
+    bytes
+        .get(at..at + 4)
+        .map(|b| u32::from_le_bytes([b[0], b[1], b[2], b[3]]))
+        .ok_or_else(bad_image)
+}
+
+/// Uncompressed Windows bitmaps at 1, 4, 8, 24 or 32 bits per pixel
+fn decode_bmp(bytes: &[u8]) -> QResult<Image> {
+    let data_offset = read_u32(bytes, 10)? as usize;
+    let header_size = read_u32(bytes, 14)? as usize;
+    let width = read_u32(bytes, 18)? as i32;
+    let raw_height = read_u32(bytes, 22)? as i32;
+    let bpp = read_u16(bytes, 28)?;
+    let compression = read_u32(bytes, 30)?;
+    // 0 = BI_RGB; 3 = BI_BITFIELDS, accepted for the usual 32-bit BGRA layout
+    if width <= 0 || raw_height == 0 || !(compression == 0 || (compression == 3 && bpp == 32)) {
+        return Err(bad_image());
+    }
+    let height = raw_height.abs();
+    let top_down = raw_height < 0;
+
+    let palette: Vec<u32> = if bpp <= 8 {
+        let used = read_u32(bytes, 46).unwrap_or(0) as usize;
+        let count = if used == 0 { 1 << bpp } else { used };
+        (0..count)
+            .map(|i| {
+                let at = 14 + header_size + i * 4;
+                bytes
+                    .get(at..at + 3)
+                    .map(|c| argb(255, c[2], c[1], c[0]))
+                    .ok_or_else(bad_image)
+            })
+            .collect::<QResult<_>>()?
+    } else {
+        Vec::new()
+    };
+
+    let stride = (usize::from(bpp) * width as usize).div_ceil(32) * 4;
+    let mut image = Image::new(width as u32, height as u32, 32);
+    for row in 0..height {
+        let start = data_offset + row as usize * stride;
+        let line = bytes.get(start..start + stride).ok_or_else(bad_image)?;
+        let y = if top_down { row } else { height - 1 - row };
+        for x in 0..width {
+            let xu = x as usize;
+            let color = match bpp {
+                1 | 4 | 8 => {
+                    let bit = xu * usize::from(bpp);
+                    let byte = line[bit / 8];
+                    let shift = 8 - usize::from(bpp) - bit % 8;
+                    let index = (byte >> shift) & ((1u16 << bpp) - 1) as u8;
+                    *palette.get(usize::from(index)).ok_or_else(bad_image)?
+                }
+                24 => {
+                    let px = &line[xu * 3..xu * 3 + 3];
+                    argb(255, px[2], px[1], px[0])
+                }
+                32 => {
+                    let px = &line[xu * 4..xu * 4 + 4];
+                    argb(px[3], px[2], px[1], px[0])
+                }
+                _ => return Err(bad_image()),
+            };
+            image.pset(x, y, color);
+        }
+    }
+    Ok(image)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// A 2x2 24-bit bitmap, bottom-up: blue, green / red, white
+    fn tiny_bmp() -> Vec<u8> {
+        let mut bytes = b"BM".to_vec();
+        let pixels: [u8; 16] = [
+            255, 0, 0, 0, 255, 0, 0, 0, // bottom row: blue, green, padding
+            0, 0, 255, 255, 255, 255, 0, 0, // top row: red, white, padding
+        ];
+        bytes.extend(&(54u32 + 16).to_le_bytes());
+        bytes.extend(&[0; 4]);
+        bytes.extend(&54u32.to_le_bytes());
+        bytes.extend(&40u32.to_le_bytes());
+        bytes.extend(&2i32.to_le_bytes());
+        bytes.extend(&2i32.to_le_bytes());
+        bytes.extend(&1u16.to_le_bytes());
+        bytes.extend(&24u16.to_le_bytes());
+        bytes.extend(&[0; 24]);
+        bytes.extend(&pixels);
+        bytes
+    }
+
+    #[test]
+    fn test_decode_bmp() {
+        let image = decode_bmp(&tiny_bmp()).unwrap();
+        assert_eq!((image.width, image.height), (2, 2));
+        assert_eq!(image.point(0, 0), Some(0xFFFF0000));
+        assert_eq!(image.point(1, 0), Some(0xFFFFFFFF));
+        assert_eq!(image.point(0, 1), Some(0xFF0000FF));
+        assert_eq!(image.point(1, 1), Some(0xFF00FF00));
+    }
+
+    #[test]
+    fn test_save_png() {
+        let mut image = Image::new(3, 2, 8);
+        image.pset(2, 1, 4);
+        let path = std::env::temp_dir().join(format!("qb-save-{}.png", std::process::id()));
+        save_png(&image, &path).unwrap();
+        let loaded = load_image(&path).unwrap();
+        std::fs::remove_file(&path).unwrap();
+        assert_eq!((loaded.width, loaded.height), (3, 2));
+        assert_eq!(loaded.point(2, 1), Some(0xFFAA0000));
+        assert_eq!(loaded.point(0, 0), Some(0xFF000000));
+    }
+
+    #[test]
+    fn test_reject_unknown_format() {
+        assert!(decode_bmp(b"BM").is_err());
+    }
+}
@@ -0,0 +1,393 @@
+//! Joystick emulation for STICK and STRIG, and the QB64 device functions
+//!
+//! Reads the first two game controllers as joysticks A and B. On Linux the
+//! kernel joystick interface (/dev/input/js0 and js1) is used; elsewhere no
+//! controller is attached and the sticks report their centre position.
+//!
+//! _DEVICES, _DEVICE$, _DEVICEINPUT, _BUTTON and _AXIS see the same
+//! controllers with all their buttons and axes. As in QB64 the keyboard and
+//! mouse are devices 1 and 2, so controllers are numbered from 3; neither
+//! reports input through these functions here.
+
+/// Value STICK returns for a centred axis
+const STICK_CENTER: i16 = 100;
+
+/// Button presses kept for event trapping before older ones are dropped
+const MAX_EVENTS: usize = 16;
+
+/// Device numbers of the keyboard and mouse, and of the first controller
+const KEYBOARD: usize = 1;
+const MOUSE: usize = 2;
+const FIRST_CONTROLLER: usize = 3;
+
+/// Buttons and axes QB64 gives the keyboard and mouse
+const KEYBOARD_BUTTONS: usize = 512;
+const MOUSE_BUTTONS: usize = 3;
+const MOUSE_AXES: usize = 2;
+
+/// Controller state as the BIOS saw it, with every axis and button the
+/// controller has reported
+#[derive(Debug, Clone, Default)]
+struct StickState {
+    /// None until the controller is opened
+    name: Option<String>,
+    /// Raw readings, from -32768 to 32767
+    axes: Vec<i16>,
+    buttons: Vec<bool>,
+    /// First two buttons pressed since STRIG last reported them
+    latched: [bool; 2],
+    /// Input arrived since _DEVICEINPUT last reported it
+    changed: bool,
+}
+
+impl StickState {
+    /// STICK's 1 to 200 reading of an axis, centred if never reported
+    fn stick_axis(&self, axis: usize) -> i16 {
+        self.axes.get(axis).map_or(STICK_CENTER, |&value| scale_axis(value))
+    }
+
+    fn button(&self, button: usize) -> bool {
+        self.buttons.get(button).copied().unwrap_or(false)
+    }
+}
+
+/// A button press, numbered as in ON STRIG(n): 0/2 for button 1 of
+/// sticks A/B and 4/6 for button 2
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct StrigEvent(pub i32);
+
+pub struct Joysticks {
+    sticks: [StickState; 2],
+    /// Axes sampled by the last STICK(0)
+    sampled: [i16; 4],
+    devices: Option<[Option<backend::Device>; 2]>,
+    /// Button presses not yet collected by take_events
+    events: Vec<StrigEvent>,
+    /// The device _BUTTON and _AXIS read, chosen by _DEVICEINPUT
+    selected: Option<usize>,
+}
+
+impl Joysticks {
+    pub fn new() -> Self {
+        Self {
+            sticks: Default::default(),
+            sampled: [STICK_CENTER; 4],
+            devices: None,
+            events: Vec::new(),
+            selected: None,
+        }
+    }
+
+    /// Read pending controller input
+    pub fn poll(&mut self) {
+        let sticks = &mut self.sticks;
+        let devices = self.devices.get_or_insert_with(|| {
+            [0, 1].map(|index| {
+                let device = backend::Device::open(index);
+                sticks[index].name = device.as_ref().map(|device| device.name());
+                device
+            })
+        });
+        let events = &mut self.events;
+        for (index, device) in devices.iter_mut().enumerate() {
+            let Some(device) = device else { continue };
+            let stick = &mut self.sticks[index];
+            while let Some(input) = device.read() {
+                stick.changed = true;
+                match input {
+                    backend::Input::Axis(axis, value) => {
+                        if stick.axes.len() <= axis {
+                            stick.axes.resize(axis + 1, 0);
+                        }
+                        stick.axes[axis] = value;
+                    }
+                    backend::Input::Button(button, pressed) => {
+                        if stick.buttons.len() <= button {
+                            stick.buttons.resize(button + 1, false);
+                        }
+                        if button < 2 && pressed && !stick.buttons[button] {
+                            stick.latched[button] = true;
+                            if events.len() < MAX_EVENTS {
+                                events.push(StrigEvent((button * 4 + index * 2) as i32));
+                            }
+                        }
+                        stick.buttons[button] = pressed;
+                    }
+                }
+            }
+        }
+    }
+
+    /// Button presses seen since the last call, for ON STRIG trapping
+    pub fn take_events(&mut self) -> Vec<StrigEvent> {
+        std::mem::take(&mut self.events)
+    }
+
+    /// STICK(n): 0 samples all axes and returns A's x; 1-3 return A's y,
+    /// B's x and B's y from that sample
+    pub fn stick(&mut self, n: i32) -> Option<i16> {
+        if n == 0 {
+            self.poll();
+            let [a, b] = &self.sticks;
+            self.sampled = [a.stick_axis(0), a.stick_axis(1), b.stick_axis(0), b.stick_axis(1)];
+        }
+        self.sampled.get(usize::try_from(n).ok()?).copied()
+    }
+
+    /// STRIG(n): even n reports a press of the button since the last call,
+    /// odd n whether it is down now
+    pub fn strig(&mut self, n: i32) -> Option<bool> {
+        if !(0..=7).contains(&n) {
+            return None;
+        }
+        self.poll();
+        let stick = &mut self.sticks[(n as usize / 2) % 2];
+        let button = n as usize / 4;
+        if n % 2 == 0 {
+            Some(std::mem::take(&mut stick.latched[button]))
+        } else {
+            Some(stick.button(button))
+        }
+    }
+
+    /// _DEVICES: the keyboard, the mouse and the controllers attached
+    pub fn device_count(&mut self) -> usize {
+        self.poll();
+        FIRST_CONTROLLER - 1 + self.controllers().count()
+    }
+
+    /// _DEVICE$(n): what device `n` is and has
+    pub fn device_name(&mut self, device: usize) -> Option<String> {
+        self.poll();
+        match device {
+            KEYBOARD => Some("[KEYBOARD][BUTTON]".to_string()),
+            MOUSE => Some("[MOUSE][BUTTON][AXIS]".to_string()),
+            _ => {
+                let stick = self.controller(device)?;
+                Some(format!("[CONTROLLER][[NAME][{}]][BUTTON][AXIS]", stick.name.as_deref().unwrap_or_default()))
+            }
+        }
+    }
+
+    /// _DEVICEINPUT(n): whether device `n` had input since the last call.
+    /// The device becomes the one _BUTTON and _AXIS read.
+    pub fn device_input(&mut self, device: usize) -> Option<bool> {
+        self.poll();
+        if device == 0 || device > FIRST_CONTROLLER - 1 + self.controllers().count() {
+            return None;
+        }
+        self.selected = Some(device);
+        let index = self.controller_index(device);
+        Some(index.is_some_and(|index| std::mem::take(&mut self.sticks[index].changed)))
+    }
+
+    /// _DEVICEINPUT: the first device with input since the last call, which
+    /// becomes the one _BUTTON and _AXIS read, or None
+    pub fn next_device_input(&mut self) -> Option<usize> {
+        self.poll();
+        let device = (FIRST_CONTROLLER..)
+            .zip(self.controllers())
+            .find(|(_, index)| self.sticks[*index].changed)
+            .map(|(device, _)| device)?;
+        self.device_input(device);
+        Some(device)
+    }
+
+    /// _LASTBUTTON(n): how many buttons device `n` has
+    pub fn button_count(&mut self, device: usize) -> Option<usize> {
+        self.poll();
+        match device {
+            KEYBOARD => Some(KEYBOARD_BUTTONS),
+            MOUSE => Some(MOUSE_BUTTONS),
+            _ => Some(self.controller(device)?.buttons.len()),
+        }
+    }
+
+    /// _LASTAXIS(n): how many axes device `n` has
+    pub fn axis_count(&mut self, device: usize) -> Option<usize> {
+        self.poll();
+        match device {
+            KEYBOARD => Some(0),
+            MOUSE => Some(MOUSE_AXES),
+            _ => Some(self.controller(device)?.axes.len()),
+        }
+    }
+
+    /// _BUTTON(n): whether button `n`, from 1, of the selected device is
+    /// down
+    pub fn button(&mut self, button: usize) -> Option<bool> {
+        let device = self.selected?;
+        if !(1..=self.button_count(device)?).contains(&button) {
+            return None;
+        }
+        Some(self.controller(device).is_some_and(|stick| stick.button(button - 1)))
+    }
+
+    /// _AXIS(n): where axis `n`, from 1, of the selected device is, from
+    /// -1 to 1
+    pub fn axis(&mut self, axis: usize) -> Option<f32> {
+        let device = self.selected?;
+        if !(1..=self.axis_count(device)?).contains(&axis) {
+            return None;
+        }
+        let value = self.controller(device).map_or(0, |stick| stick.axes[axis - 1]);
+        Some((f32::from(value) / 32767.0).max(-1.0))
+    }
+
+    /// Indexes of the controllers attached, in device order
+    fn controllers(&self) -> impl Iterator<Item = usize> + '_ {
+        (0..self.sticks.len()).filter(|&index| self.sticks[index].name.is_some())
+    }
+
+    fn controller_index(&self, device: usize) -> Option<usize> {
+        self.controllers().nth(device.checked_sub(FIRST_CONTROLLER)?)
+    }
+
+    fn controller(&self, device: usize) -> Option<&StickState> {
+        Some(&self.sticks[self.controller_index(device)?])
+    }
+}
+
+impl Default for Joysticks {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// Map a signed axis reading onto STICK's 1 to 200 range
+fn scale_axis(value: i16) -> i16 {
+    (1 + (i32::from(value) + 32768) * 199 / 65535) as i16
+}
+
+#[cfg(target_os = "linux")]
+mod backend {
+    use std::fs::File;
+    use std::io::Read;
+    use std::os::unix::fs::OpenOptionsExt;
+
+    const O_NONBLOCK: i32 = 0o4000;
+    const JS_EVENT_BUTTON: u8 = 0x01;
+    const JS_EVENT_AXIS: u8 = 0x02;
+    const JS_EVENT_INIT: u8 = 0x80;
+
+    pub enum Input {
+        Axis(usize, i16),
+        Button(usize, bool),
+    }
+
+    pub struct Device {
+        file: File,
+        index: usize,
+    }
+
+    impl Device {
+        pub fn open(index: usize) -> Option<Self> {
+            std::fs::OpenOptions::new()
+                .read(true)
+                .custom_flags(O_NONBLOCK)
+                .open(format!("/dev/input/js{}", index))
+                .ok()
+                .map(|file| Device { file, index })
+        }
+
+        /// The name the controller gives itself, as sysfs reports it
+        pub fn name(&self) -> String {
+            std::fs::read_to_string(format!("/sys/class/input/js{}/device/name", self.index))
+                .map(|name| name.trim().to_string())
+                .unwrap_or_default()
+        }
+
+        /// Next event from the kernel's struct js_event, if one is waiting.
+        /// Opening the device sends one for every axis and button.
+        pub fn read(&mut self) -> Option<Input> {
+            let mut event = [0u8; 8];
+            self.file.read_exact(&mut event).ok()?;
+            let value = i16::from_ne_bytes([event[4], event[5]]);
+            let number = usize::from(event[7]);
+            match event[6] & !JS_EVENT_INIT {
+                JS_EVENT_AXIS => Some(Input::Axis(number, value)),
+                JS_EVENT_BUTTON => Some(Input::Button(number, value != 0)),
+                _ => self.read(),
+            }
+        }
+    }
+}
+
+#[cfg(not(target_os = "linux"))]
+mod backend {
+    pub enum Input {
+        Axis(usize, i16),
+        Button(usize, bool),
+    }
+
+    pub struct Device;
+
+    impl Device {
+        pub fn open(_index: usize) -> Option<Self> {
+            None
+        }
+
+        pub fn name(&self) -> String {
+            String::new()
+        }
+
+        pub fn read(&mut self) -> Option<Input> {
+            None
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_scale_axis() {
+        assert_eq!(scale_axis(i16::MIN), 1);
+        assert_eq!(scale_axis(0), 100);
+        assert_eq!(scale_axis(i16::MAX), 200);
+    }
+
+    #[test]
+    fn test_argument_ranges() {
+        let mut joysticks = Joysticks::new();
+        assert_eq!(joysticks.stick(0), Some(joysticks.stick(1).unwrap()));
+        assert_eq!(joysticks.stick(4), None);
+        assert_eq!(joysticks.strig(8), None);
+    }
+
+    #[test]
+    fn test_devices() {
+        let mut joysticks = Joysticks::new();
+        // No controller is opened; the second stick is attached by hand
+        joysticks.devices = Some([None, None]);
+        joysticks.sticks[1] = StickState {
+            name: Some("Pad".to_string()),
+            axes: vec![-32768, 16384],
+            buttons: vec![false, true, false],
+            changed: true,
+            ..StickState::default()
+        };
+        assert_eq!(joysticks.device_count(), 3);
+        assert_eq!(joysticks.device_name(3).as_deref(), Some("[CONTROLLER][[NAME][Pad]][BUTTON][AXIS]"));
+        assert_eq!(joysticks.device_name(4), None);
+        assert_eq!((joysticks.button_count(3), joysticks.axis_count(3)), (Some(3), Some(2)));
+        // Nothing is selected until _DEVICEINPUT
+        assert_eq!(joysticks.button(1), None);
+
+        assert_eq!(joysticks.next_device_input(), Some(3));
+        assert_eq!(joysticks.next_device_input(), None);
+        assert_eq!(joysticks.button(2), Some(true));
+        assert_eq!(joysticks.button(4), None);
+        assert_eq!(joysticks.axis(1), Some(-1.0));
+        assert!((joysticks.axis(2).unwrap() - 0.5).abs() < 0.001);
+        // STICK sees the same controller as joystick B
+        joysticks.stick(0);
+        assert_eq!(joysticks.stick(2), Some(1));
+
+        assert_eq!(joysticks.device_input(2), Some(false));
+        assert_eq!(joysticks.button(3), Some(false));
+        assert_eq!(joysticks.axis(1), Some(0.0));
+        assert_eq!(joysticks.device_input(5), None);
+    }
+}
@@ -0,0 +1,339 @@
+//! Keyboard buffer emulation
+//!
+//! Keys wait in a ring of 16 words laid out as in the BIOS data area at
+//! 0040:001A, so programs that PEEK and POKE the head and tail pointers
+//! (e.g. POKE &H41A, PEEK(&H41C) to flush typed-ahead keys) see the same
+//! bytes they would under DOS.
+//!
+//! Keys come from the terminal: a `Keymap` turns the bytes it sends into
+//! character and scan code pairs, with arrows, editing and function keys
+//! as character 0 and their extended scan code.
+
+/// Offsets within segment &H40 of the head and tail pointers and the buffer
+pub const HEAD_OFFSET: u16 = 0x1A;
+pub const TAIL_OFFSET: u16 = 0x1C;
+pub const BUFFER_START: u16 = 0x1E;
+pub const BUFFER_END: u16 = 0x3E;
+
+pub struct KeyBuffer {
+    /// Head and tail as the BIOS stores them: offsets into segment &H40
+    head: u16,
+    tail: u16,
+    /// Low byte: character code, high byte: scan code
+    slots: [u16; 16],
+}
+
+impl KeyBuffer {
+    pub fn new() -> Self {
+        Self { head: BUFFER_START, tail: BUFFER_START, slots: [0; 16] }
+    }
+
+    fn next(offset: u16) -> u16 {
+        if offset + 2 >= BUFFER_END { BUFFER_START } else { offset + 2 }
+    }
+
+    /// Queue a key; false if the buffer is full, when the BIOS would beep
+    pub fn push(&mut self, ascii: u8, scan: u8) -> bool {
+        let tail = Self::next(self.tail);
+        if tail == self.head {
+            return false;
+        }
+        self.slots[usize::from(self.tail - BUFFER_START) / 2] = u16::from(scan) << 8 | u16::from(ascii);
+        self.tail = tail;
+        true
+    }
+
+    /// Take the oldest key as (character code, scan code)
+    pub fn pop(&mut self) -> Option<(u8, u8)> {
+        if self.is_empty() {
+            return None;
+        }
+        let word = self.slots[usize::from(self.head - BUFFER_START) / 2];
+        self.head = Self::next(self.head);
+        Some((word as u8, (word >> 8) as u8))
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.head == self.tail
+    }
+
+    /// Whether `offset` in segment &H40 belongs to the keyboard buffer
+    pub fn contains(offset: u16) -> bool {
+        (HEAD_OFFSET..BUFFER_END).contains(&offset)
+    }
+
+    /// Byte at `offset` in segment &H40, for PEEK
+    pub fn peek(&self, offset: u16) -> u8 {
+        let word = match offset & !1 {
+            HEAD_OFFSET => self.head,
+            TAIL_OFFSET => self.tail,
+            slot => self.slots[usize::from(slot - BUFFER_START) / 2],
+        };
+        if offset & 1 == 0 { word as u8 } else { (word >> 8) as u8 }
+    }
+
+    /// Store a byte at `offset` in segment &H40, for POKE. A head or tail
+    /// pointer outside the buffer would corrupt it and is ignored.
+    pub fn poke(&mut self, offset: u16, value: u8) {
+        let replace = |word: u16| {
+            if offset & 1 == 0 { word & 0xFF00 | u16::from(value) } else { word & 0x00FF | u16::from(value) << 8 }
+        };
+        match offset & !1 {
+            pointer @ (HEAD_OFFSET | TAIL_OFFSET) => {
+                let field = if pointer == HEAD_OFFSET { &mut self.head } else { &mut self.tail };
+                let word = replace(*field);
+                if (BUFFER_START..BUFFER_END).contains(&word) && word % 2 == 0 {
+                    *field = word;
+                }
+            }
+            slot => {
+                let slot = &mut self.slots[usize::from(slot - BUFFER_START) / 2];
+                *slot = replace(*slot);
+            }
+        }
+    }
+}
+
+impl Default for KeyBuffer {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// Scan codes of the keys on a US keyboard, indexed by the character
+/// they type (shifted or not) from space to '~'
+const PRINTABLE_SCANS: [u8; 95] = [
+    0x39, 0x02, 0x28, 0x04, 0x05, 0x06, 0x08, 0x28, 0x0A, 0x0B, 0x09, 0x0D, 0x33, 0x0C, 0x34, 0x35, // space to /
+    0x0B, 0x02, 0x03, 0x04, 0x05, 0x06, 0x07, 0x08, 0x09, 0x0A, // 0 to 9
+    0x27, 0x27, 0x33, 0x0D, 0x34, 0x35, 0x03, // : to @
+    0x1E, 0x30, 0x2E, 0x20, 0x12, 0x21, 0x22, 0x23, 0x17, 0x24, 0x25, 0x26, 0x32, // A to M
+    0x31, 0x18, 0x19, 0x10, 0x13, 0x1F, 0x14, 0x16, 0x2F, 0x11, 0x2D, 0x15, 0x2C, // N to Z
+    0x1A, 0x2B, 0x1B, 0x07, 0x0C, 0x29, // [ to `
+    0x1E, 0x30, 0x2E, 0x20, 0x12, 0x21, 0x22, 0x23, 0x17, 0x24, 0x25, 0x26, 0x32, // a to m
+    0x31, 0x18, 0x19, 0x10, 0x13, 0x1F, 0x14, 0x16, 0x2F, 0x11, 0x2D, 0x15, 0x2C, // n to z
+    0x1A, 0x2B, 0x1B, 0x29, // { to ~
+];
+
+/// Escape sequences VT100 and xterm terminals send, with the extended
+/// scan code of the key
+const EXTENDED_KEYS: [(&str, u8); 34] = [
+    ("\x1b[A", 0x48), ("\x1bOA", 0x48), // up
+    ("\x1b[B", 0x50), ("\x1bOB", 0x50), // down
+    ("\x1b[C", 0x4D), ("\x1bOC", 0x4D), // right
+    ("\x1b[D", 0x4B), ("\x1bOD", 0x4B), // left
+    ("\x1b[H", 0x47), ("\x1bOH", 0x47), ("\x1b[1~", 0x47), // home
+    ("\x1b[F", 0x4F), ("\x1bOF", 0x4F), ("\x1b[4~", 0x4F), // end
+    ("\x1b[2~", 0x52), ("\x1b[3~", 0x53), // insert, delete
+    ("\x1b[5~", 0x49), ("\x1b[6~", 0x51), // page up, page down
+    ("\x1bOP", 0x3B), ("\x1bOQ", 0x3C), ("\x1bOR", 0x3D), ("\x1bOS", 0x3E), // F1-F4
+    ("\x1b[11~", 0x3B), ("\x1b[12~", 0x3C), ("\x1b[13~", 0x3D), ("\x1b[14~", 0x3E), // F1-F4 (rxvt)
+    ("\x1b[15~", 0x3F), ("\x1b[17~", 0x40), ("\x1b[18~", 0x41), ("\x1b[19~", 0x42), // F5-F8
+    ("\x1b[20~", 0x43), ("\x1b[21~", 0x44), // F9, F10
+    ("\x1b[23~", 0x85), ("\x1b[24~", 0x86), // F11, F12
+];
+
+/// What the bytes a terminal sends mean as DOS keys. Starts with the
+/// VT100/xterm sequences; `bind` adds or replaces one.
+#[derive(Debug, Clone)]
+pub struct Keymap {
+    /// Byte sequence, character code, scan code
+    sequences: Vec<(Vec<u8>, u8, u8)>,
+}
+
+impl Keymap {
+    pub fn new() -> Self {
+        let sequences = EXTENDED_KEYS.iter().map(|(seq, scan)| (seq.as_bytes().to_vec(), 0, *scan)).collect();
+        Self { sequences }
+    }
+
+    /// Make `sequence` type the key with this character and scan code
+    pub fn bind(&mut self, sequence: &[u8], ascii: u8, scan: u8) {
+        self.sequences.retain(|(bound, _, _)| bound != sequence);
+        self.sequences.push((sequence.to_vec(), ascii, scan));
+    }
+
+    /// Scan code of the key that types character `ascii`
+    pub fn scan_code(ascii: u8) -> u8 {
+        match ascii {
+            8 => 0x0E,
+            9 => 0x0F,
+            13 => 0x1C,
+            27 => 0x01,
+            // Ctrl+A to Ctrl+Z
+            1..=26 => PRINTABLE_SCANS[usize::from(ascii - 1 + b'a' - b' ')],
+            b' '..=b'~' => PRINTABLE_SCANS[usize::from(ascii - b' ')],
+            _ => 0,
+        }
+    }
+
+    /// Keys typed by `input`, as (character code, scan code). Unknown
+    /// escape sequences are dropped; other characters outside ASCII are
+    /// typed by their code page 437 code.
+    pub fn decode(&self, input: &[u8]) -> Vec<(u8, u8)> {
+        let mut keys = Vec::new();
+        let mut rest = input;
+        while let Some(&byte) = rest.first() {
+            let bound = self
+                .sequences
+                .iter()
+                .filter(|(seq, _, _)| rest.starts_with(seq))
+                .max_by_key(|(seq, _, _)| seq.len());
+            if let Some((seq, ascii, scan)) = bound {
+                keys.push((*ascii, *scan));
+                rest = &rest[seq.len()..];
+                continue;
+            }
+            let length = match byte {
+                0x1B if rest.get(1) == Some(&b'[') => {
+                    // An unbound control sequence runs to its final byte
+                    rest[2..].iter().position(|b| (0x40..=0x7E).contains(b)).map_or(rest.len(), |end| end + 3)
+                }
+                0x80.. => {
+                    let length = rest.len().min(4);
+                    let text = (1..=length).find_map(|n| std::str::from_utf8(&rest[..n]).ok());
+                    if let Some(code) = text.and_then(|t| t.chars().next()).and_then(qb_core::cp437::from_char) {
+                        keys.push((code, 0));
+                    }
+                    text.map_or(1, str::len)
+                }
+                // Terminals send LF for Enter and DEL for Backspace
+                b'\n' => {
+                    keys.push((13, Self::scan_code(13)));
+                    1
+                }
+                0x7F => {
+                    keys.push((8, Self::scan_code(8)));
+                    1
+                }
+                _ => {
+                    keys.push((byte, Self::scan_code(byte)));
+                    1
+                }
+            };
+            rest = &rest[length..];
+        }
+        keys
+    }
+}
+
+impl Default for Keymap {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// Keys typed at the console since the last call, without waiting. Empty
+/// when standard input is not a console.
+pub fn poll_terminal(keymap: &Keymap) -> Vec<(u8, u8)> {
+    platform::poll(keymap)
+}
+
+#[cfg(unix)]
+mod platform {
+    use super::Keymap;
+
+    pub fn poll(keymap: &Keymap) -> Vec<(u8, u8)> {
+        let mut input = Vec::new();
+        // SAFETY: termios is plain data filled in by tcgetattr, and read
+        // writes at most buffer.len() bytes into the buffer
+        unsafe {
+            if libc::isatty(libc::STDIN_FILENO) == 0 {
+                return Vec::new();
+            }
+            let mut saved: libc::termios = std::mem::zeroed();
+            if libc::tcgetattr(libc::STDIN_FILENO, &mut saved) != 0 {
+                return Vec::new();
+            }
+            // Without line editing or echo, and with reads that return at
+            // once; Ctrl+C still interrupts
+            let mut raw = saved;
+            raw.c_lflag &= !(libc::ICANON | libc::ECHO);
+            raw.c_cc[libc::VMIN] = 0;
+            raw.c_cc[libc::VTIME] = 0;
+            libc::tcsetattr(libc::STDIN_FILENO, libc::TCSANOW, &raw);
+            let mut buffer = [0u8; 64];
+            loop {
+                let read = libc::read(libc::STDIN_FILENO, buffer.as_mut_ptr().cast(), buffer.len());
+                if read <= 0 {
+                    break;
+                }
+                input.extend_from_slice(&buffer[..read as usize]);
+            }
+            libc::tcsetattr(libc::STDIN_FILENO, libc::TCSANOW, &saved);
+        }
+        keymap.decode(&input)
+    }
+}
+
+#[cfg(windows)]
+mod platform {
+    use super::Keymap;
+
+    extern "C" {
+        fn _kbhit() -> i32;
+        fn _getch() -> i32;
+    }
+
+    /// The C runtime already reports keys the DOS way: extended keys as
+    /// 0 or 0xE0 followed by the scan code
+    pub fn poll(_keymap: &Keymap) -> Vec<(u8, u8)> {
+        let mut keys = Vec::new();
+        // SAFETY: _kbhit and _getch only read the console input buffer
+        unsafe {
+            while _kbhit() != 0 {
+                match _getch() {
+                    0 | 0xE0 => keys.push((0, _getch() as u8)),
+                    ascii => keys.push((ascii as u8, Keymap::scan_code(ascii as u8))),
+                }
+            }
+        }
+        keys
+    }
+}
+
+#[cfg(not(any(unix, windows)))]
+mod platform {
+    use super::Keymap;
+
+    pub fn poll(_keymap: &Keymap) -> Vec<(u8, u8)> {
+        Vec::new()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_ring_and_bios_pointers() {
+        let mut keys = KeyBuffer::new();
+        assert!(keys.push(b'a', 0x1E));
+        assert!(keys.push(0, 0x48)); // up arrow
+        assert_eq!(keys.peek(HEAD_OFFSET), 0x1E);
+        assert_eq!(keys.peek(TAIL_OFFSET), 0x22);
+        assert_eq!(keys.peek(BUFFER_START + 1), 0x1E);
+        assert_eq!(keys.pop(), Some((b'a', 0x1E)));
+
+        // POKE &H41A, PEEK(&H41C) empties the buffer
+        keys.poke(HEAD_OFFSET, keys.peek(TAIL_OFFSET));
+        assert!(keys.is_empty());
+        assert_eq!(keys.pop(), None);
+
+        // Fifteen keys fit; the slot before the head stays free
+        for _ in 0..15 {
+            assert!(keys.push(b'x', 0x2D));
+        }
+        assert!(!keys.push(b'y', 0x15));
+    }
+
+    #[test]
+    fn test_keymap_decodes_terminal_input() {
+        let mut keymap = Keymap::new();
+        let keys = keymap.decode(b"aZ\n\x1b[A\x1bOP\x1b[99X\x7f\x1b");
+        assert_eq!(keys, [(b'a', 0x1E), (b'Z', 0x2C), (13, 0x1C), (0, 0x48), (0, 0x3B), (8, 0x0E), (27, 0x01)]);
+        assert_eq!(keymap.decode("é".as_bytes()), [(130, 0)]);
+        assert_eq!(Keymap::scan_code(3), 0x2E); // Ctrl+C
+        keymap.bind(b"\x1b[A", 0, 0x50);
+        assert_eq!(keymap.decode(b"\x1b[A"), [(0, 0x50)]);
+    }
+}
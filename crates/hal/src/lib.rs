@@ -1,11 +1,44 @@
 //! QB-HAL: Hardware Abstraction Layer
 //! 
 //! Provides DOS hardware emulation for graphics, sound, and I/O.
-//! This is a placeholder for future full implementation.
+//! Graphics are shown in a window with the `window` feature, or else in
+//! the terminal.
 
 use qb_core::errors::QResult;
 use qb_core::memory_map::DosMemory;
 
+pub mod audio_file;
+pub mod break_key;
+pub mod clock;
+pub mod display;
+pub mod draw;
+pub mod font;
+pub mod framebuffer;
+pub mod graphics;
+pub mod image_file;
+pub mod joystick;
+pub mod keyboard;
+pub mod mixer;
+pub mod music;
+pub mod palette;
+pub mod recording;
+pub mod sound;
+pub mod terminal;
+pub mod window;
+
+pub use clock::{Clock, Limiter};
+pub use display::DisplayOptions;
+pub use draw::Turtle;
+pub use framebuffer::FramebufferWindow;
+pub use graphics::Graphics;
+pub use joystick::Joysticks;
+pub use keyboard::{KeyBuffer, Keymap};
+pub use mixer::{AudioSink, Mixer};
+pub use recording::ScreenRecorder;
+pub use sound::SoundSynth;
+pub use terminal::{TerminalDisplay, TerminalGraphics};
+pub use window::Window;
+
 /// VGA Graphics emulator
 pub struct VgaGraphics {
     memory: DosMemory,
@@ -68,33 +101,6 @@ impl Default for VgaGraphics {
     }
 }
 
-/// Sound synthesizer
-pub struct SoundSynth;
-
-impl SoundSynth {
-    pub fn new() -> Self {
-        Self
-    }
-
-    pub fn beep(&self) {
-        print!("\x07");
-    }
-
-    pub fn sound(&self, _frequency: u16, _duration: f32) {
-        // Not implemented - would require audio library
-    }
-
-    pub fn play(&self, _mml: &str) {
-        // Not implemented - would require audio library
-    }
-}
-
-impl Default for SoundSynth {
-    fn default() -> Self {
-        Self::new()
-    }
-}
-
 /// File I/O handler
 pub struct FileIO;
 
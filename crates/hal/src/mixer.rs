@@ -0,0 +1,318 @@
+//! Mixing every sound a program makes into one stream of samples: SOUND
+//! and PLAY tones, sounds opened with _SNDOPEN, and the samples _SNDRAW
+//! queues. Each is placed on the clock's timeline when it starts, and the
+//! timeline becomes samples as the clock passes it, for whatever sink the
+//! sound goes to.
+
+use qb_core::errors::{QError, QErrorCode, QResult};
+use std::collections::{HashMap, VecDeque};
+use std::io;
+use std::time::Duration;
+
+/// Samples per second of everything mixed (_SNDRATE)
+pub const SAMPLE_RATE: u32 = 44100;
+
+/// Loudness of a tone. Square waves are loud, and voices add up.
+const TONE_LEVEL: f32 = 0.2;
+
+/// Samples mixed at a time
+const BLOCK: u64 = 4096;
+
+/// Where mixed samples go: a sound device or a file
+pub trait AudioSink {
+    fn write(&mut self, samples: &[f32]) -> io::Result<()>;
+    /// Called once after the last samples
+    fn finish(&mut self) -> io::Result<()>;
+}
+
+/// A square wave on one voice, between two sample positions
+#[derive(Debug, Clone, Copy, PartialEq)]
+struct Tone {
+    voice: usize,
+    start: u64,
+    end: u64,
+    frequency: f64,
+}
+
+struct Sound {
+    samples: Vec<f32>,
+    volume: f32,
+}
+
+struct Playback {
+    handle: i32,
+    start: u64,
+    looped: bool,
+}
+
+#[derive(Default)]
+pub struct Mixer {
+    tones: Vec<Tone>,
+    sounds: HashMap<i32, Sound>,
+    next_handle: i32,
+    playing: Vec<Playback>,
+    /// _SNDRAW samples not yet played, the first at `raw_start`
+    raw: VecDeque<f32>,
+    raw_start: u64,
+    /// Samples mixed, or passed over when there is no sink
+    mixed: u64,
+    sink: Option<Box<dyn AudioSink>>,
+    /// The first error the sink returned, after which it is dropped
+    sink_error: Option<io::Error>,
+}
+
+/// The sample playing at a time on the clock
+fn position(time: Duration) -> u64 {
+    (time.as_secs_f64() * f64::from(SAMPLE_RATE)) as u64
+}
+
+fn illegal_function_call() -> QError {
+    QError::runtime(QErrorCode::IllegalFunctionCall, 0, 0)
+}
+
+impl Mixer {
+    pub fn new() -> Self {
+        Self { next_handle: 1, ..Self::default() }
+    }
+
+    /// Send the mix from now on to `sink`
+    pub fn set_sink(&mut self, sink: Box<dyn AudioSink>) {
+        self.sink = Some(sink);
+    }
+
+    /// A square wave of `frequency` on `voice` from `start` to `end`
+    pub fn add_tone(&mut self, voice: usize, start: Duration, end: Duration, frequency: f64) {
+        if frequency > 0.0 {
+            self.tones.push(Tone { voice, start: position(start), end: position(end), frequency });
+        }
+    }
+
+    /// Silence `voice` from `time`, cutting short the tone playing then
+    pub fn cut_tones(&mut self, voice: usize, time: Duration) {
+        let at = position(time);
+        self.tones.retain(|tone| tone.voice != voice || tone.start < at);
+        for tone in self.tones.iter_mut().filter(|tone| tone.voice == voice) {
+            tone.end = tone.end.min(at);
+        }
+    }
+
+    /// _SNDOPEN: a handle for samples at `SAMPLE_RATE`
+    pub fn open(&mut self, samples: Vec<f32>) -> i32 {
+        let handle = self.next_handle;
+        self.next_handle += 1;
+        self.sounds.insert(handle, Sound { samples, volume: 1.0 });
+        handle
+    }
+
+    /// _SNDPLAY and _SNDLOOP: start a sound from its beginning at `time`
+    pub fn play(&mut self, handle: i32, time: Duration, looped: bool) -> QResult<()> {
+        if !self.sounds.contains_key(&handle) {
+            return Err(illegal_function_call());
+        }
+        self.playing.retain(|playback| playback.handle != handle);
+        self.playing.push(Playback { handle, start: position(time), looped });
+        Ok(())
+    }
+
+    /// _SNDSTOP
+    pub fn stop(&mut self, handle: i32) -> QResult<()> {
+        if !self.sounds.contains_key(&handle) {
+            return Err(illegal_function_call());
+        }
+        self.playing.retain(|playback| playback.handle != handle);
+        Ok(())
+    }
+
+    /// _SNDCLOSE, which also stops it
+    pub fn close(&mut self, handle: i32) -> QResult<()> {
+        self.stop(handle)?;
+        self.sounds.remove(&handle);
+        Ok(())
+    }
+
+    /// _SNDVOL, from 0 (silent) to 1
+    pub fn set_volume(&mut self, handle: i32, volume: f32) -> QResult<()> {
+        let sound = self.sounds.get_mut(&handle).ok_or_else(illegal_function_call)?;
+        sound.volume = volume.clamp(0.0, 1.0);
+        Ok(())
+    }
+
+    /// _SNDPLAYING: whether the sound is still playing at `time`
+    pub fn is_playing(&self, handle: i32, time: Duration) -> QResult<bool> {
+        let sound = self.sounds.get(&handle).ok_or_else(illegal_function_call)?;
+        let at = position(time);
+        Ok(self
+            .playing
+            .iter()
+            .any(|playback| playback.handle == handle && (playback.looped || playback.start + sound.samples.len() as u64 > at)))
+    }
+
+    /// _SNDRAW: queue a sample after those still waiting, or at `time` when
+    /// none are
+    pub fn queue_raw(&mut self, sample: f32, time: Duration) {
+        let at = position(time);
+        if self.raw_start + self.raw.len() as u64 <= at {
+            self.raw.clear();
+            self.raw_start = at;
+        }
+        self.raw.push_back(sample.clamp(-1.0, 1.0));
+    }
+
+    /// _SNDRAWLEN: how long the queued _SNDRAW samples play for after `time`
+    pub fn raw_remaining(&self, time: Duration) -> Duration {
+        let end = self.raw_start + self.raw.len() as u64;
+        Duration::from_secs_f64(end.saturating_sub(position(time)) as f64 / f64::from(SAMPLE_RATE))
+    }
+
+    /// Mix up to `time` into the sink, or pass over it when there is none
+    pub fn advance(&mut self, time: Duration) {
+        self.mix_until(position(time));
+    }
+
+    /// Mix up to `time` and on until every sound queued by then has ended,
+    /// then close the sink. Sounds that loop stop there.
+    pub fn finish(&mut self, time: Duration) -> io::Result<()> {
+        let last_tone = self.tones.iter().map(|tone| tone.end).max().unwrap_or(0);
+        let last_sound = self
+            .playing
+            .iter()
+            .filter(|playback| !playback.looped)
+            .filter_map(|playback| Some(playback.start + self.sounds.get(&playback.handle)?.samples.len() as u64))
+            .max()
+            .unwrap_or(0);
+        let last_raw = self.raw_start + self.raw.len() as u64;
+        self.mix_until(position(time).max(last_tone).max(last_sound).max(last_raw));
+        if let Some(error) = self.sink_error.take() {
+            return Err(error);
+        }
+        match self.sink.take() {
+            Some(mut sink) => sink.finish(),
+            None => Ok(()),
+        }
+    }
+
+    fn mix_until(&mut self, end: u64) {
+        while self.mixed < end {
+            let block_end = end.min(self.mixed + BLOCK);
+            if self.sink.is_some() {
+                let samples = self.mix(self.mixed, block_end);
+                if let Some(Err(error)) = self.sink.as_mut().map(|sink| sink.write(&samples)) {
+                    self.sink = None;
+                    self.sink_error = Some(error);
+                }
+            }
+            self.mixed = block_end;
+            self.forget(block_end);
+        }
+    }
+
+    /// The samples from `start` up to `end`
+    fn mix(&self, start: u64, end: u64) -> Vec<f32> {
+        (start..end)
+            .map(|n| {
+                let mut sum = 0.0;
+                for tone in self.tones.iter().filter(|tone| (tone.start..tone.end).contains(&n)) {
+                    let phase = (n - tone.start) as f64 * tone.frequency / f64::from(SAMPLE_RATE);
+                    sum += if phase.fract() < 0.5 { TONE_LEVEL } else { -TONE_LEVEL };
+                }
+                for playback in self.playing.iter().filter(|playback| playback.start <= n) {
+                    let Some(sound) = self.sounds.get(&playback.handle) else { continue };
+                    let mut i = (n - playback.start) as usize;
+                    if playback.looped && !sound.samples.is_empty() {
+                        i %= sound.samples.len();
+                    }
+                    sum += sound.samples.get(i).copied().unwrap_or(0.0) * sound.volume;
+                }
+                if n >= self.raw_start {
+                    sum += self.raw.get((n - self.raw_start) as usize).copied().unwrap_or(0.0);
+                }
+                sum.clamp(-1.0, 1.0)
+            })
+            .collect()
+    }
+
+    /// Drop what has finished playing by sample `at`
+    fn forget(&mut self, at: u64) {
+        self.tones.retain(|tone| tone.end > at);
+        let sounds = &self.sounds;
+        self.playing.retain(|playback| {
+            playback.looped || sounds.get(&playback.handle).is_some_and(|sound| playback.start + sound.samples.len() as u64 > at)
+        });
+        while self.raw_start < at && self.raw.pop_front().is_some() {
+            self.raw_start += 1;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::cell::RefCell;
+    use std::rc::Rc;
+
+    /// Keeps what it is sent, for the test to look at
+    struct Capture(Rc<RefCell<Vec<f32>>>);
+
+    impl AudioSink for Capture {
+        fn write(&mut self, samples: &[f32]) -> io::Result<()> {
+            self.0.borrow_mut().extend_from_slice(samples);
+            Ok(())
+        }
+
+        fn finish(&mut self) -> io::Result<()> {
+            Ok(())
+        }
+    }
+
+    fn seconds(s: f64) -> Duration {
+        Duration::from_secs_f64(s)
+    }
+
+    #[test]
+    fn test_mixes_voices_sounds_and_raw() {
+        let output = Rc::new(RefCell::new(Vec::new()));
+        let mut mixer = Mixer::new();
+        mixer.set_sink(Box::new(Capture(Rc::clone(&output))));
+        // Two voices an octave apart add up while both sound
+        mixer.add_tone(0, seconds(0.0), seconds(0.1), 441.0);
+        mixer.add_tone(1, seconds(0.0), seconds(0.05), 882.0);
+        let handle = mixer.open(vec![0.5; 100]);
+        mixer.play(handle, seconds(0.0), false).unwrap();
+        mixer.set_volume(handle, 0.5).unwrap();
+        mixer.queue_raw(0.1, seconds(0.0));
+        assert!(mixer.is_playing(handle, seconds(0.001)).unwrap());
+        assert!(!mixer.is_playing(handle, seconds(0.01)).unwrap());
+        mixer.advance(seconds(0.05));
+        mixer.finish(seconds(0.05)).unwrap();
+
+        let output = output.borrow();
+        assert_eq!(output.len(), 4410);
+        assert!((output[0] - (0.2 + 0.2 + 0.25 + 0.1)).abs() < 1e-6);
+        assert!((output[1] - 0.65).abs() < 1e-6);
+        // Only the first voice is left, in the second half of its cycle
+        assert!((output[3000 + 75] + 0.2).abs() < 1e-6);
+    }
+
+    #[test]
+    fn test_cut_and_handles() {
+        let mut mixer = Mixer::new();
+        mixer.add_tone(0, seconds(0.0), seconds(1.0), 440.0);
+        mixer.add_tone(0, seconds(1.0), seconds(2.0), 440.0);
+        mixer.cut_tones(0, seconds(0.5));
+        assert_eq!(mixer.tones.len(), 1);
+        assert_eq!(mixer.tones[0].end, u64::from(SAMPLE_RATE) / 2);
+
+        assert!(mixer.play(7, seconds(0.0), false).is_err());
+        let handle = mixer.open(vec![0.0; 10]);
+        mixer.play(handle, seconds(0.0), true).unwrap();
+        assert!(mixer.is_playing(handle, seconds(60.0)).unwrap());
+        mixer.close(handle).unwrap();
+        assert!(mixer.is_playing(handle, seconds(0.0)).is_err());
+
+        mixer.queue_raw(0.0, seconds(1.0));
+        mixer.queue_raw(0.0, seconds(1.0));
+        assert_eq!(mixer.raw_remaining(seconds(1.0)), seconds(2.0 / f64::from(SAMPLE_RATE)));
+        mixer.advance(seconds(2.0));
+        assert_eq!(mixer.raw_remaining(seconds(2.0)), Duration::ZERO);
+    }
+}
@@ -0,0 +1,271 @@
+//! PLAY: the music macro language
+//!
+//! A PLAY string is a list of notes and settings. A to G play a note, with
+//! # or + for sharp and - for flat, an optional length and dots that each
+//! make it half as long again; N plays a note by number (0 is a rest) and P
+//! is a pause. O, < and > set the octave, L the length, T the tempo in
+//! quarter notes a minute, MN, ML and MS how long each note sounds (7/8,
+//! all or 3/4 of it), and MF and MB whether PLAY waits for the music to
+//! end or lets the program go on. Settings carry over from one PLAY string
+//! to the next.
+//!
+//! X substrings and =variable; arguments are expanded by the caller, which
+//! knows the program's variables, before the string gets here.
+
+use qb_core::errors::{QError, QErrorCode, QResult};
+use std::time::Duration;
+
+/// Highest octave, and the number N takes for its last note
+const MAX_OCTAVE: u32 = 6;
+const NOTES: u32 = (MAX_OCTAVE + 1) * 12;
+
+/// Number N gives A in octave 3, the one starting at middle C, which is
+/// 440 Hz
+const A440: u32 = 3 * 12 + 10;
+
+/// How much of its length a note sounds for
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Articulation {
+    Normal,
+    Legato,
+    Staccato,
+}
+
+/// One note, or a rest when the frequency is 0
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Note {
+    pub frequency: f64,
+    /// How long it sounds for
+    pub sounding: Duration,
+    /// How long until the next note starts
+    pub length: Duration,
+}
+
+/// The settings of the music macro language that carry over between PLAY
+/// strings
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Music {
+    tempo: u32,
+    octave: u32,
+    /// L: 1 is a whole note, 4 a quarter note
+    length: u32,
+    articulation: Articulation,
+    /// MB: PLAY returns while the music plays
+    background: bool,
+}
+
+impl Music {
+    pub fn new() -> Self {
+        Self { tempo: 120, octave: 4, length: 4, articulation: Articulation::Normal, background: false }
+    }
+
+    /// Whether PLAY leaves the music playing in the background (MB) or
+    /// waits for it to end (MF, the default)
+    pub fn background(&self) -> bool {
+        self.background
+    }
+
+    /// The notes of a PLAY string, taking in its settings
+    pub fn parse(&mut self, commands: &str) -> QResult<Vec<Note>> {
+        let mut reader = Reader { chars: commands.chars().collect(), pos: 0 };
+        let mut notes = Vec::new();
+        while let Some(command) = reader.next_command() {
+            match command {
+                'A'..='G' => {
+                    const STEPS: [i32; 7] = [9, 11, 0, 2, 4, 5, 7];
+                    let mut step = STEPS[(command as u8 - b'A') as usize];
+                    match reader.peek() {
+                        Some('#' | '+') => {
+                            reader.pos += 1;
+                            step += 1;
+                        }
+                        Some('-') => {
+                            reader.pos += 1;
+                            step -= 1;
+                        }
+                        _ => {}
+                    }
+                    let number = (self.octave * 12) as i32 + step + 1;
+                    let length = reader.number()?.unwrap_or(self.length);
+                    if !(1..=64).contains(&length) || !(1..=NOTES as i32).contains(&number) {
+                        return Err(illegal_function_call());
+                    }
+                    let dots = reader.dots();
+                    notes.push(self.note(number as u32, length, dots));
+                }
+                'N' => {
+                    let number = reader.number()?.ok_or_else(illegal_function_call)?;
+                    if number > NOTES {
+                        return Err(illegal_function_call());
+                    }
+                    let dots = reader.dots();
+                    notes.push(self.note(number, self.length, dots));
+                }
+                'P' => {
+                    let length = reader.number()?.ok_or_else(illegal_function_call)?;
+                    if !(1..=64).contains(&length) {
+                        return Err(illegal_function_call());
+                    }
+                    let dots = reader.dots();
+                    notes.push(self.note(0, length, dots));
+                }
+                'O' => {
+                    let octave = reader.number()?.ok_or_else(illegal_function_call)?;
+                    if octave > MAX_OCTAVE {
+                        return Err(illegal_function_call());
+                    }
+                    self.octave = octave;
+                }
+                '<' => self.octave = self.octave.saturating_sub(1),
+                '>' => self.octave = (self.octave + 1).min(MAX_OCTAVE),
+                'L' => {
+                    let length = reader.number()?.ok_or_else(illegal_function_call)?;
+                    if !(1..=64).contains(&length) {
+                        return Err(illegal_function_call());
+                    }
+                    self.length = length;
+                }
+                'T' => {
+                    let tempo = reader.number()?.ok_or_else(illegal_function_call)?;
+                    if !(32..=255).contains(&tempo) {
+                        return Err(illegal_function_call());
+                    }
+                    self.tempo = tempo;
+                }
+                'M' => match reader.next_command() {
+                    Some('N') => self.articulation = Articulation::Normal,
+                    Some('L') => self.articulation = Articulation::Legato,
+                    Some('S') => self.articulation = Articulation::Staccato,
+                    Some('F') => self.background = false,
+                    Some('B') => self.background = true,
+                    _ => return Err(illegal_function_call()),
+                },
+                _ => return Err(illegal_function_call()),
+            }
+        }
+        Ok(notes)
+    }
+
+    /// Note `number` (0 for a rest) lasting 1/`length` of a whole note
+    fn note(&self, number: u32, length: u32, dots: i32) -> Note {
+        let quarter = 60.0 / f64::from(self.tempo);
+        let seconds = quarter * 4.0 / f64::from(length) * 1.5f64.powi(dots);
+        let length = Duration::from_secs_f64(seconds);
+        if number == 0 {
+            return Note { frequency: 0.0, sounding: Duration::ZERO, length };
+        }
+        let share = match self.articulation {
+            Articulation::Normal => 7.0 / 8.0,
+            Articulation::Legato => 1.0,
+            Articulation::Staccato => 3.0 / 4.0,
+        };
+        Note {
+            frequency: 440.0 * 2f64.powf((f64::from(number) - f64::from(A440)) / 12.0),
+            sounding: length.mul_f64(share),
+            length,
+        }
+    }
+}
+
+impl Default for Music {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+struct Reader {
+    chars: Vec<char>,
+    pos: usize,
+}
+
+impl Reader {
+    /// Skip blanks and semicolons, which only separate commands
+    fn skip_separators(&mut self) {
+        while matches!(self.chars.get(self.pos), Some(' ' | ';' | '\t')) {
+            self.pos += 1;
+        }
+    }
+
+    fn peek(&self) -> Option<char> {
+        self.chars.get(self.pos).copied()
+    }
+
+    fn next_command(&mut self) -> Option<char> {
+        self.skip_separators();
+        let command = self.peek()?.to_ascii_uppercase();
+        self.pos += 1;
+        Some(command)
+    }
+
+    /// A decimal number, or None when there is none
+    fn number(&mut self) -> QResult<Option<u32>> {
+        self.skip_separators();
+        let start = self.pos;
+        while self.peek().is_some_and(|c| c.is_ascii_digit()) {
+            self.pos += 1;
+        }
+        let text: String = self.chars[start..self.pos].iter().collect();
+        match text.as_str() {
+            "" => Ok(None),
+            _ => text.parse().map(Some).map_err(|_| illegal_function_call()),
+        }
+    }
+
+    fn dots(&mut self) -> i32 {
+        let mut dots = 0;
+        while self.peek() == Some('.') {
+            self.pos += 1;
+            dots += 1;
+        }
+        dots
+    }
+}
+
+fn illegal_function_call() -> QError {
+    QError::runtime(QErrorCode::IllegalFunctionCall, 0, 0)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn frequencies(notes: &[Note]) -> Vec<u32> {
+        notes.iter().map(|note| note.frequency.round() as u32).collect()
+    }
+
+    #[test]
+    fn test_notes_and_octaves() {
+        let mut music = Music::new();
+        let notes = music.parse("O3 A A# B- > C < C N0 N47").unwrap();
+        assert_eq!(frequencies(&notes), vec![440, 466, 466, 523, 262, 0, 466]);
+        // C- is the B below
+        assert_eq!(frequencies(&music.parse("O4 C-").unwrap()), vec![494]);
+        // The octave stays within range
+        assert_eq!(frequencies(&music.parse("O6 > A").unwrap()), vec![3520]);
+    }
+
+    #[test]
+    fn test_lengths_and_articulation() {
+        let mut music = Music::new();
+        // At T120 a quarter note is half a second
+        let notes = music.parse("C C8 C4. P2 L16 C").unwrap();
+        let lengths: Vec<u128> = notes.iter().map(|note| note.length.as_millis()).collect();
+        assert_eq!(lengths, vec![500, 250, 750, 1000, 125]);
+        assert_eq!(notes[0].sounding.as_millis(), 437);
+        assert_eq!(notes[3].sounding, Duration::ZERO);
+
+        let notes = music.parse("T240 ML C MS C").unwrap();
+        assert_eq!(notes[0].sounding, notes[0].length);
+        assert_eq!(notes[1].sounding.as_micros(), 46875);
+        assert!(!music.background());
+        music.parse("mb").unwrap();
+        assert!(music.background());
+    }
+
+    #[test]
+    fn test_bad_commands() {
+        for bad in ["O7", "L0", "T20", "N85", "P", "MX", "H", "C65"] {
+            assert!(Music::new().parse(bad).is_err(), "{}", bad);
+        }
+    }
+}
@@ -0,0 +1,115 @@
+//! Default palettes of the CGA/EGA/VGA screen modes, as 0xAARRGGBB
+
+/// The 16 EGA colors
+pub const EGA_COLORS: [u32; 16] = [
+    0xFF000000, 0xFF0000AA, 0xFF00AA00, 0xFF00AAAA,
+    0xFFAA0000, 0xFFAA00AA, 0xFFAA5500, 0xFFAAAAAA,
+    0xFF555555, 0xFF5555FF, 0xFF55FF55, 0xFF55FFFF,
+    0xFFFF5555, 0xFFFF55FF, 0xFFFFFF55, 0xFFFFFFFF,
+];
+
+/// Gray ramp at VGA palette entries 16-31, in 6-bit DAC units
+const VGA_GRAYS: [u8; 16] = [0, 5, 8, 11, 14, 17, 20, 24, 28, 32, 36, 40, 45, 50, 56, 63];
+
+/// Levels (low, 1/4, 1/2, 3/4, high) of the nine 24-color hue blocks at
+/// VGA entries 32-247: three intensities, each at three saturations
+const VGA_HUE_LEVELS: [[u8; 5]; 9] = [
+    [0, 16, 31, 47, 63],
+    [31, 39, 47, 55, 63],
+    [45, 49, 54, 58, 63],
+    [0, 7, 14, 21, 28],
+    [14, 17, 21, 24, 28],
+    [20, 22, 24, 26, 28],
+    [0, 4, 8, 12, 16],
+    [8, 10, 12, 14, 16],
+    [11, 12, 13, 15, 16],
+];
+
+/// Scale a 6-bit DAC value to 8 bits
+fn dac(value: u8) -> u32 {
+    u32::from((value << 2) | (value >> 4))
+}
+
+fn rgb(r: u8, g: u8, b: u8) -> u32 {
+    0xFF000000 | (dac(r) << 16) | (dac(g) << 8) | dac(b)
+}
+
+/// The 256-color palette SCREEN 13 starts with
+pub fn vga_palette() -> Vec<u32> {
+    let mut colors = EGA_COLORS.to_vec();
+    colors.extend(VGA_GRAYS.iter().map(|&v| rgb(v, v, v)));
+    for [lo, a, b, c, hi] in VGA_HUE_LEVELS {
+        // Hue wheel: blue, magenta, red, yellow, green, cyan
+        let ramp_up = [lo, a, b, c];
+        let ramp_down = [hi, c, b, a];
+        colors.extend(ramp_up.iter().map(|&v| rgb(v, lo, hi)));
+        colors.extend(ramp_down.iter().map(|&v| rgb(hi, lo, v)));
+        colors.extend(ramp_up.iter().map(|&v| rgb(hi, v, lo)));
+        colors.extend(ramp_down.iter().map(|&v| rgb(v, hi, lo)));
+        colors.extend(ramp_up.iter().map(|&v| rgb(lo, hi, v)));
+        colors.extend(ramp_down.iter().map(|&v| rgb(lo, v, hi)));
+    }
+    colors.resize(256, 0xFF000000);
+    colors
+}
+
+/// Palette an image of the given depth starts with; empty for 32-bit
+pub fn default_palette(bits: u8) -> Vec<u32> {
+    match bits {
+        1 => vec![EGA_COLORS[0], EGA_COLORS[15]],
+        // SCREEN 1 palette 1: black, cyan, magenta, white
+        2 => vec![EGA_COLORS[0], EGA_COLORS[11], EGA_COLORS[13], EGA_COLORS[15]],
+        4 => EGA_COLORS.to_vec(),
+        8 => vga_palette(),
+        _ => Vec::new(),
+    }
+}
+
+/// _RGBA32: pack channels into ARGB, clamping each to 0-255
+pub fn rgba32(r: i32, g: i32, b: i32, a: i32) -> u32 {
+    let channel = |value: i32| value.clamp(0, 255) as u32;
+    (channel(a) << 24) | (channel(r) << 16) | (channel(g) << 8) | channel(b)
+}
+
+/// Index of the palette entry closest to an ARGB color
+pub fn nearest(palette: &[u32], color: u32) -> u32 {
+    let channel = |c: u32, shift: u32| ((c >> shift) & 0xFF) as i32;
+    palette
+        .iter()
+        .enumerate()
+        .min_by_key(|(_, &entry)| {
+            [16, 8, 0]
+                .iter()
+                .map(|&shift| (channel(entry, shift) - channel(color, shift)).pow(2))
+                .sum::<i32>()
+        })
+        .map_or(0, |(index, _)| index as u32)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_vga_palette() {
+        let palette = vga_palette();
+        assert_eq!(palette.len(), 256);
+        assert_eq!(palette[15], 0xFFFFFFFF);
+        assert_eq!(palette[31], 0xFFFFFFFF);
+        assert_eq!(palette[32], 0xFF0000FF); // start of the hue wheel: blue
+        assert_eq!(palette[40], 0xFFFF0000); // red
+        assert_eq!(palette[255], 0xFF000000);
+    }
+
+    #[test]
+    fn test_rgba32_clamps() {
+        assert_eq!(rgba32(255, 128, 0, 255), 0xFFFF8000);
+        assert_eq!(rgba32(300, -5, 16, 0), 0x00FF0010);
+    }
+
+    #[test]
+    fn test_nearest() {
+        assert_eq!(nearest(&EGA_COLORS, 0xFFA00000), 4);
+        assert_eq!(nearest(&EGA_COLORS, 0xFFFFFFF0), 15);
+    }
+}
@@ -0,0 +1,116 @@
+//! Recording the screen as an animated PNG, for documentation and bug
+//! reports. Frames are kept in memory as the program draws them and the
+//! file is written once the run ends.
+
+use crate::graphics::Image;
+use crate::image_file;
+use std::io::{self, Write};
+use std::time::{Duration, Instant};
+
+/// Frames closer together than this are merged, the later one winning
+const FRAME_INTERVAL: Duration = Duration::from_millis(40);
+
+/// How long the last frame stays up before the animation starts over
+const LAST_FRAME: Duration = Duration::from_secs(2);
+
+#[derive(Debug, Default)]
+pub struct ScreenRecorder {
+    /// Each distinct frame and when it was first shown
+    frames: Vec<(Image, Instant)>,
+}
+
+impl ScreenRecorder {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Add the frame on the screen at `now`, if it changed
+    pub fn capture(&mut self, frame: &Image, now: Instant) {
+        match self.frames.last_mut() {
+            Some((last, _)) if last == frame => {}
+            Some((last, at)) if now.duration_since(*at) < FRAME_INTERVAL => {
+                *last = frame.clone();
+            }
+            _ => self.frames.push((frame.clone(), now)),
+        }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.frames.is_empty()
+    }
+
+    /// Write the frames as an APNG, as large as the largest of them.
+    /// Smaller frames, from a change of screen mode, sit in the top left.
+    pub fn write_apng(&self, out: impl Write) -> io::Result<()> {
+        let width = self.frames.iter().map(|(frame, _)| frame.width).max().unwrap_or(1);
+        let height = self.frames.iter().map(|(frame, _)| frame.height).max().unwrap_or(1);
+        let mut encoder = png::Encoder::new(out, width, height);
+        encoder.set_color(png::ColorType::Rgba);
+        encoder.set_depth(png::BitDepth::Eight);
+        encoder.set_animated(self.frames.len().max(1) as u32, 0)?;
+        let mut writer = encoder.write_header()?;
+        if self.frames.is_empty() {
+            writer.write_image_data(&vec![0; (width * height * 4) as usize])?;
+        }
+        for (i, (frame, at)) in self.frames.iter().enumerate() {
+            let shown = match self.frames.get(i + 1) {
+                Some((_, next)) => next.duration_since(*at),
+                None => LAST_FRAME,
+            };
+            let millis = shown.as_millis().clamp(1, u128::from(u16::MAX)) as u16;
+            writer.set_frame_delay(millis, 1000)?;
+            writer.write_image_data(&canvas(frame, width, height))?;
+        }
+        writer.finish()?;
+        Ok(())
+    }
+}
+
+/// A frame's pixels, opaque, on a black canvas of `width` by `height`
+fn canvas(frame: &Image, width: u32, height: u32) -> Vec<u8> {
+    let pixels = image_file::rgba(frame);
+    let row = frame.width as usize * 4;
+    let mut bytes = vec![0; (width * height * 4) as usize];
+    for (y, line) in pixels.chunks_exact(row).enumerate() {
+        let start = y * width as usize * 4;
+        bytes[start..start + row].copy_from_slice(line);
+    }
+    for alpha in bytes.iter_mut().skip(3).step_by(4) {
+        *alpha = 255;
+    }
+    bytes
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_capture_and_write() {
+        let start = Instant::now();
+        let mut frame = Image::new(4, 2, 8);
+        let mut recorder = ScreenRecorder::new();
+        recorder.capture(&frame, start);
+        // Unchanged frames are not kept
+        recorder.capture(&frame, start + Duration::from_millis(100));
+        frame.pset(0, 0, 15);
+        recorder.capture(&frame, start + Duration::from_millis(200));
+        // A change right after another replaces it
+        frame.pset(1, 0, 15);
+        recorder.capture(&frame, start + Duration::from_millis(210));
+        recorder.capture(&Image::new(2, 3, 8), start + Duration::from_millis(500));
+        assert_eq!(recorder.frames.len(), 3);
+        assert_eq!(recorder.frames[1].0.point(1, 0), Some(15));
+
+        let mut bytes = Vec::new();
+        recorder.write_apng(&mut bytes).unwrap();
+        let mut reader = png::Decoder::new(bytes.as_slice()).read_info().unwrap();
+        assert_eq!((reader.info().width, reader.info().height), (4, 3));
+        assert_eq!(reader.info().animation_control().map(|control| control.num_frames), Some(3));
+        let mut buffer = vec![0; reader.output_buffer_size()];
+        reader.next_frame(&mut buffer).unwrap();
+        reader.next_frame(&mut buffer).unwrap();
+        assert_eq!(reader.info().frame_control().map(|control| control.delay_num), Some(300));
+        assert_eq!(buffer[4..8], [255, 255, 255, 255]);
+    }
+}
@@ -0,0 +1,285 @@
+//! PC speaker emulation: SOUND/PLAY note queue on the 18.2 Hz tick clock
+//!
+//! Notes are queued and play in the background, so the program keeps
+//! running while they sound. The queue drains against the clock; PLAY(n)
+//! reports how many notes are still waiting. PLAY can also sound three
+//! voices at once, as the PCjr and Tandy did, each with its own queue. The notes, and the sounds of
+//! the QB64 _SND statements, are mixed together by the `Mixer`.
+
+use crate::audio_file;
+use crate::clock::Clock;
+use crate::mixer::{AudioSink, Mixer};
+use crate::music::Music;
+use qb_core::errors::{QError, QErrorCode, QResult};
+use std::collections::VecDeque;
+use std::io;
+use std::path::Path;
+use std::time::Duration;
+
+/// BIOS timer tick rate: the 1.193182 MHz PIT clock divided by 65536
+pub const TICKS_PER_SECOND: f64 = 1_193_182.0 / 65_536.0;
+
+/// Notes the background music buffer holds before SOUND/PLAY must wait
+pub const QUEUE_CAPACITY: usize = 32;
+
+/// Voices PLAY can sound at once; SOUND and PLAY(n) use the first
+pub const VOICES: usize = 3;
+
+/// Convert a duration in clock ticks to wall-clock time
+pub fn ticks_to_duration(ticks: f64) -> Duration {
+    Duration::from_secs_f64(ticks / TICKS_PER_SECOND)
+}
+
+/// A queued tone; frequency 0 is a rest
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Tone {
+    pub frequency: f64,
+    /// Clock time at which it stops
+    pub ends_at: Duration,
+}
+
+/// Sound synthesizer
+pub struct SoundSynth {
+    /// The notes queued on each voice
+    voices: [VecDeque<Tone>; VOICES],
+    clock: Clock,
+    mixer: Mixer,
+    /// The PLAY settings of each voice, which carry over between PLAY
+    /// statements
+    music: [Music; VOICES],
+}
+
+impl SoundSynth {
+    pub fn new() -> Self {
+        Self::with_clock(Clock::real())
+    }
+
+    pub fn with_clock(clock: Clock) -> Self {
+        Self { voices: Default::default(), clock, mixer: Mixer::new(), music: [Music::new(); VOICES] }
+    }
+
+    pub fn beep(&self) {
+        print!("\x07");
+    }
+
+    /// SOUND frequency, ticks. A duration of 0 silences the speaker and
+    /// discards queued notes.
+    pub fn sound(&mut self, frequency: f64, ticks: f64) -> QResult<()> {
+        if !(0.0..=65535.0).contains(&ticks) {
+            return Err(QError::runtime(QErrorCode::IllegalFunctionCall, 0, 0));
+        }
+        if ticks == 0.0 {
+            self.stop();
+            return Ok(());
+        }
+        if !(37.0..=32767.0).contains(&frequency) {
+            return Err(QError::runtime(QErrorCode::IllegalFunctionCall, 0, 0));
+        }
+        self.enqueue(frequency, ticks_to_duration(ticks));
+        Ok(())
+    }
+
+    /// Queue a note (or a rest with frequency 0) after the ones already
+    /// playing, waiting first if the queue is full
+    pub fn enqueue(&mut self, frequency: f64, length: Duration) {
+        self.enqueue_note(0, frequency, length, length);
+    }
+
+    /// Queue a note on `voice` that sounds for `sounding` of its `length`,
+    /// the rest of it silent
+    fn enqueue_note(&mut self, voice: usize, frequency: f64, sounding: Duration, length: Duration) {
+        self.drain();
+        if self.voices[voice].len() >= QUEUE_CAPACITY {
+            if let Some(first) = self.voices[voice].front() {
+                self.clock.sleep_until(first.ends_at);
+            }
+            self.drain();
+        }
+        let now = self.clock.elapsed();
+        let start = self.voice_end(voice).max(now);
+        self.voices[voice].push_back(Tone { frequency, ends_at: start + length });
+        self.mixer.add_tone(voice, start, start + sounding, frequency);
+    }
+
+    /// When the last note queued on `voice` ends
+    fn voice_end(&self, voice: usize) -> Duration {
+        self.voices[voice].back().map_or(Duration::ZERO, |tone| tone.ends_at)
+    }
+
+    /// PLAY(n): notes still waiting to be played on the first voice,
+    /// including the current one
+    pub fn pending(&mut self) -> usize {
+        self.drain();
+        self.voices[0].len()
+    }
+
+    /// Time left until every queued note has finished
+    pub fn remaining(&mut self) -> Duration {
+        self.drain();
+        let end = (0..VOICES).map(|voice| self.voice_end(voice)).max().unwrap_or_default();
+        end.saturating_sub(self.clock.elapsed())
+    }
+
+    /// Silence the speaker and drop queued notes
+    pub fn stop(&mut self) {
+        let now = self.clock.elapsed();
+        for (voice, queue) in self.voices.iter_mut().enumerate() {
+            queue.clear();
+            self.mixer.cut_tones(voice, now);
+        }
+    }
+
+    /// _SNDOPEN: load a WAV file, returning its handle
+    pub fn open_sound(&mut self, path: &Path) -> QResult<i32> {
+        let samples = audio_file::load_wav(path)?;
+        Ok(self.mixer.open(samples))
+    }
+
+    /// _SNDPLAY, or _SNDLOOP when `looped`
+    pub fn play_sound(&mut self, handle: i32, looped: bool) -> QResult<()> {
+        self.drain();
+        self.mixer.play(handle, self.clock.elapsed(), looped)
+    }
+
+    pub fn mixer(&mut self) -> &mut Mixer {
+        self.drain();
+        &mut self.mixer
+    }
+
+    /// _SNDPLAYING
+    pub fn sound_playing(&mut self, handle: i32) -> QResult<bool> {
+        self.drain();
+        self.mixer.is_playing(handle, self.clock.elapsed())
+    }
+
+    /// _SNDRAW: queue one sample, from -1 to 1
+    pub fn raw(&mut self, sample: f32) {
+        self.drain();
+        self.mixer.queue_raw(sample, self.clock.elapsed());
+    }
+
+    /// _SNDRAWLEN: seconds of _SNDRAW samples still to play
+    pub fn raw_remaining(&mut self) -> f64 {
+        self.drain();
+        self.mixer.raw_remaining(self.clock.elapsed()).as_secs_f64()
+    }
+
+    /// Send the mixed sound from now on to `sink`
+    pub fn set_output(&mut self, sink: Box<dyn AudioSink>) {
+        self.drain();
+        self.mixer.set_sink(sink);
+    }
+
+    /// Mix the sound up to now, as the program runs
+    pub fn update(&mut self) {
+        self.drain();
+    }
+
+    /// Mix everything queued to its end and close the output
+    pub fn finish_output(&mut self) -> io::Result<()> {
+        self.mixer.finish(self.clock.elapsed())
+    }
+
+    /// Remove notes that have finished playing
+    fn drain(&mut self) {
+        let now = self.clock.elapsed();
+        for queue in &mut self.voices {
+            while queue.front().is_some_and(|tone| tone.ends_at <= now) {
+                queue.pop_front();
+            }
+        }
+        self.mixer.advance(now);
+    }
+
+    /// PLAY: queue the notes of up to three music macro language strings,
+    /// one for each voice, then wait for the voices not in the background
+    /// (MB) to finish
+    pub fn play<S: AsRef<str>>(&mut self, voices: &[S]) -> QResult<()> {
+        if voices.len() > VOICES {
+            return Err(QError::runtime(QErrorCode::IllegalFunctionCall, 0, 0));
+        }
+        let mut notes = voices
+            .iter()
+            .zip(&mut self.music)
+            .map(|(commands, music)| music.parse(commands.as_ref()).map(VecDeque::from))
+            .collect::<QResult<Vec<_>>>()?;
+        // The voice furthest behind goes next, so the voices keep together
+        // when a full queue has to wait
+        while let Some(voice) = (0..notes.len()).filter(|&v| !notes[v].is_empty()).min_by_key(|&v| self.voice_end(v)) {
+            if let Some(note) = notes[voice].pop_front() {
+                self.enqueue_note(voice, note.frequency, note.sounding, note.length);
+            }
+        }
+        let foreground = (0..voices.len()).filter(|&voice| !self.music[voice].background());
+        if let Some(end) = foreground.map(|voice| self.voice_end(voice)).max() {
+            self.clock.sleep_until(end);
+            self.drain();
+        }
+        Ok(())
+    }
+}
+
+impl Default for SoundSynth {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_ticks_to_duration() {
+        let second = ticks_to_duration(18.2);
+        assert!((second.as_secs_f64() - 1.0).abs() < 0.001);
+    }
+
+    #[test]
+    fn test_queue_runs_in_background() {
+        let mut synth = SoundSynth::new();
+        synth.sound(440.0, 18.2).unwrap();
+        synth.sound(880.0, 18.2).unwrap();
+        assert_eq!(synth.pending(), 2);
+        assert!(synth.remaining() > Duration::from_millis(1500));
+        synth.sound(440.0, 0.0).unwrap();
+        assert_eq!(synth.pending(), 0);
+    }
+
+    #[test]
+    fn test_play_foreground_and_background() {
+        let mut synth = SoundSynth::with_clock(Clock::fixed(0.0));
+        // MF waits for the music to end
+        synth.play(&["T120 L4 C D"]).unwrap();
+        assert_eq!(synth.pending(), 0);
+        assert!(synth.clock.elapsed() >= Duration::from_secs(1));
+        synth.play(&["MB C8 P8 E8"]).unwrap();
+        assert_eq!(synth.pending(), 3);
+        assert!((synth.remaining().as_secs_f64() - 0.75).abs() < 0.01);
+        assert!(synth.play(&["Z"]).is_err());
+    }
+
+    #[test]
+    fn test_play_voices_together() {
+        let mut synth = SoundSynth::with_clock(Clock::fixed(0.0));
+        // Each voice keeps its own settings
+        synth.play(&["MB T240 L4 C C C C", "MB T120 L2 E", "MB O2 L1 G"]).unwrap();
+        assert_eq!(synth.pending(), 4);
+        assert_eq!(synth.voices[1].len(), 1);
+        assert_eq!(synth.voice_end(0), synth.voice_end(1));
+        assert!((synth.remaining().as_secs_f64() - 2.0).abs() < 0.01);
+        // A voice in the foreground is waited for, here until 2 seconds in
+        synth.play(&["MB C C C C C C", "MF C"]).unwrap();
+        assert_eq!(synth.clock.elapsed(), Duration::from_secs(2));
+        assert_eq!(synth.pending(), 2);
+        assert!(synth.play(&["C"; 4]).is_err());
+    }
+
+    #[test]
+    fn test_sound_range() {
+        let mut synth = SoundSynth::new();
+        assert!(synth.sound(20.0, 1.0).is_err());
+        assert!(synth.sound(440.0, -1.0).is_err());
+        assert!(synth.sound(440.0, 70000.0).is_err());
+    }
+}
@@ -0,0 +1,398 @@
+//! Graphics in the terminal, for when there is no window to draw in (over
+//! SSH, in CI). The screen image is scaled to fit as the display options
+//! say and drawn with Unicode half blocks, two pixels to a character cell
+//! in 24-bit or xterm's 256 colors, or as a sixel image on terminals that
+//! show them.
+
+use crate::display::DisplayOptions;
+use crate::graphics::Image;
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::fmt::{self, Write as _};
+use std::io::{self, IsTerminal, Write};
+use std::str::FromStr;
+use std::time::{Duration, Instant};
+
+/// Fewest seconds between two frames, so a busy loop is not slowed down
+/// by redrawing the terminal after every pixel
+const FRAME_INTERVAL: Duration = Duration::from_millis(40);
+
+/// Pixel size assumed for a character cell when the terminal does not say
+const CELL_PIXELS: (u32, u32) = (8, 16);
+
+/// How graphics modes are shown in the terminal
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum TerminalGraphics {
+    /// Not at all; the program's graphics are only drawn in memory
+    #[default]
+    Off,
+    /// Upper half block characters colored by the pixels above and below
+    #[serde(rename = "blocks")]
+    HalfBlocks,
+    /// Half blocks in the nearest of xterm's 256 colors, for terminals
+    /// without 24-bit color
+    #[serde(rename = "blocks256")]
+    HalfBlocks256,
+    /// DEC sixel images
+    Sixel,
+}
+
+impl TerminalGraphics {
+    /// What standard output can likely show: half blocks in 24-bit color
+    /// where COLORTERM says the terminal has it, in 256 colors on other
+    /// terminals, and nothing when output is not a terminal
+    pub fn detect() -> Self {
+        if !io::stdout().is_terminal() {
+            return TerminalGraphics::Off;
+        }
+        match std::env::var("COLORTERM") {
+            Ok(colors) if colors == "truecolor" || colors == "24bit" => TerminalGraphics::HalfBlocks,
+            _ => TerminalGraphics::HalfBlocks256,
+        }
+    }
+}
+
+impl FromStr for TerminalGraphics {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_lowercase().as_str() {
+            "off" | "none" => Ok(TerminalGraphics::Off),
+            "blocks" | "halfblocks" => Ok(TerminalGraphics::HalfBlocks),
+            "blocks256" => Ok(TerminalGraphics::HalfBlocks256),
+            "sixel" => Ok(TerminalGraphics::Sixel),
+            _ => Err(format!("unknown terminal graphics '{}' (expected blocks, blocks256, sixel or off)", s)),
+        }
+    }
+}
+
+impl fmt::Display for TerminalGraphics {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            TerminalGraphics::Off => "off",
+            TerminalGraphics::HalfBlocks => "blocks",
+            TerminalGraphics::HalfBlocks256 => "blocks256",
+            TerminalGraphics::Sixel => "sixel",
+        })
+    }
+}
+
+/// Draws frames of the screen image to standard output
+#[derive(Debug)]
+pub struct TerminalDisplay {
+    mode: TerminalGraphics,
+    options: DisplayOptions,
+    /// The frame last drawn, to skip drawing it again
+    shown: Option<Image>,
+    shown_at: Option<Instant>,
+}
+
+impl TerminalDisplay {
+    pub fn new(mode: TerminalGraphics) -> Self {
+        Self { mode, options: DisplayOptions::default(), shown: None, shown_at: None }
+    }
+
+    pub fn mode(&self) -> TerminalGraphics {
+        self.mode
+    }
+
+    pub fn options(&self) -> DisplayOptions {
+        self.options
+    }
+
+    /// Change how frames are scaled and colored, redrawing the next one
+    pub fn set_options(&mut self, options: DisplayOptions) {
+        self.options = options;
+        self.shown = None;
+    }
+
+    /// Draw `frame` unless it is the one on show. Unless `now` is set, a
+    /// frame that comes too soon after the last one waits for a later call.
+    pub fn present(&mut self, frame: &Image, now: bool) -> io::Result<()> {
+        if self.mode == TerminalGraphics::Off || self.shown.as_ref() == Some(frame) {
+            return Ok(());
+        }
+        if !now && self.shown_at.is_some_and(|at| at.elapsed() < FRAME_INTERVAL) {
+            return Ok(());
+        }
+        let (columns, rows) = terminal_size();
+        let text = match self.mode {
+            TerminalGraphics::Off => return Ok(()),
+            // The last row is left for the cursor, so the picture does not scroll
+            TerminalGraphics::HalfBlocks | TerminalGraphics::HalfBlocks256 => {
+                let rows = rows.saturating_sub(1).max(1);
+                let picture = self.options.render(frame, (columns, rows * 2));
+                half_blocks(&picture, columns, rows, self.mode == TerminalGraphics::HalfBlocks)
+            }
+            TerminalGraphics::Sixel => {
+                let (width, height) = platform::pixel_size()
+                    .unwrap_or((columns * CELL_PIXELS.0, rows * CELL_PIXELS.1));
+                let height = height.saturating_sub(CELL_PIXELS.1).max(6);
+                sixel(&self.options.render(frame, (width, height)), width, height)
+            }
+        };
+        let mut stdout = io::stdout().lock();
+        write!(stdout, "\x1b[H{}", text)?;
+        stdout.flush()?;
+        self.shown = Some(frame.clone());
+        self.shown_at = Some(Instant::now());
+        Ok(())
+    }
+}
+
+/// Character columns and rows of the terminal on standard output
+pub fn terminal_size() -> (u32, u32) {
+    platform::cell_size()
+        .or_else(|| {
+            let columns = std::env::var("COLUMNS").ok()?.parse().ok()?;
+            let rows = std::env::var("LINES").ok()?.parse().ok()?;
+            Some((columns, rows))
+        })
+        .filter(|&(columns, rows)| columns > 0 && rows > 0)
+        .unwrap_or((80, 25))
+}
+
+/// The image as rows of half block characters, at most `columns` wide and
+/// `rows` high, in 24-bit color or else xterm's 256. Each row ends by
+/// resetting the colors.
+pub fn half_blocks(image: &Image, columns: u32, rows: u32, full_color: bool) -> String {
+    let (width, height, pixels) = fit(image, columns, rows * 2);
+    let mut text = String::new();
+    for y in (0..height).step_by(2) {
+        let mut last = None;
+        for x in 0..width {
+            let top = pixels[(y * width + x) as usize];
+            let bottom = if y + 1 < height { pixels[((y + 1) * width + x) as usize] } else { 0 };
+            if last != Some((top, bottom)) {
+                for (layer, argb) in [(38, top), (48, bottom)] {
+                    let (r, g, b) = channels(argb);
+                    if full_color {
+                        let _ = write!(text, "\x1b[{};2;{};{};{}m", layer, r, g, b);
+                    } else {
+                        let _ = write!(text, "\x1b[{};5;{}m", layer, xterm_color(r, g, b));
+                    }
+                }
+                last = Some((top, bottom));
+            }
+            text.push('▀');
+        }
+        text.push_str("\x1b[0m\r\n");
+    }
+    text
+}
+
+/// The image as a sixel sequence at most `width` by `height` pixels. Up to
+/// 256 colors are sent as they are; more are reduced to a 6x6x6 color cube.
+pub fn sixel(image: &Image, width: u32, height: u32) -> String {
+    let (width, height, mut pixels) = fit(image, width, height);
+    let mut palette: HashMap<u32, usize> = HashMap::new();
+    for &argb in &pixels {
+        let next = palette.len();
+        palette.entry(argb).or_insert(next);
+    }
+    if palette.len() > 256 {
+        for argb in pixels.iter_mut() {
+            let (r, g, b) = channels(*argb);
+            let level = |c: u8| (u32::from(c) * 5 + 127) / 255 * 51;
+            *argb = (level(r) << 16) | (level(g) << 8) | level(b);
+        }
+        palette.clear();
+        for &argb in &pixels {
+            let next = palette.len();
+            palette.entry(argb).or_insert(next);
+        }
+    }
+
+    let mut text = format!("\x1bPq\"1;1;{};{}", width, height);
+    let mut colors: Vec<(u32, usize)> = palette.iter().map(|(&argb, &index)| (argb, index)).collect();
+    colors.sort_by_key(|&(_, index)| index);
+    for &(argb, index) in &colors {
+        let (r, g, b) = channels(argb);
+        let percent = |c: u8| (u32::from(c) * 100 + 127) / 255;
+        let _ = write!(text, "#{};2;{};{};{}", index, percent(r), percent(g), percent(b));
+    }
+    for top in (0..height).step_by(6) {
+        let band = top..(top + 6).min(height);
+        for &(argb, index) in &colors {
+            let sixels: Vec<u8> = (0..width)
+                .map(|x| {
+                    band.clone()
+                        .filter(|&y| pixels[(y * width + x) as usize] == argb)
+                        .fold(0, |bits, y| bits | 1 << (y - top))
+                })
+                .collect();
+            if sixels.iter().all(|&bits| bits == 0) {
+                continue;
+            }
+            let _ = write!(text, "#{}", index);
+            run_length(&mut text, &sixels);
+            text.push('$');
+        }
+        text.push('-');
+    }
+    text.push_str("\x1b\\");
+    text
+}
+
+/// Sixel characters for a row of bit patterns, with repeats counted and
+/// the blank end of the row left out
+fn run_length(text: &mut String, sixels: &[u8]) {
+    let end = sixels.iter().rposition(|&bits| bits != 0).map_or(0, |last| last + 1);
+    let mut rest = &sixels[..end];
+    while let Some(&bits) = rest.first() {
+        let run = rest.iter().take_while(|&&b| b == bits).count();
+        let c = char::from(63 + bits);
+        if run > 3 {
+            let _ = write!(text, "!{}{}", run, c);
+        } else {
+            text.extend(std::iter::repeat_n(c, run));
+        }
+        rest = &rest[run..];
+    }
+}
+
+/// The image scaled down, never up, to fit `width` by `height` keeping its
+/// shape, as RGB. Each pixel averages the pixels it covers.
+fn fit(image: &Image, width: u32, height: u32) -> (u32, u32, Vec<u32>) {
+    let scale = (width as f64 / image.width as f64)
+        .min(height as f64 / image.height as f64)
+        .min(1.0);
+    let out_width = ((image.width as f64 * scale) as u32).max(1);
+    let out_height = ((image.height as f64 * scale) as u32).max(1);
+    let span = |i: u32, out: u32, size: u32| {
+        let start = (u64::from(i) * u64::from(size) / u64::from(out)) as u32;
+        let end = (u64::from(i + 1) * u64::from(size) / u64::from(out)) as u32;
+        start..end.max(start + 1)
+    };
+    let mut pixels = Vec::with_capacity((out_width * out_height) as usize);
+    for y in 0..out_height {
+        for x in 0..out_width {
+            let mut sum = [0u32; 3];
+            let mut count = 0;
+            for sy in span(y, out_height, image.height) {
+                for sx in span(x, out_width, image.width) {
+                    let value = image.point(sx as i32, sy as i32).unwrap_or(0);
+                    let (r, g, b) = channels(image.to_argb(value));
+                    sum[0] += u32::from(r);
+                    sum[1] += u32::from(g);
+                    sum[2] += u32::from(b);
+                    count += 1;
+                }
+            }
+            let [r, g, b] = sum.map(|c| (c + count / 2) / count);
+            pixels.push((r << 16) | (g << 8) | b);
+        }
+    }
+    (out_width, out_height, pixels)
+}
+
+/// The nearest color in xterm's 256: its 6x6x6 cube or its gray ramp
+fn xterm_color(r: u8, g: u8, b: u8) -> u8 {
+    const CUBE: [u8; 6] = [0, 95, 135, 175, 215, 255];
+    let level = |c: u8| match c {
+        0..48 => 0,
+        48..115 => 1,
+        _ => (c - 35) / 40,
+    };
+    let distance = |(r2, g2, b2): (u8, u8, u8)| {
+        [(r, r2), (g, g2), (b, b2)].iter().map(|&(a, b)| (i32::from(a) - i32::from(b)).pow(2)).sum::<i32>()
+    };
+    let (lr, lg, lb) = (level(r), level(g), level(b));
+    let cube = (CUBE[lr as usize], CUBE[lg as usize], CUBE[lb as usize]);
+    let average = ((u32::from(r) + u32::from(g) + u32::from(b)) / 3) as u8;
+    let step = if average > 238 { 23 } else { average.saturating_sub(3) / 10 };
+    let gray = 8 + 10 * step;
+    if distance((gray, gray, gray)) < distance(cube) {
+        232 + step
+    } else {
+        16 + 36 * lr + 6 * lg + lb
+    }
+}
+
+fn channels(argb: u32) -> (u8, u8, u8) {
+    ((argb >> 16) as u8, (argb >> 8) as u8, argb as u8)
+}
+
+#[cfg(unix)]
+mod platform {
+    fn window_size() -> Option<libc::winsize> {
+        // SAFETY: TIOCGWINSZ fills in the winsize it is given and nothing else
+        unsafe {
+            let mut size: libc::winsize = std::mem::zeroed();
+            (libc::ioctl(libc::STDOUT_FILENO, libc::TIOCGWINSZ, &mut size) == 0).then_some(size)
+        }
+    }
+
+    pub fn cell_size() -> Option<(u32, u32)> {
+        window_size().map(|size| (u32::from(size.ws_col), u32::from(size.ws_row)))
+    }
+
+    pub fn pixel_size() -> Option<(u32, u32)> {
+        window_size()
+            .map(|size| (u32::from(size.ws_xpixel), u32::from(size.ws_ypixel)))
+            .filter(|&(width, height)| width > 0 && height > 0)
+    }
+}
+
+#[cfg(not(unix))]
+mod platform {
+    pub fn cell_size() -> Option<(u32, u32)> {
+        None
+    }
+
+    pub fn pixel_size() -> Option<(u32, u32)> {
+        None
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_half_blocks() {
+        // 4x4 scaled to 2x2: each pixel averages a 2x2 square
+        let mut image = Image::new(4, 4, 32);
+        image.fill_box((0, 0), (1, 1), 0xFFFF0000);
+        image.fill_box((2, 2), (3, 3), 0xFF0000FF);
+        let text = half_blocks(&image, 2, 1, true);
+        assert_eq!(
+            text,
+            "\x1b[38;2;255;0;0m\x1b[48;2;0;0;0m▀\x1b[38;2;0;0;0m\x1b[48;2;0;0;255m▀\x1b[0m\r\n"
+        );
+        // Never scaled up
+        assert_eq!(half_blocks(&image, 80, 25, true).matches('▀').count(), 8);
+        assert_eq!(
+            half_blocks(&image, 2, 1, false),
+            "\x1b[38;5;196m\x1b[48;5;16m▀\x1b[38;5;16m\x1b[48;5;21m▀\x1b[0m\r\n"
+        );
+    }
+
+    #[test]
+    fn test_xterm_color() {
+        assert_eq!(xterm_color(0xAA, 0, 0), 124);
+        assert_eq!(xterm_color(0x55, 0x55, 0xFF), 63);
+        // Grays between the cube's steps go to the gray ramp
+        assert_eq!(xterm_color(0x80, 0x80, 0x80), 244);
+        assert_eq!(xterm_color(255, 255, 255), 231);
+    }
+
+    #[test]
+    fn test_sixel() {
+        let mut image = Image::new(8, 2, 4);
+        image.line((0, 1), (7, 1), 4, 0xFFFF);
+        let text = sixel(&image, 640, 480);
+        // Black is #0 and red (palette 4, 0xAA0000) is #1; the red row is the
+        // second bit of the band and the black one the first
+        assert_eq!(text, "\x1bPq\"1;1;8;2#0;2;0;0;0#1;2;67;0;0#0!8@$#1!8A$-\x1b\\");
+    }
+
+    #[test]
+    fn test_parse_mode() {
+        assert_eq!("Blocks".parse(), Ok(TerminalGraphics::HalfBlocks));
+        assert_eq!("sixel".parse(), Ok(TerminalGraphics::Sixel));
+        assert_eq!("blocks256".parse(), Ok(TerminalGraphics::HalfBlocks256));
+        assert!("ascii".parse::<TerminalGraphics>().is_err());
+    }
+}
@@ -0,0 +1,120 @@
+//! Program window: title, position and resize policy (_TITLE, _SCREENMOVE, _RESIZE)
+//!
+//! Backends apply these settings to their window and report user resizes
+//! through `notify_resize`. In the terminal the title is set with an xterm
+//! escape sequence.
+
+use serde::{Deserialize, Serialize};
+use std::io::{self, IsTerminal, Write};
+
+/// How the screen image is fitted to a resized window
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Serialize, Deserialize)]
+pub enum ResizeMode {
+    /// The program handles the new size itself (_RESIZE ON)
+    #[default]
+    Program,
+    /// Scale the image to the window, ignoring aspect ratio
+    Stretch,
+    /// Scale the image keeping its aspect ratio
+    Smooth,
+}
+
+/// Where _SCREENMOVE put the window
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum WindowPosition {
+    Middle,
+    At(i32, i32),
+}
+
+#[derive(Debug, Default)]
+pub struct Window {
+    title: String,
+    position: Option<WindowPosition>,
+    resize_enabled: bool,
+    resize_mode: ResizeMode,
+    /// Set when the user resized the window since _RESIZE last checked
+    resized: bool,
+    resize_size: (u32, u32),
+}
+
+impl Window {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// _TITLE
+    pub fn set_title(&mut self, title: &str) {
+        self.title = title.to_string();
+        let mut stdout = io::stdout();
+        if stdout.is_terminal() {
+            let _ = write!(stdout, "\x1b]0;{}\x07", title);
+            let _ = stdout.flush();
+        }
+    }
+
+    pub fn title(&self) -> &str {
+        &self.title
+    }
+
+    /// _SCREENMOVE x, y or _SCREENMOVE _MIDDLE
+    pub fn move_to(&mut self, position: WindowPosition) {
+        self.position = Some(position);
+    }
+
+    pub fn position(&self) -> Option<WindowPosition> {
+        self.position
+    }
+
+    /// _RESIZE ON|OFF [, _STRETCH|_SMOOTH]
+    pub fn set_resize(&mut self, enabled: bool, mode: ResizeMode) {
+        self.resize_enabled = enabled;
+        self.resize_mode = mode;
+        if !enabled {
+            self.resized = false;
+        }
+    }
+
+    pub fn resize_enabled(&self) -> bool {
+        self.resize_enabled
+    }
+
+    pub fn resize_mode(&self) -> ResizeMode {
+        self.resize_mode
+    }
+
+    /// Called by the backend when the user resizes the window
+    pub fn notify_resize(&mut self, width: u32, height: u32) {
+        if self.resize_enabled {
+            self.resized = true;
+            self.resize_size = (width, height);
+        }
+    }
+
+    /// _RESIZE function: true once per user resize
+    pub fn take_resize(&mut self) -> bool {
+        std::mem::take(&mut self.resized)
+    }
+
+    /// _RESIZEWIDTH and _RESIZEHEIGHT: size requested by the last resize
+    pub fn resize_size(&self) -> (u32, u32) {
+        self.resize_size
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_resize_events() {
+        let mut window = Window::new();
+        window.notify_resize(800, 600);
+        assert!(!window.take_resize()); // ignored while _RESIZE is off
+
+        window.set_resize(true, ResizeMode::Smooth);
+        window.notify_resize(1024, 768);
+        assert!(window.take_resize());
+        assert!(!window.take_resize());
+        assert_eq!(window.resize_size(), (1024, 768));
+    }
+}
@@ -5,5 +5,5 @@
 pub mod scanner;
 pub mod tokens;
 
-pub use scanner::{Scanner, tokenize, CharStream};
-pub use tokens::{Token, TokenInfo, string_to_keyword};
+pub use scanner::{Scanner, tokenize, tokenize_dialect, tokenize_recovering, try_tokenize, decode, CharStream};
+pub use tokens::{Token, TokenInfo, KEYWORDS, is_qb64_keyword, string_to_keyword};
@@ -1,5 +1,7 @@
-use crate::tokens::{Token, TokenInfo, string_to_keyword};
+use crate::tokens::{Token, TokenInfo, is_qb64_keyword, string_to_keyword};
+use qb_core::{cp437, Dialect};
 use qb_core::errors::{QError, QResult};
+use std::borrow::Cow;
 
 /// Character stream for lexical analysis
 pub struct CharStream {
@@ -73,10 +75,10 @@ impl CharStream {
         }
     }
 
+    /// Skip to the end of the line, leaving the newline itself unread
     pub fn skip_line(&mut self) {
         while let Some(c) = self.peek() {
             if c == '\n' {
-                self.advance();
                 break;
             }
             self.advance();
@@ -88,16 +90,35 @@ impl CharStream {
 pub struct Scanner {
     stream: CharStream,
     tokens: Vec<TokenInfo>,
+    /// Under QB 4.5, QB64's keywords are ordinary identifiers
+    dialect: Dialect,
+    /// Extension: `\xNN` in a string literal is the character with code NN
+    string_escapes: bool,
 }
 
 impl Scanner {
+    /// Scanner recognizing every keyword, as QB64 does
     pub fn new(source: &str) -> Self {
+        Self::for_dialect(source, Dialect::Qb64)
+    }
+
+    pub fn for_dialect(source: &str, dialect: Dialect) -> Self {
         Self {
             stream: CharStream::new(source),
             tokens: Vec::new(),
+            dialect,
+            string_escapes: false,
         }
     }
 
+    /// Read `\xNN` in string literals as the character with hex code NN,
+    /// mapped as CHR$ maps it. A backslash followed by anything else is
+    /// itself.
+    pub fn with_string_escapes(mut self, on: bool) -> Self {
+        self.string_escapes = on;
+        self
+    }
+
     pub fn scan_tokens(mut self) -> QResult<Vec<TokenInfo>> {
         while !self.stream.is_at_end() {
             self.scan_token()?;
@@ -111,12 +132,33 @@ impl Scanner {
         Ok(self.tokens)
     }
 
+    /// Scan the whole source, skipping past lexical errors instead of
+    /// stopping at the first, and return the tokens with every error found
+    pub fn scan_tokens_recovering(mut self) -> (Vec<TokenInfo>, Vec<QError>) {
+        let mut errors = Vec::new();
+        while !self.stream.is_at_end() {
+            self.stream.skip_whitespace();
+            let position = self.stream.position();
+            if let Err(e) = self.scan_token() {
+                errors.push(e);
+                if self.stream.position() == position {
+                    self.stream.advance();
+                }
+            }
+        }
+
+        let line = self.stream.line();
+        let col = self.stream.column();
+        self.tokens.push(TokenInfo::new(Token::EOF, line, col, 0));
+
+        (self.tokens, errors)
+    }
+
     fn scan_token(&mut self) -> QResult<()> {
+        self.stream.skip_whitespace();
         let start_line = self.stream.line();
         let start_col = self.stream.column();
         
-        self.stream.skip_whitespace();
-        
         if self.stream.is_at_end() {
             return Ok(());
         }
@@ -127,12 +169,8 @@ impl Scanner {
         match c {
             // Comments
             '\'' => {
-                self.stream.skip_line();
-                // Skip newline if present
-                if self.stream.peek() == Some('\n') {
-                    self.add_token(Token::NewLine, start_line, start_col, 1);
-                    self.stream.advance();
-                }
+                self.stream.advance();
+                self.scan_comment(start_line, start_col, 1);
             }
             
             // Newlines
@@ -314,6 +352,8 @@ impl Scanner {
                     self.stream.line(),
                     self.stream.column()
                 ));
+            } else if let Some(escaped) = self.string_escape() {
+                value.push(escaped);
             } else {
                 value.push(c);
                 self.stream.advance();
@@ -325,6 +365,22 @@ impl Scanner {
         Ok(())
     }
 
+    /// The character a `\xNN` escape at the cursor stands for, consuming it
+    fn string_escape(&mut self) -> Option<char> {
+        if !self.string_escapes || self.stream.peek() != Some('\\') {
+            return None;
+        }
+        let start = self.stream.position();
+        let text: String = self.stream.source.get(start + 1..start + 4)?.iter().collect();
+        let hex = text.strip_prefix(['x', 'X']).filter(|hex| hex.chars().all(|c| c.is_ascii_hexdigit()))?;
+        let code = u8::from_str_radix(hex, 16).ok()?;
+        let c = self.dialect.chr(i32::from(code))?;
+        for _ in 0..4 {
+            self.stream.advance();
+        }
+        Some(c)
+    }
+
     fn scan_number(&mut self, line: usize, col: usize) -> QResult<()> {
         let start_pos = self.stream.position();
         let mut has_decimal = false;
@@ -421,13 +477,16 @@ impl Scanner {
         let num_str: String = self.stream.source[start_pos..self.stream.position()]
             .iter().collect();
 
-        // Determine type based on format
+        // Determine type based on format: a D exponent or more than 7
+        // digits make a DOUBLE, other floating-point literals are SINGLE
         if has_decimal || has_exponent || is_double {
-            let value: f64 = num_str.parse().map_err(|_| {
+            let value: f64 = num_str.replace(['D', 'd'], "E").parse().map_err(|_| {
                 QError::compile("Invalid floating point literal", line, col)
             })?;
-            self.add_token(Token::Double(value), line, col, 
-                self.stream.position() - start_pos);
+            let mantissa = num_str.split(['E', 'e', 'D', 'd']).next().unwrap_or("");
+            let digits = mantissa.trim_start_matches(['0', '.']).chars().filter(char::is_ascii_digit).count();
+            let token = if is_double || digits > 7 { Token::Double(value) } else { Token::Single(value as f32) };
+            self.add_token(token, line, col, self.stream.position() - start_pos);
         } else {
             // Try as integer first
             if let Ok(val) = num_str.parse::<i16>() {
@@ -476,24 +535,7 @@ impl Scanner {
                     QError::compile("Invalid hexadecimal literal", line, col)
                 })?;
 
-                // Check for type suffix
-                if let Some(c) = self.stream.peek() {
-                    if c == '&' {
-                        self.stream.advance();
-                        self.add_token(Token::Long(value), line, col, 
-                            self.stream.position() - start_pos);
-                        return Ok(());
-                    }
-                }
-
-                // Default to integer if fits, otherwise long
-                if value >= i16::MIN as i64 && value <= i16::MAX as i64 {
-                    self.add_token(Token::Integer(value as i32), line, col, 
-                        self.stream.position() - start_pos);
-                } else {
-                    self.add_token(Token::Long(value), line, col, 
-                        self.stream.position() - start_pos);
-                }
+                self.add_radix_literal(value, line, col, start_pos)?;
             }
             Some('O') | Some('o') => {
                 // Octal
@@ -514,24 +556,7 @@ impl Scanner {
                     QError::compile("Invalid octal literal", line, col)
                 })?;
 
-                // Check for type suffix
-                if let Some(c) = self.stream.peek() {
-                    if c == '&' {
-                        self.stream.advance();
-                        self.add_token(Token::Long(value), line, col, 
-                            self.stream.position() - start_pos);
-                        return Ok(());
-                    }
-                }
-
-                // Default to integer if fits, otherwise long
-                if value >= i16::MIN as i64 && value <= i16::MAX as i64 {
-                    self.add_token(Token::Integer(value as i32), line, col, 
-                        self.stream.position() - start_pos);
-                } else {
-                    self.add_token(Token::Long(value), line, col, 
-                        self.stream.position() - start_pos);
-                }
+                self.add_radix_literal(value, line, col, start_pos)?;
             }
             _ => {
                 // Just an & (bitwise AND will be handled differently)
@@ -542,6 +567,25 @@ impl Scanner {
         Ok(())
     }
 
+    /// Add a &H or &O literal. As in QBasic, the digits are a bit pattern:
+    /// up to 16 bits make an INTEGER and up to 32 bits (or a & suffix) a LONG,
+    /// so &HFFFF is -1 and &HFF000000 is negative.
+    fn add_radix_literal(&mut self, value: i64, line: usize, col: usize, start_pos: usize) -> QResult<()> {
+        let long_suffix = self.stream.peek() == Some('&');
+        if long_suffix {
+            self.stream.advance();
+        }
+        let token = if value > u32::MAX as i64 {
+            return Err(QError::compile("Overflow", line, col));
+        } else if value > u16::MAX as i64 || long_suffix {
+            Token::Long(value as u32 as i32 as i64)
+        } else {
+            Token::Integer(value as u16 as i16 as i32)
+        };
+        self.add_token(token, line, col, self.stream.position() - start_pos);
+        Ok(())
+    }
+
     fn scan_identifier(&mut self, line: usize, col: usize) -> QResult<()> {
         let start_pos = self.stream.position();
         
@@ -572,11 +616,7 @@ impl Scanner {
 
         // Check for REM comment (special handling)
         if ident_str == "REM" {
-            self.stream.skip_line();
-            if self.stream.peek() == Some('\n') {
-                self.add_token(Token::NewLine, line, col, 1);
-                self.stream.advance();
-            }
+            self.scan_comment(line, col, 3);
             return Ok(());
         }
 
@@ -599,30 +639,23 @@ impl Scanner {
 
         // Check for LINE INPUT (special keyword)
         if ident_str == "LINE" {
+            // Only INPUT joins it; any other word (LINE STEP(...)) is left to be read
             self.stream.skip_whitespace();
-            if let Some(c) = self.stream.peek() {
-                if c.is_ascii_alphabetic() {
-                    let next_start = self.stream.position();
-                    while let Some(c2) = self.stream.peek() {
-                        if c2.is_ascii_alphabetic() {
-                            self.stream.advance();
-                        } else {
-                            break;
-                        }
-                    }
-                    let next_str: String = self.stream.source[next_start..self.stream.position()]
-                        .iter().collect::<String>().to_uppercase();
-                    if next_str == "INPUT" {
-                        self.add_token(Token::LineInput, line, col, 
-                            self.stream.position() - start_pos);
-                        return Ok(());
-                    }
+            let next_word: String = self.stream.source[self.stream.position()..]
+                .iter()
+                .take_while(|c| c.is_ascii_alphabetic())
+                .collect();
+            if next_word.eq_ignore_ascii_case("INPUT") {
+                for _ in 0..next_word.len() {
+                    self.stream.advance();
                 }
+                self.add_token(Token::LineInput, line, col, self.stream.position() - start_pos);
+                return Ok(());
             }
         }
 
         // Check for _UNSIGNED variants (QB64)
-        if ident_str == "_UNSIGNED" {
+        if ident_str == "_UNSIGNED" && self.dialect == Dialect::Qb64 {
             self.stream.skip_whitespace();
             if let Some(c) = self.stream.peek() {
                 if c.is_ascii_alphabetic() || c == '_' {
@@ -668,7 +701,9 @@ impl Scanner {
         }
 
         // Try to match as keyword
-        if let Some(keyword) = string_to_keyword(&ident_str) {
+        let keyword = string_to_keyword(&ident_str)
+            .filter(|_| self.dialect == Dialect::Qb64 || !is_qb64_keyword(&ident_str));
+        if let Some(keyword) = keyword {
             let token = match (&keyword, suffix) {
                 (Token::DefInt, _) | (Token::DefLng, _) | (Token::DefSng, _) |
                 (Token::DefDbl, _) | (Token::DefStr, _) => {
@@ -682,6 +717,7 @@ impl Scanner {
             };
             
             // Check for INPUT#, PRINT#, WRITE# (file I/O)
+            let word_end = self.stream.position();
             let final_token = match token {
                 Token::Input | Token::Print | Token::Write => {
                     self.stream.skip_whitespace();
@@ -700,7 +736,11 @@ impl Scanner {
                 _ => token,
             };
             
-            self.add_token(final_token, line, col, self.stream.position() - start_pos);
+            let end = match final_token {
+                Token::InputHash | Token::PrintHash | Token::WriteHash => self.stream.position(),
+                _ => word_end,
+            };
+            self.add_token(final_token, line, col, end - start_pos);
         } else {
             // It's an identifier
             let name = ident_str;
@@ -715,6 +755,24 @@ impl Scanner {
         self.tokens.push(TokenInfo::new(token, line, col, length));
     }
 
+    /// The rest of a ' or REM comment, `length` the marker's, as Rem and
+    /// its text. A comment after a statement goes on a line of its own, so
+    /// the statement still ends where the comment starts.
+    fn scan_comment(&mut self, line: usize, col: usize, length: usize) {
+        let start = self.stream.position();
+        self.stream.skip_line();
+        let text: String = self.stream.source[start..self.stream.position()].iter().collect();
+        if !matches!(self.tokens.last().map(|t| &t.token), None | Some(Token::NewLine | Token::Colon)) {
+            self.add_token(Token::NewLine, line, col, 0);
+        }
+        self.add_token(Token::Rem, line, col, length);
+        self.add_token(Token::String(text.trim().to_string()), line, col + length, self.stream.position() - start);
+        if self.stream.peek() == Some('\n') {
+            self.add_token(Token::NewLine, self.stream.line(), self.stream.column(), 1);
+            self.stream.advance();
+        }
+    }
+
     fn scan_metacommand(&mut self, line: usize, col: usize) -> QResult<()> {
         let start_pos = self.stream.position();
         self.stream.advance(); // Skip $
@@ -783,10 +841,83 @@ pub fn tokenize(source: &str) -> QResult<Vec<TokenInfo>> {
     scanner.scan_tokens()
 }
 
+/// The text of a source file: UTF-8 if it is valid UTF-8, and otherwise
+/// code page 437, as QBasic saved it
+pub fn decode(bytes: &[u8]) -> Cow<'_, str> {
+    match std::str::from_utf8(bytes) {
+        Ok(text) => Cow::Borrowed(text),
+        Err(_) => Cow::Owned(bytes.iter().map(|&byte| cp437::to_char(byte)).collect()),
+    }
+}
+
+/// Tokenize the bytes of a source file, which may be anything at all:
+/// whatever they hold, the result is an error and never a panic
+pub fn try_tokenize(bytes: &[u8]) -> QResult<Vec<TokenInfo>> {
+    tokenize(&decode(bytes))
+}
+
+/// Tokenize source code written for `dialect`
+pub fn tokenize_dialect(source: &str, dialect: Dialect) -> QResult<Vec<TokenInfo>> {
+    Scanner::for_dialect(source, dialect).scan_tokens()
+}
+
+/// Tokenize source code, collecting every lexical error instead of
+/// stopping at the first
+pub fn tokenize_recovering(source: &str) -> (Vec<TokenInfo>, Vec<QError>) {
+    Scanner::new(source).scan_tokens_recovering()
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
 
+    #[test]
+    fn test_recovering_lists_every_error() {
+        let (tokens, errors) = tokenize_recovering("PRINT x ? 2 ~ 3\na$ = \"open\nEND");
+        assert_eq!(errors.len(), 3);
+        assert!(matches!(errors[0], QError::Compile { line: 1, column: 9, .. }));
+        // Spans cover the token text, not the blanks around it
+        assert_eq!((tokens[0].column, tokens[0].length), (1, 5));
+        assert_eq!((tokens[1].column, tokens[1].length), (7, 1));
+        assert!(matches!(tokens.iter().rev().nth(1).unwrap().token, Token::End));
+    }
+
+    #[test]
+    fn test_qb64_keywords_are_identifiers_in_qb45() {
+        let tokens = |source: &str, dialect| -> Vec<Token> {
+            tokenize_dialect(source, dialect).unwrap().into_iter().map(|t| t.token).collect()
+        };
+        let qb45 = tokens("_title = 1: trim$ = \"x\": WIDTH 40", Dialect::Qb45);
+        assert!(matches!(qb45[0], Token::Identifier(ref name) if name == "_TITLE"));
+        assert!(matches!(qb45[4], Token::Identifier(ref name) if name == "TRIM$"));
+        assert!(qb45.contains(&Token::Width));
+        let qb64 = tokens("_title = 1: trim$ = \"x\": WIDTH _WIDTH", Dialect::Qb64);
+        assert_eq!(qb64[0], Token::Title);
+        assert_eq!(qb64[4], Token::Trim);
+        assert_eq!(qb64[8..10], [Token::Width, Token::QB64Width]);
+    }
+
+    #[test]
+    fn test_string_escapes() {
+        let literal = |scanner: Scanner| match scanner.scan_tokens().unwrap().remove(0).token {
+            Token::String(s) => s,
+            other => panic!("expected a string, got {:?}", other),
+        };
+        let source = r#""\x1B[0m\xc9 C:\DOS\x4""#;
+        assert_eq!(literal(Scanner::new(source)), r"\x1B[0m\xc9 C:\DOS\x4");
+        assert_eq!(literal(Scanner::for_dialect(source, Dialect::Qb45).with_string_escapes(true)), "\u{1b}[0m╔ C:\\DOS\\x4");
+        assert_eq!(literal(Scanner::for_dialect(source, Dialect::Qb64).with_string_escapes(true)), "\u{1b}[0mÉ C:\\DOS\\x4");
+    }
+
+    #[test]
+    fn test_builtin_keywords_are_registered() {
+        for (spelling, token) in crate::tokens::KEYWORDS {
+            if let Some(name) = token.as_builtin_function_name() {
+                assert!(qb_core::builtins::lookup(name).is_some(), "{} calls unknown builtin {}", spelling, name);
+            }
+        }
+    }
+
     #[test]
     fn test_simple_tokens() {
         let source = "PRINT \"Hello World\"";
@@ -798,13 +929,30 @@ mod tests {
 
     #[test]
     fn test_numbers() {
-        let source = "10 3.14 &HFF &O77";
+        let source = "10 3.14 &HFF &O77 3.141592653 1D-3 .0000125";
         let tokens = tokenize(source).unwrap();
         assert!(matches!(tokens[0].token, Token::Integer(10)));
         // Allow approx_constant for test clarity
         #[allow(clippy::approx_constant)]
         let expected = 3.14;
-        assert!(matches!(tokens[1].token, Token::Double(d) if (d - expected).abs() < 0.001));
+        assert!(matches!(tokens[1].token, Token::Single(d) if (d - expected).abs() < 0.001));
+        assert!(matches!(tokens[2].token, Token::Integer(255)));
+        assert!(matches!(tokens[3].token, Token::Integer(63)));
+        // More than 7 digits, or a D exponent, make a DOUBLE
+        assert!(matches!(tokens[4].token, Token::Double(_)));
+        assert!(matches!(tokens[5].token, Token::Double(d) if d == 0.001));
+        assert!(matches!(tokens[6].token, Token::Single(_)));
+    }
+
+    #[test]
+    fn test_radix_literals_wrap() {
+        let tokens = tokenize("&HFFFF &H10000 &HFF000000 &HFF& &O177777").unwrap();
+        assert!(matches!(tokens[0].token, Token::Integer(-1)));
+        assert!(matches!(tokens[1].token, Token::Long(65536)));
+        assert!(matches!(tokens[2].token, Token::Long(-16777216)));
+        assert!(matches!(tokens[3].token, Token::Long(255)));
+        assert!(matches!(tokens[4].token, Token::Integer(-1)));
+        assert!(tokenize("&H100000000").is_err());
     }
 
     #[test]
@@ -813,8 +961,12 @@ mod tests {
         let tokens = tokenize(source).unwrap();
         assert!(matches!(tokens[0].token, Token::Print));
         assert!(matches!(tokens[1].token, Token::Integer(1)));
-        // After comment parsing, the newline is consumed
-        assert!(matches!(tokens[2].token, Token::Print));
-        assert!(matches!(tokens[3].token, Token::Integer(2)));
+        // The comment goes on a line of its own after the statement
+        assert!(matches!(tokens[2].token, Token::NewLine));
+        assert!(matches!(tokens[3].token, Token::Rem));
+        assert!(matches!(&tokens[4].token, Token::String(text) if text == "This is a comment"));
+        assert!(matches!(tokens[5].token, Token::NewLine));
+        assert!(matches!(tokens[6].token, Token::Print));
+        assert!(matches!(tokens[7].token, Token::Integer(2)));
     }
 }
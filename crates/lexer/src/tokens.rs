@@ -1,3 +1,5 @@
+use std::fmt;
+
 /// Token types for QBasic lexical analysis
 #[derive(Debug, Clone, PartialEq)]
 pub enum Token {
@@ -20,6 +22,7 @@ pub enum Token {
     Const,                  // Constant declaration
     Dim,                    // Variable declaration
     Redim,                  // Redimension array
+    Erase,                  // Clear or free arrays
     Shared,                 // Shared variable
     Common,                 // Common variable
     Static,                 // Static variable
@@ -67,11 +70,13 @@ pub enum Token {
     
     // I/O
     Print,                  // Print statement
+    LPrint,                 // Print to the printer
     Input,                  // Input statement
     LineInput,              // Line input statement
     Write,                  // Write statement
     Open,                   // Open file
     Close,                  // Close file
+    Kill,                   // Delete files
     Output,                 // OPEN FOR OUTPUT
     Append,                 // OPEN FOR APPEND
     Random,                 // OPEN FOR RANDOM
@@ -81,6 +86,7 @@ pub enum Token {
     Seek,                   // Seek position
     Lock,                   // Lock file
     Unlock,                 // Unlock file
+    Access,                 // ACCESS clause of OPEN
     InputHash,              // Input #
     PrintHash,              // Print #
     WriteHash,              // Write #
@@ -105,6 +111,7 @@ pub enum Token {
     Beep,                   // Beep
     Sound,                  // Sound
     Play,                   // Play music
+    Sleep,                  // Wait for seconds or a key
     
     // Memory & System
     Poke,                   // Write to memory
@@ -114,6 +121,7 @@ pub enum Token {
     Wait,                   // Wait for port
     DefSeg,                 // Define segment
     VarPtr,                 // Get variable pointer
+    VarPtrString,           // VARPTR$: a variable reference for DRAW and PLAY
     VarSeg,                 // Get variable segment
     
     // Error handling
@@ -132,9 +140,11 @@ pub enum Token {
     // Environment
     Environ,                // Environment variable
     Shell,                  // Execute shell command
+    Chain,                  // Run another program
     System,                 // Exit to system
     End,                    // End program
     Stop,                   // Stop execution
+    Clear,                  // Reset variables and close files
     
     // Operators
     Plus,                   // +
@@ -174,7 +184,7 @@ pub enum Token {
     Abs, Atn, Cos, Exp, Fix, Int, Log, Randomize, Rnd, Sgn, Sin, Sqr, Tan,
     
     // Built-in functions (string)
-    Asc, Chr, Cvi, Cvs, Cvd, InStr, Left, LenFunc, LSet, Mid, 
+    Asc, Chr, Cvi, Cvl, Cvs, Cvd, InStr, Left, LenFunc, LSet, Mid, 
     MkD, MkI, MkL, MkS, Oct, Right, RSet, Space, Str, StringFunc,
     Trim, LTrim, RTrim, UCase, LCase, InKey, 
     
@@ -191,6 +201,9 @@ pub enum Token {
     Command, Dir, FileAttr, FileDateTime, FileLen, 
     GetAttr, InputFunc, IOStat, LBound, UBound,
     Saddle, SAdd,
+
+    // Built-in functions (devices)
+    Stick, Strig,
     
     // Type suffixes
     IntegerSuffix,          // %
@@ -248,6 +261,7 @@ pub enum Token {
     // QB64 Graphics commands
     NewImage,               // _NEWIMAGE
     LoadImage,              // _LOADIMAGE
+    SaveImage,              // _SAVEIMAGE
     PutImage,               // _PUTIMAGE
     GetImage,               // _GETIMAGE
     ScreenImage,            // _SCREENIMAGE
@@ -257,12 +271,37 @@ pub enum Token {
     Green,                  // _GREEN
     Blue,                   // _BLUE
     Alpha,                  // _ALPHA
+    RGB32,                  // _RGB32
+    RGBA32,                 // _RGBA32
+    Red32,                  // _RED32
+    Green32,                // _GREEN32
+    Blue32,                 // _BLUE32
+    Alpha32,                // _ALPHA32
     
     // QB64 Sound commands
     SndOpen,                // _SNDOPEN
     SndPlay,                // _SNDPLAY
     SndLoop,                // _SNDLOOP
     SndClose,               // _SNDCLOSE
+    SndStop,                // _SNDSTOP
+    SndVol,                 // _SNDVOL
+    SndPlaying,             // _SNDPLAYING
+    SndRaw,                 // _SNDRAW
+    SndRate,                // _SNDRATE
+    SndRawLen,              // _SNDRAWLEN
+    
+    // QB64 Networking
+    OpenHost,               // _OPENHOST
+    OpenClient,             // _OPENCLIENT
+    OpenConnection,         // _OPENCONNECTION
+    Connected,              // _CONNECTED
+    ConnectionAddress,      // _CONNECTIONADDRESS$
+
+    // QB64-style reflection
+    SourceLine,             // _SOURCELINE
+    SourceLineText,         // _SOURCELINE$
+    CallDepth,              // _CALLDEPTH
+    ProgramName,            // _PROGRAMNAME$
     
     // QB64 Input/Events
     MouseInput,             // _MOUSEINPUT
@@ -272,19 +311,47 @@ pub enum Token {
     MouseWheel,             // _MOUSEWHEEL
     KeyHit,                 // _KEYHIT
     KeyClear,               // _KEYCLEAR
+    Devices,                // _DEVICES
+    DeviceName,             // _DEVICE$
+    DeviceInput,            // _DEVICEINPUT
+    Button,                 // _BUTTON
+    Axis,                   // _AXIS
+    LastButton,             // _LASTBUTTON
+    LastAxis,               // _LASTAXIS
     // QB64 Screen/Window
     Resize,                 // _RESIZE
     QB64Width,              // _WIDTH
     Height,                 // _HEIGHT
     Font,                   // _FONT
     PrintString,            // _PRINTSTRING
+    PrintWidth,             // _PRINTWIDTH
+    Title,                  // _TITLE
+    ScreenMove,             // _SCREENMOVE
+    Middle,                 // _MIDDLE
+    ResizeWidth,            // _RESIZEWIDTH
+    ResizeHeight,           // _RESIZEHEIGHT
+    Stretch,                // _STRETCH
+    Smooth,                 // _SMOOTH
+    
+    // QB64 Memory
+    MemType,                // _MEM
+    DictType,               // _DICT
+    MemNew,                 // _MEMNEW
+    MemImage,               // _MEMIMAGE
+    MemGet,                 // _MEMGET
+    MemPut,                 // _MEMPUT
+    MemFree,                // _MEMFREE
     
     // QB64 Math/Other
     Define,                 // _DEFINE
     Preserve,               // _PRESERVE
+    Mat,                    // MAT (whole-array extension)
     FreeImage,              // _FREEIMAGE
     CopyImage,              // _COPYIMAGE
+    Dest,                   // _DEST
+    Source,                 // _SOURCE
     Limit,                  // _LIMIT
+    Timer64,                // _TIMER64
     Display,                // _DISPLAY
     AutoDisplay,            // _AUTODISPLAY
     FullScreen,             // _FULLSCREEN
@@ -292,6 +359,7 @@ pub enum Token {
     Console,                // _CONSOLE
     ScreenShow,             // _SCREENSHOW
     ScreenHide,             // _SCREENHIDE
+    InStrRev,               // _INSTRREV
 }
 
 impl Token {
@@ -325,20 +393,20 @@ impl Token {
     /// Check if token is a statement keyword
     pub fn is_statement(&self) -> bool {
         matches!(self,
-            Token::Rem | Token::Let | Token::Const | Token::Dim | Token::Redim |
+            Token::Rem | Token::Let | Token::Const | Token::Dim | Token::Redim | Token::Erase |
             Token::Shared | Token::Common | Token::Static | Token::Type |
             Token::If | Token::Select | Token::For | Token::While | Token::Do |
             Token::GoTo | Token::GoSub | Token::On | Token::Sub | Token::Function |
-            Token::Declare | Token::Call | Token::Exit | Token::Print | Token::Input |
-            Token::LineInput | Token::Write | Token::Open | Token::Close |
+            Token::Declare | Token::Call | Token::Exit | Token::Print | Token::LPrint | Token::Input |
+            Token::LineInput | Token::Write | Token::Open | Token::Close | Token::Kill |
             Token::Get | Token::Put | Token::Seek | Token::Lock | Token::Unlock |
             Token::Screen | Token::PSet | Token::PReset | Token::Line | Token::Circle |
             Token::Draw | Token::Paint | Token::View | Token::Window | Token::Palette |
             Token::Color | Token::Cls | Token::Locate | Token::Width |
-            Token::Beep | Token::Sound | Token::Play | Token::Poke | Token::Wait |
+            Token::Beep | Token::Sound | Token::Play | Token::Sleep | Token::Poke | Token::Wait |
             Token::DefSeg | Token::Data | Token::Read | Token::Restore |
-            Token::Environ | Token::Shell | Token::System | Token::End | Token::Stop |
-            Token::Resume | Token::Error
+            Token::Environ | Token::Shell | Token::Chain | Token::System | Token::End | Token::Stop | Token::Clear |
+            Token::Resume | Token::Error | Token::Strig
         )
     }
 
@@ -361,7 +429,7 @@ impl Token {
         }
     }
 
-    /// Get the string name if token is a builtin function
+    /// Name in `qb_core::builtins` of the function this keyword calls
     pub fn as_builtin_function_name(&self) -> Option<&'static str> {
         match self {
             Token::Abs => Some("ABS"),
@@ -378,6 +446,14 @@ impl Token {
             Token::Tan => Some("TAN"),
             Token::Asc => Some("ASC"),
             Token::Chr => Some("CHR$"),
+            Token::Cvi => Some("CVI"),
+            Token::Cvl => Some("CVL"),
+            Token::Cvs => Some("CVS"),
+            Token::Cvd => Some("CVD"),
+            Token::MkI => Some("MKI$"),
+            Token::MkL => Some("MKL$"),
+            Token::MkS => Some("MKS$"),
+            Token::MkD => Some("MKD$"),
             Token::Left => Some("LEFT$"),
             Token::Len | Token::LenFunc => Some("LEN"),
             Token::Mid => Some("MID$"),
@@ -398,299 +474,500 @@ impl Token {
             Token::Space => Some("SPACE$"),
             Token::StringFunc => Some("STRING$"),
             Token::Timer => Some("TIMER"),
+            Token::Date => Some("DATE$"),
+            Token::Time => Some("TIME$"),
+            Token::Timer64 => Some("_TIMER64"),
+            Token::Err => Some("ERR"),
+            Token::ERL => Some("ERL"),
+            Token::InKey => Some("INKEY$"),
+            Token::Peek => Some("PEEK"),
+            Token::VarPtrString => Some("VARPTR$"),
+            Token::Eof => Some("EOF"),
+            Token::Lof => Some("LOF"),
+            Token::Loc => Some("LOC"),
+            Token::LBound => Some("LBOUND"),
+            Token::UBound => Some("UBOUND"),
+            Token::FreeFile => Some("FREEFILE"),
+            Token::Command => Some("COMMAND$"),
+            Token::Play => Some("PLAY"),
+            Token::Stick => Some("STICK"),
+            Token::Strig => Some("STRIG"),
+            Token::Devices => Some("_DEVICES"),
+            Token::DeviceName => Some("_DEVICE$"),
+            Token::DeviceInput => Some("_DEVICEINPUT"),
+            Token::Button => Some("_BUTTON"),
+            Token::Axis => Some("_AXIS"),
+            Token::LastButton => Some("_LASTBUTTON"),
+            Token::LastAxis => Some("_LASTAXIS"),
+            Token::Resize => Some("_RESIZE"),
+            Token::FullScreen => Some("_FULLSCREEN"),
+            Token::SndOpen => Some("_SNDOPEN"),
+            Token::SndPlaying => Some("_SNDPLAYING"),
+            Token::SndRate => Some("_SNDRATE"),
+            Token::SndRawLen => Some("_SNDRAWLEN"),
+            Token::ResizeWidth => Some("_RESIZEWIDTH"),
+            Token::ResizeHeight => Some("_RESIZEHEIGHT"),
+            Token::NewImage => Some("_NEWIMAGE"),
+            Token::LoadImage => Some("_LOADIMAGE"),
+            Token::CopyImage => Some("_COPYIMAGE"),
+            Token::QB64Width => Some("_WIDTH"),
+            Token::Height => Some("_HEIGHT"),
+            Token::Dest => Some("_DEST"),
+            Token::Source => Some("_SOURCE"),
+            Token::RGB => Some("_RGB"),
+            Token::RGBA => Some("_RGBA"),
+            Token::Red => Some("_RED"),
+            Token::Green => Some("_GREEN"),
+            Token::Blue => Some("_BLUE"),
+            Token::Alpha => Some("_ALPHA"),
+            Token::RGB32 => Some("_RGB32"),
+            Token::RGBA32 => Some("_RGBA32"),
+            Token::Red32 => Some("_RED32"),
+            Token::Green32 => Some("_GREEN32"),
+            Token::Blue32 => Some("_BLUE32"),
+            Token::Alpha32 => Some("_ALPHA32"),
+            Token::PrintWidth => Some("_PRINTWIDTH"),
+            Token::MemNew => Some("_MEMNEW"),
+            Token::MemImage => Some("_MEMIMAGE"),
+            Token::Console => Some("_CONSOLE"),
+            Token::InStrRev => Some("_INSTRREV"),
+            Token::OpenHost => Some("_OPENHOST"),
+            Token::OpenClient => Some("_OPENCLIENT"),
+            Token::OpenConnection => Some("_OPENCONNECTION"),
+            Token::Connected => Some("_CONNECTED"),
+            Token::ConnectionAddress => Some("_CONNECTIONADDRESS$"),
+            Token::SourceLine => Some("_SOURCELINE"),
+            Token::SourceLineText => Some("_SOURCELINE$"),
+            Token::CallDepth => Some("_CALLDEPTH"),
+            Token::ProgramName => Some("_PROGRAMNAME$"),
             // Can be expanded as needed
             _ => None,
         }
     }
 }
 
+/// Tokens display as they are spelled in source, for error messages
+impl fmt::Display for Token {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let symbol = match self {
+            Token::Integer(n) => return write!(f, "{}", n),
+            Token::Long(n) => return write!(f, "{}", n),
+            Token::Single(n) => return write!(f, "{}", n),
+            Token::Double(n) => return write!(f, "{}", n),
+            Token::String(text) => return write!(f, "\"{}\"", text),
+            Token::Identifier(name) | Token::Label(name) => return f.write_str(name),
+            Token::LineNumber(n) => return write!(f, "{}", n),
+            Token::Plus | Token::Concat => "+",
+            Token::Minus => "-",
+            Token::Multiply => "*",
+            Token::Divide => "/",
+            Token::IntDivide => "\\",
+            Token::Power => "^",
+            Token::Equal => "=",
+            Token::NotEqual => "<>",
+            Token::Less => "<",
+            Token::LessEqual => "<=",
+            Token::Greater => ">",
+            Token::GreaterEqual => ">=",
+            Token::IntegerSuffix => "%",
+            Token::LongSuffix => "&",
+            Token::SingleSuffix => "!",
+            Token::DoubleSuffix | Token::Hash => "#",
+            Token::StringSuffix => "$",
+            Token::LParen => "(",
+            Token::RParen => ")",
+            Token::LBracket => "[",
+            Token::RBracket => "]",
+            Token::LBrace => "{",
+            Token::RBrace => "}",
+            Token::Comma => ",",
+            Token::Semicolon => ";",
+            Token::Colon => ":",
+            Token::Period => ".",
+            Token::Apostrophe => "'",
+            Token::Underscore => "_",
+            Token::NewLine => "end of line",
+            Token::EOF => "end of file",
+            token => match KEYWORDS.iter().find(|(_, keyword)| keyword == token) {
+                Some((spelling, _)) => spelling,
+                None => return write!(f, "{:?}", token),
+            },
+        };
+        f.write_str(symbol)
+    }
+}
+
+/// Keyword spellings and the tokens they map to
+pub const KEYWORDS: &[(&str, Token)] = &[
+    // Comments
+    ("REM", Token::Rem),
+
+    // Declaration
+    ("LET", Token::Let),
+    ("CONST", Token::Const),
+    ("DIM", Token::Dim),
+    ("REDIM", Token::Redim),
+    ("ERASE", Token::Erase),
+    ("SHARED", Token::Shared),
+    ("COMMON", Token::Common),
+    ("STATIC", Token::Static),
+    ("DEFINT", Token::DefInt),
+    ("DEFLNG", Token::DefLng),
+    ("DEFSNG", Token::DefSng),
+    ("DEFDBL", Token::DefDbl),
+    ("DEFSTR", Token::DefStr),
+
+    // Control flow
+    ("IF", Token::If),
+    ("THEN", Token::Then),
+    ("ELSE", Token::Else),
+    ("ELSEIF", Token::ElseIf),
+    ("ENDIF", Token::EndIf),
+    ("END", Token::End),
+    ("SELECT", Token::Select),
+    ("CASE", Token::Case),
+    ("CASEIS", Token::CaseIs),
+    ("CASEELSE", Token::CaseElse),
+    ("ENDSELECT", Token::EndSelect),
+    ("FOR", Token::For),
+    ("TO", Token::To),
+    ("STEP", Token::Step),
+    ("NEXT", Token::Next),
+    ("WHILE", Token::While),
+    ("WEND", Token::Wend),
+    ("DO", Token::Do),
+    ("LOOP", Token::Loop),
+    ("UNTIL", Token::Until),
+    ("GOTO", Token::GoTo),
+    ("GOSUB", Token::GoSub),
+    ("RETURN", Token::Return),
+    ("ON", Token::On),
+
+    // Procedures
+    ("SUB", Token::Sub),
+    ("FUNCTION", Token::Function),
+    ("DECLARE", Token::Declare),
+    ("CALL", Token::Call),
+    ("EXIT", Token::Exit),
+
+    // I/O
+    ("PRINT", Token::Print),
+    ("LPRINT", Token::LPrint),
+    ("INPUT", Token::Input),
+    ("OUTPUT", Token::Output),
+    ("APPEND", Token::Append),
+    ("RANDOM", Token::Random),
+    ("BINARY", Token::Binary),
+
+    ("WRITE", Token::Write),
+    ("OPEN", Token::Open),
+    ("CLOSE", Token::Close),
+    ("KILL", Token::Kill),
+    ("GET", Token::Get),
+    ("PUT", Token::Put),
+    ("SEEK", Token::Seek),
+    ("LOCK", Token::Lock),
+    ("UNLOCK", Token::Unlock),
+    ("ACCESS", Token::Access),
+
+    // Graphics
+    ("SCREEN", Token::Screen),
+    ("PSET", Token::PSet),
+    ("PRESET", Token::PReset),
+    ("LINE", Token::Line),
+    ("CIRCLE", Token::Circle),
+    ("DRAW", Token::Draw),
+    ("PAINT", Token::Paint),
+    ("VIEW", Token::View),
+    ("WINDOW", Token::Window),
+    ("PALETTE", Token::Palette),
+    ("COLOR", Token::Color),
+    ("CLS", Token::Cls),
+    ("LOCATE", Token::Locate),
+    ("WIDTH", Token::Width),
+
+    // Sound
+    ("BEEP", Token::Beep),
+    ("SOUND", Token::Sound),
+    ("PLAY", Token::Play),
+    ("SLEEP", Token::Sleep),
+
+    // Joystick
+    ("STICK", Token::Stick),
+    ("STRIG", Token::Strig),
+
+    // Memory & System
+    ("POKE", Token::Poke),
+    ("PEEK", Token::Peek),
+    ("INP", Token::InP),
+    ("OUT", Token::Out),
+    ("WAIT", Token::Wait),
+    ("DEFSEG", Token::DefSeg),
+    ("VARPTR", Token::VarPtr),
+    ("VARPTR$", Token::VarPtrString),
+    ("VARSEG", Token::VarSeg),
+
+    // Error handling
+    ("ERROR", Token::Error),
+    ("RESUME", Token::Resume),
+    ("ERR", Token::Err),
+    ("ERL", Token::ERL),
+    ("STOP", Token::Stop),
+
+    // Data
+    ("DATA", Token::Data),
+    ("READ", Token::Read),
+    ("RESTORE", Token::Restore),
+
+    // Environment
+    ("ENVIRON", Token::Environ),
+    ("SHELL", Token::Shell),
+    ("CHAIN", Token::Chain),
+    ("SYSTEM", Token::System),
+    ("CLEAR", Token::Clear),
+
+    // Types
+    ("AS", Token::As),
+    ("IS", Token::Is),
+    ("TYPE", Token::Type),
+    ("LEN", Token::Len),
+    ("USING", Token::Using),
+
+    // Type keywords
+    ("INTEGER", Token::IntegerType),
+    ("LONG", Token::LongType),
+    ("SINGLE", Token::SingleType),
+    ("DOUBLE", Token::DoubleType),
+    ("STRING", Token::StringType),
+    ("VARIANT", Token::VariantType),
+    ("ANY", Token::AnyType),
+
+    // Logical operators
+    ("AND", Token::And),
+    ("OR", Token::Or),
+    ("XOR", Token::Xor),
+    ("NOT", Token::Not),
+    ("IMP", Token::Imp),
+    ("EQV", Token::Eqv),
+    ("MOD", Token::Modulo),
+
+    // Math functions
+    ("ABS", Token::Abs),
+    ("ATN", Token::Atn),
+    ("COS", Token::Cos),
+    ("EXP", Token::Exp),
+    ("FIX", Token::Fix),
+    ("INT", Token::Int),
+    ("LOG", Token::Log),
+    ("RANDOMIZE", Token::Randomize),
+    ("RND", Token::Rnd),
+    ("SGN", Token::Sgn),
+    ("SIN", Token::Sin),
+    ("SQR", Token::Sqr),
+    ("TAN", Token::Tan),
+
+    // String functions
+    ("ASC", Token::Asc),
+    ("CHR$", Token::Chr),
+    ("CVI", Token::Cvi),
+    ("CVL", Token::Cvl),
+    ("CVS", Token::Cvs),
+    ("CVD", Token::Cvd),
+    ("INSTR", Token::InStr),
+    ("LEFT$", Token::Left),
+    ("LSET", Token::LSet),
+    ("MID$", Token::Mid),
+    ("MKD$", Token::MkD),
+    ("MKI$", Token::MkI),
+    ("MKL$", Token::MkL),
+    ("MKS$", Token::MkS),
+    ("OCT$", Token::Oct),
+    ("RIGHT$", Token::Right),
+    ("RSET", Token::RSet),
+    ("SPACE$", Token::Space),
+    ("STR$", Token::Str),
+    ("STRING$", Token::StringFunc),
+    ("LCASE$", Token::LCase),
+    ("UCASE$", Token::UCase),
+    ("LTRIM$", Token::LTrim),
+    ("RTRIM$", Token::RTrim),
+    ("TRIM$", Token::Trim),
+    ("INKEY$", Token::InKey),
+
+    // Type conversion
+    ("CBOOL", Token::CBool),
+    ("CBYTE", Token::CByte),
+    ("CINT", Token::CInt),
+    ("CLNG", Token::CLng),
+    ("CSNG", Token::CSng),
+    ("CDBL", Token::CDbl),
+    ("CSTR", Token::CStr),
+    ("CDATE", Token::CDate),
+    ("CCUR", Token::CCur),
+    ("CVAR", Token::CVar),
+    ("CVERR", Token::CVErr),
+    ("VAL", Token::Val),
+
+    // Date/Time
+    ("DATE$", Token::Date),
+    ("TIME$", Token::Time),
+    ("TIMER", Token::Timer),
+
+    // File functions
+    ("EOF", Token::Eof),
+    ("LOF", Token::Lof),
+    ("LOC", Token::Loc),
+    ("FREEFILE", Token::FreeFile),
+
+    // Other functions
+    ("COMMAND$", Token::Command),
+    ("DIR$", Token::Dir),
+    ("INPUT$", Token::InputFunc),
+    ("LBOUND", Token::LBound),
+    ("UBOUND", Token::UBound),
+    ("SADD", Token::SAdd),
+    ("SADDLE", Token::Saddle),
+
+    // QB64 Extended types
+    ("_INTEGER64", Token::Integer64Type),
+    ("_UNSIGNED", Token::UnsignedIntegerType),
+    ("_FLOAT", Token::FloatType),
+
+    // QB64 Metacommands
+    ("$DYNAMIC", Token::MetaDynamic),
+    ("$STATIC", Token::MetaStatic),
+    ("$INCLUDE", Token::MetaInclude),
+    ("$IF", Token::MetaIf),
+    ("$ELSE", Token::MetaElse),
+    ("$END", Token::MetaEndIf),
+    ("$RESIZE", Token::MetaResize),
+    ("$CONSOLE", Token::MetaConsole),
+    ("$SCREENSHOW", Token::MetaScreenShow),
+    ("$SCREENHIDE", Token::ScreenHide),
+
+    // QB64 Graphics
+    ("_NEWIMAGE", Token::NewImage),
+    ("_LOADIMAGE", Token::LoadImage),
+    ("_SAVEIMAGE", Token::SaveImage),
+    ("_PUTIMAGE", Token::PutImage),
+    ("_GETIMAGE", Token::GetImage),
+    ("_SCREENIMAGE", Token::ScreenImage),
+    ("_COPYIMAGE", Token::CopyImage),
+    ("_FREEIMAGE", Token::FreeImage),
+    ("_DEST", Token::Dest),
+    ("_SOURCE", Token::Source),
+    ("_RGB", Token::RGB),
+    ("_RGBA", Token::RGBA),
+    ("_RED", Token::Red),
+    ("_GREEN", Token::Green),
+    ("_BLUE", Token::Blue),
+    ("_ALPHA", Token::Alpha),
+    ("_RGB32", Token::RGB32),
+    ("_RGBA32", Token::RGBA32),
+    ("_RED32", Token::Red32),
+    ("_GREEN32", Token::Green32),
+    ("_BLUE32", Token::Blue32),
+    ("_ALPHA32", Token::Alpha32),
+
+    // QB64 Sound
+    ("_SNDOPEN", Token::SndOpen),
+    ("_SNDPLAY", Token::SndPlay),
+    ("_SNDLOOP", Token::SndLoop),
+    ("_SNDCLOSE", Token::SndClose),
+    ("_SNDSTOP", Token::SndStop),
+    ("_SNDVOL", Token::SndVol),
+    ("_SNDPLAYING", Token::SndPlaying),
+    ("_SNDRAW", Token::SndRaw),
+    ("_SNDRATE", Token::SndRate),
+    ("_SNDRAWLEN", Token::SndRawLen),
+
+    // QB64 Networking
+    ("_OPENHOST", Token::OpenHost),
+    ("_OPENCLIENT", Token::OpenClient),
+    ("_OPENCONNECTION", Token::OpenConnection),
+    ("_CONNECTED", Token::Connected),
+    ("_CONNECTIONADDRESS$", Token::ConnectionAddress),
+    ("_CONNECTIONADDRESS", Token::ConnectionAddress),
+
+    // QB64-style reflection
+    ("_SOURCELINE", Token::SourceLine),
+    ("_SOURCELINE$", Token::SourceLineText),
+    ("_CALLDEPTH", Token::CallDepth),
+    ("_PROGRAMNAME$", Token::ProgramName),
+
+    // QB64 Input/Events
+    ("_MOUSEINPUT", Token::MouseInput),
+    ("_MOUSEX", Token::MouseX),
+    ("_MOUSEY", Token::MouseY),
+    ("_MOUSEBUTTON", Token::MouseButton),
+    ("_MOUSEWHEEL", Token::MouseWheel),
+    ("_KEYHIT", Token::KeyHit),
+    ("_KEYCLEAR", Token::KeyClear),
+    ("_DEVICES", Token::Devices),
+    ("_DEVICE$", Token::DeviceName),
+    ("_DEVICEINPUT", Token::DeviceInput),
+    ("_BUTTON", Token::Button),
+    ("_AXIS", Token::Axis),
+    ("_LASTBUTTON", Token::LastButton),
+    ("_LASTAXIS", Token::LastAxis),
+    ("_INKEY$", Token::InKey),
+
+    // QB64 Screen/Window
+    ("_RESIZE", Token::Resize),
+    ("_WIDTH", Token::QB64Width),
+    ("_HEIGHT", Token::Height),
+    ("_FONT", Token::Font),
+    ("_PRINTSTRING", Token::PrintString),
+    ("_PRINTWIDTH", Token::PrintWidth),
+    ("_TITLE", Token::Title),
+    ("_SCREENMOVE", Token::ScreenMove),
+    ("_MIDDLE", Token::Middle),
+    ("_RESIZEWIDTH", Token::ResizeWidth),
+    ("_RESIZEHEIGHT", Token::ResizeHeight),
+    ("_STRETCH", Token::Stretch),
+    ("_SMOOTH", Token::Smooth),
+    ("_FULLSCREEN", Token::FullScreen),
+    ("_ALLOWFULLSCREEN", Token::AllowFullScreen),
+    ("_DISPLAY", Token::Display),
+    ("_AUTODISPLAY", Token::AutoDisplay),
+    ("_LIMIT", Token::Limit),
+    ("_TIMER64", Token::Timer64),
+    ("_CONSOLE", Token::Console),
+
+    // QB64 Memory
+    ("_MEM", Token::MemType),
+    ("_DICT", Token::DictType),
+    ("_MEMNEW", Token::MemNew),
+    ("_MEMIMAGE", Token::MemImage),
+    ("_MEMGET", Token::MemGet),
+    ("_MEMPUT", Token::MemPut),
+    ("_MEMFREE", Token::MemFree),
+
+    // QB64 Other
+    ("_DEFINE", Token::Define),
+    ("_PRESERVE", Token::Preserve),
+    ("MAT", Token::Mat),
+    ("_INSTRREV", Token::InStrRev),
+];
+
 /// Convert string to keyword token
+/// Whether a keyword is QB64's rather than QB 4.5's: the underscore
+/// words and the few it added without one. QB 4.5 programs may use
+/// these as variable names.
+pub fn is_qb64_keyword(spelling: &str) -> bool {
+    spelling.starts_with('_')
+        || matches!(
+            spelling.to_uppercase().as_str(),
+            "TRIM$" | "ENDIF" | "CBOOL" | "CBYTE" | "CDATE" | "CCUR" | "CVAR" | "CVERR" | "DIR$" | "VARIANT" | "SADDLE" | "MAT"
+        )
+}
+
 pub fn string_to_keyword(s: &str) -> Option<Token> {
     let upper = s.to_uppercase();
-    Some(match upper.as_str() {
-        // Comments
-        "REM" => Token::Rem,
-        
-        // Declaration
-        "LET" => Token::Let,
-        "CONST" => Token::Const,
-        "DIM" => Token::Dim,
-        "REDIM" => Token::Redim,
-        "SHARED" => Token::Shared,
-        "COMMON" => Token::Common,
-        "STATIC" => Token::Static,
-        "DEFINT" => Token::DefInt,
-        "DEFLNG" => Token::DefLng,
-        "DEFSNG" => Token::DefSng,
-        "DEFDBL" => Token::DefDbl,
-        "DEFSTR" => Token::DefStr,
-        
-        // Control flow
-        "IF" => Token::If,
-        "THEN" => Token::Then,
-        "ELSE" => Token::Else,
-        "ELSEIF" => Token::ElseIf,
-        "ENDIF" => Token::EndIf,
-        "END" => Token::End,
-        "SELECT" => Token::Select,
-        "CASE" => Token::Case,
-        "CASEIS" => Token::CaseIs,
-        "CASEELSE" => Token::CaseElse,
-        "ENDSELECT" => Token::EndSelect,
-        "FOR" => Token::For,
-        "TO" => Token::To,
-        "STEP" => Token::Step,
-        "NEXT" => Token::Next,
-        "WHILE" => Token::While,
-        "WEND" => Token::Wend,
-        "DO" => Token::Do,
-        "LOOP" => Token::Loop,
-        "UNTIL" => Token::Until,
-        "GOTO" => Token::GoTo,
-        "GOSUB" => Token::GoSub,
-        "RETURN" => Token::Return,
-        "ON" => Token::On,
-        
-        // Procedures
-        "SUB" => Token::Sub,
-        "FUNCTION" => Token::Function,
-        "DECLARE" => Token::Declare,
-        "CALL" => Token::Call,
-        "EXIT" => Token::Exit,
-        
-        // I/O
-        "PRINT" => Token::Print,
-        "INPUT" => Token::Input,
-        "OUTPUT" => Token::Output,
-        "APPEND" => Token::Append,
-        "RANDOM" => Token::Random,
-        "BINARY" => Token::Binary,
-
-        "WRITE" => Token::Write,
-        "OPEN" => Token::Open,
-        "CLOSE" => Token::Close,
-        "GET" => Token::Get,
-        "PUT" => Token::Put,
-        "SEEK" => Token::Seek,
-        "LOCK" => Token::Lock,
-        "UNLOCK" => Token::Unlock,
-        
-        // Graphics
-        "SCREEN" => Token::Screen,
-        "PSET" => Token::PSet,
-        "PRESET" => Token::PReset,
-        "LINE" => Token::Line,
-        "CIRCLE" => Token::Circle,
-        "DRAW" => Token::Draw,
-        "PAINT" => Token::Paint,
-        "VIEW" => Token::View,
-        "WINDOW" => Token::Window,
-        "PALETTE" => Token::Palette,
-        "COLOR" => Token::Color,
-        "CLS" => Token::Cls,
-        "LOCATE" => Token::Locate,
-        "WIDTH" => Token::Width,
-        
-        // Sound
-        "BEEP" => Token::Beep,
-        "SOUND" => Token::Sound,
-        "PLAY" => Token::Play,
-        
-        // Memory & System
-        "POKE" => Token::Poke,
-        "PEEK" => Token::Peek,
-        "INP" => Token::InP,
-        "OUT" => Token::Out,
-        "WAIT" => Token::Wait,
-        "DEFSEG" => Token::DefSeg,
-        "VARPTR" => Token::VarPtr,
-        "VARSEG" => Token::VarSeg,
-        
-        // Error handling
-        "ERROR" => Token::Error,
-        "RESUME" => Token::Resume,
-        "ERR" => Token::Err,
-        "ERL" => Token::ERL,
-        "STOP" => Token::Stop,
-        
-        // Data
-        "DATA" => Token::Data,
-        "READ" => Token::Read,
-        "RESTORE" => Token::Restore,
-        
-        // Environment
-        "ENVIRON" => Token::Environ,
-        "SHELL" => Token::Shell,
-        "SYSTEM" => Token::System,
-        
-        // Types
-        "AS" => Token::As,
-        "IS" => Token::Is,
-        "TYPE" => Token::Type,
-        "LEN" => Token::Len,
-        "USING" => Token::Using,
-        
-        // Type keywords
-        "INTEGER" => Token::IntegerType,
-        "LONG" => Token::LongType,
-        "SINGLE" => Token::SingleType,
-        "DOUBLE" => Token::DoubleType,
-        "STRING" => Token::StringType,
-        "VARIANT" => Token::VariantType,
-        "ANY" => Token::AnyType,
-        
-        // Logical operators
-        "AND" => Token::And,
-        "OR" => Token::Or,
-        "XOR" => Token::Xor,
-        "NOT" => Token::Not,
-        "IMP" => Token::Imp,
-        "EQV" => Token::Eqv,
-        "MOD" => Token::Modulo,
-        
-        // Math functions
-        "ABS" => Token::Abs,
-        "ATN" => Token::Atn,
-        "COS" => Token::Cos,
-        "EXP" => Token::Exp,
-        "FIX" => Token::Fix,
-        "INT" => Token::Int,
-        "LOG" => Token::Log,
-        "RANDOMIZE" => Token::Randomize,
-        "RND" => Token::Rnd,
-        "SGN" => Token::Sgn,
-        "SIN" => Token::Sin,
-        "SQR" => Token::Sqr,
-        "TAN" => Token::Tan,
-        
-        // String functions
-        "ASC" => Token::Asc,
-        "CHR$" => Token::Chr,
-        "CVI" => Token::Cvi,
-        "CVS" => Token::Cvs,
-        "CVD" => Token::Cvd,
-        "INSTR" => Token::InStr,
-        "LEFT$" => Token::Left,
-        "LSET" => Token::LSet,
-        "MID$" => Token::Mid,
-        "MKD$" => Token::MkD,
-        "MKI$" => Token::MkI,
-        "MKL$" => Token::MkL,
-        "MKS$" => Token::MkS,
-        "OCT$" => Token::Oct,
-        "RIGHT$" => Token::Right,
-        "RSET" => Token::RSet,
-        "SPACE$" => Token::Space,
-        "STR$" => Token::Str,
-        "STRING$" => Token::StringFunc,
-        "LCASE$" => Token::LCase,
-        "UCASE$" => Token::UCase,
-        "LTRIM$" => Token::LTrim,
-        "RTRIM$" => Token::RTrim,
-        "TRIM$" => Token::Trim,
-        "INKEY$" => Token::InKey,
-        
-        // Type conversion
-        "CBOOL" => Token::CBool,
-        "CBYTE" => Token::CByte,
-        "CINT" => Token::CInt,
-        "CLNG" => Token::CLng,
-        "CSNG" => Token::CSng,
-        "CDBL" => Token::CDbl,
-        "CSTR" => Token::CStr,
-        "CDATE" => Token::CDate,
-        "CCUR" => Token::CCur,
-        "CVAR" => Token::CVar,
-        "CVERR" => Token::CVErr,
-        "VAL" => Token::Val,
-        
-        // Date/Time
-        "DATE$" => Token::Date,
-        "TIME$" => Token::Time,
-        "TIMER" => Token::Timer,
-        
-        // File functions
-        "EOF" => Token::Eof,
-        "LOF" => Token::Lof,
-        "LOC" => Token::Loc,
-        "FREEFILE" => Token::FreeFile,
-        
-        // Other functions
-        "COMMAND$" => Token::Command,
-        "DIR$" => Token::Dir,
-        "INPUT$" => Token::InputFunc,
-        "LBOUND" => Token::LBound,
-        "UBOUND" => Token::UBound,
-        "SADD" => Token::SAdd,
-        "SADDLE" => Token::Saddle,
-        
-        // QB64 Extended types
-        "_INTEGER64" => Token::Integer64Type,
-        "_UNSIGNED" => Token::UnsignedIntegerType,
-        "_FLOAT" => Token::FloatType,
-        
-        // QB64 Metacommands
-        "$DYNAMIC" => Token::MetaDynamic,
-        "$STATIC" => Token::MetaStatic,
-        "$INCLUDE" => Token::MetaInclude,
-        "$IF" => Token::MetaIf,
-        "$ELSE" => Token::MetaElse,
-        "$END" => Token::MetaEndIf,
-        "$RESIZE" => Token::MetaResize,
-        "$CONSOLE" => Token::MetaConsole,
-        "$SCREENSHOW" => Token::MetaScreenShow,
-        "$SCREENHIDE" => Token::ScreenHide,
-        
-        // QB64 Graphics
-        "_NEWIMAGE" => Token::NewImage,
-        "_LOADIMAGE" => Token::LoadImage,
-        "_PUTIMAGE" => Token::PutImage,
-        "_GETIMAGE" => Token::GetImage,
-        "_SCREENIMAGE" => Token::ScreenImage,
-        "_COPYIMAGE" => Token::CopyImage,
-        "_FREEIMAGE" => Token::FreeImage,
-        "_RGB" => Token::RGB,
-        "_RGBA" => Token::RGBA,
-        "_RED" => Token::Red,
-        "_GREEN" => Token::Green,
-        "_BLUE" => Token::Blue,
-        "_ALPHA" => Token::Alpha,
-        
-        // QB64 Sound
-        "_SNDOPEN" => Token::SndOpen,
-        "_SNDPLAY" => Token::SndPlay,
-        "_SNDLOOP" => Token::SndLoop,
-        "_SNDCLOSE" => Token::SndClose,
-        
-        // QB64 Input/Events
-        "_MOUSEINPUT" => Token::MouseInput,
-        "_MOUSEX" => Token::MouseX,
-        "_MOUSEY" => Token::MouseY,
-        "_MOUSEBUTTON" => Token::MouseButton,
-        "_MOUSEWHEEL" => Token::MouseWheel,
-        "_KEYHIT" => Token::KeyHit,
-        "_KEYCLEAR" => Token::KeyClear,
-        "_INKEY$" => Token::InKey,
-        
-        // QB64 Screen/Window
-        "_RESIZE" => Token::Resize,
-        "_WIDTH" => Token::Width,
-        "_HEIGHT" => Token::Height,
-        "_FONT" => Token::Font,
-        "_PRINTSTRING" => Token::PrintString,
-        "_FULLSCREEN" => Token::FullScreen,
-        "_ALLOWFULLSCREEN" => Token::AllowFullScreen,
-        "_DISPLAY" => Token::Display,
-        "_AUTODISPLAY" => Token::AutoDisplay,
-        "_LIMIT" => Token::Limit,
-        "_CONSOLE" => Token::Console,
-        
-        // QB64 Other
-        "_DEFINE" => Token::Define,
-        "_PRESERVE" => Token::Preserve,
-        
-        _ => return None,
-    })
+    KEYWORDS
+        .iter()
+        .find(|(name, _)| *name == upper)
+        .map(|(_, token)| token.clone())
 }
 
 /// Token with position information
@@ -1,4 +1,4 @@
-use qb_core::data_types::{ArrayBounds, ParamType, VariableId};
+use qb_core::data_types::{ParamType, VariableId};
 use qb_lexer::tokens::Token;
 
 /// The complete Abstract Syntax Tree for a QBasic program
@@ -37,6 +37,37 @@ pub enum Statement {
     Dim {
         vars: Vec<DimItem>,
     },
+    /// REDIM: allocate a dynamic array anew, or with PRESERVE resize it
+    /// keeping its elements
+    ReDim {
+        vars: Vec<DimItem>,
+        preserve: bool,
+    },
+    /// ERASE: clear static arrays, free dynamic ones
+    Erase {
+        arrays: Vec<VariableId>,
+    },
+    /// SHARED in a SUB or FUNCTION: module-level variables it uses. An
+    /// array is written name(), and has bounds of no dimensions.
+    Shared {
+        vars: Vec<DimItem>,
+    },
+    /// STATIC in a SUB or FUNCTION: variables that keep their values from
+    /// one call to the next
+    Static {
+        vars: Vec<DimItem>,
+    },
+    /// COMMON [SHARED]: module-level variables passed on, in order, to the
+    /// program CHAIN runs. Arrays are written as for SHARED.
+    Common {
+        vars: Vec<DimItem>,
+    },
+    /// $STATIC or $DYNAMIC: how later DIMs allocate arrays
+    ArrayStorage {
+        dynamic: bool,
+    },
+    /// OPTION _EXPLICITARRAY: arrays must be DIMmed before use
+    ExplicitArrays,
     Const {
         name: VariableId,
         value: Expression,
@@ -95,7 +126,10 @@ pub enum Statement {
     Gosub {
         label: String,
     },
-    Return,
+    /// RETURN, or RETURN line to drop the GOSUB's return point and jump
+    Return {
+        label: Option<String>,
+    },
     OnGoto {
         expr: Expression,
         labels: Vec<String>,
@@ -108,13 +142,13 @@ pub enum Statement {
     // Procedures
     Sub {
         name: String,
-        params: Vec<ParamType>,
+        params: Vec<Param>,
         body: Vec<Statement>,
         is_static: bool,
     },
     Function {
         name: String,
-        params: Vec<ParamType>,
+        params: Vec<Param>,
         return_type: Option<TypeSpec>,
         body: Vec<Statement>,
         is_static: bool,
@@ -122,7 +156,7 @@ pub enum Statement {
     Declare {
         is_sub: bool,
         name: String,
-        params: Vec<ParamType>,
+        params: Vec<Param>,
     },
     Call {
         name: String,
@@ -141,6 +175,8 @@ pub enum Statement {
     Input {
         prompt: Option<String>,
         vars: Vec<VariableId>,
+        question_mark: bool, // "prompt"; x (true) vs "prompt", x (false)
+        same_line: bool,     // INPUT ; keeps the cursor on the line
     },
     PrintHash {
         fileno: Expression,
@@ -154,7 +190,15 @@ pub enum Statement {
         prompt: Option<String>,
         var: VariableId,
     },
+    LineInputHash {
+        fileno: Expression,
+        var: VariableId,
+    },
+    LPrint {
+        items: Vec<PrintItem>,
+    },
     Write {
+        fileno: Option<Expression>,
         items: Vec<Expression>,
     },
     
@@ -163,20 +207,55 @@ pub enum Statement {
         filename: Expression,
         mode: FileMode,
         fileno: Expression,
+        access: Option<FileAccess>,
+        lock: Option<FileLock>,
         reclen: Option<Expression>,
     },
     Close {
         fileno: Option<Expression>,
     },
+    /// OPEN database FOR ISAM tabletype table AS #n
+    OpenIsam {
+        database: Expression,
+        table_type: String,
+        table: Expression,
+        fileno: Expression,
+    },
+    /// An ISAM statement of QuickBASIC PDS, as SEEKEQ #1, key or
+    /// BEGINTRANS. They parse, so programs using them compile, but there is
+    /// no ISAM to run them.
+    Isam {
+        keyword: String,
+        fileno: Option<Expression>,
+        args: Vec<Expression>,
+    },
+    /// KILL: delete the files a name or pattern matches
+    Kill {
+        filespec: Expression,
+    },
+    /// GET #: without a variable, the record goes to the FIELD variables
     Get {
         fileno: Expression,
         record: Option<Expression>,
-        var: VariableId,
+        var: Option<VariableId>,
     },
     Put {
         fileno: Expression,
         record: Option<Expression>,
-        var: VariableId,
+        var: Option<VariableId>,
+    },
+    /// FIELD #n, width AS var$, ...: string variables over the record buffer
+    Field {
+        fileno: Expression,
+        fields: Vec<(Expression, VariableId)>,
+    },
+    LSet {
+        target: LValue,
+        value: Expression,
+    },
+    RSet {
+        target: LValue,
+        value: Expression,
     },
     Seek {
         fileno: Expression,
@@ -212,19 +291,25 @@ pub enum Statement {
         x: Expression,
         y: Expression,
     },
+    /// LINE; a corner given with STEP is relative to the point before it.
+    /// A line from the last point referenced has STEP (0, 0) as its start.
     Line {
         x1: Expression,
         y1: Expression,
+        step1: bool,
         x2: Expression,
         y2: Expression,
+        step2: bool,
         color: Option<Expression>,
         style: Option<Expression>,
         is_box: bool,
         is_filled: bool,
     },
+    /// CIRCLE; the center is relative to the last point referenced with STEP
     Circle {
         x: Expression,
         y: Expression,
+        step: bool,
         radius: Expression,
         color: Option<Expression>,
         start: Option<Expression>,
@@ -265,6 +350,57 @@ pub enum Statement {
         border: Option<Expression>,
     },
     Cls,
+    Display,     // _DISPLAY
+    AutoDisplay, // _AUTODISPLAY
+
+    // QB64 images
+    PutImage {
+        area: Option<ImageArea>,
+        source: Option<Expression>,
+        dest: Option<Expression>,
+        source_area: Option<ImageArea>,
+    },
+    FreeImage {
+        handle: Expression,
+    },
+    SaveImage {
+        filename: Expression,
+        handle: Option<Expression>,
+    },
+    Dest {
+        handle: Expression,
+    },
+    Source {
+        handle: Expression,
+    },
+    PrintString {
+        x: Expression,
+        y: Expression,
+        text: Expression,
+        handle: Option<Expression>,
+    },
+
+    // QB64 memory blocks
+    MemGet {
+        block: Expression,
+        offset: Expression,
+        var: VariableId,
+    },
+    MemPut {
+        block: Expression,
+        offset: Expression,
+        value: Expression,
+        type_spec: Option<TypeSpec>,
+    },
+    MemFree {
+        block: Expression,
+    },
+
+    // Whole arrays (extension)
+    Mat {
+        target: VariableId,
+        source: MatSource,
+    },
     Locate {
         row: Option<Expression>,
         col: Option<Expression>,
@@ -282,8 +418,25 @@ pub enum Statement {
         frequency: Expression,
         duration: Expression,
     },
+    /// One music string, or up to three played together as voices
     Play {
-        command: Expression,
+        voices: Vec<Expression>,
+    },
+    Sleep {
+        seconds: Option<Expression>, // None waits for a key
+    },
+    // QB64 sound handles
+    Snd {
+        action: SndAction,
+        handle: Expression,
+    },
+    SndVol {
+        handle: Expression,
+        volume: Expression,
+    },
+    SndRaw {
+        left: Expression,
+        right: Option<Expression>,
     },
     
     // Memory
@@ -313,7 +466,13 @@ pub enum Statement {
     Shell {
         command: Option<Expression>,
     },
-    System,
+    /// CHAIN: run another program in place of this one
+    Chain {
+        file: Expression,
+    },
+    System {
+        code: Option<Expression>, // process exit status, 0 when omitted
+    },
     
     // Error handling
     OnError {
@@ -326,10 +485,57 @@ pub enum Statement {
     Error {
         code: Expression,
     },
+
+    // QB64 timing
+    Limit {
+        rate: Expression,
+    },
+
+    // QB64 window
+    Title {
+        text: Expression,
+    },
+    ScreenMove {
+        position: Option<(Expression, Expression)>, // None for _MIDDLE
+    },
+    Resize {
+        enabled: bool,
+        mode: Option<ResizeMode>,
+    },
+    FullScreen {
+        mode: FullScreenMode,
+        smooth: bool,
+    },
+    MetaConsole {
+        only: bool, // $CONSOLE:ONLY starts with _DEST _CONSOLE
+    },
+    Console {
+        visible: bool, // _CONSOLE ON/OFF
+    },
+
+    // Event trapping
+    OnEvent {
+        source: EventSource,
+        arg: Option<Expression>,
+        label: String,
+    },
+    EventControl {
+        source: EventSource,
+        arg: Option<Expression>,
+        state: EventState,
+    },
     
     // Program flow
-    End,
+    End {
+        code: Option<Expression>, // process exit status, 0 when omitted
+    },
     Stop,
+    Clear {
+        stack: Option<Expression>, // CLEAR , , stack
+    },
+    Randomize {
+        seed: Option<Expression>, // None prompts for a seed
+    },
     
     // Other
     Assignment {
@@ -342,17 +548,46 @@ pub enum Statement {
     LineNumber {
         number: u32,
     },
+    /// Marks the physical source line of the statements that follow
+    SourceLine {
+        line: usize,
+    },
+}
+
+/// Right-hand side of MAT array = ...
+#[derive(Debug, Clone)]
+pub enum MatSource {
+    Zero,             // ZER
+    Ones,             // CON
+    Fill(Expression), // (value)
+    Copy(VariableId), // another array
 }
 
 /// Dimensional item (for DIM statement)
 #[derive(Debug, Clone)]
 pub struct DimItem {
     pub name: VariableId,
-    pub bounds: Option<Vec<ArrayBounds>>,
+    pub bounds: Option<Vec<DimBounds>>,
     pub type_spec: Option<TypeSpec>,
     pub shared: bool,
 }
 
+/// A SUB or FUNCTION parameter and the type it is declared AS, if any; a
+/// simple type also gives the variable its suffix
+#[derive(Debug, Clone)]
+pub struct Param {
+    pub kind: ParamType,
+    pub type_spec: Option<TypeSpec>,
+}
+
+/// One dimension of an array in DIM or REDIM: lower TO upper, the lower
+/// bound 0 when it is left out
+#[derive(Debug, Clone)]
+pub struct DimBounds {
+    pub lower: Expression,
+    pub upper: Expression,
+}
+
 /// Type specification
 #[derive(Debug, Clone)]
 pub enum TypeSpec {
@@ -371,19 +606,104 @@ pub enum FileMode {
     Binary,
 }
 
+/// ACCESS clause of OPEN
+#[derive(Debug, Clone, Copy)]
+pub enum FileAccess {
+    Read,
+    Write,
+    ReadWrite,
+}
+
+/// Sharing clause of OPEN: SHARED, or what other opens of the file are
+/// denied by LOCK
+#[derive(Debug, Clone, Copy)]
+pub enum FileLock {
+    Shared,
+    Read,
+    Write,
+    ReadWrite,
+}
+
+/// Area of an image in _PUTIMAGE: (x1, y1) alone, or (x1, y1)-(x2, y2)
+#[derive(Debug, Clone)]
+pub struct ImageArea {
+    pub corner: (Expression, Expression),
+    pub opposite: Option<(Expression, Expression)>,
+}
+
+/// What a _SND statement does to the sound handle it is given
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SndAction {
+    Play,
+    Loop,
+    Stop,
+    Close,
+}
+
+/// Scaling requested by _RESIZE ON, _STRETCH or _SMOOTH
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ResizeMode {
+    Stretch,
+    Smooth,
+}
+
+/// Scaling requested by _FULLSCREEN
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum FullScreenMode {
+    /// No mode given: square pixels where they fit
+    Auto,
+    Off,
+    Stretch,
+    SquarePixels,
+}
+
+/// Event that ON ... GOSUB can trap
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum EventSource {
+    Strig,
+    /// KEY(n); KEY(15) is also raised by Ctrl+Break
+    Key,
+    /// TIMER(n): every n seconds
+    Timer,
+}
+
+/// Trap state set by e.g. STRIG(n) ON/OFF/STOP
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum EventState {
+    On,
+    Off,
+    Stop,
+}
+
 /// Print item (expression or separator)
 #[derive(Debug, Clone)]
 pub enum PrintItem {
     Expression(Expression),
+    /// USING format; heads the list and lays out every expression after it
+    Using(Expression),
     Semicolon,
     Comma,
 }
 
-/// Argument for procedure calls
+/// Argument for procedure calls. A variable, array element or record
+/// field is passed by reference; any other expression, including a
+/// variable in its own parentheses as in `CALL Foo((x))`, is evaluated
+/// into a temporary and passed by value.
 #[derive(Debug, Clone)]
 pub enum Argument {
     ByVal(Expression),
-    ByRef(VariableId),
+    ByRef(LValue),
+}
+
+impl Argument {
+    /// How `expr` is passed; `parenthesized` if it was written in
+    /// parentheses of its own
+    pub fn new(expr: Expression, parenthesized: bool) -> Self {
+        match LValue::from_expression(&expr) {
+            Some(target) if !parenthesized => Argument::ByRef(target),
+            _ => Argument::ByVal(expr),
+        }
+    }
 }
 
 /// Case clause for SELECT statement
@@ -409,6 +729,29 @@ pub enum LValue {
     Field(Box<LValue>, String), // Record.field
 }
 
+impl LValue {
+    /// The place `expr` names, if it can be assigned to
+    pub fn from_expression(expr: &Expression) -> Option<Self> {
+        match expr {
+            Expression::Variable(var) => Some(LValue::Variable(var.clone())),
+            Expression::ArrayAccess(var, indices) => Some(LValue::ArrayElement(var.clone(), indices.clone())),
+            Expression::FieldAccess(base, field) => {
+                Some(LValue::Field(Box::new(Self::from_expression(base)?), field.clone()))
+            }
+            _ => None,
+        }
+    }
+
+    /// Expression reading the value at this place
+    pub fn to_expression(&self) -> Expression {
+        match self {
+            LValue::Variable(var) => Expression::Variable(var.clone()),
+            LValue::ArrayElement(var, indices) => Expression::ArrayAccess(var.clone(), indices.clone()),
+            LValue::Field(base, field) => Expression::FieldAccess(Box::new(base.to_expression()), field.clone()),
+        }
+    }
+}
+
 /// All possible expressions
 #[derive(Debug, Clone)]
 pub enum Expression {
@@ -447,6 +790,13 @@ pub enum Expression {
         target_type: String,
         expr: Box<Expression>,
     },
+
+    // _MEMGET(block, offset, type)
+    MemGet {
+        block: Box<Expression>,
+        offset: Box<Expression>,
+        type_spec: Box<TypeSpec>,
+    },
 }
 
 /// Binary operators
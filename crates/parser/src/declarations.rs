@@ -12,6 +12,12 @@ pub struct DeclarationManager {
     
     // Constants
     constants: std::collections::HashMap<String, crate::ast_nodes::Expression>,
+
+    // SUB procedures, which can be called without CALL
+    subs: std::collections::HashSet<String>,
+
+    // FUNCTION procedures, called rather than indexed by name(...)
+    functions: std::collections::HashSet<String>,
 }
 
 impl DeclarationManager {
@@ -83,6 +89,22 @@ impl DeclarationManager {
         self.constants.get(&name.to_uppercase())
     }
 
+    pub fn add_sub(&mut self, name: &str) {
+        self.subs.insert(name.to_uppercase());
+    }
+
+    pub fn is_sub(&self, name: &str) -> bool {
+        self.subs.contains(&name.to_uppercase())
+    }
+
+    pub fn add_function(&mut self, name: &str) {
+        self.functions.insert(name.to_uppercase());
+    }
+
+    pub fn is_function(&self, name: &str) -> bool {
+        self.functions.contains(&name.to_uppercase())
+    }
+
     pub fn type_spec_to_suffix(&self, spec: &TypeSpec) -> TypeSuffix {
         match spec {
             TypeSpec::Simple(s) => match s.as_str() {
@@ -91,6 +113,8 @@ impl DeclarationManager {
                 "SINGLE" => TypeSuffix::Single,
                 "DOUBLE" => TypeSuffix::Double,
                 "STRING" => TypeSuffix::String,
+                "_INTEGER64" => TypeSuffix::Integer64,
+                "_FLOAT" => TypeSuffix::Float,
                 _ => TypeSuffix::Single,
             }
             TypeSpec::FixedString(_) => TypeSuffix::String,
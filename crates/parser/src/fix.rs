@@ -0,0 +1,173 @@
+//! Safe automatic corrections for `qb check --fix`
+//!
+//! Each fix works on the text of one line, before it is parsed, for a
+//! mistake that stops a program from parsing but has only one sensible
+//! reading: ENDIF written for END IF, a NEXT without the variable of the
+//! FOR it closes, a string left open at the end of its line, and a banner
+//! of asterisks or box characters that is not marked as a comment. No fix
+//! adds or removes lines, so errors found afterwards keep their numbers.
+
+/// A line a fix changed
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Fix {
+    /// From 1
+    pub line: usize,
+    pub message: String,
+    pub before: String,
+    pub after: String,
+}
+
+/// `source` with every fix made, and the fixes, in line order
+pub fn fix_source(source: &str) -> (String, Vec<Fix>) {
+    let mut fixed = String::with_capacity(source.len());
+    let mut fixes = Vec::new();
+    // Variables of the FOR loops open at each line
+    let mut loops: Vec<String> = Vec::new();
+    for (index, raw) in source.split_inclusive('\n').enumerate() {
+        let body = raw.trim_end_matches(['\r', '\n']);
+        let ending = &raw[body.len()..];
+        let mut line = body.to_string();
+        let mut messages = Vec::new();
+        if is_banner(&line) {
+            line = format!("' {}", line);
+            messages.push("banner made a comment");
+        } else {
+            let (statements, open_string) = split_statements(&line);
+            if open_string {
+                line.push('"');
+                messages.push("string closed at the end of the line");
+            }
+            // Edit from the end, so earlier offsets stay right
+            let mut edits = Vec::new();
+            for &(start, end) in &statements {
+                if let Some(edit) = statement_fix(&line[start..end], &mut loops) {
+                    edits.push((start, end, edit));
+                }
+            }
+            for (start, end, (text, message)) in edits.into_iter().rev() {
+                line.replace_range(start..end, &text);
+                messages.push(message);
+            }
+        }
+        if !messages.is_empty() {
+            fixes.push(Fix { line: index + 1, message: messages.join(", "), before: body.to_string(), after: line.clone() });
+        }
+        fixed.push_str(&line);
+        fixed.push_str(ending);
+    }
+    (fixed, fixes)
+}
+
+/// A line of nothing but punctuation or box drawing, which no statement
+/// is, meant as decoration
+fn is_banner(line: &str) -> bool {
+    let text = line.trim();
+    text.chars().count() >= 2
+        && text.chars().all(|c| {
+            !c.is_alphanumeric() && !c.is_whitespace() && !matches!(c, '\'' | '"' | '?' | ':' | '$')
+        })
+}
+
+/// The byte ranges of the statements of a line, without its comment, and
+/// whether a string is still open at its end. DATA and REM take the rest of
+/// the line as they find it.
+fn split_statements(line: &str) -> (Vec<(usize, usize)>, bool) {
+    let mut statements = Vec::new();
+    let mut start = 0;
+    let mut in_string = false;
+    for (at, c) in line.char_indices() {
+        if in_string {
+            in_string = c != '"';
+            continue;
+        }
+        if at == start || line[start..at].trim().is_empty() {
+            let word = first_word(&line[at..]);
+            if word.eq_ignore_ascii_case("REM") || word.eq_ignore_ascii_case("DATA") {
+                return (statements, false);
+            }
+        }
+        match c {
+            '"' => in_string = true,
+            '\'' => return (statements, false),
+            ':' => {
+                statements.push((start, at));
+                start = at + 1;
+            }
+            _ => {}
+        }
+    }
+    statements.push((start, line.len()));
+    (statements, in_string)
+}
+
+/// The name or keyword `text` starts with
+fn first_word(text: &str) -> &str {
+    let end = text.find(|c: char| !(c.is_ascii_alphanumeric() || c == '_' || c == '.')).unwrap_or(text.len());
+    &text[..end]
+}
+
+/// The corrected text of one statement and why, keeping track of the FOR
+/// loops it opens and closes
+fn statement_fix(statement: &str, loops: &mut Vec<String>) -> Option<(String, &'static str)> {
+    // A line number is not part of the statement
+    let code = statement.trim_start().trim_start_matches(|c: char| c.is_ascii_digit()).trim_start();
+    let indent = &statement[..statement.len() - code.len()];
+    let keyword = first_word(code);
+    let rest = code[keyword.len()..].trim();
+    match keyword.to_ascii_uppercase().as_str() {
+        "FOR" => {
+            let name = rest.split(|c: char| c == '=' || c.is_whitespace()).next().unwrap_or_default();
+            loops.push(name.to_string());
+            None
+        }
+        "NEXT" if rest.is_empty() => {
+            let name = loops.pop().filter(|name| !name.is_empty())?;
+            Some((format!("{}{} {}", indent, keyword, name), "NEXT given its variable"))
+        }
+        "NEXT" => {
+            for _ in rest.split(',') {
+                loops.pop();
+            }
+            None
+        }
+        // A SUB or FUNCTION starts with no loops open
+        "SUB" | "FUNCTION" => {
+            loops.clear();
+            None
+        }
+        "ENDIF" if rest.is_empty() => {
+            let end_if = if keyword == "endif" { "end if" } else { "END IF" };
+            Some((format!("{}{}", indent, end_if), "ENDIF written as END IF"))
+        }
+        _ => None,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_fixes() {
+        let source = "FOR i = 1 TO 2\r\n  FOR j = 1 TO 2: PRINT \"a:b\": NEXT\r\nIF i THEN\r\nEND IF\r\n10 NEXT\r\nIF i THEN\r\nendif\r\n\
+                      *** Title ***\r\n╔══╗\r\nPRINT \"open\r\nPRINT \"x\" ' NEXT\r\nDATA \"a\r\nREM \"b\r\n";
+        let (fixed, fixes) = fix_source(source);
+        assert_eq!(
+            fixed,
+            "FOR i = 1 TO 2\r\n  FOR j = 1 TO 2: PRINT \"a:b\": NEXT j\r\nIF i THEN\r\nEND IF\r\n10 NEXT i\r\nIF i THEN\r\nend if\r\n\
+             *** Title ***\r\n' ╔══╗\r\nPRINT \"open\"\r\nPRINT \"x\" ' NEXT\r\nDATA \"a\r\nREM \"b\r\n"
+        );
+        let lines: Vec<usize> = fixes.iter().map(|fix| fix.line).collect();
+        assert_eq!(lines, vec![2, 5, 7, 9, 10]);
+        assert_eq!(fixes[1].before, "10 NEXT");
+        assert_eq!(fixes[1].message, "NEXT given its variable");
+    }
+
+    #[test]
+    fn test_leaves_correct_code() {
+        let source = "FOR k = 1 TO 3\nNEXT k, m\nNEXT\nSUB s\nNEXT\nEND SUB\n? 1\n'=====\nx = -1\n";
+        let (fixed, fixes) = fix_source(source);
+        assert_eq!(fixed, source);
+        assert!(fixes.is_empty());
+    }
+}
@@ -4,8 +4,11 @@
 
 pub mod ast_nodes;
 pub mod declarations;
+pub mod fix;
 pub mod parser;
+pub mod printer;
 
 pub use ast_nodes::*;
 pub use declarations::DeclarationManager;
-pub use parser::{Parser, parse};
+pub use parser::{Parser, parse, parse_expression, try_parse};
+pub use printer::{to_source, expression_to_source};
@@ -1,8 +1,18 @@
 use crate::ast_nodes::*;
 use crate::declarations::DeclarationManager;
-use qb_core::data_types::{ArrayBounds, ParamType};
+use qb_core::builtins;
+use qb_core::data_types::ParamType;
 use qb_core::errors::{QError, QResult};
 use qb_lexer::tokens::{Token, TokenInfo};
+use qb_lexer::try_tokenize;
+
+/// Voices PLAY can play at once, as on the PCjr and Tandy
+const MAX_VOICES: usize = 3;
+
+/// How deeply blocks and expressions may nest, each operator of a chain
+/// such as a + b + c counting as a level, well short of where the parser,
+/// or a later pass over the tree, would run out of stack
+const MAX_NESTING: usize = 128;
 
 
 /// Recursive descent parser for QBasic
@@ -13,10 +23,18 @@ pub struct Parser {
     in_sub: bool,
     in_function: bool,
     in_loop: bool,
+    last_source_line: usize,
+    /// Blocks and expressions open around the current token
+    depth: usize,
 }
 
 impl Parser {
-    pub fn new(tokens: Vec<TokenInfo>) -> Self {
+    pub fn new(mut tokens: Vec<TokenInfo>) -> Self {
+        // Tokens not from the scanner may lack the EOF that ends the input
+        if !matches!(tokens.last(), Some(TokenInfo { token: Token::EOF, .. })) {
+            let (line, column) = tokens.last().map_or((1, 1), |last| (last.line, last.column + last.length));
+            tokens.push(TokenInfo::new(Token::EOF, line, column, 0));
+        }
         Self {
             tokens,
             current: 0,
@@ -24,12 +42,23 @@ impl Parser {
             in_sub: false,
             in_function: false,
             in_loop: false,
+            last_source_line: 0,
+            depth: 0,
         }
     }
 
     pub fn parse(mut self) -> QResult<Program> {
         let mut program = Program::new();
 
+        // Procedures can be called before their definitions
+        for pair in self.tokens.windows(2) {
+            match (&pair[0].token, &pair[1].token) {
+                (Token::Sub, Token::Identifier(name)) => self.declaration_manager.add_sub(name),
+                (Token::Function, Token::Identifier(name)) => self.declaration_manager.add_function(name),
+                _ => {}
+            }
+        }
+
         while !self.is_at_end() {
             // Skip newlines
             self.skip_newlines();
@@ -38,15 +67,11 @@ impl Parser {
                 break;
             }
 
-            // Check for line number
-            if let Some(Token::LineNumber(n)) = self.peek_token() {
-                let num = *n;
-                self.advance();
-                program.add_statement(Statement::LineNumber { number: num });
-                program.line_numbers.insert(num, program.statements.len() - 1);
-            }
-
+            self.mark_source_line(&mut program.statements);
             let stmt = self.parse_statement()?;
+            if let Statement::LineNumber { number } = stmt {
+                program.line_numbers.insert(number, program.statements.len());
+            }
             // Skip empty REM statements (from newlines)
             if !matches!(stmt, Statement::Rem(ref s) if s.is_empty()) {
                 program.add_statement(stmt);
@@ -57,6 +82,14 @@ impl Parser {
     }
 
     fn parse_statement(&mut self) -> QResult<Statement> {
+        self.nested(Self::parse_one_statement)
+    }
+
+    fn parse_one_statement(&mut self) -> QResult<Statement> {
+        if let Some(number) = self.line_number() {
+            self.advance();
+            return Ok(Statement::LineNumber { number });
+        }
         match self.peek_token() {
             Some(Token::Rem) => {
                 self.advance();
@@ -69,7 +102,18 @@ impl Parser {
                 };
                 Ok(Statement::Rem(comment))
             }
-            Some(Token::Dim) => self.parse_dim(),
+            Some(Token::Dim) | Some(Token::Redim) => self.parse_dim(),
+            Some(Token::Erase) => self.parse_erase(),
+            Some(Token::Shared) | Some(Token::Static) => self.parse_shared_static(),
+            Some(Token::Common) => self.parse_common(),
+            Some(Token::Identifier(name))
+                if name.eq_ignore_ascii_case("OPTION")
+                    && matches!(self.peek_next_token(), Some(Token::Identifier(word)) if word.eq_ignore_ascii_case("_EXPLICITARRAY")) =>
+            {
+                self.advance(); // OPTION
+                self.advance(); // _EXPLICITARRAY
+                Ok(Statement::ExplicitArrays)
+            }
             Some(Token::Const) => self.parse_const(),
             Some(Token::DefInt) | Some(Token::DefLng) | Some(Token::DefSng) | 
             Some(Token::DefDbl) | Some(Token::DefStr) => self.parse_deftype(),
@@ -83,7 +127,8 @@ impl Parser {
             Some(Token::GoSub) => self.parse_gosub(),
             Some(Token::Return) => {
                 self.advance();
-                Ok(Statement::Return)
+                let label = if self.at_statement_end() { None } else { Some(self.expect_label()?) };
+                Ok(Statement::Return { label })
             }
             Some(Token::On) => self.parse_on(),
             Some(Token::Sub) => self.parse_sub(),
@@ -93,14 +138,23 @@ impl Parser {
             Some(Token::Exit) => self.parse_exit(),
             Some(Token::Print) => self.parse_print(),
             Some(Token::PrintHash) => self.parse_print_hash(),
+            Some(Token::LPrint) => self.parse_lprint(),
             Some(Token::Input) => self.parse_input(),
             Some(Token::InputHash) => self.parse_input_hash(),
             Some(Token::LineInput) => self.parse_line_input(),
-            Some(Token::Write) => self.parse_write(),
+            Some(Token::Write) | Some(Token::WriteHash) => self.parse_write(),
             Some(Token::Open) => self.parse_open(),
             Some(Token::Close) => self.parse_close(),
+            Some(Token::Kill) => self.parse_kill(),
             Some(Token::Get) => self.parse_get(),
             Some(Token::Put) => self.parse_put(),
+            Some(Token::Identifier(name))
+                if name.eq_ignore_ascii_case("FIELD")
+                    && matches!(self.peek_next_token(), Some(Token::Hash) | Some(Token::Integer(_))) =>
+            {
+                self.parse_field()
+            }
+            Some(Token::LSet) | Some(Token::RSet) => self.parse_lset(),
             Some(Token::Seek) => self.parse_seek(),
             Some(Token::Lock) => self.parse_lock(),
             Some(Token::Unlock) => self.parse_unlock(),
@@ -119,6 +173,40 @@ impl Parser {
                 self.advance();
                 Ok(Statement::Cls)
             }
+            Some(Token::Display) => {
+                self.advance();
+                Ok(Statement::Display)
+            }
+            Some(Token::AutoDisplay) => {
+                self.advance();
+                Ok(Statement::AutoDisplay)
+            }
+            Some(Token::PutImage) => self.parse_put_image(),
+            Some(Token::PrintString) => self.parse_print_string(),
+            Some(Token::FreeImage) => {
+                self.advance(); // _FREEIMAGE
+                let handle = self.parse_expression()?;
+                Ok(Statement::FreeImage { handle })
+            }
+            Some(Token::SaveImage) => self.parse_save_image(),
+            Some(Token::Dest) => {
+                self.advance(); // _DEST
+                let handle = self.parse_expression()?;
+                Ok(Statement::Dest { handle })
+            }
+            Some(Token::Source) => {
+                self.advance(); // _SOURCE
+                let handle = self.parse_expression()?;
+                Ok(Statement::Source { handle })
+            }
+            Some(Token::MemGet) => self.parse_mem_get(),
+            Some(Token::MemPut) => self.parse_mem_put(),
+            Some(Token::MemFree) => {
+                self.advance(); // _MEMFREE
+                let block = self.parse_expression()?;
+                Ok(Statement::MemFree { block })
+            }
+            Some(Token::Mat) => self.parse_mat(),
             Some(Token::Locate) => self.parse_locate(),
             Some(Token::Width) => self.parse_width(),
             Some(Token::Beep) => {
@@ -127,27 +215,108 @@ impl Parser {
             }
             Some(Token::Sound) => self.parse_sound(),
             Some(Token::Play) => self.parse_play(),
+            Some(Token::Sleep) => {
+                self.advance();
+                let seconds = self.parse_optional_expression()?;
+                Ok(Statement::Sleep { seconds })
+            }
+            Some(Token::SndPlay) => self.parse_snd(SndAction::Play),
+            Some(Token::SndLoop) => self.parse_snd(SndAction::Loop),
+            Some(Token::SndStop) => self.parse_snd(SndAction::Stop),
+            Some(Token::SndClose) => self.parse_snd(SndAction::Close),
+            Some(Token::SndVol) => {
+                self.advance(); // _SNDVOL
+                let handle = self.parse_expression()?;
+                self.expect(Token::Comma)?;
+                let volume = self.parse_expression()?;
+                Ok(Statement::SndVol { handle, volume })
+            }
+            Some(Token::SndRaw) => {
+                self.advance(); // _SNDRAW
+                let left = self.parse_expression()?;
+                let right = if self.check(Token::Comma) {
+                    self.advance();
+                    Some(self.parse_expression()?)
+                } else {
+                    None
+                };
+                Ok(Statement::SndRaw { left, right })
+            }
             Some(Token::Poke) => self.parse_poke(),
             Some(Token::DefSeg) => self.parse_defseg(),
+            Some(Token::Identifier(name))
+                if name.eq_ignore_ascii_case("DEF")
+                    && matches!(self.peek_next_token(), Some(Token::Identifier(seg)) if seg.eq_ignore_ascii_case("SEG")) =>
+            {
+                self.advance(); // DEF
+                self.parse_defseg()
+            }
             Some(Token::Randomize) => self.parse_randomize(),
+            Some(Token::Clear) => self.parse_clear(),
+            Some(Token::Strig) => {
+                self.advance();
+                self.parse_event_control(EventSource::Strig)
+            }
+            Some(Token::Identifier(_)) if self.isam_keyword().is_some() => self.parse_isam(),
+            Some(Token::Identifier(name)) if name.eq_ignore_ascii_case("KEY") && self.is_key_control() => {
+                self.advance();
+                self.parse_event_control(EventSource::Key)
+            }
+            Some(Token::Timer) => {
+                self.advance();
+                self.parse_event_control(EventSource::Timer)
+            }
+            Some(Token::Limit) => {
+                self.advance(); // _LIMIT
+                let rate = self.parse_expression()?;
+                Ok(Statement::Limit { rate })
+            }
+            Some(Token::Title) => {
+                self.advance(); // _TITLE
+                let text = self.parse_expression()?;
+                Ok(Statement::Title { text })
+            }
+            Some(Token::ScreenMove) => self.parse_screen_move(),
+            Some(Token::Resize) => self.parse_resize(),
+            Some(Token::FullScreen) => self.parse_full_screen(),
+            Some(Token::Console) => self.parse_console(),
             Some(Token::Data) => self.parse_data(),
             Some(Token::Read) => self.parse_read(),
             Some(Token::Restore) => self.parse_restore(),
             Some(Token::Environ) => self.parse_environ(),
             Some(Token::Shell) => self.parse_shell(),
+            Some(Token::Chain) => {
+                self.advance(); // CHAIN
+                Ok(Statement::Chain { file: self.parse_expression()? })
+            }
             Some(Token::System) => {
                 self.advance();
-                Ok(Statement::System)
+                let code = self.parse_optional_expression()?;
+                Ok(Statement::System { code })
             }
             Some(Token::OnError) => self.parse_on_error(),
             Some(Token::Resume) => self.parse_resume(),
             Some(Token::Error) => self.parse_error(),
             // QB64 Metacommands (treated as comments/ignored for now)
-            Some(Token::MetaDynamic) | Some(Token::MetaStatic) | Some(Token::MetaConsole) |
+            Some(Token::MetaDynamic) | Some(Token::MetaStatic) => {
+                let dynamic = self.check(Token::MetaDynamic);
+                self.advance();
+                Ok(Statement::ArrayStorage { dynamic })
+            }
             Some(Token::MetaResize) | Some(Token::MetaScreenShow) | Some(Token::ScreenHide) => {
                 self.advance();
                 Ok(Statement::Rem(format!("Metacommand: {:?}", self.peek_token())))
             }
+            Some(Token::MetaConsole) => {
+                self.advance(); // $CONSOLE
+                let only = self.check(Token::Colon)
+                    && matches!(self.peek_next_token(), Some(Token::Identifier(word)) if word.eq_ignore_ascii_case("ONLY"));
+                if only {
+                    self.advance(); // :
+                    self.advance(); // ONLY
+                }
+                Ok(Statement::MetaConsole { only })
+            }
             Some(Token::MetaInclude) => {
                 self.advance();
                 // Skip the include path
@@ -186,7 +355,10 @@ impl Parser {
                         self.advance();
                         Ok(Statement::Rem(String::from("END SELECT")))
                     }
-                    _ => Ok(Statement::End),
+                    _ => {
+                        let code = self.parse_optional_expression()?;
+                        Ok(Statement::End { code })
+                    }
                 }
             }
             Some(Token::Stop) => {
@@ -195,9 +367,12 @@ impl Parser {
             }
             Some(Token::Identifier(name)) => {
                 let name = name.clone();
+                let starts_line = self.starts_line();
                 self.advance();
                 
-                if self.check(Token::Colon) {
+                // name: is a label at the start of a line; elsewhere the
+                // colon separates a SUB call from the next statement
+                if starts_line && self.check(Token::Colon) {
                     self.advance();
                     Ok(Statement::Label { name })
                 } else {
@@ -220,14 +395,14 @@ impl Parser {
                     Err(QError::compile("Expected identifier after LET", line, col))
                 }
             }
-            Some(Token::NewLine) => {
+            Some(Token::NewLine) | Some(Token::Colon) => {
                 self.advance();
                 Ok(Statement::Rem(String::new()))
             }
             _ => {
                 let (line, col) = self.current_pos();
                 Err(QError::compile(
-                    format!("Unexpected token: {:?}", self.peek_token()),
+                    format!("Unexpected token: {}", self.found()),
                     line,
                     col
                 ))
@@ -237,7 +412,10 @@ impl Parser {
 
     fn parse_identifier_statement(&mut self, name: &str) -> QResult<Statement> {
         // Check for assignment or procedure call
-        if self.check(Token::Equal) {
+        if self.declaration_manager.is_sub(name) && !self.check(Token::Equal) {
+            // SUB call without CALL: the rest of the line is its arguments
+            Ok(Statement::Call { name: name.to_string(), args: self.parse_sub_arguments()? })
+        } else if self.check(Token::Equal) {
             // Simple assignment
             self.advance();
             let value = self.parse_expression()?;
@@ -272,29 +450,51 @@ impl Parser {
                 })
             }
         } else {
-            // Simple procedure call or variable assignment without LET
-            let line = self.current_line();
-            let col = self.current_column();
-            Err(QError::compile(
-                format!("Expected '=' or '(' after identifier '{}'", name),
-                line,
-                col
-            ))
+            // Nothing to assign, so a procedure call without CALL
+            Ok(Statement::Call { name: name.to_string(), args: self.parse_sub_arguments()? })
         }
     }
 
+    /// Arguments of a SUB called without CALL, up to the end of the
+    /// statement; one in parentheses of its own is passed by value
+    fn parse_sub_arguments(&mut self) -> QResult<Vec<Argument>> {
+        let mut args = Vec::new();
+        while !self.at_statement_end() {
+            let parenthesized = self.check(Token::LParen);
+            args.push(Argument::new(self.parse_expression()?, parenthesized));
+            if !self.check(Token::Comma) {
+                break;
+            }
+            self.advance();
+        }
+        Ok(args)
+    }
+
     // ... (rest of parser methods - would continue with each parse method)
     fn parse_dim(&mut self) -> QResult<Statement> {
-        self.advance(); // DIM
+        let redim = self.check(Token::Redim);
+        self.advance(); // DIM or REDIM
+        // REDIM PRESERVE, or QB64's _PRESERVE; PRESERVE alone names an array
+        let preserve = redim
+            && match self.peek_token() {
+                Some(Token::Preserve) => true,
+                Some(Token::Identifier(word)) => {
+                    (word.eq_ignore_ascii_case("PRESERVE") || word.eq_ignore_ascii_case("_PRESERVE"))
+                        && matches!(self.peek_next_token(), Some(Token::Identifier(_)) | Some(Token::Shared))
+                }
+                _ => false,
+            };
+        if preserve {
+            self.advance();
+        }
         let mut vars = Vec::new();
+        // DIM SHARED shares every variable in the list
+        let shared = self.check(Token::Shared);
+        if shared {
+            self.advance();
+        }
 
         loop {
-            let shared = if self.check(Token::Shared) {
-                self.advance();
-                true
-            } else {
-                false
-            };
 
             let name = self.expect_identifier()?;
             let var_name = name.clone();
@@ -347,49 +547,85 @@ impl Parser {
             }
         }
 
-        Ok(Statement::Dim { vars })
+        Ok(if redim { Statement::ReDim { vars, preserve } } else { Statement::Dim { vars } })
     }
 
-    fn parse_dim_bounds(&mut self) -> QResult<Vec<ArrayBounds>> {
-        self.expect(Token::LParen)?;
-        let mut bounds = Vec::new();
+    /// SHARED or STATIC, then variables
+    fn parse_shared_static(&mut self) -> QResult<Statement> {
+        let shared = self.check(Token::Shared);
+        self.advance(); // SHARED or STATIC
+        let vars = self.parse_declared_names(shared)?;
+        Ok(if shared { Statement::Shared { vars } } else { Statement::Static { vars } })
+    }
 
-        loop {
-            let lower = if self.peek_token() == Some(&Token::To) {
-                0
-            } else {
-                let expr = self.parse_expression()?;
-                match expr {
-                    Expression::Integer(n) => n,
-                    Expression::Long(n) => n as i32,
-                    _ => 0,
-                }
-            };
+    /// COMMON [SHARED] variables
+    fn parse_common(&mut self) -> QResult<Statement> {
+        self.advance(); // COMMON
+        let shared = self.check(Token::Shared);
+        if shared {
+            self.advance();
+        }
+        let vars = self.parse_declared_names(shared)?;
+        Ok(Statement::Common { vars })
+    }
 
-            let upper = if self.check(Token::To) {
+    /// Variables, each an array if followed by () and with an optional AS
+    /// type
+    fn parse_declared_names(&mut self, shared: bool) -> QResult<Vec<DimItem>> {
+        let mut vars = Vec::new();
+        loop {
+            let name = self.expect_identifier()?;
+            let suffix = self.parse_optional_suffix();
+            let bounds = if self.check(Token::LParen) {
                 self.advance();
-                let expr = self.parse_expression()?;
-                match expr {
-                    Expression::Integer(n) => n,
-                    Expression::Long(n) => n as i32,
-                    _ => 10,
-                }
+                self.expect(Token::RParen)?;
+                Some(Vec::new())
             } else {
-                // If no TO specified, default lower is 0 (or 1 for QBASIC compatibility)
-                // DIM arr(5) means arr(0 TO 5) - 6 elements
-                lower
+                None
             };
-            
-            // Adjust: if no TO was specified, we need to swap the logic
-            // The first number parsed becomes upper bound, and lower bound defaults to 0
-            let (actual_lower, actual_upper) = if !self.check(Token::To) && upper == lower {
-                // This was a single number like DIM arr(5)
-                (0, lower)
+            let type_spec = if self.check(Token::As) {
+                self.advance();
+                Some(self.parse_type_spec()?)
             } else {
-                (lower, upper)
+                None
             };
+            vars.push(DimItem { name: qb_core::data_types::VariableId::new(name, suffix), bounds, type_spec, shared });
+            if !self.check(Token::Comma) {
+                break;
+            }
+            self.advance();
+        }
+        Ok(vars)
+    }
+
+    fn parse_erase(&mut self) -> QResult<Statement> {
+        self.advance(); // ERASE
+        let mut arrays = Vec::new();
+        loop {
+            let name = self.expect_identifier()?;
+            let suffix = self.parse_optional_suffix();
+            arrays.push(qb_core::data_types::VariableId::new(name, suffix));
+            if !self.check(Token::Comma) {
+                break;
+            }
+            self.advance();
+        }
+        Ok(Statement::Erase { arrays })
+    }
 
-            bounds.push(ArrayBounds::new(actual_lower, actual_upper));
+    fn parse_dim_bounds(&mut self) -> QResult<Vec<DimBounds>> {
+        self.expect(Token::LParen)?;
+        let mut bounds = Vec::new();
+
+        loop {
+            // DIM arr(5) is arr(0 TO 5)
+            let first = self.parse_expression()?;
+            bounds.push(if self.check(Token::To) {
+                self.advance();
+                DimBounds { lower: first, upper: self.parse_expression()? }
+            } else {
+                DimBounds { lower: Expression::Integer(0), upper: first }
+            });
 
             if self.check(Token::Comma) {
                 self.advance();
@@ -450,6 +686,11 @@ impl Parser {
             if self.check(Token::End) {
                 break;
             }
+            // A TYPE keeps only its fields; comments between them are dropped
+            if self.check(Token::Rem) {
+                self.parse_statement()?;
+                continue;
+            }
             let field_name = self.expect_identifier()?;
             self.expect(Token::As)?;
             let type_spec = self.parse_type_spec()?;
@@ -474,30 +715,30 @@ impl Parser {
     fn parse_if(&mut self) -> QResult<Statement> {
         self.advance(); // IF
         let condition = self.parse_expression()?;
-        self.expect(Token::Then)?;
-
-        // Check for single-line IF
-        let is_single_line = !matches!(self.peek_token(), Some(Token::NewLine) | None);
-
         let mut then_branch = Vec::new();
         let mut else_if_branches = Vec::new();
         let mut else_branch = None;
 
-        if is_single_line {
-            // Single line IF
-            while !self.check(Token::Else) && !self.is_at_end() {
-                if self.check(Token::NewLine) {
-                    break;
-                }
-                then_branch.push(self.parse_statement()?);
-            }
+        // IF cond GOTO label is the single-line IF cond THEN GOTO label
+        let is_single_line = if self.check(Token::GoTo) {
+            then_branch.push(self.parse_goto()?);
+            true
+        } else {
+            self.expect(Token::Then)?;
+            self.parse_line_target(&mut then_branch)?;
+            // Anything but a line end after THEN makes it a single-line IF
+            // (a trailing comment does not: the lexer puts it on a line of its own)
+            !then_branch.is_empty() || !matches!(self.peek_token(), Some(Token::NewLine) | None)
+        };
 
+        if is_single_line {
+            // Single line IF; an ELSE on this line belongs to the innermost IF
+            self.parse_line_statements(&mut then_branch)?;
             if self.check(Token::Else) {
                 self.advance();
                 let mut else_stmts = Vec::new();
-                while !self.is_at_end() && !self.check(Token::NewLine) {
-                    else_stmts.push(self.parse_statement()?);
-                }
+                self.parse_line_target(&mut else_stmts)?;
+                self.parse_line_statements(&mut else_stmts)?;
                 else_branch = Some(else_stmts);
             }
         } else {
@@ -515,8 +756,7 @@ impl Parser {
                     break; // This is END IF, stop here
                 }
                 // Otherwise this is just END (program end), parse it as statement
-                let stmt = self.parse_statement()?;
-                then_branch.push(stmt);
+                self.parse_statement_into(&mut then_branch)?;
             }
 
             // Parse ELSEIF branches
@@ -536,8 +776,7 @@ impl Parser {
                         break; // This is END IF, stop here
                     }
                     // Otherwise this is just END, parse it as statement
-                    let stmt = self.parse_statement()?;
-                    elseif_body.push(stmt);
+                    self.parse_statement_into(&mut elseif_body)?;
                 }
                 else_if_branches.push((elseif_cond, elseif_body));
             }
@@ -557,8 +796,7 @@ impl Parser {
                         break; // This is END IF, stop here
                     }
                     // Otherwise this is just END (program end), parse it as statement
-                    let stmt = self.parse_statement()?;
-                    else_stmts.push(stmt);
+                    self.parse_statement_into(&mut else_stmts)?;
                 }
                 else_branch = Some(else_stmts);
             }
@@ -581,6 +819,28 @@ impl Parser {
         })
     }
 
+    /// The colon-separated statements of a single-line IF branch, up to
+    /// the end of the line or an ELSE
+    /// THEN line or ELSE line: a line number alone means GOTO line
+    fn parse_line_target(&mut self, body: &mut Vec<Statement>) -> QResult<()> {
+        if matches!(self.peek_token(), Some(Token::Integer(_))) {
+            body.push(Statement::Goto { label: self.expect_label()? });
+        }
+        Ok(())
+    }
+
+    fn parse_line_statements(&mut self, body: &mut Vec<Statement>) -> QResult<()> {
+        loop {
+            while self.check(Token::Colon) {
+                self.advance();
+            }
+            if self.check(Token::Else) || self.check(Token::NewLine) || self.is_at_end() {
+                return Ok(());
+            }
+            body.push(self.parse_statement()?);
+        }
+    }
+
     fn parse_for(&mut self) -> QResult<Statement> {
         self.advance(); // FOR
         let var_name = self.expect_identifier()?;
@@ -605,7 +865,7 @@ impl Parser {
         self.in_loop = true;
 
         while !self.check(Token::Next) && !self.is_at_end() {
-            body.push(self.parse_statement()?);
+            self.parse_statement_into(&mut body)?;
             self.skip_newlines();
         }
 
@@ -629,7 +889,7 @@ impl Parser {
         self.in_loop = true;
 
         while !self.check(Token::Wend) && !self.is_at_end() {
-            body.push(self.parse_statement()?);
+            self.parse_statement_into(&mut body)?;
             self.skip_newlines();
         }
 
@@ -650,7 +910,7 @@ impl Parser {
             self.in_loop = true;
             let mut body = Vec::new();
             while !self.check(Token::Loop) && !self.is_at_end() {
-                body.push(self.parse_statement()?);
+                self.parse_statement_into(&mut body)?;
                 self.skip_newlines();
             }
             self.expect(Token::Loop)?;
@@ -665,7 +925,7 @@ impl Parser {
             self.in_loop = true;
             let mut body = Vec::new();
             while !self.check(Token::Loop) && !self.is_at_end() {
-                body.push(self.parse_statement()?);
+                self.parse_statement_into(&mut body)?;
                 self.skip_newlines();
             }
             self.expect(Token::Loop)?;
@@ -678,7 +938,7 @@ impl Parser {
         self.in_loop = true;
         let mut body = Vec::new();
         while !self.check(Token::Loop) && !self.is_at_end() {
-            body.push(self.parse_statement()?);
+            self.parse_statement_into(&mut body)?;
             self.skip_newlines();
         }
 
@@ -705,10 +965,27 @@ impl Parser {
 
     fn parse_print(&mut self) -> QResult<Statement> {
         self.advance(); // PRINT
+        let items = self.parse_print_items()?;
+        Ok(Statement::Print { items, is_question: false })
+    }
+
+    fn parse_lprint(&mut self) -> QResult<Statement> {
+        self.advance(); // LPRINT
+        let items = self.parse_print_items()?;
+        Ok(Statement::LPrint { items })
+    }
 
+    /// Expressions of PRINT, PRINT # and LPRINT, with the separators that
+    /// lay them out
+    fn parse_print_items(&mut self) -> QResult<Vec<PrintItem>> {
         let mut items = Vec::new();
+        if self.check(Token::Using) {
+            self.advance(); // USING
+            items.push(PrintItem::Using(self.parse_expression()?));
+            self.expect(Token::Semicolon)?;
+        }
 
-        while !self.check(Token::NewLine) && !self.is_at_end() {
+        while !self.at_statement_end() {
             if self.check(Token::Semicolon) {
                 self.advance();
                 items.push(PrintItem::Semicolon);
@@ -719,17 +996,27 @@ impl Parser {
                 items.push(PrintItem::Expression(self.parse_expression()?));
             }
         }
-
-        Ok(Statement::Print { items, is_question: false })
+        Ok(items)
     }
 
     fn parse_input(&mut self) -> QResult<Statement> {
         self.advance(); // INPUT
+        // INPUT ; keeps the cursor on the input line after ENTER
+        let same_line = self.check(Token::Semicolon);
+        if same_line {
+            self.advance();
+        }
+
+        // A prompt followed by ';' gets a question mark, ',' suppresses it
+        let mut question_mark = true;
         let prompt = if let Some(Token::String(s)) = self.peek_token() {
             let s = s.clone();
             self.advance();
-            if self.check(Token::Semicolon) || self.check(Token::Comma) {
+            if self.check(Token::Semicolon) {
                 self.advance();
+            } else if self.check(Token::Comma) {
+                self.advance();
+                question_mark = false;
             }
             Some(s)
         } else {
@@ -749,28 +1036,64 @@ impl Parser {
             }
         }
 
-        Ok(Statement::Input { prompt, vars })
+        Ok(Statement::Input { prompt, vars, question_mark, same_line })
     }
 
     fn parse_goto(&mut self) -> QResult<Statement> {
         self.advance(); // GOTO
-        let label = self.expect_identifier()?;
+        let label = self.expect_label()?;
         Ok(Statement::Goto { label })
     }
 
     fn parse_gosub(&mut self) -> QResult<Statement> {
         self.advance(); // GOSUB
-        let label = self.expect_identifier()?;
+        let label = self.expect_label()?;
         Ok(Statement::Gosub { label })
     }
 
+    /// Parse the tokens as one expression with nothing after it
+    pub fn parse_standalone_expression(mut self) -> QResult<Expression> {
+        self.skip_newlines();
+        let expr = self.parse_expression()?;
+        self.skip_newlines();
+        if !self.is_at_end() {
+            let (line, col) = self.current_pos();
+            return Err(QError::compile(
+                format!("Unexpected token: {}", self.found()),
+                line,
+                col
+            ));
+        }
+        Ok(expr)
+    }
+
     fn parse_expression(&mut self) -> QResult<Expression> {
-        self.parse_or()
+        self.nested(Self::parse_or)
+    }
+
+    /// Parse one level further in
+    fn nested<T>(&mut self, parse: fn(&mut Self) -> QResult<T>) -> QResult<T> {
+        self.enter()?;
+        let result = parse(self);
+        self.depth -= 1;
+        result
+    }
+
+    /// Go a level deeper, or fail when the input nests too deeply
+    fn enter(&mut self) -> QResult<()> {
+        if self.depth >= MAX_NESTING {
+            let (line, col) = self.current_pos();
+            return Err(QError::compile("Nesting too deep", line, col));
+        }
+        self.depth += 1;
+        Ok(())
     }
 
     fn parse_or(&mut self) -> QResult<Expression> {
         let mut left = self.parse_and()?;
+        let depth = self.depth;
         while self.check(Token::Or) {
+            self.enter()?;
             self.advance();
             let right = self.parse_and()?;
             left = Expression::Binary {
@@ -779,12 +1102,15 @@ impl Parser {
                 right: Box::new(right),
             };
         }
+        self.depth = depth;
         Ok(left)
     }
 
     fn parse_and(&mut self) -> QResult<Expression> {
         let mut left = self.parse_equality()?;
+        let depth = self.depth;
         while self.check(Token::And) {
+            self.enter()?;
             self.advance();
             let right = self.parse_equality()?;
             left = Expression::Binary {
@@ -793,12 +1119,15 @@ impl Parser {
                 right: Box::new(right),
             };
         }
+        self.depth = depth;
         Ok(left)
     }
 
     fn parse_equality(&mut self) -> QResult<Expression> {
         let mut left = self.parse_comparison()?;
+        let depth = self.depth;
         while let Some(op) = self.match_equality_op() {
+            self.enter()?;
             let right = self.parse_comparison()?;
             left = Expression::Binary {
                 op,
@@ -806,12 +1135,15 @@ impl Parser {
                 right: Box::new(right),
             };
         }
+        self.depth = depth;
         Ok(left)
     }
 
     fn parse_comparison(&mut self) -> QResult<Expression> {
         let mut left = self.parse_addition()?;
+        let depth = self.depth;
         while let Some(op) = self.match_comparison_op() {
+            self.enter()?;
             let right = self.parse_addition()?;
             left = Expression::Binary {
                 op,
@@ -819,12 +1151,15 @@ impl Parser {
                 right: Box::new(right),
             };
         }
+        self.depth = depth;
         Ok(left)
     }
 
     fn parse_addition(&mut self) -> QResult<Expression> {
         let mut left = self.parse_multiplication()?;
+        let depth = self.depth;
         while self.check(Token::Plus) || self.check(Token::Minus) {
+            self.enter()?;
             let op = if self.check(Token::Plus) { BinaryOp::Add } else { BinaryOp::Subtract };
             self.advance();
             let right = self.parse_multiplication()?;
@@ -834,12 +1169,15 @@ impl Parser {
                 right: Box::new(right),
             };
         }
+        self.depth = depth;
         Ok(left)
     }
 
     fn parse_multiplication(&mut self) -> QResult<Expression> {
         let mut left = self.parse_power()?;
+        let depth = self.depth;
         while self.check(Token::Multiply) || self.check(Token::Divide) || self.check(Token::IntDivide) || self.check(Token::Modulo) {
+            self.enter()?;
             let op = if self.check(Token::Multiply) {
                 BinaryOp::Multiply
             } else if self.check(Token::Divide) {
@@ -857,6 +1195,7 @@ impl Parser {
                 right: Box::new(right),
             };
         }
+        self.depth = depth;
         Ok(left)
     }
 
@@ -864,7 +1203,7 @@ impl Parser {
         let left = self.parse_unary()?;
         if self.check(Token::Power) {
             self.advance();
-            let right = self.parse_power()?; // Right-associative
+            let right = self.nested(Self::parse_power)?; // Right-associative
             Ok(Expression::Binary {
                 op: BinaryOp::Power,
                 left: Box::new(left),
@@ -878,14 +1217,14 @@ impl Parser {
     fn parse_unary(&mut self) -> QResult<Expression> {
         if self.check(Token::Minus) {
             self.advance();
-            let expr = self.parse_unary()?;
+            let expr = self.nested(Self::parse_unary)?;
             Ok(Expression::Negate(Box::new(expr)))
         } else if self.check(Token::Plus) {
             self.advance();
-            self.parse_unary()
+            self.nested(Self::parse_unary)
         } else if self.check(Token::Not) {
             self.advance();
-            let expr = self.parse_unary()?;
+            let expr = self.nested(Self::parse_unary)?;
             Ok(Expression::Not(Box::new(expr)))
         } else {
             self.parse_primary()
@@ -907,6 +1246,19 @@ impl Parser {
             }
         }
 
+        if self.check(Token::MemGet) {
+            // _MEMGET(block, offset, type): the last argument is a type name
+            self.advance();
+            self.expect(Token::LParen)?;
+            let block = Box::new(self.parse_expression()?);
+            self.expect(Token::Comma)?;
+            let offset = Box::new(self.parse_expression()?);
+            self.expect(Token::Comma)?;
+            let type_spec = Box::new(self.parse_type_spec()?);
+            self.expect(Token::RParen)?;
+            return Ok(Expression::MemGet { block, offset, type_spec });
+        }
+
         match self.peek_token() {
             Some(Token::Integer(n)) => {
                 let val = *n;
@@ -941,7 +1293,7 @@ impl Parser {
                     // Function call or array access
                     let args = self.parse_argument_list()?;
                     // Check if it's a known function
-                    if self.is_builtin_function(&name) {
+                    if builtins::lookup(&name).is_some() || self.declaration_manager.is_function(&name) {
                         Ok(Expression::FunctionCall { name, args })
                     } else {
                         Ok(Expression::ArrayAccess(
@@ -949,6 +1301,8 @@ impl Parser {
                             args
                         ))
                     }
+                } else if self.declaration_manager.is_function(&name) {
+                    Ok(Expression::FunctionCall { name, args: Vec::new() })
                 } else {
                     Ok(Expression::Variable(qb_core::data_types::VariableId::new(name, None)))
                 }
@@ -1051,6 +1405,14 @@ impl Parser {
                 self.advance();
                 Ok(TypeSpec::Simple("_FLOAT".to_string()))
             }
+            Some(Token::MemType) => {
+                self.advance();
+                Ok(TypeSpec::Simple("_MEM".to_string()))
+            }
+            Some(Token::DictType) => {
+                self.advance();
+                Ok(TypeSpec::Simple("_DICT".to_string()))
+            }
             Some(Token::Identifier(name)) => {
                 let name = name.clone();
                 self.advance();
@@ -1084,154 +1446,322 @@ impl Parser {
         let mut cases = Vec::new();
         let mut case_else = None;
         
-        // Parse CASE clauses
-        while !self.check(Token::End) && !self.is_at_end() {
+        // Parse CASE clauses; only comments may come before the first one
+        loop {
             self.skip_newlines();
-            
-            if self.check(Token::End) {
+            if self.at_end_select() || self.is_at_end() {
                 break;
             }
-            
-            if self.check(Token::Case) {
-                self.advance(); // CASE
-                
-                // Check for CASE ELSE
-                if self.check(Token::Else) {
-                    self.advance(); // ELSE
-                    self.expect_newline()?;
-                    let mut else_stmts = Vec::new();
-                    while !self.check(Token::End) && !self.check(Token::Case) && !self.is_at_end() {
-                        self.skip_newlines();
-                        if self.check(Token::End) || self.check(Token::Case) {
-                            break;
+            if self.check(Token::Rem) {
+                self.parse_statement()?;
+                continue;
+            }
+            if !self.check(Token::Case) || case_else.is_some() {
+                let (line, col) = self.current_pos();
+                return Err(QError::compile("Expected CASE or END SELECT", line, col));
+            }
+            self.advance(); // CASE
+
+            // Check for CASE ELSE
+            if self.check(Token::Else) {
+                self.advance(); // ELSE
+                self.expect_newline()?;
+                case_else = Some(self.parse_case_body()?);
+                continue;
+            }
+
+            // Parse case conditions
+            let mut conditions = Vec::new();
+            loop {
+                // Check for IS keyword
+                if self.check(Token::Is) {
+                    self.advance(); // IS
+                    let op = match self.peek_token() {
+                        Some(token @ (Token::Equal | Token::NotEqual | Token::Less | Token::LessEqual
+                            | Token::Greater | Token::GreaterEqual)) => token.clone(),
+                        _ => {
+                            let (line, col) = self.current_pos();
+                            return Err(QError::compile("Expected relational operator after IS", line, col));
                         }
-                        let stmt = self.parse_statement()?;
-                        else_stmts.push(stmt);
+                    };
+                    self.advance();
+                    let expr2 = self.parse_expression()?;
+                    conditions.push(CaseCondition::Is(op, expr2));
+                }
+                // Check for range (e.g., 1 TO 10)
+                else {
+                    let expr1 = self.parse_expression()?;
+                    if self.check(Token::To) {
+                        self.advance(); // TO
+                        let expr2 = self.parse_expression()?;
+                        conditions.push(CaseCondition::Range(expr1, expr2));
+                    } else {
+                        conditions.push(CaseCondition::Expression(expr1));
                     }
-                    case_else = Some(else_stmts);
+                }
+
+                if self.check(Token::Comma) {
+                    self.advance(); // Comma for multiple conditions
                 } else {
-                    // Parse case conditions
-                    let mut conditions = Vec::new();
-                    
-                    loop {
-                        // Check for IS keyword
-                        if self.check(Token::Is) {
-                            self.advance(); // IS
-                            let op = if let Some(token) = self.peek_token() {
-                                token.clone()
-                            } else {
-                                break;
-                            };
-                            self.advance();
-                            let expr2 = self.parse_expression()?;
-                            conditions.push(CaseCondition::Is(op, expr2));
-                        } 
-                        // Check for range (e.g., 1 TO 10)
-                        else {
-                            let expr1 = self.parse_expression()?;
-                            if self.check(Token::To) {
-                                self.advance(); // TO
-                                let expr2 = self.parse_expression()?;
-                                conditions.push(CaseCondition::Range(expr1, expr2));
-                            } else {
-                                conditions.push(CaseCondition::Expression(expr1));
-                            }
-                        }
-                        
-                        if self.check(Token::Comma) {
-                            self.advance(); // Comma for multiple conditions
-                        } else {
-                            break;
-                        }
-                    }
-                    
-                    self.expect_newline()?;
-                    
-                    // Parse case body
-                    let mut body = Vec::new();
-                    while !self.check(Token::End) && !self.check(Token::Case) && !self.is_at_end() {
-                        self.skip_newlines();
-                        if self.check(Token::End) || self.check(Token::Case) {
-                            break;
-                        }
-                        let stmt = self.parse_statement()?;
-                        body.push(stmt);
-                    }
-                    
-                    cases.push(CaseClause { conditions, body });
+                    break;
                 }
-            } else {
-                // Unexpected token, skip
-                self.advance();
             }
+
+            self.expect_newline()?;
+            let body = self.parse_case_body()?;
+            cases.push(CaseClause { conditions, body });
         }
-        
+
         self.expect(Token::End)?;
-        self.skip_newlines();
-        if self.check(Token::Select) {
-            self.advance();
-        }
-        
+        self.expect(Token::Select)?;
+
         Ok(Statement::Select { expr, cases, case_else })
     }
 
+    /// Statements of one CASE section, up to the next CASE or END SELECT.
+    /// A bare END here ends the program, not the SELECT; an empty section is fine.
+    fn parse_case_body(&mut self) -> QResult<Vec<Statement>> {
+        let mut body = Vec::new();
+        loop {
+            self.skip_newlines();
+            if self.check(Token::Case) || self.at_end_select() || self.is_at_end() {
+                break;
+            }
+            self.parse_statement_into(&mut body)?;
+        }
+        Ok(body)
+    }
+
+    fn at_end_select(&self) -> bool {
+        self.check(Token::End) && self.peek_next_token() == Some(&Token::Select)
+    }
+
     fn parse_on(&mut self) -> QResult<Statement> {
+        if matches!(self.peek_next_token(), Some(Token::Error)) {
+            return self.parse_on_error();
+        }
         self.advance(); // ON
-        let _expr = self.parse_expression()?;
-        // Simplified - just consume tokens
-        while !self.check(Token::NewLine) && !self.is_at_end() {
+        let source = match self.peek_token() {
+            Some(Token::Strig) => Some(EventSource::Strig),
+            Some(Token::Timer) => Some(EventSource::Timer),
+            Some(Token::Identifier(name))
+                if name.eq_ignore_ascii_case("KEY") && matches!(self.peek_next_token(), Some(Token::LParen)) =>
+            {
+                Some(EventSource::Key)
+            }
+            _ => None,
+        };
+        if let Some(source) = source {
             self.advance();
+            let arg = self.parse_event_arg()?;
+            self.expect(Token::GoSub)?;
+            let label = self.expect_label()?;
+            return Ok(Statement::OnEvent { source, arg, label });
         }
-        Ok(Statement::Rem(String::from("ON GOTO/GOSUB")))
-    }
-
-    fn parse_sub(&mut self) -> QResult<Statement> {
-        self.advance(); // SUB
-        let name = self.expect_identifier()?;
-        let params = if self.check(Token::LParen) {
-            self.parse_param_list()?
+        // ON n GOTO | GOSUB label, label, ...
+        let expr = self.parse_expression()?;
+        let gosub = self.check(Token::GoSub);
+        if !gosub {
+            self.expect(Token::GoTo)?;
         } else {
-            Vec::new()
-        };
-        self.expect_newline()?;
-        
-        self.in_sub = true;
-        let mut body = Vec::new();
-        while !self.check(Token::End) && !self.is_at_end() {
-            body.push(self.parse_statement()?);
-            self.skip_newlines();
+            self.advance();
         }
-        self.expect(Token::End)?;
-        if self.check(Token::Sub) {
+        let mut labels = vec![self.expect_label()?];
+        while self.check(Token::Comma) {
             self.advance();
+            labels.push(self.expect_label()?);
         }
-        self.in_sub = false;
-        
-        Ok(Statement::Sub { name, params, body, is_static: false })
+        Ok(if gosub { Statement::OnGosub { expr, labels } } else { Statement::OnGoto { expr, labels } })
     }
 
-    fn parse_function(&mut self) -> QResult<Statement> {
-        self.advance(); // FUNCTION
-        let name = self.expect_identifier()?;
-        let params = if self.check(Token::LParen) {
-            self.parse_param_list()?
-        } else {
-            Vec::new()
-        };
-        
+    /// Optional parenthesized argument of an event trap, e.g. the n in STRIG(n)
+    fn parse_event_arg(&mut self) -> QResult<Option<Expression>> {
+        if !self.check(Token::LParen) {
+            return Ok(None);
+        }
+        self.advance();
+        let arg = self.parse_expression()?;
+        self.expect(Token::RParen)?;
+        Ok(Some(arg))
+    }
+
+    /// Whether KEY at the current token starts KEY(n) ON | OFF | STOP rather
+    /// than an assignment to an array named KEY
+    fn is_key_control(&self) -> bool {
+        if !matches!(self.peek_next_token(), Some(Token::LParen)) {
+            return false;
+        }
+        let mut depth = 0;
+        let mut index = self.current + 1;
+        while let Some(info) = self.tokens.get(index) {
+            match info.token {
+                Token::LParen => depth += 1,
+                Token::RParen => depth -= 1,
+                Token::NewLine | Token::EOF => return false,
+                _ => {}
+            }
+            index += 1;
+            if depth == 0 {
+                break;
+            }
+        }
+        match self.tokens.get(index).map(|info| &info.token) {
+            Some(Token::On) | Some(Token::Stop) => true,
+            Some(Token::Identifier(word)) => word.eq_ignore_ascii_case("OFF"),
+            _ => false,
+        }
+    }
+
+    /// ON | OFF | STOP after STRIG(n), KEY(n) or TIMER; the keyword is consumed
+    fn parse_event_control(&mut self, source: EventSource) -> QResult<Statement> {
+        let arg = self.parse_event_arg()?;
+        let state = match self.peek_token() {
+            Some(Token::On) => EventState::On,
+            Some(Token::Identifier(word)) if word.eq_ignore_ascii_case("OFF") => EventState::Off,
+            Some(Token::Stop) => EventState::Stop,
+            _ => {
+                let (line, col) = self.current_pos();
+                return Err(QError::compile("Expected ON, OFF or STOP", line, col));
+            }
+        };
+        self.advance();
+        Ok(Statement::EventControl { source, arg, state })
+    }
+
+    fn parse_screen_move(&mut self) -> QResult<Statement> {
+        self.advance(); // _SCREENMOVE
+        if self.check(Token::Middle) {
+            self.advance();
+            return Ok(Statement::ScreenMove { position: None });
+        }
+        let x = self.parse_expression()?;
+        self.expect(Token::Comma)?;
+        let y = self.parse_expression()?;
+        Ok(Statement::ScreenMove { position: Some((x, y)) })
+    }
+
+    /// _CONSOLE ON | OFF
+    fn parse_console(&mut self) -> QResult<Statement> {
+        self.advance(); // _CONSOLE
+        let visible = match self.peek_token() {
+            Some(Token::On) => true,
+            Some(Token::Identifier(word)) if word.eq_ignore_ascii_case("OFF") => false,
+            _ => {
+                let (line, col) = self.current_pos();
+                return Err(QError::compile("Expected ON or OFF after _CONSOLE", line, col));
+            }
+        };
+        self.advance();
+        Ok(Statement::Console { visible })
+    }
+
+    /// _RESIZE ON | OFF [, _STRETCH | _SMOOTH]
+    fn parse_resize(&mut self) -> QResult<Statement> {
+        self.advance(); // _RESIZE
+        let enabled = match self.peek_token() {
+            Some(Token::On) => true,
+            Some(Token::Identifier(word)) if word.eq_ignore_ascii_case("OFF") => false,
+            _ => {
+                let (line, col) = self.current_pos();
+                return Err(QError::compile("Expected ON or OFF after _RESIZE", line, col));
+            }
+        };
+        self.advance();
+        let mode = if self.check(Token::Comma) {
+            self.advance();
+            match self.peek_token() {
+                Some(Token::Stretch) => { self.advance(); Some(ResizeMode::Stretch) }
+                Some(Token::Smooth) => { self.advance(); Some(ResizeMode::Smooth) }
+                _ => {
+                    let (line, col) = self.current_pos();
+                    return Err(QError::compile("Expected _STRETCH or _SMOOTH", line, col));
+                }
+            }
+        } else {
+            None
+        };
+        Ok(Statement::Resize { enabled, mode })
+    }
+
+    /// _FULLSCREEN [_STRETCH | _SQUAREPIXELS | _OFF] [, _SMOOTH]
+    fn parse_full_screen(&mut self) -> QResult<Statement> {
+        self.advance(); // _FULLSCREEN
+        let mode = match self.peek_token() {
+            Some(Token::Stretch) => FullScreenMode::Stretch,
+            Some(Token::Identifier(word)) if word.eq_ignore_ascii_case("_SQUAREPIXELS") => FullScreenMode::SquarePixels,
+            Some(Token::Identifier(word)) if word.eq_ignore_ascii_case("_OFF") => FullScreenMode::Off,
+            _ => FullScreenMode::Auto,
+        };
+        if mode != FullScreenMode::Auto {
+            self.advance();
+        }
+        let smooth = self.check(Token::Comma);
+        if smooth {
+            self.advance();
+            if !self.check(Token::Smooth) {
+                let (line, col) = self.current_pos();
+                return Err(QError::compile("Expected _SMOOTH", line, col));
+            }
+            self.advance();
+        }
+        Ok(Statement::FullScreen { mode, smooth })
+    }
+
+    fn parse_sub(&mut self) -> QResult<Statement> {
+        self.advance(); // SUB
+        let name = self.expect_identifier()?;
+        let params = if self.check(Token::LParen) {
+            self.parse_param_list()?
+        } else {
+            Vec::new()
+        };
+        let is_static = self.check(Token::Static);
+        if is_static {
+            self.advance();
+        }
+        self.expect_newline()?;
+        
+        self.in_sub = true;
+        let mut body = Vec::new();
+        while !self.check(Token::End) && !self.is_at_end() {
+            self.parse_statement_into(&mut body)?;
+            self.skip_newlines();
+        }
+        self.expect(Token::End)?;
+        if self.check(Token::Sub) {
+            self.advance();
+        }
+        self.in_sub = false;
+        
+        Ok(Statement::Sub { name, params, body, is_static })
+    }
+
+    fn parse_function(&mut self) -> QResult<Statement> {
+        self.advance(); // FUNCTION
+        let name = self.expect_identifier()?;
+        let params = if self.check(Token::LParen) {
+            self.parse_param_list()?
+        } else {
+            Vec::new()
+        };
+        
         let return_type = if self.check(Token::As) {
             self.advance();
             Some(self.parse_type_spec()?)
         } else {
             None
         };
+        let is_static = self.check(Token::Static);
+        if is_static {
+            self.advance();
+        }
         
         self.expect_newline()?;
         
         self.in_function = true;
         let mut body = Vec::new();
         while !self.check(Token::End) && !self.is_at_end() {
-            body.push(self.parse_statement()?);
+            self.parse_statement_into(&mut body)?;
             self.skip_newlines();
         }
         self.expect(Token::End)?;
@@ -1240,10 +1770,10 @@ impl Parser {
         }
         self.in_function = false;
         
-        Ok(Statement::Function { name, params, return_type, body, is_static: false })
+        Ok(Statement::Function { name, params, return_type, body, is_static })
     }
 
-    fn parse_param_list(&mut self) -> QResult<Vec<ParamType>> {
+    fn parse_param_list(&mut self) -> QResult<Vec<Param>> {
         self.expect(Token::LParen)?;
         let mut params = Vec::new();
         
@@ -1262,14 +1792,33 @@ impl Parser {
                 }
                 
                 let name = self.expect_identifier()?;
-                let suffix = self.parse_optional_suffix();
+                let mut suffix = self.parse_optional_suffix();
+                // a() takes a whole array
+                let is_array = self.check(Token::LParen);
+                if is_array {
+                    self.advance();
+                    self.expect(Token::RParen)?;
+                }
+                // n AS INTEGER is recorded as n with the INTEGER suffix
+                let mut type_spec = None;
+                if self.check(Token::As) {
+                    self.advance();
+                    let spec = self.parse_type_spec()?;
+                    if let TypeSpec::Simple(_) | TypeSpec::FixedString(_) = spec {
+                        suffix = Some(self.declaration_manager.type_spec_to_suffix(&spec));
+                    }
+                    type_spec = Some(spec);
+                }
                 let var = qb_core::data_types::VariableId::new(name, suffix);
                 
-                if by_val {
-                    params.push(ParamType::ByVal(var));
+                let kind = if is_array {
+                    ParamType::Array(var)
+                } else if by_val {
+                    ParamType::ByVal(var)
                 } else {
-                    params.push(ParamType::ByRef(var));
-                }
+                    ParamType::ByRef(var)
+                };
+                params.push(Param { kind, type_spec });
                 
                 if self.check(Token::Comma) {
                     self.advance();
@@ -1304,11 +1853,33 @@ impl Parser {
         self.advance(); // CALL
         let name = self.expect_identifier()?;
         let args = if self.check(Token::LParen) {
-            self.parse_argument_list()?
+            self.parse_call_arguments()?
         } else {
             Vec::new()
         };
-        Ok(Statement::Call { name, args: args.into_iter().map(Argument::ByVal).collect() })
+        Ok(Statement::Call { name, args })
+    }
+
+    /// Parenthesized CALL arguments, noting which are in parentheses of
+    /// their own and so passed by value
+    fn parse_call_arguments(&mut self) -> QResult<Vec<Argument>> {
+        self.expect(Token::LParen)?;
+        let mut args = Vec::new();
+
+        if !self.check(Token::RParen) {
+            loop {
+                let parenthesized = self.check(Token::LParen);
+                args.push(Argument::new(self.parse_expression()?, parenthesized));
+                if self.check(Token::Comma) {
+                    self.advance();
+                } else {
+                    break;
+                }
+            }
+        }
+
+        self.expect(Token::RParen)?;
+        Ok(args)
     }
 
     fn parse_exit(&mut self) -> QResult<Statement> {
@@ -1327,6 +1898,15 @@ impl Parser {
 
     fn parse_line_input(&mut self) -> QResult<Statement> {
         self.advance(); // LINE INPUT
+        if self.check(Token::Hash) {
+            self.advance();
+            let fileno = self.parse_expression()?;
+            self.expect(Token::Comma)?;
+            let name = self.expect_identifier()?;
+            let suffix = self.parse_optional_suffix();
+            let var = qb_core::data_types::VariableId::new(name, suffix);
+            return Ok(Statement::LineInputHash { fileno, var });
+        }
         let prompt = if let Some(Token::String(s)) = self.peek_token() {
             let s = s.clone();
             self.advance();
@@ -1344,9 +1924,17 @@ impl Parser {
     }
 
     fn parse_write(&mut self) -> QResult<Statement> {
-        self.advance(); // WRITE
+        let fileno = if self.check(Token::WriteHash) {
+            self.advance(); // WRITE #
+            let fileno = self.parse_expression()?;
+            self.expect(Token::Comma)?;
+            Some(fileno)
+        } else {
+            self.advance(); // WRITE
+            None
+        };
         let mut items = Vec::new();
-        while !self.check(Token::NewLine) && !self.is_at_end() {
+        while !self.at_statement_end() {
             items.push(self.parse_expression()?);
             if self.check(Token::Comma) {
                 self.advance();
@@ -1354,7 +1942,7 @@ impl Parser {
                 break;
             }
         }
-        Ok(Statement::Write { items })
+        Ok(Statement::Write { fileno, items })
     }
 
     fn parse_open(&mut self) -> QResult<Statement> {
@@ -1370,11 +1958,42 @@ impl Parser {
                 Some(Token::Append) => { self.advance(); FileMode::Append }
                 Some(Token::Random) => { self.advance(); FileMode::Random }
                 Some(Token::Binary) => { self.advance(); FileMode::Binary }
+                Some(Token::Identifier(word)) if word.eq_ignore_ascii_case("ISAM") => {
+                    self.advance();
+                    return self.parse_open_isam(filename);
+                }
                 _ => FileMode::Random,
             }
         } else {
             FileMode::Random
         };
+
+        // ACCESS READ | WRITE | READ WRITE
+        let access = if self.check(Token::Access) {
+            self.advance();
+            Some(match self.parse_read_write()? {
+                (true, true) => FileAccess::ReadWrite,
+                (true, false) => FileAccess::Read,
+                _ => FileAccess::Write,
+            })
+        } else {
+            None
+        };
+
+        // SHARED | LOCK READ | LOCK WRITE | LOCK READ WRITE
+        let lock = if self.check(Token::Shared) {
+            self.advance();
+            Some(FileLock::Shared)
+        } else if self.check(Token::Lock) {
+            self.advance();
+            Some(match self.parse_read_write()? {
+                (true, true) => FileLock::ReadWrite,
+                (true, false) => FileLock::Read,
+                _ => FileLock::Write,
+            })
+        } else {
+            None
+        };
         
         // Parse AS #fileno
         let fileno = if self.check(Token::As) {
@@ -1386,42 +2005,209 @@ impl Parser {
         } else {
             Expression::Integer(1)
         };
+
+        // LEN = reclen
+        let reclen = if self.check(Token::Len) {
+            self.advance();
+            self.expect(Token::Equal)?;
+            Some(self.parse_expression()?)
+        } else {
+            None
+        };
         
-        Ok(Statement::Open { filename, mode, fileno, reclen: None })
+        Ok(Statement::Open { filename, mode, fileno, access, lock, reclen })
+    }
+
+    /// The rest of OPEN database FOR ISAM tabletype table AS #n
+    fn parse_open_isam(&mut self, database: Expression) -> QResult<Statement> {
+        let table_type = match self.peek_token() {
+            Some(Token::Identifier(name)) => name.clone(),
+            _ => {
+                let (line, col) = self.current_pos();
+                return Err(QError::compile("Expected the table's type after ISAM", line, col));
+            }
+        };
+        self.advance();
+        let table = self.parse_expression()?;
+        self.expect(Token::As)?;
+        if self.check(Token::Hash) {
+            self.advance();
+        }
+        let fileno = self.parse_expression()?;
+        Ok(Statement::OpenIsam { database, table_type, table, fileno })
+    }
+
+    /// The ISAM statement the current identifier starts, if it is one.
+    /// Those on a file need the # before the number, so the names stay
+    /// free for variables and SUBs.
+    fn isam_keyword(&self) -> Option<&'static str> {
+        const ON_FILE: [&str; 14] = [
+            "CREATEINDEX", "DELETEINDEX", "SETINDEX", "SEEKEQ", "SEEKGE", "SEEKGT", "INSERT", "UPDATE",
+            "RETRIEVE", "DELETE", "MOVEFIRST", "MOVELAST", "MOVENEXT", "MOVEPREVIOUS",
+        ];
+        const OTHERS: [&str; 5] = ["BEGINTRANS", "COMMITTRANS", "CHECKPOINT", "ROLLBACK", "DELETETABLE"];
+        let Some(Token::Identifier(name)) = self.peek_token() else {
+            return None;
+        };
+        let next = self.peek_next_token();
+        if let Some(keyword) = ON_FILE.iter().find(|keyword| name.eq_ignore_ascii_case(keyword)) {
+            return matches!(next, Some(Token::Hash)).then_some(*keyword);
+        }
+        let keyword = OTHERS.iter().find(|keyword| name.eq_ignore_ascii_case(keyword))?;
+        (!matches!(next, Some(Token::Equal) | Some(Token::LParen))).then_some(*keyword)
+    }
+
+    fn parse_isam(&mut self) -> QResult<Statement> {
+        let mut keyword = self.isam_keyword().unwrap_or_default().to_string();
+        self.advance();
+        if keyword == "ROLLBACK" && matches!(self.peek_token(), Some(Token::Identifier(all)) if all.eq_ignore_ascii_case("ALL")) {
+            self.advance();
+            keyword.push_str(" ALL");
+        }
+        let fileno = if self.check(Token::Hash) {
+            self.advance();
+            Some(self.parse_expression()?)
+        } else {
+            None
+        };
+        let mut args = Vec::new();
+        if fileno.is_none() && !self.at_statement_end() {
+            args.push(self.parse_expression()?);
+        }
+        while self.check(Token::Comma) {
+            self.advance();
+            args.push(self.parse_expression()?);
+        }
+        Ok(Statement::Isam { keyword, fileno, args })
+    }
+
+    /// READ, WRITE or READ WRITE after ACCESS or LOCK, as (read, write)
+    fn parse_read_write(&mut self) -> QResult<(bool, bool)> {
+        let read = self.check(Token::Read);
+        if read {
+            self.advance();
+        }
+        let write = self.check(Token::Write);
+        if write {
+            self.advance();
+        }
+        if !read && !write {
+            let (line, col) = self.current_pos();
+            return Err(QError::compile("Expected READ or WRITE", line, col));
+        }
+        Ok((read, write))
     }
 
     fn parse_close(&mut self) -> QResult<Statement> {
         self.advance(); // CLOSE
+        // CLOSE #n, or CLOSE handle for a connection
         let fileno = if self.check(Token::Hash) {
             self.advance();
             Some(self.parse_expression()?)
+        } else if !self.at_statement_end() {
+            Some(self.parse_expression()?)
         } else {
             None
         };
         Ok(Statement::Close { fileno })
     }
 
+    fn parse_kill(&mut self) -> QResult<Statement> {
+        self.advance(); // KILL
+        let filespec = self.parse_expression()?;
+        Ok(Statement::Kill { filespec })
+    }
+
     fn parse_get(&mut self) -> QResult<Statement> {
         self.advance(); // GET
-        // Simplified
-        while !self.check(Token::NewLine) && !self.is_at_end() {
-            self.advance();
+        if self.check(Token::LParen) {
+            // Graphics GET (x1, y1)-(x2, y2), array is not supported
+            self.skip_to_statement_end();
+            return Ok(Statement::Rem(String::from("GET")));
         }
-        Ok(Statement::Get { fileno: Expression::Integer(1), record: None, var: qb_core::data_types::VariableId::new("X", None) })
+        let (fileno, record, var) = self.parse_record_io()?;
+        Ok(Statement::Get { fileno, record, var })
     }
 
     fn parse_put(&mut self) -> QResult<Statement> {
         self.advance(); // PUT
-        while !self.check(Token::NewLine) && !self.is_at_end() {
+        if self.check(Token::LParen) {
+            // Graphics PUT (x, y), array is not supported
+            self.skip_to_statement_end();
+            return Ok(Statement::Rem(String::from("PUT")));
+        }
+        let (fileno, record, var) = self.parse_record_io()?;
+        Ok(Statement::Put { fileno, record, var })
+    }
+
+    /// The `[#]n[, [record][, variable]]` of GET and PUT
+    fn parse_record_io(&mut self) -> QResult<(Expression, Option<Expression>, Option<qb_core::data_types::VariableId>)> {
+        if self.check(Token::Hash) {
+            self.advance();
+        }
+        let fileno = self.parse_expression()?;
+        if !self.check(Token::Comma) {
+            return Ok((fileno, None, None));
+        }
+        self.advance(); // ,
+        let record = if self.check(Token::Comma) || self.at_statement_end() {
+            None
+        } else {
+            Some(self.parse_expression()?)
+        };
+        if !self.check(Token::Comma) {
+            return Ok((fileno, record, None));
+        }
+        self.advance(); // ,
+        let name = self.expect_identifier()?;
+        let suffix = self.parse_optional_suffix();
+        Ok((fileno, record, Some(qb_core::data_types::VariableId::new(name, suffix))))
+    }
+
+    fn parse_field(&mut self) -> QResult<Statement> {
+        self.advance(); // FIELD
+        if self.check(Token::Hash) {
+            self.advance();
+        }
+        let fileno = self.parse_expression()?;
+        let mut fields = Vec::new();
+        while self.check(Token::Comma) {
+            self.advance();
+            let width = self.parse_expression()?;
+            self.expect(Token::As)?;
+            let name = self.expect_identifier()?;
+            let suffix = self.parse_optional_suffix();
+            fields.push((width, qb_core::data_types::VariableId::new(name, suffix)));
+        }
+        Ok(Statement::Field { fileno, fields })
+    }
+
+    /// LSET or RSET target = value
+    fn parse_lset(&mut self) -> QResult<Statement> {
+        let right = self.check(Token::RSet);
+        self.advance(); // LSET or RSET
+        let name = self.expect_identifier()?;
+        let var = qb_core::data_types::VariableId::new(name, None);
+        let target = if self.check(Token::LParen) {
+            LValue::ArrayElement(var, self.parse_array_indices()?)
+        } else {
+            LValue::Variable(var)
+        };
+        self.expect(Token::Equal)?;
+        let value = self.parse_expression()?;
+        Ok(if right { Statement::RSet { target, value } } else { Statement::LSet { target, value } })
+    }
+
+    fn skip_to_statement_end(&mut self) {
+        while !self.at_statement_end() {
             self.advance();
         }
-        Ok(Statement::Put { fileno: Expression::Integer(1), record: None, var: qb_core::data_types::VariableId::new("X", None) })
     }
 
     fn parse_seek(&mut self) -> QResult<Statement> {
         self.advance(); // SEEK
         // Simplified
-        while !self.check(Token::NewLine) && !self.is_at_end() {
+        while !self.at_statement_end() {
             self.advance();
         }
         Ok(Statement::Seek { fileno: Expression::Integer(1), position: Expression::Integer(1) })
@@ -1429,7 +2215,7 @@ impl Parser {
 
     fn parse_lock(&mut self) -> QResult<Statement> {
         self.advance(); // LOCK
-        while !self.check(Token::NewLine) && !self.is_at_end() {
+        while !self.at_statement_end() {
             self.advance();
         }
         Ok(Statement::Lock { fileno: Expression::Integer(1), record: None })
@@ -1437,7 +2223,7 @@ impl Parser {
 
     fn parse_unlock(&mut self) -> QResult<Statement> {
         self.advance(); // UNLOCK
-        while !self.check(Token::NewLine) && !self.is_at_end() {
+        while !self.at_statement_end() {
             self.advance();
         }
         Ok(Statement::Unlock { fileno: Expression::Integer(1), record: None })
@@ -1447,18 +2233,7 @@ impl Parser {
         self.advance(); // PRINT #
         let fileno = self.parse_expression()?;
         self.expect(Token::Comma)?;
-        let mut items = Vec::new();
-        while !self.check(Token::NewLine) && !self.is_at_end() {
-            if self.check(Token::Semicolon) {
-                self.advance();
-                items.push(PrintItem::Semicolon);
-            } else if self.check(Token::Comma) {
-                self.advance();
-                items.push(PrintItem::Comma);
-            } else {
-                items.push(PrintItem::Expression(self.parse_expression()?));
-            }
-        }
+        let items = self.parse_print_items()?;
         Ok(Statement::PrintHash { fileno, items })
     }
 
@@ -1486,11 +2261,156 @@ impl Parser {
         Ok(Statement::Screen { mode })
     }
 
-    fn parse_pset(&mut self) -> QResult<Statement> {
-        self.advance(); // PSET
+    /// Point as written in graphics statements: (x, y), or x, y
+    fn parse_point(&mut self) -> QResult<(Expression, Expression)> {
+        let parenthesized = self.check(Token::LParen);
+        if parenthesized {
+            self.advance();
+        }
         let x = self.parse_expression()?;
         self.expect(Token::Comma)?;
         let y = self.parse_expression()?;
+        if parenthesized {
+            self.expect(Token::RParen)?;
+        }
+        Ok((x, y))
+    }
+
+    /// _PUTIMAGE [(dx1, dy1)[-(dx2, dy2)]][, [source][, [dest][, (sx1, sy1)[-(sx2, sy2)]]]]
+    fn parse_put_image(&mut self) -> QResult<Statement> {
+        self.advance(); // _PUTIMAGE
+        let area = if self.check(Token::LParen) {
+            Some(self.parse_image_area()?)
+        } else {
+            None
+        };
+        let mut handles = [None, None];
+        for handle in &mut handles {
+            if !self.check(Token::Comma) {
+                break;
+            }
+            self.advance();
+            if !self.check(Token::Comma) && !self.at_statement_end() {
+                *handle = Some(self.parse_expression()?);
+            }
+        }
+        let [source, dest] = handles;
+        let source_area = if self.check(Token::Comma) {
+            self.advance();
+            Some(self.parse_image_area()?)
+        } else {
+            None
+        };
+        Ok(Statement::PutImage { area, source, dest, source_area })
+    }
+
+    /// _SAVEIMAGE filename$[, handle]
+    fn parse_save_image(&mut self) -> QResult<Statement> {
+        self.advance(); // _SAVEIMAGE
+        let filename = self.parse_expression()?;
+        let handle = if self.check(Token::Comma) {
+            self.advance();
+            Some(self.parse_expression()?)
+        } else {
+            None
+        };
+        Ok(Statement::SaveImage { filename, handle })
+    }
+
+    /// _PRINTSTRING (x, y), text$[, handle]
+    fn parse_print_string(&mut self) -> QResult<Statement> {
+        self.advance(); // _PRINTSTRING
+        let (x, y) = self.parse_point()?;
+        self.expect(Token::Comma)?;
+        let text = self.parse_expression()?;
+        let handle = if self.check(Token::Comma) {
+            self.advance();
+            Some(self.parse_expression()?)
+        } else {
+            None
+        };
+        Ok(Statement::PrintString { x, y, text, handle })
+    }
+
+    /// _MEMGET block, offset, variable
+    fn parse_mem_get(&mut self) -> QResult<Statement> {
+        self.advance(); // _MEMGET
+        let block = self.parse_expression()?;
+        self.expect(Token::Comma)?;
+        let offset = self.parse_expression()?;
+        self.expect(Token::Comma)?;
+        let var = self.parse_variable_id()?;
+        Ok(Statement::MemGet { block, offset, var })
+    }
+
+    /// MAT array = ZER | CON | (value) | array
+    fn parse_mat(&mut self) -> QResult<Statement> {
+        self.advance(); // MAT
+        let target = self.parse_variable_id()?;
+        self.expect(Token::Equal)?;
+        let source = match self.peek_token() {
+            Some(Token::LParen) => MatSource::Fill(self.parse_expression()?),
+            Some(Token::Identifier(name)) if name.eq_ignore_ascii_case("ZER") => {
+                self.advance();
+                MatSource::Zero
+            }
+            Some(Token::Identifier(name)) if name.eq_ignore_ascii_case("CON") => {
+                self.advance();
+                MatSource::Ones
+            }
+            _ => MatSource::Copy(self.parse_variable_id()?),
+        };
+        Ok(Statement::Mat { target, source })
+    }
+
+    fn parse_variable_id(&mut self) -> QResult<qb_core::data_types::VariableId> {
+        let name = self.expect_identifier()?;
+        let suffix = self.parse_optional_suffix();
+        Ok(qb_core::data_types::VariableId::new(name, suffix))
+    }
+
+    /// _MEMPUT block, offset, value [AS type]
+    fn parse_mem_put(&mut self) -> QResult<Statement> {
+        self.advance(); // _MEMPUT
+        let block = self.parse_expression()?;
+        self.expect(Token::Comma)?;
+        let offset = self.parse_expression()?;
+        self.expect(Token::Comma)?;
+        let value = self.parse_expression()?;
+        let type_spec = if self.check(Token::As) {
+            self.advance();
+            Some(self.parse_type_spec()?)
+        } else {
+            None
+        };
+        Ok(Statement::MemPut { block, offset, value, type_spec })
+    }
+
+    fn parse_image_area(&mut self) -> QResult<ImageArea> {
+        self.expect(Token::LParen)?;
+        let corner = self.parse_point()?;
+        self.expect(Token::RParen)?;
+        let opposite = if self.check(Token::Minus) {
+            self.advance();
+            self.expect(Token::LParen)?;
+            let point = self.parse_point()?;
+            self.expect(Token::RParen)?;
+            Some(point)
+        } else {
+            None
+        };
+        Ok(ImageArea { corner, opposite })
+    }
+
+    /// Whether the statement being parsed ends here: at the end of the line,
+    /// at a colon before the next statement, or at the ELSE of a single-line IF
+    fn at_statement_end(&self) -> bool {
+        self.is_at_end() || matches!(self.peek_token(), Some(Token::NewLine) | Some(Token::Colon) | Some(Token::Else))
+    }
+
+    fn parse_pset(&mut self) -> QResult<Statement> {
+        self.advance(); // PSET
+        let (x, y) = self.parse_point()?;
         let color = if self.check(Token::Comma) {
             self.advance();
             Some(self.parse_expression()?)
@@ -1502,27 +2422,76 @@ impl Parser {
 
     fn parse_preset(&mut self) -> QResult<Statement> {
         self.advance(); // PRESET
-        let x = self.parse_expression()?;
-        self.expect(Token::Comma)?;
-        let y = self.parse_expression()?;
+        let (x, y) = self.parse_point()?;
         Ok(Statement::PReset { x, y })
     }
 
+    /// LINE [[STEP](x1, y1)]-[STEP](x2, y2)[, [color][, [B|BF][, style]]]
     fn parse_line(&mut self) -> QResult<Statement> {
         self.advance(); // LINE
-        // Simplified
-        while !self.check(Token::NewLine) && !self.is_at_end() {
+        let (step1, (x1, y1)) = if self.check(Token::Minus) {
+            (true, (Expression::Integer(0), Expression::Integer(0)))
+        } else {
+            self.parse_step_point()?
+        };
+        self.expect(Token::Minus)?;
+        let (step2, (x2, y2)) = self.parse_step_point()?;
+
+        let mut color = None;
+        let mut style = None;
+        let (mut is_box, mut is_filled) = (false, false);
+        if self.check(Token::Comma) {
+            self.advance();
+            if !self.check(Token::Comma) && !self.at_statement_end() {
+                color = Some(self.parse_expression()?);
+            }
+        }
+        if self.check(Token::Comma) {
+            self.advance();
+            if let Some(Token::Identifier(shape)) = self.peek_token() {
+                is_filled = shape.eq_ignore_ascii_case("BF");
+                is_box = is_filled || shape.eq_ignore_ascii_case("B");
+                if !is_box {
+                    let (line, col) = self.current_pos();
+                    return Err(QError::compile("Expected B or BF", line, col));
+                }
+                self.advance();
+            }
+        }
+        if self.check(Token::Comma) {
             self.advance();
+            style = Some(self.parse_expression()?);
         }
-        Ok(Statement::Rem(String::from("LINE")))
+        Ok(Statement::Line { x1, y1, step1, x2, y2, step2, color, style, is_box, is_filled })
     }
 
+    /// [STEP](x, y)
+    fn parse_step_point(&mut self) -> QResult<(bool, (Expression, Expression))> {
+        let step = self.check(Token::Step);
+        if step {
+            self.advance();
+        }
+        Ok((step, self.parse_point()?))
+    }
+
+    /// CIRCLE [STEP](x, y), radius[, [color][, [start][, [end][, aspect]]]]
     fn parse_circle(&mut self) -> QResult<Statement> {
         self.advance(); // CIRCLE
-        while !self.check(Token::NewLine) && !self.is_at_end() {
+        let (step, (x, y)) = self.parse_step_point()?;
+        self.expect(Token::Comma)?;
+        let radius = self.parse_expression()?;
+        let mut optional = [None, None, None, None];
+        for arg in optional.iter_mut() {
+            if !self.check(Token::Comma) {
+                break;
+            }
             self.advance();
+            if !self.check(Token::Comma) && !self.at_statement_end() {
+                *arg = Some(self.parse_expression()?);
+            }
         }
-        Ok(Statement::Rem(String::from("CIRCLE")))
+        let [color, start, end, aspect] = optional;
+        Ok(Statement::Circle { x, y, step, radius, color, start, end, aspect })
     }
 
     fn parse_draw(&mut self) -> QResult<Statement> {
@@ -1533,7 +2502,7 @@ impl Parser {
 
     fn parse_paint(&mut self) -> QResult<Statement> {
         self.advance(); // PAINT
-        while !self.check(Token::NewLine) && !self.is_at_end() {
+        while !self.at_statement_end() {
             self.advance();
         }
         Ok(Statement::Rem(String::from("PAINT")))
@@ -1541,7 +2510,7 @@ impl Parser {
 
     fn parse_view(&mut self) -> QResult<Statement> {
         self.advance(); // VIEW
-        while !self.check(Token::NewLine) && !self.is_at_end() {
+        while !self.at_statement_end() {
             self.advance();
         }
         Ok(Statement::Rem(String::from("VIEW")))
@@ -1549,7 +2518,7 @@ impl Parser {
 
     fn parse_window(&mut self) -> QResult<Statement> {
         self.advance(); // WINDOW
-        while !self.check(Token::NewLine) && !self.is_at_end() {
+        while !self.at_statement_end() {
             self.advance();
         }
         Ok(Statement::Rem(String::from("WINDOW")))
@@ -1562,7 +2531,7 @@ impl Parser {
 
     fn parse_color(&mut self) -> QResult<Statement> {
         self.advance(); // COLOR
-        let foreground = if !self.check(Token::NewLine) {
+        let foreground = if !self.at_statement_end() {
             Some(self.parse_expression()?)
         } else {
             None
@@ -1588,7 +2557,7 @@ impl Parser {
 
     fn parse_locate(&mut self) -> QResult<Statement> {
         self.advance(); // LOCATE
-        let row = if !self.check(Token::NewLine) && !self.check(Token::Comma) {
+        let row = if !self.at_statement_end() && !self.check(Token::Comma) {
             Some(self.parse_expression()?)
         } else {
             None
@@ -1620,10 +2589,26 @@ impl Parser {
         Ok(Statement::Sound { frequency, duration })
     }
 
+    /// PLAY voice1$ [, voice2$ [, voice3$]]
     fn parse_play(&mut self) -> QResult<Statement> {
         self.advance(); // PLAY
-        let command = self.parse_expression()?;
-        Ok(Statement::Play { command })
+        let mut voices = vec![self.parse_expression()?];
+        while self.check(Token::Comma) {
+            if voices.len() == MAX_VOICES {
+                let (line, col) = self.current_pos();
+                return Err(QError::compile("PLAY takes at most three voices", line, col));
+            }
+            self.advance();
+            voices.push(self.parse_expression()?);
+        }
+        Ok(Statement::Play { voices })
+    }
+
+    /// _SNDPLAY, _SNDLOOP, _SNDSTOP or _SNDCLOSE handle
+    fn parse_snd(&mut self, action: SndAction) -> QResult<Statement> {
+        self.advance();
+        let handle = self.parse_expression()?;
+        Ok(Statement::Snd { action, handle })
     }
 
     fn parse_poke(&mut self) -> QResult<Statement> {
@@ -1635,9 +2620,9 @@ impl Parser {
     }
 
     fn parse_defseg(&mut self) -> QResult<Statement> {
-        self.advance(); // DEF SEG
-        let segment = if !self.check(Token::NewLine) {
-            self.expect(Token::Equal)?;
+        self.advance(); // DEF SEG, or SEG after a separate DEF
+        let segment = if self.check(Token::Equal) {
+            self.advance();
             Some(self.parse_expression()?)
         } else {
             None
@@ -1706,8 +2691,8 @@ impl Parser {
 
     fn parse_restore(&mut self) -> QResult<Statement> {
         self.advance(); // RESTORE
-        let label = if !self.check(Token::NewLine) {
-            Some(self.expect_identifier()?)
+        let label = if !self.at_statement_end() {
+            Some(self.expect_label()?)
         } else {
             None
         };
@@ -1722,7 +2707,7 @@ impl Parser {
 
     fn parse_shell(&mut self) -> QResult<Statement> {
         self.advance(); // SHELL
-        let command = if !self.check(Token::NewLine) {
+        let command = if !self.at_statement_end() {
             Some(self.parse_expression()?)
         } else {
             None
@@ -1734,18 +2719,20 @@ impl Parser {
         self.advance(); // ON
         self.expect(Token::Error)?;
         self.expect(Token::GoTo)?;
-        let label = self.expect_identifier()?;
+        // GOTO 0 turns error trapping off
+        let label = self.expect_label()?;
         Ok(Statement::OnError { label })
     }
 
+    /// RESUME [0 | NEXT | label]
     fn parse_resume(&mut self) -> QResult<Statement> {
         self.advance(); // RESUME
         if self.check(Token::Next) {
             self.advance();
             Ok(Statement::Resume { next: true, label: None })
-        } else if !self.check(Token::NewLine) {
-            let label = self.expect_identifier()?;
-            Ok(Statement::Resume { next: false, label: Some(label) })
+        } else if !self.at_statement_end() {
+            let label = self.expect_label()?;
+            Ok(Statement::Resume { next: false, label: Some(label).filter(|label| label != "0") })
         } else {
             Ok(Statement::Resume { next: false, label: None })
         }
@@ -1757,14 +2744,43 @@ impl Parser {
         Ok(Statement::Error { code })
     }
 
+    /// CLEAR [string space] [, [unused] [, stack size]]: the first two
+    /// arguments only exist for compatibility and are not kept
+    fn parse_clear(&mut self) -> QResult<Statement> {
+        self.advance(); // CLEAR
+        let mut args = Vec::new();
+        loop {
+            args.push(if self.check(Token::Comma) { None } else { self.parse_optional_expression()? });
+            if !self.check(Token::Comma) {
+                break;
+            }
+            self.advance();
+        }
+        if args.len() > 3 {
+            let (line, col) = self.current_pos();
+            return Err(QError::compile("CLEAR takes at most 3 arguments", line, col));
+        }
+        Ok(Statement::Clear { stack: args.into_iter().nth(2).flatten() })
+    }
+
     fn parse_randomize(&mut self) -> QResult<Statement> {
         self.advance(); // RANDOMIZE
         // Parse optional seed expression (e.g., TIMER or a number)
-        if !self.check(Token::NewLine) && !self.is_at_end() {
-            let _seed = self.parse_expression()?;
+        let seed = if !self.at_statement_end() {
+            Some(self.parse_expression()?)
+        } else {
+            None
+        };
+        Ok(Statement::Randomize { seed })
+    }
+
+    /// Optional expression that ends the statement, e.g. END's exit code
+    fn parse_optional_expression(&mut self) -> QResult<Option<Expression>> {
+        if self.at_statement_end() {
+            Ok(None)
+        } else {
+            Ok(Some(self.parse_expression()?))
         }
-        // Return as Rem for now (RANDOMIZE doesn't need AST representation yet)
-        Ok(Statement::Rem(String::from("RANDOMIZE")))
     }
 
     // Helper methods
@@ -1772,6 +2788,11 @@ impl Parser {
         self.tokens.get(self.current).map(|t| &t.token)
     }
 
+    /// The current token as an error message names it
+    fn found(&self) -> String {
+        self.peek_token().map_or_else(|| "end of file".to_string(), Token::to_string)
+    }
+
     fn peek_next_token(&self) -> Option<&Token> {
         self.tokens.get(self.current + 1).map(|t| &t.token)
     }
@@ -1799,7 +2820,7 @@ impl Parser {
         } else {
             let (line, col) = self.current_pos();
             Err(QError::compile(
-                format!("Expected {:?}, found {:?}", expected, self.peek_token()),
+                format!("Expected {}, found {}", expected, self.found()),
                 line,
                 col
             ))
@@ -1820,8 +2841,18 @@ impl Parser {
         }
     }
 
+    /// A label or line number to jump to
+    fn expect_label(&mut self) -> QResult<String> {
+        if let Some(Token::Integer(number)) = self.peek_token() {
+            let number = number.to_string();
+            self.advance();
+            return Ok(number);
+        }
+        self.expect_identifier()
+    }
+
     fn expect_newline(&mut self) -> QResult<()> {
-        if self.check(Token::NewLine) {
+        if self.check(Token::NewLine) || self.check(Token::Colon) {
             self.advance();
             Ok(())
         } else {
@@ -1830,12 +2861,40 @@ impl Parser {
         }
     }
 
+    /// Move on to the next statement, past line ends and the colons
+    /// between statements on one line
     fn skip_newlines(&mut self) {
-        while self.check(Token::NewLine) {
+        while self.check(Token::NewLine) || self.check(Token::Colon) {
             self.advance();
         }
     }
 
+    /// The line number that starts this line, if the current token is one
+    fn line_number(&self) -> Option<u32> {
+        self.line_number_at(self.current)
+    }
+
+    fn line_number_at(&self, index: usize) -> Option<u32> {
+        match self.tokens.get(index)?.token {
+            Token::LineNumber(number) => Some(number),
+            Token::Integer(number) if self.first_on_line(index) => u32::try_from(number).ok(),
+            _ => None,
+        }
+    }
+
+    /// Whether the current token is the first of its line, after any line number
+    fn starts_line(&self) -> bool {
+        self.first_on_line(self.current)
+            || self.current.checked_sub(1).is_some_and(|previous| self.line_number_at(previous).is_some())
+    }
+
+    fn first_on_line(&self, index: usize) -> bool {
+        match (index.checked_sub(1).and_then(|i| self.tokens.get(i)), self.tokens.get(index)) {
+            (Some(previous), Some(current)) => previous.line != current.line,
+            _ => true,
+        }
+    }
+
     fn current_pos(&self) -> (usize, usize) {
         if let Some(token) = self.tokens.get(self.current) {
             (token.line, token.column)
@@ -1848,8 +2907,21 @@ impl Parser {
         self.current_pos().0
     }
 
-    fn current_column(&self) -> usize {
-        self.current_pos().1
+    /// Emit a `SourceLine` marker into `body` when the next statement starts
+    /// on a different source line than the previous one.
+    fn mark_source_line(&mut self, body: &mut Vec<Statement>) {
+        let line = self.current_line();
+        if line != 0 && line != self.last_source_line {
+            self.last_source_line = line;
+            body.push(Statement::SourceLine { line });
+        }
+    }
+
+    fn parse_statement_into(&mut self, body: &mut Vec<Statement>) -> QResult<()> {
+        self.mark_source_line(body);
+        let stmt = self.parse_statement()?;
+        body.push(stmt);
+        Ok(())
     }
 
     fn match_equality_op(&mut self) -> Option<BinaryOp> {
@@ -1881,17 +2953,6 @@ impl Parser {
             None
         }
     }
-
-    fn is_builtin_function(&self, name: &str) -> bool {
-        let upper = name.to_uppercase();
-        matches!(upper.as_str(),
-            "ABS" | "ASC" | "ATN" | "CHR$" | "COS" | "DATE$" | "EXP" | "FIX" | "INT" |
-            "INSTR" | "LCASE$" | "LEFT$" | "LEN" | "LOG" | "MID$" | "RIGHT$" | "RND" |
-            "SGN" | "SIN" | "SPACE$" | "SQR" | "STR$" | "STRING$" | "TAN" | "TIME$" |
-            "TIMER" | "UCASE$" | "VAL" | "CINT" | "CLNG" | "CSNG" | "CDBL" | "CSTR" |
-            "PEEK" | "INP" | "EOF" | "LOF" | "LOC" | "FREEFILE" | "LBOUND" | "UBOUND"
-        )
-    }
 }
 
 /// Parse source code into an AST
@@ -1899,3 +2960,82 @@ pub fn parse(tokens: Vec<TokenInfo>) -> QResult<Program> {
     let parser = Parser::new(tokens);
     parser.parse()
 }
+
+/// Tokenize and parse the bytes of a source file, which may be anything at
+/// all: whatever they hold, the result is an error and never a panic
+pub fn try_parse(bytes: &[u8]) -> QResult<Program> {
+    try_tokenize(bytes).and_then(parse)
+}
+
+/// Parse source code that holds a single expression
+pub fn parse_expression(tokens: Vec<TokenInfo>) -> QResult<Expression> {
+    Parser::new(tokens).parse_standalone_expression()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn nesting_error(source: &str) -> bool {
+        matches!(try_parse(source.as_bytes()), Err(QError::Compile { message, .. }) if message == "Nesting too deep")
+    }
+
+    #[test]
+    fn test_try_parse_any_bytes() {
+        // Code page 437, as QBasic saved it
+        let program = try_parse(b"PRINT \"\xC9\xCD\xBB\"\n").unwrap();
+        assert_eq!(crate::to_source(&program), "PRINT \"╔═╗\"\n");
+        assert!(try_parse(&[0xFF, 0x00, b'(', b'"', 0x80, b'\n', b':']).is_err());
+        assert!(try_parse(b"").unwrap().statements.is_empty());
+        // Tokens without the EOF the scanner ends them with
+        assert!(parse(Vec::new()).unwrap().statements.is_empty());
+        assert!(parse(vec![TokenInfo::new(Token::Print, 1, 1, 5)]).is_ok());
+    }
+
+    #[test]
+    fn test_error_messages_spell_tokens() {
+        let message = |source: &str| match try_parse(source.as_bytes()) {
+            Err(QError::Compile { message, .. }) => message,
+            other => panic!("{:?}", other),
+        };
+        assert_eq!(message("CASE 1\n"), "Unexpected token: CASE");
+        assert_eq!(message("x = (1\n"), "Expected ), found end of line");
+        assert_eq!(message("FOR i = 1 2\n"), "Expected TO, found 2");
+    }
+
+    #[test]
+    fn test_deep_nesting_is_an_error() {
+        let parens = |depth| format!("x = {}1{}\n", "(".repeat(depth), ")".repeat(depth));
+        assert!(try_parse(parens(100).as_bytes()).is_ok());
+        assert!(nesting_error(&parens(100_000)));
+        assert!(nesting_error(&format!("x = 1{}\n", " + 1".repeat(100_000))));
+        assert!(nesting_error(&format!("x = {}1\n", "-".repeat(100_000))));
+        assert!(nesting_error(&format!("x = 2{}\n", " ^ 2".repeat(100_000))));
+
+        // A block takes much more stack than an expression in a debug build
+        let blocks = std::thread::Builder::new().stack_size(32 << 20).spawn(|| {
+            assert!(nesting_error(&"DO\n".repeat(100_000)));
+            assert!(nesting_error(&"IF x THEN\n".repeat(100_000)));
+            assert!(nesting_error(&"IF x THEN IF x THEN ".repeat(100_000)));
+        });
+        blocks.unwrap().join().unwrap();
+    }
+
+    #[test]
+    fn test_input_prompts() {
+        let input = |source: &str| {
+            let program = try_parse(source.as_bytes()).unwrap();
+            program.statements.into_iter().find_map(|statement| match statement {
+                Statement::Input { prompt, vars, question_mark, same_line } => {
+                    Some((prompt, vars.len(), question_mark, same_line))
+                }
+                _ => None,
+            })
+        };
+        assert_eq!(input("INPUT \"Name\"; n$\n"), Some((Some("Name".into()), 1, true, false)));
+        assert_eq!(input("INPUT \"Name\", n$\n"), Some((Some("Name".into()), 1, false, false)));
+        assert_eq!(input("INPUT ; \"Age\"; a, b\n"), Some((Some("Age".into()), 2, true, true)));
+        assert_eq!(input("INPUT ; a\n"), Some((None, 1, true, true)));
+        assert_eq!(input("INPUT a\n"), Some((None, 1, true, false)));
+    }
+}
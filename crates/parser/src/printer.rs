@@ -0,0 +1,1043 @@
+//! AST printer: turns a parsed Program back into QBasic source that parses
+//! to the same tree
+//!
+//! The layout is normalized rather than preserved: one statement per line,
+//! blocks indented by four spaces, names and keywords upper case, and
+//! parentheses only where the parser's precedence needs them. Comments come
+//! back as REM lines, one after the statement they followed, and statements
+//! the parser keeps only as placeholders come back out as REM comments too.
+
+use crate::ast_nodes::*;
+use qb_core::data_types::{ParamType, VariableId};
+use qb_lexer::tokens::Token;
+
+const INDENT: &str = "    ";
+
+/// Source text for a whole program
+pub fn to_source(program: &Program) -> String {
+    let mut printer = Printer { out: String::new(), depth: 0, line_number: None };
+    for stmt in &program.statements {
+        printer.statement(stmt);
+    }
+    printer.flush_line_number();
+    printer.out
+}
+
+/// Source text for a single expression
+pub fn expression_to_source(expr: &Expression) -> String {
+    match expr {
+        Expression::Integer(n) => n.to_string(),
+        Expression::Long(n) => format!("{}&", n),
+        Expression::Single(x) => format!("{:?}!", x),
+        Expression::Double(x) => format!("{:?}#", x),
+        Expression::String(s) => quote(s),
+        Expression::Empty => String::new(),
+        Expression::Variable(var) => variable(var),
+        Expression::ArrayAccess(var, indices) => format!("{}({})", variable(var), list(indices)),
+        Expression::FieldAccess(record, field) => format!("{}.{}", expression_to_source(record), field),
+        Expression::Negate(operand) => format!("-{}", unary_operand(operand)),
+        Expression::Not(operand) => format!("NOT {}", unary_operand(operand)),
+        Expression::Binary { op, left, right } => {
+            let left_text = operand(left, *op, !op.is_left_associative());
+            let right_text = operand(right, *op, op.is_left_associative());
+            format!("{} {} {}", left_text, operator(*op), right_text)
+        }
+        Expression::FunctionCall { name, args } if args.is_empty() => name.clone(),
+        Expression::FunctionCall { name, args } => format!("{}({})", name, list(args)),
+        Expression::TypeConversion { target_type, expr } => {
+            format!("{}({})", target_type, expression_to_source(expr))
+        }
+        Expression::MemGet { block, offset, type_spec } => format!(
+            "_MEMGET({}, {}, {})",
+            expression_to_source(block),
+            expression_to_source(offset),
+            type_name(type_spec)
+        ),
+    }
+}
+
+/// How tightly the parser binds each operator; MOD and \ sit with * and /
+fn binding(op: BinaryOp) -> u8 {
+    match op {
+        BinaryOp::Or | BinaryOp::Xor | BinaryOp::Imp | BinaryOp::Eqv => 1,
+        BinaryOp::And => 2,
+        BinaryOp::Equal | BinaryOp::NotEqual => 3,
+        BinaryOp::Less | BinaryOp::LessEqual | BinaryOp::Greater | BinaryOp::GreaterEqual => 4,
+        BinaryOp::Add | BinaryOp::Subtract | BinaryOp::Concat => 5,
+        BinaryOp::Multiply | BinaryOp::Divide | BinaryOp::IntDivide | BinaryOp::Modulo => 6,
+        BinaryOp::Power => 7,
+    }
+}
+
+fn operator(op: BinaryOp) -> &'static str {
+    match op {
+        BinaryOp::Add | BinaryOp::Concat => "+",
+        BinaryOp::Subtract => "-",
+        BinaryOp::Multiply => "*",
+        BinaryOp::Divide => "/",
+        BinaryOp::IntDivide => "\\",
+        BinaryOp::Modulo => "MOD",
+        BinaryOp::Power => "^",
+        BinaryOp::Equal => "=",
+        BinaryOp::NotEqual => "<>",
+        BinaryOp::Less => "<",
+        BinaryOp::LessEqual => "<=",
+        BinaryOp::Greater => ">",
+        BinaryOp::GreaterEqual => ">=",
+        BinaryOp::And => "AND",
+        BinaryOp::Or => "OR",
+        BinaryOp::Xor => "XOR",
+        BinaryOp::Imp => "IMP",
+        BinaryOp::Eqv => "EQV",
+    }
+}
+
+/// Operand of a binary operator, parenthesized when it binds more loosely,
+/// or equally on the side the operator does not associate toward
+fn operand(expr: &Expression, parent: BinaryOp, wrap_equal: bool) -> String {
+    let text = expression_to_source(expr);
+    match expr {
+        Expression::Binary { op, .. }
+            if binding(*op) < binding(parent) || (wrap_equal && binding(*op) == binding(parent)) =>
+        {
+            format!("({})", text)
+        }
+        _ => text,
+    }
+}
+
+/// Operand of - or NOT, which bind tighter than any binary operator
+fn unary_operand(expr: &Expression) -> String {
+    let text = expression_to_source(expr);
+    if matches!(expr, Expression::Binary { .. }) || text.starts_with('-') {
+        format!("({})", text)
+    } else {
+        text
+    }
+}
+
+fn list(exprs: &[Expression]) -> String {
+    exprs.iter().map(expression_to_source).collect::<Vec<_>>().join(", ")
+}
+
+/// A string literal; embedded quotes are doubled
+fn quote(s: &str) -> String {
+    format!("\"{}\"", s.replace('"', "\"\""))
+}
+
+fn variable(var: &VariableId) -> String {
+    var.full_name()
+}
+
+fn variables(vars: &[VariableId]) -> String {
+    vars.iter().map(variable).collect::<Vec<_>>().join(", ")
+}
+
+fn type_name(spec: &TypeSpec) -> String {
+    match spec {
+        TypeSpec::Simple(name) => name.clone(),
+        TypeSpec::FixedString(len) => format!("STRING * {}", expression_to_source(len)),
+        TypeSpec::UserDefined(name) => name.to_uppercase(),
+    }
+}
+
+fn params(params: &[Param]) -> String {
+    if params.is_empty() {
+        return String::new();
+    }
+    let names: Vec<String> = params
+        .iter()
+        .map(|p| {
+            // A type given AS stands in for the suffix it gave the name
+            let name = |var: &VariableId| match p.type_spec {
+                Some(_) => var.name.to_uppercase(),
+                None => variable(var),
+            };
+            let mut text = match &p.kind {
+                ParamType::ByVal(var) => format!("BYVAL {}", name(var)),
+                ParamType::ByRef(var) => name(var),
+                ParamType::Array(var) => format!("{}()", name(var)),
+            };
+            if let Some(spec) = &p.type_spec {
+                text.push_str(&format!(" AS {}", type_name(spec)));
+            }
+            text
+        })
+        .collect();
+    format!(" ({})", names.join(", "))
+}
+
+/// Optional trailing arguments: ", a, , c", dropping the omitted ones at
+/// the end
+fn optional(args: &[Option<String>]) -> String {
+    let used = args.iter().rposition(Option::is_some).map_or(0, |i| i + 1);
+    args[..used].iter().map(|a| format!(", {}", a.as_deref().unwrap_or(""))).collect()
+}
+
+fn opt(expr: &Option<Expression>) -> Option<String> {
+    expr.as_ref().map(expression_to_source)
+}
+
+fn point(x: &Expression, y: &Expression) -> String {
+    format!("({}, {})", expression_to_source(x), expression_to_source(y))
+}
+
+fn image_area(area: &ImageArea) -> String {
+    let (x, y) = &area.corner;
+    match &area.opposite {
+        Some((x2, y2)) => format!("{}-{}", point(x, y), point(x2, y2)),
+        None => point(x, y),
+    }
+}
+
+fn print_items(items: &[PrintItem]) -> String {
+    let mut text = String::new();
+    let mut after_expression = false;
+    for item in items {
+        match item {
+            PrintItem::Expression(expr) => {
+                if after_expression {
+                    text.push(' ');
+                }
+                text.push_str(&expression_to_source(expr));
+            }
+            PrintItem::Using(format) => text.push_str(&format!("USING {}; ", expression_to_source(format))),
+            PrintItem::Semicolon => text.push_str("; "),
+            PrintItem::Comma => text.push_str(", "),
+        }
+        after_expression = matches!(item, PrintItem::Expression(_));
+    }
+    text.trim_end().to_string()
+}
+
+fn file_record(record: &Option<(Expression, Option<Expression>)>) -> String {
+    match record {
+        Some((first, Some(last))) => {
+            format!(", {} TO {}", expression_to_source(first), expression_to_source(last))
+        }
+        Some((first, None)) => format!(", {}", expression_to_source(first)),
+        None => String::new(),
+    }
+}
+
+fn lvalue(target: &LValue) -> String {
+    match target {
+        LValue::Variable(var) => variable(var),
+        LValue::ArrayElement(var, indices) => format!("{}({})", variable(var), list(indices)),
+        LValue::Field(record, field) => format!("{}.{}", lvalue(record), field),
+    }
+}
+
+fn comparison(token: &Token) -> &'static str {
+    match token {
+        Token::Equal => "=",
+        Token::NotEqual => "<>",
+        Token::Less => "<",
+        Token::LessEqual => "<=",
+        Token::Greater => ">",
+        _ => ">=",
+    }
+}
+
+fn case_condition(condition: &CaseCondition) -> String {
+    match condition {
+        CaseCondition::Expression(expr) => expression_to_source(expr),
+        CaseCondition::Range(low, high) => {
+            format!("{} TO {}", expression_to_source(low), expression_to_source(high))
+        }
+        CaseCondition::Is(op, expr) => format!("IS {} {}", comparison(op), expression_to_source(expr)),
+    }
+}
+
+fn data_value(expr: &Expression) -> String {
+    match expr {
+        Expression::String(s) => format!("\"{}\"", s),
+        // DATA numbers take no type suffix
+        Expression::Double(x) => format!("{:?}", x),
+        other => expression_to_source(other),
+    }
+}
+
+fn on_off(on: bool) -> &'static str {
+    if on { "ON" } else { "OFF" }
+}
+
+/// The variables of DIM, SHARED or STATIC, with their bounds and types
+fn dim_items(vars: &[DimItem]) -> String {
+    let items: Vec<String> = vars
+        .iter()
+        .map(|item| {
+            let mut text = variable(&item.name);
+            if let Some(bounds) = &item.bounds {
+                let dims: Vec<String> =
+                    bounds.iter().map(|b| format!("{} TO {}", expression_to_source(&b.lower), expression_to_source(&b.upper))).collect();
+                text.push_str(&format!("({})", dims.join(", ")));
+            }
+            if let Some(spec) = &item.type_spec {
+                text.push_str(&format!(" AS {}", type_name(spec)));
+            }
+            text
+        })
+        .collect();
+    items.join(", ")
+}
+
+/// The statement on a single line, or None for block statements
+fn simple(stmt: &Statement) -> Option<String> {
+    let text = match stmt {
+        Statement::Rem(text) => format!("REM {}", text),
+        Statement::Dim { vars } | Statement::ReDim { vars, .. } => {
+            let keyword = match stmt {
+                Statement::ReDim { preserve: true, .. } => "REDIM PRESERVE",
+                Statement::ReDim { .. } => "REDIM",
+                _ => "DIM",
+            };
+            let shared = if vars.iter().any(|v| v.shared) { "SHARED " } else { "" };
+            format!("{} {}{}", keyword, shared, dim_items(vars))
+        }
+        Statement::Shared { vars } => format!("SHARED {}", dim_items(vars)),
+        Statement::Static { vars } => format!("STATIC {}", dim_items(vars)),
+        Statement::Common { vars } => {
+            let shared = if vars.iter().any(|v| v.shared) { "SHARED " } else { "" };
+            format!("COMMON {}{}", shared, dim_items(vars))
+        }
+        Statement::Erase { arrays } => {
+            format!("ERASE {}", arrays.iter().map(variable).collect::<Vec<_>>().join(", "))
+        }
+        Statement::ArrayStorage { dynamic: true } => "$DYNAMIC".to_string(),
+        Statement::ArrayStorage { dynamic: false } => "$STATIC".to_string(),
+        Statement::ExplicitArrays => "OPTION _EXPLICITARRAY".to_string(),
+        Statement::Const { name, value } => format!("CONST {} = {}", variable(name), expression_to_source(value)),
+        Statement::DefType { type_char, letter_range: (first, last) } => {
+            let keyword = match type_char {
+                'I' => "DEFINT",
+                'L' => "DEFLNG",
+                'D' => "DEFDBL",
+                '$' => "DEFSTR",
+                _ => "DEFSNG",
+            };
+            if first == last {
+                format!("{} {}", keyword, first)
+            } else {
+                format!("{} {}-{}", keyword, first, last)
+            }
+        }
+        Statement::If { condition, then_branch, else_if_branches, else_branch, is_single_line: true }
+            if else_if_branches.is_empty() =>
+        {
+            let then_text = inline(then_branch)?;
+            let mut text = format!("IF {} THEN {}", expression_to_source(condition), then_text);
+            if let Some(else_branch) = else_branch {
+                text.push_str(&format!(" ELSE {}", inline(else_branch)?));
+            }
+            text
+        }
+        Statement::Goto { label } => format!("GOTO {}", label),
+        Statement::Gosub { label } => format!("GOSUB {}", label),
+        Statement::Return { label: None } => "RETURN".to_string(),
+        Statement::Return { label: Some(label) } => format!("RETURN {}", label),
+        Statement::OnGoto { expr, labels } => format!("ON {} GOTO {}", expression_to_source(expr), labels.join(", ")),
+        Statement::OnGosub { expr, labels } => format!("ON {} GOSUB {}", expression_to_source(expr), labels.join(", ")),
+        Statement::Declare { is_sub, name, params: list } => {
+            format!("DECLARE {} {}{}", if *is_sub { "SUB" } else { "FUNCTION" }, name, params(list))
+        }
+        Statement::Call { name, args } if args.is_empty() => format!("CALL {}", name),
+        Statement::Call { name, args } => {
+            let args: Vec<String> = args
+                .iter()
+                .map(|arg| match arg {
+                    Argument::ByVal(expr) if LValue::from_expression(expr).is_some() => {
+                        format!("({})", expression_to_source(expr))
+                    }
+                    Argument::ByVal(expr) => expression_to_source(expr),
+                    Argument::ByRef(target) => lvalue(target),
+                })
+                .collect();
+            format!("CALL {}({})", name, args.join(", "))
+        }
+        Statement::ExitSub => "EXIT SUB".to_string(),
+        Statement::ExitFunction => "EXIT FUNCTION".to_string(),
+        Statement::ExitFor => "EXIT FOR".to_string(),
+        Statement::ExitDo => "EXIT DO".to_string(),
+        Statement::Print { items, .. } if items.is_empty() => "PRINT".to_string(),
+        Statement::Print { items, .. } => format!("PRINT {}", print_items(items)),
+        Statement::Input { prompt, vars, question_mark, same_line } => {
+            let mut text = String::from("INPUT ");
+            if *same_line {
+                text.push_str("; ");
+            }
+            if let Some(prompt) = prompt {
+                text.push_str(&quote(prompt));
+                text.push_str(if *question_mark { "; " } else { ", " });
+            }
+            text.push_str(&variables(vars));
+            text
+        }
+        Statement::PrintHash { fileno, items } | Statement::PrintFile { fileno, items } => {
+            format!("PRINT #{}, {}", expression_to_source(fileno), print_items(items)).trim_end().to_string()
+        }
+        Statement::InputHash { fileno, vars } | Statement::InputFile { fileno, vars } => {
+            format!("INPUT #{}, {}", expression_to_source(fileno), variables(vars))
+        }
+        Statement::LineInput { prompt: Some(prompt), var } => format!("LINE INPUT {}; {}", quote(prompt), variable(var)),
+        Statement::LineInput { prompt: None, var } => format!("LINE INPUT {}", variable(var)),
+        Statement::LineInputHash { fileno, var } => {
+            format!("LINE INPUT #{}, {}", expression_to_source(fileno), variable(var))
+        }
+        Statement::LPrint { items } => format!("LPRINT {}", print_items(items)).trim_end().to_string(),
+        Statement::Write { fileno: None, items } => format!("WRITE {}", list(items)).trim_end().to_string(),
+        Statement::Write { fileno: Some(fileno), items } => {
+            format!("WRITE #{}, {}", expression_to_source(fileno), list(items)).trim_end().to_string()
+        }
+        Statement::Open { filename, mode, fileno, access, lock, reclen } => {
+            let mut text = format!("OPEN {} FOR {}", expression_to_source(filename), format!("{:?}", mode).to_uppercase());
+            match access {
+                Some(FileAccess::Read) => text.push_str(" ACCESS READ"),
+                Some(FileAccess::Write) => text.push_str(" ACCESS WRITE"),
+                Some(FileAccess::ReadWrite) => text.push_str(" ACCESS READ WRITE"),
+                None => {}
+            }
+            match lock {
+                Some(FileLock::Shared) => text.push_str(" SHARED"),
+                Some(FileLock::Read) => text.push_str(" LOCK READ"),
+                Some(FileLock::Write) => text.push_str(" LOCK WRITE"),
+                Some(FileLock::ReadWrite) => text.push_str(" LOCK READ WRITE"),
+                None => {}
+            }
+            text.push_str(&format!(" AS #{}", expression_to_source(fileno)));
+            if let Some(reclen) = reclen {
+                text.push_str(&format!(" LEN = {}", expression_to_source(reclen)));
+            }
+            text
+        }
+        Statement::Close { fileno: Some(fileno) } => format!("CLOSE #{}", expression_to_source(fileno)),
+        Statement::Close { fileno: None } => "CLOSE".to_string(),
+        Statement::OpenIsam { database, table_type, table, fileno } => format!(
+            "OPEN {} FOR ISAM {} {} AS #{}",
+            expression_to_source(database),
+            table_type,
+            expression_to_source(table),
+            expression_to_source(fileno)
+        ),
+        Statement::Isam { keyword, fileno, args } => {
+            let mut text = keyword.clone();
+            if let Some(fileno) = fileno {
+                text.push_str(&format!(" #{}", expression_to_source(fileno)));
+                if !args.is_empty() {
+                    text.push(',');
+                }
+            }
+            if !args.is_empty() {
+                text.push_str(&format!(" {}", list(args)));
+            }
+            text
+        }
+        Statement::Kill { filespec } => format!("KILL {}", expression_to_source(filespec)),
+        Statement::Get { fileno, record, var } | Statement::Put { fileno, record, var } => {
+            let keyword = if matches!(stmt, Statement::Get { .. }) { "GET" } else { "PUT" };
+            let record = record.as_ref().map(expression_to_source);
+            let fileno = expression_to_source(fileno);
+            match (record, var) {
+                (record, Some(var)) => format!("{} #{}, {}, {}", keyword, fileno, record.unwrap_or_default(), variable(var)),
+                (Some(record), None) => format!("{} #{}, {}", keyword, fileno, record),
+                (None, None) => format!("{} #{}", keyword, fileno),
+            }
+        }
+        Statement::Field { fileno, fields } => {
+            let fields: Vec<String> = fields
+                .iter()
+                .map(|(width, var)| format!("{} AS {}", expression_to_source(width), variable(var)))
+                .collect();
+            format!("FIELD #{}, {}", expression_to_source(fileno), fields.join(", "))
+        }
+        Statement::LSet { target, value } => format!("LSET {} = {}", lvalue(target), expression_to_source(value)),
+        Statement::RSet { target, value } => format!("RSET {} = {}", lvalue(target), expression_to_source(value)),
+        Statement::Seek { fileno, position } => {
+            format!("SEEK #{}, {}", expression_to_source(fileno), expression_to_source(position))
+        }
+        Statement::Lock { fileno, record } => format!("LOCK #{}{}", expression_to_source(fileno), file_record(record)),
+        Statement::Unlock { fileno, record } => format!("UNLOCK #{}{}", expression_to_source(fileno), file_record(record)),
+        Statement::Screen { mode } => format!("SCREEN {}", expression_to_source(mode)),
+        Statement::PSet { x, y, color } => format!("PSET {}{}", point(x, y), optional(&[opt(color)])),
+        Statement::PReset { x, y } => format!("PRESET {}", point(x, y)),
+        Statement::Line { x1, y1, step1, x2, y2, step2, color, style, is_box, is_filled } => {
+            let shape = match (is_box, is_filled) {
+                (true, true) => Some("BF".to_string()),
+                (true, false) => Some("B".to_string()),
+                _ => None,
+            };
+            let step = |relative: &bool| if *relative { "STEP" } else { "" };
+            format!(
+                "LINE {}{}-{}{}{}",
+                step(step1),
+                point(x1, y1),
+                step(step2),
+                point(x2, y2),
+                optional(&[opt(color), shape, opt(style)])
+            )
+        }
+        Statement::Circle { x, y, step, radius, color, start, end, aspect } => format!(
+            "CIRCLE {}{}, {}{}",
+            if *step { "STEP" } else { "" },
+            point(x, y),
+            expression_to_source(radius),
+            optional(&[opt(color), opt(start), opt(end), opt(aspect)])
+        ),
+        Statement::Draw { command } => format!("DRAW {}", expression_to_source(command)),
+        Statement::Paint { x, y, paint_color, border_color } => {
+            format!("PAINT {}{}", point(x, y), optional(&[opt(paint_color), opt(border_color)]))
+        }
+        Statement::View { x1, y1, x2, y2, color, border } => {
+            format!("VIEW {}-{}{}", point(x1, y1), point(x2, y2), optional(&[opt(color), opt(border)]))
+        }
+        Statement::Window { x1, y1, x2, y2, screen_coords } => format!(
+            "WINDOW {}{}-{}",
+            if *screen_coords { "SCREEN " } else { "" },
+            point(x1, y1),
+            point(x2, y2)
+        ),
+        Statement::Palette { attribute: Some(attribute), color: Some(color) } => {
+            format!("PALETTE {}, {}", expression_to_source(attribute), expression_to_source(color))
+        }
+        Statement::Palette { .. } => "PALETTE".to_string(),
+        Statement::Color { foreground, background, border } => {
+            let args = optional(&[opt(foreground), opt(background), opt(border)]);
+            format!("COLOR {}", args.strip_prefix(", ").unwrap_or(&args)).trim_end().to_string()
+        }
+        Statement::Cls => "CLS".to_string(),
+        Statement::Display => "_DISPLAY".to_string(),
+        Statement::AutoDisplay => "_AUTODISPLAY".to_string(),
+        Statement::PutImage { area, source, dest, source_area } => {
+            let args = optional(&[area.as_ref().map(image_area), opt(source), opt(dest), source_area.as_ref().map(image_area)]);
+            format!("_PUTIMAGE {}", args.strip_prefix(", ").unwrap_or(&args)).trim_end().to_string()
+        }
+        Statement::FreeImage { handle } => format!("_FREEIMAGE {}", expression_to_source(handle)),
+        Statement::SaveImage { filename, handle } => {
+            format!("_SAVEIMAGE {}{}", expression_to_source(filename), optional(&[opt(handle)]))
+        }
+        Statement::Dest { handle } => format!("_DEST {}", expression_to_source(handle)),
+        Statement::Source { handle } => format!("_SOURCE {}", expression_to_source(handle)),
+        Statement::PrintString { x, y, text, handle } => {
+            format!("_PRINTSTRING {}, {}{}", point(x, y), expression_to_source(text), optional(&[opt(handle)]))
+        }
+        Statement::MemGet { block, offset, var } => {
+            format!("_MEMGET {}, {}, {}", expression_to_source(block), expression_to_source(offset), variable(var))
+        }
+        Statement::MemPut { block, offset, value, type_spec } => {
+            let mut text = format!(
+                "_MEMPUT {}, {}, {}",
+                expression_to_source(block),
+                expression_to_source(offset),
+                expression_to_source(value)
+            );
+            if let Some(spec) = type_spec {
+                text.push_str(&format!(" AS {}", type_name(spec)));
+            }
+            text
+        }
+        Statement::MemFree { block } => format!("_MEMFREE {}", expression_to_source(block)),
+        Statement::Mat { target, source } => {
+            let source = match source {
+                MatSource::Zero => "ZER".to_string(),
+                MatSource::Ones => "CON".to_string(),
+                MatSource::Fill(value) => format!("({})", expression_to_source(value)),
+                MatSource::Copy(array) => variable(array),
+            };
+            format!("MAT {} = {}", variable(target), source)
+        }
+        Statement::Locate { row, col, cursor, start, stop } => {
+            let args = optional(&[opt(row), opt(col), opt(cursor), opt(start), opt(stop)]);
+            format!("LOCATE {}", args.strip_prefix(", ").unwrap_or(&args)).trim_end().to_string()
+        }
+        Statement::Width { value } => format!("WIDTH {}", expression_to_source(value)),
+        Statement::Beep => "BEEP".to_string(),
+        Statement::Sound { frequency, duration } => {
+            format!("SOUND {}, {}", expression_to_source(frequency), expression_to_source(duration))
+        }
+        Statement::Play { voices } => format!("PLAY {}", list(voices)),
+        Statement::Sleep { seconds: Some(seconds) } => format!("SLEEP {}", expression_to_source(seconds)),
+        Statement::Sleep { seconds: None } => "SLEEP".to_string(),
+        Statement::Snd { action, handle } => {
+            let keyword = match action {
+                SndAction::Play => "_SNDPLAY",
+                SndAction::Loop => "_SNDLOOP",
+                SndAction::Stop => "_SNDSTOP",
+                SndAction::Close => "_SNDCLOSE",
+            };
+            format!("{} {}", keyword, expression_to_source(handle))
+        }
+        Statement::SndVol { handle, volume } => {
+            format!("_SNDVOL {}, {}", expression_to_source(handle), expression_to_source(volume))
+        }
+        Statement::SndRaw { left, right } => format!("_SNDRAW {}{}", expression_to_source(left), optional(&[opt(right)])),
+        Statement::Poke { address, value } => {
+            format!("POKE {}, {}", expression_to_source(address), expression_to_source(value))
+        }
+        Statement::DefSeg { segment: Some(segment) } => format!("DEF SEG = {}", expression_to_source(segment)),
+        Statement::DefSeg { segment: None } => "DEF SEG".to_string(),
+        Statement::Data { values } => {
+            format!("DATA {}", values.iter().map(data_value).collect::<Vec<_>>().join(", ")).trim_end().to_string()
+        }
+        Statement::Read { vars } => format!("READ {}", variables(vars)),
+        Statement::Restore { label: Some(label) } => format!("RESTORE {}", label),
+        Statement::Restore { label: None } => "RESTORE".to_string(),
+        Statement::Environ { expr } => format!("ENVIRON {}", expression_to_source(expr)),
+        Statement::Shell { command: Some(command) } => format!("SHELL {}", expression_to_source(command)),
+        Statement::Shell { command: None } => "SHELL".to_string(),
+        Statement::Chain { file } => format!("CHAIN {}", expression_to_source(file)),
+        Statement::System { code: Some(code) } => format!("SYSTEM {}", expression_to_source(code)),
+        Statement::System { code: None } => "SYSTEM".to_string(),
+        Statement::OnError { label } => format!("ON ERROR GOTO {}", label),
+        Statement::Resume { next: true, .. } => "RESUME NEXT".to_string(),
+        Statement::Resume { label: Some(label), .. } => format!("RESUME {}", label),
+        Statement::Resume { .. } => "RESUME".to_string(),
+        Statement::Error { code } => format!("ERROR {}", expression_to_source(code)),
+        Statement::Limit { rate } => format!("_LIMIT {}", expression_to_source(rate)),
+        Statement::Title { text } => format!("_TITLE {}", expression_to_source(text)),
+        Statement::ScreenMove { position: Some((x, y)) } => {
+            format!("_SCREENMOVE {}, {}", expression_to_source(x), expression_to_source(y))
+        }
+        Statement::ScreenMove { position: None } => "_SCREENMOVE _MIDDLE".to_string(),
+        Statement::Resize { enabled, mode } => {
+            let mode = match mode {
+                Some(ResizeMode::Stretch) => ", _STRETCH",
+                Some(ResizeMode::Smooth) => ", _SMOOTH",
+                None => "",
+            };
+            format!("_RESIZE {}{}", on_off(*enabled), mode)
+        }
+        Statement::FullScreen { mode, smooth } => {
+            let mode = match mode {
+                FullScreenMode::Auto => "",
+                FullScreenMode::Off => " _OFF",
+                FullScreenMode::Stretch => " _STRETCH",
+                FullScreenMode::SquarePixels => " _SQUAREPIXELS",
+            };
+            format!("_FULLSCREEN{}{}", mode, if *smooth { ", _SMOOTH" } else { "" })
+        }
+        Statement::MetaConsole { only: true } => "$CONSOLE:ONLY".to_string(),
+        Statement::MetaConsole { only: false } => "$CONSOLE".to_string(),
+        Statement::Console { visible } => format!("_CONSOLE {}", on_off(*visible)),
+        Statement::OnEvent { source, arg, label } => {
+            format!("ON {}{} GOSUB {}", event_source(*source), event_arg(arg), label)
+        }
+        Statement::EventControl { source, arg, state } => {
+            let state = match state {
+                EventState::On => "ON",
+                EventState::Off => "OFF",
+                EventState::Stop => "STOP",
+            };
+            format!("{}{} {}", event_source(*source), event_arg(arg), state)
+        }
+        Statement::End { code: Some(code) } => format!("END {}", expression_to_source(code)),
+        Statement::End { code: None } => "END".to_string(),
+        Statement::Stop => "STOP".to_string(),
+        Statement::Clear { stack: Some(stack) } => format!("CLEAR , , {}", expression_to_source(stack)),
+        Statement::Clear { stack: None } => "CLEAR".to_string(),
+        Statement::Randomize { seed: Some(seed) } => format!("RANDOMIZE {}", expression_to_source(seed)),
+        Statement::Randomize { seed: None } => "RANDOMIZE".to_string(),
+        Statement::Assignment { target, value } => format!("{} = {}", lvalue(target), expression_to_source(value)),
+        Statement::Label { name } => format!("{}:", name),
+        _ => return None,
+    };
+    Some(text)
+}
+
+fn event_source(source: EventSource) -> &'static str {
+    match source {
+        EventSource::Strig => "STRIG",
+        EventSource::Key => "KEY",
+        EventSource::Timer => "TIMER",
+    }
+}
+
+fn event_arg(arg: &Option<Expression>) -> String {
+    arg.as_ref().map(|a| format!("({})", expression_to_source(a))).unwrap_or_default()
+}
+
+/// The statement of a single-line IF branch, or None if the branch needs
+/// the block form: the parser reads one statement per branch there
+fn inline(body: &[Statement]) -> Option<String> {
+    let parts = body
+        .iter()
+        .filter(|s| !matches!(s, Statement::SourceLine { .. }))
+        .map(simple)
+        .collect::<Option<Vec<_>>>()?;
+    (!parts.is_empty()).then(|| parts.join(": "))
+}
+
+struct Printer {
+    out: String,
+    depth: usize,
+    /// Line number waiting to prefix the next line
+    line_number: Option<u32>,
+}
+
+impl Printer {
+    fn line(&mut self, text: &str) {
+        if let Some(number) = self.line_number.take() {
+            self.out.push_str(&format!("{} ", number));
+        }
+        for _ in 0..self.depth {
+            self.out.push_str(INDENT);
+        }
+        self.out.push_str(text);
+        self.out.push('\n');
+    }
+
+    fn flush_line_number(&mut self) {
+        if let Some(number) = self.line_number.take() {
+            self.out.push_str(&format!("{}\n", number));
+        }
+    }
+
+    fn block(&mut self, body: &[Statement]) {
+        self.depth += 1;
+        for stmt in body {
+            self.statement(stmt);
+        }
+        self.depth -= 1;
+    }
+
+    fn statement(&mut self, stmt: &Statement) {
+        match stmt {
+            Statement::SourceLine { .. } => return,
+            Statement::LineNumber { number } => {
+                self.flush_line_number();
+                self.line_number = Some(*number);
+                return;
+            }
+            _ => {}
+        }
+        if let Some(text) = simple(stmt) {
+            self.line(&text);
+            return;
+        }
+
+        match stmt {
+            Statement::If { condition, then_branch, else_if_branches, else_branch, .. } => {
+                self.line(&format!("IF {} THEN", expression_to_source(condition)));
+                self.block(then_branch);
+                for (condition, body) in else_if_branches {
+                    self.line(&format!("ELSEIF {} THEN", expression_to_source(condition)));
+                    self.block(body);
+                }
+                if let Some(body) = else_branch {
+                    self.line("ELSE");
+                    self.block(body);
+                }
+                self.line("END IF");
+            }
+            Statement::Select { expr, cases, case_else } => {
+                self.line(&format!("SELECT CASE {}", expression_to_source(expr)));
+                self.depth += 1;
+                for case in cases {
+                    let conditions: Vec<String> = case.conditions.iter().map(case_condition).collect();
+                    self.line(&format!("CASE {}", conditions.join(", ")));
+                    self.block(&case.body);
+                }
+                if let Some(body) = case_else {
+                    self.line("CASE ELSE");
+                    self.block(body);
+                }
+                self.depth -= 1;
+                self.line("END SELECT");
+            }
+            Statement::For { var, start, end, step, body } => {
+                let mut header = format!(
+                    "FOR {} = {} TO {}",
+                    variable(var),
+                    expression_to_source(start),
+                    expression_to_source(end)
+                );
+                if let Some(step) = step {
+                    header.push_str(&format!(" STEP {}", expression_to_source(step)));
+                }
+                self.line(&header);
+                self.block(body);
+                self.line(&format!("NEXT {}", variable(var)));
+            }
+            Statement::While { condition, body } => {
+                self.line(&format!("WHILE {}", expression_to_source(condition)));
+                self.block(body);
+                self.line("WEND");
+            }
+            Statement::DoWhile { condition, body } => {
+                self.line(&format!("DO WHILE {}", expression_to_source(condition)));
+                self.block(body);
+                self.line("LOOP");
+            }
+            Statement::DoUntil { condition, body } => {
+                self.line(&format!("DO UNTIL {}", expression_to_source(condition)));
+                self.block(body);
+                self.line("LOOP");
+            }
+            Statement::DoLoop { body, condition, is_until } => {
+                self.line("DO");
+                self.block(body);
+                match condition {
+                    Some(condition) => {
+                        let keyword = if *is_until { "UNTIL" } else { "WHILE" };
+                        self.line(&format!("LOOP {} {}", keyword, expression_to_source(condition)));
+                    }
+                    None => self.line("LOOP"),
+                }
+            }
+            Statement::Sub { name, params: list, body, is_static } => {
+                let suffix = if *is_static { " STATIC" } else { "" };
+                self.line(&format!("SUB {}{}{}", name, params(list), suffix));
+                self.block(body);
+                self.line("END SUB");
+            }
+            Statement::Function { name, params: list, return_type, body, is_static } => {
+                let mut header = format!("FUNCTION {}{}", name, params(list));
+                if let Some(spec) = return_type {
+                    header.push_str(&format!(" AS {}", type_name(spec)));
+                }
+                if *is_static {
+                    header.push_str(" STATIC");
+                }
+                self.line(&header);
+                self.block(body);
+                self.line("END FUNCTION");
+            }
+            Statement::TypeDef { name, fields } => {
+                self.line(&format!("TYPE {}", name));
+                self.depth += 1;
+                for (field, spec) in fields {
+                    self.line(&format!("{} AS {}", field, type_name(spec)));
+                }
+                self.depth -= 1;
+                self.line("END TYPE");
+            }
+            _ => {}
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::parse;
+    use qb_lexer::tokenize;
+
+    fn round_trip(source: &str) -> String {
+        to_source(&parse(tokenize(source).unwrap()).unwrap())
+    }
+
+    #[test]
+    fn test_round_trip_is_stable() {
+        let source = "\
+' Every kind of statement
+DIM a(1 TO 10) AS INTEGER, n$
+CONST limit = 3 ' the last
+TYPE point2
+x AS SINGLE
+label AS STRING * 8
+END TYPE
+FOR i = 1 TO limit STEP 2
+IF i MOD 2 = 0 AND NOT done THEN n$ = \"even\" ELSE GOSUB tail
+NEXT
+SELECT CASE n$
+CASE \"a\", \"b\" TO \"c\", IS > \"x\"
+PRINT (1 + 2) * 3 - (4 - 5) ^ 2 ^ -1
+CASE ELSE
+x = 2 ^ (3 ^ 2)\ny = (2 ^ 3) ^ 2
+END SELECT
+OPEN \"f\" FOR INPUT ACCESS READ LOCK WRITE AS #1 LEN = 64
+FIELD #1, 20 AS n$, 44 AS rest$
+RSET n$ = \"right\"
+GET #1, , p
+PUT #1, 3
+WRITE #1, n$, 2
+LPRINT \"x\"; i,
+PRINT #1, USING \"##.##\"; i; 2
+DATA 1, 2.5, \"three\"
+IF n$ = \"q\" THEN END 2 ELSE SYSTEM
+ON KEY(15) GOSUB tail
+ON i + 1 GOTO tail, tail
+ON i GOSUB tail
+KEY(15) STOP
+key(3) = 5
+CLEAR , , 2048
+CLEAR
+DEF SEG = 0
+POKE &H41A, PEEK(&H41C)
+DEF SEG
+LINE (0, 0)-STEP(5, 5), 4, BF
+LINE -(9, 9), , B, &HF0F0
+CIRCLE STEP(1, 2), 3, , , -1
+_FULLSCREEN _SQUAREPIXELS, _SMOOTH
+_FULLSCREEN
+_SAVEIMAGE \"shot.png\", h&
+_SNDLOOP h&: _SNDVOL h&, 0.5
+_SNDRAW 0.5, -l
+PLAY \"MB C\", \"E\", n$
+SLEEP: SLEEP 2
+KILL \"*.TMP\"
+OPEN \"db\" FOR ISAM Rec \"t\" AS 1: SEEKGE #1, 5, k$
+BEGINTRANS: ROLLBACK all: delete = 1
+IF i THEN PRINT 1: PRINT 2 ELSE n$ = \"a\": i = 0
+a = 1: b = 2
+$DYNAMIC
+OPTION _EXPLICITARRAY
+REDIM SHARED b(5)
+REDIM _PRESERVE b(1 TO i + 1)
+ERASE b, c$
+COMMON SHARED b(), n$: COMMON total AS LONG
+CHAIN \"next\" + n$
+CALL Sort(a())
+SUB Sort (v() AS INTEGER, BYVAL n) STATIC
+SHARED b(), total AS LONG
+STATIC calls
+END SUB
+";
+        let first = round_trip(source);
+        assert_eq!(round_trip(&first), first);
+        assert!(first.starts_with("REM Every kind of statement\nDIM A(1 TO 10) AS INTEGER, N$\nCONST LIMIT = 3\nREM the last\n"));
+        assert!(first.contains("PRINT (1 + 2) * 3 - (4 - 5) ^ 2 ^ -1"));
+        assert!(first.contains("IF N$ = \"q\" THEN END 2 ELSE SYSTEM"));
+        assert!(first.contains("\nON I + 1 GOTO TAIL, TAIL\nON I GOSUB TAIL\n"));
+        assert!(first.contains("\nKEY(15) STOP\nKEY(3) = 5\nCLEAR , , 2048\nCLEAR\n"));
+        assert!(first.contains("\nDEF SEG = 0\nPOKE 1050, PEEK(1052)\nDEF SEG\n"));
+        assert!(first.contains("\nLINE (0, 0)-STEP(5, 5), 4, BF\nLINE STEP(0, 0)-(9, 9), , B, -3856\nCIRCLE STEP(1, 2), 3, , , -1\n"));
+        assert!(first.contains("\n_FULLSCREEN _SQUAREPIXELS, _SMOOTH\n_FULLSCREEN\n_SAVEIMAGE \"shot.png\", H&\n_SNDLOOP H&\n_SNDVOL H&, 0.5!\n_SNDRAW 0.5!, -L\nPLAY \"MB C\", \"E\", N$\nSLEEP\nSLEEP 2\nKILL \"*.TMP\"\n"));
+        assert!(first.contains("\nOPEN \"db\" FOR ISAM REC \"t\" AS #1\nSEEKGE #1, 5, K$\nBEGINTRANS\nROLLBACK ALL\nDELETE = 1\n"));
+        assert!(first.contains("\n    CASE \"a\", \"b\" TO \"c\", IS > \"x\""));
+        assert!(first.contains("\nWRITE #1, N$, 2\nLPRINT \"x\"; I,\n"));
+        assert!(first.contains("\nFIELD #1, 20 AS N$, 44 AS REST$\nRSET N$ = \"right\"\nGET #1, , P\nPUT #1, 3\n"));
+        assert!(first.contains("\nIF I THEN PRINT 1: PRINT 2 ELSE N$ = \"a\": I = 0\nA = 1\nB = 2\n"));
+        assert!(first.contains("\n$DYNAMIC\nOPTION _EXPLICITARRAY\nREDIM SHARED B(0 TO 5)\nREDIM PRESERVE B(1 TO I + 1)\nERASE B, C$\n\
+                                    COMMON SHARED B(), N$\nCOMMON TOTAL AS LONG\nCHAIN \"next\" + N$\n"));
+        assert!(first.contains("\nCALL SORT(A())\nSUB SORT (V() AS INTEGER, BYVAL N) STATIC\n    SHARED B(), TOTAL AS LONG\n    STATIC CALLS\n"));
+    }
+
+    #[test]
+    fn test_expression_parentheses() {
+        let expr = |source: &str| match &parse(tokenize(&format!("x = {}", source)).unwrap()).unwrap().statements[1] {
+            Statement::Assignment { value, .. } => expression_to_source(value),
+            other => panic!("expected an assignment, got {:?}", other),
+        };
+        assert_eq!(expr("a - (b - c)"), "A - (B - C)");
+        assert_eq!(expr("(a - b) - c"), "A - B - C");
+        assert_eq!(expr("(2 ^ 3) ^ 2"), "(2 ^ 3) ^ 2");
+        assert_eq!(expr("a MOD (b * c)"), "A MOD (B * C)");
+        assert_eq!(expr("-(a + 1)"), "-(A + 1)");
+        assert_eq!(expr("\"say \"\"hi\"\"\""), "\"say \"\"hi\"\"\"");
+    }
+
+    #[test]
+    fn test_call_arguments() {
+        let source = "CALL Foo((x), y, a(1) + 1, (a(2)), p.x)";
+        let program = parse(tokenize(source).unwrap()).unwrap();
+        let Some(Statement::Call { args, .. }) = program.statements.iter().find(|s| matches!(s, Statement::Call { .. })) else {
+            panic!("expected a CALL");
+        };
+        let by_ref: Vec<bool> = args.iter().map(|arg| matches!(arg, Argument::ByRef(_))).collect();
+        assert_eq!(by_ref, [false, true, false, false, true]);
+        assert!(round_trip(source).contains("CALL FOO((X), Y, A(1) + 1, (A(2)), P.X)"));
+    }
+
+    #[test]
+    fn test_sub_call_without_call() {
+        let source = "Show (x), y, 1\nTick\nSUB Show (a, b, c)\nEND SUB\n";
+        let program = parse(tokenize(source).unwrap()).unwrap();
+        let calls: Vec<&Vec<Argument>> = program
+            .statements
+            .iter()
+            .filter_map(|s| match s {
+                Statement::Call { args, .. } => Some(args),
+                _ => None,
+            })
+            .collect();
+        assert_eq!(calls.len(), 2);
+        let by_ref: Vec<bool> = calls[0].iter().map(|arg| matches!(arg, Argument::ByRef(_))).collect();
+        assert_eq!(by_ref, [false, true, false]);
+        assert!(calls[1].is_empty());
+    }
+
+    #[test]
+    fn test_single_and_block_if() {
+        let source = "IF a THEN PRINT 1 ELSE IF b THEN PRINT 2 ELSE PRINT 3\nIF a THEN IF b THEN x = 1 ELSE x = 2\n\
+                      IF a THEN ' block\nIF b THEN PRINT 4\nELSE\nIF c GOTO done\nEND IF\ndone:\n";
+        let program = parse(tokenize(source).unwrap()).unwrap();
+        let ifs: Vec<&Statement> = program.statements.iter().filter(|s| matches!(s, Statement::If { .. })).collect();
+        assert_eq!(ifs.len(), 3);
+        // ELSE IF on one line nests a single-line IF in the ELSE branch
+        let Statement::If { is_single_line: true, else_branch: Some(else_branch), .. } = ifs[0] else {
+            panic!("expected a single-line IF, got {:?}", ifs[0]);
+        };
+        assert!(matches!(else_branch[..], [Statement::If { is_single_line: true, else_branch: Some(_), .. }]));
+        // The ELSE goes with the inner IF
+        let Statement::If { then_branch, else_branch: None, .. } = ifs[1] else {
+            panic!("expected no outer ELSE, got {:?}", ifs[1]);
+        };
+        assert!(matches!(then_branch[..], [Statement::If { else_branch: Some(_), .. }]));
+        // A comment after THEN still opens a block, and the ELSE on the next line is the block's
+        let Statement::If { is_single_line: false, then_branch, else_branch: Some(else_branch), .. } = ifs[2] else {
+            panic!("expected a block IF with ELSE, got {:?}", ifs[2]);
+        };
+        assert!(then_branch.iter().any(|s| matches!(s, Statement::If { is_single_line: true, else_branch: None, .. })));
+        assert!(else_branch.iter().any(|s| matches!(s, Statement::If { then_branch, .. }
+            if matches!(then_branch[..], [Statement::Goto { .. }]))));
+        assert!(round_trip(source).contains("\n    IF C THEN GOTO DONE\n"));
+    }
+
+    #[test]
+    fn test_line_numbers() {
+        let source = "10 FOR i = 1 TO 2\n20 IF i = 1 THEN 40 ELSE GOSUB 100\n30 NEXT\n40 RESTORE 40: GOTO 10\n\
+                      100 RETURN 30\n";
+        let first = round_trip(source);
+        assert_eq!(round_trip(&first), first);
+        assert!(first.contains("10 FOR I = 1 TO 2\n20     IF I = 1 THEN GOTO 40 ELSE GOSUB 100\n30 NEXT I\n"));
+        assert!(first.contains("\n40 RESTORE 40\nGOTO 10\n100 RETURN 30\n"));
+    }
+
+    #[test]
+    fn test_select_case_blocks() {
+        let source = "SELECT CASE a\n' only comments before the first CASE\nCASE 1\nCASE 2: END\n\
+                      CASE 3\nIF a THEN\nEND\nEND IF\nSELECT CASE b\nCASE ELSE\nEND SELECT\n\
+                      CASE ELSE\nEND SELECT\nPRINT \"after\"\n";
+        let program = parse(tokenize(source).unwrap()).unwrap();
+        let code = |body: &[Statement]| -> Vec<Statement> {
+            body.iter().filter(|s| !matches!(s, Statement::SourceLine { .. })).cloned().collect()
+        };
+        let top = code(&program.statements);
+        let [Statement::Select { cases, case_else: Some(case_else), .. }, Statement::Print { .. }] = &top[..] else {
+            panic!("expected SELECT then PRINT, got {:?}", top);
+        };
+        assert_eq!(cases.len(), 3);
+        assert!(code(&cases[0].body).is_empty());
+        assert!(matches!(code(&cases[1].body)[..], [Statement::End { .. }]));
+        assert!(matches!(
+            code(&cases[2].body)[..],
+            [Statement::If { .. }, Statement::Select { ref cases, case_else: Some(_), .. }] if cases.is_empty()
+        ));
+        assert!(case_else.is_empty());
+
+        for bad in [
+            "SELECT CASE a\nPRINT 1\nCASE 1\nEND SELECT\n",
+            "SELECT CASE a\nCASE ELSE\nCASE 1\nEND SELECT\n",
+            "SELECT CASE a\nCASE IS + 1\nEND SELECT\n",
+            "SELECT CASE a\nCASE 1\nEND\n",
+        ] {
+            assert!(parse(tokenize(bad).unwrap()).is_err(), "{}", bad);
+        }
+    }
+
+    #[test]
+    fn test_function_calls_are_not_arrays() {
+        let source = "DIM a(5)\ny = Twice(a(1)) + Twice\nFUNCTION Twice (n)\nTwice = n * 2\nEND FUNCTION\n";
+        let program = parse(tokenize(source).unwrap()).unwrap();
+        let Some(Statement::Assignment { value: Expression::Binary { left, right, .. }, .. }) =
+            program.statements.iter().find(|s| matches!(s, Statement::Assignment { .. }))
+        else {
+            panic!("expected y = ...");
+        };
+        let Expression::FunctionCall { args, .. } = left.as_ref() else {
+            panic!("expected a call, got {:?}", left);
+        };
+        assert!(matches!(args[..], [Expression::ArrayAccess(..)]));
+        assert!(matches!(right.as_ref(), Expression::FunctionCall { args, .. } if args.is_empty()));
+    }
+}
@@ -2,8 +2,16 @@
 //! 
 //! Provides semantic analysis and type checking for QBasic.
 
+pub mod lint;
+pub mod names;
 pub mod scope;
 pub mod type_checker;
+pub mod usage;
+pub mod xref;
 
+pub use lint::{Lint, float_equality};
+pub use names::Names;
 pub use scope::{Scope, SymbolTable};
 pub use type_checker::{TypeChecker, analyze};
+pub use usage::{UsageReport, VariableUsage, analyze_usage};
+pub use xref::{Symbol, SymbolIndex, SymbolKind, build_index};
@@ -0,0 +1,239 @@
+use crate::xref::{build_index, SymbolIndex, SymbolKind};
+use qb_core::builtins::{self, Returns};
+use qb_core::data_types::{TypeSuffix, VariableId};
+use qb_lexer::Token;
+use qb_parser::ast_nodes::*;
+use serde::Serialize;
+
+/// Something legal that is probably not what the programmer meant
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct Lint {
+    pub line: usize,
+    pub message: String,
+}
+
+/// Flags `=` and `<>` tests, SELECT CASE values included, that compare a
+/// SINGLE or DOUBLE. QBasic compares them exactly, so a computed value
+/// such as 0.1 * 3 is not equal to 0.3.
+pub fn float_equality(program: &Program) -> Vec<Lint> {
+    let mut linter = FloatEquality {
+        index: build_index(program),
+        scope: None,
+        line: 0,
+        lints: Vec::new(),
+    };
+    linter.block(&program.statements);
+    linter.lints
+}
+
+struct FloatEquality {
+    index: SymbolIndex,
+    /// Procedure being walked, upper case
+    scope: Option<String>,
+    line: usize,
+    lints: Vec<Lint>,
+}
+
+impl FloatEquality {
+    fn block(&mut self, stmts: &[Statement]) {
+        for stmt in stmts {
+            self.statement(stmt);
+        }
+    }
+
+    fn statement(&mut self, stmt: &Statement) {
+        match stmt {
+            Statement::SourceLine { line } => self.line = *line,
+            Statement::Sub { name, body, .. } | Statement::Function { name, body, .. } => {
+                self.scope = Some(name.to_uppercase());
+                self.block(body);
+                self.scope = None;
+            }
+            Statement::If { condition, then_branch, else_if_branches, else_branch, .. } => {
+                self.expr(condition);
+                self.block(then_branch);
+                for (condition, branch) in else_if_branches {
+                    self.expr(condition);
+                    self.block(branch);
+                }
+                if let Some(branch) = else_branch {
+                    self.block(branch);
+                }
+            }
+            Statement::Select { expr, cases, case_else } => {
+                self.expr(expr);
+                let selector_is_float = self.is_float(expr);
+                for case in cases {
+                    for condition in &case.conditions {
+                        match condition {
+                            CaseCondition::Expression(value) | CaseCondition::Is(Token::Equal, value) => {
+                                self.expr(value);
+                                if selector_is_float || self.is_float(value) {
+                                    self.flag("CASE");
+                                }
+                            }
+                            CaseCondition::Is(Token::NotEqual, value) => {
+                                self.expr(value);
+                                if selector_is_float || self.is_float(value) {
+                                    self.flag("CASE IS <>");
+                                }
+                            }
+                            CaseCondition::Is(_, value) => self.expr(value),
+                            CaseCondition::Range(low, high) => {
+                                self.expr(low);
+                                self.expr(high);
+                            }
+                        }
+                    }
+                    self.block(&case.body);
+                }
+                if let Some(body) = case_else {
+                    self.block(body);
+                }
+            }
+            Statement::For { start, end, step, body, .. } => {
+                self.expr(start);
+                self.expr(end);
+                if let Some(step) = step {
+                    self.expr(step);
+                }
+                self.block(body);
+            }
+            Statement::While { condition, body }
+            | Statement::DoWhile { condition, body }
+            | Statement::DoUntil { condition, body } => {
+                self.expr(condition);
+                self.block(body);
+            }
+            Statement::DoLoop { body, condition, .. } => {
+                self.block(body);
+                if let Some(condition) = condition {
+                    self.expr(condition);
+                }
+            }
+            Statement::Assignment { value, .. } => self.expr(value),
+            Statement::Print { items, .. } | Statement::LPrint { items } => {
+                for item in items {
+                    if let PrintItem::Expression(e) | PrintItem::Using(e) = item {
+                        self.expr(e);
+                    }
+                }
+            }
+            Statement::Call { args, .. } => {
+                for arg in args {
+                    if let Argument::ByVal(e) = arg {
+                        self.expr(e);
+                    }
+                }
+            }
+            _ => {}
+        }
+    }
+
+    fn expr(&mut self, expr: &Expression) {
+        match expr {
+            Expression::Binary { op, left, right } => {
+                self.expr(left);
+                self.expr(right);
+                let test = match op {
+                    BinaryOp::Equal => "=",
+                    BinaryOp::NotEqual => "<>",
+                    _ => return,
+                };
+                if self.is_float(left) || self.is_float(right) {
+                    self.flag(test);
+                }
+            }
+            Expression::Negate(e) | Expression::Not(e) => self.expr(e),
+            Expression::ArrayAccess(_, args) | Expression::FunctionCall { args, .. } => {
+                for arg in args {
+                    self.expr(arg);
+                }
+            }
+            Expression::TypeConversion { expr, .. } => self.expr(expr),
+            _ => {}
+        }
+    }
+
+    fn flag(&mut self, test: &str) {
+        self.lints.push(Lint {
+            line: self.line,
+            message: format!("{} compares a floating-point value exactly", test),
+        });
+    }
+
+    /// Whether `expr` has type SINGLE or DOUBLE
+    fn is_float(&self, expr: &Expression) -> bool {
+        match expr {
+            Expression::Single(_) | Expression::Double(_) => true,
+            Expression::Variable(var) | Expression::ArrayAccess(var, _) => self.variable_is_float(var),
+            Expression::Negate(e) => self.is_float(e),
+            Expression::Binary { op, left, right } => match op {
+                BinaryOp::Divide | BinaryOp::Power => true,
+                BinaryOp::Add | BinaryOp::Subtract | BinaryOp::Multiply => {
+                    self.is_float(left) || self.is_float(right)
+                }
+                _ => false,
+            },
+            Expression::FunctionCall { name, .. } => match self.symbol_type(name, SymbolKind::Function) {
+                Some(type_name) => is_float_type(&type_name),
+                None => builtins::lookup(name).is_some_and(|b| matches!(b.returns, Returns::Single | Returns::Double)),
+            },
+            Expression::TypeConversion { target_type, .. } => is_float_type(target_type),
+            _ => false,
+        }
+    }
+
+    fn variable_is_float(&self, var: &VariableId) -> bool {
+        let name = var.full_name();
+        let kinds = [SymbolKind::Variable, SymbolKind::Parameter, SymbolKind::Constant];
+        match kinds.iter().find_map(|kind| self.symbol_type(&name, *kind)) {
+            Some(type_name) => is_float_type(&type_name),
+            None => matches!(var.suffix, Some(TypeSuffix::Single | TypeSuffix::Double | TypeSuffix::Float)),
+        }
+    }
+
+    /// Type of the symbol called exactly `name` seen from the current scope
+    fn symbol_type(&self, name: &str, kind: SymbolKind) -> Option<String> {
+        let wanted = name.to_uppercase();
+        let matching = |scope: Option<&str>| {
+            self.index
+                .symbols
+                .iter()
+                .find(|s| s.kind == kind && s.name == wanted && s.scope.as_deref() == scope)
+        };
+        matching(self.scope.as_deref())
+            .or_else(|| matching(None))
+            .and_then(|s| s.type_name.clone())
+    }
+}
+
+fn is_float_type(type_name: &str) -> bool {
+    matches!(type_name.to_uppercase().as_str(), "SINGLE" | "DOUBLE" | "_FLOAT")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use qb_lexer::tokenize;
+    use qb_parser::parse;
+
+    fn lint(src: &str) -> Vec<usize> {
+        float_equality(&parse(tokenize(src).unwrap()).unwrap()).iter().map(|l| l.line).collect()
+    }
+
+    #[test]
+    fn test_float_equality() {
+        let src = "DIM n AS INTEGER\n\
+                   x = 0.1 * 3\n\
+                   IF x = 0.3 THEN PRINT \"same\"\n\
+                   IF n = 3 THEN PRINT \"three\"\n\
+                   SELECT CASE x\n\
+                   CASE 0.3\n\
+                   CASE IS > 1\n\
+                   END SELECT\n\
+                   PRINT n / 2 <> 1, a$ = \"\"\n";
+        // CASE clauses carry no line of their own, so they report the SELECT
+        assert_eq!(lint(src), [3, 5, 9]);
+    }
+}
@@ -0,0 +1,700 @@
+//! Canonical variable names
+//!
+//! `count%` and `count` are different variables, but an unsuffixed name
+//! takes the type it was DIMmed AS, or else the DEFtype of its first
+//! letter (SINGLE by default), so `count` and `count!` are the same one.
+//! Every VariableId is rewritten to spell out that suffix, giving the VM
+//! one key per variable. Records, _MEM blocks and constants declared
+//! without a suffix keep their bare names; a _DICT is named for the type
+//! of its values, as an array is.
+
+use qb_core::data_types::{ParamType, TypeSuffix, VariableId};
+use qb_parser::ast_nodes::*;
+use std::collections::{HashMap, HashSet};
+
+/// Suffixes a name can end in, longest first
+const SUFFIXES: [(&str, TypeSuffix); 7] = [
+    ("&&", TypeSuffix::Integer64),
+    ("##", TypeSuffix::Float),
+    ("%", TypeSuffix::Integer),
+    ("&", TypeSuffix::Long),
+    ("!", TypeSuffix::Single),
+    ("#", TypeSuffix::Double),
+    ("$", TypeSuffix::String),
+];
+
+/// What a name was declared as: the suffix it stands for, or None to keep
+/// it bare
+type Declarations = HashMap<String, Option<TypeSuffix>>;
+
+pub struct Names {
+    defaults: [TypeSuffix; 26],
+    /// Module-level declarations, then those of the procedure being walked
+    scopes: Vec<Declarations>,
+    /// DIM SHARED and CONST at module level, which procedures see too
+    shared: Declarations,
+    /// SUB and FUNCTION names, which are not variables
+    procedures: HashSet<String>,
+}
+
+impl Names {
+    pub fn new() -> Self {
+        Self {
+            defaults: [TypeSuffix::Single; 26],
+            scopes: vec![Declarations::new()],
+            shared: Declarations::new(),
+            procedures: HashSet::new(),
+        }
+    }
+
+    /// Rewrite every variable in `program` to its canonical name
+    pub fn canonicalize(&mut self, program: &mut Program) {
+        for stmt in &program.statements {
+            match stmt {
+                Statement::Sub { name, .. } | Statement::Function { name, .. } | Statement::Declare { name, .. } => {
+                    self.procedures.insert(name.to_uppercase());
+                }
+                _ => {}
+            }
+        }
+        self.block(&mut program.statements);
+    }
+
+    /// Rewrite the variables of an expression typed against the program,
+    /// such as a debugger's watch, as the module level sees them
+    pub fn canonicalize_expression(&self, expr: &mut Expression) {
+        self.expr(expr);
+    }
+
+    /// Canonical name of `var` where the walk currently is
+    pub fn resolve(&self, var: &VariableId) -> VariableId {
+        let full = var.full_name();
+        if self.procedures.contains(&full) {
+            return var.clone();
+        }
+        if split_suffix(&full).1.is_some() {
+            return VariableId::new(full, None);
+        }
+        // x.y is a field of x when x is a record; otherwise the dot is just
+        // part of the name
+        let base = full.split('.').next().unwrap_or(&full);
+        let declared = self.scopes.last().and_then(|scope| scope.get(base))
+            .or_else(|| if self.scopes.len() > 1 { self.shared.get(base) } else { None });
+        let suffix = match declared {
+            Some(None) => return VariableId::new(full, None),
+            Some(Some(suffix)) if base == full => *suffix,
+            _ => self.default_suffix(&full),
+        };
+        VariableId::new(format!("{}{}", full, suffix), None)
+    }
+
+    fn default_suffix(&self, name: &str) -> TypeSuffix {
+        match name.bytes().next() {
+            Some(letter @ b'A'..=b'Z') => self.defaults[usize::from(letter - b'A')],
+            _ => TypeSuffix::Single,
+        }
+    }
+
+    fn declare(&mut self, var: &VariableId, suffix: Option<TypeSuffix>, shared: bool) {
+        let name = var.full_name();
+        let (base, explicit) = split_suffix(&name);
+        if explicit.is_some() {
+            return;
+        }
+        let base = base.to_string();
+        if shared && self.scopes.len() == 1 {
+            self.shared.insert(base.clone(), suffix);
+        }
+        if let Some(scope) = self.scopes.last_mut() {
+            scope.insert(base, suffix);
+        }
+    }
+
+    fn var(&self, var: &mut VariableId) {
+        *var = self.resolve(var);
+    }
+
+    fn block(&mut self, stmts: &mut [Statement]) {
+        for stmt in stmts {
+            self.statement(stmt);
+        }
+    }
+
+    fn procedure(&mut self, params: &mut [Param], body: &mut [Statement]) {
+        self.scopes.push(Declarations::new());
+        for param in params.iter_mut() {
+            match &mut param.kind {
+                ParamType::ByVal(var) | ParamType::ByRef(var) | ParamType::Array(var) => {
+                    // A parameter given AS a type carries it as its suffix,
+                    // and the bare name in the body means it
+                    if let Some(suffix) = var.suffix {
+                        let base = var.name.to_uppercase();
+                        self.scopes.last_mut().expect("procedure scope").insert(base, Some(suffix));
+                    }
+                    self.var(var);
+                }
+            }
+        }
+        self.block(body);
+        self.scopes.pop();
+    }
+
+    fn statement(&mut self, stmt: &mut Statement) {
+        match stmt {
+            Statement::Dim { vars } | Statement::ReDim { vars, .. } | Statement::Common { vars } => {
+                for item in vars {
+                    for bounds in item.bounds.iter_mut().flatten() {
+                        self.expr(&mut bounds.lower);
+                        self.expr(&mut bounds.upper);
+                    }
+                    if let Some(spec) = &mut item.type_spec {
+                        self.type_spec(spec);
+                        if matches!(spec, TypeSpec::Simple(s) if s == "_DICT") {
+                            self.var(&mut item.name);
+                            continue;
+                        }
+                        let suffix = spec_suffix(spec);
+                        self.declare(&item.name, suffix, item.shared);
+                    }
+                    self.var(&mut item.name);
+                }
+            }
+            // A module-level variable SHARED in a procedure has the
+            // module's declaration
+            Statement::Shared { vars } => {
+                for item in vars {
+                    let name = item.name.full_name();
+                    let declared = self.scopes[0].get(split_suffix(&name).0).copied();
+                    match (&item.type_spec, declared) {
+                        (Some(spec), _) => self.declare(&item.name, spec_suffix(spec), false),
+                        (None, Some(suffix)) => self.declare(&item.name, suffix, false),
+                        (None, None) => {}
+                    }
+                    self.var(&mut item.name);
+                }
+            }
+            Statement::Static { vars } => {
+                for item in vars {
+                    if let Some(spec) = &mut item.type_spec {
+                        self.type_spec(spec);
+                        let suffix = spec_suffix(spec);
+                        self.declare(&item.name, suffix, false);
+                    }
+                    self.var(&mut item.name);
+                }
+            }
+            Statement::Const { name, value } => {
+                self.expr(value);
+                self.declare(name, None, true);
+                self.var(name);
+            }
+            Statement::DefType { type_char, letter_range: (first, last) } => {
+                let suffix = match type_char {
+                    'I' => TypeSuffix::Integer,
+                    'L' => TypeSuffix::Long,
+                    'D' => TypeSuffix::Double,
+                    '$' => TypeSuffix::String,
+                    _ => TypeSuffix::Single,
+                };
+                let first = first.to_ascii_uppercase() as u8;
+                let last = last.to_ascii_uppercase() as u8;
+                for letter in first.max(b'A')..=last.min(b'Z') {
+                    self.defaults[usize::from(letter - b'A')] = suffix;
+                }
+            }
+            Statement::TypeDef { fields, .. } => {
+                for (_, spec) in fields {
+                    self.type_spec(spec);
+                }
+            }
+            Statement::If { condition, then_branch, else_if_branches, else_branch, .. } => {
+                self.expr(condition);
+                self.block(then_branch);
+                for (condition, body) in else_if_branches {
+                    self.expr(condition);
+                    self.block(body);
+                }
+                if let Some(body) = else_branch {
+                    self.block(body);
+                }
+            }
+            Statement::Select { expr, cases, case_else } => {
+                self.expr(expr);
+                for case in cases {
+                    for condition in &mut case.conditions {
+                        match condition {
+                            CaseCondition::Expression(e) | CaseCondition::Is(_, e) => self.expr(e),
+                            CaseCondition::Range(low, high) => {
+                                self.expr(low);
+                                self.expr(high);
+                            }
+                        }
+                    }
+                    self.block(&mut case.body);
+                }
+                if let Some(body) = case_else {
+                    self.block(body);
+                }
+            }
+            Statement::For { var, start, end, step, body } => {
+                self.var(var);
+                self.expr(start);
+                self.expr(end);
+                self.opt(step);
+                self.block(body);
+            }
+            Statement::While { condition, body }
+            | Statement::DoWhile { condition, body }
+            | Statement::DoUntil { condition, body } => {
+                self.expr(condition);
+                self.block(body);
+            }
+            Statement::DoLoop { body, condition, .. } => {
+                self.block(body);
+                self.opt(condition);
+            }
+            Statement::OnGoto { expr, .. } | Statement::OnGosub { expr, .. } => self.expr(expr),
+            Statement::Sub { params, body, .. } | Statement::Function { params, body, .. } => {
+                self.procedure(params, body);
+            }
+            Statement::Declare { params, .. } => {
+                for param in params {
+                    match &mut param.kind {
+                        ParamType::ByVal(var) | ParamType::ByRef(var) | ParamType::Array(var) => self.var(var),
+                    }
+                }
+            }
+            Statement::Call { args, .. } => {
+                for arg in args {
+                    match arg {
+                        Argument::ByVal(e) => self.expr(e),
+                        Argument::ByRef(target) => self.lvalue(target),
+                    }
+                }
+            }
+            Statement::Print { items, .. } | Statement::LPrint { items } => self.print_items(items),
+            Statement::PrintHash { fileno, items } | Statement::PrintFile { fileno, items } => {
+                self.expr(fileno);
+                self.print_items(items);
+            }
+            Statement::Input { vars, .. } | Statement::Read { vars } | Statement::Erase { arrays: vars } => {
+                for var in vars {
+                    self.var(var);
+                }
+            }
+            Statement::InputHash { fileno, vars } | Statement::InputFile { fileno, vars } => {
+                self.expr(fileno);
+                for var in vars {
+                    self.var(var);
+                }
+            }
+            Statement::LineInput { var, .. } => self.var(var),
+            Statement::LineInputHash { fileno, var } => {
+                self.expr(fileno);
+                self.var(var);
+            }
+            Statement::Write { fileno, items } => {
+                if let Some(fileno) = fileno {
+                    self.expr(fileno);
+                }
+                for item in items {
+                    self.expr(item);
+                }
+            }
+            Statement::Data { values: items } => {
+                for item in items {
+                    self.expr(item);
+                }
+            }
+            Statement::Open { filename, fileno, reclen, .. } => {
+                self.expr(filename);
+                self.expr(fileno);
+                self.opt(reclen);
+            }
+            Statement::Close { fileno } => self.opt(fileno),
+            Statement::Kill { filespec } | Statement::Chain { file: filespec } => self.expr(filespec),
+            Statement::OpenIsam { database, table, fileno, .. } => {
+                self.expr(database);
+                self.expr(table);
+                self.expr(fileno);
+            }
+            Statement::Isam { fileno, args, .. } => {
+                self.opt(fileno);
+                for e in args {
+                    self.expr(e);
+                }
+            }
+            Statement::Get { fileno, record, var } | Statement::Put { fileno, record, var } => {
+                self.expr(fileno);
+                self.opt(record);
+                if let Some(var) = var {
+                    self.var(var);
+                }
+            }
+            Statement::Field { fileno, fields } => {
+                self.expr(fileno);
+                for (width, var) in fields {
+                    self.expr(width);
+                    self.var(var);
+                }
+            }
+            Statement::Seek { fileno, position } => {
+                self.expr(fileno);
+                self.expr(position);
+            }
+            Statement::Lock { fileno, record } | Statement::Unlock { fileno, record } => {
+                self.expr(fileno);
+                if let Some((first, last)) = record {
+                    self.expr(first);
+                    self.opt(last);
+                }
+            }
+            Statement::Screen { mode: e }
+            | Statement::Draw { command: e }
+            | Statement::FreeImage { handle: e }
+            | Statement::Dest { handle: e }
+            | Statement::Source { handle: e }
+            | Statement::MemFree { block: e }
+            | Statement::Width { value: e }
+            | Statement::Snd { handle: e, .. }
+            | Statement::Environ { expr: e }
+            | Statement::Error { code: e }
+            | Statement::Limit { rate: e }
+            | Statement::Title { text: e } => self.expr(e),
+            Statement::Play { voices } => {
+                for e in voices {
+                    self.expr(e);
+                }
+            }
+            Statement::PSet { x, y, color } => {
+                self.expr(x);
+                self.expr(y);
+                self.opt(color);
+            }
+            Statement::PReset { x, y }
+            | Statement::Sound { frequency: x, duration: y }
+            | Statement::SndVol { handle: x, volume: y }
+            | Statement::Poke { address: x, value: y } => {
+                self.expr(x);
+                self.expr(y);
+            }
+            Statement::Line { x1, y1, x2, y2, color, style, .. } => {
+                for e in [x1, y1, x2, y2] {
+                    self.expr(e);
+                }
+                self.opt(color);
+                self.opt(style);
+            }
+            Statement::Circle { x, y, radius, color, start, end, aspect, .. } => {
+                for e in [x, y, radius] {
+                    self.expr(e);
+                }
+                for e in [color, start, end, aspect] {
+                    self.opt(e);
+                }
+            }
+            Statement::Paint { x, y, paint_color, border_color } => {
+                self.expr(x);
+                self.expr(y);
+                self.opt(paint_color);
+                self.opt(border_color);
+            }
+            Statement::View { x1, y1, x2, y2, color, border } => {
+                for e in [x1, y1, x2, y2] {
+                    self.expr(e);
+                }
+                self.opt(color);
+                self.opt(border);
+            }
+            Statement::Window { x1, y1, x2, y2, .. } => {
+                for e in [x1, y1, x2, y2] {
+                    self.expr(e);
+                }
+            }
+            Statement::Palette { attribute, color } => {
+                self.opt(attribute);
+                self.opt(color);
+            }
+            Statement::Color { foreground, background, border } => {
+                for e in [foreground, background, border] {
+                    self.opt(e);
+                }
+            }
+            Statement::PutImage { area, source, dest, source_area } => {
+                self.image_area(area);
+                self.opt(source);
+                self.opt(dest);
+                self.image_area(source_area);
+            }
+            Statement::PrintString { x, y, text, handle } => {
+                for e in [x, y, text] {
+                    self.expr(e);
+                }
+                self.opt(handle);
+            }
+            Statement::SaveImage { filename, handle } => {
+                self.expr(filename);
+                self.opt(handle);
+            }
+            Statement::SndRaw { left, right } => {
+                self.expr(left);
+                self.opt(right);
+            }
+            Statement::Mat { target, source } => {
+                self.var(target);
+                match source {
+                    MatSource::Fill(value) => self.expr(value),
+                    MatSource::Copy(array) => self.var(array),
+                    MatSource::Zero | MatSource::Ones => {}
+                }
+            }
+            Statement::MemGet { block, offset, var } => {
+                self.expr(block);
+                self.expr(offset);
+                self.var(var);
+            }
+            Statement::MemPut { block, offset, value, type_spec } => {
+                for e in [block, offset, value] {
+                    self.expr(e);
+                }
+                if let Some(spec) = type_spec {
+                    self.type_spec(spec);
+                }
+            }
+            Statement::Locate { row, col, cursor, start, stop } => {
+                for e in [row, col, cursor, start, stop] {
+                    self.opt(e);
+                }
+            }
+            Statement::DefSeg { segment: e }
+            | Statement::Shell { command: e }
+            | Statement::System { code: e }
+            | Statement::End { code: e }
+            | Statement::Clear { stack: e }
+            | Statement::Randomize { seed: e }
+            | Statement::Sleep { seconds: e } => self.opt(e),
+            Statement::ScreenMove { position } => {
+                if let Some((x, y)) = position {
+                    self.expr(x);
+                    self.expr(y);
+                }
+            }
+            Statement::OnEvent { arg, .. } | Statement::EventControl { arg, .. } => self.opt(arg),
+            Statement::Assignment { target, value } | Statement::LSet { target, value } | Statement::RSet { target, value } => {
+                self.lvalue(target);
+                self.expr(value);
+            }
+            Statement::Rem(_)
+            | Statement::ArrayStorage { .. }
+            | Statement::ExplicitArrays
+            | Statement::Goto { .. }
+            | Statement::Gosub { .. }
+            | Statement::Return { .. }
+            | Statement::ExitSub
+            | Statement::ExitFunction
+            | Statement::ExitFor
+            | Statement::ExitDo
+            | Statement::Cls
+            | Statement::Display
+            | Statement::AutoDisplay
+            | Statement::Beep
+            | Statement::Restore { .. }
+            | Statement::OnError { .. }
+            | Statement::Resume { .. }
+            | Statement::Resize { .. }
+            | Statement::FullScreen { .. }
+            | Statement::MetaConsole { .. }
+            | Statement::Console { .. }
+            | Statement::Stop
+            | Statement::Label { .. }
+            | Statement::LineNumber { .. }
+            | Statement::SourceLine { .. } => {}
+        }
+    }
+
+    fn lvalue(&self, target: &mut LValue) {
+        match target {
+            LValue::Variable(var) => self.var(var),
+            LValue::ArrayElement(var, indices) => {
+                self.var(var);
+                for index in indices {
+                    self.expr(index);
+                }
+            }
+            LValue::Field(base, _) => self.lvalue(base),
+        }
+    }
+
+    fn print_items(&self, items: &mut [PrintItem]) {
+        for item in items {
+            if let PrintItem::Expression(e) | PrintItem::Using(e) = item {
+                self.expr(e);
+            }
+        }
+    }
+
+    fn image_area(&self, area: &mut Option<ImageArea>) {
+        if let Some(area) = area {
+            self.expr(&mut area.corner.0);
+            self.expr(&mut area.corner.1);
+            if let Some((x, y)) = &mut area.opposite {
+                self.expr(x);
+                self.expr(y);
+            }
+        }
+    }
+
+    fn type_spec(&self, spec: &mut TypeSpec) {
+        if let TypeSpec::FixedString(len) = spec {
+            self.expr(len);
+        }
+    }
+
+    fn opt(&self, expr: &mut Option<Expression>) {
+        if let Some(e) = expr {
+            self.expr(e);
+        }
+    }
+
+    fn expr(&self, expr: &mut Expression) {
+        // A FUNCTION the parser could not tell from a variable or array,
+        // as in an expression typed without the program, is a call
+        match expr {
+            Expression::Variable(var) if self.procedures.contains(&var.full_name()) => {
+                *expr = Expression::FunctionCall { name: var.full_name(), args: Vec::new() };
+            }
+            Expression::ArrayAccess(var, indices) if self.procedures.contains(&var.full_name()) => {
+                *expr = Expression::FunctionCall { name: var.full_name(), args: std::mem::take(indices) };
+            }
+            _ => {}
+        }
+        match expr {
+            Expression::Variable(var) => self.var(var),
+            Expression::ArrayAccess(var, indices) => {
+                self.var(var);
+                for index in indices {
+                    self.expr(index);
+                }
+            }
+            Expression::FieldAccess(base, _) | Expression::Negate(base) | Expression::Not(base) => self.expr(base),
+            Expression::Binary { left, right, .. } => {
+                self.expr(left);
+                self.expr(right);
+            }
+            Expression::FunctionCall { args, .. } => {
+                for arg in args {
+                    self.expr(arg);
+                }
+            }
+            Expression::TypeConversion { expr, .. } => self.expr(expr),
+            Expression::MemGet { block, offset, type_spec } => {
+                self.expr(block);
+                self.expr(offset);
+                self.type_spec(type_spec);
+            }
+            Expression::Integer(_)
+            | Expression::Long(_)
+            | Expression::Single(_)
+            | Expression::Double(_)
+            | Expression::String(_)
+            | Expression::Empty => {}
+        }
+    }
+}
+
+impl Default for Names {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// Split a name into its base and type suffix, if it has one
+fn split_suffix(name: &str) -> (&str, Option<TypeSuffix>) {
+    SUFFIXES
+        .iter()
+        .find_map(|(text, suffix)| name.strip_suffix(text).map(|base| (base, Some(*suffix))))
+        .unwrap_or((name, None))
+}
+
+/// Suffix a name DIMmed AS `spec` stands for; None for types without one
+fn spec_suffix(spec: &TypeSpec) -> Option<TypeSuffix> {
+    match spec {
+        TypeSpec::Simple(name) => match name.as_str() {
+            "INTEGER" => Some(TypeSuffix::Integer),
+            "LONG" => Some(TypeSuffix::Long),
+            "SINGLE" => Some(TypeSuffix::Single),
+            "DOUBLE" => Some(TypeSuffix::Double),
+            "STRING" => Some(TypeSuffix::String),
+            "_INTEGER64" => Some(TypeSuffix::Integer64),
+            "_FLOAT" => Some(TypeSuffix::Float),
+            _ => None,
+        },
+        TypeSpec::FixedString(_) => Some(TypeSuffix::String),
+        TypeSpec::UserDefined(_) => None,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use qb_lexer::tokenize;
+    use qb_parser::parse;
+
+    /// Canonical names of every assignment target, in order
+    fn targets(source: &str) -> Vec<String> {
+        let mut program = parse(tokenize(source).unwrap()).unwrap();
+        Names::new().canonicalize(&mut program);
+        let mut names = Vec::new();
+        fn collect(stmts: &[Statement], names: &mut Vec<String>) {
+            for stmt in stmts {
+                match stmt {
+                    Statement::Assignment { target: LValue::Variable(var), .. } => names.push(var.full_name()),
+                    Statement::Sub { body, .. } => collect(body, names),
+                    _ => {}
+                }
+            }
+        }
+        collect(&program.statements, &mut names);
+        names
+    }
+
+    #[test]
+    fn test_suffixes_are_spelled_out() {
+        assert_eq!(targets("count% = 1\ncount = 2\ncount! = 3\n"), ["COUNT%", "COUNT!", "COUNT!"]);
+        assert_eq!(targets("DEFINT I-K\ni = 1\nj& = 2\nx = 3\n"), ["I%", "J&", "X!"]);
+        assert_eq!(targets("DIM n AS LONG\nn = 1\nn& = 2\nDIM t AS STRING * 4\nt = \"a\"\n"), ["N&", "N&", "T$"]);
+    }
+
+    #[test]
+    fn test_records_and_constants_stay_bare() {
+        let source = "TYPE Point\nx AS INTEGER\nEND TYPE\nDIM p AS Point\np.x = 1\nmy.var = 2\nCONST limit = 10\nlimit2 = limit\n";
+        assert_eq!(targets(source), ["P.X", "MY.VAR!", "LIMIT2!"]);
+    }
+
+    #[test]
+    fn test_procedures_have_their_own_declarations() {
+        let source = "DIM n AS INTEGER\nDIM SHARED s AS DOUBLE\nn = 1\nSUB Work\nn = 2\ns = 3\nEND SUB\n";
+        assert_eq!(targets(source), ["N%", "N!", "S#"]);
+        // SHARED brings in the module's declaration; STATIC declares its own
+        let source = "DIM n AS INTEGER\nSUB Work\nSHARED n\nSTATIC k AS LONG\nn = 2\nk = 3\nEND SUB\n";
+        assert_eq!(targets(source), ["N%", "K&"]);
+    }
+
+    #[test]
+    fn test_functions_are_not_arrays() {
+        let mut program = parse(tokenize("FUNCTION Area (w, h)\nArea = w * h\nEND FUNCTION\n").unwrap()).unwrap();
+        let mut names = Names::new();
+        names.canonicalize(&mut program);
+        // A watch is parsed without the program, so Area(...) looks like an array
+        let watch = parse(tokenize("x = Area(2, n) + Area").unwrap()).unwrap();
+        let Some(Statement::Assignment { value: mut expr, .. }) = watch.statements.into_iter().last() else {
+            panic!("expected an assignment");
+        };
+        names.canonicalize_expression(&mut expr);
+        let Expression::Binary { left, right, .. } = expr else {
+            panic!("expected a sum");
+        };
+        assert!(matches!(*left, Expression::FunctionCall { ref name, ref args } if name == "AREA" && args.len() == 2));
+        assert!(matches!(*right, Expression::FunctionCall { ref args, .. } if args.is_empty()));
+    }
+}
@@ -1,6 +1,6 @@
 use qb_core::data_types::QType;
 use indexmap::IndexMap;
-use std::collections::HashMap;
+use std::collections::{HashMap, HashSet};
 
 /// Scope for variable tracking
 #[derive(Debug, Clone)]
@@ -81,6 +81,19 @@ impl SymbolTable {
         self.scopes.push(new_scope);
     }
 
+    /// The scope of a SUB or FUNCTION body, which sees only the module's
+    /// variables named in `visible`, each with the fields of a record
+    pub fn enter_procedure_scope(&mut self, visible: &HashSet<String>) {
+        let mut scope = Scope::new();
+        for (name, type_) in &self.global_scope.variables {
+            let base = name.split('.').next().unwrap_or(name);
+            if visible.contains(base) {
+                scope.define(name.clone(), type_.clone());
+            }
+        }
+        self.scopes.push(scope);
+    }
+
     pub fn exit_scope(&mut self) {
         if let Some(scope) = self.scopes.pop() {
             if let Some(parent) = scope.parent {
@@ -1,13 +1,22 @@
+use crate::names::Names;
 use crate::scope::SymbolTable;
-use qb_core::data_types::{QType, TypeSuffix};
+use qb_core::builtins;
+use qb_core::data_types::{ParamType, QType, TypeSuffix};
 use qb_core::errors::{QError, QErrorCode, QResult};
 use qb_parser::ast_nodes::*;
+use std::collections::{HashMap, HashSet};
 
 /// Type checker for QBasic AST
 pub struct TypeChecker {
     symbol_table: SymbolTable,
     current_function: Option<String>,
     default_types: [TypeSuffix; 26], // DEFINT A-Z, etc.
+    /// Fields of each TYPE, by type name
+    user_types: HashMap<String, Vec<(String, TypeSpec)>>,
+    /// Which parameters of each SUB and FUNCTION take a whole array
+    array_params: HashMap<String, Vec<bool>>,
+    /// Module-level variables every procedure sees: DIM SHARED and CONST
+    shared: HashSet<String>,
 }
 
 impl TypeChecker {
@@ -17,6 +26,9 @@ impl TypeChecker {
             symbol_table: SymbolTable::new(),
             current_function: None,
             default_types: [TypeSuffix::Single; 26],
+            user_types: HashMap::new(),
+            array_params: HashMap::new(),
+            shared: HashSet::new(),
         }
     }
 
@@ -36,15 +48,29 @@ impl TypeChecker {
 
     fn collect_declaration(&mut self, stmt: &Statement) -> QResult<()> {
         match stmt {
-            Statement::Dim { vars } => {
+            Statement::Dim { vars } | Statement::ReDim { vars, .. } | Statement::Common { vars } => {
                 for var in vars {
                     let type_ = self.infer_type_from_spec(&var.type_spec, &var.name);
                     self.symbol_table.define_variable(&var.name.name, type_);
+                    if var.shared {
+                        self.shared.insert(var.name.name.clone());
+                    }
+                    // Each field of a record is a variable named record.field
+                    if let Some(TypeSpec::UserDefined(type_name)) = &var.type_spec {
+                        for (field, spec) in self.user_types.get(type_name).cloned().unwrap_or_default() {
+                            let type_ = self.type_spec_to_qtype(&spec);
+                            self.symbol_table.define_variable(format!("{}.{}", var.name.name, field), type_);
+                        }
+                    }
                 }
             }
+            Statement::TypeDef { name, fields } => {
+                self.user_types.insert(name.clone(), fields.clone());
+            }
             Statement::Const { name, value } => {
                 let type_ = self.infer_type_from_expr(value)?;
                 self.symbol_table.define_variable(&name.name, type_);
+                self.shared.insert(name.name.clone());
             }
             Statement::DefType { type_char, letter_range } => {
                 let suffix = match type_char {
@@ -69,10 +95,12 @@ impl TypeChecker {
                 };
                 let param_types = params.iter().map(|_| QType::Single(0.0)).collect();
                 self.symbol_table.define_function(name.clone(), param_types, return_qtype);
+                self.define_array_params(name, params);
             }
             Statement::Sub { name, params, .. } => {
                 let param_types = params.iter().map(|_| QType::Single(0.0)).collect();
                 self.symbol_table.define_subroutine(name.clone(), param_types);
+                self.define_array_params(name, params);
             }
             Statement::LineNumber { number } => {
                 self.symbol_table.add_line_number(*number, 0);
@@ -145,7 +173,7 @@ impl TypeChecker {
                 self.symbol_table.exit_scope();
             }
             Statement::Sub { name, body, .. } => {
-                self.symbol_table.enter_scope();
+                self.enter_procedure(body);
                 self.symbol_table.define_subroutine(name.clone(), Vec::new());
                 for s in body {
                     self.check_statement(s)?;
@@ -154,7 +182,7 @@ impl TypeChecker {
             }
             Statement::Function { name, body, .. } => {
                 self.current_function = Some(name.clone());
-                self.symbol_table.enter_scope();
+                self.enter_procedure(body);
                 for s in body {
                     self.check_statement(s)?;
                 }
@@ -163,8 +191,14 @@ impl TypeChecker {
             }
             Statement::Print { items, .. } => {
                 for item in items {
-                    if let PrintItem::Expression(expr) = item {
-                        self.infer_type_from_expr(expr)?;
+                    match item {
+                        PrintItem::Expression(expr) => {
+                            self.infer_type_from_expr(expr)?;
+                        }
+                        PrintItem::Using(format) if self.infer_type_from_expr(format)?.is_numeric() => {
+                            return Err(QError::runtime(QErrorCode::TypeMismatch, 0, 0));
+                        }
+                        _ => {}
                     }
                 }
             }
@@ -182,10 +216,21 @@ impl TypeChecker {
                    self.symbol_table.lookup_function(name).is_none() {
                     // Allow undefined calls (could be external)
                 }
+                let whole_arrays = args.iter().map(|arg| {
+                    matches!(arg, Argument::ByRef(LValue::ArrayElement(_, indices)) if indices.is_empty())
+                });
+                self.check_array_arguments(name, whole_arrays)?;
                 for arg in args {
-                    if let Argument::ByVal(expr) = arg {
-                        self.infer_type_from_expr(expr)?;
-                    }
+                    match arg {
+                        Argument::ByVal(expr) => self.infer_type_from_expr(expr)?,
+                        Argument::ByRef(target) => self.infer_lvalue_type(target)?,
+                    };
+                }
+            }
+            Statement::Shared { vars } | Statement::Static { vars } => {
+                for var in vars.iter().filter(|var| var.type_spec.is_some() || matches!(stmt, Statement::Static { .. })) {
+                    let type_ = self.infer_type_from_spec(&var.type_spec, &var.name);
+                    self.symbol_table.define_variable(&var.name.name, type_);
                 }
             }
             Statement::Goto { label: _ } | Statement::Gosub { label: _ } => {
@@ -198,6 +243,35 @@ impl TypeChecker {
         Ok(())
     }
 
+    /// Enter the scope of a procedure: it sees the module's DIM SHARED
+    /// variables and constants, and those its SHARED statements name
+    fn enter_procedure(&mut self, body: &[Statement]) {
+        let mut visible = self.shared.clone();
+        for stmt in body {
+            if let Statement::Shared { vars } = stmt {
+                visible.extend(vars.iter().map(|var| var.name.name.clone()));
+            }
+        }
+        self.symbol_table.enter_procedure_scope(&visible);
+    }
+
+    fn define_array_params(&mut self, name: &str, params: &[Param]) {
+        let arrays = params.iter().map(|param| matches!(param.kind, ParamType::Array(_))).collect();
+        self.array_params.insert(name.to_uppercase(), arrays);
+    }
+
+    /// A whole array, passed as a(), must go to an array parameter, and
+    /// only there
+    fn check_array_arguments(&self, name: &str, whole_arrays: impl Iterator<Item = bool>) -> QResult<()> {
+        let Some(arrays) = self.array_params.get(&name.to_uppercase()) else {
+            return Ok(());
+        };
+        if arrays.iter().copied().zip(whole_arrays).any(|(param, arg)| param != arg) {
+            return Err(QError::compile("Parameter type mismatch", 0, 0));
+        }
+        Ok(())
+    }
+
     fn infer_lvalue_type(&self, lvalue: &LValue) -> QResult<QType> {
         match lvalue {
             LValue::Variable(var) => {
@@ -259,17 +333,26 @@ impl TypeChecker {
                 let right_type = self.infer_type_from_expr(right)?;
                 self.infer_binary_type(*op, &left_type, &right_type)
             }
-            Expression::FunctionCall { name, .. } => {
+            Expression::FunctionCall { name, args } => {
                 if let Some((_, return_type)) = self.symbol_table.lookup_function(name) {
+                    let whole_arrays = args.iter().map(|arg| {
+                        matches!(arg, Expression::ArrayAccess(_, indices) if indices.is_empty())
+                    });
+                    self.check_array_arguments(name, whole_arrays)?;
                     Ok(return_type.clone())
+                } else if let Some(builtin) = builtins::lookup(name) {
+                    if !builtin.accepts(args.len()) {
+                        return Err(QError::compile(format!("Argument-count mismatch in {}", builtin.name), 0, 0));
+                    }
+                    Ok(builtin.return_type())
                 } else {
-                    // Built-in function - infer from name
-                    self.infer_builtin_function_type(name)
+                    Ok(QType::Single(0.0))
                 }
             }
             Expression::TypeConversion { target_type, .. } => {
                 self.type_name_to_qtype(target_type)
             }
+            Expression::MemGet { type_spec, .. } => Ok(self.type_spec_to_qtype(type_spec)),
             Expression::FieldAccess(base, _) => {
                 // Simplified - just return type of base for now
                 self.infer_type_from_expr(base)
@@ -325,6 +408,10 @@ impl TypeChecker {
     }
 
     fn infer_type_from_spec(&self, spec: &Option<TypeSpec>, var: &qb_core::data_types::VariableId) -> QType {
+        // A _DICT holds values of its name's type
+        if matches!(spec, Some(TypeSpec::Simple(s)) if s == "_DICT") {
+            return self.infer_type_from_suffix(&var.full_name());
+        }
         if let Some(spec) = spec {
             self.type_spec_to_qtype(spec)
         } else if let Some(suffix) = &var.suffix {
@@ -379,6 +466,8 @@ impl TypeChecker {
                 "_UNSIGNED INTEGER" => QType::UnsignedInteger(0),
                 "_UNSIGNED LONG" => QType::UnsignedLong(0),
                 "_UNSIGNED _INTEGER64" => QType::UnsignedInteger64(0),
+                // A _MEM variable holds a block handle
+                "_MEM" => QType::Long(0),
                 _ => QType::Single(0.0),
             }
             TypeSpec::FixedString(_) => QType::String(String::new()),
@@ -402,34 +491,6 @@ impl TypeChecker {
         }
     }
 
-    fn infer_builtin_function_type(&self, name: &str) -> QResult<QType> {
-        let upper = name.to_uppercase();
-        match upper.as_str() {
-            // Math functions returning numeric
-            "ABS" | "ATN" | "COS" | "EXP" | "FIX" | "INT" | "LOG" | "RND" | 
-            "SGN" | "SIN" | "SQR" | "TAN" => Ok(QType::Single(0.0)),
-            // String functions
-            "CHR$" | "DATE$" | "LEFT$" | "LTRIM$" | "MID$" | "RIGHT$" | "RTRIM$" |
-            "SPACE$" | "STR$" | "STRING$" | "TIME$" | "TRIM$" | "UCASE$" | "LCASE$" |
-            "INKEY$" => Ok(QType::String(String::new())),
-            // Integer functions
-            "ASC" | "CINT" | "LEN" | "INSTR" | "LBOUND" | "UBOUND" => Ok(QType::Integer(0)),
-            "CLNG" | "FREEFILE" => Ok(QType::Long(0)),
-            // Type conversion
-            "CSNG" => Ok(QType::Single(0.0)),
-            "CDBL" => Ok(QType::Double(0.0)),
-            "CSTR" => Ok(QType::String(String::new())),
-            "VAL" => Ok(QType::Single(0.0)),
-            "TIMER" => Ok(QType::Single(0.0)),
-            // Memory
-            "PEEK" | "INP" => Ok(QType::Integer(0)),
-            // File
-            "EOF" | "LOF" | "LOC" => Ok(QType::Long(0)),
-            // Default
-            _ => Ok(QType::Single(0.0)),
-        }
-    }
-
     fn are_types_compatible(&self, target: &QType, source: &QType) -> bool {
         match (target, source) {
             (QType::String(_), QType::String(_)) => true,
@@ -451,7 +512,10 @@ impl Default for TypeChecker {
 }
 
 /// Analyze a program for semantic errors
-pub fn analyze(program: &Program) -> QResult<()> {
+pub fn analyze(program: &mut Program) -> QResult<Names> {
+    let mut names = Names::new();
+    names.canonicalize(program);
     let mut checker = TypeChecker::new();
-    checker.check_program(program)
+    checker.check_program(program)?;
+    Ok(names)
 }
@@ -0,0 +1,137 @@
+use crate::xref::{build_index, Access, SymbolIndex, SymbolKind};
+use qb_parser::ast_nodes::Program;
+use serde::Serialize;
+
+/// How a variable came into existence
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum DeclarationKind {
+    Dim,
+    Const,
+    Parameter,
+    Implicit,
+}
+
+impl DeclarationKind {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            DeclarationKind::Dim => "DIM",
+            DeclarationKind::Const => "CONST",
+            DeclarationKind::Parameter => "PARAM",
+            DeclarationKind::Implicit => "implicit",
+        }
+    }
+}
+
+/// Usage summary for a single variable
+#[derive(Debug, Clone, Serialize)]
+pub struct VariableUsage {
+    pub name: String,
+    /// Owning procedure, or `None` for module-level variables
+    pub scope: Option<String>,
+    pub inferred_type: String,
+    pub is_array: bool,
+    pub shared: bool,
+    pub declaration: DeclarationKind,
+    /// Source line of the declaration (or first use for implicit variables)
+    pub line: usize,
+    pub reads: usize,
+    pub writes: usize,
+}
+
+/// Report of every variable in a program
+#[derive(Debug, Clone, Default, Serialize)]
+pub struct UsageReport {
+    pub variables: Vec<VariableUsage>,
+}
+
+impl UsageReport {
+    /// Variables that are written but never read
+    pub fn unread(&self) -> impl Iterator<Item = &VariableUsage> {
+        self.variables.iter().filter(|v| v.reads == 0 && v.writes > 0)
+    }
+
+    /// Variables that are read but never assigned
+    pub fn unassigned(&self) -> impl Iterator<Item = &VariableUsage> {
+        self.variables.iter().filter(|v| v.writes == 0 && v.reads > 0)
+    }
+}
+
+impl From<&SymbolIndex> for UsageReport {
+    fn from(index: &SymbolIndex) -> Self {
+        let variables = index
+            .symbols
+            .iter()
+            .filter(|s| s.kind.is_variable())
+            .map(|s| VariableUsage {
+                name: s.name.clone(),
+                scope: s.scope.clone(),
+                inferred_type: s.type_name.clone().unwrap_or_default(),
+                is_array: s.is_array,
+                shared: s.shared,
+                declaration: match (s.kind, s.definition) {
+                    (SymbolKind::Constant, _) => DeclarationKind::Const,
+                    (SymbolKind::Parameter, _) => DeclarationKind::Parameter,
+                    (_, Some(_)) => DeclarationKind::Dim,
+                    (_, None) => DeclarationKind::Implicit,
+                },
+                line: s.first_line(),
+                // A CONST definition is its only assignment
+                reads: s.count(Access::Read),
+                writes: s.count(Access::Write) + usize::from(s.kind == SymbolKind::Constant),
+            })
+            .collect();
+        UsageReport { variables }
+    }
+}
+
+/// Build a variable usage report for a program
+pub fn analyze_usage(program: &Program) -> UsageReport {
+    UsageReport::from(&build_index(program))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use qb_lexer::tokenize;
+    use qb_parser::parse;
+
+    fn report(src: &str) -> UsageReport {
+        let tokens = tokenize(src).unwrap();
+        analyze_usage(&parse(tokens).unwrap())
+    }
+
+    #[test]
+    fn test_counts_reads_and_writes() {
+        let r = report("DIM SHARED total AS LONG\nx = 1\nx = x + 2\nPRINT x, total\n");
+        let x = r.variables.iter().find(|v| v.name == "X").unwrap();
+        assert_eq!((x.reads, x.writes), (2, 2));
+        assert_eq!(x.declaration, DeclarationKind::Implicit);
+        assert_eq!(x.line, 2);
+        let total = r.variables.iter().find(|v| v.name == "TOTAL").unwrap();
+        assert!(total.shared);
+        assert_eq!(total.inferred_type, "LONG");
+        assert_eq!(total.line, 1);
+    }
+
+    #[test]
+    fn test_procedure_scopes() {
+        let r = report("DIM SHARED g\nSUB Foo (a)\nb = a + g\nEND SUB\n");
+        let g: Vec<_> = r.variables.iter().filter(|v| v.name == "G").collect();
+        assert_eq!(g.len(), 1);
+        assert_eq!(g[0].scope, None);
+        assert_eq!(g[0].reads, 1);
+        let b = r.variables.iter().find(|v| v.name == "B").unwrap();
+        assert_eq!(b.scope.as_deref(), Some("FOO"));
+    }
+
+    #[test]
+    fn test_canonical_names() {
+        // Spelled out first, X and X! are one variable
+        let mut program = parse(tokenize("x = 1: x! = 2\n").unwrap()).unwrap();
+        crate::Names::new().canonicalize(&mut program);
+        let r = analyze_usage(&program);
+        assert_eq!(r.variables.len(), 1);
+        assert_eq!((r.variables[0].name.as_str(), r.variables[0].writes), ("X!", 2));
+    }
+}
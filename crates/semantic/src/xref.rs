@@ -0,0 +1,865 @@
+use indexmap::IndexMap;
+use qb_core::data_types::{ParamType, TypeSuffix, VariableId};
+use qb_parser::ast_nodes::*;
+use serde::Serialize;
+use std::collections::HashSet;
+
+/// Kind of symbol tracked by the cross-reference index
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum SymbolKind {
+    Variable,
+    Parameter,
+    Constant,
+    Label,
+    Sub,
+    Function,
+}
+
+impl SymbolKind {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            SymbolKind::Variable => "variable",
+            SymbolKind::Parameter => "parameter",
+            SymbolKind::Constant => "constant",
+            SymbolKind::Label => "label",
+            SymbolKind::Sub => "sub",
+            SymbolKind::Function => "function",
+        }
+    }
+
+    pub fn is_variable(&self) -> bool {
+        matches!(self, SymbolKind::Variable | SymbolKind::Parameter | SymbolKind::Constant)
+    }
+}
+
+/// How a symbol is used at a reference site
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Access {
+    Read,
+    Write,
+    Call,
+    Jump,
+    Declare,
+}
+
+impl Access {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Access::Read => "read",
+            Access::Write => "write",
+            Access::Call => "call",
+            Access::Jump => "jump",
+            Access::Declare => "declare",
+        }
+    }
+}
+
+/// Source location of a definition or reference.
+/// The AST only records physical lines, so spans cover a whole line.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+pub struct Span {
+    pub line: usize,
+}
+
+/// A single use of a symbol
+#[derive(Debug, Clone, Serialize)]
+pub struct Reference {
+    pub span: Span,
+    pub access: Access,
+}
+
+/// A symbol together with its definition and every reference to it
+#[derive(Debug, Clone, Serialize)]
+pub struct Symbol {
+    pub name: String,
+    pub kind: SymbolKind,
+    /// Owning procedure, or `None` for module-level symbols
+    pub scope: Option<String>,
+    /// Inferred type for variables and functions
+    pub type_name: Option<String>,
+    pub is_array: bool,
+    pub shared: bool,
+    /// Explicit definition site; `None` for implicitly created variables
+    /// and for labels or procedures that are referenced but never defined
+    pub definition: Option<Span>,
+    pub references: Vec<Reference>,
+}
+
+impl Symbol {
+    pub fn count(&self, access: Access) -> usize {
+        self.references.iter().filter(|r| r.access == access).count()
+    }
+
+    /// Line of the definition, or of the first reference for implicit symbols
+    pub fn first_line(&self) -> usize {
+        self.definition
+            .or_else(|| self.references.first().map(|r| r.span))
+            .map(|s| s.line)
+            .unwrap_or(0)
+    }
+}
+
+/// Cross-reference index of every label, procedure and variable in a program
+#[derive(Debug, Clone, Default, Serialize)]
+pub struct SymbolIndex {
+    pub symbols: Vec<Symbol>,
+}
+
+impl SymbolIndex {
+    /// Find all symbols with the given name (case-insensitive).
+    /// A name without a type suffix also matches suffixed variables.
+    pub fn lookup(&self, name: &str) -> Vec<&Symbol> {
+        let wanted = name.trim().to_uppercase();
+        self.symbols
+            .iter()
+            .filter(|s| s.name == wanted || strip_suffix(&s.name) == wanted)
+            .collect()
+    }
+
+    /// Find the symbol visible under `name` from inside `scope`
+    pub fn resolve(&self, name: &str, scope: Option<&str>) -> Option<&Symbol> {
+        let matches = self.lookup(name);
+        matches
+            .iter()
+            .find(|s| s.scope.as_deref() == scope)
+            .or_else(|| matches.iter().find(|s| s.scope.is_none()))
+            .copied()
+    }
+}
+
+/// Walks a program and builds a [`SymbolIndex`]
+pub struct SymbolIndexer {
+    symbols: IndexMap<(Option<String>, String), Symbol>,
+    default_types: [TypeSuffix; 26],
+    shared_globals: HashSet<String>,
+    procedures: HashSet<String>,
+    current_proc: Option<String>,
+    proc_locals: HashSet<String>,
+    /// Module-level variables the current procedure names in SHARED
+    proc_shared: HashSet<String>,
+    current_line: usize,
+}
+
+impl SymbolIndexer {
+    pub fn new() -> Self {
+        Self {
+            symbols: IndexMap::new(),
+            default_types: [TypeSuffix::Single; 26],
+            shared_globals: HashSet::new(),
+            procedures: HashSet::new(),
+            current_proc: None,
+            proc_locals: HashSet::new(),
+            proc_shared: HashSet::new(),
+            current_line: 0,
+        }
+    }
+
+    pub fn index(mut self, program: &Program) -> SymbolIndex {
+        // First pass: procedure names and module-level DIM SHARED variables
+        for stmt in &program.statements {
+            match stmt {
+                Statement::Sub { name, .. }
+                | Statement::Function { name, .. }
+                | Statement::Declare { name, .. } => {
+                    self.procedures.insert(name.to_uppercase());
+                }
+                Statement::Dim { vars } | Statement::ReDim { vars, .. } | Statement::Common { vars } => {
+                    for var in vars.iter().filter(|v| v.shared) {
+                        self.shared_globals.insert(var.name.full_name());
+                    }
+                }
+                _ => {}
+            }
+        }
+
+        // Second pass: definitions and references
+        self.visit_block(&program.statements);
+
+        let mut symbols: Vec<Symbol> = self.symbols.into_values().collect();
+        symbols.sort_by(|a, b| {
+            a.scope
+                .is_some()
+                .cmp(&b.scope.is_some())
+                .then_with(|| a.scope.cmp(&b.scope))
+                .then_with(|| a.name.cmp(&b.name))
+        });
+        SymbolIndex { symbols }
+    }
+
+    fn visit_block(&mut self, stmts: &[Statement]) {
+        for stmt in stmts {
+            self.visit_statement(stmt);
+        }
+    }
+
+    fn visit_statement(&mut self, stmt: &Statement) {
+        match stmt {
+            Statement::SourceLine { line } => self.current_line = *line,
+            Statement::Label { name } => self.define_global(name, SymbolKind::Label, None),
+            Statement::LineNumber { number } => {
+                self.define_global(&number.to_string(), SymbolKind::Label, None)
+            }
+            Statement::Goto { label } | Statement::Gosub { label } => self.jump(label),
+            Statement::OnGoto { expr, labels } | Statement::OnGosub { expr, labels } => {
+                self.visit_expr(expr);
+                for label in labels {
+                    self.jump(label);
+                }
+            }
+            // ON ERROR GOTO 0 disables the handler rather than naming a label
+            Statement::OnError { label } if label != "0" => self.jump(label),
+            Statement::OnEvent { arg, label, .. } => {
+                self.visit_opt(arg);
+                self.jump(label);
+            }
+            Statement::Resume { label: Some(label), .. }
+            | Statement::Restore { label: Some(label) }
+            | Statement::Return { label: Some(label) } => self.jump(label),
+            Statement::DefType { type_char, letter_range } => {
+                let suffix = match type_char {
+                    'I' => TypeSuffix::Integer,
+                    'L' => TypeSuffix::Long,
+                    'D' => TypeSuffix::Double,
+                    '$' => TypeSuffix::String,
+                    _ => TypeSuffix::Single,
+                };
+                let start = (letter_range.0.to_ascii_uppercase() as u8).saturating_sub(b'A') as usize;
+                let end = (letter_range.1.to_ascii_uppercase() as u8).saturating_sub(b'A') as usize;
+                for i in start..=end.min(25) {
+                    self.default_types[i] = suffix;
+                }
+            }
+            Statement::Dim { vars } | Statement::ReDim { vars, .. } | Statement::Common { vars } => {
+                for item in vars {
+                    for bounds in item.bounds.iter().flatten() {
+                        self.visit_expr(&bounds.lower);
+                        self.visit_expr(&bounds.upper);
+                    }
+                    let type_name = item
+                        .type_spec
+                        .as_ref()
+                        .map(type_spec_name)
+                        .unwrap_or_else(|| self.suffix_type_name(&item.name));
+                    let symbol = self.declare(&item.name, SymbolKind::Variable, type_name);
+                    symbol.is_array |= item.bounds.is_some();
+                    symbol.shared |= item.shared;
+                }
+            }
+            Statement::Shared { vars } => {
+                for item in vars {
+                    self.proc_shared.insert(item.name.full_name());
+                    self.variable(&item.name, Access::Declare, item.bounds.is_some());
+                    if let Some(symbol) = self.symbols.get_mut(&(None, item.name.full_name())) {
+                        symbol.shared = true;
+                    }
+                }
+            }
+            Statement::Static { vars } => {
+                for item in vars {
+                    let type_name = item
+                        .type_spec
+                        .as_ref()
+                        .map(type_spec_name)
+                        .unwrap_or_else(|| self.suffix_type_name(&item.name));
+                    let symbol = self.declare(&item.name, SymbolKind::Variable, type_name);
+                    symbol.is_array |= item.bounds.is_some();
+                }
+            }
+            Statement::Const { name, value } => {
+                self.visit_expr(value);
+                let type_name = self.suffix_type_name(name);
+                self.declare(name, SymbolKind::Constant, type_name);
+            }
+            Statement::Declare { is_sub, name, .. } => {
+                let kind = if *is_sub { SymbolKind::Sub } else { SymbolKind::Function };
+                self.reference_global(name, kind, Access::Declare);
+            }
+            Statement::Sub { name, params, body, .. } => {
+                self.define_global(name, SymbolKind::Sub, None);
+                self.visit_procedure(name, params, body);
+            }
+            Statement::Function { name, params, body, return_type, .. } => {
+                let type_name = match return_type {
+                    Some(spec) => type_spec_name(spec),
+                    None => self.suffix_type_name(&VariableId::new(name.clone(), None)),
+                };
+                self.define_global(name, SymbolKind::Function, Some(type_name));
+                self.visit_procedure(name, params, body);
+            }
+            Statement::Call { name, args } => {
+                self.reference_global(name, SymbolKind::Sub, Access::Call);
+                for arg in args {
+                    match arg {
+                        Argument::ByVal(e) => self.visit_expr(e),
+                        Argument::ByRef(target) => self.visit_expr(&target.to_expression()),
+                    }
+                }
+            }
+            Statement::Assignment { target, value } | Statement::LSet { target, value } | Statement::RSet { target, value } => {
+                self.visit_expr(value);
+                self.visit_lvalue(target);
+            }
+            Statement::If { condition, then_branch, else_if_branches, else_branch, .. } => {
+                self.visit_expr(condition);
+                self.visit_block(then_branch);
+                for (cond, body) in else_if_branches {
+                    self.visit_expr(cond);
+                    self.visit_block(body);
+                }
+                if let Some(body) = else_branch {
+                    self.visit_block(body);
+                }
+            }
+            Statement::Select { expr, cases, case_else } => {
+                self.visit_expr(expr);
+                for case in cases {
+                    for cond in &case.conditions {
+                        match cond {
+                            CaseCondition::Expression(e) | CaseCondition::Is(_, e) => self.visit_expr(e),
+                            CaseCondition::Range(lo, hi) => {
+                                self.visit_expr(lo);
+                                self.visit_expr(hi);
+                            }
+                        }
+                    }
+                    self.visit_block(&case.body);
+                }
+                if let Some(body) = case_else {
+                    self.visit_block(body);
+                }
+            }
+            Statement::For { var, start, end, step, body } => {
+                self.visit_expr(start);
+                self.visit_expr(end);
+                if let Some(step) = step {
+                    self.visit_expr(step);
+                }
+                self.variable(var, Access::Write, false);
+                self.visit_block(body);
+            }
+            Statement::While { condition, body }
+            | Statement::DoWhile { condition, body }
+            | Statement::DoUntil { condition, body } => {
+                self.visit_expr(condition);
+                self.visit_block(body);
+            }
+            Statement::DoLoop { body, condition, .. } => {
+                self.visit_block(body);
+                if let Some(cond) = condition {
+                    self.visit_expr(cond);
+                }
+            }
+            Statement::Print { items, .. } | Statement::LPrint { items } => self.visit_print_items(items),
+            Statement::PrintHash { fileno, items } | Statement::PrintFile { fileno, items } => {
+                self.visit_expr(fileno);
+                self.visit_print_items(items);
+            }
+            Statement::Input { vars, .. } | Statement::Read { vars } => {
+                for var in vars {
+                    self.variable(var, Access::Write, false);
+                }
+            }
+            Statement::Erase { arrays } => {
+                for var in arrays {
+                    self.variable(var, Access::Write, true);
+                }
+            }
+            Statement::InputHash { fileno, vars } | Statement::InputFile { fileno, vars } => {
+                self.visit_expr(fileno);
+                for var in vars {
+                    self.variable(var, Access::Write, false);
+                }
+            }
+            Statement::LineInput { var, .. } => self.variable(var, Access::Write, false),
+            Statement::LineInputHash { fileno, var } => {
+                self.visit_expr(fileno);
+                self.variable(var, Access::Write, false);
+            }
+            Statement::Write { fileno, items } => {
+                self.visit_opt(fileno);
+                for item in items {
+                    self.visit_expr(item);
+                }
+            }
+            Statement::Open { filename, fileno, reclen, .. } => {
+                self.visit_expr(filename);
+                self.visit_expr(fileno);
+                self.visit_opt(reclen);
+            }
+            Statement::Close { fileno } => self.visit_opt(fileno),
+            Statement::Kill { filespec } | Statement::Chain { file: filespec } => self.visit_expr(filespec),
+            Statement::OpenIsam { database, table, fileno, .. } => {
+                self.visit_expr(database);
+                self.visit_expr(table);
+                self.visit_expr(fileno);
+            }
+            Statement::Isam { fileno, args, .. } => {
+                self.visit_opt(fileno);
+                for e in args {
+                    self.visit_expr(e);
+                }
+            }
+            Statement::Get { fileno, record, var } => {
+                self.visit_expr(fileno);
+                self.visit_opt(record);
+                if let Some(var) = var {
+                    self.variable(var, Access::Write, false);
+                }
+            }
+            Statement::Put { fileno, record, var } => {
+                self.visit_expr(fileno);
+                self.visit_opt(record);
+                if let Some(var) = var {
+                    self.variable(var, Access::Read, false);
+                }
+            }
+            Statement::Field { fileno, fields } => {
+                self.visit_expr(fileno);
+                for (width, var) in fields {
+                    self.visit_expr(width);
+                    self.variable(var, Access::Write, false);
+                }
+            }
+            Statement::Seek { fileno, position } => {
+                self.visit_expr(fileno);
+                self.visit_expr(position);
+            }
+            Statement::Lock { fileno, record } | Statement::Unlock { fileno, record } => {
+                self.visit_expr(fileno);
+                if let Some((from, to)) = record {
+                    self.visit_expr(from);
+                    self.visit_opt(to);
+                }
+            }
+            Statement::Screen { mode } => self.visit_expr(mode),
+            Statement::PSet { x, y, color } => {
+                self.visit_expr(x);
+                self.visit_expr(y);
+                self.visit_opt(color);
+            }
+            Statement::PReset { x, y } => {
+                self.visit_expr(x);
+                self.visit_expr(y);
+            }
+            Statement::Line { x1, y1, x2, y2, color, style, .. } => {
+                for e in [x1, y1, x2, y2] {
+                    self.visit_expr(e);
+                }
+                self.visit_opt(color);
+                self.visit_opt(style);
+            }
+            Statement::Circle { x, y, radius, color, start, end, aspect, .. } => {
+                for e in [x, y, radius] {
+                    self.visit_expr(e);
+                }
+                for e in [color, start, end, aspect] {
+                    self.visit_opt(e);
+                }
+            }
+            Statement::Draw { command } => self.visit_expr(command),
+            Statement::Play { voices } => {
+                for e in voices {
+                    self.visit_expr(e);
+                }
+            }
+            Statement::Snd { handle, .. } => self.visit_expr(handle),
+            Statement::SndVol { handle, volume } => {
+                self.visit_expr(handle);
+                self.visit_expr(volume);
+            }
+            Statement::SndRaw { left, right } => {
+                self.visit_expr(left);
+                self.visit_opt(right);
+            }
+            Statement::Paint { x, y, paint_color, border_color } => {
+                self.visit_expr(x);
+                self.visit_expr(y);
+                self.visit_opt(paint_color);
+                self.visit_opt(border_color);
+            }
+            Statement::View { x1, y1, x2, y2, color, border } => {
+                for e in [x1, y1, x2, y2] {
+                    self.visit_expr(e);
+                }
+                self.visit_opt(color);
+                self.visit_opt(border);
+            }
+            Statement::Window { x1, y1, x2, y2, .. } => {
+                for e in [x1, y1, x2, y2] {
+                    self.visit_expr(e);
+                }
+            }
+            Statement::Palette { attribute, color } => {
+                self.visit_opt(attribute);
+                self.visit_opt(color);
+            }
+            Statement::Color { foreground, background, border } => {
+                for e in [foreground, background, border] {
+                    self.visit_opt(e);
+                }
+            }
+            Statement::Locate { row, col, cursor, start, stop } => {
+                for e in [row, col, cursor, start, stop] {
+                    self.visit_opt(e);
+                }
+            }
+            Statement::Width { value } => self.visit_expr(value),
+            Statement::Sound { frequency, duration } => {
+                self.visit_expr(frequency);
+                self.visit_expr(duration);
+            }
+            Statement::Poke { address, value } => {
+                self.visit_expr(address);
+                self.visit_expr(value);
+            }
+            Statement::DefSeg { segment } => self.visit_opt(segment),
+            Statement::Environ { expr } => self.visit_expr(expr),
+            Statement::Shell { command } => self.visit_opt(command),
+            Statement::Error { code } => self.visit_expr(code),
+            Statement::Randomize { seed } => self.visit_opt(seed),
+            Statement::Sleep { seconds } => self.visit_opt(seconds),
+            Statement::EventControl { arg, .. } => self.visit_opt(arg),
+            Statement::Limit { rate } => self.visit_expr(rate),
+            Statement::Title { text } => self.visit_expr(text),
+            Statement::ScreenMove { position: Some((x, y)) } => {
+                self.visit_expr(x);
+                self.visit_expr(y);
+            }
+            Statement::PutImage { area, source, dest, source_area } => {
+                for area in area.iter().chain(source_area) {
+                    for (x, y) in std::iter::once(&area.corner).chain(&area.opposite) {
+                        self.visit_expr(x);
+                        self.visit_expr(y);
+                    }
+                }
+                self.visit_opt(source);
+                self.visit_opt(dest);
+            }
+            Statement::PrintString { x, y, text, handle } => {
+                self.visit_expr(x);
+                self.visit_expr(y);
+                self.visit_expr(text);
+                self.visit_opt(handle);
+            }
+            Statement::SaveImage { filename, handle } => {
+                self.visit_expr(filename);
+                self.visit_opt(handle);
+            }
+            Statement::FreeImage { handle }
+            | Statement::Dest { handle }
+            | Statement::Source { handle } => self.visit_expr(handle),
+            Statement::Mat { target, source } => {
+                match source {
+                    MatSource::Fill(value) => self.visit_expr(value),
+                    MatSource::Copy(array) => self.variable(array, Access::Read, true),
+                    MatSource::Zero | MatSource::Ones => {}
+                }
+                self.variable(target, Access::Write, true);
+            }
+            Statement::MemGet { block, offset, var } => {
+                self.visit_expr(block);
+                self.visit_expr(offset);
+                self.variable(var, Access::Write, false);
+            }
+            Statement::MemPut { block, offset, value, .. } => {
+                self.visit_expr(block);
+                self.visit_expr(offset);
+                self.visit_expr(value);
+            }
+            Statement::MemFree { block } => self.visit_expr(block),
+            _ => {}
+        }
+    }
+
+    fn visit_procedure(&mut self, name: &str, params: &[Param], body: &[Statement]) {
+        self.current_proc = Some(name.to_uppercase());
+        self.proc_locals.clear();
+        self.proc_shared.clear();
+        for param in params {
+            let var = match &param.kind {
+                ParamType::ByVal(v) | ParamType::ByRef(v) | ParamType::Array(v) => v,
+            };
+            let type_name = match &param.type_spec {
+                Some(spec) => type_spec_name(spec),
+                None => self.suffix_type_name(var),
+            };
+            self.declare(var, SymbolKind::Parameter, type_name);
+        }
+        self.visit_block(body);
+        self.current_proc = None;
+        self.proc_locals.clear();
+        self.proc_shared.clear();
+    }
+
+    fn visit_print_items(&mut self, items: &[PrintItem]) {
+        for item in items {
+            if let PrintItem::Expression(e) | PrintItem::Using(e) = item {
+                self.visit_expr(e);
+            }
+        }
+    }
+
+    fn visit_opt(&mut self, expr: &Option<Expression>) {
+        if let Some(e) = expr {
+            self.visit_expr(e);
+        }
+    }
+
+    fn visit_expr(&mut self, expr: &Expression) {
+        match expr {
+            Expression::Variable(var) => self.variable(var, Access::Read, false),
+            Expression::ArrayAccess(var, indices) => {
+                for index in indices {
+                    self.visit_expr(index);
+                }
+                self.variable(var, Access::Read, true);
+            }
+            Expression::FieldAccess(base, _) => self.visit_expr(base),
+            Expression::Negate(e) | Expression::Not(e) => self.visit_expr(e),
+            Expression::Binary { left, right, .. } => {
+                self.visit_expr(left);
+                self.visit_expr(right);
+            }
+            Expression::FunctionCall { name, args } => {
+                if self.procedures.contains(&name.to_uppercase()) {
+                    self.reference_global(name, SymbolKind::Function, Access::Call);
+                }
+                for arg in args {
+                    self.visit_expr(arg);
+                }
+            }
+            Expression::TypeConversion { expr, .. } => self.visit_expr(expr),
+            Expression::MemGet { block, offset, .. } => {
+                self.visit_expr(block);
+                self.visit_expr(offset);
+            }
+            _ => {}
+        }
+    }
+
+    fn visit_lvalue(&mut self, target: &LValue) {
+        match target {
+            LValue::Variable(var) => self.variable(var, Access::Write, false),
+            LValue::ArrayElement(var, indices) => {
+                for index in indices {
+                    self.visit_expr(index);
+                }
+                self.variable(var, Access::Write, true);
+            }
+            LValue::Field(base, _) => self.visit_lvalue(base),
+        }
+    }
+
+    fn span(&self) -> Span {
+        Span { line: self.current_line }
+    }
+
+    fn jump(&mut self, label: &str) {
+        self.reference_global(label, SymbolKind::Label, Access::Jump);
+    }
+
+    fn define_global(&mut self, name: &str, kind: SymbolKind, type_name: Option<String>) {
+        let span = self.span();
+        let symbol = self.global_entry(name, kind);
+        symbol.definition = Some(span);
+        if type_name.is_some() {
+            symbol.type_name = type_name;
+        }
+    }
+
+    fn reference_global(&mut self, name: &str, kind: SymbolKind, access: Access) {
+        let span = self.span();
+        self.global_entry(name, kind).references.push(Reference { span, access });
+    }
+
+    fn global_entry(&mut self, name: &str, kind: SymbolKind) -> &mut Symbol {
+        let name = name.to_uppercase();
+        // Labels live in their own namespace; SUBs and FUNCTIONs share one
+        let key_scope = if kind == SymbolKind::Label { Some(String::from(":label")) } else { None };
+        let key = (key_scope, name.clone());
+        self.symbols.entry(key).or_insert_with(|| Symbol {
+            name,
+            kind,
+            scope: None,
+            type_name: None,
+            is_array: false,
+            shared: false,
+            definition: None,
+            references: Vec::new(),
+        })
+    }
+
+    /// Record a read or write of a variable, creating it implicitly if needed.
+    /// References that are really procedure names (a FUNCTION call parsed as
+    /// a variable, or the return value assignment) go to the procedure.
+    fn variable(&mut self, var: &VariableId, access: Access, is_array: bool) {
+        let name = var.full_name();
+        let span = self.span();
+        if self.procedures.contains(&name) {
+            let access = if access == Access::Read { Access::Call } else { access };
+            self.reference_global(&name, SymbolKind::Function, access);
+            return;
+        }
+        let key = self.resolve(&name);
+        if !self.symbols.contains_key(&key) {
+            let type_name = self.suffix_type_name(var);
+            self.insert(key.clone(), name, SymbolKind::Variable, type_name);
+        }
+        if let Some(symbol) = self.symbols.get_mut(&key) {
+            symbol.is_array |= is_array;
+            symbol.references.push(Reference { span, access });
+        }
+    }
+
+    fn declare(&mut self, var: &VariableId, kind: SymbolKind, type_name: String) -> &mut Symbol {
+        let name = var.full_name();
+        let key = (self.current_proc.clone(), name.clone());
+        if self.current_proc.is_some() {
+            self.proc_locals.insert(name.clone());
+        }
+        let span = self.span();
+        match self.symbols.get_mut(&key) {
+            Some(symbol) => {
+                // An explicit declaration after implicit use still defines the type
+                if symbol.definition.is_none() {
+                    symbol.kind = kind;
+                    symbol.type_name = Some(type_name);
+                    symbol.definition = Some(span);
+                }
+            }
+            None => {
+                self.insert(key.clone(), name, kind, type_name);
+                if let Some(symbol) = self.symbols.get_mut(&key) {
+                    symbol.definition = Some(span);
+                }
+            }
+        }
+        self.symbols.get_mut(&key).expect("entry inserted above")
+    }
+
+    fn insert(&mut self, key: (Option<String>, String), name: String, kind: SymbolKind, type_name: String) {
+        let shared = key.0.is_none() && self.shared_globals.contains(&name);
+        self.symbols.insert(key.clone(), Symbol {
+            name,
+            kind,
+            scope: key.0,
+            type_name: Some(type_name),
+            is_array: false,
+            shared,
+            definition: None,
+            references: Vec::new(),
+        });
+    }
+
+    /// Procedures see module-level variables only when they are DIM SHARED,
+    /// or named in a SHARED statement of their own
+    fn resolve(&self, name: &str) -> (Option<String>, String) {
+        match &self.current_proc {
+            Some(_)
+                if !self.proc_locals.contains(name)
+                    && (self.shared_globals.contains(name) || self.proc_shared.contains(name)) =>
+            {
+                (None, name.to_string())
+            }
+            Some(proc) => (Some(proc.clone()), name.to_string()),
+            None => (None, name.to_string()),
+        }
+    }
+
+    fn suffix_type_name(&self, var: &VariableId) -> String {
+        let suffix = var.suffix.or_else(|| {
+            let base = strip_suffix(&var.name);
+            match &var.name[base.len()..] {
+                "" => None,
+                s => TypeSuffix::from_str(s),
+            }
+        });
+        let suffix = suffix.unwrap_or_else(|| {
+            let first = var.name.chars().next().unwrap_or('A').to_ascii_uppercase();
+            if first.is_ascii_uppercase() {
+                self.default_types[(first as u8 - b'A') as usize]
+            } else {
+                TypeSuffix::Single
+            }
+        });
+        suffix_type_name(suffix).to_string()
+    }
+}
+
+impl Default for SymbolIndexer {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+fn strip_suffix(name: &str) -> &str {
+    name.trim_end_matches(['%', '&', '!', '#', '$'])
+}
+
+fn suffix_type_name(suffix: TypeSuffix) -> &'static str {
+    match suffix {
+        TypeSuffix::Integer => "INTEGER",
+        TypeSuffix::Long => "LONG",
+        TypeSuffix::Single => "SINGLE",
+        TypeSuffix::Double => "DOUBLE",
+        TypeSuffix::String => "STRING",
+        TypeSuffix::Integer64 => "_INTEGER64",
+        TypeSuffix::Float => "_FLOAT",
+    }
+}
+
+fn type_spec_name(spec: &TypeSpec) -> String {
+    match spec {
+        TypeSpec::Simple(name) | TypeSpec::UserDefined(name) => name.to_uppercase(),
+        TypeSpec::FixedString(Expression::Integer(n)) => format!("STRING * {}", n),
+        TypeSpec::FixedString(_) => "STRING * n".to_string(),
+    }
+}
+
+/// Build the cross-reference index for a program
+pub fn build_index(program: &Program) -> SymbolIndex {
+    SymbolIndexer::new().index(program)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use qb_lexer::tokenize;
+    use qb_parser::parse;
+
+    fn index(src: &str) -> SymbolIndex {
+        build_index(&parse(tokenize(src).unwrap()).unwrap())
+    }
+
+    #[test]
+    fn test_label_references() {
+        let idx = index("GOSUB Work\nEND\nWork:\nPRINT 1\nRETURN\n");
+        let work = idx.lookup("work");
+        assert_eq!(work.len(), 1);
+        assert_eq!(work[0].kind, SymbolKind::Label);
+        assert_eq!(work[0].definition, Some(Span { line: 3 }));
+        assert_eq!(work[0].references.len(), 1);
+        assert_eq!(work[0].references[0].span.line, 1);
+    }
+
+    #[test]
+    fn test_sub_references() {
+        let idx = index("CALL Greet\nCALL Greet\nSUB Greet\nPRINT \"hi\"\nEND SUB\n");
+        let greet = idx.lookup("GREET")[0];
+        assert_eq!(greet.kind, SymbolKind::Sub);
+        assert_eq!(greet.definition, Some(Span { line: 3 }));
+        assert_eq!(greet.count(Access::Call), 2);
+    }
+
+    #[test]
+    fn test_shared_statement() {
+        let idx = index("total = 1\nSUB Add\nSHARED total\ntotal = total + 1\nlocal = 2\nEND SUB\n");
+        let total = idx.lookup("TOTAL");
+        assert_eq!(total.len(), 1);
+        assert!(total[0].shared && total[0].scope.is_none());
+        assert_eq!(total[0].count(Access::Write), 2);
+        assert_eq!(idx.lookup("LOCAL")[0].scope.as_deref(), Some("ADD"));
+    }
+}
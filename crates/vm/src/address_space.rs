@@ -0,0 +1,103 @@
+//! PEEK, POKE and DEF SEG: a 1 MB DOS address space. The BIOS data area
+//! stays live where programs poll it: the keyboard buffer pointers at
+//! 0040:001A and the timer tick count at 0040:006C.
+
+use qb_core::memory_map::DosMemory;
+use qb_hal::{Clock, KeyBuffer};
+
+/// Segment of the BIOS data area
+const BIOS_SEGMENT: usize = 0x40;
+/// Offset of the tick count (a DWORD) in the BIOS data area
+const TICKS_OFFSET: usize = 0x6C;
+/// Timer ticks in a day, about 18.2 a second
+const TICKS_PER_DAY: f64 = 1_573_040.0;
+/// Stands in for BASIC's own data segment, the default of DEF SEG
+const DATA_SEGMENT: u16 = 0x1000;
+
+pub struct AddressSpace {
+    ram: DosMemory,
+    /// Set by DEF SEG; None for the default data segment
+    segment: Option<u16>,
+    pub keys: KeyBuffer,
+    /// Drives the tick count
+    pub clock: Clock,
+}
+
+impl AddressSpace {
+    pub fn new() -> Self {
+        Self { ram: DosMemory::new(), segment: None, keys: KeyBuffer::new(), clock: Clock::real() }
+    }
+
+    /// DEF SEG = segment, or plain DEF SEG for None
+    pub fn set_segment(&mut self, segment: Option<u16>) {
+        self.segment = segment;
+    }
+
+    /// Physical address of `offset` in the current segment, wrapping at
+    /// 1 MB as the 8086 did
+    fn address(&self, offset: u16) -> usize {
+        let segment = self.segment.unwrap_or(DATA_SEGMENT);
+        (usize::from(segment) * 16 + usize::from(offset)) & (DosMemory::SIZE - 1)
+    }
+
+    /// Offset within the BIOS data area, if `address` is in it
+    fn bios_offset(address: usize) -> Option<u16> {
+        (DosMemory::BIOS_DATA_START..=DosMemory::BIOS_DATA_END)
+            .contains(&address)
+            .then(|| (address - BIOS_SEGMENT * 16) as u16)
+    }
+
+    pub fn peek(&self, offset: u16) -> u8 {
+        let address = self.address(offset);
+        match Self::bios_offset(address) {
+            Some(bios) if KeyBuffer::contains(bios) => self.keys.peek(bios),
+            Some(bios) if (TICKS_OFFSET..TICKS_OFFSET + 4).contains(&usize::from(bios)) => {
+                let ticks = (self.clock.seconds_since_midnight() * TICKS_PER_DAY / 86_400.0) as u32;
+                ticks.to_le_bytes()[usize::from(bios) - TICKS_OFFSET]
+            }
+            _ => self.ram.peek(address).unwrap_or_default(),
+        }
+    }
+
+    /// Writes to the tick count are kept but never read back, since it
+    /// follows the clock
+    pub fn poke(&mut self, offset: u16, value: u8) {
+        let address = self.address(offset);
+        match Self::bios_offset(address) {
+            Some(bios) if KeyBuffer::contains(bios) => self.keys.poke(bios, value),
+            _ => {
+                let _ = self.ram.poke(address, value);
+            }
+        }
+    }
+}
+
+impl Default for AddressSpace {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_bios_area_is_live() {
+        let mut memory = AddressSpace::new();
+        memory.set_segment(Some(0));
+        memory.keys.push(b'q', 0x10);
+        assert_ne!(memory.peek(0x41A), memory.peek(0x41C));
+        memory.poke(0x41A, memory.peek(0x41C));
+        assert!(memory.keys.is_empty());
+
+        // The same byte through another segment
+        memory.set_segment(Some(0x40));
+        assert_eq!(memory.peek(0x1A), memory.peek(0x1C));
+
+        memory.poke(0x100, 42);
+        assert_eq!(memory.peek(0x100), 42);
+        memory.set_segment(None);
+        assert_eq!(memory.peek(0x100), 0);
+    }
+}
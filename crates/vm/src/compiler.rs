@@ -1,16 +1,33 @@
-use crate::opcodes::{ByteCode, OpCode};
-use qb_core::data_types::QType;
+use crate::events::{TrapSource, TrapState};
+use crate::files::{Access, Lock};
+use crate::mem::MemField;
+use crate::opcodes::{ArgPass, ByteCode, CommonItem, OpCode, ProcEntry};
+use qb_core::builtins;
+use qb_core::data_types::{ParamType, QType, TypeSuffix, VariableId};
+use qb_hal::display::{Filter, FullScreen};
+use qb_hal::graphics::LineShape;
+use qb_hal::window::ResizeMode as WindowResize;
 use qb_core::errors::{QError, QErrorCode, QResult};
-use std::collections::HashMap;
+use std::collections::{HashMap, HashSet};
 use qb_parser::ast_nodes::*;
 
 /// Compiles AST to bytecode
 pub struct ByteCodeCompiler {
     bytecode: ByteCode,
     label_addresses: HashMap<String, u32>,
-    data_label_addresses: HashMap<String, u32>, // For DATA/RESTORE
     pending_jumps: Vec<(usize, String)>, // (instruction_index, label_name)
     current_line: usize,
+    mem_vars: HashSet<String>, // Variables declared AS _MEM
+    procedures: HashMap<String, Vec<ParamType>>, // SUB and FUNCTION parameters by name
+    procedure_addresses: HashMap<String, u32>,
+    pending_calls: Vec<(usize, String)>, // (instruction_index, procedure name)
+    shared_vars: Vec<String>, // DIM SHARED at module level
+    in_procedure: bool,
+    user_types: HashMap<String, Vec<(String, TypeSpec)>>, // TYPE fields by type name
+    records: HashMap<String, String>, // Variables DIMmed AS a TYPE, with its name
+    arrays: HashMap<String, bool>, // Arrays DIMmed so far in this scope, and whether each is dynamic
+    dynamic_arrays: bool, // $DYNAMIC is in effect
+    explicit_arrays: bool, // OPTION _EXPLICITARRAY
 }
 
 impl ByteCodeCompiler {
@@ -18,9 +35,19 @@ impl ByteCodeCompiler {
         Self {
             bytecode: ByteCode::new(),
             label_addresses: HashMap::new(),
-            data_label_addresses: HashMap::new(),
             pending_jumps: Vec::new(),
             current_line: 1,
+            mem_vars: HashSet::new(),
+            procedures: HashMap::new(),
+            procedure_addresses: HashMap::new(),
+            pending_calls: Vec::new(),
+            shared_vars: Vec::new(),
+            in_procedure: false,
+            user_types: HashMap::new(),
+            records: HashMap::new(),
+            arrays: HashMap::new(),
+            dynamic_arrays: false,
+            explicit_arrays: false,
         }
     }
 
@@ -28,40 +55,200 @@ impl ByteCodeCompiler {
         // First pass: collect DATA items and their labels
         self.collect_data_labels(program)?;
         
-        // Second pass: compile statements - labels are collected during compilation
+        // Procedure parameters, so calls can be compiled before the bodies,
+        // and the layout of each TYPE
         for stmt in &program.statements {
-            // Collect label at current instruction position (before compiling statement)
             match stmt {
-                Statement::Label { name } => {
-                    self.label_addresses.insert(name.to_uppercase(), self.bytecode.len() as u32);
+                Statement::Sub { name, params, .. } | Statement::Function { name, params, .. } => {
+                    self.procedures.insert(name.to_uppercase(), params.iter().map(|param| param.kind.clone()).collect());
                 }
-                Statement::LineNumber { number } => {
-                    self.label_addresses.insert(number.to_string(), self.bytecode.len() as u32);
+                Statement::TypeDef { name, fields } => {
+                    self.user_types.insert(name.to_uppercase(), fields.clone());
                 }
                 _ => {}
             }
-            self.compile_statement(stmt)?;
         }
 
+        // Second pass: compile statements - labels are collected during compilation
+        self.compile_body(&program.statements)?;
+
         // Add halt at end
         self.bytecode.emit(OpCode::Halt);
 
+        // Each SUB and FUNCTION follows the module code
+        for stmt in &program.statements {
+            match stmt {
+                Statement::Sub { name, params, body, is_static } => {
+                    self.compile_procedure(name, params, None, body, *is_static)?
+                }
+                Statement::Function { name, params, return_type, body, is_static } => {
+                    let result = match return_type {
+                        Some(spec) => self.type_spec_to_qtype(spec),
+                        None => TypeSuffix::of_name(name).map_or(QType::Single(0.0), |s| s.default_value()),
+                    };
+                    self.compile_procedure(name, params, Some(result), body, *is_static)?;
+                }
+                _ => {}
+            }
+        }
+
         // Resolve pending jumps
         self.resolve_jumps()?;
+        for (idx, name) in &self.pending_calls {
+            if let OpCode::CallProc(addr, _) = &mut self.bytecode.instructions[*idx] {
+                *addr = self.procedure_addresses[name];
+            }
+        }
 
         Ok(self.bytecode)
     }
+
+    /// Statements of the module or a procedure
+    fn compile_body(&mut self, stmts: &[Statement]) -> QResult<()> {
+        for stmt in stmts {
+            self.compile_statement(stmt)?;
+        }
+        Ok(())
+    }
+
+    /// A SUB or FUNCTION (with the initial value of its result): its
+    /// parameters are bound to the call's arguments in a frame of its own.
+    /// Its SHARED and STATIC statements are taken in before the body.
+    fn compile_procedure(
+        &mut self,
+        name: &str,
+        params: &[Param],
+        result: Option<QType>,
+        body: &[Statement],
+        is_static: bool,
+    ) -> QResult<()> {
+        let name = name.to_uppercase();
+        self.procedure_addresses.insert(name.clone(), self.bytecode.len() as u32);
+        let mut shared: Vec<String> = self.bytecode.const_names.iter().chain(&self.shared_vars).cloned().collect();
+        let mut statics = Vec::new();
+        for stmt in body {
+            match stmt {
+                Statement::Shared { vars } => {
+                    for var in vars {
+                        let full = var.name.full_name();
+                        if let Some(type_name) = self.records.get(&full).cloned() {
+                            shared.extend(self.record_fields(&full, &type_name)?.into_iter().map(|(field, _)| field));
+                        }
+                        shared.push(full);
+                    }
+                }
+                Statement::Static { vars } => {
+                    for var in vars.iter().filter(|var| var.bounds.is_none()) {
+                        let full = var.name.full_name();
+                        if let Some(TypeSpec::UserDefined(type_name)) = &var.type_spec {
+                            statics.extend(self.record_fields(&full, type_name)?);
+                            self.records.insert(full.clone(), type_name.to_uppercase());
+                        }
+                        let blank = match &var.type_spec {
+                            Some(spec) => self.type_spec_to_qtype(spec),
+                            None => TypeSuffix::of_name(&full).map_or(QType::Single(0.0), |s| s.default_value()),
+                        };
+                        statics.push((full, blank));
+                    }
+                }
+                _ => {}
+            }
+        }
+        let entry = ProcEntry {
+            name: name.clone(),
+            params: params.iter().map(|param| match &param.kind {
+                ParamType::ByVal(var) | ParamType::ByRef(var) | ParamType::Array(var) => var.full_name(),
+            }).collect(),
+            result: result.map(|blank| (name, blank)),
+            shared,
+            statics,
+            all_static: is_static,
+        };
+        // The procedure sees the module's shared arrays and its array parameters
+        let module_arrays = std::mem::take(&mut self.arrays);
+        for (array, dynamic) in &module_arrays {
+            if entry.shared.contains(array) {
+                self.arrays.insert(array.clone(), *dynamic);
+            }
+        }
+        self.bytecode.emit(OpCode::EnterProc(Box::new(entry)));
+        for param in params {
+            if let ParamType::Array(var) = &param.kind {
+                self.arrays.insert(var.full_name(), true);
+            }
+        }
+        self.in_procedure = true;
+        self.compile_body(body)?;
+        self.in_procedure = false;
+        self.arrays = module_arrays;
+        self.bytecode.emit(OpCode::LeaveProc);
+        Ok(())
+    }
+
+    /// CALL or a FUNCTION reference. A variable or array element passed
+    /// to a BYREF parameter is passed itself, as is a whole array passed
+    /// as a() to an array parameter; anything else is a copy.
+    fn compile_call(&mut self, name: &str, args: &[Argument]) -> QResult<()> {
+        let name = name.to_uppercase();
+        let Some(params) = self.procedures.get(&name).cloned() else {
+            return Err(QError::compile("Subprogram not defined", self.current_line, 0));
+        };
+        if params.len() != args.len() {
+            return Err(QError::compile("Argument-count mismatch", self.current_line, 0));
+        }
+        let mut passing = Vec::with_capacity(args.len());
+        for (param, arg) in params.iter().zip(args) {
+            let pass = match (param, arg) {
+                (ParamType::Array(_), Argument::ByRef(LValue::ArrayElement(var, indices))) if indices.is_empty() => {
+                    self.check_array(var)?;
+                    ArgPass::Array(var.full_name())
+                }
+                (ParamType::Array(_), _) => {
+                    return Err(QError::compile("Parameter type mismatch", self.current_line, 0));
+                }
+                (_, Argument::ByRef(LValue::ArrayElement(_, indices))) if indices.is_empty() => {
+                    return Err(QError::compile("Parameter type mismatch", self.current_line, 0));
+                }
+                (ParamType::ByRef(_), Argument::ByRef(LValue::Variable(var)))
+                    if !self.bytecode.const_names.contains(&var.full_name()) =>
+                {
+                    ArgPass::Var(var.full_name())
+                }
+                (ParamType::ByRef(_), Argument::ByRef(LValue::ArrayElement(var, indices))) => {
+                    self.check_element(var, indices.len())?;
+                    for index in indices {
+                        self.compile_expression(index)?;
+                    }
+                    ArgPass::Element(var.full_name(), indices.len())
+                }
+                (_, Argument::ByVal(expr)) => {
+                    self.compile_expression(expr)?;
+                    ArgPass::Value
+                }
+                (_, Argument::ByRef(target)) => {
+                    self.compile_expression(&target.to_expression())?;
+                    ArgPass::Value
+                }
+            };
+            passing.push(pass);
+        }
+        self.pending_calls.push((self.bytecode.len(), name));
+        self.bytecode.emit(OpCode::CallProc(0, passing)); // Placeholder
+        Ok(())
+    }
     
     fn collect_data_labels(&mut self, program: &Program) -> QResult<()> {
         for stmt in &program.statements {
             match stmt {
                 Statement::Label { name } => {
                     // Store current data pointer position for this label
-                    self.data_label_addresses.insert(name.to_uppercase(), self.bytecode.data_items.len() as u32);
+                    let index = self.bytecode.data_items.len() as u32;
+                    self.bytecode.data_labels.insert(name.to_uppercase(), index);
                 }
                 Statement::LineNumber { number } => {
                     // Store current data pointer position for this line number
-                    self.data_label_addresses.insert(number.to_string(), self.bytecode.data_items.len() as u32);
+                    let index = self.bytecode.data_items.len() as u32;
+                    self.bytecode.data_labels.insert(number.to_string(), index);
                 }
                 Statement::Data { values } => {
                     // Add data items and track the index
@@ -111,6 +298,20 @@ impl ByteCodeCompiler {
                     OpCode::Call(_) => {
                         self.bytecode.instructions[*idx] = OpCode::Call(addr);
                     }
+                    OpCode::OnEvent(source, _) => {
+                        self.bytecode.instructions[*idx] = OpCode::OnEvent(source, addr);
+                    }
+                    OpCode::OnError(_) => {
+                        self.bytecode.instructions[*idx] = OpCode::OnError(Some(addr));
+                    }
+                    OpCode::ResumeAt(_) => {
+                        self.bytecode.instructions[*idx] = OpCode::ResumeAt(addr);
+                    }
+                    OpCode::ReturnTo(_) => {
+                        self.bytecode.instructions[*idx] = OpCode::ReturnTo(addr);
+                    }
+                    // Its labels are pending in order
+                    OpCode::OnJump(ref mut targets, _) => targets.push(addr),
                     _ => {}
                 }
             } else {
@@ -129,30 +330,120 @@ impl ByteCodeCompiler {
             Statement::Rem(_) => {
                 // Comments are ignored
             }
-            Statement::Dim { vars } => {
+            Statement::Dim { vars } | Statement::ReDim { vars, .. } => {
+                let redim = matches!(stmt, Statement::ReDim { .. });
+                let preserve = matches!(stmt, Statement::ReDim { preserve: true, .. });
                 for var in vars {
-                    // Check if it's an array
-                    if let Some(ref bounds) = var.bounds {
-                        // Array - emit DimArray opcode with shape and type
-                        let shape: Vec<(i32, i32)> = bounds.iter().map(|b| (b.lower, b.upper)).collect();
+                    if var.shared && !self.in_procedure {
+                        self.shared_vars.push(var.name.full_name());
+                    }
+                    let is_dict = matches!(&var.type_spec, Some(TypeSpec::Simple(s)) if s == "_DICT");
+                    let shape = var.bounds.as_deref().and_then(constant_shape);
+                    if is_dict || var.bounds.is_some() {
+                        // An array sized as the program runs is dynamic
+                        self.dimension(&var.name, redim || (var.bounds.is_some() && shape.is_none()))?;
+                    }
+                    if is_dict {
+                        if var.bounds.is_some() {
+                            return Err(QError::runtime(QErrorCode::TypeMismatch, self.current_line, 0));
+                        }
+                        let blank = TypeSuffix::of_name(&var.name.full_name())
+                            .map_or(QType::Single(0.0), |s| s.default_value());
+                        self.bytecode.emit(OpCode::DimDict(var.name.full_name(), blank));
+                    } else if let Some(ref bounds) = var.bounds {
                         let type_str = if let Some(ref spec) = var.type_spec {
                             match spec {
                                 TypeSpec::Simple(s) => s.clone(),
                                 _ => "SINGLE".to_string(),
                             }
                         } else {
-                            "SINGLE".to_string()
+                            TypeSuffix::of_name(&var.name.full_name())
+                                .map_or(QType::Single(0.0), |s| s.default_value())
+                                .type_name()
+                                .to_string()
                         };
-                        self.bytecode.emit(OpCode::DimArray(var.name.full_name(), shape, type_str));
+                        let dynamic = self.arrays.get(&var.name.full_name()) == Some(&true);
+                        match shape {
+                            Some(shape) if !dynamic => {
+                                self.bytecode.emit(OpCode::DimArray(var.name.full_name(), shape, type_str));
+                            }
+                            _ => {
+                                for dim in bounds {
+                                    self.compile_expression(&dim.lower)?;
+                                    self.compile_expression(&dim.upper)?;
+                                }
+                                self.bytecode.emit(OpCode::ReDim(var.name.full_name(), bounds.len(), type_str, preserve));
+                            }
+                        }
                     } else {
                         // Scalar variable - Initialize with default value
                         let type_ = if let Some(ref spec) = var.type_spec {
+                            if matches!(spec, TypeSpec::Simple(s) if s == "_MEM") {
+                                self.mem_vars.insert(var.name.full_name());
+                            }
                             self.type_spec_to_qtype(spec)
                         } else {
-                            QType::Single(0.0)
+                            TypeSuffix::of_name(&var.name.full_name())
+                                .map_or(QType::Single(0.0), |s| s.default_value())
                         };
                         self.bytecode.emit(OpCode::Push(type_.default_value()));
                         self.bytecode.emit(OpCode::StoreVar(var.name.full_name()));
+                        if let Some(TypeSpec::UserDefined(type_name)) = &var.type_spec {
+                            // Each field is a variable of its own, record.field
+                            for (field, blank) in self.record_fields(&var.name.full_name(), type_name)? {
+                                self.bytecode.emit(OpCode::Push(blank));
+                                self.bytecode.emit(OpCode::StoreVar(field));
+                            }
+                            self.records.insert(var.name.full_name(), type_name.to_uppercase());
+                        }
+                    }
+                }
+            }
+            Statement::ArrayStorage { dynamic } => self.dynamic_arrays = *dynamic,
+            Statement::Erase { arrays } => {
+                for var in arrays {
+                    self.check_array(var)?;
+                    self.bytecode.emit(OpCode::Erase(var.full_name()));
+                }
+            }
+            Statement::ExplicitArrays => self.explicit_arrays = true,
+            // Taken in when the procedure is entered
+            Statement::Shared { .. } | Statement::Static { .. } if self.in_procedure => {}
+            Statement::Shared { .. } | Statement::Static { .. } => {
+                return Err(QError::compile("Illegal outside SUB, FUNCTION or DEF FN", self.current_line, 0));
+            }
+            Statement::Common { .. } if self.in_procedure => {
+                return Err(QError::compile("Illegal in procedure or DEF FN", self.current_line, 0));
+            }
+            // A record passes on its fields one by one, so a chained
+            // program may call it and them by other names
+            Statement::Common { vars } => {
+                for var in vars {
+                    let full = var.name.full_name();
+                    if var.shared {
+                        self.shared_vars.push(full.clone());
+                    }
+                    let mut items = Vec::new();
+                    if var.bounds.is_some() {
+                        // Dimensioned by a later DIM or REDIM, or by the
+                        // program that chained here
+                        self.arrays.entry(full.clone()).or_insert(true);
+                        items.push(CommonItem::Array(full));
+                    } else {
+                        let blank = match &var.type_spec {
+                            Some(spec) => self.type_spec_to_qtype(spec),
+                            None => TypeSuffix::of_name(&full).map_or(QType::Single(0.0), |s| s.default_value()),
+                        };
+                        items.push(CommonItem::Variable(full.clone(), blank));
+                        if let Some(TypeSpec::UserDefined(type_name)) = &var.type_spec {
+                            let fields = self.record_fields(&full, type_name)?;
+                            items.extend(fields.into_iter().map(|(field, blank)| CommonItem::Variable(field, blank)));
+                            self.records.insert(full, type_name.to_uppercase());
+                        }
+                    }
+                    for item in items {
+                        self.bytecode.emit(OpCode::Common(self.bytecode.common.len() as u32));
+                        self.bytecode.common.push(item);
                     }
                 }
             }
@@ -160,6 +451,7 @@ impl ByteCodeCompiler {
                 // Initialize constant
                 self.compile_expression(value)?;
                 self.bytecode.emit(OpCode::StoreVar(name.full_name()));
+                self.bytecode.const_names.push(name.full_name());
             }
             Statement::Assignment { target, value } => {
                 match target {
@@ -168,6 +460,7 @@ impl ByteCodeCompiler {
                         self.bytecode.emit(OpCode::StoreVar(var.full_name()));
                     }
                     LValue::ArrayElement(var, indices) => {
+                        self.check_element(var, indices.len())?;
                         // For array: compile indices first, then value
                         for idx in indices {
                             self.compile_expression(idx)?;
@@ -215,39 +508,31 @@ impl ByteCodeCompiler {
                 self.compile_expression(expr)?;
                 
                 let mut end_jumps = Vec::new();
-                let mut next_case_jump = None;
                 
                 for case in cases {
-                    if let Some(idx) = next_case_jump {
-                        let current_idx = self.bytecode.len() as u32;
-                        self.bytecode.instructions[idx] = OpCode::JumpIfFalse(current_idx);
-                    }
-                    
-                    // Evaluate case conditions (combined with OR)
-                    // For now, only simple Expressions are handled exactly (Is and Range omitted for simplicity)
-                    let mut first = true;
+                    // Each condition tests a copy of 'expr' and jumps to the body
+                    // on its first match; a failed test moves on to the next one
+                    let mut body_jumps = Vec::new();
                     for cond in &case.conditions {
-                        if !first {
-                            self.bytecode.emit(OpCode::LogOr);
-                        }
-                        
+                        let mut fail_jumps = Vec::new();
                         match cond {
                             CaseCondition::Expression(e) => {
-                                self.bytecode.emit(OpCode::Dup); // Duplicate 'expr' to compare
+                                self.bytecode.emit(OpCode::Dup);
                                 self.compile_expression(e)?;
                                 self.bytecode.emit(OpCode::Eq);
+                                fail_jumps.push(self.bytecode.emit(OpCode::JumpIfFalse(0)));
                             }
                             CaseCondition::Range(start, end) => {
                                 // expr >= start AND expr <= end
                                 self.bytecode.emit(OpCode::Dup);
                                 self.compile_expression(start)?;
                                 self.bytecode.emit(OpCode::Ge);
+                                fail_jumps.push(self.bytecode.emit(OpCode::JumpIfFalse(0)));
                                 
                                 self.bytecode.emit(OpCode::Dup);
                                 self.compile_expression(end)?;
                                 self.bytecode.emit(OpCode::Le);
-                                
-                                self.bytecode.emit(OpCode::LogAnd);
+                                fail_jumps.push(self.bytecode.emit(OpCode::JumpIfFalse(0)));
                             }
                             CaseCondition::Is(op_tok, e) => {
                                 self.bytecode.emit(OpCode::Dup);
@@ -257,29 +542,33 @@ impl ByteCodeCompiler {
                                 } else {
                                     self.bytecode.emit(OpCode::Eq); // Fallback
                                 }
+                                fail_jumps.push(self.bytecode.emit(OpCode::JumpIfFalse(0)));
                             }
                         }
-                        first = false;
+                        body_jumps.push(self.bytecode.emit(OpCode::Jump(0)));
+                        let next_cond = self.bytecode.len() as u32;
+                        for idx in fail_jumps {
+                            self.bytecode.instructions[idx] = OpCode::JumpIfFalse(next_cond);
+                        }
                     }
                     
-                    let false_jump = self.bytecode.len();
-                    self.bytecode.emit(OpCode::JumpIfFalse(0)); // Jump to next case
-                    next_case_jump = Some(false_jump);
+                    // No condition matched: skip the body
+                    let next_case_jump = self.bytecode.emit(OpCode::Jump(0));
                     
                     // Case body
+                    let body_start = self.bytecode.len() as u32;
+                    for idx in body_jumps {
+                        self.bytecode.instructions[idx] = OpCode::Jump(body_start);
+                    }
                     for s in &case.body {
                         self.compile_statement(s)?;
                     }
                     
                     // Jump to end of select
-                    let end_jump = self.bytecode.len();
-                    self.bytecode.emit(OpCode::Jump(0));
-                    end_jumps.push(end_jump);
-                }
-                
-                if let Some(idx) = next_case_jump {
-                    let current_idx = self.bytecode.len() as u32;
-                    self.bytecode.instructions[idx] = OpCode::JumpIfFalse(current_idx);
+                    end_jumps.push(self.bytecode.emit(OpCode::Jump(0)));
+                    
+                    let next_case = self.bytecode.len() as u32;
+                    self.bytecode.instructions[next_case_jump] = OpCode::Jump(next_case);
                 }
                 
                 if let Some(else_stmts) = case_else {
@@ -393,6 +682,87 @@ impl ByteCodeCompiler {
                 let after_loop = self.bytecode.len() as u32;
                 self.bytecode.instructions[exit_jump_idx] = OpCode::JumpIfTrue(after_loop);
             }
+            Statement::DoLoop { body, condition, is_until } => {
+                let loop_start = self.bytecode.len() as u32;
+
+                for s in body {
+                    self.compile_statement(s)?;
+                }
+
+                match condition {
+                    Some(cond) => {
+                        self.compile_expression(cond)?;
+                        if *is_until {
+                            self.bytecode.emit(OpCode::JumpIfFalse(loop_start));
+                        } else {
+                            self.bytecode.emit(OpCode::JumpIfTrue(loop_start));
+                        }
+                    }
+                    None => {
+                        self.bytecode.emit(OpCode::Jump(loop_start));
+                    }
+                }
+            }
+            Statement::Limit { rate } => {
+                self.compile_expression(rate)?;
+                self.bytecode.emit(OpCode::Limit);
+            }
+            Statement::Title { text } => {
+                self.compile_expression(text)?;
+                self.bytecode.emit(OpCode::Title);
+            }
+            Statement::ScreenMove { position } => {
+                if let Some((x, y)) = position {
+                    self.compile_expression(x)?;
+                    self.compile_expression(y)?;
+                }
+                self.bytecode.emit(OpCode::ScreenMove(position.is_some()));
+            }
+            Statement::Resize { enabled, mode } => {
+                let mode = match mode {
+                    None => WindowResize::Program,
+                    Some(ResizeMode::Stretch) => WindowResize::Stretch,
+                    Some(ResizeMode::Smooth) => WindowResize::Smooth,
+                };
+                self.bytecode.emit(OpCode::Resize(*enabled, mode));
+            }
+            Statement::FullScreen { mode, smooth } => {
+                let full_screen = match mode {
+                    FullScreenMode::Auto | FullScreenMode::SquarePixels => FullScreen::SquarePixels,
+                    FullScreenMode::Stretch => FullScreen::Stretch,
+                    FullScreenMode::Off => FullScreen::Off,
+                };
+                let filter = if *smooth { Filter::Smooth } else { Filter::Nearest };
+                self.bytecode.emit(OpCode::FullScreen(full_screen, filter));
+            }
+            Statement::MetaConsole { only } => {
+                self.bytecode.emit(OpCode::ConsoleOpen(*only));
+            }
+            Statement::Console { visible } => {
+                self.bytecode.emit(OpCode::ConsoleVisible(*visible));
+            }
+            Statement::OnEvent { source, arg, label } => {
+                match arg {
+                    Some(arg) => self.compile_expression(arg)?,
+                    None => {
+                        self.bytecode.emit(OpCode::Push(QType::Integer(0)));
+                    }
+                }
+                let idx = self.bytecode.len();
+                self.bytecode.emit(OpCode::OnEvent(trap_source(*source), 0)); // Placeholder
+                self.pending_jumps.push((idx, label.clone()));
+            }
+            Statement::EventControl { source, arg, state } => {
+                if let Some(arg) = arg {
+                    self.compile_expression(arg)?;
+                }
+                let state = match state {
+                    EventState::On => TrapState::On,
+                    EventState::Off => TrapState::Off,
+                    EventState::Stop => TrapState::Stop,
+                };
+                self.bytecode.emit(OpCode::EventControl(trap_source(*source), state, arg.is_some()));
+            }
             Statement::Goto { label } => {
                 let idx = self.bytecode.len();
                 self.bytecode.emit(OpCode::Jump(0)); // Placeholder
@@ -403,105 +773,219 @@ impl ByteCodeCompiler {
                 self.bytecode.emit(OpCode::Call(0)); // Placeholder
                 self.pending_jumps.push((idx, label.clone()));
             }
-            Statement::Return => {
+            Statement::Return { label: None } => {
                 self.bytecode.emit(OpCode::Return);
             }
-            Statement::Print { items, .. } => {
-                let mut needs_newline = true;
-                
-                for item in items.iter() {
-                    match item {
-                        PrintItem::Expression(expr) => {
-                            self.compile_expression(expr)?;
-                            self.bytecode.emit(OpCode::Print(false));
-                            needs_newline = true;
-                        }
-                        PrintItem::Semicolon => {
-                            needs_newline = false;
-                        }
-                        PrintItem::Comma => {
-                            self.bytecode.emit(OpCode::PrintComma);
-                            needs_newline = false;
-                        }
-                    }
-                }
-                
-                if needs_newline {
-                    self.bytecode.emit(OpCode::Push(QType::String(String::new())));
-                    self.bytecode.emit(OpCode::Print(true));
+            Statement::Return { label: Some(label) } => {
+                let idx = self.bytecode.emit(OpCode::ReturnTo(0)); // Placeholder
+                self.pending_jumps.push((idx, label.clone()));
+            }
+            Statement::OnGoto { expr, labels } | Statement::OnGosub { expr, labels } => {
+                self.compile_expression(expr)?;
+                let idx = self.bytecode.len();
+                self.bytecode.emit(OpCode::OnJump(Vec::new(), matches!(stmt, Statement::OnGosub { .. })));
+                for label in labels {
+                    self.pending_jumps.push((idx, label.clone()));
                 }
             }
-            Statement::Input { prompt, vars } => {
-                let prompt_str = prompt.clone().unwrap_or_else(|| "? ".to_string());
-                for var in vars {
-                    self.bytecode.emit(OpCode::Input(prompt_str.clone()));
-                    self.bytecode.emit(OpCode::StoreVar(var.full_name()));
+            Statement::OnError { label } if label == "0" => {
+                self.bytecode.emit(OpCode::OnError(None));
+            }
+            Statement::OnError { label } => {
+                let idx = self.bytecode.len();
+                self.bytecode.emit(OpCode::OnError(Some(0))); // Placeholder
+                self.pending_jumps.push((idx, label.clone()));
+            }
+            Statement::Resume { label: Some(label), .. } => {
+                let idx = self.bytecode.len();
+                self.bytecode.emit(OpCode::ResumeAt(0)); // Placeholder
+                self.pending_jumps.push((idx, label.clone()));
+            }
+            Statement::Resume { next, .. } => {
+                self.bytecode.emit(OpCode::Resume(*next));
+            }
+            Statement::Error { code } => {
+                self.compile_expression(code)?;
+                self.bytecode.emit(OpCode::Error);
+            }
+            Statement::Print { items, .. } => {
+                self.compile_print_items(items)?;
+            }
+            Statement::Input { prompt, vars, question_mark, same_line } => {
+                let mut prompt_str = prompt.clone().unwrap_or_default();
+                if *question_mark {
+                    prompt_str.push_str("? ");
                 }
+                let names = vars.iter().map(|v| v.full_name()).collect();
+                self.bytecode.emit(OpCode::Input(prompt_str, *same_line, names));
             }
             Statement::LineInput { prompt, var } => {
                 let prompt_str = prompt.clone().unwrap_or_default();
                 self.bytecode.emit(OpCode::LineInput(prompt_str));
                 self.bytecode.emit(OpCode::StoreVar(var.full_name()));
             }
-            Statement::Open { filename, mode, fileno, .. } => {
-                // Simple file open: evaluate filename, mode, fileno
-                if let Expression::String(fname) = filename {
-                    let mode_str = format!("{:?}", mode);
-                    let fileno_val = if let Expression::Integer(n) = fileno { *n as u8 } else { 1 };
-                    self.bytecode.emit(OpCode::Open(fname.clone(), mode_str, fileno_val));
+            Statement::LineInputHash { fileno, var } => {
+                self.compile_expression(fileno)?;
+                self.bytecode.emit(OpCode::LineInputHash);
+                self.bytecode.emit(OpCode::StoreVar(var.full_name()));
+            }
+            Statement::Open { filename, mode, fileno, access, lock, reclen } => {
+                self.compile_expression(filename)?;
+                self.compile_expression(fileno)?;
+                if let Some(reclen) = reclen {
+                    self.compile_expression(reclen)?;
                 }
+                let access = access.map(|access| match access {
+                    FileAccess::Read => Access::Read,
+                    FileAccess::Write => Access::Write,
+                    FileAccess::ReadWrite => Access::ReadWrite,
+                });
+                let lock = lock.map(|lock| match lock {
+                    FileLock::Shared => Lock::Shared,
+                    FileLock::Read => Lock::Read,
+                    FileLock::Write => Lock::Write,
+                    FileLock::ReadWrite => Lock::ReadWrite,
+                });
+                self.bytecode.emit(OpCode::Open(format!("{:?}", mode), access, lock, reclen.is_some()));
             }
             Statement::Close { fileno } => {
-                let fileno_val = if let Some(Expression::Integer(n)) = fileno { *n as u8 } else { 0 };
-                self.bytecode.emit(OpCode::Close(fileno_val));
+                match fileno {
+                    Some(expr) => self.compile_expression(expr)?,
+                    None => {
+                        self.bytecode.emit(OpCode::Push(QType::Integer(0)));
+                    }
+                }
+                self.bytecode.emit(OpCode::Close);
             }
-            Statement::PrintHash { fileno, items } => {
-                let fileno_val = if let Expression::Integer(n) = fileno { *n as u8 } else { 1 };
-                for item in items {
-                    match item {
-                        PrintItem::Expression(expr) => {
-                            self.compile_expression(expr)?;
-                        }
-                        PrintItem::Comma => {
-                            self.bytecode.emit(OpCode::PrintComma);
-                        }
-                        PrintItem::Semicolon => {
-                            self.bytecode.emit(OpCode::PrintSemicolon);
-                        }
+            // There is no ISAM: these are error 73 where they run, which ON
+            // ERROR can trap
+            Statement::OpenIsam { .. } | Statement::Isam { .. } => {
+                self.bytecode.emit(OpCode::Push(QType::Integer(QErrorCode::AdvancedFeatureUnavailable as i16)));
+                self.bytecode.emit(OpCode::Error);
+            }
+            Statement::Kill { filespec } => {
+                self.compile_expression(filespec)?;
+                self.bytecode.emit(OpCode::Kill);
+            }
+            Statement::Chain { file } => {
+                self.compile_expression(file)?;
+                self.bytecode.emit(OpCode::Chain);
+            }
+            Statement::Get { fileno, record, var } => {
+                // The variable's current value gives the type to read
+                self.compile_expression(fileno)?;
+                if let Some(record) = record {
+                    self.compile_expression(record)?;
+                }
+                match var {
+                    Some(var) => {
+                        self.compile_load_record(var)?;
+                        self.bytecode.emit(OpCode::Get(record.is_some()));
+                        self.compile_store_record(var)?;
+                    }
+                    None => {
+                        self.bytecode.emit(OpCode::GetBuffer(record.is_some()));
                     }
                 }
-                self.bytecode.emit(OpCode::PrintHash(fileno_val));
             }
-            Statement::InputHash { fileno, vars } => {
-                let fileno_val = if let Expression::Integer(n) = fileno { *n as u8 } else { 1 };
-                for var in vars {
-                    self.bytecode.emit(OpCode::InputHash(fileno_val));
-                    self.bytecode.emit(OpCode::StoreVar(var.full_name()));
+            Statement::Put { fileno, record, var } => {
+                self.compile_expression(fileno)?;
+                if let Some(record) = record {
+                    self.compile_expression(record)?;
+                }
+                match var {
+                    Some(var) => {
+                        self.compile_load_record(var)?;
+                        self.bytecode.emit(OpCode::Put(record.is_some()));
+                    }
+                    None => {
+                        self.bytecode.emit(OpCode::PutBuffer(record.is_some()));
+                    }
                 }
             }
-            Statement::Call { name, args } => {
-                for arg in args {
-                    if let Argument::ByVal(expr) = arg {
-                        self.compile_expression(expr)?;
+            Statement::Field { fileno, fields } => {
+                self.compile_expression(fileno)?;
+                for (width, _) in fields {
+                    self.compile_expression(width)?;
+                }
+                self.bytecode.emit(OpCode::Field(fields.iter().map(|(_, var)| var.full_name()).collect()));
+            }
+            Statement::LSet { target, value } | Statement::RSet { target, value } => {
+                // The target's current length is the width to fill
+                if let LValue::ArrayElement(var, indices) = target {
+                    self.check_element(var, indices.len())?;
+                    for idx in indices {
+                        self.compile_expression(idx)?;
                     }
                 }
-                // For now, treat as label call
-                let idx = self.bytecode.len();
-                self.bytecode.emit(OpCode::Call(0)); // Placeholder
-                self.pending_jumps.push((idx, name.clone()));
+                self.compile_expression(&target.to_expression())?;
+                self.compile_expression(value)?;
+                self.bytecode.emit(OpCode::Justify(matches!(stmt, Statement::RSet { .. })));
+                match target {
+                    LValue::Variable(var) => {
+                        self.bytecode.emit(OpCode::StoreVar(var.full_name()));
+                    }
+                    LValue::ArrayElement(var, indices) => {
+                        self.bytecode.emit(OpCode::StoreArray(var.full_name(), indices.len()));
+                    }
+                    LValue::Field(var, field) => {
+                        let base_name = self.lvalue_to_string(var);
+                        self.bytecode.emit(OpCode::StoreField(base_name, field.clone()));
+                    }
+                }
+            }
+            Statement::LPrint { items } => {
+                self.bytecode.emit(OpCode::SelectPrinter);
+                self.compile_print_items(items)?;
+                self.bytecode.emit(OpCode::Push(QType::Integer(0)));
+                self.bytecode.emit(OpCode::SelectOutput);
             }
-            Statement::Screen { mode: Expression::Integer(m) } => {
-                self.bytecode.emit(OpCode::Screen(*m as u8));
+            Statement::Write { fileno, items } => {
+                if let Some(fileno) = fileno {
+                    self.compile_expression(fileno)?;
+                    self.bytecode.emit(OpCode::SelectOutput);
+                }
+                for (i, item) in items.iter().enumerate() {
+                    self.compile_expression(item)?;
+                    self.bytecode.emit(OpCode::Write(i + 1 == items.len()));
+                }
+                if items.is_empty() {
+                    self.bytecode.emit(OpCode::Push(QType::String(String::new())));
+                    self.bytecode.emit(OpCode::Print(true));
+                }
+                if fileno.is_some() {
+                    self.bytecode.emit(OpCode::Push(QType::Integer(0)));
+                    self.bytecode.emit(OpCode::SelectOutput);
+                }
+            }
+            Statement::PrintHash { fileno, items } => {
+                self.compile_expression(fileno)?;
+                self.bytecode.emit(OpCode::SelectOutput);
+                self.compile_print_items(items)?;
+                self.bytecode.emit(OpCode::Push(QType::Integer(0)));
+                self.bytecode.emit(OpCode::SelectOutput);
+            }
+            Statement::InputHash { fileno, vars } => {
+                for var in vars {
+                    self.compile_expression(fileno)?;
+                    self.bytecode.emit(OpCode::InputHash(var.full_name()));
+                }
+            }
+            Statement::Call { name, args } => self.compile_call(name, args)?,
+            Statement::ExitSub | Statement::ExitFunction => {
+                self.bytecode.emit(OpCode::LeaveProc);
+            }
+            Statement::Screen { mode } => {
+                self.compile_expression(mode)?;
+                self.bytecode.emit(OpCode::Screen);
             }
             Statement::PSet { x, y, color } => {
                 self.compile_expression(x)?;
                 self.compile_expression(y)?;
                 if let Some(c) = color {
                     self.compile_expression(c)?;
-                } else {
-                    self.bytecode.emit(OpCode::Push(QType::Integer(-1)));
                 }
-                self.bytecode.emit(OpCode::PSet);
+                self.bytecode.emit(OpCode::PSet(color.is_some()));
             }
             Statement::PReset { x, y } => {
                 self.compile_expression(x)?;
@@ -511,23 +995,93 @@ impl ByteCodeCompiler {
             Statement::Cls => {
                 self.bytecode.emit(OpCode::Cls);
             }
-            Statement::Color { foreground, background, border } => {
-                if let Some(fg) = foreground {
-                    self.compile_expression(fg)?;
-                } else {
-                    self.bytecode.emit(OpCode::Push(QType::Integer(-1)));
+            Statement::Display => {
+                self.bytecode.emit(OpCode::Display);
+            }
+            Statement::AutoDisplay => {
+                self.bytecode.emit(OpCode::AutoDisplay);
+            }
+            Statement::PutImage { area, source, dest, source_area } => {
+                let area_corners = self.compile_image_area(area.as_ref())?;
+                match source {
+                    Some(handle) => self.compile_expression(handle)?,
+                    None => { self.bytecode.emit(OpCode::Source); }
                 }
-                if let Some(bg) = background {
-                    self.compile_expression(bg)?;
-                } else {
-                    self.bytecode.emit(OpCode::Push(QType::Integer(-1)));
+                match dest {
+                    Some(handle) => self.compile_expression(handle)?,
+                    None => { self.bytecode.emit(OpCode::Dest); }
+                }
+                let source_corners = self.compile_image_area(source_area.as_ref())?;
+                self.bytecode.emit(OpCode::PutImage(area_corners, source_corners));
+            }
+            Statement::PrintString { x, y, text, handle } => {
+                self.compile_expression(x)?;
+                self.compile_expression(y)?;
+                self.compile_expression(text)?;
+                if let Some(handle) = handle {
+                    self.compile_expression(handle)?;
                 }
-                if let Some(bd) = border {
-                    self.compile_expression(bd)?;
+                self.bytecode.emit(OpCode::PrintString(handle.is_some()));
+            }
+            Statement::FreeImage { handle } => {
+                self.compile_expression(handle)?;
+                self.bytecode.emit(OpCode::FreeImage);
+            }
+            Statement::SaveImage { filename, handle } => {
+                self.compile_expression(filename)?;
+                if let Some(handle) = handle {
+                    self.compile_expression(handle)?;
+                }
+                self.bytecode.emit(OpCode::SaveImage(handle.is_some()));
+            }
+            Statement::Dest { handle } => {
+                self.compile_expression(handle)?;
+                self.bytecode.emit(OpCode::SetDest);
+            }
+            Statement::Source { handle } => {
+                self.compile_expression(handle)?;
+                self.bytecode.emit(OpCode::SetSource);
+            }
+            Statement::Mat { target, source } => {
+                if let MatSource::Copy(array) = source {
+                    self.bytecode.emit(OpCode::ArrayCopy(target.full_name(), array.full_name()));
                 } else {
-                    self.bytecode.emit(OpCode::Push(QType::Integer(-1)));
+                    match source {
+                        MatSource::Fill(value) => self.compile_expression(value)?,
+                        MatSource::Ones => { self.bytecode.emit(OpCode::Push(QType::Integer(1))); }
+                        _ => { self.bytecode.emit(OpCode::Push(QType::Integer(0))); }
+                    }
+                    self.bytecode.emit(OpCode::ArrayFill(target.full_name()));
+                }
+            }
+            Statement::MemGet { block, offset, var } => {
+                // The variable's current value gives the type to read
+                self.compile_expression(block)?;
+                self.compile_expression(offset)?;
+                self.bytecode.emit(OpCode::LoadVar(var.full_name()));
+                self.bytecode.emit(OpCode::MemGet);
+                self.bytecode.emit(OpCode::StoreVar(var.full_name()));
+            }
+            Statement::MemPut { block, offset, value, type_spec } => {
+                self.compile_expression(block)?;
+                self.compile_expression(offset)?;
+                self.compile_expression(value)?;
+                let template = type_spec.as_ref().map(|spec| self.mem_template(spec)).transpose()?;
+                self.bytecode.emit(OpCode::MemPut(template));
+            }
+            Statement::MemFree { block } => {
+                self.compile_expression(block)?;
+                self.bytecode.emit(OpCode::MemFree);
+            }
+            Statement::Color { foreground, background, border } => {
+                for e in [foreground, background, border].into_iter().flatten() {
+                    self.compile_expression(e)?;
                 }
-                self.bytecode.emit(OpCode::Color);
+                self.bytecode.emit(OpCode::Color(
+                    foreground.is_some(),
+                    background.is_some(),
+                    border.is_some(),
+                ));
             }
             Statement::Beep => {
                 self.bytecode.emit(OpCode::Beep);
@@ -537,14 +1091,76 @@ impl ByteCodeCompiler {
                 self.compile_expression(duration)?;
                 self.bytecode.emit(OpCode::Sound);
             }
-            Statement::End => {
-                self.bytecode.emit(OpCode::End);
+            Statement::Snd { action, handle } => {
+                self.compile_expression(handle)?;
+                self.bytecode.emit(match action {
+                    SndAction::Play => OpCode::SndPlay(false),
+                    SndAction::Loop => OpCode::SndPlay(true),
+                    SndAction::Stop => OpCode::SndStop,
+                    SndAction::Close => OpCode::SndClose,
+                });
+            }
+            Statement::SndVol { handle, volume } => {
+                self.compile_expression(handle)?;
+                self.compile_expression(volume)?;
+                self.bytecode.emit(OpCode::SndVolume);
+            }
+            Statement::SndRaw { left, right } => {
+                self.compile_expression(left)?;
+                if let Some(right) = right {
+                    self.compile_expression(right)?;
+                }
+                self.bytecode.emit(OpCode::SndRaw(right.is_some()));
+            }
+            Statement::End { code } | Statement::System { code } => {
+                if let Some(code) = code {
+                    self.compile_expression(code)?;
+                }
+                self.bytecode.emit(OpCode::End(code.is_some()));
+            }
+            Statement::Sleep { seconds } => {
+                if let Some(seconds) = seconds {
+                    self.compile_expression(seconds)?;
+                }
+                self.bytecode.emit(OpCode::Sleep(seconds.is_some()));
+            }
+            Statement::Randomize { seed } => {
+                if let Some(seed) = seed {
+                    self.compile_expression(seed)?;
+                }
+                self.bytecode.emit(OpCode::Randomize(seed.is_some()));
             }
             Statement::Stop => {
                 self.bytecode.emit(OpCode::Stop);
             }
-            Statement::Label { .. } | Statement::LineNumber { .. } => {
-                // Labels are handled during collection
+            Statement::Poke { address, value } => {
+                self.compile_expression(address)?;
+                self.compile_expression(value)?;
+                self.bytecode.emit(OpCode::Poke);
+            }
+            Statement::DefSeg { segment } => {
+                if let Some(segment) = segment {
+                    self.compile_expression(segment)?;
+                }
+                self.bytecode.emit(OpCode::DefSeg(segment.is_some()));
+            }
+            Statement::Clear { stack } => {
+                if let Some(stack) = stack {
+                    self.compile_expression(stack)?;
+                }
+                self.bytecode.emit(OpCode::Clear(stack.is_some()));
+            }
+            // Labels mark where they fall, also inside blocks such as a FOR body
+            Statement::Label { name } => {
+                self.label_addresses.insert(name.to_uppercase(), self.bytecode.len() as u32);
+            }
+            Statement::LineNumber { number } => {
+                self.label_addresses.insert(number.to_string(), self.bytecode.len() as u32);
+                self.bytecode.line_numbers.push((self.bytecode.len(), *number));
+            }
+            Statement::SourceLine { line } => {
+                self.current_line = *line;
+                self.bytecode.mark_line(*line);
             }
             Statement::Data { .. } => {
                 // DATA statements are processed in collect_data_labels, nothing to do here
@@ -557,38 +1173,42 @@ impl ByteCodeCompiler {
             }
             Statement::Restore { label } => {
                 if let Some(lbl) = label {
-                    if let Some(&addr) = self.data_label_addresses.get(&lbl.to_uppercase()) {
-                        self.bytecode.emit(OpCode::Restore(addr));
-                    } else {
-                        // Label not found, restore to beginning
-                        self.bytecode.emit(OpCode::Restore(0));
-                    }
+                    let addr = self.bytecode.data_index(lbl).ok_or_else(|| {
+                        QError::runtime(QErrorCode::LabelNotDefined, self.current_line, 0)
+                    })?;
+                    self.bytecode.emit(OpCode::Restore(addr));
                 } else {
                     self.bytecode.emit(OpCode::Restore(0)); // Restore to beginning
                 }
             }
-            Statement::Line { x1, y1, x2, y2, color, style: _, is_box: _, is_filled: _ } => {
-                self.compile_expression(x1)?;
-                self.compile_expression(y1)?;
-                self.compile_expression(x2)?;
-                self.compile_expression(y2)?;
-                if let Some(c) = color {
-                    self.compile_expression(c)?;
-                } else {
-                    self.bytecode.emit(OpCode::Push(QType::Integer(-1)));
+            Statement::Line { x1, y1, step1, x2, y2, step2, color, style, is_box, is_filled } => {
+                for e in [x1, y1, x2, y2].into_iter().chain(color).chain(style) {
+                    self.compile_expression(e)?;
                 }
-                self.bytecode.emit(OpCode::Line);
+                let shape = match (is_box, is_filled) {
+                    (_, true) => LineShape::FilledBox,
+                    (true, false) => LineShape::Box,
+                    (false, false) => LineShape::Line,
+                };
+                self.bytecode.emit(OpCode::Line(*step1, *step2, color.is_some(), shape, style.is_some()));
             }
-            Statement::Circle { x, y, radius, color, start: _, end: _, aspect: _ } => {
-                self.compile_expression(x)?;
-                self.compile_expression(y)?;
-                self.compile_expression(radius)?;
-                if let Some(c) = color {
-                    self.compile_expression(c)?;
-                } else {
-                    self.bytecode.emit(OpCode::Push(QType::Integer(-1)));
+            Statement::Circle { x, y, step, radius, color, start, end, aspect } => {
+                let optional = [color, start, end, aspect];
+                for e in [x, y, radius].into_iter().chain(optional.into_iter().flatten()) {
+                    self.compile_expression(e)?;
                 }
-                self.bytecode.emit(OpCode::Circle);
+                let [color, start, end, aspect] = optional.map(Option::is_some);
+                self.bytecode.emit(OpCode::Circle(*step, color, start, end, aspect));
+            }
+            Statement::Draw { command } => {
+                self.compile_expression(command)?;
+                self.bytecode.emit(OpCode::Draw);
+            }
+            Statement::Play { voices } => {
+                for voice in voices {
+                    self.compile_expression(voice)?;
+                }
+                self.bytecode.emit(OpCode::Play(voices.len() as u8));
             }
             Statement::Locate { row, col, cursor: _, start: _, stop: _ } => {
                 // Optional arguments push -1 if omitted
@@ -604,6 +1224,10 @@ impl ByteCodeCompiler {
     }
 
     fn compile_expression(&mut self, expr: &Expression) -> QResult<()> {
+        if let Some(text) = constant_string(expr) {
+            self.bytecode.emit(OpCode::Push(QType::String(text)));
+            return Ok(());
+        }
         match expr {
             Expression::Integer(n) => {
                 // Use Integer (i16) for small values, Long (i32) for larger values
@@ -631,9 +1255,15 @@ impl ByteCodeCompiler {
                 self.bytecode.emit(OpCode::Push(QType::String(s.clone())));
             }
             Expression::Variable(var) => {
-                self.bytecode.emit(OpCode::LoadVar(var.full_name()));
+                if let Some((base, field)) = self.mem_field(var) {
+                    self.bytecode.emit(OpCode::LoadVar(base));
+                    self.bytecode.emit(OpCode::MemField(field));
+                } else {
+                    self.bytecode.emit(OpCode::LoadVar(var.full_name()));
+                }
             }
             Expression::ArrayAccess(var, indices) => {
+                self.check_element(var, indices.len())?;
                 for idx in indices {
                     self.compile_expression(idx)?;
                 }
@@ -652,9 +1282,40 @@ impl ByteCodeCompiler {
                 self.compile_expression(right)?;
                 self.compile_binary_op(*op)?;
             }
+            Expression::FunctionCall { name, args } if self.procedures.contains_key(&name.to_uppercase()) => {
+                let args: Vec<Argument> = args.iter().map(|arg| Argument::new(arg.clone(), false)).collect();
+                self.compile_call(name, &args)?;
+            }
+            Expression::FunctionCall { name, args } if name.eq_ignore_ascii_case("LBOUND") || name.eq_ignore_ascii_case("UBOUND") => {
+                // The array is named, as v or v(), not evaluated
+                let array = match args.first() {
+                    Some(Expression::Variable(var)) => var,
+                    Some(Expression::ArrayAccess(var, indices)) if indices.is_empty() => var,
+                    _ => return Err(QError::compile("Type mismatch", self.current_line, 0)),
+                };
+                self.check_array(array)?;
+                if let Some(dimension) = args.get(1) {
+                    self.compile_expression(dimension)?;
+                }
+                let upper = name.eq_ignore_ascii_case("UBOUND");
+                self.bytecode.emit(OpCode::Bound(array.full_name(), upper, args.len() > 1));
+            }
+            Expression::FunctionCall { name, args } if name.eq_ignore_ascii_case("VARPTR$") => match args.as_slice() {
+                // The variable itself is named, not evaluated
+                [Expression::Variable(var)] => {
+                    self.bytecode.emit(OpCode::VarPtrString(var.full_name()));
+                }
+                _ => return Err(QError::compile("Type mismatch", self.current_line, 0)),
+            },
             Expression::FunctionCall { name, args } => {
                 for arg in args {
-                    self.compile_expression(arg)?;
+                    match arg {
+                        // A whole record, as LEN takes, is its packed image
+                        Expression::Variable(var) if self.records.contains_key(&var.full_name()) => {
+                            self.compile_load_record(var)?;
+                        }
+                        arg => self.compile_expression(arg)?,
+                    }
                 }
                 self.compile_builtin_function(name, args.len())?;
             }
@@ -662,6 +1323,13 @@ impl ByteCodeCompiler {
                 self.compile_expression(expr)?;
                 self.compile_conversion(target_type)?;
             }
+            Expression::MemGet { block, offset, type_spec } => {
+                self.compile_expression(block)?;
+                self.compile_expression(offset)?;
+                let template = self.mem_template(type_spec)?;
+                self.bytecode.emit(OpCode::Push(template));
+                self.bytecode.emit(OpCode::MemGet);
+            }
             Expression::FieldAccess(expr, field) => {
                 // For now, assume expr is a variable
                 if let Expression::Variable(var) = expr.as_ref() {
@@ -673,6 +1341,49 @@ impl ByteCodeCompiler {
         Ok(())
     }
 
+    /// PRINT item list, shared by screen PRINT and PRINT #
+    fn compile_print_items(&mut self, items: &[PrintItem]) -> QResult<()> {
+        if let Some(PrintItem::Using(format)) = items.first() {
+            // Separators only end the list; the format does the layout
+            self.compile_expression(format)?;
+            let mut count = 0;
+            for item in &items[1..] {
+                if let PrintItem::Expression(expr) = item {
+                    self.compile_expression(expr)?;
+                    count += 1;
+                }
+            }
+            self.bytecode.emit(OpCode::PrintUsing(count));
+            let newline = !matches!(items.last(), Some(PrintItem::Semicolon | PrintItem::Comma));
+            self.bytecode.emit(OpCode::Print(newline));
+            return Ok(());
+        }
+        let mut needs_newline = true;
+
+        for item in items.iter() {
+            match item {
+                PrintItem::Expression(expr) => {
+                    self.compile_expression(expr)?;
+                    self.bytecode.emit(OpCode::Print(false));
+                    needs_newline = true;
+                }
+                PrintItem::Semicolon | PrintItem::Using(_) => {
+                    needs_newline = false;
+                }
+                PrintItem::Comma => {
+                    self.bytecode.emit(OpCode::PrintComma);
+                    needs_newline = false;
+                }
+            }
+        }
+
+        if needs_newline {
+            self.bytecode.emit(OpCode::Push(QType::String(String::new())));
+            self.bytecode.emit(OpCode::Print(true));
+        }
+        Ok(())
+    }
+
     fn compile_binary_op(&mut self, op: BinaryOp) -> QResult<()> {
         let opcode = match op {
             BinaryOp::Add => OpCode::Add,
@@ -699,9 +1410,41 @@ impl ByteCodeCompiler {
         Ok(())
     }
 
-    fn compile_builtin_function(&mut self, name: &str, _arg_count: usize) -> QResult<()> {
-        let upper = name.to_uppercase();
-        let opcode = match upper.as_str() {
+    /// Push the coordinates of a _PUTIMAGE area; returns how many corners it has
+    fn compile_image_area(&mut self, area: Option<&ImageArea>) -> QResult<u8> {
+        let Some(area) = area else { return Ok(0) };
+        let mut corners = 0;
+        for (x, y) in std::iter::once(&area.corner).chain(area.opposite.as_ref()) {
+            self.compile_expression(x)?;
+            self.compile_expression(y)?;
+            corners += 1;
+        }
+        Ok(corners)
+    }
+
+    /// Emit the opcode that evaluates a builtin from the registry; names
+    /// it does not list are not builtins
+    fn compile_builtin_function(&mut self, name: &str, arg_count: usize) -> QResult<()> {
+        let Some(builtin) = builtins::lookup(name) else {
+            return Err(QError::compile("Function not defined", self.current_line, 0));
+        };
+        let upper = builtin.name;
+        if upper == "RND" && arg_count == 0 {
+            // Plain RND behaves like RND(1)
+            self.bytecode.emit(OpCode::Push(QType::Single(1.0)));
+        }
+        // Omitted arguments: 256-color _NEWIMAGE, 32-bit _LOADIMAGE, and
+        // _COPYIMAGE and _MEMIMAGE of the _DEST image
+        let default = match upper {
+            "_NEWIMAGE" if arg_count == 2 => Some(OpCode::Push(QType::Integer(256))),
+            "_LOADIMAGE" if arg_count == 1 => Some(OpCode::Push(QType::Integer(32))),
+            "_COPYIMAGE" | "_MEMIMAGE" if arg_count == 0 => Some(OpCode::Dest),
+            _ => None,
+        };
+        if let Some(op) = default {
+            self.bytecode.emit(op);
+        }
+        let opcode = match upper {
             "ABS" => OpCode::Abs,
             "ATN" => OpCode::Atn,
             "COS" => OpCode::Cos,
@@ -710,6 +1453,12 @@ impl ByteCodeCompiler {
             "INT" => OpCode::IntOp,
             "LOG" => OpCode::Log,
             "RND" => OpCode::Rnd,
+            "TIMER" => OpCode::Timer(arg_count > 0),
+            "_TIMER64" => OpCode::Timer64,
+            "DATE$" => OpCode::Date,
+            "TIME$" => OpCode::Time,
+            "INKEY$" => OpCode::InKey,
+            "PEEK" => OpCode::Peek,
             "SGN" => OpCode::Sgn,
             "SIN" => OpCode::Sin,
             "SQR" => OpCode::Sqr,
@@ -717,19 +1466,99 @@ impl ByteCodeCompiler {
             "CHR$" => OpCode::Chr,
             "LEFT$" => OpCode::Left,
             "RIGHT$" => OpCode::Right,
-            "MID$" => OpCode::Mid,
+            "MID$" => OpCode::Mid(arg_count > 2),
             "LEN" => OpCode::Len,
-            "ASC" => OpCode::Asc,
+            "ASC" => OpCode::Asc(arg_count > 1),
+            "INSTR" => OpCode::InStr(arg_count > 2),
+            "_INSTRREV" => OpCode::InStrRev(arg_count > 2),
             "STR$" => OpCode::Str,
             "VAL" => OpCode::Val,
-            "UCASE" | "UCASE$" => OpCode::UCase,
-            "LCASE" | "LCASE$" => OpCode::LCase,
+            "UCASE$" => OpCode::UCase,
+            "LCASE$" => OpCode::LCase,
+            "SPACE$" => OpCode::Space,
+            "STRING$" => OpCode::StringFill,
+            "LTRIM$" => OpCode::LTrim,
+            "RTRIM$" => OpCode::RTrim,
+            "TRIM$" => {
+                self.bytecode.emit(OpCode::LTrim);
+                OpCode::RTrim
+            }
+            "MKI$" => OpCode::MkString(QType::Integer(0)),
+            "MKL$" => OpCode::MkString(QType::Long(0)),
+            "MKS$" => OpCode::MkString(QType::Single(0.0)),
+            "MKD$" => OpCode::MkString(QType::Double(0.0)),
+            "CVI" => OpCode::CvNumber(QType::Integer(0)),
+            "CVL" => OpCode::CvNumber(QType::Long(0)),
+            "CVS" => OpCode::CvNumber(QType::Single(0.0)),
+            "CVD" => OpCode::CvNumber(QType::Double(0.0)),
             "CINT" => OpCode::CInt,
             "CLNG" => OpCode::CLng,
             "CSNG" => OpCode::CSng,
             "CDBL" => OpCode::CDbl,
             "CSTR" => OpCode::CStr,
-            _ => OpCode::Nop,
+            "EOF" => OpCode::Eof,
+            "LOF" => OpCode::Lof,
+            "LOC" => OpCode::Loc,
+            "_OPENHOST" => OpCode::OpenHost,
+            "_OPENCLIENT" => OpCode::OpenClient,
+            "_OPENCONNECTION" => OpCode::OpenConnection,
+            "_CONNECTED" => OpCode::Connected,
+            "_CONNECTIONADDRESS$" => OpCode::ConnectionAddress,
+            "_SOURCELINE" => OpCode::SourceLine(false),
+            "_SOURCELINE$" => OpCode::SourceLine(true),
+            "_CALLDEPTH" => OpCode::CallDepth,
+            "ERR" => OpCode::Err,
+            "ERL" => OpCode::Erl,
+            "_PROGRAMNAME$" => OpCode::ProgramName,
+            "FREEFILE" => OpCode::FreeFile,
+            "BOF" | "GETINDEX$" | "TEXTCOMP" => {
+                self.bytecode.emit(OpCode::Push(QType::Integer(QErrorCode::AdvancedFeatureUnavailable as i16)));
+                OpCode::Error
+            }
+            "FRE" => OpCode::Fre,
+            "COMMAND$" => OpCode::Command(arg_count > 0),
+            "PLAY" => OpCode::PlayCount,
+            "_RESIZE" => OpCode::ResizeEvent,
+            "_FULLSCREEN" => OpCode::FullScreenMode,
+            "_SNDOPEN" => OpCode::SndOpen(arg_count == 2),
+            "_SNDPLAYING" => OpCode::SndPlaying,
+            "_SNDRATE" => OpCode::SndRate,
+            "_SNDRAWLEN" => OpCode::SndRawLen,
+            "_RESIZEWIDTH" => OpCode::ResizeWidth,
+            "_RESIZEHEIGHT" => OpCode::ResizeHeight,
+            "STICK" => OpCode::Stick,
+            "STRIG" => OpCode::Strig,
+            "_DEVICES" => OpCode::Devices,
+            "_DEVICE$" => OpCode::DeviceName,
+            "_DEVICEINPUT" => OpCode::DeviceInput(arg_count == 1),
+            "_BUTTON" => OpCode::Button,
+            "_AXIS" => OpCode::Axis,
+            "_LASTBUTTON" => OpCode::LastButton,
+            "_LASTAXIS" => OpCode::LastAxis,
+            "_NEWIMAGE" => OpCode::NewImage,
+            "_LOADIMAGE" => OpCode::LoadImage,
+            "_COPYIMAGE" => OpCode::CopyImage,
+            "_MEMNEW" => OpCode::MemNew,
+            "_MEMIMAGE" => OpCode::MemImage,
+            "_CONSOLE" => OpCode::Console,
+            "_DEST" => OpCode::Dest,
+            "_SOURCE" => OpCode::Source,
+            "_RGB" => OpCode::RGB(arg_count > 3),
+            "_RGBA" => OpCode::RGBA(arg_count > 4),
+            "_RGB32" | "_RGBA32" => OpCode::RGB32(arg_count.clamp(1, 4) as u8),
+            "_RED" => OpCode::Channel(16, arg_count > 1),
+            "_GREEN" => OpCode::Channel(8, arg_count > 1),
+            "_BLUE" => OpCode::Channel(0, arg_count > 1),
+            "_ALPHA" => OpCode::Channel(24, arg_count > 1),
+            "_RED32" => OpCode::Channel32(16),
+            "_GREEN32" => OpCode::Channel32(8),
+            "_BLUE32" => OpCode::Channel32(0),
+            "_ALPHA32" => OpCode::Channel32(24),
+            "_PRINTWIDTH" => OpCode::PrintWidth(arg_count > 1),
+            "_WIDTH" => OpCode::ImageSize(false, arg_count > 0),
+            "_HEIGHT" => OpCode::ImageSize(true, arg_count > 0),
+            // Listed in the registry but with nothing to run
+            _ => return Err(QError::compile(format!("Advanced feature unavailable: {}", upper), self.current_line, 0)),
         };
         self.bytecode.emit(opcode);
         Ok(())
@@ -771,12 +1600,131 @@ impl ByteCodeCompiler {
                 "_UNSIGNED INTEGER" => QType::UnsignedInteger(0),
                 "_UNSIGNED LONG" => QType::UnsignedLong(0),
                 "_UNSIGNED _INTEGER64" => QType::UnsignedInteger64(0),
+                // A _MEM variable holds a block handle
+                "_MEM" => QType::Long(0),
                 _ => QType::Single(0.0),
             }
             TypeSpec::FixedString(_) => QType::String(String::new()),
             TypeSpec::UserDefined(_) => QType::UserDefined(Vec::new()),
         }
     }
+
+    /// The variables holding the fields of record `name`, a `type_name`,
+    /// in order, with their blank values; a nested record's fields are
+    /// listed in its place
+    fn record_fields(&self, name: &str, type_name: &str) -> QResult<Vec<(String, QType)>> {
+        let Some(fields) = self.user_types.get(&type_name.to_uppercase()) else {
+            return Err(QError::compile("Type not defined", self.current_line, 0));
+        };
+        let mut vars = Vec::new();
+        for (field, spec) in fields {
+            let var = format!("{}.{}", name, field.to_uppercase());
+            match spec {
+                TypeSpec::UserDefined(inner) => vars.extend(self.record_fields(&var, inner)?),
+                TypeSpec::FixedString(Expression::Integer(len)) if *len >= 0 => {
+                    vars.push((var, QType::FixedString(*len as usize, String::new())));
+                }
+                spec => vars.push((var, self.type_spec_to_qtype(spec))),
+            }
+        }
+        Ok(vars)
+    }
+
+    /// Note an array being DIMmed or REDIMmed. A static array is allocated
+    /// once, so it can neither be DIMmed again nor REDIMmed.
+    fn dimension(&mut self, var: &VariableId, redim: bool) -> QResult<()> {
+        let name = var.full_name();
+        match self.arrays.get(&name) {
+            Some(false) if redim => Err(QError::compile("Array already dimensioned", self.current_line, 0)),
+            Some(false) => Err(QError::compile("Duplicate definition", self.current_line, 0)),
+            _ => {
+                self.arrays.insert(name, redim || self.dynamic_arrays);
+                Ok(())
+            }
+        }
+    }
+
+    /// Under OPTION _EXPLICITARRAY an array must be DIMmed before use
+    fn check_array(&self, var: &VariableId) -> QResult<()> {
+        if self.explicit_arrays && !self.arrays.contains_key(&var.full_name()) {
+            return Err(QError::compile("Array not defined", self.current_line, 0));
+        }
+        Ok(())
+    }
+
+    /// An element of array `var` with `dims` subscripts. Without OPTION
+    /// _EXPLICITARRAY an array used before any DIM is a static array of
+    /// that many dimensions, each 0 TO 10.
+    fn check_element(&mut self, var: &VariableId, dims: usize) -> QResult<()> {
+        self.check_array(var)?;
+        let name = var.full_name();
+        if dims == 0 || self.arrays.contains_key(&name) {
+            return Ok(());
+        }
+        let type_str = TypeSuffix::of_name(&name)
+            .map_or(QType::Single(0.0), |s| s.default_value())
+            .type_name()
+            .to_string();
+        self.bytecode.implicit_arrays.insert(name.clone(), (dims, type_str));
+        self.arrays.insert(name, false);
+        Ok(())
+    }
+
+    /// Push the value GET or PUT transfers for `var`: a record's fields
+    /// packed into its fixed-length image, or the variable itself
+    fn compile_load_record(&mut self, var: &VariableId) -> QResult<()> {
+        match self.records.get(&var.full_name()) {
+            Some(type_name) => {
+                let fields = self.record_fields(&var.full_name(), type_name)?;
+                self.bytecode.emit(OpCode::LoadRecord(fields.into_iter().map(|(field, _)| field).collect()));
+            }
+            None => {
+                self.bytecode.emit(OpCode::LoadVar(var.full_name()));
+            }
+        }
+        Ok(())
+    }
+
+    /// Store the value GET read into `var`, unpacking a record's fields
+    fn compile_store_record(&mut self, var: &VariableId) -> QResult<()> {
+        match self.records.get(&var.full_name()) {
+            Some(type_name) => {
+                let fields = self.record_fields(&var.full_name(), type_name)?;
+                self.bytecode.emit(OpCode::StoreRecord(fields.into_iter().map(|(field, _)| field).collect()));
+            }
+            None => {
+                self.bytecode.emit(OpCode::StoreVar(var.full_name()));
+            }
+        }
+        Ok(())
+    }
+
+    /// Value whose type _MEMGET reads or _MEMPUT ... AS writes; strings
+    /// need a constant length
+    fn mem_template(&self, spec: &TypeSpec) -> QResult<QType> {
+        match spec {
+            TypeSpec::FixedString(Expression::Integer(len)) if *len >= 0 => {
+                Ok(QType::FixedString(*len as usize, String::new()))
+            }
+            TypeSpec::FixedString(_) | TypeSpec::UserDefined(_) => {
+                Err(QError::runtime(QErrorCode::TypeMismatch, self.current_line, 0))
+            }
+            TypeSpec::Simple(name) if name == "STRING" || name == "_MEM" => {
+                Err(QError::runtime(QErrorCode::TypeMismatch, self.current_line, 0))
+            }
+            TypeSpec::Simple(_) => Ok(self.type_spec_to_qtype(spec)),
+        }
+    }
+
+    /// `m.OFFSET`, `m.SIZE` etc. of a variable declared AS _MEM
+    fn mem_field(&self, var: &VariableId) -> Option<(String, MemField)> {
+        let name = var.full_name();
+        let (base, field) = name.rsplit_once('.')?;
+        if !self.mem_vars.contains(base) {
+            return None;
+        }
+        MemField::from_name(field).map(|field| (base.to_string(), field))
+    }
 }
 
 impl Default for ByteCodeCompiler {
@@ -785,8 +1733,89 @@ impl Default for ByteCodeCompiler {
     }
 }
 
+fn trap_source(source: EventSource) -> TrapSource {
+    match source {
+        EventSource::Strig => TrapSource::Strig,
+        EventSource::Key => TrapSource::Key,
+        EventSource::Timer => TrapSource::Timer,
+    }
+}
+
 /// Compile a program to bytecode
 pub fn compile(program: &Program) -> QResult<ByteCode> {
     let compiler = ByteCodeCompiler::new();
     compiler.compile(program)
 }
+
+/// The bounds of an array when they are all literal numbers, so it can be
+/// allocated without evaluating them
+fn constant_shape(bounds: &[DimBounds]) -> Option<Vec<(i32, i32)>> {
+    fn constant(expr: &Expression) -> Option<i32> {
+        match expr {
+            Expression::Integer(n) => Some(*n),
+            Expression::Long(n) => i32::try_from(*n).ok(),
+            Expression::Negate(inner) => constant(inner)?.checked_neg(),
+            _ => None,
+        }
+    }
+    bounds.iter().map(|dim| Some((constant(&dim.lower)?, constant(&dim.upper)?))).collect()
+}
+
+/// Value of a string expression built only from literals and CHR$ of
+/// constant codes, so it can be pushed as one literal. Codes from 128 up
+/// depend on the dialect and are left to the VM.
+fn constant_string(expr: &Expression) -> Option<String> {
+    match expr {
+        Expression::String(s) => Some(s.clone()),
+        Expression::FunctionCall { name, args } if name.eq_ignore_ascii_case("CHR$") => match args.as_slice() {
+            [Expression::Integer(code)] => u8::try_from(*code).ok().filter(u8::is_ascii).map(|c| char::from(c).to_string()),
+            _ => None,
+        },
+        Expression::Binary { op: BinaryOp::Add | BinaryOp::Concat, left, right } => {
+            Some(constant_string(left)? + &constant_string(right)?)
+        }
+        _ => None,
+    }
+}
+
+/// Compile a single expression to bytecode that leaves its value on the stack
+pub fn compile_expression(expr: &Expression) -> QResult<ByteCode> {
+    let mut compiler = ByteCodeCompiler::new();
+    compiler.compile_expression(expr)?;
+    compiler.bytecode.emit(OpCode::Halt);
+    Ok(compiler.bytecode)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use qb_core::dialect::Dialect;
+    use qb_lexer::{tokenize, tokenize_dialect};
+    use qb_parser::parse_expression;
+
+    fn code(source: &str) -> Vec<OpCode> {
+        compile_expression(&parse_expression(tokenize(source).unwrap()).unwrap()).unwrap().instructions
+    }
+
+    #[test]
+    fn test_every_builtin_compiles() {
+        for builtin in builtins::BUILTINS {
+            // LBOUND, UBOUND and VARPTR$ name a variable rather than take a value
+            let arg = if matches!(builtin.name, "LBOUND" | "UBOUND" | "VARPTR$") { "a" } else { "1" };
+            let args = vec![arg; builtin.min_args].join(", ");
+            let source = if args.is_empty() { builtin.name.to_string() } else { format!("{}({})", builtin.name, args) };
+            let expr = parse_expression(tokenize_dialect(&source, Dialect::Qb64).unwrap()).unwrap();
+            assert!(matches!(expr, Expression::FunctionCall { .. }), "{} is not a call", source);
+            assert!(compile_expression(&expr).is_ok(), "{} does not compile", source);
+        }
+    }
+
+    #[test]
+    fn test_constant_strings_are_folded() {
+        let folded = code("\"A\" + CHR$(13) + CHR$(10) + \"B\"");
+        assert!(matches!(&folded[..], [OpCode::Push(QType::String(s)), OpCode::Halt] if s == "A\r\nB"));
+        // Upper codes depend on the dialect, and bad ones must still fail at run time
+        assert!(code("\"A\" + CHR$(201)").len() > 2);
+        assert!(code("CHR$(-1)").len() > 2);
+    }
+}
@@ -0,0 +1,92 @@
+//! Line coverage: how many times each source line ran, kept per file and
+//! merged across runs for `qb run --coverage` and `qb cov`
+
+use crate::opcodes::ByteCode;
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+
+/// Hit counts for every executable line of each file
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct CoverageData {
+    pub files: BTreeMap<String, FileCoverage>,
+}
+
+/// Executable lines of one file and how often each ran; 0 means never
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct FileCoverage {
+    pub lines: BTreeMap<usize, u64>,
+}
+
+impl CoverageData {
+    /// Add a run of `file`, given how many times each instruction of its
+    /// bytecode executed. A line counts as run each time its first
+    /// instruction is.
+    pub fn record(&mut self, file: &str, bytecode: &ByteCode, counts: &[u64]) {
+        // A line whose code is split in pieces, such as a FOR, ran as often
+        // as its busiest piece
+        let mut run = FileCoverage::default();
+        for (i, &(start, line)) in bytecode.lines.iter().enumerate() {
+            let end = bytecode.lines.get(i + 1).map_or(bytecode.len(), |next| next.0);
+            if start >= end {
+                continue;
+            }
+            let hits = counts.get(start).copied().unwrap_or(0);
+            let entry = run.lines.entry(line).or_insert(0);
+            *entry = (*entry).max(hits);
+        }
+        self.merge(CoverageData { files: BTreeMap::from([(file.to_string(), run)]) });
+    }
+
+    /// Add the runs recorded in `other`
+    pub fn merge(&mut self, other: CoverageData) {
+        for (file, coverage) in other.files {
+            let merged = self.files.entry(file).or_default();
+            for (line, hits) in coverage.lines {
+                *merged.lines.entry(line).or_insert(0) += hits;
+            }
+        }
+    }
+}
+
+impl FileCoverage {
+    /// Number of executable lines that ran at least once
+    pub fn covered(&self) -> usize {
+        self.lines.values().filter(|&&hits| hits > 0).count()
+    }
+
+    pub fn percent(&self) -> f64 {
+        if self.lines.is_empty() {
+            100.0
+        } else {
+            self.covered() as f64 * 100.0 / self.lines.len() as f64
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::opcodes::OpCode;
+
+    #[test]
+    fn test_record_and_merge() {
+        let mut bytecode = ByteCode::new();
+        bytecode.mark_line(1);
+        bytecode.emit(OpCode::Nop);
+        bytecode.emit(OpCode::Nop);
+        bytecode.mark_line(2);
+        bytecode.emit(OpCode::Nop);
+        bytecode.mark_line(3);
+        bytecode.emit(OpCode::Nop);
+
+        let mut first = CoverageData::default();
+        first.record("a.bas", &bytecode, &[1, 1, 0, 3]);
+        let mut second = CoverageData::default();
+        second.record("a.bas", &bytecode, &[1, 1, 2, 0]);
+        first.merge(second);
+
+        let lines = &first.files["a.bas"].lines;
+        assert_eq!(lines.iter().map(|(&l, &h)| (l, h)).collect::<Vec<_>>(), vec![(1, 2), (2, 2), (3, 3)]);
+        assert_eq!(first.files["a.bas"].covered(), 3);
+    }
+}
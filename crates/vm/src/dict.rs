@@ -0,0 +1,66 @@
+//! _DICT: tables of values looked up by string, an extension of the QB64
+//! dialect. `DIM ages AS _DICT` declares one; `ages("Ann") = 31` stores
+//! and `ages("Ann")` reads. The values have the type of the table's name,
+//! as an array's elements do, and a key never stored reads as 0 or "".
+
+use qb_core::data_types::QType;
+use qb_core::errors::{QError, QErrorCode, QResult};
+use std::collections::HashMap;
+
+#[derive(Debug, Clone)]
+pub struct Dict {
+    /// Value of a missing key, and the type stored values convert to
+    blank: QType,
+    entries: HashMap<String, QType>,
+}
+
+impl Dict {
+    pub fn new(blank: QType) -> Self {
+        Self { blank, entries: HashMap::new() }
+    }
+
+    pub fn get(&self, key: &str) -> QType {
+        self.entries.get(key).unwrap_or(&self.blank).clone()
+    }
+
+    pub fn set(&mut self, key: String, value: QType) -> QResult<()> {
+        let value = value.convert_to(&self.blank)?;
+        self.entries.insert(key, value);
+        Ok(())
+    }
+
+    pub fn len(&self) -> usize {
+        self.entries.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.entries.is_empty()
+    }
+}
+
+/// The key written between the parentheses: one string or number
+pub fn key(subscripts: &[QType]) -> QResult<String> {
+    match subscripts {
+        [key] => key.to_qstring(),
+        _ => Err(QError::runtime(QErrorCode::SubscriptOutOfRange, 0, 0)),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_dict() {
+        let mut ages = Dict::new(QType::Integer(0));
+        assert_eq!(ages.get("Ann"), QType::Integer(0));
+        ages.set("Ann".into(), QType::Single(30.6)).unwrap();
+        assert_eq!(ages.get("Ann"), QType::Integer(31));
+        assert!(ages.set("Bob".into(), QType::String("x".into())).is_err());
+        assert_eq!(ages.len(), 1);
+
+        assert_eq!(key(&[QType::String("k".into())]).unwrap(), "k");
+        assert_eq!(key(&[QType::Integer(5)]).unwrap(), "5");
+        assert!(key(&[QType::Integer(1), QType::Integer(2)]).is_err());
+    }
+}
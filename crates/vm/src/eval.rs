@@ -0,0 +1,108 @@
+//! Evaluating an expression typed at run time, such as `x * 2` or
+//! `LEFT$(name$, 3)`, against a program's current variables and arrays
+//!
+//! This backs the REPL's immediate mode and any tooling that needs to look
+//! at a stopped program: a debugger's print command or the condition of a
+//! breakpoint.
+
+use crate::compiler::compile_expression;
+use crate::runtime::VirtualMachine;
+use crate::watch::Watch;
+use qb_core::data_types::QType;
+use qb_core::errors::{QError, QResult};
+use qb_lexer::tokenize;
+use qb_parser::{parse_expression, Expression};
+use qb_semantic::Names;
+
+/// Tokenize, parse and evaluate `source` as one expression in the VM's
+/// current scope, leaving the program where it was. `names` are those of
+/// the analyzed program, so `x` finds the variable the program calls `x`.
+pub fn evaluate_expression(vm: &mut VirtualMachine, names: &Names, source: &str) -> QResult<QType> {
+    let mut expr = parse_expression(tokenize(source)?)?;
+    names.canonicalize_expression(&mut expr);
+    let bytecode = compile_expression(&expr)?;
+    vm.evaluate(&bytecode)
+}
+
+/// Parse what a `watch` names: a variable, a whole array, or an array
+/// element whose subscripts are evaluated now
+pub fn parse_watch(vm: &mut VirtualMachine, names: &Names, source: &str) -> QResult<Watch> {
+    let mut expr = parse_expression(tokenize(source)?)?;
+    names.canonicalize_expression(&mut expr);
+    match expr {
+        Expression::Variable(var) => Ok(Watch { name: var.full_name(), indices: None }),
+        Expression::ArrayAccess(var, subscripts) => {
+            let indices = subscripts
+                .iter()
+                .map(|s| vm.evaluate(&compile_expression(s)?)?.to_long())
+                .collect::<QResult<Vec<i32>>>()?;
+            Ok(Watch { name: var.full_name(), indices: Some(indices) })
+        }
+        _ => Err(QError::compile("Only a variable or array element can be watched", 1, 1)),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::compiler::compile;
+    use crate::opcodes::ByteCode;
+    use crate::runtime::Pause;
+    use qb_parser::parse;
+    use qb_semantic::analyze;
+
+    fn build(source: &str) -> (ByteCode, Names) {
+        let mut program = parse(tokenize(source).unwrap()).unwrap();
+        let names = analyze(&mut program).unwrap();
+        (compile(&program).unwrap(), names)
+    }
+
+    fn run(source: &str) -> (VirtualMachine, Names) {
+        let (bytecode, names) = build(source);
+        let mut vm = VirtualMachine::new();
+        vm.execute(&bytecode).unwrap();
+        (vm, names)
+    }
+
+    #[test]
+    fn test_evaluates_against_program_state() {
+        let (mut vm, names) = run("DIM a(1 TO 3)\nFOR i = 1 TO 3\na(i) = i * 10\nNEXT i\nname$ = \"QBasic\"\n");
+        assert_eq!(evaluate_expression(&mut vm, &names, "a(2) + 1").unwrap().to_string(), "21");
+        assert_eq!(evaluate_expression(&mut vm, &names, "LEFT$(name$, 2)").unwrap(), QType::String("QB".to_string()));
+        assert_eq!(evaluate_expression(&mut vm, &names, "a(3) > 25 AND i = 4").unwrap().to_string(), "-1");
+        assert_eq!(evaluate_expression(&mut vm, &names, "i! - i").unwrap().to_string(), "0");
+    }
+
+    #[test]
+    fn test_watch_pauses_on_write() {
+        let source = "DIM a(5)\nbalance# = 100\nFOR i = 1 TO 5\na(i) = i\nNEXT i\nbalance# = balance# + 50\n";
+        let (bytecode, names) = build(source);
+        let mut vm = VirtualMachine::new();
+        let balance = parse_watch(&mut vm, &names, "balance#").unwrap();
+        vm.add_watch(balance);
+        let element = parse_watch(&mut vm, &names, "a(1 + 2)").unwrap();
+        vm.add_watch(element);
+        vm.start();
+
+        let hits: Vec<String> = std::iter::from_fn(|| vm.resume(&bytecode).unwrap())
+            .map(|pause| match pause {
+                Pause::Watch(hit) => hit.to_string(),
+                Pause::Break { .. } => unreachable!(),
+            })
+            .collect();
+        assert_eq!(hits.len(), 3);
+        assert!(hits[0].starts_with("BALANCE# = 100 (was "), "{}", hits[0]);
+        assert!(hits[0].ends_with("at line 2"), "{}", hits[0]);
+        assert_eq!(hits[1], "A!(3) = 3 (was 0) at line 4");
+        assert_eq!(hits[2], "BALANCE# = 150 (was 100) at line 6");
+        assert!(parse_watch(&mut vm, &names, "a + 1").is_err());
+    }
+
+    #[test]
+    fn test_rejects_trailing_tokens_and_keeps_state() {
+        let (mut vm, names) = run("x = 5\n");
+        assert!(matches!(evaluate_expression(&mut vm, &names, "x 1"), Err(QError::Compile { .. })));
+        assert!(evaluate_expression(&mut vm, &names, "1 / 0").is_err());
+        assert_eq!(evaluate_expression(&mut vm, &names, "x").unwrap().to_string(), "5");
+    }
+}
@@ -0,0 +1,147 @@
+//! Event trapping: ON <event> GOSUB handlers and their ON/OFF/STOP state
+
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+
+/// Event source a trap listens to
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize)]
+pub enum TrapSource {
+    Strig,
+    Key,
+    Timer,
+}
+
+/// ON runs the handler, STOP remembers the event until the trap is turned
+/// back on, OFF discards it
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub enum TrapState {
+    On,
+    Off,
+    Stop,
+}
+
+/// A trap is identified by its source and argument, e.g. STRIG(4)
+type TrapKey = (TrapSource, i32);
+
+#[derive(Debug, Clone, Copy)]
+struct Trap {
+    handler: Option<u32>,
+    state: TrapState,
+    pending: bool,
+}
+
+impl Default for Trap {
+    fn default() -> Self {
+        Self { handler: None, state: TrapState::Off, pending: false }
+    }
+}
+
+#[derive(Debug, Default)]
+pub struct EventTraps {
+    traps: HashMap<TrapKey, Trap>,
+    /// Handlers currently running: call depth, trap, state to restore on RETURN
+    active: Vec<(usize, TrapKey, TrapState)>,
+}
+
+impl EventTraps {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// ON <event>(n) GOSUB: the trap keeps its ON/OFF/STOP state
+    pub fn set_handler(&mut self, source: TrapSource, n: i32, address: u32) {
+        self.traps.entry((source, n)).or_default().handler = Some(address);
+    }
+
+    /// <event>(n) ON/OFF/STOP; n of None applies to every trap of the source
+    pub fn set_state(&mut self, source: TrapSource, n: Option<i32>, state: TrapState) {
+        let keys: Vec<TrapKey> = match n {
+            Some(n) => vec![(source, n)],
+            None => self.traps.keys().filter(|(s, _)| *s == source).copied().collect(),
+        };
+        for key in keys {
+            let trap = self.traps.entry(key).or_default();
+            trap.state = state;
+            if state == TrapState::Off {
+                trap.pending = false;
+            }
+        }
+    }
+
+    /// Whether any trap of the source is listening, so the device needs polling
+    pub fn is_watching(&self, source: TrapSource) -> bool {
+        self.traps
+            .iter()
+            .any(|((s, _), trap)| *s == source && trap.state != TrapState::Off)
+    }
+
+    /// Whether the event would reach a handler, now or once the trap is
+    /// turned back on
+    pub fn is_trapped(&self, source: TrapSource, n: i32) -> bool {
+        self.traps
+            .get(&(source, n))
+            .is_some_and(|trap| trap.state != TrapState::Off && trap.handler.is_some())
+    }
+
+    /// Record that an event happened
+    pub fn signal(&mut self, source: TrapSource, n: i32) {
+        if let Some(trap) = self.traps.get_mut(&(source, n)) {
+            if trap.state != TrapState::Off {
+                trap.pending = true;
+            }
+        }
+    }
+
+    /// Take the next event whose handler should run now. The trap is
+    /// stopped while its handler runs and restored by `leave`.
+    pub fn take_ready(&mut self, depth: usize) -> Option<u32> {
+        let (&key, trap) = self
+            .traps
+            .iter_mut()
+            .find(|(_, trap)| trap.pending && trap.state == TrapState::On && trap.handler.is_some())?;
+        trap.pending = false;
+        trap.state = TrapState::Stop;
+        self.active.push((depth, key, TrapState::On));
+        trap.handler
+    }
+
+    /// Called on RETURN from call depth `depth`; re-enables the trap whose
+    /// handler is returning, unless the handler changed its state itself
+    pub fn leave(&mut self, depth: usize) {
+        if let Some(&(active_depth, key, state)) = self.active.last() {
+            if active_depth == depth {
+                self.active.pop();
+                if let Some(trap) = self.traps.get_mut(&key) {
+                    if trap.state == TrapState::Stop {
+                        trap.state = state;
+                    }
+                }
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_trap_states() {
+        let mut traps = EventTraps::new();
+        traps.set_handler(TrapSource::Strig, 0, 42);
+        traps.signal(TrapSource::Strig, 0);
+        assert_eq!(traps.take_ready(1), None); // still OFF
+
+        traps.set_state(TrapSource::Strig, Some(0), TrapState::Stop);
+        traps.signal(TrapSource::Strig, 0);
+        assert_eq!(traps.take_ready(1), None);
+        traps.set_state(TrapSource::Strig, None, TrapState::On);
+        assert_eq!(traps.take_ready(1), Some(42));
+
+        // Stopped while the handler runs, back on after RETURN
+        traps.signal(TrapSource::Strig, 0);
+        assert_eq!(traps.take_ready(1), None);
+        traps.leave(1);
+        assert_eq!(traps.take_ready(1), Some(42));
+    }
+}
@@ -0,0 +1,749 @@
+//! Open file table for the VM: OPEN/CLOSE, sequential file I/O, and the
+//! records and bytes of RANDOM and BINARY files, and the SCRN: and NUL
+//! devices
+
+use crate::vfs::Device;
+use qb_core::errors::{QError, QErrorCode, QResult};
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::fs::{File, OpenOptions};
+use std::io::{self, BufRead, BufReader, BufWriter, Cursor, Read, Seek, SeekFrom, Write};
+use std::path::{Path, PathBuf};
+
+/// DOS end-of-file marker; sequential reads stop here
+const CTRL_Z: u8 = 0x1A;
+/// Record length of a file opened without LEN =
+const DEFAULT_RECORD_LEN: usize = 128;
+/// Largest LEN = accepted by OPEN
+const MAX_RECORD_LEN: i32 = 32767;
+
+/// Mode a file number was opened in
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum OpenMode {
+    Input,
+    Output,
+    Append,
+    Random,
+    Binary,
+}
+
+impl OpenMode {
+    /// Parse the mode name stored in the OPEN opcode
+    pub fn from_name(name: &str) -> Option<Self> {
+        match name.to_uppercase().as_str() {
+            "INPUT" => Some(OpenMode::Input),
+            "OUTPUT" => Some(OpenMode::Output),
+            "APPEND" => Some(OpenMode::Append),
+            "RANDOM" => Some(OpenMode::Random),
+            "BINARY" => Some(OpenMode::Binary),
+            _ => None,
+        }
+    }
+}
+
+/// ACCESS clause of OPEN
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub enum Access {
+    Read,
+    Write,
+    ReadWrite,
+}
+
+impl Access {
+    fn reads(self) -> bool {
+        self != Access::Write
+    }
+
+    fn writes(self) -> bool {
+        self != Access::Read
+    }
+
+    /// What a file opened in `mode` may do, narrowed by an ACCESS clause
+    fn granted(mode: OpenMode, requested: Option<Access>) -> QResult<Access> {
+        let allowed = match mode {
+            OpenMode::Input => Access::Read,
+            OpenMode::Output | OpenMode::Append => Access::Write,
+            OpenMode::Random | OpenMode::Binary => Access::ReadWrite,
+        };
+        match requested {
+            None => Ok(allowed),
+            Some(requested) if allowed == Access::ReadWrite => Ok(requested),
+            Some(Access::ReadWrite) => Ok(allowed),
+            Some(requested) if requested == allowed => Ok(allowed),
+            Some(_) => Err(QError::runtime(QErrorCode::BadFileMode, 0, 0)),
+        }
+    }
+}
+
+/// Sharing clause of OPEN; without one the file is opened in compatibility
+/// mode and shares freely with other opens in this program
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub enum Lock {
+    Shared,
+    /// Other opens may not read
+    Read,
+    /// Other opens may not write
+    Write,
+    ReadWrite,
+}
+
+impl Lock {
+    fn denies(self, access: Access) -> bool {
+        match self {
+            Lock::Shared => false,
+            Lock::Read => access.reads(),
+            Lock::Write => access.writes(),
+            Lock::ReadWrite => true,
+        }
+    }
+}
+
+/// The optional clauses of OPEN
+#[derive(Debug, Clone, Copy, Default)]
+pub struct OpenClauses {
+    pub access: Option<Access>,
+    pub lock: Option<Lock>,
+    /// LEN =
+    pub record_len: Option<i32>,
+}
+
+enum Handle {
+    Reader(BufReader<File>),
+    Writer(BufWriter<File>),
+    /// RANDOM and BINARY files, read and written in place
+    Direct(File),
+    /// Contents already in memory, such as a download from the HTTP: device
+    Memory(Cursor<Vec<u8>>),
+    /// A device opened for output. What is written goes nowhere here; the
+    /// VM also shows it when the device is the screen.
+    Device(Device, io::Sink),
+}
+
+/// A single open file
+pub struct OpenFile {
+    pub path: String,
+    pub mode: OpenMode,
+    /// Output column (0-based) for PRINT # zones
+    pub column: usize,
+    /// LEN = of OPEN, 128 by default
+    pub record_len: usize,
+    access: Access,
+    lock: Option<Lock>,
+    /// Canonical path, to find other opens of the same file
+    key: Option<PathBuf>,
+    handle: Handle,
+}
+
+impl OpenFile {
+    fn reader(&mut self) -> QResult<&mut dyn BufRead> {
+        match &mut self.handle {
+            Handle::Reader(r) => Ok(r),
+            Handle::Memory(m) => Ok(m),
+            Handle::Writer(_) | Handle::Direct(_) | Handle::Device(..) => {
+                Err(QError::runtime(QErrorCode::BadFileMode, 0, 0))
+            }
+        }
+    }
+
+    fn writer(&mut self) -> QResult<&mut dyn Write> {
+        match &mut self.handle {
+            Handle::Writer(w) => Ok(w),
+            Handle::Direct(f) => Ok(f),
+            Handle::Device(_, sink) => Ok(sink),
+            Handle::Reader(_) | Handle::Memory(_) => Err(QError::runtime(QErrorCode::BadFileMode, 0, 0)),
+        }
+    }
+
+    /// The file of a RANDOM or BINARY open, moved to `position` if given:
+    /// a record number for RANDOM, a byte number for BINARY, both from 1
+    fn direct(&mut self, position: Option<i64>) -> QResult<&mut File> {
+        let record_len = self.record_len as u64;
+        let random = self.mode == OpenMode::Random;
+        let Handle::Direct(file) = &mut self.handle else {
+            return Err(QError::runtime(QErrorCode::BadFileMode, 0, 0));
+        };
+        if let Some(position) = position {
+            if position < 1 {
+                return Err(QError::runtime(QErrorCode::BadRecordNumber, 0, 0));
+            }
+            let index = position as u64 - 1;
+            file.seek(SeekFrom::Start(if random { index * record_len } else { index }))?;
+        }
+        Ok(file)
+    }
+
+    fn peek_byte(&mut self) -> QResult<Option<u8>> {
+        let buf = self.reader()?.fill_buf()?;
+        Ok(match buf.first() {
+            Some(&CTRL_Z) | None => None,
+            Some(&b) => Some(b),
+        })
+    }
+
+    fn next_byte(&mut self) -> QResult<Option<u8>> {
+        let b = self.peek_byte()?;
+        if b.is_some() {
+            self.reader()?.consume(1);
+        }
+        Ok(b)
+    }
+
+    /// Consume a line terminator: CR, LF or CR LF
+    fn skip_line_end(&mut self) -> QResult<()> {
+        if self.next_byte()? == Some(b'\r') && self.peek_byte()? == Some(b'\n') {
+            self.next_byte()?;
+        }
+        Ok(())
+    }
+}
+
+/// Table of open file numbers
+#[derive(Default)]
+pub struct FileTable {
+    files: HashMap<i32, OpenFile>,
+}
+
+impl FileTable {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    fn check_unused(&self, number: i32) -> QResult<()> {
+        if !(1..=255).contains(&number) {
+            return Err(QError::runtime(QErrorCode::BadFileNumber, 0, 0));
+        }
+        if self.files.contains_key(&number) {
+            return Err(QError::runtime(QErrorCode::FileAlreadyOpen, 0, 0));
+        }
+        Ok(())
+    }
+
+    pub fn open(&mut self, number: i32, path: &str, mode: OpenMode) -> QResult<()> {
+        self.open_with(number, path, mode, OpenClauses::default())
+    }
+
+    /// OPEN with ACCESS, SHARED/LOCK and LEN = clauses
+    pub fn open_with(&mut self, number: i32, path: &str, mode: OpenMode, clauses: OpenClauses) -> QResult<()> {
+        self.check_unused(number)?;
+        if path.is_empty() {
+            return Err(QError::runtime(QErrorCode::BadFileName, 0, 0));
+        }
+        let record_len = match clauses.record_len {
+            Some(len) if !(1..=MAX_RECORD_LEN).contains(&len) => {
+                return Err(QError::runtime(QErrorCode::BadRecordLength, 0, 0));
+            }
+            Some(len) => len as usize,
+            None => DEFAULT_RECORD_LEN,
+        };
+        let access = Access::granted(mode, clauses.access)?;
+        if std::path::Path::new(path).is_dir() {
+            return Err(QError::runtime(QErrorCode::PathFileAccessError, 0, 0));
+        }
+
+        // Check the locks before opening, since OUTPUT truncates the file
+        let key = std::fs::canonicalize(path).ok();
+        if key.is_some() {
+            let conflict = self.files.values().filter(|f| f.key == key).any(|f| {
+                f.lock.is_some_and(|lock| lock.denies(access))
+                    || clauses.lock.is_some_and(|lock| lock.denies(f.access))
+            });
+            if conflict {
+                return Err(QError::runtime(QErrorCode::PermissionDenied, 0, 0));
+            }
+        }
+
+        let handle = match mode {
+            OpenMode::Input => {
+                let file = File::open(path).map_err(|e| open_error(&e, path))?;
+                Handle::Reader(BufReader::new(file))
+            }
+            OpenMode::Output => {
+                let file = File::create(path).map_err(|e| open_error(&e, path))?;
+                Handle::Writer(BufWriter::new(file))
+            }
+            OpenMode::Append => {
+                let file = OpenOptions::new()
+                    .append(true)
+                    .create(true)
+                    .open(path)
+                    .map_err(|e| open_error(&e, path))?;
+                Handle::Writer(BufWriter::new(file))
+            }
+            OpenMode::Random | OpenMode::Binary => {
+                let file = OpenOptions::new()
+                    .read(access.reads())
+                    .write(access.writes())
+                    .create(access.writes())
+                    .truncate(false)
+                    .open(path)
+                    .map_err(|e| open_error(&e, path))?;
+                Handle::Direct(file)
+            }
+        };
+
+        let key = key.or_else(|| std::fs::canonicalize(path).ok());
+        let file = OpenFile {
+            path: path.to_string(),
+            mode,
+            column: 0,
+            record_len,
+            access,
+            lock: clauses.lock,
+            key,
+            handle,
+        };
+        self.files.insert(number, file);
+        Ok(())
+    }
+
+    /// Open a file number for INPUT over contents fetched by a device
+    pub fn open_contents(&mut self, number: i32, name: &str, contents: Vec<u8>) -> QResult<()> {
+        self.check_unused(number)?;
+        let file = OpenFile {
+            path: name.to_string(),
+            mode: OpenMode::Input,
+            column: 0,
+            record_len: DEFAULT_RECORD_LEN,
+            access: Access::Read,
+            lock: None,
+            key: None,
+            handle: Handle::Memory(Cursor::new(contents)),
+        };
+        self.files.insert(number, file);
+        Ok(())
+    }
+
+    /// Open a device in place of a file. The screen takes only output;
+    /// NUL also opens for INPUT, with nothing to read.
+    pub fn open_device(&mut self, number: i32, name: &str, device: Device, mode: OpenMode) -> QResult<()> {
+        self.check_unused(number)?;
+        let handle = match (device, mode) {
+            (_, OpenMode::Output | OpenMode::Append) => Handle::Device(device, io::sink()),
+            (Device::Null, OpenMode::Input) => Handle::Memory(Cursor::new(Vec::new())),
+            _ => return Err(QError::runtime(QErrorCode::BadFileMode, 0, 0)),
+        };
+        let file = OpenFile {
+            path: name.to_string(),
+            mode,
+            column: 0,
+            record_len: DEFAULT_RECORD_LEN,
+            access: Access::granted(mode, None)?,
+            lock: None,
+            key: None,
+            handle,
+        };
+        self.files.insert(number, file);
+        Ok(())
+    }
+
+    /// Whether file number `number` is open on the screen device
+    pub fn is_screen(&self, number: i32) -> bool {
+        self.files.get(&number).is_some_and(|file| matches!(file.handle, Handle::Device(Device::Screen, _)))
+    }
+
+    /// Whether some file number has the file at `path` open
+    pub fn is_open(&self, path: &Path) -> bool {
+        let key = std::fs::canonicalize(path).ok();
+        key.is_some() && self.files.values().any(|file| file.key == key)
+    }
+
+    /// CLOSE #n; closing a number that is not open does nothing
+    pub fn close(&mut self, number: i32) -> QResult<()> {
+        if let Some(mut file) = self.files.remove(&number) {
+            if let Handle::Writer(w) = &mut file.handle {
+                w.flush()?;
+            }
+        }
+        Ok(())
+    }
+
+    pub fn close_all(&mut self) -> QResult<()> {
+        let numbers: Vec<i32> = self.files.keys().copied().collect();
+        for number in numbers {
+            self.close(number)?;
+        }
+        Ok(())
+    }
+
+    /// Lowest file number not currently in use (FREEFILE)
+    pub fn free_number(&self) -> i32 {
+        (1..=255).find(|n| !self.files.contains_key(n)).unwrap_or(0)
+    }
+
+    fn get(&mut self, number: i32) -> QResult<&mut OpenFile> {
+        self.files
+            .get_mut(&number)
+            .ok_or_else(|| QError::runtime(QErrorCode::BadFileNumber, 0, 0))
+    }
+
+    fn get_input(&mut self, number: i32) -> QResult<&mut OpenFile> {
+        let file = self.get(number)?;
+        if file.mode != OpenMode::Input {
+            return Err(QError::runtime(QErrorCode::BadFileMode, 0, 0));
+        }
+        Ok(file)
+    }
+
+    /// EOF(n): true when no more data can be read; for RANDOM and BINARY,
+    /// when the position is at or past the end. A file opened for OUTPUT
+    /// or APPEND has nothing to read: "Bad file mode".
+    pub fn eof(&mut self, number: i32) -> QResult<bool> {
+        let file = self.get(number)?;
+        if matches!(file.mode, OpenMode::Output | OpenMode::Append) {
+            return Err(QError::runtime(QErrorCode::BadFileMode, 0, 0));
+        }
+        match &mut file.handle {
+            Handle::Reader(_) | Handle::Memory(_) => Ok(file.peek_byte()?.is_none()),
+            Handle::Direct(f) => Ok(f.stream_position()? >= f.metadata()?.len()),
+            Handle::Writer(_) | Handle::Device(..) => Ok(true),
+        }
+    }
+
+    /// LOC(n): for RANDOM the last record read or written, for BINARY the
+    /// last byte, for sequential files the position in 128-byte blocks
+    pub fn loc(&mut self, number: i32) -> QResult<u64> {
+        let file = self.get(number)?;
+        let position = match &mut file.handle {
+            Handle::Reader(r) => r.stream_position()?,
+            Handle::Writer(w) => w.stream_position()?,
+            Handle::Direct(f) => f.stream_position()?,
+            Handle::Memory(m) => m.position(),
+            Handle::Device(..) => 0,
+        };
+        Ok(match file.mode {
+            OpenMode::Random => position.div_ceil(file.record_len as u64),
+            OpenMode::Binary => position,
+            _ => position.div_ceil(DEFAULT_RECORD_LEN as u64),
+        })
+    }
+
+    /// GET: `len` bytes of a RANDOM record or at a BINARY position, None
+    /// for the current one. Bytes past the end of the file read as zeros.
+    /// A RANDOM read moves on to the next record.
+    pub fn get_bytes(&mut self, number: i32, position: Option<i64>, len: usize) -> QResult<Vec<u8>> {
+        let file = self.get(number)?;
+        if file.mode == OpenMode::Random && len > file.record_len {
+            return Err(QError::runtime(QErrorCode::BadRecordLength, 0, 0));
+        }
+        if !file.access.reads() {
+            return Err(QError::runtime(QErrorCode::PermissionDenied, 0, 0));
+        }
+        let step = if file.mode == OpenMode::Random { file.record_len } else { len };
+        let f = file.direct(position)?;
+        let start = f.stream_position()?;
+        let mut bytes = Vec::with_capacity(len);
+        Read::by_ref(f).take(len as u64).read_to_end(&mut bytes)?;
+        bytes.resize(len, 0);
+        f.seek(SeekFrom::Start(start + step as u64))?;
+        Ok(bytes)
+    }
+
+    /// PUT: write `bytes` as a RANDOM record, padded to the record length,
+    /// or at a BINARY position; None for the current one
+    pub fn put_bytes(&mut self, number: i32, position: Option<i64>, bytes: &[u8]) -> QResult<()> {
+        let file = self.get(number)?;
+        let mut bytes = bytes.to_vec();
+        if file.mode == OpenMode::Random {
+            if bytes.len() > file.record_len {
+                return Err(QError::runtime(QErrorCode::BadRecordLength, 0, 0));
+            }
+            bytes.resize(file.record_len, 0);
+        }
+        if !file.access.writes() {
+            return Err(QError::runtime(QErrorCode::PermissionDenied, 0, 0));
+        }
+        file.direct(position)?.write_all(&bytes)?;
+        Ok(())
+    }
+
+    /// Mode and record length of an open file
+    pub fn shape(&mut self, number: i32) -> QResult<(OpenMode, usize)> {
+        let file = self.get(number)?;
+        Ok((file.mode, file.record_len))
+    }
+
+    /// LOF(n): length of the file in bytes
+    pub fn length(&mut self, number: i32) -> QResult<u64> {
+        let file = self.get(number)?;
+        let meta = match &mut file.handle {
+            Handle::Reader(r) => r.get_ref().metadata()?,
+            Handle::Memory(m) => return Ok(m.get_ref().len() as u64),
+            Handle::Device(..) => return Ok(0),
+            Handle::Direct(f) => f.metadata()?,
+            Handle::Writer(w) => {
+                w.flush()?;
+                w.get_ref().metadata()?
+            }
+        };
+        Ok(meta.len())
+    }
+
+    /// LINE INPUT #: the raw line, leading spaces and commas included
+    pub fn read_line(&mut self, number: i32) -> QResult<String> {
+        let file = self.get_input(number)?;
+        if file.peek_byte()?.is_none() {
+            return Err(QError::runtime(QErrorCode::InputPastEndOfFile, 0, 0));
+        }
+        let mut bytes = Vec::new();
+        while let Some(b) = file.peek_byte()? {
+            if b == b'\r' || b == b'\n' {
+                file.skip_line_end()?;
+                break;
+            }
+            bytes.push(b);
+            file.next_byte()?;
+        }
+        Ok(decode(bytes))
+    }
+
+    /// INPUT #: the next comma- or line-delimited field. Quoted strings may
+    /// contain commas; unquoted numbers also end at a space.
+    pub fn read_field(&mut self, number: i32, is_string: bool) -> QResult<String> {
+        let file = self.get_input(number)?;
+        while matches!(file.peek_byte()?, Some(b' ') | Some(b'\t') | Some(b'\r') | Some(b'\n')) {
+            file.next_byte()?;
+        }
+        if file.peek_byte()?.is_none() {
+            return Err(QError::runtime(QErrorCode::InputPastEndOfFile, 0, 0));
+        }
+
+        let mut bytes = Vec::new();
+        if file.peek_byte()? == Some(b'"') {
+            file.next_byte()?;
+            while let Some(b) = file.next_byte()? {
+                if b == b'"' {
+                    break;
+                }
+                bytes.push(b);
+            }
+        } else {
+            while let Some(b) = file.peek_byte()? {
+                let ends = b == b',' || b == b'\r' || b == b'\n' || (!is_string && b == b' ');
+                if ends {
+                    break;
+                }
+                bytes.push(b);
+                file.next_byte()?;
+            }
+            if is_string {
+                while bytes.last() == Some(&b' ') {
+                    bytes.pop();
+                }
+            }
+        }
+
+        // Skip trailing blanks and a single delimiter
+        while file.peek_byte()? == Some(b' ') {
+            file.next_byte()?;
+        }
+        match file.peek_byte()? {
+            Some(b',') => {
+                file.next_byte()?;
+            }
+            Some(b'\r') | Some(b'\n') => file.skip_line_end()?,
+            _ => {}
+        }
+        Ok(decode(bytes))
+    }
+
+    fn get_output(&mut self, number: i32) -> QResult<&mut OpenFile> {
+        let file = self.get(number)?;
+        if file.mode == OpenMode::Input {
+            return Err(QError::runtime(QErrorCode::BadFileMode, 0, 0));
+        }
+        if !file.access.writes() {
+            return Err(QError::runtime(QErrorCode::PermissionDenied, 0, 0));
+        }
+        Ok(file)
+    }
+
+    /// Check that a file number is open for writing
+    pub fn check_output(&mut self, number: i32) -> QResult<()> {
+        self.get_output(number).map(|_| ())
+    }
+
+    /// Current output column of a file opened for writing
+    pub fn column(&mut self, number: i32) -> QResult<usize> {
+        Ok(self.get_output(number)?.column)
+    }
+
+    /// Write text to a file opened for OUTPUT, APPEND, RANDOM or BINARY
+    pub fn write_str(&mut self, number: i32, text: &str) -> QResult<()> {
+        let file = self.get_output(number)?;
+        file.writer()?.write_all(&encode(text))?;
+        match text.rfind('\n') {
+            Some(pos) => file.column = text[pos + 1..].chars().count(),
+            None => file.column += text.chars().count(),
+        }
+        Ok(())
+    }
+}
+
+impl Drop for FileTable {
+    fn drop(&mut self) {
+        let _ = self.close_all();
+    }
+}
+
+/// The error OPEN reports for `path`: a missing directory on the way to
+/// the file is "Path not found"
+fn open_error(e: &std::io::Error, path: &str) -> QError {
+    let code = match e.kind() {
+        std::io::ErrorKind::NotFound => {
+            let parent = std::path::Path::new(path).parent().filter(|p| !p.as_os_str().is_empty());
+            if parent.is_some_and(|p| !p.is_dir()) {
+                QErrorCode::PathNotFound
+            } else {
+                QErrorCode::FileNotFound
+            }
+        }
+        std::io::ErrorKind::PermissionDenied => QErrorCode::PathFileAccessError,
+        _ => QErrorCode::DeviceIOError,
+    };
+    QError::runtime(code, 0, 0)
+}
+
+/// File bytes to a string: UTF-8 when valid, otherwise one char per byte
+fn decode(bytes: Vec<u8>) -> String {
+    String::from_utf8(bytes).unwrap_or_else(|e| e.into_bytes().iter().map(|&b| b as char).collect())
+}
+
+fn encode(text: &str) -> Vec<u8> {
+    text.as_bytes().to_vec()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn temp_file(name: &str, contents: &[u8]) -> String {
+        let path = std::env::temp_dir().join(format!("qb_files_{}_{}", std::process::id(), name));
+        std::fs::write(&path, contents).unwrap();
+        path.to_string_lossy().into_owned()
+    }
+
+    #[test]
+    fn test_read_line_endings() {
+        let path = temp_file("lines", b"  a, b\r\nsecond\rthird\nlast\x1Agarbage");
+        let mut table = FileTable::new();
+        table.open(1, &path, OpenMode::Input).unwrap();
+        assert_eq!(table.read_line(1).unwrap(), "  a, b");
+        assert_eq!(table.read_line(1).unwrap(), "second");
+        assert_eq!(table.read_line(1).unwrap(), "third");
+        assert_eq!(table.read_line(1).unwrap(), "last");
+        assert!(table.eof(1).unwrap());
+        assert!(table.read_line(1).is_err());
+        std::fs::remove_file(path).unwrap();
+    }
+
+    #[test]
+    fn test_read_fields() {
+        let path = temp_file("fields", b"\"Smith, J\", 42\r\n3.5\r\n");
+        let mut table = FileTable::new();
+        table.open(2, &path, OpenMode::Input).unwrap();
+        assert_eq!(table.read_field(2, true).unwrap(), "Smith, J");
+        assert_eq!(table.read_field(2, false).unwrap(), "42");
+        assert_eq!(table.read_field(2, false).unwrap(), "3.5");
+        assert!(table.eof(2).unwrap());
+        std::fs::remove_file(path).unwrap();
+    }
+
+    #[test]
+    fn test_write_tracks_column() {
+        let path = temp_file("write", b"");
+        let mut table = FileTable::new();
+        table.open(3, &path, OpenMode::Output).unwrap();
+        table.write_str(3, "abc").unwrap();
+        assert_eq!(table.column(3).unwrap(), 3);
+        table.write_str(3, "de\nf").unwrap();
+        assert_eq!(table.column(3).unwrap(), 1);
+        table.close(3).unwrap();
+        assert_eq!(std::fs::read_to_string(&path).unwrap(), "abcde\nf");
+        assert!(table.write_str(3, "x").is_err());
+        table.close(3).unwrap(); // not open: nothing to do
+
+        table.open(3, &path, OpenMode::Append).unwrap();
+        assert!(table.eof(3).is_err());
+        table.close(3).unwrap();
+        std::fs::remove_file(path).unwrap();
+    }
+
+    #[test]
+    fn test_unopened_file_number() {
+        let mut table = FileTable::new();
+        let code = |result: QResult<bool>| match result {
+            Err(QError::Runtime { code, .. }) => Some(code),
+            _ => None,
+        };
+        assert_eq!(code(table.eof(7)), Some(QErrorCode::BadFileNumber));
+        assert_eq!(code(table.read_line(7).map(|_| true)), Some(QErrorCode::BadFileNumber));
+        assert_eq!(code(table.write_str(7, "x").map(|_| true)), Some(QErrorCode::BadFileNumber));
+    }
+
+    #[test]
+    fn test_access_and_locks() {
+        let path = temp_file("locks", b"data\r\n");
+        let mut table = FileTable::new();
+        let error = |result: QResult<()>| match result {
+            Err(QError::Runtime { code, .. }) => code,
+            other => panic!("expected a runtime error, got {:?}", other),
+        };
+        let locked = OpenClauses { lock: Some(Lock::Write), ..OpenClauses::default() };
+        table.open_with(1, &path, OpenMode::Input, locked).unwrap();
+
+        // LOCK WRITE lets others read but not write
+        table.open(2, &path, OpenMode::Input).unwrap();
+        assert_eq!(error(table.open(3, &path, OpenMode::Append)), QErrorCode::PermissionDenied);
+        // A lock may not deny what an existing open already does
+        let exclusive = OpenClauses { lock: Some(Lock::ReadWrite), ..OpenClauses::default() };
+        assert_eq!(error(table.open_with(3, &path, OpenMode::Input, exclusive)), QErrorCode::PermissionDenied);
+        table.close_all().unwrap();
+
+        let read_only = OpenClauses { access: Some(Access::Read), ..OpenClauses::default() };
+        table.open_with(4, &path, OpenMode::Binary, read_only).unwrap();
+        assert_eq!(error(table.write_str(4, "x")), QErrorCode::PermissionDenied);
+        assert_eq!(error(table.open_with(5, &path, OpenMode::Output, read_only)), QErrorCode::BadFileMode);
+
+        let too_long = OpenClauses { record_len: Some(40000), ..OpenClauses::default() };
+        assert_eq!(error(table.open_with(5, &path, OpenMode::Random, too_long)), QErrorCode::BadRecordLength);
+        table.close_all().unwrap();
+
+        let missing = format!("{}.dir/file", path);
+        assert_eq!(error(table.open(6, &missing, OpenMode::Output)), QErrorCode::PathNotFound);
+        assert_eq!(error(table.open(6, &format!("{}.none", path), OpenMode::Input)), QErrorCode::FileNotFound);
+        let directory = std::env::temp_dir().to_string_lossy().into_owned();
+        assert_eq!(error(table.open(6, &directory, OpenMode::Input)), QErrorCode::PathFileAccessError);
+        assert_eq!(std::fs::read(&path).unwrap(), b"data\r\n");
+        std::fs::remove_file(path).unwrap();
+    }
+
+    #[test]
+    fn test_random_and_binary() {
+        let path = temp_file("records", b"");
+        let mut table = FileTable::new();
+        let records = OpenClauses { record_len: Some(4), ..OpenClauses::default() };
+        table.open_with(1, &path, OpenMode::Random, records).unwrap();
+        table.put_bytes(1, Some(2), b"ab").unwrap();
+        assert_eq!(table.loc(1).unwrap(), 2);
+        table.put_bytes(1, None, b"wxyz").unwrap();
+        assert!(table.put_bytes(1, None, b"12345").is_err());
+        assert!(table.put_bytes(1, Some(0), b"").is_err());
+        assert_eq!(table.length(1).unwrap(), 12);
+
+        // Records past the end read as zeros; each GET moves on one record
+        assert_eq!(table.get_bytes(1, Some(1), 4).unwrap(), [0; 4]);
+        assert_eq!(table.get_bytes(1, None, 2).unwrap(), b"ab");
+        assert_eq!(table.get_bytes(1, None, 4).unwrap(), b"wxyz");
+        assert!(table.eof(1).unwrap());
+        assert_eq!(table.get_bytes(1, Some(9), 4).unwrap(), [0; 4]);
+        table.close(1).unwrap();
+
+        table.open(2, &path, OpenMode::Binary).unwrap();
+        assert_eq!(table.get_bytes(2, Some(5), 3).unwrap(), b"ab\0");
+        assert_eq!(table.loc(2).unwrap(), 7);
+        table.put_bytes(2, Some(1), b"Q").unwrap();
+        table.close(2).unwrap();
+        assert_eq!(std::fs::read(&path).unwrap(), b"Q\0\0\0ab\0\0wxyz");
+        std::fs::remove_file(path).unwrap();
+    }
+}
@@ -0,0 +1,68 @@
+//! Procedure frames. Each SUB or FUNCTION call gets a frame holding its
+//! local variables; a BYREF parameter is not a variable of its own but
+//! a reference to the caller's variable or array element, so assigning
+//! to it changes the caller's. An array parameter likewise stands for
+//! the caller's whole array. Module-level variables are only seen by
+//! name when they are shared. A STATIC variable is kept between calls as
+//! a module-level variable named for its procedure, which the frame
+//! refers to as it does a BYREF parameter.
+
+use qb_core::data_types::QType;
+use std::collections::HashMap;
+
+/// The variable a BYREF parameter stands for
+#[derive(Debug, Clone, PartialEq)]
+pub enum Ref {
+    /// A local of the frame at this depth, or a module-level variable
+    Var(Option<usize>, String),
+    /// An array element, by its position in the array's elements
+    Element(String, usize),
+}
+
+/// An argument as a call hands it over
+#[derive(Debug, Clone, PartialEq)]
+pub enum Binding {
+    Value(QType),
+    Ref(Ref),
+    /// A whole array, by the name it is kept under
+    Array(String),
+}
+
+/// One active SUB or FUNCTION call
+#[derive(Debug, Default)]
+pub struct Frame {
+    pub locals: HashMap<String, QType>,
+    /// BYREF parameters and what they refer to
+    pub refs: HashMap<String, Ref>,
+    /// Array parameters and the arrays they stand for
+    pub arrays: HashMap<String, String>,
+    /// Module-level variables the procedure sees
+    pub shared: Vec<String>,
+    /// Arguments waiting to be bound to the parameters
+    pub args: Vec<Binding>,
+    /// A FUNCTION's result variable
+    pub result: Option<String>,
+    /// The procedure, when it was declared STATIC and so keeps every
+    /// variable it makes
+    pub keeps: Option<String>,
+    /// The caller's ON ERROR handler and the depth it was set at, back in
+    /// force when the procedure returns
+    pub outer_handler: (Option<u32>, usize),
+    pub return_address: usize,
+}
+
+impl Frame {
+    pub fn new(args: Vec<Binding>, return_address: usize) -> Self {
+        Self { args, return_address, ..Self::default() }
+    }
+
+    pub fn shares(&self, name: &str) -> bool {
+        self.shared.iter().any(|shared| shared == name)
+    }
+}
+
+/// The module-level name STATIC variable `name` of procedure `proc` is
+/// kept under, which no variable of the program can have
+pub fn static_key(proc: &str, name: &str) -> String {
+    format!("{}:{}", proc, name)
+}
@@ -0,0 +1,207 @@
+//! The HTTP: device: `OPEN "HTTP:url" FOR INPUT AS #n` downloads a page and
+//! reads it like a sequential file
+//!
+//! The client is deliberately small: plain HTTP/1.1 over `std::net`, one
+//! blocking GET per OPEN, following redirects and decoding chunked bodies.
+//! There is no TLS, so https URLs are rejected.
+
+use qb_core::errors::{QError, QErrorCode, QResult};
+use std::io::{BufRead, BufReader, Write};
+use std::net::TcpStream;
+use std::time::Duration;
+
+const PREFIX: &str = "HTTP:";
+/// Redirects followed before giving up
+const MAX_REDIRECTS: usize = 5;
+const TIMEOUT: Duration = Duration::from_secs(30);
+
+/// URL named by an "HTTP:" file name, or None for an ordinary file
+pub fn device_url(path: &str) -> Option<String> {
+    let rest = path.trim();
+    rest.get(..PREFIX.len()).filter(|prefix| prefix.eq_ignore_ascii_case(PREFIX))?;
+    let rest = &rest[PREFIX.len()..];
+    Some(if rest.starts_with("//") {
+        format!("http:{}", rest)
+    } else if rest.contains("://") {
+        rest.to_string()
+    } else {
+        format!("http://{}", rest)
+    })
+}
+
+fn bad_name() -> QError {
+    QError::runtime(QErrorCode::BadFileName, 0, 0)
+}
+
+fn io_error() -> QError {
+    QError::runtime(QErrorCode::DeviceIOError, 0, 0)
+}
+
+/// Split "http://host[:port]/path" into host, port and path
+fn split_url(url: &str) -> QResult<(String, u16, String)> {
+    let (scheme, rest) = url.split_once("://").ok_or_else(bad_name)?;
+    if !scheme.eq_ignore_ascii_case("http") {
+        return Err(QError::runtime(QErrorCode::AdvancedFeatureUnavailable, 0, 0));
+    }
+    let (authority, path) = match rest.find(['/', '?']) {
+        Some(i) if rest[i..].starts_with('/') => (&rest[..i], rest[i..].to_string()),
+        Some(i) => (&rest[..i], format!("/{}", &rest[i..])),
+        None => (rest, "/".to_string()),
+    };
+    let (host, port) = match authority.rsplit_once(':') {
+        Some((host, port)) => (host, port.parse().map_err(|_| bad_name())?),
+        None => (authority, 80),
+    };
+    if host.is_empty() {
+        return Err(bad_name());
+    }
+    Ok((host.to_string(), port, path))
+}
+
+/// Download a URL and return the body
+pub fn fetch(url: &str) -> QResult<Vec<u8>> {
+    let mut url = url.to_string();
+    for _ in 0..=MAX_REDIRECTS {
+        let (host, port, path) = split_url(&url)?;
+        let response = request(&host, port, &path)?;
+        match response.status {
+            200..=299 => return Ok(response.body),
+            301 | 302 | 303 | 307 | 308 => {
+                let location = response.location.ok_or_else(io_error)?;
+                url = if location.contains("://") {
+                    location
+                } else if location.starts_with('/') {
+                    format!("http://{}:{}{}", host, port, location)
+                } else {
+                    let dir = &path[..path.rfind('/').map_or(0, |i| i + 1)];
+                    format!("http://{}:{}{}{}", host, port, dir, location)
+                };
+            }
+            404 | 410 => return Err(QError::runtime(QErrorCode::FileNotFound, 0, 0)),
+            401 | 403 => return Err(QError::runtime(QErrorCode::PathFileAccessError, 0, 0)),
+            _ => return Err(io_error()),
+        }
+    }
+    Err(io_error())
+}
+
+struct Response {
+    status: u16,
+    location: Option<String>,
+    body: Vec<u8>,
+}
+
+fn request(host: &str, port: u16, path: &str) -> QResult<Response> {
+    let stream = TcpStream::connect((host, port))
+        .map_err(|_| QError::runtime(QErrorCode::DeviceUnavailable, 0, 0))?;
+    stream.set_read_timeout(Some(TIMEOUT))?;
+    stream.set_write_timeout(Some(TIMEOUT))?;
+    let host_header = if port == 80 { host.to_string() } else { format!("{}:{}", host, port) };
+    write!(
+        &stream,
+        "GET {} HTTP/1.1\r\nHost: {}\r\nUser-Agent: qb\r\nAccept: */*\r\nConnection: close\r\n\r\n",
+        path, host_header
+    )?;
+    read_response(BufReader::new(stream))
+}
+
+fn read_line(reader: &mut impl BufRead) -> QResult<String> {
+    let mut line = String::new();
+    reader.read_line(&mut line)?;
+    Ok(line.trim_end_matches(['\r', '\n']).to_string())
+}
+
+fn read_response(mut reader: impl BufRead) -> QResult<Response> {
+    let status_line = read_line(&mut reader)?;
+    let status = status_line
+        .split_whitespace()
+        .nth(1)
+        .and_then(|s| s.parse().ok())
+        .ok_or_else(io_error)?;
+
+    let mut location = None;
+    let mut length = None;
+    let mut chunked = false;
+    loop {
+        let line = read_line(&mut reader)?;
+        if line.is_empty() {
+            break;
+        }
+        let Some((name, value)) = line.split_once(':') else { continue };
+        let value = value.trim();
+        match name.trim().to_ascii_lowercase().as_str() {
+            "location" => location = Some(value.to_string()),
+            "content-length" => length = value.parse::<usize>().ok(),
+            "transfer-encoding" => chunked = value.to_ascii_lowercase().contains("chunked"),
+            _ => {}
+        }
+    }
+
+    let mut body = Vec::new();
+    if chunked {
+        loop {
+            let size_line = read_line(&mut reader)?;
+            let size = size_line.split(';').next().unwrap_or("").trim();
+            let size = usize::from_str_radix(size, 16).map_err(|_| io_error())?;
+            if size == 0 {
+                break;
+            }
+            let start = body.len();
+            body.resize(start + size, 0);
+            reader.read_exact(&mut body[start..])?;
+            read_line(&mut reader)?;
+        }
+    } else if let Some(length) = length {
+        body.resize(length, 0);
+        reader.read_exact(&mut body)?;
+    } else {
+        reader.read_to_end(&mut body)?;
+    }
+    Ok(Response { status, location, body })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::net::TcpListener;
+
+    #[test]
+    fn test_device_url() {
+        assert_eq!(device_url("http:example.com/a.txt").as_deref(), Some("http://example.com/a.txt"));
+        assert_eq!(device_url("HTTP://example.com").as_deref(), Some("http://example.com"));
+        assert_eq!(device_url("HTTP:http://example.com/").as_deref(), Some("http://example.com/"));
+        assert_eq!(device_url("data.txt"), None);
+        assert_eq!(device_url("abcdé.txt"), None);
+        assert_eq!(device_url("é"), None);
+        assert_eq!(split_url("http://h:8080?q=1").unwrap(), ("h".to_string(), 8080, "/?q=1".to_string()));
+        assert!(split_url("https://example.com/").is_err());
+    }
+
+    #[test]
+    fn test_chunked_response() {
+        let raw = b"HTTP/1.1 200 OK\r\nTransfer-Encoding: chunked\r\n\r\n3\r\nabc\r\n2\r\nde\r\n0\r\n\r\n";
+        let response = read_response(&raw[..]).unwrap();
+        assert_eq!(response.status, 200);
+        assert_eq!(response.body, b"abcde");
+    }
+
+    #[test]
+    fn test_fetch_follows_redirect() {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let port = listener.local_addr().unwrap().port();
+        let server = std::thread::spawn(move || {
+            for reply in [
+                "HTTP/1.1 302 Found\r\nLocation: /b\r\nContent-Length: 0\r\n\r\n",
+                "HTTP/1.1 200 OK\r\nContent-Length: 5\r\n\r\nhello",
+            ] {
+                let (mut stream, _) = listener.accept().unwrap();
+                let mut request = BufReader::new(&stream);
+                while !read_line(&mut request).unwrap().is_empty() {}
+                stream.write_all(reply.as_bytes()).unwrap();
+            }
+        });
+        let body = fetch(&format!("http://127.0.0.1:{}/a", port)).unwrap();
+        assert_eq!(body, b"hello");
+        server.join().unwrap();
+    }
+}
@@ -2,10 +2,34 @@
 //! 
 //! Provides bytecode compiler and virtual machine for executing QBasic programs.
 
+pub mod address_space;
 pub mod opcodes;
 pub mod compiler;
+pub mod coverage;
+pub mod dict;
+pub mod eval;
 pub mod runtime;
+pub mod events;
+pub mod files;
+pub mod frames;
+pub mod http;
+pub mod mem;
+pub mod net;
+pub mod output;
+pub mod random;
+pub mod sandbox;
+pub mod session;
+pub mod strings;
+pub mod using;
+pub mod vfs;
+pub mod watch;
 
 pub use opcodes::{ByteCode, OpCode};
-pub use compiler::{ByteCodeCompiler, compile};
-pub use runtime::{VirtualMachine, run};
+pub use compiler::{ByteCodeCompiler, compile, compile_expression};
+pub use coverage::{CoverageData, FileCoverage};
+pub use eval::{evaluate_expression, parse_watch};
+pub use runtime::{ArrayView, Pause, VirtualMachine, run};
+pub use sandbox::Sandbox;
+pub use session::{ConsoleInput, RecordedInput, Session};
+pub use vfs::Vfs;
+pub use watch::{Watch, WatchHit};
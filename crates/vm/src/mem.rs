@@ -0,0 +1,216 @@
+//! _MEM memory blocks: _MEMNEW and _MEMIMAGE create them, _MEMGET and
+//! _MEMPUT read and write typed values, _MEMFREE releases them
+//!
+//! A _MEM variable holds a block handle. Each block is given its own range
+//! of addresses, reported as `m.OFFSET`, and every access is checked
+//! against that range.
+
+use qb_core::data_types::QType;
+use qb_core::dialect::Dialect;
+use qb_core::errors::{QError, QErrorCode, QResult};
+use qb_hal::graphics::Graphics;
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+
+/// Address of the first block
+const BASE_ADDRESS: i64 = 0x10000;
+/// Blocks start on this boundary
+const ALIGN: i64 = 16;
+/// _MEM is QB64's, so strings in blocks are in its character set
+const MEM_CHARSET: Dialect = Dialect::Qb64;
+
+/// Element of a _MEM variable, read as `m.OFFSET` etc.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub enum MemField {
+    Offset,
+    Size,
+    ElementSize,
+    Image,
+}
+
+impl MemField {
+    pub fn from_name(name: &str) -> Option<Self> {
+        match name.to_uppercase().as_str() {
+            "OFFSET" => Some(MemField::Offset),
+            "SIZE" => Some(MemField::Size),
+            "ELEMENTSIZE" => Some(MemField::ElementSize),
+            "IMAGE" => Some(MemField::Image),
+            _ => None,
+        }
+    }
+}
+
+/// Where a block's bytes live
+enum Region {
+    Owned(Vec<u8>),
+    /// Pixel data of an image handle
+    Image(i32),
+}
+
+struct MemBlock {
+    offset: i32,
+    size: usize,
+    element_size: usize,
+    region: Region,
+}
+
+/// Bytes a value occupies in memory; a variable-length string takes its
+/// current length
+fn value_len(value: &QType) -> usize {
+    match value {
+        QType::String(s) => s.chars().count(),
+        _ => value.size(),
+    }
+}
+
+fn invalid_handle() -> QError {
+    QError::runtime(QErrorCode::InvalidHandle, 0, 0)
+}
+
+/// Live memory blocks by handle; handle 0 is the null block of a _MEM
+/// variable that was never assigned
+pub struct MemTable {
+    blocks: HashMap<i32, MemBlock>,
+    next_handle: i32,
+    next_offset: i64,
+}
+
+impl MemTable {
+    pub fn new() -> Self {
+        Self { blocks: HashMap::new(), next_handle: 1, next_offset: BASE_ADDRESS }
+    }
+
+    fn allocate(&mut self, size: usize, element_size: usize, region: Region) -> QResult<i32> {
+        let offset = self.next_offset;
+        let end = offset + (size as i64).max(1);
+        if end > i64::from(i32::MAX) {
+            return Err(QError::runtime(QErrorCode::OutOfMemory, 0, 0));
+        }
+        self.next_offset = (end + ALIGN - 1) / ALIGN * ALIGN;
+
+        let handle = self.next_handle;
+        self.next_handle += 1;
+        self.blocks.insert(handle, MemBlock { offset: offset as i32, size, element_size, region });
+        Ok(handle)
+    }
+
+    /// _MEMNEW: a zero-filled block of `size` bytes
+    pub fn new_block(&mut self, size: i32) -> QResult<i32> {
+        if size < 0 {
+            return Err(QError::runtime(QErrorCode::IllegalFunctionCall, 0, 0));
+        }
+        self.allocate(size as usize, 1, Region::Owned(vec![0; size as usize]))
+    }
+
+    /// _MEMIMAGE: a block over the pixel data of an image
+    pub fn image_block(&mut self, graphics: &mut Graphics, image: i32) -> QResult<i32> {
+        let pixels = graphics.image(image)?;
+        let (size, element_size) = (pixels.byte_len(), pixels.bytes_per_pixel());
+        self.allocate(size, element_size, Region::Image(image))
+    }
+
+    /// _MEMFREE
+    pub fn free(&mut self, handle: i32) -> QResult<()> {
+        self.blocks.remove(&handle).map(|_| ()).ok_or_else(invalid_handle)
+    }
+
+    /// `m.OFFSET`, `m.SIZE`, `m.ELEMENTSIZE` or `m.IMAGE`; all zero (IMAGE
+    /// -1) for a null or freed block
+    pub fn field(&self, handle: i32, field: MemField) -> i32 {
+        let block = self.blocks.get(&handle);
+        match field {
+            MemField::Offset => block.map_or(0, |b| b.offset),
+            MemField::Size => block.map_or(0, |b| b.size as i32),
+            MemField::ElementSize => block.map_or(0, |b| b.element_size as i32),
+            MemField::Image => match block.map(|b| &b.region) {
+                Some(Region::Image(image)) => *image,
+                _ => -1,
+            },
+        }
+    }
+
+    /// Block of a handle and the byte index of `address` within it, checking
+    /// that `len` bytes fit
+    fn locate(&self, handle: i32, address: i32, len: usize) -> QResult<(&MemBlock, usize)> {
+        let block = self.blocks.get(&handle).ok_or_else(invalid_handle)?;
+        let start = i64::from(address) - i64::from(block.offset);
+        if start < 0 || start as usize + len > block.size {
+            return Err(QError::runtime(QErrorCode::IllegalFunctionCall, 0, 0));
+        }
+        Ok((block, start as usize))
+    }
+
+    /// _MEMGET: read a value of the same type as `template` at `address`
+    pub fn get(&self, graphics: &mut Graphics, handle: i32, address: i32, template: &QType) -> QResult<QType> {
+        let len = value_len(template);
+        let (block, start) = self.locate(handle, address, len)?;
+        let mut bytes = vec![0; len];
+        match &block.region {
+            Region::Owned(data) => bytes.copy_from_slice(&data[start..start + len]),
+            Region::Image(image) => graphics.image(*image)?.read_bytes(start, &mut bytes),
+        }
+        Ok(template.from_bytes(&bytes, MEM_CHARSET))
+    }
+
+    /// _MEMPUT: write a value at `address`
+    pub fn put(&mut self, graphics: &mut Graphics, handle: i32, address: i32, value: &QType) -> QResult<()> {
+        let bytes = value.to_bytes(MEM_CHARSET);
+        let (_, start) = self.locate(handle, address, bytes.len())?;
+        match &mut self.blocks.get_mut(&handle).expect("located block").region {
+            Region::Owned(data) => data[start..start + bytes.len()].copy_from_slice(&bytes),
+            Region::Image(image) => graphics.image_mut(*image)?.write_bytes(start, &bytes),
+        }
+        Ok(())
+    }
+}
+
+impl Default for MemTable {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_get_put_bounds() {
+        let mut graphics = Graphics::new();
+        let mut table = MemTable::new();
+        let m = table.new_block(6).unwrap();
+        let base = table.field(m, MemField::Offset);
+        assert_eq!(table.field(m, MemField::Size), 6);
+
+        table.put(&mut graphics, m, base + 2, &QType::Long(-2)).unwrap();
+        assert_eq!(table.get(&mut graphics, m, base + 2, &QType::Long(0)).unwrap(), QType::Long(-2));
+        assert_eq!(table.get(&mut graphics, m, base + 4, &QType::Integer(0)).unwrap(), QType::Integer(-1));
+        assert!(table.get(&mut graphics, m, base + 4, &QType::Long(0)).is_err());
+        assert!(table.put(&mut graphics, m, base - 1, &QType::Integer(0)).is_err());
+
+        // Blocks do not overlap
+        let other = table.new_block(4).unwrap();
+        assert!(table.field(other, MemField::Offset) >= base + 6);
+
+        table.free(m).unwrap();
+        assert!(table.get(&mut graphics, m, base, &QType::Integer(0)).is_err());
+        assert!(table.free(m).is_err());
+        assert_eq!(table.field(m, MemField::Image), -1);
+    }
+
+    #[test]
+    fn test_image_block() {
+        let mut graphics = Graphics::new();
+        let image = graphics.new_image(4, 2, 32).unwrap();
+        let mut table = MemTable::new();
+        let m = table.image_block(&mut graphics, image).unwrap();
+        assert_eq!(table.field(m, MemField::Size), 32);
+        assert_eq!(table.field(m, MemField::ElementSize), 4);
+        assert_eq!(table.field(m, MemField::Image), image);
+
+        // Second pixel of the second row
+        let address = table.field(m, MemField::Offset) + (4 + 1) * 4;
+        table.put(&mut graphics, m, address, &QType::UnsignedLong(0xFF00FF00)).unwrap();
+        assert_eq!(graphics.image(image).unwrap().point(1, 1), Some(0xFF00FF00));
+    }
+}
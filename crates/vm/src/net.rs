@@ -0,0 +1,334 @@
+//! TCP/IP and UDP/IP connections: _OPENHOST, _OPENCLIENT, _OPENCONNECTION
+//! and GET #/PUT # on their handles
+//!
+//! Addresses are written the QB64 way, "TCP/IP:port" for a host and
+//! "TCP/IP:port:address" for a client. Handles are negative so they never
+//! clash with file numbers. Reads never block: GET takes what has arrived.
+
+use qb_core::errors::{QError, QErrorCode, QResult};
+use std::collections::HashMap;
+use std::io::{ErrorKind, Read, Write};
+use std::net::{SocketAddr, TcpListener, TcpStream, UdpSocket};
+
+/// Largest UDP datagram read in one go
+const DATAGRAM_SIZE: usize = 65536;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Protocol {
+    Tcp,
+    Udp,
+}
+
+/// Split "TCP/IP:port[:address]"
+fn parse_address(spec: &str) -> QResult<(Protocol, u16, Option<&str>)> {
+    let invalid = || QError::runtime(QErrorCode::IllegalFunctionCall, 0, 0);
+    let mut parts = spec.trim().splitn(3, ':');
+    let protocol = match parts.next().map(str::to_uppercase).as_deref() {
+        Some("TCP/IP") => Protocol::Tcp,
+        Some("UDP/IP") => Protocol::Udp,
+        _ => return Err(invalid()),
+    };
+    let port = parts.next().and_then(|p| p.trim().parse().ok()).ok_or_else(invalid)?;
+    let address = parts.next().map(str::trim).filter(|a| !a.is_empty());
+    Ok((protocol, port, address))
+}
+
+fn protocol_name(protocol: Protocol) -> &'static str {
+    match protocol {
+        Protocol::Tcp => "TCP/IP",
+        Protocol::Udp => "UDP/IP",
+    }
+}
+
+enum Link {
+    Tcp(TcpStream),
+    /// UDP sends to the connected address, or replies to the last sender
+    /// on a host
+    Udp { socket: UdpSocket, peer: Option<SocketAddr> },
+}
+
+/// A client or accepted connection, or a UDP host
+struct Connection {
+    link: Link,
+    /// Bytes received but not yet taken by GET
+    pending: Vec<u8>,
+    connected: bool,
+    /// The last GET found too few bytes; reported by EOF
+    short_read: bool,
+}
+
+impl Connection {
+    fn new(link: Link) -> Self {
+        Self { link, pending: Vec::new(), connected: true, short_read: false }
+    }
+
+    /// Move whatever has arrived into `pending`
+    fn fill(&mut self) {
+        let mut buf = [0; DATAGRAM_SIZE];
+        loop {
+            let result = match &mut self.link {
+                Link::Tcp(stream) => stream.read(&mut buf),
+                Link::Udp { socket, peer } => socket.recv_from(&mut buf).map(|(n, from)| {
+                    *peer = Some(from);
+                    n
+                }),
+            };
+            match result {
+                // A TCP read of nothing means the other side closed
+                Ok(0) if matches!(self.link, Link::Tcp(_)) => {
+                    self.connected = false;
+                    return;
+                }
+                Ok(n) => self.pending.extend_from_slice(&buf[..n]),
+                Err(e) if e.kind() == ErrorKind::WouldBlock => return,
+                Err(e) if e.kind() == ErrorKind::Interrupted => {}
+                Err(_) => {
+                    self.connected = false;
+                    return;
+                }
+            }
+        }
+    }
+
+    fn send(&mut self, bytes: &[u8]) {
+        let sent = match &mut self.link {
+            // Writes wait until everything is sent
+            Link::Tcp(stream) => stream
+                .set_nonblocking(false)
+                .and_then(|_| stream.write_all(bytes))
+                .and_then(|_| stream.set_nonblocking(true)),
+            Link::Udp { socket, peer: Some(peer) } => socket.send_to(bytes, *peer).map(|_| ()),
+            // A UDP host has no one to reply to before its first datagram
+            Link::Udp { peer: None, .. } => Ok(()),
+        };
+        if sent.is_err() {
+            self.connected = false;
+        }
+    }
+
+    fn address(&self) -> Option<SocketAddr> {
+        match &self.link {
+            Link::Tcp(stream) => stream.peer_addr().ok(),
+            Link::Udp { socket, peer } => peer.or_else(|| socket.local_addr().ok()),
+        }
+    }
+}
+
+enum Socket {
+    /// TCP listener waiting for _OPENCONNECTION
+    Host(TcpListener),
+    Connection(Connection),
+}
+
+/// Open hosts and connections by handle
+pub struct NetTable {
+    sockets: HashMap<i32, Socket>,
+    next_handle: i32,
+}
+
+impl NetTable {
+    pub fn new() -> Self {
+        Self { sockets: HashMap::new(), next_handle: -1 }
+    }
+
+    /// Whether a GET/PUT/CLOSE/EOF number is a connection handle
+    pub fn is_handle(number: i32) -> bool {
+        number < 0
+    }
+
+    fn insert(&mut self, socket: Socket) -> i32 {
+        let handle = self.next_handle;
+        self.next_handle -= 1;
+        self.sockets.insert(handle, socket);
+        handle
+    }
+
+    fn connection(&mut self, handle: i32) -> QResult<&mut Connection> {
+        match self.sockets.get_mut(&handle) {
+            Some(Socket::Connection(connection)) => Ok(connection),
+            Some(Socket::Host(_)) => Err(QError::runtime(QErrorCode::BadFileMode, 0, 0)),
+            None => Err(QError::runtime(QErrorCode::InvalidHandle, 0, 0)),
+        }
+    }
+
+    /// _OPENHOST("TCP/IP:port"): listen for connections; 0 if the port
+    /// cannot be opened
+    pub fn open_host(&mut self, spec: &str) -> QResult<i32> {
+        let (protocol, port, _) = parse_address(spec)?;
+        let socket = match protocol {
+            Protocol::Tcp => TcpListener::bind(("0.0.0.0", port))
+                .and_then(|listener| listener.set_nonblocking(true).map(|_| Socket::Host(listener))),
+            Protocol::Udp => UdpSocket::bind(("0.0.0.0", port)).and_then(|socket| {
+                socket.set_nonblocking(true)?;
+                Ok(Socket::Connection(Connection::new(Link::Udp { socket, peer: None })))
+            }),
+        };
+        Ok(socket.map_or(0, |socket| self.insert(socket)))
+    }
+
+    /// _OPENCLIENT("TCP/IP:port:address"): connect to a host; 0 if it
+    /// cannot be reached
+    pub fn open_client(&mut self, spec: &str) -> QResult<i32> {
+        let (protocol, port, address) = parse_address(spec)?;
+        let address = address.ok_or_else(|| QError::runtime(QErrorCode::IllegalFunctionCall, 0, 0))?;
+        let link = match protocol {
+            Protocol::Tcp => TcpStream::connect((address, port)).and_then(|stream| {
+                stream.set_nonblocking(true)?;
+                Ok(Link::Tcp(stream))
+            }),
+            Protocol::Udp => UdpSocket::bind(("0.0.0.0", 0)).and_then(|socket| {
+                socket.connect((address, port))?;
+                socket.set_nonblocking(true)?;
+                let peer = socket.peer_addr().ok();
+                Ok(Link::Udp { socket, peer })
+            }),
+        };
+        Ok(link.map_or(0, |link| self.insert(Socket::Connection(Connection::new(link)))))
+    }
+
+    /// _OPENCONNECTION(host): the next waiting client, or 0 if none
+    pub fn accept(&mut self, host: i32) -> QResult<i32> {
+        let accepted = match self.sockets.get(&host) {
+            Some(Socket::Host(listener)) => listener.accept(),
+            // UDP hosts have no separate connections
+            Some(Socket::Connection(_)) => return Ok(0),
+            None => return Err(QError::runtime(QErrorCode::InvalidHandle, 0, 0)),
+        };
+        match accepted {
+            Ok((stream, _)) => {
+                stream.set_nonblocking(true)?;
+                Ok(self.insert(Socket::Connection(Connection::new(Link::Tcp(stream)))))
+            }
+            Err(e) if e.kind() == ErrorKind::WouldBlock => Ok(0),
+            Err(e) => Err(e.into()),
+        }
+    }
+
+    /// PUT #handle: send the bytes of a value
+    pub fn send(&mut self, handle: i32, bytes: &[u8]) -> QResult<()> {
+        self.connection(handle)?.send(bytes);
+        Ok(())
+    }
+
+    /// GET #handle: exactly `len` bytes, or everything that has arrived
+    /// when `len` is None. None if fewer than `len` bytes are waiting.
+    pub fn receive(&mut self, handle: i32, len: Option<usize>) -> QResult<Option<Vec<u8>>> {
+        let connection = self.connection(handle)?;
+        connection.fill();
+        let len = len.unwrap_or(connection.pending.len());
+        connection.short_read = connection.pending.len() < len;
+        if connection.short_read {
+            return Ok(None);
+        }
+        Ok(Some(connection.pending.drain(..len).collect()))
+    }
+
+    /// EOF(handle): the last GET came up short
+    pub fn eof(&mut self, handle: i32) -> QResult<bool> {
+        Ok(self.connection(handle)?.short_read)
+    }
+
+    /// _CONNECTED(handle)
+    pub fn connected(&mut self, handle: i32) -> QResult<bool> {
+        match self.sockets.get_mut(&handle) {
+            Some(Socket::Host(_)) => Ok(true),
+            Some(Socket::Connection(connection)) => {
+                if connection.connected {
+                    connection.fill();
+                }
+                Ok(connection.connected)
+            }
+            None => Err(QError::runtime(QErrorCode::InvalidHandle, 0, 0)),
+        }
+    }
+
+    /// _CONNECTIONADDRESS$(handle): "TCP/IP:port:address" of the other
+    /// side, or of the local port for a host
+    pub fn address(&self, handle: i32) -> QResult<String> {
+        let (protocol, address) = match self.sockets.get(&handle) {
+            Some(Socket::Host(listener)) => (Protocol::Tcp, listener.local_addr().ok()),
+            Some(Socket::Connection(connection)) => {
+                let protocol = match connection.link {
+                    Link::Tcp(_) => Protocol::Tcp,
+                    Link::Udp { .. } => Protocol::Udp,
+                };
+                (protocol, connection.address())
+            }
+            None => return Err(QError::runtime(QErrorCode::InvalidHandle, 0, 0)),
+        };
+        Ok(match address {
+            Some(address) => format!("{}:{}:{}", protocol_name(protocol), address.port(), address.ip()),
+            None => String::new(),
+        })
+    }
+
+    /// CLOSE handle
+    pub fn close(&mut self, handle: i32) -> QResult<()> {
+        self.sockets
+            .remove(&handle)
+            .map(|_| ())
+            .ok_or_else(|| QError::runtime(QErrorCode::InvalidHandle, 0, 0))
+    }
+
+    pub fn close_all(&mut self) {
+        self.sockets.clear();
+    }
+}
+
+impl Default for NetTable {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::time::{Duration, Instant};
+
+    /// Retry a non-blocking call until it succeeds
+    fn wait_for<T>(mut attempt: impl FnMut() -> Option<T>) -> T {
+        let start = Instant::now();
+        loop {
+            if let Some(value) = attempt() {
+                return value;
+            }
+            assert!(start.elapsed() < Duration::from_secs(5), "timed out");
+            std::thread::sleep(Duration::from_millis(5));
+        }
+    }
+
+    #[test]
+    fn test_parse_address() {
+        assert_eq!(parse_address("tcp/ip:80:example.com").unwrap(), (Protocol::Tcp, 80, Some("example.com")));
+        assert_eq!(parse_address("UDP/IP:7000").unwrap(), (Protocol::Udp, 7000, None));
+        assert!(parse_address("HTTP:80").is_err());
+        assert!(parse_address("TCP/IP:port").is_err());
+    }
+
+    #[test]
+    fn test_tcp_round_trip() {
+        let mut table = NetTable::new();
+        let host = table.open_host("TCP/IP:0").unwrap();
+        assert!(host < 0);
+        let port = match &table.sockets[&host] {
+            Socket::Host(listener) => listener.local_addr().unwrap().port(),
+            Socket::Connection(_) => unreachable!(),
+        };
+        assert_eq!(table.accept(host).unwrap(), 0);
+
+        let client = table.open_client(&format!("TCP/IP:{}:127.0.0.1", port)).unwrap();
+        let server = wait_for(|| Some(table.accept(host).unwrap()).filter(|&h| h != 0));
+        assert!(table.address(client).unwrap().starts_with(&format!("TCP/IP:{}:", port)));
+
+        table.send(client, b"ping").unwrap();
+        let data = wait_for(|| table.receive(server, Some(4)).unwrap());
+        assert_eq!(data, b"ping");
+        assert_eq!(table.receive(server, Some(1)).unwrap(), None);
+        assert!(table.eof(server).unwrap());
+
+        table.close(client).unwrap();
+        wait_for(|| (!table.connected(server).unwrap()).then_some(()));
+        assert!(table.send(client, b"x").is_err());
+    }
+}
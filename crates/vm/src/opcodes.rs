@@ -1,5 +1,16 @@
+use crate::events::{TrapSource, TrapState};
+use crate::files::{Access, Lock};
+use crate::mem::MemField;
 use qb_core::data_types::QType;
+use qb_core::errors::{QError, QResult};
+use qb_hal::display::{Filter, FullScreen};
+use qb_hal::graphics::LineShape;
+use qb_hal::window::ResizeMode;
 use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+
+/// Start of a .qbc file, ending in the format version
+const QBC_MAGIC: &[u8; 4] = b"QBC\x05";
 
 /// Bytecode instructions for the QBasic VM
 #[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
@@ -18,6 +29,12 @@ pub enum OpCode {
     LoadField(String, String), // Load field from record (var, field)
     StoreField(String, String), // Store to field in record (var, field)
     DimArray(String, Vec<(i32, i32)>, String), // Create array with shape [(lo, hi), ...] and type
+    Erase(String),         // ERASE: free a dynamic array, clear a static one
+    ReDim(String, usize, String, bool), // Create a dynamic array of a type with the dimensions given, keeping its elements when PRESERVE (pops lo, hi of each)
+    ArrayFill(String),       // Pop a value and store it in every element
+    ArrayCopy(String, String), // Copy the second array's elements into the first
+    Bound(String, bool, bool), // LBOUND or UBOUND (upper) of an array, with the dimension on the stack if given
+    DimDict(String, QType),  // Create an empty _DICT whose values have the type of the QType
     
     // Arithmetic operations
     Add,                   // Pop two values, push sum
@@ -56,66 +73,174 @@ pub enum OpCode {
     JumpIfFalse(u32),      // Jump if top of stack is false
     Call(u32),             // Call subroutine
     Return,                // Return from subroutine
+    ReturnTo(u32),         // RETURN line: leave the GOSUB but jump to the address
+    OnJump(Vec<u32>, bool), // ON n GOTO, or GOSUB (true), the nth address; other n fall through
+
+    // Error trapping
+    OnError(Option<u32>),  // ON ERROR GOTO a handler, or GOTO 0 (None) to stop trapping
+    Resume(bool),          // RESUME the statement that failed, or RESUME NEXT (true) after it
+    ResumeAt(u32),         // RESUME label
+    Error,                 // ERROR n: raise the error code on the stack
+    Err,                   // ERR: code of the error being handled
+    Erl,                   // ERL: the numbered line it happened in
     
     // I/O operations
     Print(bool),           // Print with newline (true) or not
-    PrintComma,            // Print tab
+    PrintComma,            // Advance to the next 14-column print zone
+    PrintUsing(usize),     // PRINT USING: pops format and n values, pushes the text
     PrintSemicolon,        // Print nothing (continue on same line)
-    PrintHash(u8),         // Print to file
-    Input(String),         // Input with prompt
+    Write(bool),           // WRITE a value, then a comma or (true) the end of the line
+    SelectOutput,          // Send PRINT and WRITE output to file (pops fileno; 0 = screen)
+    SelectPrinter,         // Send PRINT output to the printer (LPRINT)
+    Input(String, bool, Vec<String>), // Input (prompt, stay on line, target variables)
     LineInput(String),     // Line input with prompt
-    InputHash(u8),         // Input from file
-    Open(String, String, u8), // Open file (filename, mode, fileno)
-    Close(u8),             // Close file
-    WriteHash(u8),         // Write to file
+    InputHash(String),     // Input a field from file into variable (pops fileno)
+    LineInputHash,         // Input a raw line from file (pops fileno)
+    Open(String, Option<Access>, Option<Lock>, bool), // Open file in mode with ACCESS, SHARED/LOCK and LEN = given (pops reclen, fileno, filename)
+    Close,                 // Close file (pops fileno; 0 closes all)
+    Kill,                  // KILL: pops the file name or pattern
+    Get(bool),             // GET #: pops the variable's value, [record], fileno; pushes the value read
+    Put(bool),             // PUT #: pops value, [record], fileno
+    GetBuffer(bool),       // GET # into the FIELD variables: pops [record], fileno
+    PutBuffer(bool),       // PUT # from the FIELD variables: pops [record], fileno
+    Field(Vec<String>),    // FIELD #: pops a width per variable, fileno
+    Justify(bool),         // LSET (false) or RSET: pops value, target's value; pushes value fitted to the target
+    LoadRecord(Vec<String>), // Push the fields' values packed into one record image
+    StoreRecord(Vec<String>), // Pop a record image and unpack it into the fields
+    Eof,                   // EOF(n)
+    Lof,                   // LOF(n)
+    Loc,                   // LOC(n)
+    FreeFile,              // FREEFILE
+    Fre,                   // FRE: pops a string, or -1/-2 or another number
     
     // Graphics operations
-    Screen(u8),            // Set screen mode
-    PSet,                  // Set pixel
+    Screen,                // SCREEN: pops a mode number or image handle
+    PSet(bool),            // PSET: pops [color], y, x
     PReset,                // Reset pixel
-    Line,                  // Draw line
-    Circle,                // Draw circle
+    Line(bool, bool, bool, LineShape, bool), // LINE: STEP on each point, color given, shape, style given; pops [style], [color], y2, x2, y1, x1
+    Circle(bool, bool, bool, bool, bool), // CIRCLE: STEP, then color, start, end and aspect given; pops those given, radius, y, x
+    Draw,                  // DRAW: pops the command string
+    VarPtrString(String),  // VARPTR$: pushes a reference to the variable for DRAW and PLAY
     Cls,                   // Clear screen
-    Color,                 // Set color
+    Color(bool, bool, bool), // COLOR: pops the foreground, background and border given
     Locate,                // Position cursor
+    Display,               // _DISPLAY: show the frame drawn so far
+    AutoDisplay,           // _AUTODISPLAY
     
     // QB64 Graphics extensions
-    RGB(u8, u8, u8),       // Create RGB color
-    RGBA(u8, u8, u8, u8),  // Create RGBA color
-    NewImage(i32, i32, u8), // Create new image buffer
-    LoadImage(String),     // Load image from file
-    PutImage,              // Draw image to screen
+    RGB(bool),             // _RGB: pops [handle], b, g, r; pushes the color for that image
+    RGBA(bool),            // _RGBA: pops [handle], a, b, g, r
+    RGB32(u8),             // _RGB32/_RGBA32: pops 1-4 arguments; pushes ARGB
+    Channel(u8, bool),     // _RED/_GREEN/_BLUE/_ALPHA: bit shift, has handle
+    Channel32(u8),         // _RED32/_GREEN32/_BLUE32/_ALPHA32: bit shift
+    PrintString(bool),     // _PRINTSTRING: pops [handle], text, y, x
+    PrintWidth(bool),      // _PRINTWIDTH: pops [handle], text
+    NewImage,              // _NEWIMAGE: pops mode, height, width; pushes handle
+    LoadImage,             // _LOADIMAGE: pops mode, filename; pushes handle
+    PutImage(u8, u8),      // _PUTIMAGE: corners given for the dest and source areas
+    FreeImage,             // _FREEIMAGE: pops handle
+    SaveImage(bool),       // _SAVEIMAGE: pops [handle], filename
+    CopyImage,             // _COPYIMAGE: pops handle; pushes new handle
+    ImageSize(bool, bool), // _WIDTH (false) or _HEIGHT, of the handle popped if given (true) or the _DEST
+    SetDest,               // _DEST statement: pops handle
+    Dest,                  // _DEST function
+    SetSource,             // _SOURCE statement: pops handle
+    Source,                // _SOURCE function
+    
+    // QB64 memory blocks
+    MemNew,                // _MEMNEW: pops size; pushes block handle
+    MemImage,              // _MEMIMAGE: pops image handle; pushes block handle
+    MemGet,                // _MEMGET: pops a value of the type to read, address, block
+    MemPut(Option<QType>), // _MEMPUT: pops value, address, block; converts to the type given
+    MemFree,               // _MEMFREE: pops block
+    MemField(MemField),    // m.OFFSET etc.: pops block
     
     // QB64 Sound extensions
-    SndOpen(String),       // Open sound file
-    SndClose(i32),         // Close sound handle
-    SndPlay(i32),          // Play sound
-    SndStop(i32),          // Stop sound
-    SndLoop(i32),          // Loop sound
-    SndVolume(i32, f32),   // Set sound volume
+    SndOpen(bool),         // _SNDOPEN: pops [requirements], filename; pushes handle
+    SndPlay(bool),         // _SNDPLAY, or _SNDLOOP (true): pops handle
+    SndStop,               // _SNDSTOP: pops handle
+    SndClose,              // _SNDCLOSE: pops handle
+    SndVolume,             // _SNDVOL: pops volume, handle
+    SndPlaying,            // _SNDPLAYING: pops handle; pushes -1 while it plays
+    SndRaw(bool),          // _SNDRAW: pops [right], left
+    SndRate,               // _SNDRATE: pushes samples per second
+    SndRawLen,             // _SNDRAWLEN: pushes seconds of _SNDRAW samples left to play
     
     // Sound operations
     Beep,                  // Beep
     Sound,                 // Sound frequency, duration
-    Play,                  // Play music string
+    Play(u8),              // PLAY: pops that many voice strings, the first deepest
+    PlayCount,             // PLAY(n): notes left in the background queue
+    Sleep(bool),           // SLEEP (true: pops seconds; else waits for a key)
+    Limit,                 // _LIMIT: pops passes per second
+
+    // QB64 window
+    Title,                 // _TITLE (pops text)
+    ScreenMove(bool),      // _SCREENMOVE (true: pops x, y; false: _MIDDLE)
+    Resize(bool, ResizeMode), // _RESIZE ON/OFF
+    FullScreen(FullScreen, Filter), // _FULLSCREEN: whether the window fills the display, and how it is scaled
+    // QB64 networking
+    OpenHost,              // _OPENHOST: pops address; pushes handle or 0
+    OpenClient,            // _OPENCLIENT: pops address; pushes handle or 0
+    OpenConnection,        // _OPENCONNECTION: pops host handle; pushes handle or 0
+    Connected,             // _CONNECTED(handle)
+    ConnectionAddress,     // _CONNECTIONADDRESS$(handle)
+
+    // Reflection
+    SourceLine(bool),      // _SOURCELINE, or _SOURCELINE$ (true) as "program:line"
+    CallDepth,             // _CALLDEPTH: GOSUBs and procedure calls under way
+    ProgramName,           // _PROGRAMNAME$
+
+    ConsoleOpen(bool),     // $CONSOLE (true: :ONLY, PRINT starts on the console)
+    ConsoleVisible(bool),  // _CONSOLE ON/OFF
+    Console,               // _CONSOLE function: console handle, 0 without $CONSOLE
+    ResizeEvent,           // _RESIZE function
+    FullScreenMode,        // _FULLSCREEN function: pushes 0 (windowed), 1 (_STRETCH) or 2 (_SQUAREPIXELS)
+    ResizeWidth,           // _RESIZEWIDTH
+    ResizeHeight,          // _RESIZEHEIGHT
+
+    // Joystick
+    Stick,                 // STICK(n)
+    Strig,                 // STRIG(n)
+
+    // QB64 input devices
+    Devices,               // _DEVICES: pushes the number of devices
+    DeviceName,            // _DEVICE$: pops device number
+    DeviceInput(bool),     // _DEVICEINPUT (true: pops device number; pushes -1 on new input, else pushes the device with input)
+    Button,                // _BUTTON: pops button number; pushes -1 while it is down
+    Axis,                  // _AXIS: pops axis number; pushes its position from -1 to 1
+    LastButton,            // _LASTBUTTON: pops device number
+    LastAxis,              // _LASTAXIS: pops device number
+
+    // Event trapping
+    OnEvent(TrapSource, u32),            // ON <event>(n) GOSUB (pops n)
+    EventControl(TrapSource, TrapState, bool), // <event>(n) ON/OFF/STOP (pops n if true)
     
     // Memory operations
     Peek,                  // Peek from memory
     Poke,                  // Poke to memory
-    DefSeg(u16),           // Define segment
+    DefSeg(bool),          // DEF SEG (true: segment on stack)
     
     // String operations
     Concat,                // String concatenation
     Left,                  // Left$(string, count)
     Right,                 // Right$(string, count)
-    Mid,                   // Mid$(string, start, length)
+    Mid(bool),             // Mid$(string, start[, length])
     Len,                   // Len(string)
-    Asc,                   // Asc(char)
+    Asc(bool),             // Asc(string[, position])
+    InStr(bool),           // INSTR([start,] haystack, needle)
+    InStrRev(bool),        // _INSTRREV([start,] haystack, needle)
     Chr,                   // Chr$(code)
     Str,                   // Str$(number)
     Val,                   // Val(string)
     UCase,                 // UCase$(string)
     LCase,                 // LCase$(string)
+    Space,                 // Space$(count)
+    StringFill,            // String$(count, code or string)
+    LTrim,                 // LTrim$(string)
+    RTrim,                 // RTrim$(string)
+    MkString(QType),       // MKI$, MKL$, MKS$, MKD$(number): the type to pack as
+    CvNumber(QType),       // CVI, CVL, CVS, CVD(string): the type to unpack
     // Type conversion
     CInt,                  // Convert to integer
     CLng,                  // Convert to long
@@ -132,36 +257,94 @@ pub enum OpCode {
     IntOp,                 // Int
     Log,
     Rnd,
+    Randomize(bool),       // Reseed RND (true: seed on stack, false: prompt)
+    Timer(bool),           // TIMER: pops [accuracy]; pushes seconds since midnight
+    Timer64,               // _TIMER64: seconds since midnight to the microsecond, as a DOUBLE
+    Date,                  // DATE$: the date as mm-dd-yyyy
+    Time,                  // TIME$: the time of day as hh:mm:ss
+    InKey,                 // Next key from the keyboard buffer, or ""
+    Command(bool),         // COMMAND$ (true: argument number on stack)
     Sgn,
     Sin,
     Sqr,
     Tan,
     
     // Function/Subroutine
-    PushRet(u32),          // Push return address
-    PopRet,                // Pop return address
-    EnterScope,            // Enter new scope
-    ExitScope,             // Exit scope
+    CallProc(u32, Vec<ArgPass>), // Call a SUB or FUNCTION, passing its arguments as listed
+    EnterProc(Box<ProcEntry>),   // Start a SUB or FUNCTION: bind its parameters in a new frame
+    LeaveProc,                   // Return from a SUB or FUNCTION (a FUNCTION pushes its result)
     
     // Data operations
     Read,                  // Read from DATA
     Restore(u32),          // Restore DATA pointer
     
     // Program control
-    End,                   // End program
+    End(bool),             // End program (true: exit code on stack)
     Stop,                  // Stop execution
+    Clear(bool),           // CLEAR: reset variables and close files (true: stack size on stack)
+    Chain,                 // CHAIN: pops the file name; ends the program, passing on its COMMON variables
+    Common(u32),           // COMMON: take the value CHAIN passed on for the common item at this index
     
     // Special
     Nop,                   // No operation
     Halt,                  // Halt execution
 }
 
-/// Compiled bytecode chunk
+/// How CallProc passes one argument
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub enum ArgPass {
+    /// BYVAL or an expression: a copy of the value on the stack
+    Value,
+    /// BYREF: the variable itself
+    Var(String),
+    /// BYREF: an array element, its subscripts on the stack
+    Element(String, usize),
+    /// A whole array, to an array parameter
+    Array(String),
+}
+
+/// What a SUB or FUNCTION sets up on entry
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct ProcEntry {
+    pub name: String,
+    /// Parameter names, in order
+    pub params: Vec<String>,
+    /// A FUNCTION's result variable, named for it, and its initial value
+    pub result: Option<(String, QType)>,
+    /// Module-level variables the body sees: DIM SHARED, CONST and those
+    /// its SHARED statements name
+    pub shared: Vec<String>,
+    /// STATIC variables, with their initial values, which keep their values
+    /// from one call to the next
+    pub statics: Vec<(String, QType)>,
+    /// SUB ... STATIC: every local variable keeps its value
+    pub all_static: bool,
+}
+
+/// A variable of the COMMON block, which CHAIN passes on by its place in
+/// the block, not its name
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub enum CommonItem {
+    /// A variable or record field, and its initial value, whose type a
+    /// value passed on is converted to
+    Variable(String, QType),
+    Array(String),
+}
+
+/// Compiled bytecode chunk. A program's DATA travels with it: a chained
+/// program READs its own DATA from the first item, and RESTORE names its
+/// own labels.
 #[derive(Debug, Clone, Default, Serialize, Deserialize)]
 pub struct ByteCode {
     pub instructions: Vec<OpCode>,
     pub constants: Vec<QType>,
     pub data_items: Vec<QType>, // DATA statements
+    pub data_labels: BTreeMap<String, u32>, // label or line number -> index of the next DATA item
+    pub lines: Vec<(usize, usize)>, // (first instruction, source line), in order
+    pub line_numbers: Vec<(usize, u32)>, // (first instruction, BASIC line number), in order
+    pub const_names: Vec<String>, // CONST variables, which CLEAR leaves alone
+    pub common: Vec<CommonItem>, // COMMON variables, in order, which CHAIN passes on
+    pub implicit_arrays: BTreeMap<String, (usize, String)>, // Arrays used without a DIM: (dimensions, type), each 0 TO 10
 }
 
 impl ByteCode {
@@ -187,6 +370,57 @@ impl ByteCode {
         self.data_items.push(value);
     }
 
+    /// Where RESTORE `label` starts reading, as an index into `data_items`
+    pub fn data_index(&self, label: &str) -> Option<u32> {
+        self.data_labels.get(&label.to_uppercase()).copied()
+    }
+
+    /// The program as a .qbc file
+    pub fn to_qbc(&self) -> QResult<Vec<u8>> {
+        let mut bytes = QBC_MAGIC.to_vec();
+        bincode::serialize_into(&mut bytes, self).map_err(|e| QError::system(e.to_string()))?;
+        Ok(bytes)
+    }
+
+    /// A program read back from a .qbc file
+    pub fn from_qbc(bytes: &[u8]) -> QResult<Self> {
+        let body = bytes
+            .strip_prefix(QBC_MAGIC)
+            .ok_or_else(|| QError::system("Not a .qbc file of this version"))?;
+        bincode::deserialize(body).map_err(|e| QError::system(e.to_string()))
+    }
+
+    /// Record that the instructions emitted from here on come from `line`
+    pub fn mark_line(&mut self, line: usize) {
+        let start = self.instructions.len();
+        match self.lines.last_mut() {
+            Some(last) if last.0 == start => last.1 = line,
+            _ => self.lines.push((start, line)),
+        }
+    }
+
+    /// Source line of the instruction at `index`, if known
+    pub fn line_at(&self, index: usize) -> Option<usize> {
+        let after = self.lines.partition_point(|&(start, _)| start <= index);
+        after.checked_sub(1).map(|i| self.lines[i].1)
+    }
+
+    /// Number of the numbered line the instruction at `index` falls in,
+    /// or 0 when no numbered line comes before it
+    pub fn line_number_at(&self, index: usize) -> u32 {
+        let after = self.line_numbers.partition_point(|&(start, _)| start <= index);
+        after.checked_sub(1).map_or(0, |i| self.line_numbers[i].1)
+    }
+
+    /// Line an error raised at `index` is reported in: the number ERL gives
+    /// when the program numbers its lines, else the source line
+    pub fn error_line(&self, index: usize) -> usize {
+        match self.line_number_at(index) {
+            0 => self.line_at(index).unwrap_or(0),
+            number => number as usize,
+        }
+    }
+
     pub fn len(&self) -> usize {
         self.instructions.len()
     }
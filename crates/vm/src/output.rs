@@ -0,0 +1,104 @@
+//! Where PRINT, WRITE and LPRINT send their text. The statements differ
+//! only in how they format values; each writes to the sink selected for
+//! it, and the sink keeps the column that PRINT's comma zones count from.
+
+use qb_core::data_types::QType;
+use qb_core::errors::{QError, QErrorCode, QResult};
+use std::io::Write;
+
+/// Destination of PRINT and WRITE output
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum Sink {
+    #[default]
+    Screen,
+    /// A file opened for output, by file number (PRINT #, WRITE #)
+    File(i32),
+    /// The line printer (LPRINT)
+    Printer,
+}
+
+/// The printer LPRINT writes to. None is attached until the host
+/// attaches one, and printing then fails with "Device unavailable".
+#[derive(Default)]
+pub struct Printer {
+    out: Option<Box<dyn Write>>,
+    column: usize,
+}
+
+impl Printer {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn attach(&mut self, out: Box<dyn Write>) {
+        self.out = Some(out);
+        self.column = 0;
+    }
+
+    pub fn column(&self) -> usize {
+        self.column
+    }
+
+    pub fn write_str(&mut self, text: &str) -> QResult<()> {
+        let out = self.out.as_mut().ok_or_else(|| QError::runtime(QErrorCode::DeviceUnavailable, 0, 0))?;
+        out.write_all(text.as_bytes())?;
+        match text.rfind('\n') {
+            Some(pos) => {
+                out.flush()?;
+                self.column = text[pos + 1..].chars().count();
+            }
+            None => self.column += text.chars().count(),
+        }
+        Ok(())
+    }
+}
+
+/// A value as PRINT shows it: numbers with a space or minus sign before
+/// them and a space after, strings as they are
+pub fn print_form(value: &QType) -> String {
+    match value.str_value() {
+        Ok(number) => number + " ",
+        Err(_) => value.to_string(),
+    }
+}
+
+/// A value as WRITE shows it: strings in double quotes, numbers without
+/// the space PRINT leaves for a sign
+pub fn write_form(value: &QType) -> String {
+    if value.is_string() {
+        format!("\"{}\"", value)
+    } else {
+        value.to_string()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_print_form() {
+        assert_eq!(print_form(&QType::Integer(3)), " 3 ");
+        assert_eq!(print_form(&QType::Long(-25)), "-25 ");
+        assert_eq!(print_form(&QType::Single(0.5)), " .5 ");
+        assert_eq!(print_form(&QType::String("a".into())), "a");
+    }
+
+    #[test]
+    fn test_write_form() {
+        assert_eq!(write_form(&QType::String("a b".into())), "\"a b\"");
+        assert_eq!(write_form(&QType::Integer(-3)), "-3");
+        assert_eq!(write_form(&QType::Single(0.5)), ".5");
+    }
+
+    #[test]
+    fn test_printer() {
+        let mut printer = Printer::new();
+        assert!(printer.write_str("x").is_err());
+        printer.attach(Box::new(std::io::sink()));
+        printer.write_str("ab").unwrap();
+        assert_eq!(printer.column(), 2);
+        printer.write_str("\nc").unwrap();
+        assert_eq!(printer.column(), 1);
+    }
+}
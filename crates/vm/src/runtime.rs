@@ -1,8 +1,157 @@
-use crate::opcodes::{ByteCode, OpCode};
-use qb_core::data_types::QType;
+use crate::address_space::AddressSpace;
+use crate::dict::{self, Dict};
+use crate::opcodes::{ArgPass, ByteCode, CommonItem, OpCode};
+use crate::events::{EventTraps, TrapSource, TrapState};
+use crate::files::{FileTable, OpenClauses, OpenMode};
+use crate::frames::{static_key, Binding, Frame, Ref};
+use crate::http;
+use crate::mem::MemTable;
+use crate::net::NetTable;
+use crate::output::{self, Printer, Sink};
+use crate::sandbox::Sandbox;
+use crate::vfs::{self, Vfs};
+use crate::session::{ConsoleInput, Session};
+use crate::strings;
+use crate::using;
+use crate::watch::{Watch, WatchHit};
+use crate::random::QbRandom;
+use qb_core::data_types::{Arithmetic, QType, TypeSuffix};
+use qb_core::dialect::Dialect;
+use qb_core::locale::NumberFormat;
 use qb_core::errors::{QError, QErrorCode, QResult};
-use std::collections::HashMap;
-use std::io::{self, Write};
+use qb_hal::display::FullScreen;
+use qb_hal::mixer::SAMPLE_RATE;
+use qb_hal::graphics::{Area, CONSOLE_HANDLE, TEXT_SIZE};
+use qb_hal::palette::rgba32;
+use qb_hal::window::WindowPosition;
+use qb_hal::{break_key, keyboard, AudioSink, Clock, DisplayOptions, FramebufferWindow, Graphics, Joysticks, Keymap, Limiter, ScreenRecorder, SoundSynth, TerminalDisplay, TerminalGraphics, Turtle, Window};
+use std::collections::{HashMap, HashSet};
+use std::io::{self, IsTerminal, Write};
+use std::path::{Path, PathBuf};
+use std::time::{Duration, Instant};
+
+/// KEY(n) number that Ctrl+Break raises
+const BREAK_KEY: i32 = 15;
+
+/// How far one instruction moves a virtual clock
+const INSTRUCTION_TIME: Duration = Duration::from_micros(1);
+
+/// Instructions between offers of a new frame to the terminal display
+const FRAME_CHECK_INTERVAL: u32 = 4096;
+
+/// How deeply X can run DRAW substrings within substrings
+const MACRO_NESTING: usize = 16;
+
+/// Memory a compiled QuickBASIC program had on a 640K machine, which FRE
+/// reports against: near memory for variables and strings, the far heap
+/// for arrays, and the default stack that CLEAR , , n can resize
+const NEAR_MEMORY: usize = 61_440;
+const FAR_HEAP: usize = 524_288;
+const DEFAULT_STACK: usize = 2_048;
+
+/// Width of the print zones a comma in PRINT advances to
+const PRINT_ZONE_WIDTH: usize = 14;
+
+/// Borrowed view of an array: (lower, upper) per dimension and row-major elements
+pub struct ArrayView<'a> {
+    pub bounds: &'a [(i32, i32)],
+    pub elements: &'a [QType],
+}
+
+impl ArrayView<'_> {
+    /// Number of dimensions
+    pub fn rank(&self) -> usize {
+        self.bounds.len()
+    }
+
+    /// Type of the elements, as DIM names it
+    pub fn element_type(&self) -> &'static str {
+        self.elements.first().map_or("", QType::type_name)
+    }
+
+    /// Position in `elements` of the element with these subscripts
+    pub fn offset(&self, subscripts: &[i32]) -> Option<usize> {
+        flat_index(self.bounds, subscripts)
+    }
+
+    /// Subscripts of the element at `offset` in `elements`
+    pub fn subscripts(&self, offset: usize) -> Vec<i32> {
+        // Row-major: the last subscript varies fastest
+        let mut rest = offset;
+        let mut subscripts = vec![0; self.bounds.len()];
+        for (dim, &(lo, hi)) in self.bounds.iter().enumerate().rev() {
+            let size = (hi - lo + 1) as usize;
+            subscripts[dim] = lo + (rest % size) as i32;
+            rest /= size;
+        }
+        subscripts
+    }
+
+    pub fn get(&self, subscripts: &[i32]) -> Option<&QType> {
+        self.elements.get(self.offset(subscripts)?)
+    }
+
+    /// The elements as numbers, for plotting and comparing results; None
+    /// for an array of strings or records
+    pub fn to_f64(&self) -> Option<Vec<f64>> {
+        self.elements.iter().map(|value| value.to_double().ok()).collect()
+    }
+}
+
+/// Initial value of a variable: zero or "" as its suffix says
+fn blank_value(name: &str) -> QType {
+    TypeSuffix::of_name(name).map_or(QType::Single(0.0), |suffix| suffix.default_value())
+}
+
+/// Row-major position of the element with these subscripts, or None when
+/// their number or range does not fit the bounds
+fn flat_index(bounds: &[(i32, i32)], subscripts: &[i32]) -> Option<usize> {
+    if subscripts.len() != bounds.len() {
+        return None;
+    }
+    let mut flat = 0usize;
+    for (&index, &(lo, hi)) in subscripts.iter().zip(bounds) {
+        if index < lo || index > hi {
+            return None;
+        }
+        flat = flat * (hi - lo + 1) as usize + (index - lo) as usize;
+    }
+    Some(flat)
+}
+
+/// The value each element of a new array of `type_name` starts as
+fn array_blank(type_name: &str) -> QType {
+    match type_name {
+        "INTEGER" => QType::Integer(0),
+        "LONG" => QType::Long(0),
+        "SINGLE" => QType::Single(0.0),
+        "DOUBLE" => QType::Double(0.0),
+        "STRING" => QType::String(String::new()),
+        "_INTEGER64" => QType::Integer64(0),
+        "_UNSIGNED INTEGER" => QType::UnsignedInteger(0),
+        "_UNSIGNED LONG" => QType::UnsignedLong(0),
+        "_UNSIGNED _INTEGER64" => QType::UnsignedInteger64(0),
+        _ => QType::Single(0.0),
+    }
+}
+
+/// The value of a COMMON variable CHAIN passes on
+#[derive(Debug)]
+enum Passed {
+    Value(QType),
+    /// Elements, bounds, and whether the array is dynamic
+    Array(Vec<QType>, Vec<(i32, i32)>, bool),
+}
+
+/// Why `resume` returned before the program ended
+#[derive(Debug, Clone)]
+pub enum Pause {
+    /// A watched variable was written
+    Watch(WatchHit),
+    /// Ctrl+Break was pressed before the instruction at this line, and no
+    /// ON KEY(15) trap took it; 0 if the line is unknown
+    Break { line: usize },
+}
 
 /// Virtual Machine for executing QBasic bytecode
 pub struct VirtualMachine {
@@ -13,11 +162,14 @@ pub struct VirtualMachine {
     
     // Variable storage
     global_variables: HashMap<String, QType>,
-    local_scopes: Vec<HashMap<String, QType>>,
+    frames: Vec<Frame>,
     
     // Arrays storage
     arrays: HashMap<String, Vec<QType>>,
     array_shapes: HashMap<String, Vec<(i32, i32)>>, // (lower, upper) for each dimension
+    /// Arrays REDIM or $DYNAMIC allocated, which ERASE frees
+    dynamic_arrays: HashSet<String>,
+    dicts: HashMap<String, Dict>,
     
     // User-defined type (TYPE...END TYPE) storage: variable -> field -> value
     udt_fields: HashMap<String, HashMap<String, QType>>,
@@ -27,11 +179,118 @@ pub struct VirtualMachine {
     
     // Program state
     running: bool,
+    // Stack size set by CLEAR, for FRE(-2)
+    stack_size: usize,
+    // Reading a variable never assigned is an error rather than 0
+    strict: bool,
+    dialect: Dialect,
+    // Written in place of the period by PRINT and STR$, and read by VAL
+    decimal_separator: char,
+    arithmetic: Arithmetic,
+    // ON ERROR GOTO handler and the number of frames open when it was
+    // set, and the error it is handling with ERR and the instruction that
+    // raised it
     error_handler: Option<u32>,
+    handler_depth: usize,
+    /// The calls a handler set before them runs over, until RESUME goes
+    /// back into them
+    suspended_frames: Vec<Frame>,
     current_error: Option<QError>,
+    error_number: i32,
+    error_address: usize,
     
     // Screen mode for graphics
     screen_mode: u8,
+
+    // Console cursor column (0-based) for prompts and print zones
+    cursor_column: usize,
+
+    // Files opened with OPEN, by file number
+    files: FileTable,
+    // Variables FIELD laid over each file's record buffer, with their widths
+    field_vars: HashMap<i32, Vec<(String, usize)>>,
+    // Where PRINT and WRITE output goes: the screen, or a file or the
+    // printer during PRINT #, WRITE # and LPRINT
+    output: Sink,
+    printer: Printer,
+
+    // _MEM blocks, by handle
+    mem: MemTable,
+    // Memory seen by PEEK and POKE, and the DEF SEG segment
+    memory: AddressSpace,
+    // How terminal input becomes keys in the keyboard buffer
+    keymap: Keymap,
+
+    // TCP/IP and UDP/IP hosts and connections, by handle
+    net: NetTable,
+    // What the program may reach outside the VM
+    sandbox: Sandbox,
+    // Where the DOS paths the program names are on the host
+    vfs: Vfs,
+
+    // RND generator state
+    random: QbRandom,
+
+    // Time as TIMER, SOUND, _LIMIT and ON TIMER see it
+    clock: Clock,
+    limiter: Limiter,
+    // PC speaker note queue for SOUND and PLAY
+    sound: SoundSynth,
+
+    // Screen image for graphics modes
+    graphics: Graphics,
+    // DRAW's angle, scale and color, kept between DRAW statements
+    turtle: Turtle,
+    // Shows the screen in a window, opened at the first graphics frame when
+    // a size is set for it; the terminal shows it when none can be opened
+    screen_window: Option<FramebufferWindow>,
+    window_scale: Option<f32>,
+    full_screen: FullScreen,
+    terminal: TerminalDisplay,
+    // Every frame shown, when the screen is being recorded
+    screen_recorder: Option<ScreenRecorder>,
+    instructions_since_frame: u32,
+
+    // $CONSOLE: the terminal is available as _CONSOLE, shown or hidden by
+    // _CONSOLE ON/OFF, and may be the _DEST or _SOURCE
+    console: bool,
+    console_visible: bool,
+    console_dest: bool,
+    console_source: bool,
+
+    // Window title, position and resize state
+    window: Window,
+
+    // Game port for STICK and STRIG
+    joysticks: Joysticks,
+
+    // ON <event> GOSUB traps, checked between instructions
+    traps: EventTraps,
+    instructions_since_poll: u32,
+    // ON TIMER(n): the interval and the clock time it next fires at
+    timer: Option<(Duration, Duration)>,
+
+    // Watchpoints, and the write that tripped one during the last instruction
+    watches: Vec<Watch>,
+    watch_hit: Option<WatchHit>,
+
+    // Times each instruction has run, when coverage is on
+    instruction_counts: Option<Vec<u64>>,
+
+    // Source of INPUT and LINE INPUT lines: stdin, recorded or replayed
+    console_input: ConsoleInput,
+
+    // Where console output goes, when not to standard output
+    console_out: Option<Box<dyn Write>>,
+
+    // Program file and its arguments, for COMMAND$
+    command_line: Vec<String>,
+    // Exit status set by END or SYSTEM with a code
+    exit_code: i32,
+    // The program CHAIN asked to run next, and the COMMON values it passed
+    // on, by their place in the block, until a COMMON statement takes them
+    chain: Option<PathBuf>,
+    passed_common: Vec<Option<Passed>>,
 }
 
 impl VirtualMachine {
@@ -41,38 +300,497 @@ impl VirtualMachine {
             call_stack: Vec::with_capacity(256),
             instruction_pointer: 0,
             global_variables: HashMap::new(),
-            local_scopes: Vec::new(),
+            frames: Vec::new(),
             arrays: HashMap::new(),
             array_shapes: HashMap::new(),
+            dynamic_arrays: HashSet::new(),
+            dicts: HashMap::new(),
             udt_fields: HashMap::new(),
             data_pointer: 0,
             running: false,
+            stack_size: DEFAULT_STACK,
+            strict: false,
+            dialect: Dialect::default(),
+            decimal_separator: '.',
+            arithmetic: Arithmetic::default(),
             error_handler: None,
+            handler_depth: 0,
+            suspended_frames: Vec::new(),
             current_error: None,
+            error_number: 0,
+            error_address: 0,
             screen_mode: 0,
+            cursor_column: 0,
+            files: FileTable::new(),
+            field_vars: HashMap::new(),
+            output: Sink::Screen,
+            printer: Printer::new(),
+            mem: MemTable::new(),
+            memory: AddressSpace::new(),
+            keymap: Keymap::new(),
+            net: NetTable::new(),
+            sandbox: Sandbox::default(),
+            vfs: Vfs::new(),
+            random: QbRandom::new(),
+            clock: Clock::real(),
+            limiter: Limiter::new(),
+            sound: SoundSynth::new(),
+            graphics: Graphics::new(),
+            turtle: Turtle::new(),
+            screen_window: None,
+            window_scale: None,
+            full_screen: FullScreen::Off,
+            terminal: TerminalDisplay::new(TerminalGraphics::Off),
+            screen_recorder: None,
+            instructions_since_frame: 0,
+            console: false,
+            console_visible: true,
+            console_dest: false,
+            console_source: false,
+            window: Window::new(),
+            joysticks: Joysticks::new(),
+            traps: EventTraps::new(),
+            instructions_since_poll: 0,
+            timer: None,
+            watches: Vec::new(),
+            watch_hit: None,
+            instruction_counts: None,
+            console_input: ConsoleInput::Live,
+            console_out: None,
+            command_line: Vec::new(),
+            exit_code: 0,
+            chain: None,
+            passed_common: Vec::new(),
+        }
+    }
+
+    /// Grant the program the capabilities of a sandbox policy
+    pub fn set_sandbox(&mut self, sandbox: Sandbox) {
+        self.sandbox = sandbox;
+    }
+
+    /// Map the drive letters of the program's DOS paths onto host
+    /// directories
+    pub fn set_vfs(&mut self, vfs: Vfs) {
+        self.vfs = vfs;
+    }
+
+    /// In strict mode, reading a variable that was never assigned or DIMmed
+    /// raises "Variable not defined" instead of giving 0, to catch typos
+    pub fn set_strict(&mut self, strict: bool) {
+        self.strict = strict;
+    }
+
+    /// The BASIC the program is written for, which sets limits such as
+    /// the range of CHR$
+    /// Draw graphics screens in the terminal as they change
+    pub fn set_terminal_graphics(&mut self, mode: TerminalGraphics) {
+        let options = self.terminal.options();
+        self.terminal = TerminalDisplay::new(mode);
+        self.terminal.set_options(options);
+    }
+
+    /// Scaling, aspect correction and color accuracy of the screen as shown
+    pub fn set_display_options(&mut self, options: DisplayOptions) {
+        self.terminal.set_options(options);
+        if let Some(window) = &mut self.screen_window {
+            window.set_options(options);
+        }
+    }
+
+    /// Show graphics screens in a window `scale` times their size, where
+    /// one can be opened
+    pub fn enable_window(&mut self, scale: f32) {
+        self.window_scale = Some(scale);
+    }
+
+    /// Keep the graphics window on show after the program ends, until it
+    /// is closed or a key is pressed in it
+    pub fn hold_window(&mut self) {
+        while let Some(window) = &mut self.screen_window {
+            if !window.is_open() || !window.take_keys().is_empty() {
+                break;
+            }
+            if let Some(frame) = self.graphics.frame() {
+                window.present(frame, false);
+            }
+            std::thread::sleep(Duration::from_millis(16));
+        }
+    }
+
+    pub fn set_dialect(&mut self, dialect: Dialect) {
+        self.dialect = dialect;
+    }
+
+    /// Let INTEGER and LONG results that overflow be given wider, or not
+    pub fn set_arithmetic(&mut self, arithmetic: Arithmetic) {
+        self.arithmetic = arithmetic;
+    }
+
+    /// Write and read numbers with the decimal separator of `format`
+    pub fn set_number_format(&mut self, format: NumberFormat) {
+        self.decimal_separator = format.decimal_separator();
+    }
+
+    /// `text`, a number as QBasic writes it, with the decimal separator set
+    fn localized(&self, text: String) -> String {
+        match self.decimal_separator {
+            '.' => text,
+            separator => text.replace('.', &separator.to_string()),
         }
     }
 
+    /// Keep time by `clock` rather than the system clock, e.g. a virtual
+    /// one so that a test runs the same way every time
+    pub fn set_clock(&mut self, clock: Clock) {
+        self.sound = SoundSynth::with_clock(clock.clone());
+        self.memory.clock = clock.clone();
+        self.clock = clock;
+    }
+
+    /// Attach the printer LPRINT writes to
+    pub fn set_printer(&mut self, printer: Box<dyn Write>) {
+        self.printer.attach(printer);
+    }
+
+    /// Bind terminal input to keys differently from the VT100 defaults
+    pub fn set_keymap(&mut self, keymap: Keymap) {
+        self.keymap = keymap;
+    }
+
+    /// Pause `resume` whenever a matching variable or array element is written
+    pub fn add_watch(&mut self, watch: Watch) {
+        self.watches.push(watch);
+    }
+
+    /// Run the program to the end; Ctrl+Break ends it with `QError::Break`
     pub fn execute(&mut self, bytecode: &ByteCode) -> QResult<()> {
+        self.start();
+        while let Some(pause) = self.resume(bytecode)? {
+            if let Pause::Break { line } = pause {
+                return Err(QError::Break { line });
+            }
+        }
+        Ok(())
+    }
+
+    /// Send console output, the text PRINT shows in text modes and the
+    /// prompts of INPUT, to `out` instead of standard output
+    pub fn set_console_output(&mut self, out: Box<dyn Write>) {
+        self.console_out = Some(out);
+    }
+
+    /// Take console input from stdin, a recording of stdin, or a replay
+    pub fn set_console_input(&mut self, input: ConsoleInput) {
+        self.console_input = input;
+    }
+
+    /// Send the program's sound, mixed, to `sink` from now on
+    pub fn set_sound_output(&mut self, sink: Box<dyn AudioSink>) {
+        self.sound.set_output(sink);
+    }
+
+    /// Mix the sound still queued when the program ends to the sound output,
+    /// and close it
+    pub fn finish_sound(&mut self) -> io::Result<()> {
+        self.sound.finish_output()
+    }
+
+    /// Keep every frame the program shows, to save as an animation
+    pub fn record_screen(&mut self) {
+        self.screen_recorder = Some(ScreenRecorder::new());
+    }
+
+    /// The frames recorded so far, when recording the screen
+    pub fn screen_recording(&self) -> Option<&ScreenRecorder> {
+        self.screen_recorder.as_ref()
+    }
+
+    /// Console input recorded so far, when recording
+    pub fn recorded_session(&self) -> Option<&Session> {
+        self.console_input.session()
+    }
+
+    /// The program file followed by its arguments: COMMAND$ joins the
+    /// arguments with spaces and COMMAND$(n) returns one, 0 being the program
+    pub fn set_command_line(&mut self, command_line: Vec<String>) {
+        self.command_line = command_line;
+    }
+
+    /// File name of the program, without its directory; empty when the
+    /// host did not say
+    fn program_name(&self) -> String {
+        self.command_line
+            .first()
+            .and_then(|path| Path::new(path).file_name())
+            .map_or_else(String::new, |name| name.to_string_lossy().into_owned())
+    }
+
+    /// The program a CHAIN statement ended this one to run. The host loads
+    /// it and passes it to `execute` on this VM, where its COMMON statements
+    /// take the values passed on; open files stay open.
+    pub fn take_chain(&mut self) -> Option<PathBuf> {
+        self.chain.take()
+    }
+
+    /// Process exit status the program asked for with END or SYSTEM
+    pub fn exit_code(&self) -> i32 {
+        self.exit_code
+    }
+
+    /// Count how many times each instruction runs, for line coverage, from
+    /// now on
+    pub fn enable_coverage(&mut self) {
+        self.instruction_counts = Some(Vec::new());
+    }
+
+    /// Times each instruction has run; empty unless coverage is enabled
+    pub fn instruction_counts(&self) -> &[u64] {
+        self.instruction_counts.as_deref().unwrap_or(&[])
+    }
+
+    /// Reset to the first instruction, ready for `resume`
+    pub fn start(&mut self) {
         self.running = true;
         self.instruction_pointer = 0;
+        // Each program, chained or run again, READs its DATA from the start
+        self.data_pointer = 0;
+        // A Ctrl+C pressed before the run is not meant for it
+        break_key::take();
+        self.error_handler = None;
+        self.handler_depth = 0;
+        self.suspended_frames.clear();
+        self.current_error = None;
+        self.error_number = 0;
+    }
+
+    /// Send error `number` to the ON ERROR handler, unless there is none or
+    /// it is already handling one, when the error stops the program. A
+    /// handler set by a caller runs in the caller's frame, the calls made
+    /// since put aside until RESUME.
+    fn trap_error(&mut self, number: i32, error: QError) -> QResult<()> {
+        match self.error_handler {
+            Some(handler) if self.current_error.is_none() => {
+                self.current_error = Some(error);
+                self.error_number = number;
+                self.error_address = self.instruction_pointer;
+                self.instruction_pointer = handler as usize;
+                self.suspended_frames = self.frames.split_off(self.handler_depth.min(self.frames.len()));
+                Ok(())
+            }
+            _ => Err(error),
+        }
+    }
+
+    /// Leave the error handler for the instruction at `address`, going
+    /// back into the calls it ran over when `into_calls`, as RESUME and
+    /// RESUME NEXT do, and abandoning them for a label
+    fn resume_at(&mut self, address: usize, into_calls: bool) -> QResult<()> {
+        if self.current_error.take().is_none() {
+            return Err(QError::runtime(QErrorCode::ResumeWithoutError, 0, 0));
+        }
+        self.error_number = 0;
+        self.instruction_pointer = address;
+        let suspended = std::mem::take(&mut self.suspended_frames);
+        if into_calls {
+            self.frames.extend(suspended);
+        }
+        Ok(())
+    }
 
+    /// Run until the program ends, writes a watched variable, or is stopped
+    /// by Ctrl+Break. Pressing Ctrl+Break raises KEY(15) instead when an
+    /// ON KEY(15) trap is set and not turned off.
+    pub fn resume(&mut self, bytecode: &ByteCode) -> QResult<Option<Pause>> {
         while self.running && self.instruction_pointer < bytecode.len() {
+            if break_key::take() {
+                if self.traps.is_trapped(TrapSource::Key, BREAK_KEY) {
+                    self.traps.signal(TrapSource::Key, BREAK_KEY);
+                } else {
+                    let line = bytecode.line_at(self.instruction_pointer).unwrap_or(0);
+                    return Ok(Some(Pause::Break { line }));
+                }
+            }
+            self.clock.step(INSTRUCTION_TIME);
+            self.check_events();
+            if let Some(counts) = &mut self.instruction_counts {
+                counts.resize(counts.len().max(bytecode.len()), 0);
+                counts[self.instruction_pointer] += 1;
+            }
             let op = &bytecode.instructions[self.instruction_pointer];
             
             if let Err(e) = self.execute_instruction(op, bytecode) {
-                if let Some(handler) = self.error_handler {
-                    self.current_error = Some(e);
-                    self.instruction_pointer = handler as usize;
-                } else {
-                    return Err(e);
+                let e = e.with_line(bytecode.error_line(self.instruction_pointer));
+                self.output = Sink::Screen;
+                let number = match &e {
+                    QError::Runtime { code, .. } => Some(code.code()),
+                    QError::Io(_) => Some(QErrorCode::DeviceIOError.code()),
+                    _ => None,
+                };
+                match number {
+                    Some(number) => self.trap_error(number, e)?,
+                    None => return Err(e),
                 }
             }
+            if let Some(hit) = self.watch_hit.take() {
+                return Ok(Some(Pause::Watch(hit)));
+            }
+            self.instructions_since_frame += 1;
+            if self.instructions_since_frame >= FRAME_CHECK_INTERVAL {
+                self.instructions_since_frame = 0;
+                self.present_frame(false)?;
+                self.sound.update();
+            }
+        }
+
+        // The final picture stays on the terminal
+        self.present_frame(true)?;
+        Ok(None)
+    }
+
+    /// Offer the frame on show to the terminal display; `now` draws it even
+    /// if the last frame went out only a moment ago, as before waiting
+    fn present_frame(&mut self, now: bool) -> QResult<()> {
+        if self.window_scale.is_none()
+            && self.screen_recorder.is_none()
+            && self.terminal.mode() == TerminalGraphics::Off
+        {
+            return Ok(());
+        }
+        let Some(frame) = self.graphics.frame() else {
+            return Ok(());
+        };
+        if let Some(recorder) = &mut self.screen_recorder {
+            recorder.capture(frame, Instant::now());
+        }
+        // The first graphics frame opens the window, if it can be opened
+        if let Some(scale) = self.window_scale.take() {
+            let full_screen = self.full_screen != FullScreen::Off;
+            self.screen_window =
+                FramebufferWindow::open(self.window.title(), frame, scale, self.terminal.options(), full_screen);
+        }
+        match &mut self.screen_window {
+            // Closing the window ends the program
+            Some(window) if !window.is_open() => self.running = false,
+            Some(window) => window.present(frame, now),
+            None => self.terminal.present(frame, now)?,
+        }
+        Ok(())
+    }
+
+    /// Global variables and their current values, sorted by name
+    pub fn variables(&self) -> Vec<(&str, &QType)> {
+        let mut vars: Vec<(&str, &QType)> = self.global_variables
+            .iter()
+            .map(|(name, value)| (name.as_str(), value))
+            .collect();
+        vars.sort_by(|a, b| a.0.cmp(b.0));
+        vars
+    }
+
+    /// Names of all dimensioned arrays, sorted
+    pub fn array_names(&self) -> Vec<&str> {
+        let mut names: Vec<&str> = self.arrays.keys().map(|n| n.as_str()).collect();
+        names.sort();
+        names
+    }
+
+    /// Read-only view of an array's bounds and elements
+    pub fn array(&self, name: &str) -> Option<ArrayView<'_>> {
+        Some(ArrayView {
+            bounds: self.array_shapes.get(name)?,
+            elements: self.arrays.get(name)?,
+        })
+    }
+
+    /// Every dimensioned array with its view, sorted by name
+    pub fn arrays(&self) -> Vec<(&str, ArrayView<'_>)> {
+        self.array_names()
+            .into_iter()
+            .filter_map(|name| Some((name, self.array(name)?)))
+            .collect()
+    }
+
+    /// Store `values` in consecutive elements of an array, in row-major
+    /// order from the element with subscripts `start`. Each value is
+    /// converted to the array's type, as assignment would.
+    pub fn write_array(&mut self, name: &str, start: &[i32], values: &[QType]) -> QResult<()> {
+        let out_of_range = || QError::runtime(QErrorCode::SubscriptOutOfRange, 0, 0);
+        let shape = self.array_shapes.get(name).ok_or_else(out_of_range)?;
+        let first = flat_index(shape, start).ok_or_else(out_of_range)?;
+        let elements = self.arrays.get_mut(name).ok_or_else(out_of_range)?;
+        let slots = elements.get_mut(first..first + values.len()).ok_or_else(out_of_range)?;
+        // Convert them all before storing any, so a bad value changes nothing
+        let converted = slots
+            .iter()
+            .zip(values)
+            .map(|(old, value)| Self::coerce(old, value.clone()))
+            .collect::<QResult<Vec<_>>>()?;
+        slots.clone_from_slice(&converted);
+        Ok(())
+    }
+
+    /// MAT array = value: store one value, converted once, in every element
+    fn fill_array(&mut self, name: &str, value: QType) -> QResult<()> {
+        let elements = self.arrays.get_mut(name)
+            .ok_or_else(|| QError::runtime(QErrorCode::SubscriptOutOfRange, 0, 0))?;
+        if let Some(first) = elements.first() {
+            if first.is_string() != value.is_string() {
+                return Err(QError::runtime(QErrorCode::TypeMismatch, 0, 0));
+            }
+            let value = Self::coerce(first, value)?;
+            elements.fill(value);
         }
+        Ok(())
+    }
 
+    /// MAT dest = source: copy every element in row-major order. The
+    /// arrays must have as many elements, though not the same bounds.
+    fn copy_array(&mut self, dest: &str, source: &str) -> QResult<()> {
+        let out_of_range = || QError::runtime(QErrorCode::SubscriptOutOfRange, 0, 0);
+        let values = self.arrays.get(source).ok_or_else(out_of_range)?.clone();
+        let elements = self.arrays.get_mut(dest).ok_or_else(out_of_range)?;
+        if elements.len() != values.len() {
+            return Err(out_of_range());
+        }
+        if elements.first().map(QType::is_string) != values.first().map(QType::is_string) {
+            return Err(QError::runtime(QErrorCode::TypeMismatch, 0, 0));
+        }
+        let converted = elements
+            .iter()
+            .zip(values)
+            .map(|(old, value)| Self::coerce(old, value))
+            .collect::<QResult<Vec<_>>>()?;
+        *elements = converted;
         Ok(())
     }
 
+    /// Run expression bytecode against the current state and return its
+    /// value. The program's position and stack are left as they were, and
+    /// an error is returned rather than handed to ON ERROR.
+    pub fn evaluate(&mut self, bytecode: &ByteCode) -> QResult<QType> {
+        let saved_pointer = self.instruction_pointer;
+        let saved_running = self.running;
+        let depth = self.value_stack.len();
+        self.instruction_pointer = 0;
+        self.running = true;
+
+        let mut result = Ok(());
+        while self.running && self.instruction_pointer < bytecode.len() {
+            result = self.execute_instruction(&bytecode.instructions[self.instruction_pointer], bytecode);
+            if result.is_err() {
+                break;
+            }
+        }
+        let value = if self.value_stack.len() > depth { self.value_stack.pop() } else { None };
+        self.value_stack.truncate(depth);
+        self.instruction_pointer = saved_pointer;
+        self.running = saved_running;
+
+        result?;
+        value.ok_or_else(|| QError::runtime(QErrorCode::IllegalFunctionCall, 0, 0))
+    }
+
     fn execute_instruction(&mut self, op: &OpCode, bytecode: &ByteCode) -> QResult<()> {
         match op {
             OpCode::Push(value) => {
@@ -98,16 +816,24 @@ impl VirtualMachine {
             }
             OpCode::StoreVar(name) => {
                 let value = self.pop()?;
+                if !self.watches.is_empty() {
+                    self.check_watches(name, &[], &value, bytecode);
+                }
                 self.set_variable(name, value)?;
             }
             OpCode::LoadArray(name, dim_count) => {
                 let indices = self.pop_n(*dim_count)?;
+                self.dimension_implicitly(name, bytecode)?;
                 let value = self.get_array_element(name, &indices)?;
                 self.push(value);
             }
             OpCode::StoreArray(name, dim_count) => {
                 let value = self.pop()?;
                 let indices = self.pop_n(*dim_count)?;
+                self.dimension_implicitly(name, bytecode)?;
+                if !self.watches.is_empty() {
+                    self.check_watches(name, &indices, &value, bytecode);
+                }
                 self.set_array_element(name, &indices, value)?;
             }
             OpCode::LoadField(var, field) => {
@@ -119,40 +845,72 @@ impl VirtualMachine {
                 self.set_field(var, field, value)?;
             }
             OpCode::DimArray(name, shape, type_str) => {
-                // Calculate total size
-                let total_size: usize = shape.iter().map(|(lo, hi)| (hi - lo + 1) as usize).product();
-                // Initialize array with appropriate default values based on type
-                let default_val = match type_str.as_str() {
-                    "INTEGER" => QType::Integer(0),
-                    "LONG" => QType::Long(0),
-                    "SINGLE" => QType::Single(0.0),
-                    "DOUBLE" => QType::Double(0.0),
-                    "STRING" => QType::String(String::new()),
-                    "_INTEGER64" => QType::Integer64(0),
-                    "_UNSIGNED INTEGER" => QType::UnsignedInteger(0),
-                    "_UNSIGNED LONG" => QType::UnsignedLong(0),
-                    "_UNSIGNED _INTEGER64" => QType::UnsignedInteger64(0),
-                    _ => QType::Single(0.0),
-                };
-                let arr = vec![default_val; total_size];
-                self.arrays.insert(name.clone(), arr);
-                self.array_shapes.insert(name.clone(), shape.clone());
+                // REDIM of an array parameter resizes the caller's array
+                let name = self.array_alias(name).unwrap_or_else(|| name.clone());
+                // Sized and checked as REDIM does, but static
+                self.redim(name.clone(), shape.clone(), array_blank(type_str), false)?;
+                self.dynamic_arrays.remove(&name);
+            }
+            OpCode::ReDim(name, dims, type_str, preserve) => {
+                let mut shape = vec![(0, 0); *dims];
+                for dim in shape.iter_mut().rev() {
+                    let upper = self.pop()?.to_long()?;
+                    let lower = self.pop()?.to_long()?;
+                    *dim = (lower, upper);
+                }
+                let name = self.array_alias(name).unwrap_or_else(|| name.clone());
+                self.redim(name, shape, array_blank(type_str), *preserve)?;
+            }
+            OpCode::Erase(name) => {
+                let name = self.array_alias(name).unwrap_or_else(|| name.clone());
+                // A freed array is "Subscript out of range" until REDIMmed
+                if self.dynamic_arrays.remove(&name) {
+                    self.arrays.remove(&name);
+                    self.array_shapes.remove(&name);
+                } else if let Some(elements) = self.arrays.get_mut(&name) {
+                    for value in elements {
+                        *value = value.default_value();
+                    }
+                }
+            }
+            OpCode::ArrayFill(name) => {
+                let value = self.pop()?;
+                let alias = self.array_alias(name);
+                self.fill_array(alias.as_deref().unwrap_or(name), value)?;
+            }
+            OpCode::ArrayCopy(dest, source) => {
+                let (dest_alias, source_alias) = (self.array_alias(dest), self.array_alias(source));
+                self.copy_array(dest_alias.as_deref().unwrap_or(dest), source_alias.as_deref().unwrap_or(source))?;
+            }
+            OpCode::Bound(name, upper, has_dimension) => {
+                let dimension = if *has_dimension { self.pop()?.to_long()? } else { 1 };
+                let alias = self.array_alias(name);
+                let (lower, higher) = usize::try_from(dimension - 1)
+                    .ok()
+                    .and_then(|dimension| self.array_shapes.get(alias.as_deref().unwrap_or(name))?.get(dimension).copied())
+                    .ok_or_else(|| QError::runtime(QErrorCode::SubscriptOutOfRange, 0, 0))?;
+                let bound = if *upper { higher } else { lower };
+                let bound = i16::try_from(bound).map_err(|_| QError::runtime(QErrorCode::Overflow, 0, 0))?;
+                self.push(QType::Integer(bound));
+            }
+            OpCode::DimDict(name, blank) => {
+                self.dicts.insert(name.clone(), Dict::new(blank.clone()));
             }
 
             OpCode::Add => {
                 let b = self.pop()?;
                 let a = self.pop()?;
-                self.push(a.add(&b)?);
+                self.push(a.add_in(&b, self.arithmetic)?);
             }
             OpCode::Sub => {
                 let b = self.pop()?;
                 let a = self.pop()?;
-                self.push(a.subtract(&b)?);
+                self.push(a.subtract_in(&b, self.arithmetic)?);
             }
             OpCode::Mul => {
                 let b = self.pop()?;
                 let a = self.pop()?;
-                self.push(a.multiply(&b)?);
+                self.push(a.multiply_in(&b, self.arithmetic)?);
             }
             OpCode::Div => {
                 let b = self.pop()?;
@@ -162,7 +920,8 @@ impl VirtualMachine {
             OpCode::IntDiv => {
                 let b = self.pop()?;
                 let a = self.pop()?;
-                self.push(a.int_divide(&b)?);
+                self.push(a.int_divide_in(&b, self.arithmetic)?);
+
             }
             OpCode::Mod => {
                 let b = self.pop()?;
@@ -176,7 +935,7 @@ impl VirtualMachine {
             }
             OpCode::Neg => {
                 let a = self.pop()?;
-                self.push(a.negate()?);
+                self.push(a.negate_in(self.arithmetic)?);
             }
             OpCode::LogNot => {
                 let a = self.pop()?;
@@ -228,37 +987,37 @@ impl VirtualMachine {
             OpCode::Eq => {
                 let b = self.pop()?;
                 let a = self.pop()?;
-                let result = a.compare(&b, qb_core::data_types::CompareOp::Eq)?;
+                let result = a.compare_in(&b, qb_core::data_types::CompareOp::Eq, self.dialect)?;
                 self.push(if result { QType::Integer(-1) } else { QType::Integer(0) });
             }
             OpCode::Ne => {
                 let b = self.pop()?;
                 let a = self.pop()?;
-                let result = a.compare(&b, qb_core::data_types::CompareOp::Ne)?;
+                let result = a.compare_in(&b, qb_core::data_types::CompareOp::Ne, self.dialect)?;
                 self.push(if result { QType::Integer(-1) } else { QType::Integer(0) });
             }
             OpCode::Lt => {
                 let b = self.pop()?;
                 let a = self.pop()?;
-                let result = a.compare(&b, qb_core::data_types::CompareOp::Lt)?;
+                let result = a.compare_in(&b, qb_core::data_types::CompareOp::Lt, self.dialect)?;
                 self.push(if result { QType::Integer(-1) } else { QType::Integer(0) });
             }
             OpCode::Le => {
                 let b = self.pop()?;
                 let a = self.pop()?;
-                let result = a.compare(&b, qb_core::data_types::CompareOp::Le)?;
+                let result = a.compare_in(&b, qb_core::data_types::CompareOp::Le, self.dialect)?;
                 self.push(if result { QType::Integer(-1) } else { QType::Integer(0) });
             }
             OpCode::Gt => {
                 let b = self.pop()?;
                 let a = self.pop()?;
-                let result = a.compare(&b, qb_core::data_types::CompareOp::Gt)?;
+                let result = a.compare_in(&b, qb_core::data_types::CompareOp::Gt, self.dialect)?;
                 self.push(if result { QType::Integer(-1) } else { QType::Integer(0) });
             }
             OpCode::Ge => {
                 let b = self.pop()?;
                 let a = self.pop()?;
-                let result = a.compare(&b, qb_core::data_types::CompareOp::Ge)?;
+                let result = a.compare_in(&b, qb_core::data_types::CompareOp::Ge, self.dialect)?;
                 self.push(if result { QType::Integer(-1) } else { QType::Integer(0) });
             }
 
@@ -280,12 +1039,26 @@ impl VirtualMachine {
                     return Ok(());
                 }
             }
+            OpCode::OnJump(targets, gosub) => {
+                let n = self.pop()?.to_long()?;
+                if !(0..=255).contains(&n) {
+                    return Err(QError::runtime(QErrorCode::IllegalFunctionCall, 0, 0));
+                }
+                if let Some(&addr) = usize::try_from(n - 1).ok().and_then(|i| targets.get(i)) {
+                    if *gosub {
+                        self.call_stack.push(self.instruction_pointer + 1);
+                    }
+                    self.instruction_pointer = addr as usize;
+                    return Ok(());
+                }
+            }
             OpCode::Call(addr) => {
                 self.call_stack.push(self.instruction_pointer + 1);
                 self.instruction_pointer = *addr as usize;
                 return Ok(());
             }
             OpCode::Return => {
+                self.traps.leave(self.call_stack.len());
                 if let Some(ret_addr) = self.call_stack.pop() {
                     self.instruction_pointer = ret_addr;
                     return Ok(());
@@ -293,179 +1066,823 @@ impl VirtualMachine {
                     return Err(QError::runtime(QErrorCode::ReturnWithoutGosub, 0, 0));
                 }
             }
+            OpCode::ReturnTo(addr) => {
+                self.traps.leave(self.call_stack.len());
+                if self.call_stack.pop().is_none() {
+                    return Err(QError::runtime(QErrorCode::ReturnWithoutGosub, 0, 0));
+                }
+                self.instruction_pointer = *addr as usize;
+                return Ok(());
+            }
 
             OpCode::Print(newline) => {
                 let value = self.pop()?;
-                print!("{}", value);
+                let text = if value.is_numeric() { self.localized(output::print_form(&value)) } else { value.to_string() };
+                self.write_output(&text)?;
                 if *newline {
-                    println!();
+                    self.write_output("\n")?;
                 }
-                io::stdout().flush()?;
+                if self.output == Sink::Screen {
+                    self.console_flush()?;
+                }
+            }
+            OpCode::PrintUsing(count) => {
+                let values = self.pop_n(*count)?;
+                let format = self.pop()?;
+                let QType::String(format) = format else {
+                    return Err(QError::runtime(QErrorCode::TypeMismatch, 0, 0));
+                };
+                self.push(QType::String(using::format_using(&format, &values)?));
             }
             OpCode::PrintComma => {
-                // Print tab (move to next 14-column zone)
-                print!("\t");
+                let column = match self.output {
+                    Sink::File(fileno) => self.files.column(fileno)?,
+                    Sink::Printer => self.printer.column(),
+                    Sink::Screen if self.prints_to_image() => self.graphics.text_column()? as usize,
+                    Sink::Screen => self.cursor_column,
+                };
+                let pad = PRINT_ZONE_WIDTH - column % PRINT_ZONE_WIDTH;
+                self.write_output(&" ".repeat(pad))?;
             }
             OpCode::PrintSemicolon => {
                 // Do nothing, continue on same line
             }
-            OpCode::Input(prompt) => {
-                print!("{}", prompt);
-                io::stdout().flush()?;
-                let mut input = String::new();
-                io::stdin().read_line(&mut input)?;
-                let trimmed = input.trim();
-                
-                // Try to parse as number, otherwise string
-                if let Ok(num) = trimmed.parse::<i32>() {
-                    self.push(QType::Integer(num as i16));
-                } else if let Ok(num) = trimmed.parse::<f64>() {
-                    self.push(QType::Double(num));
-                } else {
-                    self.push(QType::String(trimmed.to_string()));
+            OpCode::Write(last) => {
+                let value = self.pop()?;
+                let separator = if *last { "\n" } else { "," };
+                self.write_output(&(output::write_form(&value) + separator))?;
+                if *last && self.output == Sink::Screen {
+                    self.console_flush()?;
                 }
             }
+            OpCode::SelectOutput => {
+                self.output = match self.pop_file_number()? {
+                    0 => Sink::Screen,
+                    fileno => {
+                        self.files.check_output(fileno)?;
+                        Sink::File(fileno)
+                    }
+                };
+            }
+            OpCode::SelectPrinter => {
+                self.output = Sink::Printer;
+            }
+            OpCode::Input(prompt, same_line, vars) => {
+                self.input_statement(prompt, *same_line, vars)?;
+            }
             OpCode::LineInput(prompt) => {
-                print!("{}", prompt);
-                io::stdout().flush()?;
-                let mut input = String::new();
-                io::stdin().read_line(&mut input)?;
+                self.present_frame(true)?;
+                self.console_raw(prompt);
+                self.console_flush()?;
+                let input = self.read_console_line()?.unwrap_or_default();
+                if self.echoes_input() {
+                    self.cursor_column = 0;
+                } else {
+                    self.console_write("\n");
+                }
                 self.push(QType::String(input.trim_end().to_string()));
             }
-            OpCode::PrintHash(fileno) => {
-                // Simplified file output - just print to stdout with prefix
+            OpCode::InputHash(name) => {
+                let fileno = self.pop_file_number()?;
+                let is_string = matches!(self.variable_template(name), QType::String(_) | QType::FixedString(..));
+                let field = self.files.read_field(fileno, is_string)?;
+                let value = match self.input_value(name, &field) {
+                    Some(value) => value,
+                    // Unparsable numbers read as 0, as in QBasic
+                    None if field.parse::<f64>().is_err() => self.variable_template(name),
+                    None => return Err(QError::runtime(QErrorCode::Overflow, 0, 0)),
+                };
+                self.set_variable(name, value)?;
+            }
+            OpCode::LineInputHash => {
+                let fileno = self.pop_file_number()?;
+                let line = self.files.read_line(fileno)?;
+                self.push(QType::String(line));
+            }
+            OpCode::Open(mode, access, lock, has_reclen) => {
+                let record_len = if *has_reclen { Some(self.pop()?.to_long()?) } else { None };
+                let fileno = self.pop_file_number()?;
+                let filename = self.pop()?.to_qstring()?;
+                let mode = OpenMode::from_name(mode)
+                    .ok_or_else(|| QError::runtime(QErrorCode::BadFileMode, 0, 0))?;
+                match http::device_url(&filename) {
+                    Some(url) => {
+                        if mode != OpenMode::Input {
+                            return Err(QError::runtime(QErrorCode::BadFileMode, 0, 0));
+                        }
+                        if !self.sandbox.network {
+                            return Err(QError::runtime(QErrorCode::PermissionDenied, 0, 0));
+                        }
+                        self.files.open_contents(fileno, &filename, http::fetch(&url)?)?;
+                    }
+                    None => match vfs::device(&filename) {
+                        Some(device) => self.files.open_device(fileno, &filename, device, mode)?,
+                        None => {
+                            let clauses = OpenClauses { access: *access, lock: *lock, record_len };
+                            let path = self.host_path(&filename)?;
+                            self.files.open_with(fileno, &path, mode, clauses)?;
+                        }
+                    },
+                }
+            }
+            OpCode::Close => {
+                match self.pop_file_number()? {
+                    0 => {
+                        self.files.close_all()?;
+                        self.field_vars.clear();
+                        self.net.close_all();
+                    }
+                    handle if NetTable::is_handle(handle) => self.net.close(handle)?,
+                    fileno => {
+                        self.files.close(fileno)?;
+                        self.field_vars.remove(&fileno);
+                    }
+                }
+            }
+            OpCode::Kill => {
+                let filespec = self.pop()?.to_qstring()?;
+                self.kill(&filespec)?;
+            }
+            OpCode::Get(has_record) => {
+                let template = self.pop()?;
+                let record = if *has_record { Some(i64::from(self.pop()?.to_long()?)) } else { None };
+                let handle = self.pop_file_number()?;
+                let value = if NetTable::is_handle(handle) {
+                    // Connections have no records. A string takes everything
+                    // that has arrived; other types wait until all their
+                    // bytes are there.
+                    let len = match template {
+                        QType::String(_) => None,
+                        _ => Some(template.size()),
+                    };
+                    match self.net.receive(handle, len)? {
+                        Some(bytes) => template.from_bytes(&bytes, self.dialect),
+                        None => template,
+                    }
+                } else {
+                    self.get_record(handle, record, template)?
+                };
+                self.push(value);
+            }
+            OpCode::Put(has_record) => {
                 let value = self.pop()?;
-                print!("[#{}]{}", fileno, value);
-            }
-            OpCode::InputHash(fileno) => {
-                // Simplified file input - read from stdin
-                print!("[#{}]? ", fileno);
-                io::stdout().flush()?;
-                let mut input = String::new();
-                io::stdin().read_line(&mut input)?;
-                let trimmed = input.trim();
-                if let Ok(num) = trimmed.parse::<i32>() {
-                    self.push(QType::Integer(num as i16));
-                } else if let Ok(num) = trimmed.parse::<f64>() {
-                    self.push(QType::Double(num));
+                let record = if *has_record { Some(i64::from(self.pop()?.to_long()?)) } else { None };
+                let handle = self.pop_file_number()?;
+                if NetTable::is_handle(handle) {
+                    // Connections have no records
+                    self.net.send(handle, &value.to_bytes(self.dialect))?;
                 } else {
-                    self.push(QType::String(trimmed.to_string()));
+                    self.put_record(handle, record, &value)?;
+                }
+            }
+            OpCode::GetBuffer(has_record) => {
+                let record = if *has_record { Some(i64::from(self.pop()?.to_long()?)) } else { None };
+                let fileno = self.pop_file_number()?;
+                let (_, record_len) = self.files.shape(fileno)?;
+                let buffer = self.files.get_bytes(fileno, record, record_len)?;
+                let mut offset = 0;
+                for (name, width) in self.field_vars.get(&fileno).cloned().unwrap_or_default() {
+                    let text = buffer[offset..offset + width].iter().map(|&b| self.dialect.byte_char(b)).collect();
+                    self.set_variable(&name, QType::String(text))?;
+                    offset += width;
                 }
             }
-            OpCode::Open(filename, mode, fileno) => {
-                println!("[OPEN] {} mode={} #{}" , filename, mode, fileno);
+            OpCode::PutBuffer(has_record) => {
+                let record = if *has_record { Some(i64::from(self.pop()?.to_long()?)) } else { None };
+                let fileno = self.pop_file_number()?;
+                let mut buffer = Vec::new();
+                for (name, width) in self.field_vars.get(&fileno).cloned().unwrap_or_default() {
+                    let mut bytes = self.get_variable(&name)?.to_bytes(self.dialect);
+                    bytes.resize(width, b' ');
+                    buffer.extend(bytes);
+                }
+                self.files.put_bytes(fileno, record, &buffer)?;
+            }
+            OpCode::Field(names) => {
+                let widths = self.pop_n(names.len())?;
+                let fileno = self.pop_file_number()?;
+                let (mode, record_len) = self.files.shape(fileno)?;
+                if mode != OpenMode::Random {
+                    return Err(QError::runtime(QErrorCode::BadFileMode, 0, 0));
+                }
+                let mut fields = Vec::with_capacity(names.len());
+                let mut total = 0;
+                for (name, width) in names.iter().zip(widths) {
+                    let width = usize::try_from(width.to_long()?)
+                        .map_err(|_| QError::runtime(QErrorCode::IllegalFunctionCall, 0, 0))?;
+                    total += width;
+                    if total > record_len {
+                        return Err(QError::runtime(QErrorCode::FieldOverflow, 0, 0));
+                    }
+                    self.set_variable(name, QType::String(" ".repeat(width)))?;
+                    fields.push((name.clone(), width));
+                }
+                self.field_vars.insert(fileno, fields);
+            }
+            OpCode::Justify(right) => {
+                let value = self.pop()?.to_qstring()?;
+                let width = self.pop()?.to_qstring()?.chars().count();
+                let text: String = value.chars().take(width).collect();
+                let pad = " ".repeat(width - text.chars().count());
+                self.push(QType::String(if *right { pad + &text } else { text + &pad }));
+            }
+            OpCode::LoadRecord(fields) => {
+                let mut image = Vec::new();
+                for field in fields {
+                    let value = self.get_variable(field)?;
+                    let mut bytes = value.to_bytes(self.dialect);
+                    bytes.resize(Self::record_size(&value), 0);
+                    image.extend(bytes);
+                }
+                self.push(QType::UserDefined(image));
             }
-            OpCode::Close(fileno) => {
-                println!("[CLOSE] #{}" , fileno);
+            OpCode::StoreRecord(fields) => {
+                let QType::UserDefined(image) = self.pop()? else {
+                    return Err(QError::runtime(QErrorCode::TypeMismatch, 0, 0));
+                };
+                let mut offset = 0;
+                for field in fields {
+                    let template = self.get_variable(field)?;
+                    let size = Self::record_size(&template);
+                    let value = template.from_bytes(&image[offset..offset + size], self.dialect);
+                    self.set_variable(field, value)?;
+                    offset += size;
+                }
             }
-            OpCode::WriteHash(fileno) => {
-                let value = self.pop()?;
-                print!("[#{}]{},", fileno, value);
+            OpCode::Eof => {
+                let fileno = self.pop_file_number()?;
+                let at_end = if NetTable::is_handle(fileno) {
+                    self.net.eof(fileno)?
+                } else {
+                    self.files.eof(fileno)?
+                };
+                self.push(QType::Integer(if at_end { -1 } else { 0 }));
+            }
+            OpCode::Lof => {
+                let fileno = self.pop_file_number()?;
+                let length = self.files.length(fileno)?;
+                self.push(QType::Long(length as i32));
+            }
+            OpCode::Loc => {
+                let fileno = self.pop_file_number()?;
+                let position = self.files.loc(fileno)?;
+                self.push(QType::Long(position as i32));
+            }
+            OpCode::Command(indexed) => {
+                let text = if *indexed {
+                    let index = self.pop()?.to_long()?;
+                    if index < 0 {
+                        return Err(QError::runtime(QErrorCode::IllegalFunctionCall, 0, 0));
+                    }
+                    self.command_line.get(index as usize).cloned().unwrap_or_default()
+                } else {
+                    self.command_line.get(1..).unwrap_or_default().join(" ")
+                };
+                self.push(QType::String(text));
+            }
+            OpCode::SourceLine(text) => {
+                let line = bytecode.line_at(self.instruction_pointer).unwrap_or(0);
+                self.push(if *text {
+                    QType::String(format!("{}:{}", self.program_name(), line))
+                } else {
+                    QType::Long(line as i32)
+                });
+            }
+            OpCode::CallDepth => {
+                self.push(QType::Long((self.call_stack.len() + self.frames.len()) as i32));
+            }
+            OpCode::ProgramName => {
+                self.push(QType::String(self.program_name()));
+            }
+            OpCode::FreeFile => {
+                self.push(QType::Integer(self.files.free_number() as i16));
+            }
+            OpCode::Fre => {
+                let free = match self.pop()? {
+                    QType::String(_) | QType::FixedString(..) => self.memory_free().0,
+                    n => match n.to_long()? {
+                        -1 => self.memory_free().1,
+                        -2 => self.memory_free().2,
+                        _ => self.memory_free().0,
+                    },
+                };
+                self.push(QType::Long(free as i32));
             }
 
-            OpCode::Screen(mode) => {
-                self.screen_mode = *mode;
-                println!("SCREEN {}", mode);
+            OpCode::Screen => {
+                match self.pop()?.to_long()? {
+                    handle if handle < -1 => self.graphics.set_screen_image(handle)?,
+                    mode @ 0..=255 => {
+                        self.graphics.set_mode(mode as u8)?;
+                        self.screen_mode = mode as u8;
+                    }
+                    _ => return Err(QError::runtime(QErrorCode::IllegalFunctionCall, 0, 0)),
+                }
+                // A new screen becomes the _DEST and _SOURCE
+                self.console_dest = false;
+                self.console_source = false;
             }
-            OpCode::PSet => {
-                let _color = self.pop()?;
-                let _y = self.pop()?;
-                let _x = self.pop()?;
-                // Graphics not fully implemented in console mode
+            OpCode::PSet(has_color) => {
+                let color = if *has_color { Some(self.pop_color()?) } else { None };
+                let y = self.pop()?.to_single()?;
+                let x = self.pop()?.to_single()?;
+                self.graphics.pset(x.round() as i32, y.round() as i32, color)?;
             }
             OpCode::PReset => {
-                let _y = self.pop()?;
-                let _x = self.pop()?;
+                let y = self.pop()?.to_single()?;
+                let x = self.pop()?.to_single()?;
+                self.graphics.pset(x.round() as i32, y.round() as i32, Some(0))?;
+            }
+            OpCode::Line(step1, step2, has_color, shape, has_style) => {
+                // Styles are 16-bit masks, given as INTEGER or as &HFFFF
+                let style = if *has_style { self.pop()?.to_long()? as u16 } else { 0xFFFF };
+                let color = if *has_color { Some(self.pop_color()?) } else { None };
+                let mut coords = [0; 4];
+                for coord in coords.iter_mut().rev() {
+                    *coord = self.pop()?.to_single()?.round() as i32;
+                }
+                let [x1, y1, x2, y2] = coords;
+                let origin = if *step1 { self.graphics.last_point()? } else { (0, 0) };
+                let from = (origin.0 + x1, origin.1 + y1);
+                let to = if *step2 { (from.0 + x2, from.1 + y2) } else { (x2, y2) };
+                self.graphics.line(from, to, color, *shape, style)?;
+            }
+            OpCode::Circle(step, has_color, has_start, has_end, has_aspect) => {
+                let aspect = if *has_aspect { Some(self.pop()?.to_single()?) } else { None };
+                let end = if *has_end { Some(self.pop()?.to_single()?) } else { None };
+                let start = if *has_start { Some(self.pop()?.to_single()?) } else { None };
+                let color = if *has_color { Some(self.pop_color()?) } else { None };
+                let radius = self.pop()?.to_single()?;
+                let y = self.pop()?.to_single()?.round() as i32;
+                let x = self.pop()?.to_single()?.round() as i32;
+                let origin = if *step { self.graphics.last_point()? } else { (0, 0) };
+                let center = (origin.0 + x, origin.1 + y);
+                self.graphics.circle(center, radius, color, (start, end), aspect)?;
+            }
+            OpCode::Draw => {
+                let commands = self.pop()?.to_qstring()?;
+                let commands = self.expand_references(&commands, 0)?;
+                self.turtle.draw(&mut self.graphics, &commands)?;
+            }
+            OpCode::VarPtrString(name) => {
+                let code: u8 = match self.get_variable(name)? {
+                    QType::Integer(_) => 2,
+                    QType::String(_) | QType::FixedString(..) => 3,
+                    QType::Single(_) => 4,
+                    QType::Double(_) => 8,
+                    QType::Long(_) => 20,
+                    _ => return Err(QError::runtime(QErrorCode::TypeMismatch, 0, 0)),
+                };
+                self.push(QType::String(format!("{}{};", char::from(code), name)));
             }
-            OpCode::Line => {
-                let _args = self.pop_n(5)?;
-                // Not implemented
+            OpCode::Cls => {
+                if self.prints_to_image() {
+                    self.graphics.cls();
+                } else {
+                    self.console_raw("\x1B[2J\x1B[1;1H"); // ANSI clear screen
+                    self.cursor_column = 0;
+                }
             }
-            OpCode::Circle => {
-                let _args = self.pop_n(4)?;
-                // Not implemented
+            OpCode::Display => {
+                self.graphics.display();
             }
-            OpCode::Cls => {
-                print!("\x1B[2J\x1B[1;1H"); // ANSI clear screen
+            OpCode::AutoDisplay => {
+                self.graphics.set_auto_display();
             }
-            OpCode::Color => {
-                let _border = self.pop()?;
-                let _background = self.pop()?;
-                let _foreground = self.pop()?;
-                // Color codes not implemented
+            OpCode::Color(has_fg, has_bg, has_border) => {
+                if *has_border {
+                    self.pop()?; // no border on modern displays
+                }
+                let bg = if *has_bg { Some(self.pop_color()?) } else { None };
+                let fg = if *has_fg { Some(self.pop_color()?) } else { None };
+                self.graphics.set_color(fg, bg)?;
             }
             OpCode::Locate => {
                 let _args = self.pop_n(2)?;
                 // Not implemented
             }
             
-            // QB64 Graphics extensions (stubs)
-            OpCode::RGB(r, g, b) => {
-                let color = ((*r as i32) << 16) | ((*g as i32) << 8) | (*b as i32);
-                self.push(QType::Long(color));
-            }
-            OpCode::RGBA(r, g, b, a) => {
-                let color = ((*a as i32) << 24) | ((*r as i32) << 16) | ((*g as i32) << 8) | (*b as i32);
-                self.push(QType::Long(color));
-            }
-            OpCode::NewImage(width, height, mode) => {
-                println!("[NEWIMAGE] {}x{} mode={}", width, height, mode);
-                self.push(QType::Long(1)); // Return image handle
-            }
-            OpCode::LoadImage(filename) => {
-                println!("[LOADIMAGE] {}", filename);
-                self.push(QType::Long(1)); // Return image handle
-            }
-            OpCode::PutImage => {
-                let _args = self.pop_n(6)?;
-                println!("[PUTIMAGE]");
+            // QB64 Graphics extensions
+            OpCode::RGB(has_handle) | OpCode::RGBA(has_handle) => {
+                let handle = if *has_handle { self.pop()?.to_long()? } else { self.graphics.dest() };
+                let alpha = match op {
+                    OpCode::RGBA(_) => self.pop()?.to_long()?,
+                    _ => 255,
+                };
+                let [r, g, b] = self.pop_channels()?;
+                let color = self.graphics.match_color(rgba32(r, g, b, alpha), handle)?;
+                self.push(QType::Long(color as i32));
+            }
+            OpCode::RGB32(count) => {
+                let (r, g, b, a) = match *count {
+                    1 => { let v = self.pop()?.to_long()?; (v, v, v, 255) }
+                    2 => {
+                        let a = self.pop()?.to_long()?;
+                        let v = self.pop()?.to_long()?;
+                        (v, v, v, a)
+                    }
+                    3 => { let [r, g, b] = self.pop_channels()?; (r, g, b, 255) }
+                    _ => {
+                        let a = self.pop()?.to_long()?;
+                        let [r, g, b] = self.pop_channels()?;
+                        (r, g, b, a)
+                    }
+                };
+                self.push(QType::Long(rgba32(r, g, b, a) as i32));
+            }
+            OpCode::Channel(shift, has_handle) => {
+                let handle = if *has_handle { self.pop()?.to_long()? } else { self.graphics.dest() };
+                let color = self.pop_color()?;
+                let argb = self.graphics.color_argb(color, handle)?;
+                self.push(QType::Long(((argb >> shift) & 0xFF) as i32));
+            }
+            OpCode::PrintString(has_handle) => {
+                let handle = if *has_handle { self.pop()?.to_long()? } else { self.graphics.dest() };
+                let text = self.pop()?.to_qstring()?;
+                let y = self.pop()?.to_single()?.round() as i32;
+                let x = self.pop()?.to_single()?.round() as i32;
+                if handle == 0 && self.graphics.text_mode() {
+                    // SCREEN 0 takes a column and row
+                    if x < 1 || y < 1 {
+                        return Err(QError::runtime(QErrorCode::IllegalFunctionCall, 0, 0));
+                    }
+                    self.console_raw(&format!("\x1B[{};{}H{}", y, x, text));
+                } else {
+                    self.graphics.print_string(x, y, &text, handle)?;
+                }
             }
-            
-            // QB64 Sound extensions (stubs)
-            OpCode::SndOpen(filename) => {
-                println!("[SNDOPEN] {}", filename);
-                self.push(QType::Long(1)); // Return sound handle
+            OpCode::PrintWidth(has_handle) => {
+                let handle = if *has_handle { self.pop()?.to_long()? } else { self.graphics.dest() };
+                let text = self.pop()?.to_qstring()?;
+                let width = self.graphics.print_width(&text, handle)?;
+                self.push(QType::Long(width as i32));
+            }
+            OpCode::ImageSize(height, has_handle) => {
+                let handle = if *has_handle {
+                    self.pop()?.to_long()?
+                } else if self.console_dest {
+                    CONSOLE_HANDLE
+                } else {
+                    self.graphics.dest()
+                };
+                // The console is a text screen of its own
+                let (width, rows) = if self.is_console(handle) { TEXT_SIZE } else { self.graphics.size(handle)? };
+                self.push(QType::Long(if *height { rows } else { width } as i32));
+            }
+            OpCode::Channel32(shift) => {
+                let color = self.pop_color()?;
+                self.push(QType::Long(((color >> shift) & 0xFF) as i32));
+            }
+            OpCode::NewImage => {
+                let mode = self.pop()?.to_long()?;
+                let height = self.pop()?.to_long()?;
+                let width = self.pop()?.to_long()?;
+                let handle = self.graphics.new_image(width, height, mode)?;
+                self.push(QType::Long(handle));
+            }
+            OpCode::LoadImage => {
+                let mode = self.pop()?.to_long()?;
+                let filename = self.pop()?.to_qstring()?;
+                let handle = self.graphics.load_image(Path::new(&self.host_path(&filename)?), mode)?;
+                self.push(QType::Long(handle));
+            }
+            OpCode::PutImage(area_corners, source_corners) => {
+                let source_area = self.pop_image_area(*source_corners)?;
+                let dest = self.pop()?.to_long()?;
+                let source = self.pop()?.to_long()?;
+                let area = self.pop_image_area(*area_corners)?;
+                self.graphics.put_image(area, source, dest, source_area)?;
+            }
+            OpCode::FreeImage => {
+                let handle = self.pop()?.to_long()?;
+                self.graphics.free_image(handle)?;
+            }
+            OpCode::SaveImage(has_handle) => {
+                // The screen by default
+                let handle = if *has_handle { self.pop()?.to_long()? } else { 0 };
+                let filename = self.pop()?.to_qstring()?;
+                self.graphics.save_image(handle, Path::new(&self.host_path(&filename)?))?;
+            }
+            OpCode::CopyImage => {
+                let handle = self.pop()?.to_long()?;
+                let copy = self.graphics.copy_image(handle)?;
+                self.push(QType::Long(copy));
+            }
+            OpCode::MemNew => {
+                let size = self.pop()?.to_long()?;
+                let handle = self.mem.new_block(size)?;
+                self.push(QType::Long(handle));
+            }
+            OpCode::MemImage => {
+                let image = self.pop()?.to_long()?;
+                let handle = self.mem.image_block(&mut self.graphics, image)?;
+                self.push(QType::Long(handle));
+            }
+            OpCode::MemGet => {
+                let template = self.pop()?;
+                let address = self.pop()?.to_long()?;
+                let block = self.pop()?.to_long()?;
+                let value = self.mem.get(&mut self.graphics, block, address, &template)?;
+                self.push(value);
             }
-            OpCode::SndClose(handle) => {
-                println!("[SNDCLOSE] #{}" , handle);
+            OpCode::MemPut(template) => {
+                let mut value = self.pop()?;
+                if let Some(template) = template {
+                    value = value.convert_to(template)?;
+                }
+                let address = self.pop()?.to_long()?;
+                let block = self.pop()?.to_long()?;
+                self.mem.put(&mut self.graphics, block, address, &value)?;
+            }
+            OpCode::MemFree => {
+                let block = self.pop()?.to_long()?;
+                self.mem.free(block)?;
+            }
+            OpCode::MemField(field) => {
+                let block = self.pop()?.to_long()?;
+                self.push(QType::Long(self.mem.field(block, *field)));
+            }
+            OpCode::SetDest => {
+                let handle = self.pop()?.to_long()?;
+                self.console_dest = self.is_console(handle);
+                if !self.console_dest {
+                    self.graphics.set_dest(handle)?;
+                }
             }
-            OpCode::SndPlay(handle) => {
-                println!("[SNDPLAY] #{}" , handle);
+            OpCode::Dest => {
+                let handle = if self.console_dest { CONSOLE_HANDLE } else { self.graphics.dest() };
+                self.push(QType::Long(handle));
             }
-            OpCode::SndStop(handle) => {
-                println!("[SNDSTOP] #{}" , handle);
+            OpCode::SetSource => {
+                let handle = self.pop()?.to_long()?;
+                self.console_source = self.is_console(handle);
+                if !self.console_source {
+                    self.graphics.set_source(handle)?;
+                }
             }
-            OpCode::SndLoop(handle) => {
-                println!("[SNDLOOP] #{}" , handle);
+            OpCode::Source => {
+                let handle = if self.console_source { CONSOLE_HANDLE } else { self.graphics.source() };
+                self.push(QType::Long(handle));
             }
-            OpCode::SndVolume(handle, vol) => {
-                println!("[SNDVOL] #{} {}" , handle, vol);
+            
+            OpCode::SndOpen(has_requirements) => {
+                // Sounds are always mixed in memory, so the requirements
+                // string asks for nothing that is not there
+                if *has_requirements {
+                    self.pop()?;
+                }
+                let filename = self.pop()?.to_qstring()?;
+                // 0 is the failure value, as with a file that is not a sound
+                let handle = self
+                    .host_path(&filename)
+                    .and_then(|path| self.sound.open_sound(Path::new(&path)))
+                    .unwrap_or(0);
+                self.push(QType::Long(handle));
+            }
+            OpCode::SndPlay(looped) => {
+                let handle = self.pop()?.to_long()?;
+                self.sound.play_sound(handle, *looped)?;
+            }
+            OpCode::SndStop => {
+                let handle = self.pop()?.to_long()?;
+                self.sound.mixer().stop(handle)?;
+            }
+            OpCode::SndClose => {
+                let handle = self.pop()?.to_long()?;
+                self.sound.mixer().close(handle)?;
+            }
+            OpCode::SndVolume => {
+                let volume = self.pop()?.to_single()?;
+                let handle = self.pop()?.to_long()?;
+                self.sound.mixer().set_volume(handle, volume)?;
+            }
+            OpCode::SndPlaying => {
+                let handle = self.pop()?.to_long()?;
+                let playing = self.sound.sound_playing(handle)?;
+                self.push(QType::Integer(if playing { -1 } else { 0 }));
+            }
+            OpCode::SndRaw(has_right) => {
+                let right = if *has_right { Some(self.pop()?.to_single()?) } else { None };
+                let left = self.pop()?.to_single()?;
+                // Mixed to one channel
+                self.sound.raw(right.map_or(left, |right| (left + right) / 2.0));
+            }
+            OpCode::SndRate => {
+                self.push(QType::Long(SAMPLE_RATE as i32));
+            }
+            OpCode::SndRawLen => {
+                let seconds = self.sound.raw_remaining();
+                self.push(QType::Double(seconds));
             }
 
             OpCode::Beep => {
-                print!("\x07"); // Bell character
+                self.sound.beep();
             }
             OpCode::Sound => {
-                let _duration = self.pop()?;
-                let _frequency = self.pop()?;
-                // Sound not implemented
+                let ticks = self.pop()?.to_double()?;
+                let frequency = self.pop()?.to_double()?;
+                self.sound.sound(frequency, ticks)?;
             }
-            OpCode::Play => {
-                let _command = self.pop()?;
-                // Play not implemented
+            OpCode::Play(voices) => {
+                let voices = self
+                    .pop_n(usize::from(*voices))?
+                    .iter()
+                    .map(|commands| self.expand_references(&commands.to_qstring()?, 0))
+                    .collect::<QResult<Vec<_>>>()?;
+                self.sound.play(&voices)?;
+            }
+            OpCode::Title => {
+                let title = self.pop()?.to_qstring()?;
+                self.window.set_title(&title);
+                if let Some(window) = &mut self.screen_window {
+                    window.set_title(&title);
+                }
+            }
+            OpCode::ScreenMove(has_position) => {
+                let position = if *has_position {
+                    let y = self.pop()?.to_long()?;
+                    let x = self.pop()?.to_long()?;
+                    WindowPosition::At(x, y)
+                } else {
+                    WindowPosition::Middle
+                };
+                self.window.move_to(position);
+            }
+            OpCode::Resize(enabled, mode) => {
+                self.window.set_resize(*enabled, *mode);
+            }
+            OpCode::FullScreen(full_screen, filter) => {
+                let options =
+                    DisplayOptions { scaling: full_screen.scaling(), filter: *filter, ..self.terminal.options() };
+                self.set_display_options(options);
+                self.full_screen = *full_screen;
+                // A window that cannot change stays as it is, which the
+                // _FULLSCREEN function then reports
+                if let Some(window) = &mut self.screen_window {
+                    if !window.set_full_screen(*full_screen != FullScreen::Off) {
+                        self.full_screen = FullScreen::Off;
+                    }
+                }
+            }
+            OpCode::OpenHost | OpCode::OpenClient => {
+                let address = self.pop()?.to_qstring()?;
+                if !self.sandbox.network {
+                    return Err(QError::runtime(QErrorCode::PermissionDenied, 0, 0));
+                }
+                let handle = match op {
+                    OpCode::OpenHost => self.net.open_host(&address)?,
+                    _ => self.net.open_client(&address)?,
+                };
+                self.push(QType::Long(handle));
+            }
+            OpCode::OpenConnection => {
+                let host = self.pop()?.to_long()?;
+                let handle = self.net.accept(host)?;
+                self.push(QType::Long(handle));
+            }
+            OpCode::Connected => {
+                let handle = self.pop()?.to_long()?;
+                let connected = self.net.connected(handle)?;
+                self.push(QType::Long(if connected { -1 } else { 0 }));
+            }
+            OpCode::ConnectionAddress => {
+                let handle = self.pop()?.to_long()?;
+                let address = self.net.address(handle)?;
+                self.push(QType::String(address));
+            }
+            OpCode::ConsoleOpen(only) => {
+                self.console = true;
+                self.console_dest |= *only;
+            }
+            OpCode::ConsoleVisible(visible) => {
+                // Without $CONSOLE there is no console to show or hide
+                if self.console {
+                    self.console_visible = *visible;
+                }
+            }
+            OpCode::Console => {
+                let handle = if self.console { CONSOLE_HANDLE } else { 0 };
+                self.push(QType::Long(handle));
+            }
+            OpCode::ResizeEvent => {
+                let resized = self.window.take_resize();
+                self.push(QType::Integer(if resized { -1 } else { 0 }));
+            }
+            OpCode::FullScreenMode => {
+                self.push(QType::Integer(self.full_screen.code()));
+            }
+            OpCode::ResizeWidth => {
+                let (width, _) = self.window.resize_size();
+                self.push(QType::Long(width as i32));
+            }
+            OpCode::ResizeHeight => {
+                let (_, height) = self.window.resize_size();
+                self.push(QType::Long(height as i32));
+            }
+            OpCode::Stick => {
+                let n = self.pop()?.to_long()?;
+                let value = self.joysticks.stick(n)
+                    .ok_or_else(|| QError::runtime(QErrorCode::IllegalFunctionCall, 0, 0))?;
+                self.push(QType::Integer(value));
+            }
+            OpCode::Strig => {
+                let n = self.pop()?.to_long()?;
+                let pressed = self.joysticks.strig(n)
+                    .ok_or_else(|| QError::runtime(QErrorCode::IllegalFunctionCall, 0, 0))?;
+                self.push(QType::Integer(if pressed { -1 } else { 0 }));
+            }
+            OpCode::Devices => {
+                let count = self.joysticks.device_count();
+                self.push(QType::Integer(count as i16));
+            }
+            OpCode::DeviceName => {
+                let device = self.pop_device()?;
+                let name = self.joysticks.device_name(device)
+                    .ok_or_else(|| QError::runtime(QErrorCode::IllegalFunctionCall, 0, 0))?;
+                self.push(QType::String(name));
+            }
+            OpCode::DeviceInput(has_device) => {
+                let result = if *has_device {
+                    let device = self.pop_device()?;
+                    let input = self.joysticks.device_input(device)
+                        .ok_or_else(|| QError::runtime(QErrorCode::IllegalFunctionCall, 0, 0))?;
+                    if input { -1 } else { 0 }
+                } else {
+                    self.joysticks.next_device_input().map_or(0, |device| device as i16)
+                };
+                self.push(QType::Integer(result));
+            }
+            OpCode::Button => {
+                let button = self.pop_device()?;
+                let down = self.joysticks.button(button)
+                    .ok_or_else(|| QError::runtime(QErrorCode::IllegalFunctionCall, 0, 0))?;
+                self.push(QType::Integer(if down { -1 } else { 0 }));
+            }
+            OpCode::Axis => {
+                let axis = self.pop_device()?;
+                let position = self.joysticks.axis(axis)
+                    .ok_or_else(|| QError::runtime(QErrorCode::IllegalFunctionCall, 0, 0))?;
+                self.push(QType::Single(position));
+            }
+            OpCode::LastButton | OpCode::LastAxis => {
+                let device = self.pop_device()?;
+                let count = match op {
+                    OpCode::LastButton => self.joysticks.button_count(device),
+                    _ => self.joysticks.axis_count(device),
+                };
+                let count = count.ok_or_else(|| QError::runtime(QErrorCode::IllegalFunctionCall, 0, 0))?;
+                self.push(QType::Integer(count as i16));
+            }
+            OpCode::OnEvent(TrapSource::Timer, handler) => {
+                let seconds = self.pop()?.to_long()?;
+                if !(1..=86_400).contains(&seconds) {
+                    return Err(QError::runtime(QErrorCode::IllegalFunctionCall, 0, 0));
+                }
+                let interval = Duration::from_secs(seconds as u64);
+                self.timer = Some((interval, self.clock.elapsed() + interval));
+                self.traps.set_handler(TrapSource::Timer, 0, *handler);
+            }
+            OpCode::OnEvent(source, handler) => {
+                let n = self.pop()?.to_long()?;
+                self.traps.set_handler(*source, n, *handler);
+            }
+            OpCode::EventControl(source, state, has_arg) => {
+                let n = if *has_arg { Some(self.pop()?.to_long()?) } else { None };
+                // TIMER ON counts a whole interval from now
+                if *source == TrapSource::Timer && *state == TrapState::On && !self.traps.is_watching(TrapSource::Timer) {
+                    if let Some((interval, due)) = &mut self.timer {
+                        *due = self.clock.elapsed() + *interval;
+                    }
+                }
+                self.traps.set_state(*source, n, *state);
+            }
+            OpCode::Limit => {
+                let rate = self.pop()?.to_double()?;
+                self.limiter.wait(&self.clock, rate);
+            }
+            OpCode::Sleep(has_seconds) => {
+                let seconds = if *has_seconds { self.pop()?.to_long()? } else { 0 };
+                self.sleep(seconds)?;
+            }
+            OpCode::PlayCount => {
+                let _voice = self.pop()?;
+                let pending = self.sound.pending();
+                self.push(QType::Integer(pending as i16));
             }
 
             OpCode::Peek => {
-                let _addr = self.pop()?;
-                self.push(QType::Integer(0)); // Placeholder
+                let offset = self.pop_word()?;
+                self.push(QType::Integer(i16::from(self.memory.peek(offset))));
             }
             OpCode::Poke => {
-                let _value = self.pop()?;
-                let _addr = self.pop()?;
-                // Not implemented
+                let value = self.pop()?.to_long()?;
+                let offset = self.pop_word()?;
+                let value = u8::try_from(value).map_err(|_| QError::runtime(QErrorCode::IllegalFunctionCall, 0, 0))?;
+                self.memory.poke(offset, value);
             }
-            OpCode::DefSeg(_seg) => {
-                // Not implemented
+            OpCode::DefSeg(has_segment) => {
+                let segment = if *has_segment { Some(self.pop_word()?) } else { None };
+                self.memory.set_segment(segment);
             }
 
             OpCode::Concat => {
@@ -487,49 +1904,77 @@ impl VirtualMachine {
                 let result: String = chars[start..].iter().collect();
                 self.push(QType::String(result));
             }
-            OpCode::Mid => {
-                let len = self.pop()?.to_integer()?;
+            OpCode::Mid(has_length) => {
+                let len = if *has_length { Some(self.pop()?.to_integer()?) } else { None };
                 let start = self.pop()?.to_integer()?;
                 let s = self.pop()?.to_qstring()?;
-                let chars: Vec<char> = s.chars().collect();
-                let start_idx = (start as usize).saturating_sub(1);
-                let result: String = chars[start_idx..]
-                    .iter()
-                    .take(len as usize)
-                    .collect();
+                if start < 1 || len.is_some_and(|len| len < 0) {
+                    return Err(QError::runtime(QErrorCode::IllegalFunctionCall, 0, 0));
+                }
+                let rest = s.chars().skip(start as usize - 1);
+                let result: String = match len {
+                    Some(len) => rest.take(len as usize).collect(),
+                    None => rest.collect(),
+                };
                 self.push(QType::String(result));
             }
             OpCode::Len => {
-                let s = self.pop()?.to_qstring()?;
-                self.push(QType::Integer(s.len() as i16));
-            }
-            OpCode::Asc => {
-                let s = self.pop()?.to_qstring()?;
-                if let Some(c) = s.chars().next() {
-                    self.push(QType::Integer(c as i16));
+                let len = match self.pop()? {
+                    QType::UserDefined(image) => image.len(),
+                    value => value.to_qstring()?.chars().count(),
+                };
+                self.push(QType::Integer(len as i16));
+            }
+            OpCode::InStr(has_start) | OpCode::InStrRev(has_start) => {
+                let needle = self.pop()?.to_qstring()?;
+                let haystack = self.pop()?.to_qstring()?;
+                let start = if *has_start {
+                    let start = self.pop()?.to_long()?;
+                    if start < 1 {
+                        return Err(QError::runtime(QErrorCode::IllegalFunctionCall, 0, 0));
+                    }
+                    Some(start as usize)
                 } else {
-                    return Err(QError::runtime(QErrorCode::IllegalFunctionCall, 0, 0));
+                    None
+                };
+                if let OpCode::InStr(_) = op {
+                    let position = strings::instr(start.unwrap_or(1), &haystack, &needle);
+                    self.push(QType::Integer(position as i16));
+                } else {
+                    let position = strings::instr_rev(start, &haystack, &needle);
+                    self.push(QType::Long(position as i32));
                 }
             }
+            OpCode::Asc(with_position) => {
+                let position = if *with_position { self.pop()?.to_long()? } else { 1 };
+                let s = self.pop()?.to_qstring()?;
+                let c = usize::try_from(position - 1)
+                    .ok()
+                    .and_then(|index| s.chars().nth(index))
+                    .ok_or_else(|| QError::runtime(QErrorCode::IllegalFunctionCall, 0, 0))?;
+                // Only Unicode characters beyond &H7FFF need a LONG
+                let code = self.dialect.asc(c);
+                self.push(i16::try_from(code).map_or(QType::Long(code as i32), QType::Integer));
+            }
             OpCode::Chr => {
-                let code = self.pop()?.to_integer()?;
-                if let Some(c) = char::from_u32(code as u32) {
-                    self.push(QType::String(c.to_string()));
-                } else {
-                    return Err(QError::runtime(QErrorCode::IllegalFunctionCall, 0, 0));
+                let code = self.pop()?.to_long()?;
+                match self.dialect.chr(code) {
+                    Some(c) => self.push(QType::String(c.to_string())),
+                    None => return Err(QError::runtime(QErrorCode::IllegalFunctionCall, 0, 0)),
                 }
             }
             OpCode::Str => {
                 let n = self.pop()?;
-                self.push(QType::String(n.to_string()));
+                let text = self.localized(n.str_value()?);
+                self.push(QType::String(text));
             }
             OpCode::Val => {
                 let s = self.pop()?.to_qstring()?;
-                if let Ok(n) = s.parse::<f64>() {
-                    self.push(QType::Double(n));
-                } else {
-                    self.push(QType::Double(0.0));
-                }
+                let s = match self.decimal_separator {
+                    '.' => s,
+                    separator => s.replace(separator, "."),
+                };
+                self.push(QType::val(&s)?);
             }
             OpCode::UCase => {
                 let s = self.pop()?.to_qstring()?;
@@ -539,6 +1984,36 @@ impl VirtualMachine {
                 let s = self.pop()?.to_qstring()?;
                 self.push(QType::String(s.to_lowercase()));
             }
+            OpCode::Space => {
+                let count = self.pop_count()?;
+                self.push(QType::String(" ".repeat(count)));
+            }
+            OpCode::StringFill => {
+                let fill = match self.pop()? {
+                    QType::String(s) => s.chars().next(),
+                    code => self.dialect.chr(code.to_long()?),
+                };
+                let count = self.pop_count()?;
+                let fill = fill.ok_or_else(|| QError::runtime(QErrorCode::IllegalFunctionCall, 0, 0))?;
+                self.push(QType::String(fill.to_string().repeat(count)));
+            }
+            // Only spaces are trimmed, not tabs
+            OpCode::LTrim => {
+                let s = self.pop()?.to_qstring()?;
+                self.push(QType::String(s.trim_start_matches(' ').to_string()));
+            }
+            OpCode::RTrim => {
+                let s = self.pop()?.to_qstring()?;
+                self.push(QType::String(s.trim_end_matches(' ').to_string()));
+            }
+            OpCode::MkString(template) => {
+                let n = self.pop()?;
+                self.push(QType::String(n.to_binary_string(template, self.dialect)?));
+            }
+            OpCode::CvNumber(template) => {
+                let s = self.pop()?.to_qstring()?;
+                self.push(QType::from_binary_string(&s, template, self.dialect)?);
+            }
 
             OpCode::CInt => {
                 let n = self.pop()?;
@@ -569,21 +2044,143 @@ impl VirtualMachine {
             OpCode::IntOp => { let n = self.pop()?; self.push(n.math_int()?); }
             OpCode::Log => { let n = self.pop()?; self.push(n.math_log()?); }
             OpCode::Rnd => {
-                let _n = self.pop()?;
-                // Use rand crate to generate a number between 0.0 and 1.0 (exclusive of 1.0)
-                let r: f32 = rand::random::<f32>();
+                let n = self.pop()?.to_single()?;
+                let r = self.random.rnd(n);
                 self.push(QType::Single(r));
             }
+            OpCode::Randomize(has_seed) => {
+                let seed = if *has_seed {
+                    self.pop()?.to_double()?
+                } else {
+                    self.prompt_random_seed()?
+                };
+                self.random.randomize(seed);
+            }
+            OpCode::Timer(has_accuracy) => {
+                let accuracy = if *has_accuracy { Some(self.pop()?.to_double()?) } else { None };
+                let seconds = if self.dialect.coarse_timer() {
+                    self.clock.bios_seconds()
+                } else {
+                    self.clock.seconds_since_midnight()
+                };
+                // TIMER(accuracy) counts in steps of that many seconds
+                let seconds = match accuracy {
+                    Some(step) if step > 0.0 => (seconds / step).floor() * step,
+                    _ => seconds,
+                };
+                self.push(QType::Single(seconds as f32));
+            }
+            OpCode::Timer64 => {
+                let micros = (self.clock.seconds_since_midnight() * 1e6).floor();
+                self.push(QType::Double(micros / 1e6));
+            }
+            OpCode::Date => {
+                let (year, month, day) = self.clock.date();
+                self.push(QType::String(format!("{:02}-{:02}-{:04}", month, day, year)));
+            }
+            OpCode::Time => {
+                let seconds = self.clock.seconds_since_midnight() as u32;
+                let time = format!("{:02}:{:02}:{:02}", seconds / 3600, seconds / 60 % 60, seconds % 60);
+                self.push(QType::String(time));
+            }
+            OpCode::InKey => {
+                let key = self.read_key();
+                self.push(QType::String(key));
+            }
             OpCode::Sgn => { let n = self.pop()?; self.push(n.math_sgn()?); }
             OpCode::Sin => { let n = self.pop()?; self.push(n.math_sin()?); }
             OpCode::Sqr => { let n = self.pop()?; self.push(n.math_sqr()?); }
             OpCode::Tan => { let n = self.pop()?; self.push(n.math_tan()?); }
 
-            OpCode::EnterScope => {
-                self.local_scopes.push(HashMap::new());
+            OpCode::CallProc(addr, passing) => {
+                let mut args = Vec::with_capacity(passing.len());
+                for pass in passing.iter().rev() {
+                    args.push(match pass {
+                        ArgPass::Value => Binding::Value(self.pop()?),
+                        ArgPass::Var(name) => Binding::Ref(self.reference(name)),
+                        ArgPass::Element(name, dims) => {
+                            let indices = self.pop_n(*dims)?;
+                            self.dimension_implicitly(name, bytecode)?;
+                            self.element_reference(name, &indices)?
+                        }
+                        ArgPass::Array(name) => Binding::Array(self.array_alias(name).unwrap_or_else(|| name.clone())),
+                    });
+                }
+                args.reverse();
+                let mut frame = Frame::new(args, self.instruction_pointer + 1);
+                frame.outer_handler = (self.error_handler, self.handler_depth);
+                self.frames.push(frame);
+                self.instruction_pointer = *addr as usize;
+                return Ok(());
             }
-            OpCode::ExitScope => {
-                self.local_scopes.pop();
+            OpCode::EnterProc(entry) => {
+                let frame = self.frames.last_mut().ok_or_else(|| QError::runtime(QErrorCode::InternalError, 0, 0))?;
+                let args = std::mem::take(&mut frame.args);
+                let mut locals = HashMap::new();
+                let mut refs = HashMap::new();
+                let mut arrays = HashMap::new();
+                for (param, arg) in entry.params.iter().zip(args) {
+                    let blank = blank_value(param);
+                    let value = match arg {
+                        Binding::Value(value) => value,
+                        Binding::Array(name) => {
+                            arrays.insert(param.clone(), name);
+                            continue;
+                        }
+                        Binding::Ref(target) => {
+                            // A variable of another type is passed as a converted copy
+                            let value = self.load(&target)?;
+                            if std::mem::discriminant(&value) == std::mem::discriminant(&blank) {
+                                refs.insert(param.clone(), target);
+                                continue;
+                            }
+                            value
+                        }
+                    };
+                    locals.insert(param.clone(), Self::coerce(&blank, value)?);
+                }
+                if let Some((name, blank)) = &entry.result {
+                    locals.insert(name.clone(), blank.clone());
+                }
+                for (name, blank) in &entry.statics {
+                    let key = static_key(&entry.name, name);
+                    self.global_variables.entry(key.clone()).or_insert_with(|| blank.clone());
+                    refs.insert(name.clone(), Ref::Var(None, key));
+                }
+                if entry.all_static {
+                    // The variables kept from earlier calls
+                    let prefix = static_key(&entry.name, "");
+                    for key in self.global_variables.keys().filter(|key| key.starts_with(&prefix)) {
+                        let name = &key[prefix.len()..];
+                        if !locals.contains_key(name) && !refs.contains_key(name) {
+                            refs.insert(name.to_string(), Ref::Var(None, key.clone()));
+                        }
+                    }
+                }
+                let frame = self.frames.last_mut().expect("frame checked above");
+                frame.locals = locals;
+                frame.refs = refs;
+                frame.arrays = arrays;
+                frame.shared = entry.shared.clone();
+                frame.result = entry.result.as_ref().map(|(name, _)| name.clone());
+                frame.keeps = entry.all_static.then(|| entry.name.clone());
+            }
+            OpCode::LeaveProc => {
+                // EXIT SUB or FUNCTION from the procedure's own handler
+                // leaves it
+                if self.current_error.is_some() && self.handler_depth == self.frames.len() {
+                    self.current_error = None;
+                    self.error_number = 0;
+                    self.suspended_frames.clear();
+                }
+                let mut frame = self.frames.pop().ok_or_else(|| QError::runtime(QErrorCode::InternalError, 0, 0))?;
+                (self.error_handler, self.handler_depth) = frame.outer_handler;
+                if let Some(name) = &frame.result {
+                    let value = frame.locals.remove(name).unwrap_or(QType::Single(0.0));
+                    self.push(value);
+                }
+                self.instruction_pointer = frame.return_address;
+                return Ok(());
             }
 
             OpCode::Read => {
@@ -599,19 +2196,95 @@ impl VirtualMachine {
                 self.data_pointer = *addr as usize;
             }
 
-            OpCode::End => {
+            OpCode::OnError(Some(handler)) => {
+                self.error_handler = Some(*handler);
+                self.handler_depth = self.frames.len();
+            }
+            OpCode::OnError(None) => {
+                self.error_handler = None;
+                // ON ERROR GOTO 0 in a handler stops on the error it handles
+                if let Some(error) = self.current_error.take() {
+                    return Err(error);
+                }
+            }
+            OpCode::Resume(next) => {
+                // The failed line runs again from its start, or the next line runs
+                let after = bytecode.lines.partition_point(|&(start, _)| start <= self.error_address);
+                let address = if *next {
+                    bytecode.lines.get(after).map_or(bytecode.len(), |&(start, _)| start)
+                } else {
+                    after.checked_sub(1).map_or(0, |line| bytecode.lines[line].0)
+                };
+                return self.resume_at(address, true);
+            }
+            OpCode::ResumeAt(address) => return self.resume_at(*address as usize, false),
+            OpCode::Error => {
+                let number = self.pop()?.to_long()?;
+                if !(1..=255).contains(&number) {
+                    return Err(QError::runtime(QErrorCode::IllegalFunctionCall, 0, 0));
+                }
+                let code = QErrorCode::from_code(number).unwrap_or(QErrorCode::UnprintableError);
+                let line = bytecode.error_line(self.instruction_pointer);
+                return self.trap_error(number, QError::runtime(code, line, 0));
+            }
+            OpCode::Err => self.push(QType::Integer(self.error_number as i16)),
+            OpCode::Erl => {
+                let line = if self.current_error.is_some() { bytecode.line_number_at(self.error_address) } else { 0 };
+                self.push(QType::Long(line as i32));
+            }
+
+            OpCode::End(has_code) => {
+                if *has_code {
+                    self.exit_code = self.pop()?.to_long()?;
+                }
                 self.running = false;
             }
             OpCode::Stop => {
                 self.running = false;
             }
+            OpCode::Clear(has_stack) => {
+                if *has_stack {
+                    let size = self.pop()?.to_long()?;
+                    self.stack_size = usize::try_from(size)
+                        .map_err(|_| QError::runtime(QErrorCode::IllegalFunctionCall, 0, 0))?;
+                }
+                self.clear(bytecode)?;
+            }
+            OpCode::Chain => {
+                let name = self.pop()?.to_qstring()?;
+                let path = self.chain_target(&name)?;
+                let passed = bytecode.common.iter().map(|item| self.pass_on(item)).collect();
+                self.passed_common = passed;
+                self.reset_for_chain();
+                self.chain = Some(path);
+                self.running = false;
+            }
+            OpCode::Common(index) => {
+                let index = *index as usize;
+                match (self.passed_common.get_mut(index).and_then(Option::take), &bytecode.common[index]) {
+                    (None, _) => {}
+                    (Some(Passed::Value(value)), CommonItem::Variable(name, blank)) => {
+                        let value = Self::coerce(blank, value)?;
+                        self.set_variable(name, value)?;
+                    }
+                    (Some(Passed::Array(elements, shape, dynamic)), CommonItem::Array(name)) => {
+                        self.arrays.insert(name.clone(), elements);
+                        self.array_shapes.insert(name.clone(), shape);
+                        if dynamic {
+                            self.dynamic_arrays.insert(name.clone());
+                        }
+                    }
+                    _ => return Err(QError::runtime(QErrorCode::TypeMismatch, 0, 0)),
+                }
+            }
             OpCode::Nop => {}
             OpCode::Halt => {
+                // Running off the end of the module inside a handler
+                if self.current_error.is_some() {
+                    return Err(QError::runtime(QErrorCode::NoResume, 0, 0));
+                }
                 self.running = false;
             }
-            OpCode::PushRet(_) | OpCode::PopRet => {
-                // Not fully implemented
-            }
         }
 
         self.instruction_pointer += 1;
@@ -628,6 +2301,320 @@ impl VirtualMachine {
         })
     }
 
+    /// Poll devices with enabled traps and GOSUB to a handler whose event
+    /// has fired. Devices are polled every few hundred instructions.
+    fn check_events(&mut self) {
+        const POLL_INTERVAL: u32 = 256;
+
+        let strig = self.traps.is_watching(TrapSource::Strig);
+        let timer = self.traps.is_watching(TrapSource::Timer);
+        if !strig && !timer && !self.traps.is_watching(TrapSource::Key) {
+            return;
+        }
+        if let (true, Some((interval, due))) = (timer, &mut self.timer) {
+            let now = self.clock.elapsed();
+            if now >= *due {
+                *due = now + *interval;
+                self.traps.signal(TrapSource::Timer, 0);
+            }
+        }
+        if strig {
+            self.instructions_since_poll += 1;
+            if self.instructions_since_poll >= POLL_INTERVAL {
+                self.instructions_since_poll = 0;
+                self.joysticks.poll();
+            }
+            for event in self.joysticks.take_events() {
+                self.traps.signal(TrapSource::Strig, event.0);
+            }
+        }
+        if let Some(handler) = self.traps.take_ready(self.call_stack.len() + 1) {
+            self.call_stack.push(self.instruction_pointer);
+            self.instruction_pointer = handler as usize;
+        }
+    }
+
+    /// CLEAR: every variable and array element goes back to zero or an
+    /// empty string, keeping its type, except CONSTs; files and connections
+    /// are closed and pending GOSUB returns forgotten. Arrays are erased as
+    /// ERASE does: a static array keeps its bounds and a dynamic one is
+    /// freed. The VM's stacks grow as needed, so a stack size only changes
+    /// what FRE(-2) reports.
+    fn clear(&mut self, bytecode: &ByteCode) -> QResult<()> {
+        if !self.frames.is_empty() {
+            return Err(QError::runtime(QErrorCode::IllegalFunctionCall, 0, 0));
+        }
+        for (name, value) in self.global_variables.iter_mut() {
+            if !bytecode.const_names.contains(name) {
+                *value = value.default_value();
+            }
+        }
+        for name in std::mem::take(&mut self.dynamic_arrays) {
+            self.arrays.remove(&name);
+            self.array_shapes.remove(&name);
+        }
+        for value in self.arrays.values_mut().flatten() {
+            *value = value.default_value();
+        }
+        for value in self.udt_fields.values_mut().flat_map(|fields| fields.values_mut()) {
+            *value = value.default_value();
+        }
+        self.output = Sink::Screen;
+        self.files.close_all()?;
+        self.field_vars.clear();
+        self.net.close_all();
+        self.call_stack.clear();
+        Ok(())
+    }
+
+    /// The file CHAIN runs: the one `name` names, or with .BAS added when
+    /// it has no extension
+    fn chain_target(&self, name: &str) -> QResult<PathBuf> {
+        let path = self.vfs.resolve(name)?;
+        if path.is_file() {
+            return Ok(path);
+        }
+        if path.extension().is_none() {
+            let bas = self.vfs.resolve(&format!("{}.BAS", name.trim_end_matches('.')))?;
+            if bas.is_file() {
+                return Ok(bas);
+            }
+        }
+        Err(QError::runtime(QErrorCode::FileNotFound, 0, 0))
+    }
+
+    /// Take the value of a COMMON variable to pass on, if it has one
+    fn pass_on(&mut self, item: &CommonItem) -> Option<Passed> {
+        match item {
+            CommonItem::Variable(name, _) => self.global_variables.get(name).cloned().map(Passed::Value),
+            CommonItem::Array(name) => {
+                let elements = self.arrays.remove(name)?;
+                let shape = self.array_shapes.remove(name).unwrap_or_default();
+                Some(Passed::Array(elements, shape, self.dynamic_arrays.contains(name)))
+            }
+        }
+    }
+
+    /// Forget the program CHAIN leaves: its variables, arrays, calls and
+    /// event traps. Open files and the screen stay as they are.
+    fn reset_for_chain(&mut self) {
+        self.global_variables.clear();
+        self.frames.clear();
+        self.arrays.clear();
+        self.array_shapes.clear();
+        self.dynamic_arrays.clear();
+        self.dicts.clear();
+        self.udt_fields.clear();
+        self.field_vars.clear();
+        self.value_stack.clear();
+        self.call_stack.clear();
+        self.traps = EventTraps::new();
+        self.timer = None;
+    }
+
+    /// Bytes left as FRE sees them: near memory (FRE("") and FRE(0)), far
+    /// heap (FRE(-1)) and stack (FRE(-2)), given what the program's
+    /// variables, strings, arrays and calls would take under DOS
+    fn memory_free(&self) -> (usize, usize, usize) {
+        // A string takes a 4-byte descriptor in near memory plus its text
+        let near_size = |value: &QType| match value {
+            QType::String(s) => 4 + s.len(),
+            other => other.size(),
+        };
+        let mut near: usize = self.global_variables.values().map(near_size).sum();
+        near += self.udt_fields.values().flat_map(|fields| fields.values()).map(near_size).sum::<usize>();
+        let mut far = 0;
+        for value in self.arrays.values().flatten() {
+            match value {
+                QType::String(_) => near += near_size(value),
+                other => far += other.size(),
+            }
+        }
+        // A return address per GOSUB or call, and the locals of each SUB
+        let stack = 4 * self.call_stack.len()
+            + self.frames.iter().flat_map(|frame| frame.locals.values()).map(near_size).sum::<usize>();
+        (
+            NEAR_MEMORY.saturating_sub(near),
+            FAR_HEAP.saturating_sub(far),
+            self.stack_size.saturating_sub(stack),
+        )
+    }
+
+    /// Pop a segment or offset: -32768 to 65535, negative values standing
+    /// for the upper half as INTEGERs do
+    fn pop_word(&mut self) -> QResult<u16> {
+        let value = self.pop()?.to_long()?;
+        if !(-32768..=65535).contains(&value) {
+            return Err(QError::runtime(QErrorCode::Overflow, 0, 0));
+        }
+        Ok(value as u16)
+    }
+
+    /// Pop the length of a string to make, as SPACE$ and STRING$ take
+    fn pop_count(&mut self) -> QResult<usize> {
+        let count = self.pop()?.to_integer()?;
+        usize::try_from(count).map_err(|_| QError::runtime(QErrorCode::IllegalFunctionCall, 0, 0))
+    }
+
+    /// Pop a file number operand (the `#n` of file statements)
+    fn pop_file_number(&mut self) -> QResult<i32> {
+        self.pop()?.to_long()
+    }
+
+    /// Expand the X substrings and =variable; arguments of a DRAW or PLAY
+    /// string.
+    /// A reference is a variable name ended by ';', as written in GW-BASIC
+    /// or produced by VARPTR$ with its type code in front.
+    fn expand_references(&self, commands: &str, depth: usize) -> QResult<String> {
+        if depth > MACRO_NESTING {
+            return Err(QError::runtime(QErrorCode::IllegalFunctionCall, 0, 0));
+        }
+        let mut expanded = String::new();
+        let mut chars = commands.chars();
+        while let Some(c) = chars.next() {
+            match c {
+                'X' | 'x' => {
+                    let name = Self::macro_reference(&mut chars)?;
+                    let substring = self.get_variable(&name)?.to_qstring()?;
+                    expanded.push_str(&self.expand_references(&substring, depth + 1)?);
+                }
+                '=' => {
+                    let name = Self::macro_reference(&mut chars)?;
+                    expanded.push_str(&self.get_variable(&name)?.to_double()?.to_string());
+                }
+                c => expanded.push(c),
+            }
+        }
+        Ok(expanded)
+    }
+
+    /// Read a variable reference up to and including its ';'
+    fn macro_reference(chars: &mut std::str::Chars) -> QResult<String> {
+        let text: String = chars.by_ref().take_while(|&c| c != ';').collect();
+        let name = text.trim_start_matches(|c: char| c.is_ascii_control()).trim();
+        if name.is_empty() {
+            return Err(QError::runtime(QErrorCode::IllegalFunctionCall, 0, 0));
+        }
+        Ok(name.to_uppercase())
+    }
+
+    /// Pop a color argument. 32-bit colors arrive as negative LONGs or as
+    /// values above the LONG range; both are taken as their low 32 bits.
+    fn pop_color(&mut self) -> QResult<u32> {
+        let value = self.pop()?.to_double()?;
+        if !value.is_finite() || value < i32::MIN as f64 || value > u32::MAX as f64 {
+            return Err(QError::runtime(QErrorCode::Overflow, 0, 0));
+        }
+        Ok(value as i64 as u32)
+    }
+
+    /// The host path of a file name the program gives, through the VFS.
+    /// An empty name stays empty, for OPEN to report.
+    fn host_path(&self, filename: &str) -> QResult<String> {
+        Ok(self.vfs.resolve(filename)?.to_string_lossy().into_owned())
+    }
+
+    /// KILL: delete every file `filespec` matches, none of which may be
+    /// open. A device is no file to delete.
+    fn kill(&mut self, filespec: &str) -> QResult<()> {
+        if vfs::device(filespec).is_some() {
+            return Err(QError::runtime(QErrorCode::BadFileName, 0, 0));
+        }
+        let paths = self.vfs.matching(filespec)?;
+        if paths.is_empty() {
+            return Err(QError::runtime(QErrorCode::FileNotFound, 0, 0));
+        }
+        if paths.iter().any(|path| self.files.is_open(path)) {
+            return Err(QError::runtime(QErrorCode::FileAlreadyOpen, 0, 0));
+        }
+        for path in paths {
+            std::fs::remove_file(path).map_err(|_| QError::runtime(QErrorCode::PathFileAccessError, 0, 0))?;
+        }
+        Ok(())
+    }
+
+    /// Allocate an array the program uses without a DIM the first time it
+    /// is reached: a static array with each dimension 0 TO 10
+    fn dimension_implicitly(&mut self, name: &str, bytecode: &ByteCode) -> QResult<()> {
+        let Some((dims, type_str)) = bytecode.implicit_arrays.get(name) else {
+            return Ok(());
+        };
+        if self.array_alias(name).is_some() || self.array_shapes.contains_key(name) {
+            return Ok(());
+        }
+        self.redim(name.to_string(), vec![(0, 10); *dims], array_blank(type_str), false)?;
+        self.dynamic_arrays.remove(name);
+        Ok(())
+    }
+
+    /// REDIM: make array `name` the `shape` given, all `blank`. With
+    /// PRESERVE only the last dimension may change, and the elements of
+    /// each row it holds are kept from the start, as many as still fit.
+    fn redim(&mut self, name: String, shape: Vec<(i32, i32)>, blank: QType, preserve: bool) -> QResult<()> {
+        let out_of_range = || QError::runtime(QErrorCode::SubscriptOutOfRange, 0, 0);
+        let span = |&(lo, hi): &(i32, i32)| {
+            usize::try_from(i64::from(hi) - i64::from(lo) + 1).ok().filter(|&n| n > 0).ok_or_else(out_of_range)
+        };
+        let mut total = 1usize;
+        for dim in &shape {
+            total = total.checked_mul(span(dim)?).ok_or_else(|| QError::runtime(QErrorCode::OutOfMemory, 0, 0))?;
+        }
+        let mut elements = Vec::new();
+        elements
+            .try_reserve_exact(total)
+            .map_err(|_| QError::runtime(QErrorCode::OutOfMemory, 0, 0))?;
+        let old = if preserve { self.array_shapes.get(&name).zip(self.arrays.get(&name)) } else { None };
+        match old {
+            Some((old_shape, values)) => {
+                let rows = shape.len().saturating_sub(1);
+                if old_shape.len() != shape.len() || old_shape[..rows] != shape[..rows] {
+                    return Err(out_of_range());
+                }
+                let (old_len, new_len) = match (old_shape.last(), shape.last()) {
+                    (Some(old_last), Some(new_last)) => (span(old_last)?, span(new_last)?),
+                    _ => (1, 1),
+                };
+                for row in values.chunks(old_len) {
+                    elements.extend(row.iter().take(new_len).cloned());
+                    elements.extend(std::iter::repeat_n(blank.clone(), new_len.saturating_sub(old_len)));
+                }
+            }
+            None => elements.resize(total, blank),
+        }
+        self.arrays.insert(name.clone(), elements);
+        self.dynamic_arrays.insert(name.clone());
+        self.array_shapes.insert(name, shape);
+        Ok(())
+    }
+
+    /// Pop a device, button or axis number, which counts from 1
+    fn pop_device(&mut self) -> QResult<usize> {
+        let n = self.pop()?.to_long()?;
+        usize::try_from(n).map_err(|_| QError::runtime(QErrorCode::IllegalFunctionCall, 0, 0))
+    }
+
+    /// Pop the red, green and blue arguments of a color function
+    fn pop_channels(&mut self) -> QResult<[i32; 3]> {
+        let b = self.pop()?.to_long()?;
+        let g = self.pop()?.to_long()?;
+        let r = self.pop()?.to_long()?;
+        Ok([r, g, b])
+    }
+
+    /// Pop the corners of a _PUTIMAGE area pushed by the compiler
+    fn pop_image_area(&mut self, corners: u8) -> QResult<Area> {
+        let coords = self
+            .pop_n(usize::from(corners) * 2)?
+            .iter()
+            .map(|value| value.to_single().map(|v| v.round() as i32))
+            .collect::<QResult<Vec<_>>>()?;
+        Ok(match coords[..] {
+            [x, y] => Area::At(x, y),
+            [x1, y1, x2, y2] => Area::Rect(x1, y1, x2, y2),
+            _ => Area::Whole,
+        })
+    }
+
     fn pop_n(&mut self, n: usize) -> QResult<Vec<QType>> {
         if self.value_stack.len() < n {
             return Err(QError::runtime(QErrorCode::OutOfMemory, 0, 0));
@@ -642,43 +2629,196 @@ impl VirtualMachine {
         })
     }
 
+    /// Note a write about to be made to a watched variable or element, so
+    /// `resume` pauses after this instruction
+    fn check_watches(&mut self, name: &str, indices: &[QType], value: &QType, bytecode: &ByteCode) {
+        let Ok(subscripts) = indices.iter().map(QType::to_long).collect::<QResult<Vec<i32>>>() else {
+            return;
+        };
+        if !self.watches.iter().any(|w| w.matches(name, &subscripts)) {
+            return;
+        }
+        let old = if indices.is_empty() {
+            self.get_variable(name)
+        } else {
+            self.get_array_element(name, indices)
+        };
+        // A bad subscript fails the store itself
+        let Ok(old) = old else { return };
+        let indices = if indices.is_empty() { None } else { Some(subscripts) };
+        self.watch_hit = Some(WatchHit {
+            target: Watch { name: name.to_string(), indices },
+            old,
+            new: value.clone(),
+            line: bytecode.line_at(self.instruction_pointer),
+        });
+    }
+
     fn get_variable(&self, name: &str) -> QResult<QType> {
-        // Check local scopes first
-        for scope in self.local_scopes.iter().rev() {
-            if let Some(value) = scope.get(name) {
-                return Ok(value.clone());
+        // Inside a procedure: its locals and parameters, then what it shares
+        let global = match self.frames.last() {
+            Some(frame) => {
+                if let Some(value) = frame.locals.get(name) {
+                    return Ok(value.clone());
+                }
+                if let Some(target) = frame.refs.get(name) {
+                    return self.load(target);
+                }
+                frame.shares(name)
             }
-        }
-        // Check global variables
-        if let Some(value) = self.global_variables.get(name) {
+            None => true,
+        };
+        if let Some(value) = self.global_variables.get(name).filter(|_| global) {
             return Ok(value.clone());
         }
-        // Return default value for undeclared variables
-        Ok(QType::Single(0.0))
+        if self.strict {
+            let message = format!("{}: {}", QErrorCode::VariableNotDefined.as_str(), name);
+            return Err(QError::runtime_with_msg(QErrorCode::VariableNotDefined, message, 0, 0));
+        }
+        // An unassigned variable is the blank value of its type
+        Ok(blank_value(name))
     }
 
     fn set_variable(&mut self, name: &str, value: QType) -> QResult<()> {
-        // Check if variable exists in any local scope (from innermost to outermost)
-        for scope in self.local_scopes.iter_mut().rev() {
-            if let Some(v) = scope.get_mut(name) {
-                *v = value;
-                return Ok(());
+        if let Some(target) = self.static_reference(name) {
+            return self.store(&target, value);
+        }
+        let mut variables = &mut self.global_variables;
+        if let Some(frame) = self.frames.last_mut() {
+            if let Some(target) = frame.refs.get(name).cloned() {
+                return self.store(&target, value);
+            }
+            if frame.locals.contains_key(name) || !frame.shares(name) {
+                variables = &mut frame.locals;
             }
         }
-        // Check if variable exists in global scope
-        if let Some(v) = self.global_variables.get_mut(name) {
-            *v = value;
+        if let Some(v) = variables.get_mut(name) {
+            *v = Self::coerce(v, value)?;
         } else {
-            // New variable - set in current local scope if exists, otherwise global
-            if let Some(scope) = self.local_scopes.last_mut() {
-                scope.insert(name.to_string(), value);
-            } else {
-                self.global_variables.insert(name.to_string(), value);
+            // New variable - a suffixed name takes the suffix's type
+            let value = match TypeSuffix::of_name(name) {
+                Some(suffix) => Self::coerce(&suffix.default_value(), value)?,
+                None => value,
+            };
+            variables.insert(name.to_string(), value);
+        }
+        Ok(())
+    }
+
+    /// A new variable of a procedure declared STATIC, which is kept as a
+    /// module-level one: what it refers to, now bound in the frame
+    fn static_reference(&mut self, name: &str) -> Option<Ref> {
+        let frame = self.frames.last_mut()?;
+        let proc = frame.keeps.as_ref()?;
+        if frame.locals.contains_key(name) || frame.refs.contains_key(name) || frame.shares(name) {
+            return None;
+        }
+        let key = static_key(proc, name);
+        frame.refs.insert(name.to_string(), Ref::Var(None, key.clone()));
+        self.global_variables.entry(key.clone()).or_insert_with(|| blank_value(name));
+        Some(Ref::Var(None, key))
+    }
+
+    /// What passing variable `name` BYREF refers to, creating the variable
+    /// if it does not exist yet
+    fn reference(&mut self, name: &str) -> Ref {
+        if let Some(target) = self.static_reference(name) {
+            return target;
+        }
+        let depth = self.frames.len().checked_sub(1);
+        if let Some(frame) = self.frames.last_mut() {
+            if let Some(target) = frame.refs.get(name) {
+                return target.clone();
+            }
+            if frame.locals.contains_key(name) || !frame.shares(name) {
+                frame.locals.entry(name.to_string()).or_insert_with(|| blank_value(name));
+                return Ref::Var(depth, name.to_string());
             }
         }
+        self.global_variables.entry(name.to_string()).or_insert_with(|| blank_value(name));
+        Ref::Var(None, name.to_string())
+    }
+
+    /// The caller's array that an array parameter stands for, if `name` is one
+    fn array_alias(&self, name: &str) -> Option<String> {
+        self.frames.last()?.arrays.get(name).cloned()
+    }
+
+    /// What passing an array element BYREF refers to; a _DICT entry is
+    /// passed as a copy
+    fn element_reference(&self, name: &str, indices: &[QType]) -> QResult<Binding> {
+        if self.dicts.contains_key(name) {
+            return self.get_array_element(name, indices).map(Binding::Value);
+        }
+        let alias = self.array_alias(name);
+        let name = alias.as_deref().unwrap_or(name);
+        let subscripts = indices.iter().map(QType::to_long).collect::<QResult<Vec<_>>>()?;
+        self.array_shapes
+            .get(name)
+            .and_then(|shape| flat_index(shape, &subscripts))
+            .map(|index| Binding::Ref(Ref::Element(name.to_string(), index)))
+            .ok_or_else(|| QError::runtime(QErrorCode::SubscriptOutOfRange, 0, 0))
+    }
+
+    fn load(&self, target: &Ref) -> QResult<QType> {
+        let value = match target {
+            Ref::Var(Some(depth), name) => self.frames.get(*depth).and_then(|frame| frame.locals.get(name)),
+            Ref::Var(None, name) => self.global_variables.get(name),
+            Ref::Element(name, index) => self.arrays.get(name).and_then(|elements| elements.get(*index)),
+        };
+        value.cloned().ok_or_else(|| QError::runtime(QErrorCode::SubscriptOutOfRange, 0, 0))
+    }
+
+    fn store(&mut self, target: &Ref, value: QType) -> QResult<()> {
+        let slot = match target {
+            Ref::Var(Some(depth), name) => self.frames.get_mut(*depth).and_then(|frame| frame.locals.get_mut(name)),
+            Ref::Var(None, name) => self.global_variables.get_mut(name),
+            Ref::Element(name, index) => self.arrays.get_mut(name).and_then(|elements| elements.get_mut(*index)),
+        };
+        let slot = slot.ok_or_else(|| QError::runtime(QErrorCode::SubscriptOutOfRange, 0, 0))?;
+        *slot = Self::coerce(slot, value)?;
         Ok(())
     }
 
+    /// Bytes a value takes in a record: a string its characters, other
+    /// types their memory image
+    fn record_size(value: &QType) -> usize {
+        match value {
+            QType::String(s) => s.chars().count(),
+            other => other.size(),
+        }
+    }
+
+    /// GET # of a file: a value of `template`'s type from a RANDOM record
+    /// or a BINARY position
+    fn get_record(&mut self, fileno: i32, position: Option<i64>, template: QType) -> QResult<QType> {
+        let (mode, record_len) = self.files.shape(fileno)?;
+        if mode == OpenMode::Random && matches!(template, QType::String(_)) {
+            // A variable-length string is kept after its 2-byte length
+            if record_len < 2 {
+                return Err(QError::runtime(QErrorCode::BadRecordLength, 0, 0));
+            }
+            let bytes = self.files.get_bytes(fileno, position, record_len)?;
+            let len = usize::from(u16::from_le_bytes([bytes[0], bytes[1]])).min(record_len - 2);
+            return Ok(template.from_bytes(&bytes[2..2 + len], self.dialect));
+        }
+        let bytes = self.files.get_bytes(fileno, position, Self::record_size(&template))?;
+        Ok(template.from_bytes(&bytes, self.dialect))
+    }
+
+    /// PUT # of a value to a file, as a RANDOM record or at a BINARY position
+    fn put_record(&mut self, fileno: i32, position: Option<i64>, value: &QType) -> QResult<()> {
+        let (mode, _) = self.files.shape(fileno)?;
+        let mut bytes = value.to_bytes(self.dialect);
+        if mode == OpenMode::Random {
+            if let QType::String(_) = value {
+                let len = u16::try_from(bytes.len()).map_err(|_| QError::runtime(QErrorCode::BadRecordLength, 0, 0))?;
+                bytes.splice(0..0, len.to_le_bytes());
+            }
+        }
+        self.files.put_bytes(fileno, position, &bytes)
+    }
+
     fn get_field(&self, var: &str, field: &str) -> QResult<QType> {
         if let Some(fields) = self.udt_fields.get(var) {
             if let Some(value) = fields.get(field) {
@@ -696,58 +2836,315 @@ impl VirtualMachine {
     }
 
     fn get_array_element(&self, name: &str, indices: &[QType]) -> QResult<QType> {
-        if let Some(shape) = self.array_shapes.get(name) {
-            if indices.len() != shape.len() {
-                return Err(QError::runtime(QErrorCode::SubscriptOutOfRange, 0, 0));
+        if let Some(dict) = self.dicts.get(name) {
+            return Ok(dict.get(&dict::key(indices)?));
+        }
+        let alias = self.array_alias(name);
+        let name = alias.as_deref().unwrap_or(name);
+        let subscripts = indices.iter().map(QType::to_long).collect::<QResult<Vec<_>>>()?;
+        self.array(name)
+            .and_then(|view| view.get(&subscripts).cloned())
+            .ok_or_else(|| QError::runtime(QErrorCode::SubscriptOutOfRange, 0, 0))
+    }
+
+    /// Value to store over `old`: numbers and strings keep the variable's
+    /// type, as assignment converts to it
+    fn coerce(old: &QType, value: QType) -> QResult<QType> {
+        if old.is_numeric() || old.is_string() {
+            value.convert_to(old)
+        } else {
+            Ok(value)
+        }
+    }
+
+    fn set_array_element(&mut self, name: &str, indices: &[QType], value: QType) -> QResult<()> {
+        if let Some(dict) = self.dicts.get_mut(name) {
+            return dict.set(dict::key(indices)?, value);
+        }
+        let alias = self.array_alias(name);
+        let name = alias.as_deref().unwrap_or(name);
+        let subscripts = indices.iter().map(QType::to_long).collect::<QResult<Vec<_>>>()?;
+        self.write_array(name, &subscripts, &[value])
+    }
+
+    /// RANDOMIZE without a seed asks for one, repeating until it is a valid integer
+    fn prompt_random_seed(&mut self) -> QResult<f64> {
+        loop {
+            self.console_write("Random-number seed (-32768 to 32767)? ");
+            self.console_flush()?;
+            let Some(line) = self.read_console_line()? else {
+                return Err(QError::runtime(QErrorCode::InputPastEndOfFile, 0, 0));
+            };
+            if self.echoes_input() {
+                self.cursor_column = 0;
+            } else {
+                self.console_write("\n");
+            }
+            if let Ok(seed) = line.trim().parse::<i16>() {
+                return Ok(f64::from(seed));
+            }
+        }
+    }
+
+    /// INKEY$: the next key typed, as "" when there is none, its character,
+    /// or CHR$(0) and the scan code for arrows and function keys. Keys
+    /// wait in the 16-byte BIOS buffer, which drops them once it is full.
+    fn read_key(&mut self) -> String {
+        self.poll_keys();
+        match self.memory.keys.pop() {
+            None => String::new(),
+            Some((0, scan)) => ['\0', char::from(scan)].iter().collect(),
+            Some((ascii, _)) => self.dialect.chr(i32::from(ascii)).map(String::from).unwrap_or_default(),
+        }
+    }
+
+    /// Move keys typed at the terminal or into the window, or replayed, to
+    /// the BIOS buffer; true if any were typed, even if the buffer had no
+    /// room
+    fn poll_keys(&mut self) -> bool {
+        let keys = if self.console_input.is_replay() {
+            self.console_input.replay_keys()
+        } else {
+            let mut keys = self.screen_window.as_mut().map(FramebufferWindow::take_keys).unwrap_or_default();
+            keys.extend(keyboard::poll_terminal(&self.keymap));
+            self.console_input.record_keys(&keys);
+            keys
+        };
+        for &(ascii, scan) in &keys {
+            self.memory.keys.push(ascii, scan);
+        }
+        !keys.is_empty()
+    }
+
+    /// SLEEP: wait `seconds`, or with 0 until a key is typed, ending early
+    /// when one is. The key stays in the buffer for INKEY$. Where no key
+    /// can be typed, as when input is piped or replayed, waiting for one
+    /// returns at once.
+    fn sleep(&mut self, seconds: i32) -> QResult<()> {
+        const POLL_INTERVAL: Duration = Duration::from_millis(10);
+
+        if seconds < 0 {
+            return Err(QError::runtime(QErrorCode::IllegalFunctionCall, 0, 0));
+        }
+        self.present_frame(true)?;
+        let keys_possible = if self.console_input.is_replay() {
+            self.console_input.has_key()
+        } else {
+            self.screen_window.is_some() || io::stdin().is_terminal()
+        };
+        let deadline = (seconds > 0).then(|| self.clock.elapsed() + Duration::from_secs(seconds as u64));
+        if deadline.is_none() && !keys_possible {
+            return Ok(());
+        }
+        self.console_flush()?;
+        while self.running && !self.poll_keys() {
+            // Ctrl+Break ends the wait and is left for the VM to act on
+            if break_key::take() {
+                break_key::press();
+                break;
+            }
+            let now = self.clock.elapsed();
+            let wait = match deadline {
+                Some(deadline) if now >= deadline => break,
+                Some(deadline) => (deadline - now).min(POLL_INTERVAL),
+                None => POLL_INTERVAL,
+            };
+            self.clock.sleep(wait);
+            self.present_frame(false)?;
+            self.sound.update();
+        }
+        Ok(())
+    }
+
+    /// Write PRINT and WRITE output to the selected sink
+    fn write_output(&mut self, text: &str) -> QResult<()> {
+        match self.output {
+            Sink::File(fileno) => {
+                self.files.write_str(fileno, text)?;
+                if self.files.is_screen(fileno) {
+                    self.write_screen(text)?;
+                }
+                Ok(())
             }
-            // Calculate flat index using proper stride calculation
-            let mut flat_idx = 0usize;
-            for (i, (idx, &(lo, hi))) in indices.iter().zip(shape.iter()).enumerate() {
-                let idx_val = idx.to_long()?;
-                if idx_val < lo || idx_val > hi {
-                    return Err(QError::runtime(QErrorCode::SubscriptOutOfRange, 0, 0));
+            Sink::Printer => self.printer.write_str(text),
+            Sink::Screen => self.write_screen(text),
+        }
+    }
+
+    fn write_screen(&mut self, text: &str) -> QResult<()> {
+        if self.prints_to_image() {
+            self.graphics.print_text(text)
+        } else if self.console_dest && !self.console_visible {
+            // _CONSOLE OFF hides what is printed to the console
+            Ok(())
+        } else {
+            self.console_write(text);
+            Ok(())
+        }
+    }
+
+    /// Screen output goes onto the _DEST image rather than to the terminal:
+    /// in graphics modes, or when _DEST is an image, unless it is _CONSOLE
+    fn prints_to_image(&self) -> bool {
+        !self.console_dest && !self.graphics.dest_is_text()
+    }
+
+    /// Whether a _DEST/_SOURCE handle names the console; without $CONSOLE,
+    /// _CONSOLE is 0 and so names the screen
+    fn is_console(&self, handle: i32) -> bool {
+        self.console && handle == CONSOLE_HANDLE
+    }
+
+    /// Write text to the console, keeping track of the cursor column
+    fn console_write(&mut self, text: &str) {
+        self.console_raw(text);
+        match text.rfind('\n') {
+            Some(pos) => self.cursor_column = text[pos + 1..].chars().count(),
+            None => self.cursor_column += text.chars().count(),
+        }
+    }
+
+    /// Write to the console as it is, escape sequences and all
+    fn console_raw(&mut self, text: &str) {
+        match &mut self.console_out {
+            Some(out) => {
+                // As print! would, but a closed pipe is not an error
+                let _ = out.write_all(text.as_bytes());
+            }
+            None => print!("{}", text),
+        }
+    }
+
+    fn console_flush(&mut self) -> io::Result<()> {
+        match &mut self.console_out {
+            Some(out) => out.flush(),
+            None => io::stdout().flush(),
+        }
+    }
+
+    /// Whether someone is at the console, typing and seeing it echoed
+    fn console_is_terminal(&self) -> bool {
+        self.console_out.is_none() && io::stdout().is_terminal()
+    }
+
+    /// Whether the terminal echoes what is typed, ENTER included; replayed
+    /// input is echoed by the VM instead
+    fn echoes_input(&self) -> bool {
+        self.console_is_terminal() && !self.console_input.is_replay()
+    }
+
+    /// The next line typed at the console. A replayed line is shown as the
+    /// user would have typed it, up to the ENTER.
+    fn read_console_line(&mut self) -> QResult<Option<String>> {
+        let line = self.console_input.read_line()?;
+        if let Some(text) = line.as_ref().filter(|_| self.console_input.is_replay()) {
+            self.console_write(text);
+        }
+        Ok(line)
+    }
+
+    /// INPUT: read one comma-separated line into all target variables,
+    /// re-prompting with "?Redo from start" until the line fits
+    fn input_statement(&mut self, prompt: &str, same_line: bool, vars: &[String]) -> QResult<()> {
+        let interactive = self.echoes_input();
+        self.present_frame(true)?;
+        loop {
+            self.console_write(prompt);
+            self.console_flush()?;
+            let Some(line) = self.read_console_line()? else {
+                return Err(QError::runtime(QErrorCode::InputPastEndOfFile, 0, 0));
+            };
+            let line = line.as_str();
+
+            if same_line {
+                if interactive {
+                    // Undo the newline the terminal echoed for ENTER
+                    let column = self.cursor_column + line.chars().count();
+                    self.console_raw(&format!("\x1b[1A\x1b[{}G", column + 1));
+                    self.cursor_column = column;
                 }
-                // Calculate stride: product of sizes of all remaining dimensions
-                let stride: usize = shape.iter().skip(i + 1)
-                    .map(|&(l, h)| (h - l + 1) as usize)
-                    .product();
-                flat_idx += (idx_val - lo) as usize * stride;
+            } else if interactive {
+                self.cursor_column = 0;
+            } else {
+                self.console_write("\n");
             }
-            if let Some(arr) = self.arrays.get(name) {
-                if flat_idx < arr.len() {
-                    return Ok(arr[flat_idx].clone());
+
+            if let Some(values) = self.parse_input_fields(line, vars) {
+                for (name, value) in vars.iter().zip(values) {
+                    self.set_variable(name, value)?;
                 }
+                return Ok(());
             }
+            if self.cursor_column != 0 {
+                self.console_write("\n");
+            }
+            self.console_write("?Redo from start\n");
         }
-        Err(QError::runtime(QErrorCode::SubscriptOutOfRange, 0, 0))
     }
 
-    fn set_array_element(&mut self, name: &str, indices: &[QType], value: QType) -> QResult<()> {
-        if let Some(shape) = self.array_shapes.get(name) {
-            if indices.len() != shape.len() {
-                return Err(QError::runtime(QErrorCode::SubscriptOutOfRange, 0, 0));
-            }
-            // Calculate flat index using proper stride calculation
-            let mut flat_idx = 0usize;
-            for (i, (idx, &(lo, hi))) in indices.iter().zip(shape.iter()).enumerate() {
-                let idx_val = idx.to_long()?;
-                if idx_val < lo || idx_val > hi {
-                    return Err(QError::runtime(QErrorCode::SubscriptOutOfRange, 0, 0));
-                }
-                // Calculate stride: product of sizes of all remaining dimensions
-                let stride: usize = shape.iter().skip(i + 1)
-                    .map(|&(l, h)| (h - l + 1) as usize)
-                    .product();
-                flat_idx += (idx_val - lo) as usize * stride;
-            }
-            if let Some(arr) = self.arrays.get_mut(name) {
-                if flat_idx < arr.len() {
-                    arr[flat_idx] = value;
-                    return Ok(());
+    /// Split an INPUT line into one value per variable, typed by the target.
+    /// Returns None when the field count or a number is wrong.
+    fn parse_input_fields(&self, line: &str, vars: &[String]) -> Option<Vec<QType>> {
+        let mut fields = Vec::new();
+        let mut current = String::new();
+        let mut quoted = false;
+        for c in line.chars() {
+            match c {
+                '"' => {
+                    quoted = !quoted;
+                    current.push(c);
                 }
+                ',' if !quoted => fields.push(std::mem::take(&mut current)),
+                _ => current.push(c),
             }
         }
-        Err(QError::runtime(QErrorCode::SubscriptOutOfRange, 0, 0))
+        fields.push(current);
+        if fields.len() != vars.len() {
+            return None;
+        }
+
+        vars.iter()
+            .zip(fields)
+            .map(|(name, field)| self.input_value(name, field.trim()))
+            .collect()
+    }
+
+    /// Convert one INPUT field to the type of its target variable; None if
+    /// a numeric field is malformed or out of range
+    fn input_value(&self, name: &str, field: &str) -> Option<QType> {
+        let template = self.variable_template(name);
+        if matches!(template, QType::String(_) | QType::FixedString(..)) {
+            let text = field
+                .strip_prefix('"')
+                .map(|f| f.strip_suffix('"').unwrap_or(f))
+                .unwrap_or(field);
+            return Some(QType::String(text.to_string()));
+        }
+        let number = if field.is_empty() { 0.0 } else { field.parse::<f64>().ok()? };
+        match template {
+            QType::Integer(_) | QType::Long(_) => QType::Double(number).convert_to(&template).ok(),
+            QType::Double(_) => Some(QType::Double(number)),
+            _ => Some(QType::Single(number as f32)),
+        }
+    }
+
+    /// Current value of a variable, or the default for its type suffix
+    fn variable_template(&self, name: &str) -> QType {
+        let existing = match self.frames.last() {
+            Some(frame) => frame.locals.get(name).cloned()
+                .or_else(|| frame.refs.get(name).and_then(|target| self.load(target).ok())),
+            None => None,
+        };
+        if let Some(value) = existing.or_else(|| self.global_variables.get(name).cloned()) {
+            return value;
+        }
+        match name.chars().last() {
+            Some('$') => QType::String(String::new()),
+            Some('%') => QType::Integer(0),
+            Some('&') => QType::Long(0),
+            Some('#') => QType::Double(0.0),
+            _ => QType::Single(0.0),
+        }
     }
 
     fn is_truthy(&self, value: &QType) -> bool {
@@ -773,3 +3170,839 @@ pub fn run(bytecode: &ByteCode) -> QResult<()> {
     let mut vm = VirtualMachine::new();
     vm.execute(bytecode)
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::compiler::compile;
+    use crate::session::RecordedInput;
+    use qb_lexer::{tokenize, tokenize_dialect};
+    use qb_parser::parse;
+    use qb_semantic::analyze;
+    use std::cell::RefCell;
+    use std::rc::Rc;
+
+    /// Build `source` and run it on a VM `setup` has configured, reading
+    /// the source as `tokenize` does
+    fn run_with(source: &str, setup: impl FnOnce(&mut VirtualMachine)) -> QResult<VirtualMachine> {
+        let mut program = parse(tokenize(source)?)?;
+        analyze(&mut program)?;
+        let mut vm = VirtualMachine::new();
+        setup(&mut vm);
+        vm.execute(&compile(&program)?)?;
+        Ok(vm)
+    }
+
+    fn run(source: &str) -> QResult<VirtualMachine> {
+        run_with(source, |_| {})
+    }
+
+    /// Build `source` written for `dialect` and run it in that dialect
+    fn run_in(source: &str, dialect: Dialect) -> QResult<VirtualMachine> {
+        let mut program = parse(tokenize_dialect(source, dialect)?)?;
+        analyze(&mut program)?;
+        let mut vm = VirtualMachine::new();
+        vm.set_dialect(dialect);
+        vm.execute(&compile(&program)?)?;
+        Ok(vm)
+    }
+
+    /// The code of the runtime error a run ended with
+    fn error_code(result: QResult<VirtualMachine>) -> Option<QErrorCode> {
+        match result {
+            Err(QError::Runtime { code, .. }) => Some(code),
+            _ => None,
+        }
+    }
+
+    #[test]
+    fn test_array_descriptors() {
+        let source = "DIM grid(1 TO 2, 0 TO 2) AS INTEGER\nFOR i = 1 TO 2\nFOR j = 0 TO 2\ngrid(i, j) = i * 10 + j\nNEXT j\nNEXT i\n";
+        let mut vm = run(source).unwrap();
+
+        let arrays = vm.arrays();
+        let (name, grid) = &arrays[0];
+        let name = name.to_string();
+        assert_eq!((grid.rank(), grid.element_type()), (2, "INTEGER"));
+        assert_eq!(grid.get(&[2, 1]), Some(&QType::Integer(21)));
+        assert_eq!(grid.offset(&[2, 0]), Some(3));
+        assert_eq!(grid.subscripts(3), [2, 0]);
+        assert_eq!(grid.get(&[3, 0]), None);
+        assert_eq!(grid.to_f64().unwrap(), [10.0, 11.0, 12.0, 20.0, 21.0, 22.0]);
+
+        // Values are converted to the element type; a write past the end changes nothing
+        vm.write_array(&name, &[1, 2], &[QType::Double(7.6), QType::Single(-1.0)]).unwrap();
+        assert!(vm.write_array(&name, &[2, 2], &[QType::Integer(0), QType::Integer(0)]).is_err());
+        let grid = vm.array(&name).unwrap();
+        assert_eq!(grid.to_f64().unwrap(), [10.0, 11.0, 8.0, -1.0, 21.0, 22.0]);
+        assert_eq!(grid.get(&[2, 0]), Some(&QType::Integer(-1)));
+    }
+
+    #[test]
+    fn test_procedures() {
+        let source = "DIM SHARED calls AS INTEGER\nx = 5\nCALL Bump(x, x)\nr# = Fact#(10)\n\
+                      SUB Bump (n, BYVAL copy)\nn = n + 1\ncopy = 0\nx = 99\nEND SUB\n\
+                      FUNCTION Fact# (k AS INTEGER)\ncalls = calls + 1\nIF k > 1 THEN Fact# = k * Fact#(k - 1) ELSE Fact# = 1\nEND FUNCTION\n";
+        let vm = run(source).unwrap();
+        // BYREF n changed x; the SUB's own x is a local
+        assert_eq!(vm.get_variable("X!").unwrap(), QType::Single(6.0));
+        assert_eq!(vm.get_variable("R#").unwrap(), QType::Double(3_628_800.0));
+        assert_eq!(vm.get_variable("CALLS%").unwrap(), QType::Integer(10));
+
+        let program = parse(tokenize("CALL Bump(1, 2)\nSUB Bump (n)\nEND SUB\n").unwrap()).unwrap();
+        assert!(compile(&program).is_err());
+    }
+
+    #[test]
+    fn test_select_case() {
+        let source = "s$ = \"\"\nFOR i = 0 TO 7\nSELECT CASE i\nCASE 1, 2\ns$ = s$ + \"a\"\nCASE 3\n\
+                      CASE IS > 5, 4 TO 4\ns$ = s$ + \"b\"\nCASE ELSE\ns$ = s$ + \"e\"\nEND SELECT\nNEXT\n";
+        let vm = run(source).unwrap();
+        assert_eq!(vm.get_variable("S$").unwrap(), QType::String("eaabebb".to_string()));
+    }
+
+    #[test]
+    fn test_string_functions() {
+        let source = "a$ = \"  hi \" + CHR$(9) + \" \"\nt$ = \"[\" + LTRIM$(a$) + \"|\" + RTRIM$(a$) + \"]\"\n\
+                      f$ = SPACE$(2) + STRING$(3, \"xy\") + STRING$(2, 65) + MID$(\"hello\", 2) + MID$(\"hello\", 2, 2)\n\
+                      f$ = f$ + MID$(\"hi\", 5) + MID$(\"hi\", 1, 0)\nn = INSTR(\"hello\", \"l\") * 10 + INSTR(4, \"hello\", \"l\")\n\
+                      ON ERROR GOTO h\nb$ = SPACE$(-1)\nb$ = STRING$(2, \"\")\nb$ = MID$(\"x\", 0)\nb$ = MID$(\"x\", 1, -1)\nEND\n\
+                      h: e = e + 1: RESUME NEXT\n";
+        let vm = run(source).unwrap();
+        assert_eq!(vm.get_variable("T$").unwrap(), QType::String("[hi \t |  hi \t]".to_string()));
+        assert_eq!(vm.get_variable("F$").unwrap(), QType::String("  xxxAAelloel".to_string()));
+        assert_eq!(vm.get_variable("N!").unwrap(), QType::Single(34.0));
+        assert_eq!(vm.get_variable("E!").unwrap(), QType::Single(4.0));
+    }
+
+    #[test]
+    fn test_binary_strings() {
+        let source = "p$ = MKI$(-300) + MKL$(100000) + MKS$(1.5) + MKD$(3.25#)\nn = LEN(p$)\n\
+                      i% = CVI(p$)\nl& = CVL(MID$(p$, 3))\ns! = CVS(MID$(p$, 7))\nd# = CVD(RIGHT$(p$, 8))\n";
+        let vm = run(source).unwrap();
+        assert_eq!(vm.get_variable("N!").unwrap(), QType::Single(18.0));
+        assert_eq!(vm.get_variable("I%").unwrap(), QType::Integer(-300));
+        assert_eq!(vm.get_variable("L&").unwrap(), QType::Long(100_000));
+        assert_eq!(vm.get_variable("S!").unwrap(), QType::Single(1.5));
+        assert_eq!(vm.get_variable("D#").unwrap(), QType::Double(3.25));
+    }
+
+    #[test]
+    fn test_decimal_separator() {
+        let source = "s$ = STR$(-2.25) + STR$(3)\nv = VAL(\"3,5\") + VAL(\"1.25\")\n";
+        let vm = run(source).unwrap();
+        assert_eq!(vm.get_variable("S$").unwrap(), QType::String("-2.25 3".to_string()));
+        assert_eq!(vm.get_variable("V!").unwrap(), QType::Single(4.25));
+
+        let vm = run_with(source, |vm| vm.decimal_separator = ',').unwrap();
+
+        assert_eq!(vm.get_variable("S$").unwrap(), QType::String("-2,25 3".to_string()));
+        assert_eq!(vm.get_variable("V!").unwrap(), QType::Single(4.75));
+    }
+
+    #[test]
+    fn test_circle_statement() {
+        let source = "SCREEN 13\nCIRCLE (160, 100), 12, 4\nCIRCLE STEP(-100, 0), 10, 5, 0, 3.14159 / 2, 1\n\
+                      CIRCLE (250, 100), 10, 6, , , 2\n";
+        let mut vm = run(source).unwrap();
+        let screen = vm.graphics.image(0).unwrap();
+        // SCREEN 13 pixels are taller than wide, so the default aspect is 5/6
+        assert_eq!(screen.point(172, 100), Some(4));
+        assert_eq!(screen.point(160, 90), Some(4));
+        assert_eq!(screen.point(160, 88), Some(0));
+        // An arc around the previous center
+        assert_eq!(screen.point(70, 100), Some(5));
+        assert_eq!(screen.point(60, 90), Some(5));
+        assert_eq!(screen.point(50, 100), Some(0));
+        // Aspect over 1 makes the radius vertical
+        assert_eq!(screen.point(250, 90), Some(6));
+        assert_eq!(screen.point(255, 100), Some(6));
+    }
+
+    #[test]
+    fn test_draw_statement() {
+        let source = "SCREEN 13\nPSET (10, 10)\nsq$ = \"R5 D5\"\nn% = 3\n\
+                      DRAW \"C4 X\" + VARPTR$(sq$) + \" BM 50,50 R=\" + VARPTR$(n%)\nDRAW \"L2\"\n";
+        let mut vm = run(source).unwrap();
+        let screen = vm.graphics.image(0).unwrap();
+        assert_eq!(screen.point(15, 10), Some(4));
+        assert_eq!(screen.point(15, 15), Some(4));
+        assert_eq!(screen.point(53, 50), Some(4));
+        // The color carries over to the next DRAW
+        assert_eq!(screen.point(51, 50), Some(4));
+        assert_eq!(screen.point(30, 30), Some(0));
+    }
+
+    #[test]
+    fn test_save_image_and_recording() {
+        let path = std::env::temp_dir().join(format!("qb-shot-{}.png", std::process::id()));
+        let source = format!("SCREEN 13\nPSET (3, 4), 14\n_SAVEIMAGE \"{}\"\n", path.display());
+        let vm = run_with(&source, VirtualMachine::record_screen).unwrap();
+        let shot = qb_hal::image_file::load_image(&path).unwrap();
+        std::fs::remove_file(&path).unwrap();
+        assert_eq!((shot.width, shot.height), (320, 200));
+        assert_eq!(shot.point(3, 4), Some(0xFFFFFF55));
+        assert!(!vm.screen_recording().unwrap().is_empty());
+    }
+
+    #[test]
+    fn test_full_screen_function() {
+        let source = "a = _FULLSCREEN\n_FULLSCREEN _STRETCH, _SMOOTH\nb = _FULLSCREEN\n_FULLSCREEN\nc = _FULLSCREEN\n\
+                      _FULLSCREEN _OFF\nd = _FULLSCREEN\n";
+        let vm = run(source).unwrap();
+        for (name, mode) in [("A!", 0.0), ("B!", 1.0), ("C!", 2.0), ("D!", 0.0)] {
+            assert_eq!(vm.get_variable(name).unwrap(), QType::Single(mode), "{}", name);
+        }
+    }
+
+    #[test]
+    fn test_sound_mixing() {
+        let path = std::env::temp_dir().join(format!("qb-sound-{}.wav", std::process::id()));
+        let source = "r& = _SNDRATE\nh& = _SNDOPEN(\"missing.wav\")\nSOUND 441, 1.82\n\
+                      FOR i = 1 TO r& / 20: _SNDRAW 0.5, 0.3: NEXT\nl# = _SNDRAWLEN\n";
+        let out = std::fs::File::create(&path).unwrap();
+        let mut vm = run_with(source, |vm| {
+            vm.set_clock(Clock::fixed(0.0));
+            vm.set_sound_output(Box::new(qb_hal::audio_file::WavWriter::new(out).unwrap()));
+        })
+        .unwrap();
+        vm.finish_sound().unwrap();
+        assert_eq!(vm.get_variable("R&").unwrap(), QType::Long(44100));
+        assert_eq!(vm.get_variable("H&").unwrap(), QType::Long(0));
+        // Some of the 0.05 seconds queued played while the loop ran
+        let queued = vm.get_variable("L#").unwrap().to_double().unwrap();
+        assert!(queued > 0.0 && queued < 0.05, "{}", queued);
+
+        // The 0.1 second tone outlasts the raw samples, which add to it
+        let samples = qb_hal::audio_file::load_wav(&path).unwrap();
+        std::fs::remove_file(&path).unwrap();
+        assert!((4400..4500).contains(&samples.len()), "{}", samples.len());
+        assert!(samples.iter().any(|s| (s - 0.6).abs() < 0.001));
+        assert!((samples[samples.len() - 1].abs() - 0.2).abs() < 0.001);
+    }
+
+    #[test]
+    fn test_input_devices() {
+        // Without controllers only the keyboard and mouse are listed
+        let source = "n = _DEVICES\nk$ = _DEVICE$(1)\nd = _DEVICEINPUT\ni = _DEVICEINPUT(2)\n\
+                      b = _BUTTON(3)\na = _AXIS(2)\nl = _LASTBUTTON(1) + _LASTAXIS(2)\n";
+        let vm = run(source).unwrap();
+        assert_eq!(vm.get_variable("N!").unwrap(), QType::Single(2.0));
+        assert_eq!(vm.get_variable("K$").unwrap(), QType::String("[KEYBOARD][BUTTON]".to_string()));
+        assert_eq!(vm.get_variable("D!").unwrap(), QType::Single(0.0));
+        assert_eq!(vm.get_variable("I!").unwrap(), QType::Single(0.0));
+        assert_eq!(vm.get_variable("B!").unwrap(), QType::Single(0.0));
+        assert_eq!(vm.get_variable("A!").unwrap(), QType::Single(0.0));
+        assert_eq!(vm.get_variable("L!").unwrap(), QType::Single(514.0));
+
+        // _BUTTON reads the device _DEVICEINPUT chose, within its buttons
+        assert!(error_code(run("i = _DEVICEINPUT(2)\nb = _BUTTON(4)\n")).is_some());
+    }
+
+    #[test]
+    fn test_dos_paths() {
+        let root = std::env::temp_dir().join(format!("qb-dos-paths-{}", std::process::id()));
+        std::fs::create_dir_all(root.join("Data")).unwrap();
+        let source = "OPEN \"C:\\DATA\\OUT.TXT\" FOR OUTPUT AS #1\nPRINT #1, \"hi\"\nCLOSE\n\
+                      OPEN \"c:/data/out.txt\" FOR INPUT AS #1\nINPUT #1, a$\nCLOSE\n";
+        let mut vfs = Vfs::new();
+        vfs.map_drive('C', root.clone());
+        let vm = run_with(source, |vm| vm.set_vfs(vfs)).unwrap();
+        assert_eq!(vm.get_variable("A$").unwrap(), QType::String("hi".to_string()));
+        assert_eq!(std::fs::read_to_string(root.join("Data/OUT.TXT")).unwrap(), "hi\n");
+        std::fs::remove_dir_all(root).unwrap();
+    }
+
+    #[test]
+    fn test_devices_and_kill() {
+        let root = std::env::temp_dir().join(format!("qb-kill-{}", std::process::id()));
+        std::fs::create_dir_all(&root).unwrap();
+        for name in ["A.TMP", "b.tmp", "KEEP.DAT"] {
+            std::fs::write(root.join(name), "").unwrap();
+        }
+        let source = "OPEN \"SCRN:\" FOR OUTPUT AS #1\nPRINT #1, \"abc\";\n\
+                      OPEN \"C:\\NUL\" FOR OUTPUT AS #2\nPRINT #2, \"gone\"\nf = FREEFILE\n\
+                      OPEN \"NUL\" FOR INPUT AS #3\ne = EOF(3)\nCLOSE\n\
+                      KILL \"C:\\*.TMP\"\n";
+        let mut vfs = Vfs::new();
+        vfs.map_drive('C', root.clone());
+        let vm = run_with(source, |vm| vm.set_vfs(vfs)).unwrap();
+        // What goes to SCRN: is printed
+        assert_eq!(vm.cursor_column, 3);
+        assert_eq!(vm.get_variable("F!").unwrap(), QType::Single(3.0));
+        assert_eq!(vm.get_variable("E!").unwrap(), QType::Single(-1.0));
+
+        let mut left: Vec<_> = std::fs::read_dir(&root).unwrap().map(|entry| entry.unwrap().file_name()).collect();
+        left.sort();
+        assert_eq!(left, ["KEEP.DAT"]);
+
+        // Open files, devices and names that match nothing cannot be killed
+        let root_path = root.to_string_lossy();
+        for source in [
+            format!("OPEN \"{}/KEEP.DAT\" FOR INPUT AS #1\nKILL \"{}/keep.dat\"\n", root_path, root_path),
+            "KILL \"NUL\"\n".to_string(),
+            format!("KILL \"{}/*.TMP\"\n", root_path),
+        ] {
+            assert!(error_code(run(&source)).is_some(), "{}", source);
+        }
+        assert!(root.join("KEEP.DAT").exists());
+        std::fs::remove_dir_all(root).unwrap();
+    }
+
+    #[test]
+    fn test_sleep() {
+        let source = "t! = TIMER\nSLEEP 2\nf! = TIMER - t!\n";
+        let vm = run_with(source, |vm| vm.set_clock(Clock::fixed(0.0))).unwrap();
+        let waited = vm.get_variable("F!").unwrap().to_double().unwrap();
+        assert!((1.9..2.1).contains(&waited), "{}", waited);
+
+        assert!(error_code(run("SLEEP -1\n")).is_some());
+    }
+
+    #[test]
+    fn test_play_music() {
+        let source = "t! = TIMER\nPLAY \"T120 L8 CDEF\"\nf! = TIMER - t!\noct = 3\n\
+                      tune$ = \"O=oct; A B\"\nPLAY \"MB X\" + VARPTR$(tune$) + \"P4\"\nn = PLAY(0)\n";
+        let vm = run_with(source, |vm| vm.set_clock(Clock::fixed(0.0))).unwrap();
+        // Four eighth notes at T120 take a second, waited for in MF; TIMER
+        // counts in whole ticks
+        let waited = vm.get_variable("F!").unwrap().to_double().unwrap();
+        assert!((0.9..1.1).contains(&waited), "{}", waited);
+        // In MB the two notes and the pause are still queued
+        assert_eq!(vm.get_variable("N!").unwrap(), QType::Single(3.0));
+
+        assert!(error_code(run("PLAY \"O9\"\n")).is_some());
+
+        // Three voices, the last one waited for
+        let source = "t! = TIMER\nPLAY \"MB L4 C\", \"MB L2 E\", \"L1 G\"\nf! = TIMER - t!\n";
+        let vm = run_with(source, |vm| vm.set_clock(Clock::fixed(0.0))).unwrap();
+
+        let waited = vm.get_variable("F!").unwrap().to_double().unwrap();
+        assert!((1.9..2.1).contains(&waited), "{}", waited);
+        assert!(parse(tokenize("PLAY a$, b$, c$, d$\n").unwrap()).is_err());
+    }
+
+    #[test]
+    fn test_isam_unavailable() {
+        let source = "ON ERROR GOTO h\nOPEN \"db\" FOR ISAM Rec \"t\" AS #1\nSEEKEQ #1, 1\nb = BOF(1)\nEND\n\
+                      h: n = n + 1: e = ERR: RESUME NEXT\n";
+        let vm = run(source).unwrap();
+        assert_eq!(vm.get_variable("N!").unwrap(), QType::Single(3.0));
+        assert_eq!(vm.get_variable("E!").unwrap(), QType::Single(73.0));
+    }
+
+    #[test]
+    fn test_line_number_targets() {
+        let source = "10 ON ERROR GOTO 500\n20 FOR i = 1 TO 3\n30 IF i = 2 THEN 50\n40 s = s + i\n50 NEXT i\n\
+                      60 GOSUB 200\n70 RESTORE 310: READ d\n80 ERROR 5\n90 END\n200 RETURN 220\n210 s = 0\n\
+                      220 t = 1: GOTO 70\n300 DATA 1\n310 DATA 2\n500 e = ERL: RESUME 90\n";
+        let vm = run(source).unwrap();
+        assert_eq!(vm.get_variable("S!").unwrap(), QType::Single(4.0));
+        assert_eq!(vm.get_variable("T!").unwrap(), QType::Single(1.0));
+        assert_eq!(vm.get_variable("D!").unwrap(), QType::Single(2.0));
+        assert_eq!(vm.get_variable("E!").unwrap(), QType::Single(80.0));
+
+        let program = parse(tokenize("RESTORE 10\n").unwrap()).unwrap();
+        assert!(compile(&program).is_err());
+    }
+
+    #[test]
+    fn test_error_line_numbers() {
+        let Err(err) = run("10 x = 1\n\n20 PRINT x;\n  x = 1 / 0\n") else { panic!() };
+
+        assert!(matches!(err, QError::Runtime { code: QErrorCode::DivisionByZero, line: 20, .. }));
+        assert_eq!(err.to_string(), "Division by zero in line 20");
+
+        assert!(matches!(run("x = 1\nERROR 5\n"), Err(QError::Runtime { line: 2, .. })));
+    }
+
+    #[test]
+    fn test_line_statement() {
+        let source = "SCREEN 13\nLINE (0, 0)-(3, 0), 4\nLINE -STEP(0, 2), 5\nLINE STEP(1, 0)-STEP(2, 2), 6, BF\n\
+                      LINE (20, 20)-(23, 22), , B\nLINE (0, 9)-(7, 9), 9, , &HCCCC\n";
+        let mut vm = run(source).unwrap();
+        let screen = vm.graphics.image(0).unwrap();
+        assert_eq!(screen.point(0, 0), Some(4));
+        assert_eq!(screen.point(3, 2), Some(5));
+        // The box runs from (4, 2) to (6, 4)
+        assert_eq!(screen.point(4, 2), Some(6));
+        assert_eq!(screen.point(6, 4), Some(6));
+        assert_eq!(screen.point(7, 4), Some(0));
+        assert_eq!(screen.point(23, 21), Some(15));
+        assert_eq!(screen.point(21, 21), Some(0));
+        let styled: Vec<u32> = (0..8).map(|x| screen.point(x, 9).unwrap()).collect();
+        assert_eq!(styled, [9, 9, 0, 0, 9, 9, 0, 0]);
+    }
+
+    #[test]
+    fn test_colon_separated_statements() {
+        let source = "DIM SHARED n: x = 5: y = 0\nIF x > 3 THEN y = 1: y = y + 1 ELSE y = 9: y = 8\n\
+                      FOR i = 1 TO 3: t = t + i: NEXT\ntop: Bump: Bump\nSUB Bump: n = n + 1: END SUB\n";
+        let vm = run(source).unwrap();
+        assert_eq!(vm.get_variable("Y!").unwrap(), QType::Single(2.0));
+        assert_eq!(vm.get_variable("T!").unwrap(), QType::Single(6.0));
+        // top: is a label, and the Bump: after it is a call
+        assert_eq!(vm.get_variable("N!").unwrap(), QType::Single(2.0));
+    }
+
+    #[test]
+    fn test_on_jumps() {
+        let source = "path$ = \"\"\nFOR i = 0 TO 4\nON i GOSUB one, two, three\nNEXT\nON 1.6 GOTO skip, done\n\
+                      skip:\npath$ = path$ + \"s\"\ndone:\nEND\n\
+                      one:\npath$ = path$ + \"1\"\nRETURN\ntwo:\npath$ = path$ + \"2\"\nRETURN\nthree:\npath$ = path$ + \"3\"\nRETURN\n";
+        let vm = run(source).unwrap();
+        // 0 and 4 fall through; 1.6 rounds to 2
+        assert_eq!(vm.get_variable("PATH$").unwrap(), QType::String("123".into()));
+
+        assert!(error_code(run("ON -1 GOTO x\nx:\n")).is_some());
+    }
+
+    #[test]
+    fn test_error_trapping() {
+        let source = "ON ERROR GOTO handler\ncodes$ = \"\"\na = 0\nb = 10 / a\nERROR 200\ne = ERR\nEND\n\
+                      handler:\ncodes$ = codes$ + STR$(ERR)\nIF ERR = 11 THEN\na = 2\nRESUME\nEND IF\nRESUME NEXT\n";
+        let vm = run(source).unwrap();
+        // RESUME ran the division again; RESUME NEXT went on after ERROR
+        assert_eq!(vm.get_variable("B!").unwrap(), QType::Single(5.0));
+        assert_eq!(vm.get_variable("CODES$").unwrap(), QType::String(" 11 200".into()));
+        assert_eq!(vm.get_variable("E!").unwrap(), QType::Single(0.0));
+
+        let code = |source: &str| error_code(run(source));
+        assert_eq!(code("RESUME NEXT\n"), Some(QErrorCode::ResumeWithoutError));
+        assert_eq!(code("ON ERROR GOTO h\nERROR 5\nh:\nPRINT\n"), Some(QErrorCode::NoResume));
+        // An error in the handler, or GOTO 0 there, stops the program
+        assert_eq!(code("ON ERROR GOTO h\nERROR 5\nEND\nh:\nERROR 6\n"), Some(QErrorCode::Overflow));
+        assert_eq!(code("ON ERROR GOTO h\nERROR 9\nEND\nh:\nON ERROR GOTO 0\n"), Some(QErrorCode::SubscriptOutOfRange));
+    }
+
+    #[test]
+    fn test_error_handlers_in_procedures() {
+        let source = "ON ERROR GOTO modh\nx = 1: log$ = \"\"\nLib\nERROR 7\nQuiet\nLeaves\nERROR 8\nEND\n\
+                      modh:\nlog$ = log$ + \" m\" + STR$(ERR) + STR$(x)\nRESUME NEXT\n\
+                      SUB Lib\nSHARED log$\nON ERROR GOTO libh\nERROR 5\nEXIT SUB\nlibh:\nlog$ = log$ + \" l\" + STR$(ERR)\nRESUME NEXT\nEND SUB\n\
+                      SUB Quiet\nSHARED log$\nx = 2\nERROR 9\nlog$ = log$ + \" q\" + STR$(x)\nEND SUB\n\
+                      SUB Leaves\nON ERROR GOTO done\nERROR 6\ndone:\nEXIT SUB\nEND SUB\n";
+        let vm = run(source).unwrap();
+        // Lib's handler is gone when it returns; the module's handler sees
+        // the module's x, not Quiet's, and RESUME NEXT goes back into Quiet;
+        // EXIT SUB from Leaves' handler leaves it, so ERROR 8 is trapped
+        assert_eq!(vm.get_variable("LOG$").unwrap(), QType::String(" l 5 m 7 1 m 9 1 q 2 m 8 1".into()));
+    }
+
+    #[test]
+    fn test_array_dimensions() {
+        let source = "OPTION _EXPLICITARRAY\n$DYNAMIC\nDIM SHARED a(5)\nREDIM a(9)\nCALL Grow\nhi = UBOUND(a)\n\
+                      SUB Grow\nREDIM a(12)\nEND SUB\n";
+        let vm = run(source).unwrap();
+        assert_eq!(vm.get_variable("HI!").unwrap(), QType::Single(12.0));
+
+        let message = |source: &str| match compile(&parse(tokenize(source).unwrap()).unwrap()) {
+            Err(QError::Compile { message, .. }) => message,
+            other => panic!("expected a compile error, got {:?}", other.map(|_| ())),
+        };
+        // Arrays are static unless $DYNAMIC or REDIM made them dynamic
+        assert_eq!(message("DIM a(5)\nDIM a(5)\n"), "Duplicate definition");
+        assert_eq!(message("DIM a(5)\nREDIM a(9)\n"), "Array already dimensioned");
+        assert_eq!(message("$DYNAMIC\nDIM a(5)\n$STATIC\nDIM b(5)\nREDIM a(9)\nREDIM b(9)\n"), "Array already dimensioned");
+        assert_eq!(message("OPTION _EXPLICITARRAY\nx(1) = 2\n"), "Array not defined");
+        assert!(compile(&parse(tokenize("x(1) = 2\nREDIM y(3)\nREDIM y(4)\n").unwrap()).unwrap()).is_ok());
+        assert_eq!(message("x(1) = 2\nDIM x(5)\n"), "Duplicate definition");
+
+        // An array used without a DIM has each dimension 0 TO 10
+        let source = "FOR i = 0 TO 10: a(i) = i: NEXT\nb$(10, 3) = \"z\"\nERASE a\ns = a(10) + UBOUND(a) + LBOUND(b$, 2)\n\
+                      t$ = b$(10, 3)\nCALL Fill(c%(2))\nu = c%(2)\nSUB Fill (n%)\nn% = 4\nEND SUB\n";
+        let vm = run(source).unwrap();
+        assert_eq!(vm.get_variable("S!").unwrap(), QType::Single(10.0));
+        assert_eq!(vm.get_variable("T$").unwrap(), QType::String("z".into()));
+        assert_eq!(vm.get_variable("U!").unwrap(), QType::Single(4.0));
+        assert_eq!(error_code(run("a(11) = 1\n")), Some(QErrorCode::SubscriptOutOfRange));
+    }
+
+    #[test]
+    fn test_redim_preserve() {
+        let source = "n = 3\nDIM a(n, 1 TO 2) AS INTEGER\nFOR i = 0 TO n: a(i, 1) = i: a(i, 2) = i * 10: NEXT\n\
+                      REDIM PRESERVE a(n, 1 TO n)\nb = a(2, 1) + a(3, 2)\nc = a(3, 3)\nREDIM _PRESERVE a(n, 1 TO 1)\n\
+                      d = UBOUND(a, 2) + a(1, 1)\nREDIM a(-1 TO 1)\ne = LBOUND(a) + a(0)\n";
+        let vm = run(source).unwrap();
+        assert_eq!(vm.get_variable("B!").unwrap(), QType::Single(32.0));
+        assert_eq!(vm.get_variable("C!").unwrap(), QType::Single(0.0));
+        assert_eq!(vm.get_variable("D!").unwrap(), QType::Single(2.0));
+        assert_eq!(vm.get_variable("E!").unwrap(), QType::Single(-1.0));
+
+        // Only the last dimension can change, and no bound can pass the other
+        for source in ["REDIM a(2, 2)\nREDIM PRESERVE a(3, 2)\n", "n = -1\nREDIM a(n)\n"] {
+            assert!(error_code(run(source)).is_some(), "{}", source);
+        }
+        // DIM checks its bounds the same way
+        assert_eq!(error_code(run("DIM a(-1 TO -5)\n")), Some(QErrorCode::SubscriptOutOfRange));
+    }
+
+    #[test]
+    fn test_erase() {
+        let source = "DIM s(3) AS INTEGER\n$DYNAMIC\nDIM d(2) AS STRING\ns(2) = 5\nd(1) = \"x\"\nERASE s, d\n\
+                      a = s(2) + UBOUND(s)\nREDIM d(4)\nb = LEN(d(1)) + UBOUND(d)\n";
+        let vm = run(source).unwrap();
+        assert_eq!(vm.get_variable("A!").unwrap(), QType::Single(3.0));
+        assert_eq!(vm.get_variable("B!").unwrap(), QType::Single(4.0));
+
+        // A freed dynamic array is gone until it is dimensioned again
+        assert!(error_code(run("REDIM d(2)\nERASE d\nx = d(1)\n")).is_some());
+    }
+
+    #[test]
+    fn test_static_and_shared() {
+        let source = "TYPE Pair\na AS INTEGER\nb AS INTEGER\nEND TYPE\nDIM total AS INTEGER, p AS Pair\nDIM SHARED x, y\n\
+                      total = 5\ncount = 100\nCALL Tally\nCALL Tally\nCALL Keep(1)\nCALL Keep(2)\nr = Depth(3)\n\
+                      SUB Tally\nSHARED total, p AS Pair\nSTATIC calls AS INTEGER\ncalls = calls + 1\n\
+                      total = total + calls\np.b = calls\ncount = count + 1\nx = 1\ny = 2\nEND SUB\n\
+                      SUB Keep (k) STATIC\nn = n + k\nEND SUB\n\
+                      FUNCTION Depth (d)\nSTATIC seen\nseen = seen + 1\nIF d > 1 THEN Depth = Depth(d - 1) ELSE Depth = seen\nEND FUNCTION\n";
+        let vm = run(source).unwrap();
+        assert_eq!(vm.get_variable("TOTAL%").unwrap(), QType::Integer(8));
+        assert_eq!(vm.get_variable("P.B").unwrap(), QType::Integer(2));
+        // Not shared, so the SUB counted its own
+        assert_eq!(vm.get_variable("COUNT!").unwrap(), QType::Single(100.0));
+        // DIM SHARED shares every variable it lists
+        assert_eq!(vm.get_variable("Y!").unwrap(), QType::Single(2.0));
+        assert_eq!(vm.get_variable("KEEP:N!").unwrap(), QType::Single(3.0));
+        // Recursive calls see the same STATIC variable
+        assert_eq!(vm.get_variable("R!").unwrap(), QType::Single(3.0));
+
+        let program = parse(tokenize("SHARED a\n").unwrap()).unwrap();
+        assert!(compile(&program).is_err());
+    }
+
+    #[test]
+    fn test_chain_passes_common() {
+        let dir = std::env::temp_dir().join(format!("qb-chain-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let second = "COMMON t%, who$, s()\nr = t% + s(2): n$ = who$: u = UBOUND(s): o = other\n";
+        std::fs::write(dir.join("second.bas"), second).unwrap();
+        let build = |source: &str| {
+            let mut program = parse(tokenize(source).unwrap()).unwrap();
+            analyze(&mut program).unwrap();
+            compile(&program).unwrap()
+        };
+        let first = format!(
+            "COMMON total%, name$, scores()\nDIM scores(3)\ntotal% = 40: name$ = \"Ann\": scores(2) = 2: other = 1\n\
+             CHAIN \"{}\"\nPRINT \"not reached\"\n",
+            dir.join("SECOND").display()
+        );
+        let mut vm = VirtualMachine::new();
+        vm.execute(&build(&first)).unwrap();
+        // .BAS is added, and the name found in any case
+        let next = vm.take_chain().unwrap();
+        assert_eq!(next, dir.join("second.bas"));
+        vm.execute(&build(&std::fs::read_to_string(next).unwrap())).unwrap();
+        assert_eq!(vm.get_variable("R!").unwrap(), QType::Single(42.0));
+        assert_eq!(vm.get_variable("N$").unwrap(), QType::String("Ann".into()));
+        assert_eq!(vm.get_variable("U!").unwrap(), QType::Single(3.0));
+        // Variables outside COMMON start anew
+        assert_eq!(vm.get_variable("O!").unwrap(), QType::Single(0.0));
+        assert!(vm.take_chain().is_none());
+
+        let missing = format!("CHAIN \"{}\"\n", dir.join("none").display());
+        assert!(matches!(vm.execute(&build(&missing)), Err(QError::Runtime { code: QErrorCode::FileNotFound, .. })));
+        std::fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn test_array_parameters() {
+        let source = "DIM v(3 TO 5) AS INTEGER\nv(4) = 7\nCALL Fill(v())\nt = Total(v())\n\
+                      SUB Fill (a() AS INTEGER)\nlo = LBOUND(a)\nhi = UBOUND(a, 1)\na(lo) = 1\nCALL Last(a())\nEND SUB\n\
+                      SUB Last (b() AS INTEGER)\nb(UBOUND(b)) = 2\nEND SUB\n\
+                      FUNCTION Total (a() AS INTEGER)\nFOR i = LBOUND(a) TO UBOUND(a)\ns = s + a(i)\nNEXT\nTotal = s\nEND FUNCTION\n";
+        let vm = run(source).unwrap();
+        // Both SUBs changed the caller's array, through a parameter of a parameter too
+        let view = vm.array("V%").unwrap();
+        assert_eq!(view.get(&[3]), Some(&QType::Integer(1)));
+        assert_eq!(view.get(&[5]), Some(&QType::Integer(2)));
+        assert_eq!(vm.get_variable("T!").unwrap(), QType::Single(10.0));
+
+        // An array to a scalar parameter, or a scalar to an array parameter
+        for call in ["CALL S(v())\nSUB S (a)\nEND SUB\n", "CALL S(v)\nSUB S (a())\nEND SUB\n"] {
+            let mut program = parse(tokenize(&format!("DIM v(3)\n{}", call)).unwrap()).unwrap();
+            assert!(analyze(&mut program).is_err());
+        }
+    }
+
+    #[test]
+    fn test_reflection() {
+        let source = "GOSUB probe\nEND\nprobe:\nwhere$ = _SOURCELINE$\ndepth = _CALLDEPTH\nRETURN\n";
+        let vm = run_with(source, |vm| vm.set_command_line(vec!["games/MAZE.BAS".to_string()])).unwrap();
+        assert_eq!(vm.get_variable("WHERE$").unwrap(), QType::String("MAZE.BAS:4".into()));
+        assert_eq!(vm.get_variable("DEPTH!").unwrap(), QType::Single(1.0));
+    }
+
+    #[test]
+    fn test_data_in_qbc() {
+        let source = "DATA 1\nparts:\nDATA 2, 3\nREAD a\nRESTORE parts\nREAD b\n";
+        let mut program = parse(tokenize(source).unwrap()).unwrap();
+        analyze(&mut program).unwrap();
+        let bytecode = ByteCode::from_qbc(&compile(&program).unwrap().to_qbc().unwrap()).unwrap();
+        assert_eq!(bytecode.data_index("Parts"), Some(1));
+        assert!(ByteCode::from_qbc(b"QBC").is_err());
+
+        // Run twice, as a chained program would start: READ begins anew
+        let mut vm = VirtualMachine::new();
+        for _ in 0..2 {
+            vm.execute(&bytecode).unwrap();
+            assert_eq!(vm.get_variable("A!").unwrap(), QType::Single(1.0));
+            assert_eq!(vm.get_variable("B!").unwrap(), QType::Single(2.0));
+        }
+    }
+
+    #[test]
+    fn test_timer_events() {
+        let source = "ON TIMER(1) GOSUB Tick\nTIMER ON\nDO\n_LIMIT 10\nLOOP UNTIL ticks = 3\nt! = TIMER\nEND\n\
+                      Tick:\nticks = ticks + 1\nRETURN\n";
+        let vm = run_with(source, |vm| {
+            vm.set_dialect(Dialect::Qb64);
+            vm.set_clock(Clock::fixed(0.0));
+        })
+        .unwrap();
+        // Three one-second ticks, with TIMER on the same virtual clock
+        let QType::Single(t) = vm.get_variable("T!").unwrap() else { panic!() };
+        assert!((3.0..3.2).contains(&t), "{t}");
+    }
+
+    #[test]
+    fn test_timer_resolution() {
+        let source = "t! = TIMER: c! = TIMER(.5)\nd# = _TIMER64\n";
+        let run = |dialect| {
+            run_with(source, |vm| {
+                vm.set_dialect(dialect);
+                vm.set_clock(Clock::fixed(100.123_456_7));
+            })
+            .unwrap()
+        };
+        // QB 4.5 counts 18.2 Hz BIOS ticks: tick 1822 began at 100.0741
+        let vm = run(Dialect::Qb45);
+        let QType::Single(t) = vm.get_variable("T!").unwrap() else { panic!() };
+        assert!((t - 100.0741).abs() < 0.001, "{t}");
+        assert_eq!(vm.get_variable("C!").unwrap(), QType::Single(100.0));
+
+        let vm = run(Dialect::Qb64);
+        let QType::Single(t) = vm.get_variable("T!").unwrap() else { panic!() };
+        assert!((t - 100.1235).abs() < 0.001, "{t}");
+        let QType::Double(d) = vm.get_variable("D#").unwrap() else { panic!() };
+        assert!((d - 100.123_456).abs() < 0.000_01, "{d}");
+    }
+
+    #[test]
+    fn test_mat() {
+        let vm = run("DIM a(1 TO 2, 1 TO 3) AS SINGLE, b(5) AS INTEGER\nMAT a = CON\nMAT b = (2.6)\nMAT a = b\n").unwrap();
+        assert_eq!(vm.array("A!").unwrap().to_f64().unwrap(), [3.0; 6]);
+        assert!(error_code(run("DIM a(3), b(4)\nMAT a = b\n")).is_some());
+        assert!(error_code(run("DIM a$(3)\nMAT a$ = ZER\n")).is_some());
+        assert!(error_code(run("DIM a$(3), b(3)\nMAT a$ = b\n")).is_some());
+    }
+
+    #[test]
+    fn test_random_records() {
+        let path = std::env::temp_dir().join(format!("qb_records_{}.dat", std::process::id()));
+        let source = format!(
+            "TYPE Person\nnm AS STRING * 6\nage AS INTEGER\nEND TYPE\nDIM p AS Person, q AS Person\n\
+             OPEN \"{}\" FOR RANDOM AS #1 LEN = LEN(p)\n\
+             p.nm = \"Bob\"\np.age = 42\nPUT #1, 2, p\nGET #1, 2, q\nsize = LEN(p)\nCLOSE #1\n\
+             OPEN \"{0}\" FOR RANDOM AS #1 LEN = 8\nFIELD #1, 6 AS n$, 2 AS a$\nGET #1, 2\ngot$ = n$\n\
+             RSET n$ = \"Al\"\nPUT #1, 1\nGET #1, 1, q\nCLOSE\n",
+            path.display()
+        );
+        let vm = run(&source).unwrap();
+        assert_eq!(vm.get_variable("SIZE!").unwrap(), QType::Single(8.0));
+        assert_eq!(vm.get_variable("GOT$").unwrap(), QType::String("Bob   ".into()));
+        // RSET kept the age bytes FIELD read, and the record reads back whole
+        assert_eq!(vm.get_variable("Q.NM").unwrap(), QType::FixedString(6, "    Al".into()));
+        assert_eq!(vm.get_variable("Q.AGE").unwrap(), QType::Integer(42));
+        assert_eq!(std::fs::read(&path).unwrap(), b"    Al*\0Bob   *\0");
+
+        let overflow = format!("OPEN \"{}\" FOR RANDOM AS #1 LEN = 4\nFIELD #1, 3 AS a$, 2 AS b$\n", path.display());
+        assert!(error_code(run(&overflow)).is_some());
+        std::fs::remove_file(path).unwrap();
+    }
+
+    #[test]
+    fn test_inkey_record_and_replay() {
+        let mut recording = ConsoleInput::record();
+        recording.record_keys(&[(b'a', 30), (0, 72)]);
+        let session = recording.session().unwrap().clone();
+
+        let source = "DO\nk$ = INKEY$\nLOOP WHILE k$ = \"\"\nDO\nup$ = INKEY$\nLOOP WHILE up$ = \"\"\nnone$ = INKEY$\n";
+        let vm = run_with(source, |vm| vm.set_console_input(ConsoleInput::replay(session, false))).unwrap();
+        assert_eq!(vm.get_variable("K$").unwrap(), QType::String("a".into()));
+        assert_eq!(vm.get_variable("UP$").unwrap(), QType::String("\0H".into()));
+        assert_eq!(vm.get_variable("NONE$").unwrap(), QType::String(String::new()));
+    }
+
+    #[test]
+    fn test_clear() {
+        let path = std::env::temp_dir().join(format!("qb_clear_{}.txt", std::process::id()));
+        let source = format!(
+            "CONST limit = 10\nDIM s(5)\nn = 3\nDIM d(1 TO n)\ns(2) = 7: d(1) = 4: x = 5: t$ = \"hi\"\n\
+             OPEN \"{}\" FOR OUTPUT AS #1\nCLEAR\nlim = limit: top = UBOUND(s): s2 = s(2): f = FREEFILE\n",
+            path.display()
+        );
+        let vm = run(&source).unwrap();
+        assert_eq!(vm.get_variable("X!").unwrap(), QType::Single(0.0));
+        assert_eq!(vm.get_variable("T$").unwrap(), QType::String(String::new()));
+        assert_eq!(vm.get_variable("LIM!").unwrap(), QType::Single(10.0));
+        // A static array keeps its bounds, and file #1 was closed
+        assert_eq!(vm.get_variable("TOP!").unwrap(), QType::Single(5.0));
+        assert_eq!(vm.get_variable("S2!").unwrap(), QType::Single(0.0));
+        assert_eq!(vm.get_variable("F!").unwrap(), QType::Single(1.0));
+        std::fs::remove_file(path).unwrap();
+
+        // A dynamic array is freed
+        assert_eq!(error_code(run("n = 3\nDIM d(1 TO n)\nCLEAR\nu = UBOUND(d)\n")), Some(QErrorCode::SubscriptOutOfRange));
+        assert_eq!(error_code(run("SUB s\nCLEAR\nEND SUB\nCALL s\n")), Some(QErrorCode::IllegalFunctionCall));
+    }
+
+    #[test]
+    fn test_input_prompts() {
+        struct Capture(Rc<RefCell<Vec<u8>>>);
+        impl Write for Capture {
+            fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+                self.0.borrow_mut().extend_from_slice(buf);
+                Ok(buf.len())
+            }
+            fn flush(&mut self) -> io::Result<()> {
+                Ok(())
+            }
+        }
+
+        let source = "INPUT \"Name\"; n$\nINPUT \"Age: \", age\nINPUT x\n";
+        let inputs = ["Ann", "abc", "36", "7"]
+            .iter()
+            .map(|text| RecordedInput::Line { delay_ms: 0, text: text.to_string() })
+            .collect();
+        let output = Rc::new(RefCell::new(Vec::new()));
+        let vm = run_with(source, |vm| {
+            vm.set_console_output(Box::new(Capture(output.clone())));
+            vm.set_console_input(ConsoleInput::replay(Session { inputs }, false));
+        })
+        .unwrap();
+        // "?" follows a prompt and ';', not ','; a bad number asks again
+        assert_eq!(
+            String::from_utf8(output.borrow().clone()).unwrap(),
+            "Name? Ann\nAge: abc\n?Redo from start\nAge: 36\n? 7\n"
+        );
+        assert_eq!(vm.get_variable("AGE!").unwrap(), QType::Single(36.0));
+    }
+
+    #[test]
+    fn test_exit_code_and_command_line() {
+        let run = |source: &str| {
+            run_with(source, |vm| vm.set_command_line(vec!["prog.bas".into(), "one".into(), "two".into()]))
+        };
+        assert_eq!(run("END 3\n").unwrap().exit_code(), 3);
+        assert_eq!(run("SYSTEM 3\n").unwrap().exit_code(), 3);
+        assert_eq!(run("SYSTEM\n").unwrap().exit_code(), 0);
+
+        let vm = run("all$ = COMMAND$\nprog$ = COMMAND$(0)\nsecond$ = COMMAND$(2)\n").unwrap();
+        assert_eq!(vm.get_variable("ALL$").unwrap(), QType::String("one two".into()));
+        assert_eq!(vm.get_variable("PROG$").unwrap(), QType::String("prog.bas".into()));
+        assert_eq!(vm.get_variable("SECOND$").unwrap(), QType::String("two".into()));
+        assert_eq!(error_code(run("x$ = COMMAND$(-1)\n")), Some(QErrorCode::IllegalFunctionCall));
+    }
+
+    #[test]
+    fn test_fre() {
+        let source = "far = FRE(-1)\nn = 100\nDIM a&(1 TO n)\nused = far - FRE(-1)\n\
+                      s0 = 0: t$ = \"\"\ns0 = FRE(\"\")\nt$ = STRING$(100, \"x\")\ngrew = s0 - FRE(\"\")\n\
+                      same = FRE(0) = FRE(\"\")\n";
+        let vm = run(source).unwrap();
+        assert_eq!(vm.get_variable("FAR!").unwrap(), QType::Single(524_288.0));
+        // 100 LONGs on the far heap, 100 characters of string space
+        assert_eq!(vm.get_variable("USED!").unwrap(), QType::Single(400.0));
+        assert_eq!(vm.get_variable("GREW!").unwrap(), QType::Single(100.0));
+        assert_eq!(vm.get_variable("SAME!").unwrap(), QType::Single(-1.0));
+    }
+
+    #[test]
+    fn test_strict_mode() {
+        let run = |source: &str, strict: bool| run_with(source, |vm| vm.set_strict(strict));
+        let typo = "total = 5\nx = totl + 1\n";
+        assert_eq!(run(typo, false).unwrap().get_variable("X!").unwrap(), QType::Single(1.0));
+        match run(typo, true) {
+            Err(QError::Runtime { code: QErrorCode::VariableNotDefined, message, .. }) => {
+                assert_eq!(message, "Variable not defined: TOTL!");
+            }
+            other => panic!("expected Variable not defined, got {:?}", other.err()),
+        }
+        assert!(run("DIM y AS INTEGER\nx = y + total\ntotal = 1\n", true).is_err());
+        assert!(run("DIM y AS INTEGER\nx = y\n", true).is_ok());
+    }
+
+    #[test]
+    fn test_chr_and_asc_ranges() {
+        // Past 255 only QB64 has characters
+        assert_eq!(error_code(run_in("c$ = CHR$(256)\n", Dialect::Qb45)), Some(QErrorCode::IllegalFunctionCall));
+        let vm = run_in("c$ = CHR$(256)\n", Dialect::Qb64).unwrap();
+        assert_eq!(vm.get_variable("C$").unwrap(), QType::String("\u{100}".into()));
+        assert_eq!(error_code(run_in("a = ASC(\"\")\n", Dialect::Qb45)), Some(QErrorCode::IllegalFunctionCall));
+        let vm = run_in("a = ASC(\"xyz\", 2)\n", Dialect::Qb64).unwrap();
+        assert_eq!(vm.get_variable("A!").unwrap(), QType::Single(121.0));
+    }
+
+    #[test]
+    fn test_array_view() {
+        let source = "DIM grid(1 TO 2, 0 TO 2)\ngrid(2, 1) = 5\nx = 1\n";
+        let vm = run(source).unwrap();
+        assert_eq!(vm.array_names(), vec!["GRID!"]);
+        assert!(vm.variables().iter().any(|(name, _)| *name == "X!"));
+        let view = vm.array("GRID!").unwrap();
+        assert_eq!(view.rank(), 2);
+        assert_eq!(view.element_type(), "SINGLE");
+        // Row-major: the last subscript varies fastest
+        let offset = view.offset(&[2, 1]).unwrap();
+        assert_eq!(offset, 4);
+        assert_eq!(view.subscripts(offset), vec![2, 1]);
+        assert_eq!(view.elements[offset], QType::Single(5.0));
+    }
+
+    #[test]
+    fn test_binary_file_characters() {
+        let path = std::env::temp_dir().join(format!("qb_chars_{}.bin", std::process::id()));
+        let source = format!(
+            "OPEN \"{}\" FOR BINARY AS #1\ns$ = \"\"\nFOR i = 128 TO 255: s$ = s$ + CHR$(i): NEXT\nPUT #1, 1, s$\n\
+             t$ = SPACE$(128)\nGET #1, 1, t$\nCLOSE #1\nsame = s$ = t$\nlast = ASC(RIGHT$(t$, 1))\n",
+            path.display()
+        );
+        let vm = run(&source).unwrap();
+        // Each character is stored as the byte CHR$ took
+        assert_eq!(std::fs::read(&path).unwrap(), (128..=255).collect::<Vec<u8>>());
+        assert_eq!(vm.get_variable("SAME!").unwrap(), QType::Single(-1.0));
+        assert_eq!(vm.get_variable("LAST!").unwrap(), QType::Single(255.0));
+        std::fs::remove_file(path).unwrap();
+    }
+
+    #[test]
+    fn test_unassigned_variables() {
+        let source = "a$ = b$ + \"x\"\nn% = m% + 1\nl = LEN(c$)\n";
+        let vm = run(source).unwrap();
+        assert_eq!(vm.get_variable("A$").unwrap(), QType::String("x".into()));
+        assert_eq!(vm.get_variable("N%").unwrap(), QType::Integer(1));
+        assert_eq!(vm.get_variable("L!").unwrap(), QType::Single(0.0));
+        assert_eq!(vm.get_variable("Z#").unwrap(), QType::Double(0.0));
+    }
+
+    #[test]
+    fn test_date_time_and_trim() {
+        let source = "d$ = DATE$\nt$ = TIME$\ns$ = TRIM$(\"  a b \")\n";
+        let vm = run_with(source, |vm| vm.set_clock(Clock::fixed(3723.5))).unwrap();
+
+        assert_eq!(vm.get_variable("D$").unwrap(), QType::String("01-01-1980".into()));
+        assert_eq!(vm.get_variable("T$").unwrap(), QType::String("01:02:03".into()));
+        assert_eq!(vm.get_variable("S$").unwrap(), QType::String("a b".into()));
+    }
+
+    #[test]
+    fn test_modern_arithmetic() {
+        let source = "a& = -2147483647 - 1\nb# = a& \\ -1\nc# = 32767 * 2\n";
+        let vm = run_with(source, |vm| vm.set_arithmetic(Arithmetic::Modern)).unwrap();
+        assert_eq!(vm.get_variable("B#").unwrap(), QType::Double(2_147_483_648.0));
+        assert_eq!(vm.get_variable("C#").unwrap(), QType::Double(65534.0));
+        assert_eq!(error_code(run(source)), Some(QErrorCode::Overflow));
+    }
+}
@@ -0,0 +1,8 @@
+//! What a running program may reach outside the VM
+
+/// Capabilities granted to a program; everything is denied by default
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct Sandbox {
+    /// _OPENHOST, _OPENCLIENT and the HTTP: device
+    pub network: bool,
+}
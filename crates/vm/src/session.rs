@@ -0,0 +1,160 @@
+//! Recording and replaying console input, so an interactive run can be
+//! reproduced exactly: `qb run --record FILE` saves every line typed at
+//! INPUT, LINE INPUT or a RANDOMIZE prompt and every key INKEY$ or SLEEP
+//! picks up, together with how long the user took, and
+//! `qb run --replay FILE` feeds the same input back.
+
+use qb_core::errors::QResult;
+use serde::{Deserialize, Serialize};
+use std::collections::VecDeque;
+use std::io;
+use std::thread;
+use std::time::{Duration, Instant};
+
+/// The console input of one run
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct Session {
+    pub inputs: Vec<RecordedInput>,
+}
+
+/// One line or key of input, and the time since the previous one (or the
+/// start)
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(untagged)]
+pub enum RecordedInput {
+    Line { delay_ms: u64, text: String },
+    /// A key as the BIOS buffer holds it: its ASCII code, or 0 and the
+    /// scan code for arrows and function keys
+    Key { delay_ms: u64, key: (u8, u8) },
+}
+
+impl RecordedInput {
+    pub fn delay_ms(&self) -> u64 {
+        match self {
+            RecordedInput::Line { delay_ms, .. } | RecordedInput::Key { delay_ms, .. } => *delay_ms,
+        }
+    }
+}
+
+/// Where the VM's console input comes from
+#[derive(Debug)]
+pub enum ConsoleInput {
+    /// Standard input
+    Live,
+    /// Standard input, saved into a session
+    Record { session: Session, since: Instant },
+    /// A recorded session, optionally waiting out the recorded delays
+    Replay { inputs: VecDeque<RecordedInput>, realtime: bool, since: Instant },
+}
+
+impl ConsoleInput {
+    pub fn record() -> Self {
+        ConsoleInput::Record { session: Session::default(), since: Instant::now() }
+    }
+
+    pub fn replay(session: Session, realtime: bool) -> Self {
+        ConsoleInput::Replay { inputs: session.inputs.into(), realtime, since: Instant::now() }
+    }
+
+    /// The next line, without its line ending; None at the end of input.
+    /// Replaying, keys recorded before the line are dropped, as nothing
+    /// polled for them.
+    pub fn read_line(&mut self) -> QResult<Option<String>> {
+        match self {
+            ConsoleInput::Live => read_stdin(),
+            ConsoleInput::Record { session, since } => {
+                let line = read_stdin()?;
+                if let Some(text) = &line {
+                    session.inputs.push(RecordedInput::Line {
+                        delay_ms: since.elapsed().as_millis() as u64,
+                        text: text.clone(),
+                    });
+                    *since = Instant::now();
+                }
+                Ok(line)
+            }
+            ConsoleInput::Replay { inputs, realtime, since } => loop {
+                let Some(input) = inputs.pop_front() else { return Ok(None) };
+                let RecordedInput::Line { delay_ms, text } = input else { continue };
+                if *realtime {
+                    thread::sleep(Duration::from_millis(delay_ms).saturating_sub(since.elapsed()));
+                }
+                *since = Instant::now();
+                return Ok(Some(text));
+            },
+        }
+    }
+
+    /// Save keys polled from the keyboard, when recording
+    pub fn record_keys(&mut self, keys: &[(u8, u8)]) {
+        if let ConsoleInput::Record { session, since } = self {
+            for &key in keys {
+                session.inputs.push(RecordedInput::Key { delay_ms: since.elapsed().as_millis() as u64, key });
+                *since = Instant::now();
+            }
+        }
+    }
+
+    /// The recorded keys due now, when replaying: those before the next
+    /// line, or with `realtime` those whose delay has passed
+    pub fn replay_keys(&mut self) -> Vec<(u8, u8)> {
+        let mut keys = Vec::new();
+        if let ConsoleInput::Replay { inputs, realtime, since } = self {
+            while let Some(&RecordedInput::Key { delay_ms, key }) = inputs.front() {
+                if *realtime && since.elapsed() < Duration::from_millis(delay_ms) {
+                    break;
+                }
+                inputs.pop_front();
+                *since = Instant::now();
+                keys.push(key);
+            }
+        }
+        keys
+    }
+
+    /// Whether replaying has a key still to give
+    pub fn has_key(&self) -> bool {
+        matches!(self, ConsoleInput::Replay { inputs, .. } if matches!(inputs.front(), Some(RecordedInput::Key { .. })))
+    }
+
+    pub fn is_replay(&self) -> bool {
+        matches!(self, ConsoleInput::Replay { .. })
+    }
+
+    /// The session recorded so far, when recording
+    pub fn session(&self) -> Option<&Session> {
+        match self {
+            ConsoleInput::Record { session, .. } => Some(session),
+            _ => None,
+        }
+    }
+}
+
+fn read_stdin() -> QResult<Option<String>> {
+    let mut line = String::new();
+    if io::stdin().read_line(&mut line)? == 0 {
+        return Ok(None);
+    }
+    Ok(Some(line.trim_end_matches(['\r', '\n']).to_string()))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_replay_feeds_recorded_lines_in_order() {
+        let session = Session {
+            inputs: vec![
+                RecordedInput::Line { delay_ms: 10, text: "5".to_string() },
+                RecordedInput::Key { delay_ms: 5, key: (27, 1) },
+                RecordedInput::Line { delay_ms: 20, text: "Ada, 36".to_string() },
+            ],
+        };
+        let mut input = ConsoleInput::replay(session, false);
+        assert_eq!(input.read_line().unwrap().as_deref(), Some("5"));
+        assert_eq!(input.read_line().unwrap().as_deref(), Some("Ada, 36"));
+        assert_eq!(input.read_line().unwrap(), None);
+        assert!(input.session().is_none());
+    }
+}
@@ -0,0 +1,66 @@
+//! String searching for INSTR and _INSTRREV. Positions count characters
+//! from 1, and 0 means not found.
+
+/// INSTR: first position at or after `start` where `needle` occurs. An
+/// empty needle is found at `start` unless that is past the end.
+pub fn instr(start: usize, haystack: &str, needle: &str) -> usize {
+    let haystack: Vec<char> = haystack.chars().collect();
+    let needle: Vec<char> = needle.chars().collect();
+    if start > haystack.len() {
+        return 0;
+    }
+    if needle.is_empty() {
+        return start;
+    }
+    haystack[start - 1..]
+        .windows(needle.len())
+        .position(|window| window == needle.as_slice())
+        .map_or(0, |i| start + i)
+}
+
+/// _INSTRREV: last position at or before `start` where `needle` begins;
+/// `start` is None for the whole string. An empty needle is found at
+/// `start`, or at the last character.
+pub fn instr_rev(start: Option<usize>, haystack: &str, needle: &str) -> usize {
+    let haystack: Vec<char> = haystack.chars().collect();
+    let needle: Vec<char> = needle.chars().collect();
+    let start = start.map_or(haystack.len(), |s| s.min(haystack.len()));
+    if needle.is_empty() || needle.len() > haystack.len() {
+        return if needle.is_empty() { start } else { 0 };
+    }
+    let last = start.min(haystack.len() - needle.len() + 1);
+    (1..=last)
+        .rev()
+        .find(|&i| haystack[i - 1..i - 1 + needle.len()] == needle[..])
+        .unwrap_or(0)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_instr() {
+        assert_eq!(instr(1, "hello world", "o"), 5);
+        assert_eq!(instr(6, "hello world", "o"), 8);
+        assert_eq!(instr(9, "hello world", "o"), 0);
+        assert_eq!(instr(1, "hello", "xyz"), 0);
+        assert_eq!(instr(1, "ab", "abc"), 0);
+        assert_eq!(instr(3, "abc", ""), 3);
+        assert_eq!(instr(4, "abc", ""), 0);
+        assert_eq!(instr(1, "", ""), 0);
+        assert_eq!(instr(2, "╔═╗", "╗"), 3);
+    }
+
+    #[test]
+    fn test_instr_rev() {
+        assert_eq!(instr_rev(None, "hello world", "o"), 8);
+        assert_eq!(instr_rev(Some(7), "hello world", "o"), 5);
+        assert_eq!(instr_rev(Some(4), "hello world", "o"), 0);
+        assert_eq!(instr_rev(Some(99), "abcabc", "abc"), 4);
+        assert_eq!(instr_rev(Some(3), "abcabc", "abc"), 1);
+        assert_eq!(instr_rev(None, "ab", "abc"), 0);
+        assert_eq!(instr_rev(None, "abc", ""), 3);
+        assert_eq!(instr_rev(None, "", ""), 0);
+    }
+}
@@ -0,0 +1,309 @@
+//! PRINT USING: laying out values by a format string. String fields are
+//! `!` (first character), `\  \` (as many characters as the field is
+//! wide) and `&` (the whole string). A numeric field is made of `#` digit
+//! positions, an optional `.`, commas before the point to group
+//! thousands, `$$` for a floating dollar sign, `**` to fill with
+//! asterisks, `^^^^` or `^^^^^` for an exponent, and a leading `+` or
+//! trailing `+` or `-` for the sign. A number too big for its field is
+//! printed whole after a `%`. `_` prints the next character as it is;
+//! anything else is printed literally. The format is reused from the
+//! start when there are more values than fields.
+
+use qb_core::data_types::QType;
+use qb_core::errors::{QError, QErrorCode, QResult};
+
+#[derive(Debug, Clone, PartialEq)]
+enum Item {
+    Literal(char),
+    /// `!`, `\  \` and `&`: the number of characters, None for all
+    Text(Option<usize>),
+    Number(NumberField),
+}
+
+#[derive(Debug, Clone, Default, PartialEq)]
+struct NumberField {
+    /// Characters the field takes
+    width: usize,
+    /// Positions left of the point, for digits and an unsigned minus
+    digits: usize,
+    decimals: usize,
+    point: bool,
+    commas: bool,
+    dollar: bool,
+    stars: bool,
+    /// Exponent digits, from `^^^^` (2) or `^^^^^` (3)
+    exponent: Option<usize>,
+    sign: Sign,
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+enum Sign {
+    /// A minus takes one of the digit positions
+    #[default]
+    Minus,
+    /// `+` before the number: always a sign
+    Leading,
+    /// `+` after the number
+    TrailingPlus,
+    /// `-` after the number: a minus or a space
+    TrailingMinus,
+}
+
+/// Format `values` by `format` as PRINT USING prints them
+pub fn format_using(format: &str, values: &[QType]) -> QResult<String> {
+    let items = parse(format);
+    if !items.iter().any(|item| !matches!(item, Item::Literal(_))) {
+        return Err(QError::runtime(QErrorCode::IllegalFunctionCall, 0, 0));
+    }
+    let mut text = String::new();
+    let mut values = values.iter().peekable();
+    let mut index = 0;
+    loop {
+        match &items[index] {
+            Item::Literal(c) => text.push(*c),
+            field => {
+                let Some(value) = values.next() else { break };
+                match (field, value) {
+                    (Item::Text(length), QType::String(s)) => text.push_str(&fit_text(s, *length)),
+                    (Item::Number(number), value) if !value.is_string() => {
+                        text.push_str(&format_number(number, value.to_double()?));
+                    }
+                    _ => return Err(QError::runtime(QErrorCode::TypeMismatch, 0, 0)),
+                }
+            }
+        }
+        index += 1;
+        if index == items.len() {
+            if values.peek().is_none() {
+                break;
+            }
+            index = 0;
+        }
+    }
+    Ok(text)
+}
+
+fn parse(format: &str) -> Vec<Item> {
+    let chars: Vec<char> = format.chars().collect();
+    let mut items = Vec::new();
+    let mut i = 0;
+    while i < chars.len() {
+        let rest = &chars[i..];
+        let (item, length) = match rest {
+            ['_', c, ..] => (Item::Literal(*c), 2),
+            ['!', ..] => (Item::Text(Some(1)), 1),
+            ['&', ..] => (Item::Text(None), 1),
+            ['\\', tail @ ..] => match tail.iter().position(|&c| c != ' ') {
+                Some(spaces) if tail[spaces] == '\\' => (Item::Text(Some(spaces + 2)), spaces + 2),
+                _ => (Item::Literal('\\'), 1),
+            },
+            _ => match number_field(rest) {
+                Some((field, length)) => (Item::Number(field), length),
+                None => (Item::Literal(rest[0]), 1),
+            },
+        };
+        items.push(item);
+        i += length;
+    }
+    items
+}
+
+/// The numeric field at the start of `chars` and how many characters it
+/// takes, if one starts there
+fn number_field(chars: &[char]) -> Option<(NumberField, usize)> {
+    let mut field = NumberField::default();
+    let mut i = 0;
+    let at = |i: usize, s: &str| s.chars().enumerate().all(|(k, c)| chars.get(i + k) == Some(&c));
+    if chars.first() == Some(&'+') {
+        field.sign = Sign::Leading;
+        i += 1;
+    }
+    if at(i, "**$") {
+        (field.stars, field.dollar, field.digits) = (true, true, 2);
+        i += 3;
+    } else if at(i, "**") {
+        (field.stars, field.digits) = (true, 2);
+        i += 2;
+    } else if at(i, "$$") {
+        (field.dollar, field.digits) = (true, 1);
+        i += 2;
+    }
+    let starts = field.digits > 0 || chars.get(i) == Some(&'#') || at(i, ".#");
+    if !starts {
+        return None;
+    }
+    while let Some(&c) = chars.get(i) {
+        match c {
+            '#' => field.digits += 1,
+            ',' if field.digits > 0 => {
+                field.commas = true;
+                field.digits += 1;
+            }
+            _ => break,
+        }
+        i += 1;
+    }
+    if chars.get(i) == Some(&'.') {
+        field.point = true;
+        i += 1;
+        while chars.get(i) == Some(&'#') {
+            field.decimals += 1;
+            i += 1;
+        }
+    }
+    if at(i, "^^^^^") {
+        field.exponent = Some(3);
+        i += 5;
+    } else if at(i, "^^^^") {
+        field.exponent = Some(2);
+        i += 4;
+    }
+    if field.sign == Sign::Minus {
+        match chars.get(i) {
+            Some('+') => field.sign = Sign::TrailingPlus,
+            Some('-') => field.sign = Sign::TrailingMinus,
+            _ => {}
+        }
+        if field.sign != Sign::Minus {
+            i += 1;
+        }
+    }
+    field.width = i;
+    Some((field, i))
+}
+
+fn fit_text(s: &str, length: Option<usize>) -> String {
+    match length {
+        Some(length) => format!("{:<length$}", s.chars().take(length).collect::<String>()),
+        None => s.to_string(),
+    }
+}
+
+fn format_number(field: &NumberField, value: f64) -> String {
+    let negative = value < 0.0;
+    let magnitude = value.abs();
+    let mut body = match field.exponent {
+        Some(exponent_digits) => scientific(field, magnitude, exponent_digits, negative),
+        None => fixed(field, magnitude),
+    };
+    if field.dollar {
+        body.insert(0, '$');
+    }
+    let text = match field.sign {
+        Sign::Minus if negative => format!("-{}", body),
+        Sign::Minus => body,
+        Sign::Leading => format!("{}{}", if negative { '-' } else { '+' }, body),
+        Sign::TrailingPlus => format!("{}{}", body, if negative { '-' } else { '+' }),
+        Sign::TrailingMinus => format!("{}{}", body, if negative { '-' } else { ' ' }),
+    };
+    let length = text.chars().count();
+    if length > field.width {
+        return format!("%{}", text);
+    }
+    let fill = if field.stars { '*' } else { ' ' };
+    let mut padded: String = std::iter::repeat_n(fill, field.width - length).collect();
+    padded.push_str(&text);
+    padded
+}
+
+/// Digits of a field without exponent: the whole part, grouped if asked,
+/// then the point and decimals
+fn fixed(field: &NumberField, magnitude: f64) -> String {
+    let digits = format!("{:.*}", field.decimals, round_half_away(magnitude, field.decimals));
+    let (whole, fraction) = digits.split_once('.').unwrap_or((&digits, ""));
+    let mut text = if whole == "0" && field.digits == 0 {
+        String::new()
+    } else if field.commas {
+        group_thousands(whole)
+    } else {
+        whole.to_string()
+    };
+    if field.point {
+        text.push('.');
+        text.push_str(fraction);
+    }
+    text
+}
+
+/// Digits of a field with exponent: the digit positions are filled,
+/// keeping one for the sign when the field has no sign of its own
+fn scientific(field: &NumberField, magnitude: f64, exponent_digits: usize, negative: bool) -> String {
+    let reserved = usize::from(field.sign == Sign::Minus && (negative || field.digits > 1));
+    let whole_digits = field.digits.saturating_sub(reserved);
+    let mut exponent = if magnitude == 0.0 {
+        0
+    } else {
+        magnitude.log10().floor() as i32 + 1 - whole_digits as i32
+    };
+    let mut mantissa = round_half_away(magnitude / 10f64.powi(exponent), field.decimals);
+    if mantissa >= 10f64.powi(whole_digits as i32) && magnitude != 0.0 {
+        exponent += 1;
+        mantissa = round_half_away(magnitude / 10f64.powi(exponent), field.decimals);
+    }
+    let digits = format!("{:.*}", field.decimals, mantissa);
+    let (whole, fraction) = digits.split_once('.').unwrap_or((&digits, ""));
+    let mut text = if whole_digits == 0 { String::new() } else { whole.to_string() };
+    if field.point {
+        text.push('.');
+        text.push_str(fraction);
+    }
+    let sign = if exponent < 0 { '-' } else { '+' };
+    text.push_str(&format!("E{}{:0exponent_digits$}", sign, exponent.unsigned_abs()));
+    text
+}
+
+fn round_half_away(value: f64, decimals: usize) -> f64 {
+    let scale = 10f64.powi(decimals as i32);
+    (value * scale).round() / scale
+}
+
+fn group_thousands(whole: &str) -> String {
+    let mut text = String::new();
+    for (i, c) in whole.chars().enumerate() {
+        if i > 0 && (whole.len() - i).is_multiple_of(3) {
+            text.push(',');
+        }
+        text.push(c);
+    }
+    text
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn using(format: &str, values: &[QType]) -> String {
+        format_using(format, values).unwrap()
+    }
+
+    #[test]
+    fn test_numeric_fields() {
+        let n = |v: f64| QType::Double(v);
+        assert_eq!(using("##.##", &[n(4.567)]), " 4.57");
+        assert_eq!(using("##.##", &[n(-4.567)]), "-4.57");
+        assert_eq!(using("#.##", &[n(0.5)]), "0.50");
+        assert_eq!(using(".##", &[n(0.5)]), ".50");
+        assert_eq!(using("##", &[n(123.0)]), "%123");
+        assert_eq!(using("#,###.##", &[n(1234.5)]), "1,234.50");
+        assert_eq!(using("$$###.##", &[n(12.5)]), "  $12.50");
+        assert_eq!(using("**###.##", &[n(12.5)]), "***12.50");
+        assert_eq!(using("**$##.##", &[n(12.5)]), "**$12.50");
+        assert_eq!(using("+###", &[n(5.0)]), "  +5");
+        assert_eq!(using("###-", &[n(-5.0)]), "  5-");
+        assert_eq!(using("###-", &[n(5.0)]), "  5 ");
+        assert_eq!(using("##.##^^^^", &[n(1234.5)]), " 1.23E+03");
+        assert_eq!(using("##.##^^^^", &[n(-0.00012)]), "-1.20E-04");
+    }
+
+    #[test]
+    fn test_string_fields_and_reuse() {
+        let s = |v: &str| QType::String(v.into());
+        assert_eq!(using("!", &[s("hello")]), "h");
+        assert_eq!(using("\\  \\|", &[s("hello")]), "hell|");
+        assert_eq!(using("\\  \\|", &[s("hi")]), "hi  |");
+        assert_eq!(using("[&] ", &[s("a"), s("bc")]), "[a] [bc] ");
+        assert_eq!(using("_####_#", &[QType::Integer(7)]), "#  7#");
+        assert!(format_using("#", &[s("x")]).is_err());
+        assert!(format_using("abc", &[s("x")]).is_err());
+    }
+}
@@ -0,0 +1,279 @@
+//! DOS paths on the host file system, so programs that hardcode paths such
+//! as C:\GAMES\SCORES.DAT run unchanged
+//!
+//! Drive letters map onto host directories. Backslashes and slashes both
+//! separate directories, and each name is looked up without regard to
+//! case, or as the 8.3 short name DOS would have shown for a long one.
+//! Names that match nothing, as for a file about to be created, are kept as
+//! written. Paths without a drive letter are relative to the working
+//! directory as before; a path from the root goes to the root of drive C
+//! when it is mapped.
+//!
+//! Some names are devices rather than files: SCRN: and CONS: print to the
+//! screen, and NUL, in any directory and with any extension, throws away
+//! what is written to it and has nothing to read.
+
+use qb_core::errors::{QError, QErrorCode, QResult};
+use std::collections::BTreeMap;
+use std::path::{Path, PathBuf};
+
+/// The drive a path from the root is on
+const CURRENT_DRIVE: char = 'C';
+
+/// A device opened by name in place of a file
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Device {
+    /// SCRN: and CONS:, for output only
+    Screen,
+    Null,
+}
+
+/// The device a file name opens, if it is one
+pub fn device(name: &str) -> Option<Device> {
+    let name = name.trim();
+    if ["SCRN:", "CONS:"].iter().any(|device| name.eq_ignore_ascii_case(device)) {
+        return Some(Device::Screen);
+    }
+    let (_, rest) = split_drive(name);
+    let last = rest.rsplit(['\\', '/']).next()?;
+    let base = last.split('.').next()?.trim_end_matches(':');
+    base.eq_ignore_ascii_case("NUL").then_some(Device::Null)
+}
+
+#[derive(Debug, Clone, Default)]
+pub struct Vfs {
+    /// Host directory of each drive letter, in upper case
+    drives: BTreeMap<char, PathBuf>,
+}
+
+impl Vfs {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Make drive `letter` the host directory `dir`; false for a letter
+    /// outside A to Z
+    pub fn map_drive(&mut self, letter: char, dir: PathBuf) -> bool {
+        if !letter.is_ascii_alphabetic() {
+            return false;
+        }
+        self.drives.insert(letter.to_ascii_uppercase(), dir);
+        true
+    }
+
+    /// The host path for a DOS path. A drive letter that is not mapped is
+    /// "Path not found", except on Windows, where it is the host's own.
+    pub fn resolve(&self, path: &str) -> QResult<PathBuf> {
+        let (drive, rest) = split_drive(path);
+        let rooted = rest.starts_with(['\\', '/']);
+        let mut host = match drive {
+            Some(letter) => match self.drives.get(&letter) {
+                Some(dir) => dir.clone(),
+                None if cfg!(windows) => PathBuf::from(format!("{}:\\", letter)),
+                None => return Err(QError::runtime(QErrorCode::PathNotFound, 0, 0)),
+            },
+            None if rooted => match self.drives.get(&CURRENT_DRIVE) {
+                Some(dir) => dir.clone(),
+                None => PathBuf::from(std::path::MAIN_SEPARATOR_STR),
+            },
+            None => PathBuf::new(),
+        };
+        let relative = drive.is_none() && !rooted;
+        // Directories entered below where the path started, which ".." may
+        // leave again
+        let mut depth = 0;
+        for name in rest.split(['\\', '/']).filter(|name| !name.is_empty()) {
+            match name {
+                "." => {}
+                ".." if depth > 0 => {
+                    host.pop();
+                    depth -= 1;
+                }
+                ".." if relative => host.push(".."),
+                // DOS stays at the root
+                ".." => {}
+                _ => {
+                    // A trailing dot only says there is no extension
+                    let name = name.strip_suffix('.').filter(|base| !base.is_empty()).unwrap_or(name);
+                    let found = find(&host, name);
+                    host.push(found.as_deref().unwrap_or(name));
+                    depth += 1;
+                }
+            }
+        }
+        Ok(host)
+    }
+
+    /// The files KILL deletes: the one `spec` names, or those its last part
+    /// matches when it holds * or ?
+    pub fn matching(&self, spec: &str) -> QResult<Vec<PathBuf>> {
+        let split = match spec.rfind(['\\', '/']) {
+            Some(at) => at + 1,
+            None if split_drive(spec).0.is_some() => 2,
+            None => 0,
+        };
+        let (dir, pattern) = spec.split_at(split);
+        if !pattern.contains(['*', '?']) {
+            let path = self.resolve(spec)?;
+            return Ok(if path.is_file() { vec![path] } else { Vec::new() });
+        }
+        let dir = self.resolve(dir)?;
+        let listed = if dir.as_os_str().is_empty() { Path::new(".") } else { dir.as_path() };
+        let mut entries: Vec<String> = std::fs::read_dir(listed)
+            .map_err(|_| QError::runtime(QErrorCode::PathNotFound, 0, 0))?
+            .filter_map(|entry| entry.ok()?.file_name().into_string().ok())
+            .filter(|entry| listed.join(entry).is_file())
+            .collect();
+        entries.sort();
+        let short = short_names(&entries);
+        let alias = |entry: &String| short.iter().find(|(_, long)| long == entry).map(|(short, _)| short.as_str());
+        Ok(entries
+            .iter()
+            .filter(|entry| wildcard_match(pattern, entry) || alias(entry).is_some_and(|short| wildcard_match(pattern, short)))
+            .map(|entry| dir.join(entry))
+            .collect())
+    }
+}
+
+/// Whether `name` fits a DOS file pattern, where * stands for any run of
+/// characters and ? for one. A name without an extension fits a pattern
+/// ending in .*, as in *.*.
+fn wildcard_match(pattern: &str, name: &str) -> bool {
+    fn fits(pattern: &[char], name: &[char]) -> bool {
+        match pattern.split_first() {
+            None => name.is_empty(),
+            Some(('*', rest)) => (0..=name.len()).any(|skip| fits(rest, &name[skip..])),
+            Some(('?', rest)) => !name.is_empty() && fits(rest, &name[1..]),
+            Some((c, rest)) => name.first().is_some_and(|n| n.eq_ignore_ascii_case(c)) && fits(rest, &name[1..]),
+        }
+    }
+    let name: Vec<char> = name.chars().collect();
+    let pattern: Vec<char> = pattern.chars().collect();
+    fits(&pattern, &name) || (!name.contains(&'.') && pattern.ends_with(&['.', '*']) && fits(&pattern[..pattern.len() - 2], &name))
+}
+
+/// The upper-case drive letter of a path, and the rest of it
+fn split_drive(path: &str) -> (Option<char>, &str) {
+    let mut chars = path.chars();
+    match (chars.next(), chars.next()) {
+        (Some(letter), Some(':')) if letter.is_ascii_alphabetic() => (Some(letter.to_ascii_uppercase()), &path[2..]),
+        _ => (None, path),
+    }
+}
+
+/// The entry of `dir` that `name` refers to: itself, the same name in
+/// another case, or the long name whose 8.3 short name it is
+fn find(dir: &Path, name: &str) -> Option<String> {
+    let listed = if dir.as_os_str().is_empty() { Path::new(".") } else { dir };
+    if listed.join(name).exists() {
+        return Some(name.to_string());
+    }
+    let mut entries: Vec<String> = std::fs::read_dir(listed)
+        .ok()?
+        .filter_map(|entry| entry.ok()?.file_name().into_string().ok())
+        .collect();
+    entries.sort();
+    if let Some(entry) = entries.iter().find(|entry| entry.eq_ignore_ascii_case(name)) {
+        return Some(entry.clone());
+    }
+    short_names(&entries)
+        .into_iter()
+        .find(|(short, _)| short.eq_ignore_ascii_case(name))
+        .map(|(_, entry)| entry)
+}
+
+/// Characters DOS allows in a name besides letters and digits
+const NAME_PUNCTUATION: &str = "!#$%&'()-@^_`{}~";
+
+fn is_name_char(c: char) -> bool {
+    c.is_ascii_alphanumeric() || NAME_PUNCTUATION.contains(c)
+}
+
+/// Whether `name` already fits 8.3, so has no other short name
+fn is_short_name(name: &str) -> bool {
+    let (base, ext) = name.split_once('.').unwrap_or((name, ""));
+    (1..=8).contains(&base.len()) && ext.len() <= 3 && base.chars().chain(ext.chars()).all(is_name_char)
+}
+
+/// The 8.3 short names of the long names among `entries`, sorted, as
+/// Windows makes them: the first six name characters, ~ and a number that
+/// counts up for names that would otherwise be the same
+fn short_names(entries: &[String]) -> Vec<(String, String)> {
+    let mut used: BTreeMap<String, u32> = BTreeMap::new();
+    let mut names = Vec::new();
+    for entry in entries.iter().filter(|entry| !is_short_name(entry)) {
+        let (base, ext) = match entry.rsplit_once('.') {
+            Some((base, ext)) if !base.is_empty() => (base, ext),
+            _ => (entry.as_str(), ""),
+        };
+        let clean = |part: &str, length: usize| -> String {
+            part.chars().filter(|&c| is_name_char(c)).take(length).collect::<String>().to_ascii_uppercase()
+        };
+        let (base, ext) = (clean(base, 6), clean(ext, 3));
+        if base.is_empty() {
+            continue;
+        }
+        let number = used.entry(format!("{}.{}", base, ext)).or_insert(0);
+        *number += 1;
+        let short = match ext.as_str() {
+            "" => format!("{}~{}", base, number),
+            _ => format!("{}~{}.{}", base, number, ext),
+        };
+        names.push((short, entry.clone()));
+    }
+    names
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_short_names() {
+        let entries = ["Long File Name.text", "LONGFILE.TXT", "longfilename.text", "readme"].map(String::from);
+        let names = short_names(&entries);
+        assert_eq!(
+            names,
+            vec![
+                ("LONGFI~1.TEX".to_string(), "Long File Name.text".to_string()),
+                ("LONGFI~2.TEX".to_string(), "longfilename.text".to_string()),
+            ]
+        );
+        assert!(is_short_name("readme") && !is_short_name("a b.txt") && !is_short_name("ABCDEFGHI"));
+    }
+
+    #[test]
+    fn test_resolve_drives_and_names() {
+        let root = std::env::temp_dir().join(format!("qb-vfs-{}", std::process::id()));
+        std::fs::create_dir_all(root.join("Games/High Scores")).unwrap();
+        std::fs::write(root.join("Games/High Scores/Table.dat"), "").unwrap();
+        let mut vfs = Vfs::new();
+        assert!(vfs.map_drive('c', root.clone()));
+        assert!(!vfs.map_drive('1', root.clone()));
+
+        let found = root.join("Games/High Scores/Table.dat");
+        assert_eq!(vfs.resolve("C:\\GAMES\\HIGHSC~1\\TABLE.DAT").unwrap(), found);
+        assert_eq!(vfs.resolve("c:/games/high scores/table.dat.").unwrap(), found);
+        // From the root of the current drive, and up past it
+        assert_eq!(vfs.resolve("\\..\\GAMES\\.\\NEW.DAT").unwrap(), root.join("Games/NEW.DAT"));
+        assert_eq!(vfs.resolve("..\\X.DAT").unwrap(), PathBuf::from("../X.DAT"));
+        if !cfg!(windows) {
+            assert!(vfs.resolve("D:\\X.DAT").is_err());
+        }
+        std::fs::remove_dir_all(root).unwrap();
+    }
+
+    #[test]
+    fn test_devices_and_wildcards() {
+        assert_eq!(device("scrn:"), Some(Device::Screen));
+        assert_eq!(device("C:\\TEMP\\NUL.TXT"), Some(Device::Null));
+        assert_eq!(device("NUL:"), Some(Device::Null));
+        assert_eq!(device("SCRN"), None);
+        assert_eq!(device("NULL.TXT"), None);
+
+        assert!(wildcard_match("*.*", "README"));
+        assert!(wildcard_match("score?.dat", "SCORE1.DAT"));
+        assert!(!wildcard_match("*.DAT", "SCORE.DATA"));
+        assert!(!wildcard_match("?", ""));
+    }
+}
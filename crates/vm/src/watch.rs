@@ -0,0 +1,72 @@
+//! Watchpoints: pause the program whenever a variable or array element is
+//! written, for `watch` in `qb debug`
+
+use qb_core::data_types::QType;
+use std::fmt;
+
+/// A watched variable, whole array, or single array element
+#[derive(Debug, Clone, PartialEq)]
+pub struct Watch {
+    /// Name as the VM stores it, e.g. "BALANCE#"
+    pub name: String,
+    /// Subscripts of one array element; None watches every write to the name
+    pub indices: Option<Vec<i32>>,
+}
+
+impl Watch {
+    /// Whether a write to `name` at `indices` (empty for a plain variable)
+    /// trips this watch
+    pub fn matches(&self, name: &str, indices: &[i32]) -> bool {
+        self.name == name && self.indices.as_ref().is_none_or(|watched| watched == indices)
+    }
+}
+
+impl fmt::Display for Watch {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match &self.indices {
+            Some(indices) => {
+                let subscripts: Vec<String> = indices.iter().map(|i| i.to_string()).collect();
+                write!(f, "{}({})", self.name, subscripts.join(", "))
+            }
+            None => write!(f, "{}", self.name),
+        }
+    }
+}
+
+/// A write that tripped a watchpoint; the new value is already stored
+#[derive(Debug, Clone)]
+pub struct WatchHit {
+    /// The variable or element written
+    pub target: Watch,
+    pub old: QType,
+    pub new: QType,
+    /// Source line of the statement that wrote it, if known
+    pub line: Option<usize>,
+}
+
+impl fmt::Display for WatchHit {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{} = {} (was {})", self.target, self.new, self.old)?;
+        if let Some(line) = self.line {
+            write!(f, " at line {}", line)?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_matches() {
+        let whole = Watch { name: "A".to_string(), indices: None };
+        let element = Watch { name: "A".to_string(), indices: Some(vec![2, 3]) };
+        assert!(whole.matches("A", &[]));
+        assert!(whole.matches("A", &[1, 1]));
+        assert!(!whole.matches("B", &[]));
+        assert!(element.matches("A", &[2, 3]));
+        assert!(!element.matches("A", &[3, 2]));
+        assert_eq!(element.to_string(), "A(2, 3)");
+    }
+}
@@ -0,0 +1,25 @@
+' BUBBLE.BAS - sorting DATA with a bubble sort
+DIM items(1 TO 8) AS INTEGER
+FOR i = 1 TO 8
+    READ item
+    items(i) = item
+NEXT i
+DATA 42, 7, 19, 88, 3, 56, 21, 9
+
+CALL SortItems(items())
+FOR i = 1 TO 8
+    PRINT items(i);
+NEXT i
+PRINT
+
+SUB SortItems (a() AS INTEGER)
+    DO
+        swapped = 0
+        FOR i = LBOUND(a) TO UBOUND(a) - 1
+            IF a(i) > a(i + 1) THEN
+                t = a(i): a(i) = a(i + 1): a(i + 1) = t
+                swapped = -1
+            END IF
+        NEXT i
+    LOOP WHILE swapped
+END SUB
@@ -0,0 +1,15 @@
+' ERRORS.BAS - trapping errors with ON ERROR and RESUME
+ON ERROR GOTO Handler
+PRINT "Dividing by zero"
+x = 1 / 0
+PRINT "Opening a missing file"
+OPEN "NO_SUCH.DAT" FOR INPUT AS #1
+PRINT "Subscript out of range"
+DIM a(5)
+a(10) = 1
+PRINT "Done"
+END
+
+Handler:
+PRINT "  Error"; ERR
+RESUME NEXT
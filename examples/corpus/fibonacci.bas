@@ -0,0 +1,10 @@
+' FIBONACCI.BAS - the first twenty Fibonacci numbers
+DIM a AS LONG, b AS LONG, c AS LONG
+a = 0: b = 1
+FOR i% = 1 TO 20
+    PRINT a;
+    c = a + b
+    a = b
+    b = c
+NEXT i%
+PRINT
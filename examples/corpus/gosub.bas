@@ -0,0 +1,12 @@
+' GOSUB.BAS - line numbers, GOSUB and ON GOTO, as older programs wrote them
+10 FOR N = 1 TO 3
+20 GOSUB 100
+30 ON N GOTO 40, 50, 60
+40 PRINT "one": GOTO 70
+50 PRINT "two": GOTO 70
+60 PRINT "three"
+70 NEXT N
+80 PRINT "Bye"
+90 END
+100 PRINT "Square of"; N; "is"; N * N
+110 RETURN
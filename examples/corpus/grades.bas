@@ -0,0 +1,22 @@
+' GRADES.BAS - SELECT CASE and a table with PRINT USING
+DIM names(1 TO 5) AS STRING, scores(1 TO 5) AS INTEGER
+FOR i = 1 TO 5
+    READ n$, s
+    names(i) = n$
+    scores(i) = s
+NEXT i
+DATA "Alice", 93, "Bob", 78, "Carol", 85, "Dave", 61, "Eve", 47
+
+total = 0
+FOR i = 1 TO 5
+    SELECT CASE scores(i)
+        CASE IS >= 90: grade$ = "A"
+        CASE 80 TO 89: grade$ = "B"
+        CASE 70 TO 79: grade$ = "C"
+        CASE 60 TO 69: grade$ = "D"
+        CASE ELSE: grade$ = "F"
+    END SELECT
+    PRINT USING "\      \ ### &"; names(i); scores(i); grade$
+    total = total + scores(i)
+NEXT i
+PRINT USING "Average: ###.##"; total / 5
@@ -0,0 +1,10 @@
+' GUESS.BAS - a guessing game, its guesses typed from GUESS.IN
+secret = 37
+tries = 0
+DO
+    INPUT "Your guess"; g
+    tries = tries + 1
+    IF g < secret THEN PRINT "Too low"
+    IF g > secret THEN PRINT "Too high"
+LOOP UNTIL g = secret
+PRINT "Got it in"; tries; "tries"
@@ -0,0 +1,13 @@
+' HANOI.BAS - the Towers of Hanoi, solved recursively
+DECLARE SUB Move (n AS INTEGER, src AS STRING, dst AS STRING, via AS STRING)
+DIM SHARED moves AS INTEGER
+Move 3, "A", "C", "B"
+PRINT "Solved in"; moves; "moves"
+
+SUB Move (n AS INTEGER, src AS STRING, dst AS STRING, via AS STRING)
+    IF n = 0 THEN EXIT SUB
+    Move n - 1, src, via, dst
+    moves = moves + 1
+    PRINT "Disk"; n; "from "; src; " to "; dst
+    Move n - 1, via, dst, src
+END SUB
@@ -0,0 +1,3 @@
+' HELLO.BAS - the first program
+PRINT "Hello, World!"
+PRINT "2 + 2 ="; 2 + 2
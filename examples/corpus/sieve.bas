@@ -0,0 +1,16 @@
+' SIEVE.BAS - primes below 100 by the Sieve of Eratosthenes
+CONST LIMIT = 100
+DIM composite(2 TO LIMIT) AS INTEGER
+FOR i = 2 TO SQR(LIMIT)
+    IF NOT composite(i) THEN
+        FOR j = i * i TO LIMIT STEP i
+            composite(j) = -1
+        NEXT j
+    END IF
+NEXT i
+count = 0
+FOR i = 2 TO LIMIT
+    IF NOT composite(i) THEN PRINT i;: count = count + 1
+NEXT i
+PRINT
+PRINT count; "primes"
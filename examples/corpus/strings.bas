@@ -0,0 +1,20 @@
+' STRINGS.BAS - taking text apart and putting it together
+s$ = "The quick brown fox"
+PRINT UCASE$(s$)
+PRINT LCASE$(s$)
+PRINT LEN(s$)
+PRINT LEFT$(s$, 3); "|"; MID$(s$, 5, 5); "|"; RIGHT$(s$, 3)
+PRINT INSTR(s$, "brown")
+r$ = ""
+FOR i = 1 TO LEN(s$)
+    r$ = MID$(s$, i, 1) + r$
+NEXT i
+PRINT r$
+words = 1
+FOR i = 1 TO LEN(s$)
+    IF MID$(s$, i, 1) = " " THEN words = words + 1
+NEXT i
+PRINT words; "words"
+PRINT STRING$(10, "*"); SPACE$(2); CHR$(65); ASC("a")
+PRINT LTRIM$("   left"); RTRIM$("right   "); "|"
+PRINT STR$(3.5); VAL("12abc")
@@ -0,0 +1,5 @@
+' UNHANDLED.BAS - an error with no handler ends the program
+PRINT "Before"
+DIM a(3)
+a(4) = 1
+PRINT "After"
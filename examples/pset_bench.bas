@@ -0,0 +1,21 @@
+' PSET_BENCH.BAS
+' Graphics benchmark: fill the SCREEN 13 frame pixel by pixel and time it
+' Run with: qb run --dialect qb64 examples/pset_bench.bas
+
+CONST FRAMES = 20
+
+SCREEN 13
+start! = TIMER
+FOR frame = 1 TO FRAMES
+    FOR y = 0 TO 199
+        FOR x = 0 TO 319
+            PSET (x, y), (x + y + frame) MOD 256
+        NEXT x
+    NEXT y
+    _DISPLAY
+NEXT frame
+elapsed! = TIMER - start!
+
+SCREEN 0
+PRINT FRAMES; "frames of 64000 PSETs in"; elapsed!; "seconds"
+IF elapsed! > 0 THEN PRINT INT(FRAMES * 64000 / elapsed!); "PSETs per second"
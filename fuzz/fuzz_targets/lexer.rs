@@ -0,0 +1,9 @@
+//! Any bytes at all must tokenize or fail with an error, never panic
+#![no_main]
+
+use libfuzzer_sys::fuzz_target;
+
+fuzz_target!(|data: &[u8]| {
+    let _ = qb_lexer::try_tokenize(data);
+    let _ = qb_lexer::tokenize_recovering(&qb_lexer::decode(data));
+});
@@ -0,0 +1,13 @@
+//! Any bytes at all must parse or fail with an error, never panic, and
+//! what parses must print back as source
+#![no_main]
+
+use libfuzzer_sys::fuzz_target;
+
+fuzz_target!(|data: &[u8]| {
+    let (tokens, _) = qb_lexer::tokenize_recovering(&qb_lexer::decode(data));
+    let _ = qb_parser::parse(tokens);
+    if let Ok(program) = qb_parser::try_parse(data) {
+        let _ = qb_parser::try_parse(qb_parser::to_source(&program).as_bytes());
+    }
+});